@@ -0,0 +1,11 @@
+fn main() {
+    #[cfg(feature = "grpc")]
+    {
+        println!("cargo:rerun-if-changed=proto/smvblock.proto");
+        let protoc =
+            protoc_bin_vendored::protoc_bin_path().expect("No bundled protoc for this host");
+        // SAFETY: build scripts run single-threaded.
+        unsafe { std::env::set_var("PROTOC", protoc) };
+        tonic_build::compile_protos("proto/smvblock.proto").expect("Failed to compile protos");
+    }
+}
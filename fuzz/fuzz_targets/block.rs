@@ -0,0 +1,17 @@
+//! Blocks as peers send them, checked the way a node checks a block before
+//! looking at the rest of its chain.
+
+#![no_main]
+
+use bincode::config::standard;
+use libfuzzer_sys::fuzz_target;
+use smvblock::blockchain::Block;
+use smvblock::p2p::MAX_FRAME_SIZE;
+
+fuzz_target!(|data: &[u8]| {
+    let config = standard().with_limit::<MAX_FRAME_SIZE>();
+    if let Ok((block, _)) = bincode::serde::decode_from_slice::<Block, _>(data, config) {
+        let _ = block.hash();
+        let _ = block.verify();
+    }
+});
@@ -0,0 +1,15 @@
+//! Peer message payloads, as read from a frame.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use smvblock::p2p::{decode_message, encode_frame};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(message) = decode_message(data) {
+        // Whatever decodes must survive the trip back onto the wire.
+        if let Ok(frame) = encode_frame(&message, None) {
+            assert_eq!(decode_message(&frame[6..]).as_ref(), Ok(&message));
+        }
+    }
+});
@@ -0,0 +1,13 @@
+//! Transactions in their canonical encoding, as `tx_submitRaw` takes them.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use smvblock::blockchain::Transaction;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(tx) = Transaction::from_bytes(data) {
+        let _ = tx.verify();
+        assert_eq!(Transaction::from_bytes(&tx.to_bytes()).as_ref(), Ok(&tx));
+    }
+});
@@ -0,0 +1,276 @@
+//! A typed async client for smvblock nodes, for programs that want to read
+//! the chain, send transfers and follow new blocks without shelling out to
+//! the `client` binary.
+//!
+//! Requests go over the node's JSON-RPC API and subscriptions over its
+//! WebSocket endpoint. Transactions are signed in this process; the node
+//! never sees a key.
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), smv_client::RpcError> {
+//! let client = smv_client::Client::connect("http://127.0.0.1:8545")?;
+//! let status = client.status().await?;
+//! println!("at height {:?}", status.height);
+//!
+//! let mut blocks = client.subscribe_blocks().await?;
+//! while let Some(head) = blocks.next().await {
+//!     println!("block {}", head?.height);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use futures_util::{SinkExt, StreamExt};
+use serde_json::{Value, json};
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+
+pub use ed25519_dalek::SigningKey;
+pub use smvblock::amount::Amount;
+pub use smvblock::blockchain::{Address, Hash, Transaction, Transfer, TxKind};
+pub use smvblock::client::{Account, BlockId, RpcClient, parse_hash};
+pub use smvblock::error::RpcError;
+pub use smvblock::wallet::key_address;
+
+/// A node's view of the chain, as `chain_getStatus` gives it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Status {
+    /// Height of the node's latest block, or `None` before genesis.
+    pub height: Option<u64>,
+    pub latest_hash: Option<Hash>,
+    pub finalized_height: Option<u64>,
+    pub finalized_hash: Option<Hash>,
+    /// Height the node believes the network is at.
+    pub network_height: Option<u64>,
+    /// Whether the node is still catching up with the network.
+    pub syncing: bool,
+    pub pending_transactions: u64,
+    pub peers: u64,
+    pub version: String,
+}
+
+impl Status {
+    fn from_json(status: &Value) -> Result<Self, RpcError> {
+        let hash = |name: &str| -> Result<Option<Hash>, RpcError> {
+            match &status[name] {
+                Value::Null => Ok(None),
+                Value::String(hash) => parse_hash(hash).map(Some).map_err(invalid),
+                _ => Err(invalid(format!("Invalid {} in status", name))),
+            }
+        };
+        Ok(Status {
+            height: status["height"].as_u64(),
+            latest_hash: hash("latest_hash")?,
+            finalized_height: status["finalized_height"].as_u64(),
+            finalized_hash: hash("finalized_hash")?,
+            network_height: status["network_height"].as_u64(),
+            syncing: status["sync"]["state"] == "syncing",
+            pending_transactions: status["pending_transactions"].as_u64().unwrap_or(0),
+            peers: status["peers"].as_u64().unwrap_or(0),
+            version: status["version"].as_str().unwrap_or_default().to_string(),
+        })
+    }
+}
+
+/// The header of a block a node has applied, as pushed to block
+/// subscribers.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Head {
+    pub hash: Hash,
+    pub previous_hash: Hash,
+    pub height: u64,
+    pub timestamp: i64,
+    pub proposer: Address,
+    pub state_root: Hash,
+    pub coinbase: Amount,
+}
+
+impl Head {
+    fn from_json(head: &Value) -> Result<Self, RpcError> {
+        let field = |name: &str| {
+            head[name]
+                .as_str()
+                .ok_or(invalid(format!("Missing {} in block header", name)))
+        };
+        let hash = |name: &str| parse_hash(field(name)?).map_err(invalid);
+        Ok(Head {
+            hash: hash("hash")?,
+            previous_hash: hash("previous_hash")?,
+            height: head["height"]
+                .as_u64()
+                .ok_or(invalid("Missing height in block header".to_string()))?,
+            timestamp: head["timestamp"]
+                .as_i64()
+                .ok_or(invalid("Missing timestamp in block header".to_string()))?,
+            proposer: hash("proposer")?,
+            state_root: hash("state_root")?,
+            coinbase: field("coinbase")?.parse().map_err(invalid)?,
+        })
+    }
+}
+
+fn invalid(reason: String) -> RpcError {
+    RpcError::InvalidResponse(reason)
+}
+
+/// One or more nodes, reached over their RPC servers.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Client {
+    rpc: RpcClient,
+}
+
+impl Client {
+    /// A client for the nodes at `url`, given as `http://HOST:PORT` or
+    /// several of them separated by commas.
+    pub fn connect(url: &str) -> Result<Self, RpcError> {
+        let rpc = url.parse().map_err(RpcError::Unreachable)?;
+        Ok(Client { rpc })
+    }
+
+    pub fn new(rpc: RpcClient) -> Self {
+        Client { rpc }
+    }
+
+    /// Sets how long each attempt to reach a node may take.
+    pub fn with_timeout(self, timeout: Duration) -> Self {
+        Client {
+            rpc: self.rpc.with_timeout(timeout),
+        }
+    }
+
+    /// The underlying JSON-RPC client, for calls this API does not cover.
+    pub fn rpc(&self) -> &RpcClient {
+        &self.rpc
+    }
+
+    pub async fn status(&self) -> Result<Status, RpcError> {
+        Status::from_json(&self.rpc.status().await?)
+    }
+
+    /// The balance and stake of the account at `address`.
+    pub async fn balance(&self, address: &Address) -> Result<Account, RpcError> {
+        self.rpc.balance(address).await
+    }
+
+    /// The nonce the next transaction from `address` must carry.
+    pub async fn nonce(&self, address: &Address) -> Result<u64, RpcError> {
+        self.rpc.nonce(address).await
+    }
+
+    /// Signs a transfer of `amount` to `receiver` with `key` and submits
+    /// it, returning its hash.
+    pub async fn send(
+        &self,
+        key: &SigningKey,
+        receiver: Address,
+        amount: Amount,
+        fee: Amount,
+    ) -> Result<Hash, RpcError> {
+        self.rpc.transfer(key, receiver, amount, fee).await
+    }
+
+    /// Submits a transaction signed elsewhere, returning its hash.
+    pub async fn submit(&self, tx: &Transaction) -> Result<Hash, RpcError> {
+        self.rpc.submit(tx).await
+    }
+
+    /// Follows the blocks the node applies from now on.
+    pub async fn subscribe_blocks(&self) -> Result<BlockStream, RpcError> {
+        let mut failures = Vec::new();
+        for addr in self.rpc.endpoints() {
+            let url = format!("ws://{}/ws", addr);
+            // The server takes the token in the query too; the URL carrying
+            // it is kept out of errors.
+            let endpoint = match self.rpc.token() {
+                Some(token) => format!("{}?token={}", url, token),
+                None => url.clone(),
+            };
+            let connect = tokio_tungstenite::connect_async(endpoint.as_str());
+            let mut socket = match tokio::time::timeout(self.rpc.timeout(), connect).await {
+                Ok(Ok((socket, _))) => socket,
+                Ok(Err(e)) => {
+                    failures.push(format!("{}: {}", url, e));
+                    continue;
+                }
+                Err(_) => {
+                    failures.push(format!("{}: timed out connecting", url));
+                    continue;
+                }
+            };
+            let request =
+                json!({ "jsonrpc": "2.0", "id": 1, "method": "subscribe_newHeads", "params": [] });
+            socket
+                .send(Message::text(request.to_string()))
+                .await
+                .map_err(|e| RpcError::Unreachable(format!("{}: {}", url, e)))?;
+            let mut stream = BlockStream {
+                socket,
+                subscription: None,
+            };
+            let confirmation = stream
+                .receive()
+                .await
+                .ok_or(RpcError::Unreachable(format!(
+                    "{}: closed before confirming the subscription",
+                    url
+                )))??;
+            if let Some(error) = confirmation.get("error") {
+                return Err(RpcError::Rejected {
+                    method: "subscribe_newHeads".to_string(),
+                    code: error["code"].as_i64().unwrap_or(0),
+                    message: error["message"].as_str().unwrap_or_default().to_string(),
+                });
+            }
+            stream.subscription = Some(confirmation["result"].clone());
+            return Ok(stream);
+        }
+        Err(RpcError::Unreachable(format!(
+            "Failed to reach a node: {}",
+            failures.join("; ")
+        )))
+    }
+}
+
+/// Headers of new blocks as a node applies them. Dropping it closes the
+/// subscription.
+pub struct BlockStream {
+    socket: WebSocketStream<MaybeTlsStream<TcpStream>>,
+    subscription: Option<Value>,
+}
+
+impl BlockStream {
+    /// The next block applied, or `None` once the node closes the
+    /// connection.
+    pub async fn next(&mut self) -> Option<Result<Head, RpcError>> {
+        loop {
+            let message = match self.receive().await? {
+                Ok(message) => message,
+                Err(e) => return Some(Err(e)),
+            };
+            let params = &message["params"];
+            if message["method"] == "subscription"
+                && self.subscription.as_ref() == Some(&params["subscription"])
+            {
+                return Some(Head::from_json(&params["result"]));
+            }
+        }
+    }
+
+    /// The next JSON message from the node.
+    async fn receive(&mut self) -> Option<Result<Value, RpcError>> {
+        loop {
+            let text = match self.socket.next().await? {
+                Ok(Message::Text(text)) => text,
+                Ok(Message::Close(_)) => return None,
+                Ok(_) => continue,
+                Err(e) => return Some(Err(RpcError::Unreachable(e.to_string()))),
+            };
+            return Some(
+                serde_json::from_str(text.as_str())
+                    .map_err(|e| invalid(format!("Invalid message from node: {}", e))),
+            );
+        }
+    }
+}
@@ -0,0 +1,50 @@
+use smv_client::{Amount, Client, RpcError};
+use smvblock::blockchain::User;
+use smvblock::node::{Node, NodeType};
+use std::time::Duration;
+
+#[tokio::test]
+async fn test_client_reads_sends_and_follows_blocks() {
+    let mut node = Node::new(NodeType::FullNode, true).unwrap();
+    let (user, key) = User::generate(Amount::from_smv(50));
+    let (receiver, _) = User::generate(Amount::ZERO);
+    node.add_user(user.clone()).await.unwrap();
+    node.add_user(receiver.clone()).await.unwrap();
+    node.stake(user.address, Amount::from_smv(10))
+        .await
+        .unwrap();
+    let addr = node
+        .start_rpc("127.0.0.1:0".parse().unwrap())
+        .await
+        .unwrap();
+    let client = Client::connect(&format!("http://{}", addr)).unwrap();
+
+    let status = client.status().await.unwrap();
+    assert_eq!(status.height, None);
+    assert!(!status.syncing);
+    let mut blocks = client.subscribe_blocks().await.unwrap();
+
+    let hash = client
+        .send(&key, receiver.address, Amount::from_smv(5), Amount::ZERO)
+        .await
+        .unwrap();
+    assert_eq!(client.status().await.unwrap().pending_transactions, 1);
+    let block = node.produce_block().await.unwrap();
+
+    let head = tokio::time::timeout(Duration::from_secs(5), blocks.next())
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+    assert_eq!((head.hash, head.height), (block, 0));
+    let status = client.status().await.unwrap();
+    assert_eq!(status.latest_hash, Some(block));
+    let tx = client.rpc().transaction(&hash).await.unwrap().unwrap();
+    assert_eq!(tx["status"], "confirmed");
+    let account = client.balance(&receiver.address).await.unwrap();
+    assert_eq!(account.balance, Amount::from_smv(5));
+
+    let missing = client.balance(&[9; 32]).await.unwrap_err();
+    assert!(matches!(missing, RpcError::Rejected { .. }), "{}", missing);
+    assert!(Client::connect("not a url").is_err());
+}
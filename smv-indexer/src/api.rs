@@ -0,0 +1,270 @@
+//! The read-only HTTP API an explorer frontend reads the indexed tables
+//! through. Every route answers JSON:
+//!
+//! - `GET /status`: the highest block indexed.
+//! - `GET /blocks?before=HEIGHT&limit=N`: the newest blocks, a page at a time.
+//! - `GET /blocks/{height or hash}`: one block with its transactions.
+//! - `GET /transactions/{hash}`: one transaction and the block it is in.
+//! - `GET /addresses/{address}/transactions?offset=N&limit=N`: transactions
+//!   to or from an address, newest first.
+//! - `GET /validators`: blocks proposed and rewards earned per validator.
+//! - `GET /volumes?days=N`: transactions, transfer volume and fees per day.
+//! - `POST /graphql`: the same data as one linked graph; see [`graphql`].
+//! - `GET /graphql/schema`: that graph's schema in SDL.
+//!
+//! Amounts are given as the node gives them, e.g. `"5 SMV"`, and
+//! hashes and addresses as hex.
+
+use crate::graphql::{self, ChainSchema};
+use crate::store::{BlockSummary, DailyVolume, IndexedTx, Store, TxRecord, ValidatorStats};
+use axum::Router;
+use axum::extract::{FromRef, Path, Query, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use serde::Deserialize;
+use serde_json::{Value, json};
+use smv_client::{BlockId, Client, parse_hash};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio::sync::watch;
+
+/// Most items one page may hold.
+pub const MAX_PAGE: u64 = 100;
+
+#[derive(Clone)]
+struct ApiState {
+    store: Arc<Store>,
+    schema: ChainSchema,
+}
+
+impl FromRef<ApiState> for Arc<Store> {
+    fn from_ref(state: &ApiState) -> Self {
+        state.store.clone()
+    }
+}
+
+impl FromRef<ApiState> for ChainSchema {
+    fn from_ref(state: &ApiState) -> Self {
+        state.schema.clone()
+    }
+}
+
+/// Serves the API on `addr` in the background until `shutdown` turns true.
+/// `client` reaches the node for the account state GraphQL queries ask
+/// for. Returns the bound address.
+pub async fn serve(
+    store: Arc<Store>,
+    client: Client,
+    addr: SocketAddr,
+    mut shutdown: watch::Receiver<bool>,
+) -> Result<SocketAddr, String> {
+    let listener = TcpListener::bind(addr)
+        .await
+        .map_err(|e| format!("Failed to bind explorer API on {}: {}", addr, e))?;
+    let local_addr = listener
+        .local_addr()
+        .map_err(|e| format!("Failed to read explorer API address: {}", e))?;
+
+    let router = Router::new()
+        .route("/status", get(status))
+        .route("/blocks", get(blocks))
+        .route("/blocks/{id}", get(block))
+        .route("/transactions/{hash}", get(transaction))
+        .route(
+            "/addresses/{address}/transactions",
+            get(address_transactions),
+        )
+        .route("/validators", get(validators))
+        .route("/volumes", get(volumes))
+        .route("/graphql", post(graphql))
+        .route("/graphql/schema", get(graphql_schema))
+        .with_state(ApiState {
+            schema: graphql::schema(store.clone(), client),
+            store,
+        });
+    tokio::spawn(async move {
+        let stopped = async move {
+            let _ = shutdown.wait_for(|stopped| *stopped).await;
+        };
+        if let Err(e) = axum::serve(listener, router)
+            .with_graceful_shutdown(stopped)
+            .await
+        {
+            tracing::error!(error = %e, "Explorer API stopped");
+        }
+    });
+
+    Ok(local_addr)
+}
+
+/// A failed request, answered with its status and `{"error": message}`.
+struct ApiError(StatusCode, String);
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.0, axum::Json(json!({ "error": self.1 }))).into_response()
+    }
+}
+
+impl From<String> for ApiError {
+    fn from(message: String) -> Self {
+        tracing::error!(error = %message, "Explorer query failed");
+        ApiError(StatusCode::INTERNAL_SERVER_ERROR, message)
+    }
+}
+
+type ApiResult = Result<axum::Json<Value>, ApiError>;
+
+fn bad_request(message: String) -> ApiError {
+    ApiError(StatusCode::BAD_REQUEST, message)
+}
+
+fn not_found(what: &str) -> ApiError {
+    ApiError(StatusCode::NOT_FOUND, format!("No such {}", what))
+}
+
+#[derive(Deserialize)]
+struct Page {
+    before: Option<u64>,
+    offset: Option<u64>,
+    limit: Option<u64>,
+    days: Option<u64>,
+}
+
+impl Page {
+    fn limit(&self) -> u64 {
+        self.limit.unwrap_or(25).min(MAX_PAGE)
+    }
+}
+
+async fn status(State(store): State<Arc<Store>>) -> ApiResult {
+    let tip = store.tip()?;
+    Ok(axum::Json(json!({
+        "height": tip.map(|(height, _)| height),
+        "hash": tip.map(|(_, hash)| hex::encode(hash)),
+    })))
+}
+
+async fn blocks(State(store): State<Arc<Store>>, Query(page): Query<Page>) -> ApiResult {
+    let blocks = store.blocks(page.before, page.limit())?;
+    // Where the next page starts, when this one came back full.
+    let next = (blocks.len() as u64 == page.limit())
+        .then(|| blocks.last().map(|block| block.height))
+        .flatten();
+    Ok(axum::Json(json!({
+        "blocks": blocks.iter().map(summary_json).collect::<Vec<_>>(),
+        "next": next,
+    })))
+}
+
+async fn block(State(store): State<Arc<Store>>, Path(id): Path<String>) -> ApiResult {
+    let id = match id.parse() {
+        Ok(height) => BlockId::Height(height),
+        Err(_) => BlockId::Hash(parse_hash(&id).map_err(bad_request)?),
+    };
+    let summary = store.block_summary(id)?.ok_or(not_found("block"))?;
+    let block = store.block(id)?.ok_or(not_found("block"))?;
+    let mut json = summary_json(&summary);
+    json["transactions"] = block.transactions.iter().map(tx_json).collect();
+    Ok(axum::Json(json))
+}
+
+async fn transaction(State(store): State<Arc<Store>>, Path(hash): Path<String>) -> ApiResult {
+    let hash = parse_hash(&hash).map_err(bad_request)?;
+    let tx = store.transaction(&hash)?.ok_or(not_found("transaction"))?;
+    Ok(axum::Json(indexed_tx_json(&tx)))
+}
+
+async fn address_transactions(
+    State(store): State<Arc<Store>>,
+    Path(address): Path<String>,
+    Query(page): Query<Page>,
+) -> ApiResult {
+    let address = parse_hash(&address).map_err(bad_request)?;
+    let offset = page.offset.unwrap_or(0);
+    let transactions = store.address_transactions(&address, offset, page.limit())?;
+    Ok(axum::Json(json!({
+        "transactions": transactions.iter().map(indexed_tx_json).collect::<Vec<_>>(),
+        "next_offset": (transactions.len() as u64 == page.limit())
+            .then_some(offset + page.limit()),
+    })))
+}
+
+async fn validators(State(store): State<Arc<Store>>) -> ApiResult {
+    let validators = store.validators()?;
+    Ok(axum::Json(json!({
+        "validators": validators.iter().map(validator_json).collect::<Vec<_>>(),
+    })))
+}
+
+async fn volumes(State(store): State<Arc<Store>>, Query(page): Query<Page>) -> ApiResult {
+    let days = store.daily_volumes(page.days.unwrap_or(30).min(366))?;
+    Ok(axum::Json(json!({
+        "days": days.iter().map(volume_json).collect::<Vec<_>>(),
+    })))
+}
+
+async fn graphql(
+    State(schema): State<ChainSchema>,
+    axum::Json(request): axum::Json<async_graphql::Request>,
+) -> axum::Json<async_graphql::Response> {
+    axum::Json(schema.execute(request).await)
+}
+
+async fn graphql_schema(State(schema): State<ChainSchema>) -> String {
+    schema.sdl()
+}
+
+fn summary_json(block: &BlockSummary) -> Value {
+    json!({
+        "hash": hex::encode(block.hash),
+        "previous_hash": hex::encode(block.previous_hash),
+        "height": block.height,
+        "timestamp": block.timestamp,
+        "proposer": hex::encode(block.proposer),
+        "coinbase": block.coinbase.to_string(),
+        "transaction_count": block.transactions,
+        "fees": block.fees.to_string(),
+    })
+}
+
+fn tx_json(tx: &TxRecord) -> Value {
+    json!({
+        "hash": hex::encode(tx.hash),
+        "sender": hex::encode(tx.sender),
+        "receiver": hex::encode(tx.receiver),
+        "amount": tx.amount.to_string(),
+        "fee": tx.fee.to_string(),
+        "nonce": tx.nonce,
+        "kind": tx.kind.as_str(),
+    })
+}
+
+fn indexed_tx_json(indexed: &IndexedTx) -> Value {
+    let mut json = tx_json(&indexed.tx);
+    json["height"] = json!(indexed.height);
+    json["position"] = json!(indexed.position);
+    json["timestamp"] = json!(indexed.timestamp);
+    json
+}
+
+fn validator_json(validator: &ValidatorStats) -> Value {
+    json!({
+        "address": hex::encode(validator.address),
+        "blocks_proposed": validator.blocks_proposed,
+        "rewards": validator.rewards.to_string(),
+        "last_height": validator.last_height,
+    })
+}
+
+fn volume_json(volume: &DailyVolume) -> Value {
+    json!({
+        "day": volume.day,
+        "blocks": volume.blocks,
+        "transactions": volume.transactions,
+        "volume": volume.volume.to_string(),
+        "fees": volume.fees.to_string(),
+    })
+}
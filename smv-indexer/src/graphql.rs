@@ -0,0 +1,367 @@
+//! A GraphQL view of the indexed chain, served at `POST /graphql`.
+//!
+//! Blocks, transactions, accounts and validators link to each other, so a
+//! frontend can fetch a block with its proposer's balance and each
+//! transaction's sender in one query rather than many JSON-RPC calls.
+//! Balances, stakes and nonces are asked of the node when a query selects
+//! them; everything else comes from the indexed tables. Lists are paged
+//! like the HTTP API: `first` bounds a page at [`MAX_PAGE`] and `next`
+//! gives where the following page starts.
+//!
+//! ```graphql
+//! {
+//!   blocks(first: 5) {
+//!     blocks { height proposer { address balance } transactions { hash amount } }
+//!     next
+//!   }
+//! }
+//! ```
+
+use crate::api::MAX_PAGE;
+use crate::store::{BlockSummary, DailyVolume, IndexedTx, Store, ValidatorStats};
+use async_graphql::{
+    Context, EmptyMutation, EmptySubscription, Object, Result, Schema, SimpleObject,
+};
+use smv_client::{Address, BlockId, Client, parse_hash};
+use std::sync::Arc;
+
+/// How deeply a query may nest relations.
+const MAX_DEPTH: usize = 10;
+
+pub type ChainSchema = Schema<QueryRoot, EmptyMutation, EmptySubscription>;
+
+/// The schema over `store`, asking `client`'s node for account state.
+pub fn schema(store: Arc<Store>, client: Client) -> ChainSchema {
+    Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+        .data(store)
+        .data(client)
+        .limit_depth(MAX_DEPTH)
+        .finish()
+}
+
+fn store<'a>(ctx: &Context<'a>) -> &'a Arc<Store> {
+    ctx.data_unchecked::<Arc<Store>>()
+}
+
+fn page_size(first: Option<u64>) -> u64 {
+    first.unwrap_or(25).min(MAX_PAGE)
+}
+
+fn address(hex: &str) -> Result<Address> {
+    Ok(parse_hash(hex).map_err(|e| format!("Invalid address: {}", e))?)
+}
+
+pub struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    /// The highest block indexed.
+    async fn head(&self, ctx: &Context<'_>) -> Result<Option<Block>> {
+        let Some((height, _)) = store(ctx).tip()? else {
+            return Ok(None);
+        };
+        Ok(store(ctx)
+            .block_summary(BlockId::Height(height))?
+            .map(Block))
+    }
+
+    /// A block by height or by hex hash.
+    async fn block(
+        &self,
+        ctx: &Context<'_>,
+        height: Option<u64>,
+        hash: Option<String>,
+    ) -> Result<Option<Block>> {
+        let id = match (height, hash) {
+            (Some(height), None) => BlockId::Height(height),
+            (None, Some(hash)) => BlockId::Hash(parse_hash(&hash)?),
+            _ => return Err("Give exactly one of height and hash".into()),
+        };
+        Ok(store(ctx).block_summary(id)?.map(Block))
+    }
+
+    /// The newest blocks below `before`, newest first.
+    async fn blocks(
+        &self,
+        ctx: &Context<'_>,
+        before: Option<u64>,
+        first: Option<u64>,
+    ) -> Result<BlockPage> {
+        let limit = page_size(first);
+        Ok(BlockPage::new(store(ctx).blocks(before, limit)?, limit))
+    }
+
+    async fn transaction(&self, ctx: &Context<'_>, hash: String) -> Result<Option<Transaction>> {
+        Ok(store(ctx)
+            .transaction(&parse_hash(&hash)?)?
+            .map(Transaction))
+    }
+
+    async fn account(&self, address: String) -> Result<Account> {
+        Ok(Account(self::address(&address)?))
+    }
+
+    /// Every validator that has proposed an indexed block, busiest first.
+    async fn validators(&self, ctx: &Context<'_>) -> Result<Vec<Validator>> {
+        Ok(store(ctx)
+            .validators()?
+            .into_iter()
+            .map(Validator)
+            .collect())
+    }
+
+    /// Activity on the latest `days` days that had blocks, newest first.
+    async fn volumes(&self, ctx: &Context<'_>, days: Option<u64>) -> Result<Vec<Volume>> {
+        Ok(store(ctx)
+            .daily_volumes(days.unwrap_or(30).min(366))?
+            .into_iter()
+            .map(Volume::from)
+            .collect())
+    }
+}
+
+pub struct Block(BlockSummary);
+
+#[Object]
+impl Block {
+    async fn hash(&self) -> String {
+        hex::encode(self.0.hash)
+    }
+
+    async fn previous_hash(&self) -> String {
+        hex::encode(self.0.previous_hash)
+    }
+
+    async fn height(&self) -> u64 {
+        self.0.height
+    }
+
+    async fn timestamp(&self) -> i64 {
+        self.0.timestamp
+    }
+
+    async fn coinbase(&self) -> String {
+        self.0.coinbase.to_string()
+    }
+
+    async fn fees(&self) -> String {
+        self.0.fees.to_string()
+    }
+
+    async fn transaction_count(&self) -> u64 {
+        self.0.transactions
+    }
+
+    async fn proposer(&self) -> Account {
+        Account(self.0.proposer)
+    }
+
+    /// The block this one extends, unless it is the first indexed.
+    async fn parent(&self, ctx: &Context<'_>) -> Result<Option<Block>> {
+        let Some(height) = self.0.height.checked_sub(1) else {
+            return Ok(None);
+        };
+        Ok(store(ctx)
+            .block_summary(BlockId::Height(height))?
+            .map(Block))
+    }
+
+    async fn transactions(&self, ctx: &Context<'_>) -> Result<Vec<Transaction>> {
+        Ok(store(ctx)
+            .block_transactions(&self.0)?
+            .into_iter()
+            .map(Transaction)
+            .collect())
+    }
+}
+
+pub struct Transaction(IndexedTx);
+
+#[Object]
+impl Transaction {
+    async fn hash(&self) -> String {
+        hex::encode(self.0.tx.hash)
+    }
+
+    async fn amount(&self) -> String {
+        self.0.tx.amount.to_string()
+    }
+
+    async fn fee(&self) -> String {
+        self.0.tx.fee.to_string()
+    }
+
+    async fn nonce(&self) -> u64 {
+        self.0.tx.nonce
+    }
+
+    /// `transfer`, `stake` or `unstake`.
+    async fn kind(&self) -> &str {
+        self.0.tx.kind.as_str()
+    }
+
+    async fn height(&self) -> u64 {
+        self.0.height
+    }
+
+    /// Where the transaction sits in its block, from 0.
+    async fn position(&self) -> u64 {
+        self.0.position
+    }
+
+    async fn timestamp(&self) -> i64 {
+        self.0.timestamp
+    }
+
+    async fn sender(&self) -> Account {
+        Account(self.0.tx.sender)
+    }
+
+    async fn receiver(&self) -> Account {
+        Account(self.0.tx.receiver)
+    }
+
+    async fn block(&self, ctx: &Context<'_>) -> Result<Option<Block>> {
+        Ok(store(ctx)
+            .block_summary(BlockId::Height(self.0.height))?
+            .map(Block))
+    }
+}
+
+pub struct Account(Address);
+
+impl Account {
+    async fn state(&self, ctx: &Context<'_>) -> Result<smv_client::Account> {
+        Ok(ctx.data_unchecked::<Client>().balance(&self.0).await?)
+    }
+}
+
+#[Object]
+impl Account {
+    async fn address(&self) -> String {
+        hex::encode(self.0)
+    }
+
+    /// Spendable balance, as the node has it now.
+    async fn balance(&self, ctx: &Context<'_>) -> Result<String> {
+        Ok(self.state(ctx).await?.balance.to_string())
+    }
+
+    /// Amount staked, as the node has it now.
+    async fn stake(&self, ctx: &Context<'_>) -> Result<String> {
+        Ok(self.state(ctx).await?.stake.to_string())
+    }
+
+    /// The nonce the account's next transaction must carry.
+    async fn nonce(&self, ctx: &Context<'_>) -> Result<u64> {
+        Ok(ctx.data_unchecked::<Client>().nonce(&self.0).await?)
+    }
+
+    /// Transactions to or from the account, newest first, skipping the
+    /// newest `offset`.
+    async fn transactions(
+        &self,
+        ctx: &Context<'_>,
+        offset: Option<u64>,
+        first: Option<u64>,
+    ) -> Result<TransactionPage> {
+        let offset = offset.unwrap_or(0);
+        let limit = page_size(first);
+        let transactions = store(ctx).address_transactions(&self.0, offset, limit)?;
+        Ok(TransactionPage {
+            next: (transactions.len() as u64 == limit).then_some(offset + limit),
+            transactions: transactions.into_iter().map(Transaction).collect(),
+        })
+    }
+
+    /// The account's record as a proposer, if it has proposed a block.
+    async fn validator(&self, ctx: &Context<'_>) -> Result<Option<Validator>> {
+        Ok(store(ctx).validator(&self.0)?.map(Validator))
+    }
+}
+
+pub struct Validator(ValidatorStats);
+
+#[Object]
+impl Validator {
+    async fn account(&self) -> Account {
+        Account(self.0.address)
+    }
+
+    async fn blocks_proposed(&self) -> u64 {
+        self.0.blocks_proposed
+    }
+
+    /// Coinbase and fees of the blocks it proposed.
+    async fn rewards(&self) -> String {
+        self.0.rewards.to_string()
+    }
+
+    async fn last_height(&self) -> u64 {
+        self.0.last_height
+    }
+
+    /// The newest blocks it proposed below `before`, newest first.
+    async fn blocks(
+        &self,
+        ctx: &Context<'_>,
+        before: Option<u64>,
+        first: Option<u64>,
+    ) -> Result<BlockPage> {
+        let limit = page_size(first);
+        let blocks = store(ctx).proposed_blocks(&self.0.address, before, limit)?;
+        Ok(BlockPage::new(blocks, limit))
+    }
+}
+
+#[derive(SimpleObject)]
+pub struct BlockPage {
+    blocks: Vec<Block>,
+    /// The `before` that fetches the next page, when this one came back
+    /// full.
+    next: Option<u64>,
+}
+
+impl BlockPage {
+    fn new(blocks: Vec<BlockSummary>, limit: u64) -> Self {
+        let next = (blocks.len() as u64 == limit)
+            .then(|| blocks.last().map(|block| block.height))
+            .flatten();
+        BlockPage {
+            blocks: blocks.into_iter().map(Block).collect(),
+            next,
+        }
+    }
+}
+
+#[derive(SimpleObject)]
+pub struct TransactionPage {
+    transactions: Vec<Transaction>,
+    /// The `offset` that fetches the next page, when this one came back
+    /// full.
+    next: Option<u64>,
+}
+
+/// Activity on one UTC day.
+#[derive(SimpleObject)]
+pub struct Volume {
+    /// As `YYYY-MM-DD`.
+    day: String,
+    blocks: u64,
+    transactions: u64,
+    /// Amount moved by transfers.
+    volume: String,
+    fees: String,
+}
+
+impl From<DailyVolume> for Volume {
+    fn from(volume: DailyVolume) -> Self {
+        Volume {
+            day: volume.day,
+            blocks: volume.blocks,
+            transactions: volume.transactions,
+            volume: volume.volume.to_string(),
+            fees: volume.fees.to_string(),
+        }
+    }
+}
@@ -0,0 +1,127 @@
+//! An explorer indexer for smvblock nodes.
+//!
+//! It follows a node's new blocks over its WebSocket subscription, fetches
+//! each one in full over JSON-RPC, and keeps tables an explorer frontend
+//! needs but the node does not: recent blocks, transactions per address,
+//! what each validator has proposed, and daily volumes. Those are served
+//! read-only over HTTP, and as GraphQL, by [`api::serve`].
+//!
+//! The node is the source of truth. When a block the node reports does not
+//! extend the indexed chain, indexed blocks are removed until it does, so
+//! reorgs are followed as well as new blocks.
+
+pub mod api;
+pub mod graphql;
+pub mod store;
+
+use smv_client::{BlockId, Client, RpcError};
+use std::sync::Arc;
+use std::time::Duration;
+use store::{BlockRecord, Store};
+use tokio::sync::watch;
+
+/// How long to wait before subscribing again once the node goes away.
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+pub struct Indexer {
+    client: Client,
+    store: Arc<Store>,
+}
+
+impl Indexer {
+    pub fn new(client: Client, store: Arc<Store>) -> Self {
+        Indexer { client, store }
+    }
+
+    pub fn store(&self) -> &Arc<Store> {
+        &self.store
+    }
+
+    /// Indexes every block up to the node's head, then each new block as
+    /// the node applies it, until `shutdown` turns true. A node that cannot
+    /// be reached is retried rather than ending the loop.
+    pub async fn run(&self, mut shutdown: watch::Receiver<bool>) {
+        while !*shutdown.borrow() {
+            tokio::select! {
+                result = self.follow() => {
+                    if let Err(e) = result {
+                        tracing::warn!(error = %e, "Lost the node, reconnecting");
+                    }
+                }
+                _ = shutdown.wait_for(|stopped| *stopped) => return,
+            }
+            tokio::select! {
+                _ = tokio::time::sleep(RECONNECT_DELAY) => {}
+                _ = shutdown.wait_for(|stopped| *stopped) => return,
+            }
+        }
+    }
+
+    /// Catches up with the node, then indexes blocks as they are announced
+    /// until the subscription ends.
+    async fn follow(&self) -> Result<(), String> {
+        // Subscribing first means no block applied while catching up is
+        // missed.
+        let mut heads = self.client.subscribe_blocks().await.map_err(rpc_error)?;
+        if let Some(height) = self.client.status().await.map_err(rpc_error)?.height {
+            self.catch_up(height).await?;
+        }
+        while let Some(head) = heads.next().await {
+            self.catch_up(head.map_err(rpc_error)?.height).await?;
+        }
+        Err("The node closed the subscription".to_string())
+    }
+
+    /// Indexes the node's blocks up to `height`, first removing indexed
+    /// blocks the node no longer has on its chain.
+    pub async fn catch_up(&self, height: u64) -> Result<(), String> {
+        // The block at `height` itself is checked even when indexed, in
+        // case the node replaced it.
+        let mut next = self
+            .store
+            .tip()?
+            .map_or(0, |(tip, _)| (tip + 1).min(height));
+        while next <= height {
+            let Some(block) = self
+                .client
+                .rpc()
+                .block(BlockId::Height(next))
+                .await
+                .map_err(rpc_error)?
+            else {
+                // The node's head moved back; a later head picks this up.
+                return Ok(());
+            };
+            let block = BlockRecord::from_json(&block)?;
+            if self.store.hash_at(next)? == Some(block.hash) {
+                next += 1;
+                continue;
+            }
+            let extends = match next.checked_sub(1) {
+                Some(parent) => self.store.hash_at(parent)? == Some(block.previous_hash),
+                None => true,
+            };
+            let above_tip = self.store.tip()?.is_none_or(|(tip, _)| tip < next);
+            if extends && above_tip {
+                self.store.insert_block(&block)?;
+                tracing::debug!(height = next, "Indexed block");
+                next += 1;
+            } else {
+                let removed = self
+                    .store
+                    .remove_tip()?
+                    .ok_or("Nothing indexed below a mismatched parent")?;
+                tracing::info!(
+                    height = removed,
+                    "Removed a block the node reorganized away"
+                );
+                next = next.min(removed);
+            }
+        }
+        Ok(())
+    }
+}
+
+fn rpc_error(e: RpcError) -> String {
+    e.to_string()
+}
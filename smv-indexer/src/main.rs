@@ -0,0 +1,56 @@
+use clap::Parser;
+use smv_client::Client;
+use smv_indexer::{Indexer, api, store::Store};
+use smvblock::logging;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::sync::watch;
+
+/// Indexes an smvblock node's chain for an explorer and serves the tables
+/// over HTTP.
+#[derive(Parser)]
+#[command(version)]
+struct Args {
+    /// The node's RPC server, as `http://HOST:PORT`. Several separated by
+    /// commas are tried in turn when one cannot be reached.
+    #[arg(long, env = "SMVBLOCK_NODE")]
+    node: String,
+    /// Where to keep the explorer tables.
+    #[arg(long, default_value = "explorer.db")]
+    db: PathBuf,
+    /// Address to serve the explorer API on.
+    #[arg(long, default_value = "127.0.0.1:8080")]
+    listen: SocketAddr,
+    /// Log filter, e.g. `info` or `info,smv_indexer=debug`.
+    #[arg(long, default_value = "info")]
+    log_level: String,
+    /// Log as JSON lines instead of text.
+    #[arg(long)]
+    log_json: bool,
+}
+
+#[tokio::main]
+async fn main() {
+    let args = Args::parse();
+    if let Err(e) = run(args).await {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+async fn run(args: Args) -> Result<(), String> {
+    logging::init(&args.log_level, args.log_json)?;
+    let client = Client::connect(&args.node).map_err(|e| e.to_string())?;
+    let store = Arc::new(Store::open(Some(&args.db))?);
+
+    let (shutdown, stopped) = watch::channel(false);
+    let addr = api::serve(store.clone(), client.clone(), args.listen, stopped.clone()).await?;
+    println!("Serving the explorer API on {}", addr);
+
+    let indexer = Indexer::new(client, store);
+    let indexing = tokio::spawn(async move { indexer.run(stopped).await });
+    let _ = tokio::signal::ctrl_c().await;
+    let _ = shutdown.send(true);
+    indexing.await.map_err(|e| format!("Indexer failed: {}", e))
+}
@@ -0,0 +1,663 @@
+//! The explorer tables, kept in SQLite.
+//!
+//! Blocks and their transactions are stored as the node reported them;
+//! validator statistics and daily volumes are running totals updated as
+//! each block is added and undone when a reorg removes it.
+
+use chrono::DateTime;
+use rusqlite::{Connection, OptionalExtension, Row, params};
+use serde_json::Value;
+use smv_client::{Address, Amount, BlockId, Hash, TxKind, parse_hash};
+use std::path::Path;
+use std::sync::Mutex;
+
+/// A block on the node's chain, with its transactions.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BlockRecord {
+    pub hash: Hash,
+    pub previous_hash: Hash,
+    pub height: u64,
+    pub timestamp: i64,
+    pub proposer: Address,
+    pub coinbase: Amount,
+    pub transactions: Vec<TxRecord>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TxRecord {
+    pub hash: Hash,
+    pub sender: Address,
+    pub receiver: Address,
+    pub amount: Amount,
+    pub fee: Amount,
+    pub nonce: u64,
+    pub kind: TxKind,
+}
+
+impl BlockRecord {
+    /// Reads a block as `chain_getBlock` gives it.
+    pub fn from_json(block: &Value) -> Result<Self, String> {
+        let transactions = block["transactions"]
+            .as_array()
+            .ok_or("Missing transactions in block")?
+            .iter()
+            .map(TxRecord::from_json)
+            .collect::<Result<_, _>>()?;
+        Ok(BlockRecord {
+            hash: hash_field(block, "hash")?,
+            previous_hash: hash_field(block, "previous_hash")?,
+            height: block["height"].as_u64().ok_or("Missing height in block")?,
+            timestamp: block["timestamp"]
+                .as_i64()
+                .ok_or("Missing timestamp in block")?,
+            proposer: hash_field(block, "proposer")?,
+            coinbase: str_field(block, "coinbase")?.parse()?,
+            transactions,
+        })
+    }
+
+    /// Fees of every transaction in the block together.
+    pub fn fees(&self) -> Result<Amount, String> {
+        Amount::checked_sum(self.transactions.iter().map(|tx| tx.fee)).map_err(|e| e.to_string())
+    }
+
+    /// The UTC day the block was made on, as `YYYY-MM-DD`.
+    fn day(&self) -> Result<String, String> {
+        DateTime::from_timestamp(self.timestamp, 0)
+            .map(|time| time.date_naive().to_string())
+            .ok_or(format!("Block {} has an invalid timestamp", self.height))
+    }
+}
+
+impl TxRecord {
+    fn from_json(tx: &Value) -> Result<Self, String> {
+        Ok(TxRecord {
+            hash: hash_field(tx, "hash")?,
+            sender: hash_field(tx, "sender")?,
+            receiver: hash_field(tx, "receiver")?,
+            amount: str_field(tx, "amount")?.parse()?,
+            fee: str_field(tx, "fee")?.parse()?,
+            nonce: tx["nonce"].as_u64().ok_or("Missing nonce in transaction")?,
+            kind: str_field(tx, "kind")?.parse()?,
+        })
+    }
+}
+
+fn str_field<'a>(value: &'a Value, name: &str) -> Result<&'a str, String> {
+    value[name]
+        .as_str()
+        .ok_or(format!("Missing {} in response", name))
+}
+
+fn hash_field(value: &Value, name: &str) -> Result<Hash, String> {
+    parse_hash(str_field(value, name)?).map_err(|e| format!("Invalid {}: {}", name, e))
+}
+
+/// A block without its transactions, as explorer listings show it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BlockSummary {
+    pub hash: Hash,
+    pub previous_hash: Hash,
+    pub height: u64,
+    pub timestamp: i64,
+    pub proposer: Address,
+    pub coinbase: Amount,
+    pub transactions: u64,
+    pub fees: Amount,
+}
+
+/// A transaction with where it sits on the chain.
+#[derive(Clone, Debug, PartialEq)]
+pub struct IndexedTx {
+    pub height: u64,
+    pub position: u64,
+    pub timestamp: i64,
+    pub tx: TxRecord,
+}
+
+/// What a validator has produced on the indexed chain.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ValidatorStats {
+    pub address: Address,
+    pub blocks_proposed: u64,
+    /// Coinbase and fees of the blocks it proposed.
+    pub rewards: Amount,
+    pub last_height: u64,
+}
+
+/// Activity on one UTC day.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DailyVolume {
+    pub day: String,
+    pub blocks: u64,
+    pub transactions: u64,
+    /// Amount moved by transfers; stakes and unstakes are not counted.
+    pub volume: Amount,
+    pub fees: Amount,
+}
+
+pub struct Store {
+    conn: Mutex<Connection>,
+}
+
+impl Store {
+    /// Opens the tables at `path`, creating them if needed, or keeps them
+    /// in memory when `path` is `None`.
+    pub fn open(path: Option<&Path>) -> Result<Self, String> {
+        let conn = match path {
+            Some(path) => Connection::open(path),
+            None => Connection::open_in_memory(),
+        }
+        .map_err(db_error)?;
+        create_tables(&conn).map_err(db_error)?;
+        Ok(Store {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    fn conn(&self) -> std::sync::MutexGuard<'_, Connection> {
+        self.conn.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Height and hash of the highest block indexed.
+    pub fn tip(&self) -> Result<Option<(u64, Hash)>, String> {
+        self.conn()
+            .query_row(
+                "SELECT height, hash FROM blocks ORDER BY height DESC LIMIT 1",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()
+            .map_err(db_error)
+    }
+
+    pub fn hash_at(&self, height: u64) -> Result<Option<Hash>, String> {
+        self.conn()
+            .query_row(
+                "SELECT hash FROM blocks WHERE height = ?1",
+                [height],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(db_error)
+    }
+
+    /// Adds the block above the current tip.
+    pub fn insert_block(&self, block: &BlockRecord) -> Result<(), String> {
+        let fees = block.fees()?;
+        let rewards = block
+            .coinbase
+            .checked_add(fees)
+            .map_err(|e| e.to_string())?;
+        let volume = Amount::checked_sum(
+            block
+                .transactions
+                .iter()
+                .filter(|tx| tx.kind == TxKind::Transfer)
+                .map(|tx| tx.amount),
+        )
+        .map_err(|e| e.to_string())?;
+        let day = block.day()?;
+
+        let mut conn = self.conn();
+        let tx = conn.transaction().map_err(db_error)?;
+        tx.execute(
+            "INSERT INTO blocks (height, hash, previous_hash, timestamp, proposer, coinbase, transactions, fees)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![
+                block.height,
+                block.hash,
+                block.previous_hash,
+                block.timestamp,
+                block.proposer,
+                block.coinbase,
+                block.transactions.len() as u64,
+                fees,
+            ],
+        )
+        .map_err(db_error)?;
+        for (position, record) in block.transactions.iter().enumerate() {
+            tx.execute(
+                "INSERT INTO transactions (height, position, hash, sender, receiver, amount, fee, nonce, kind)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+                params![
+                    block.height,
+                    position as u64,
+                    record.hash,
+                    record.sender,
+                    record.receiver,
+                    record.amount,
+                    record.fee,
+                    record.nonce,
+                    record.kind.as_str(),
+                ],
+            )
+            .map_err(db_error)?;
+        }
+
+        let (blocks_proposed, previous_rewards) = tx
+            .query_row(
+                "SELECT blocks_proposed, rewards FROM validators WHERE address = ?1",
+                [block.proposer],
+                |row| Ok((row.get::<_, u64>(0)?, row.get::<_, Amount>(1)?)),
+            )
+            .optional()
+            .map_err(db_error)?
+            .unwrap_or((0, Amount::ZERO));
+        tx.execute(
+            "INSERT OR REPLACE INTO validators (address, blocks_proposed, rewards, last_height)
+             VALUES (?1, ?2, ?3, ?4)",
+            params![
+                block.proposer,
+                blocks_proposed + 1,
+                previous_rewards
+                    .checked_add(rewards)
+                    .map_err(|e| e.to_string())?,
+                block.height,
+            ],
+        )
+        .map_err(db_error)?;
+
+        let mut today = tx
+            .query_row(
+                "SELECT day, blocks, transactions, volume, fees FROM daily_volumes WHERE day = ?1",
+                [&day],
+                daily_volume,
+            )
+            .optional()
+            .map_err(db_error)?
+            .unwrap_or(DailyVolume {
+                day,
+                blocks: 0,
+                transactions: 0,
+                volume: Amount::ZERO,
+                fees: Amount::ZERO,
+            });
+        today.blocks += 1;
+        today.transactions += block.transactions.len() as u64;
+        today.volume = today
+            .volume
+            .checked_add(volume)
+            .map_err(|e| e.to_string())?;
+        today.fees = today.fees.checked_add(fees).map_err(|e| e.to_string())?;
+        write_daily_volume(&tx, &today).map_err(db_error)?;
+
+        tx.commit().map_err(db_error)
+    }
+
+    /// Removes the highest block, undoing what it added to the statistics,
+    /// and returns its height.
+    pub fn remove_tip(&self) -> Result<Option<u64>, String> {
+        let Some((height, _)) = self.tip()? else {
+            return Ok(None);
+        };
+        let block = self.block(BlockId::Height(height))?.ok_or("Tip vanished")?;
+        let fees = block.fees()?;
+        let rewards = block
+            .coinbase
+            .checked_add(fees)
+            .map_err(|e| e.to_string())?;
+        let volume = Amount::checked_sum(
+            block
+                .transactions
+                .iter()
+                .filter(|tx| tx.kind == TxKind::Transfer)
+                .map(|tx| tx.amount),
+        )
+        .map_err(|e| e.to_string())?;
+        let day = block.day()?;
+
+        let mut conn = self.conn();
+        let tx = conn.transaction().map_err(db_error)?;
+        tx.execute("DELETE FROM transactions WHERE height = ?1", [height])
+            .map_err(db_error)?;
+        tx.execute("DELETE FROM blocks WHERE height = ?1", [height])
+            .map_err(db_error)?;
+
+        let (blocks_proposed, previous_rewards) = tx
+            .query_row(
+                "SELECT blocks_proposed, rewards FROM validators WHERE address = ?1",
+                [block.proposer],
+                |row| Ok((row.get::<_, u64>(0)?, row.get::<_, Amount>(1)?)),
+            )
+            .map_err(db_error)?;
+        if blocks_proposed <= 1 {
+            tx.execute(
+                "DELETE FROM validators WHERE address = ?1",
+                [block.proposer],
+            )
+            .map_err(db_error)?;
+        } else {
+            let last_height: u64 = tx
+                .query_row(
+                    "SELECT MAX(height) FROM blocks WHERE proposer = ?1",
+                    [block.proposer],
+                    |row| row.get(0),
+                )
+                .map_err(db_error)?;
+            tx.execute(
+                "UPDATE validators SET blocks_proposed = ?2, rewards = ?3, last_height = ?4
+                 WHERE address = ?1",
+                params![
+                    block.proposer,
+                    blocks_proposed - 1,
+                    previous_rewards.saturating_sub(rewards),
+                    last_height,
+                ],
+            )
+            .map_err(db_error)?;
+        }
+
+        let mut today = tx
+            .query_row(
+                "SELECT day, blocks, transactions, volume, fees FROM daily_volumes WHERE day = ?1",
+                [&day],
+                daily_volume,
+            )
+            .map_err(db_error)?;
+        today.blocks -= 1;
+        if today.blocks == 0 {
+            tx.execute("DELETE FROM daily_volumes WHERE day = ?1", [&day])
+                .map_err(db_error)?;
+        } else {
+            today.transactions -= block.transactions.len() as u64;
+            today.volume = today.volume.saturating_sub(volume);
+            today.fees = today.fees.saturating_sub(fees);
+            write_daily_volume(&tx, &today).map_err(db_error)?;
+        }
+
+        tx.commit().map_err(db_error)?;
+        Ok(Some(height))
+    }
+
+    /// The newest blocks below `before`, or below the tip when it is
+    /// `None`, newest first.
+    pub fn blocks(&self, before: Option<u64>, limit: u64) -> Result<Vec<BlockSummary>, String> {
+        let conn = self.conn();
+        let mut statement = conn
+            .prepare(
+                "SELECT hash, previous_hash, height, timestamp, proposer, coinbase, transactions, fees
+                 FROM blocks WHERE height < ?1 ORDER BY height DESC LIMIT ?2",
+            )
+            .map_err(db_error)?;
+        let before = before.map_or(i64::MAX, |height| height.min(i64::MAX as u64) as i64);
+        statement
+            .query_map(params![before, limit], block_summary)
+            .and_then(Iterator::collect)
+            .map_err(db_error)
+    }
+
+    /// The newest blocks `proposer` proposed below `before`, newest first.
+    pub fn proposed_blocks(
+        &self,
+        proposer: &Address,
+        before: Option<u64>,
+        limit: u64,
+    ) -> Result<Vec<BlockSummary>, String> {
+        let conn = self.conn();
+        let mut statement = conn
+            .prepare(
+                "SELECT hash, previous_hash, height, timestamp, proposer, coinbase, transactions, fees
+                 FROM blocks WHERE proposer = ?1 AND height < ?2 ORDER BY height DESC LIMIT ?3",
+            )
+            .map_err(db_error)?;
+        let before = before.map_or(i64::MAX, |height| height.min(i64::MAX as u64) as i64);
+        statement
+            .query_map(params![proposer, before, limit], block_summary)
+            .and_then(Iterator::collect)
+            .map_err(db_error)
+    }
+
+    pub fn block_summary(&self, id: BlockId) -> Result<Option<BlockSummary>, String> {
+        let (clause, key) = block_key(id);
+        self.conn()
+            .query_row(
+                &format!(
+                    "SELECT hash, previous_hash, height, timestamp, proposer, coinbase, transactions, fees
+                     FROM blocks WHERE {} = ?1",
+                    clause
+                ),
+                [key],
+                block_summary,
+            )
+            .optional()
+            .map_err(db_error)
+    }
+
+    /// The block with its transactions in order.
+    pub fn block(&self, id: BlockId) -> Result<Option<BlockRecord>, String> {
+        let Some(summary) = self.block_summary(id)? else {
+            return Ok(None);
+        };
+        let transactions = self.block_transactions(&summary)?;
+        Ok(Some(BlockRecord {
+            hash: summary.hash,
+            previous_hash: summary.previous_hash,
+            height: summary.height,
+            timestamp: summary.timestamp,
+            proposer: summary.proposer,
+            coinbase: summary.coinbase,
+            transactions: transactions.into_iter().map(|indexed| indexed.tx).collect(),
+        }))
+    }
+
+    /// The transactions of `block`, in order.
+    pub fn block_transactions(&self, block: &BlockSummary) -> Result<Vec<IndexedTx>, String> {
+        let conn = self.conn();
+        let mut statement = conn
+            .prepare(
+                "SELECT height, position, hash, sender, receiver, amount, fee, nonce, kind
+                 FROM transactions WHERE height = ?1 ORDER BY position",
+            )
+            .map_err(db_error)?;
+        statement
+            .query_map([block.height], |row| indexed_tx(row, block.timestamp))
+            .and_then(Iterator::collect)
+            .map_err(db_error)
+    }
+
+    pub fn transaction(&self, hash: &Hash) -> Result<Option<IndexedTx>, String> {
+        self.conn()
+            .query_row(
+                "SELECT t.height, t.position, t.hash, t.sender, t.receiver, t.amount, t.fee, t.nonce, t.kind, b.timestamp
+                 FROM transactions t JOIN blocks b ON b.height = t.height
+                 WHERE t.hash = ?1 ORDER BY t.height DESC LIMIT 1",
+                [hash],
+                |row| indexed_tx(row, row.get(9)?),
+            )
+            .optional()
+            .map_err(db_error)
+    }
+
+    /// Transactions to or from `address`, newest first, skipping the
+    /// newest `offset`.
+    pub fn address_transactions(
+        &self,
+        address: &Address,
+        offset: u64,
+        limit: u64,
+    ) -> Result<Vec<IndexedTx>, String> {
+        let conn = self.conn();
+        let mut statement = conn
+            .prepare(
+                "SELECT t.height, t.position, t.hash, t.sender, t.receiver, t.amount, t.fee, t.nonce, t.kind, b.timestamp
+                 FROM transactions t JOIN blocks b ON b.height = t.height
+                 WHERE t.sender = ?1 OR t.receiver = ?1
+                 ORDER BY t.height DESC, t.position DESC LIMIT ?2 OFFSET ?3",
+            )
+            .map_err(db_error)?;
+        statement
+            .query_map(params![address, limit, offset], |row| {
+                indexed_tx(row, row.get(9)?)
+            })
+            .and_then(Iterator::collect)
+            .map_err(db_error)
+    }
+
+    /// Every validator that has proposed an indexed block, busiest first.
+    pub fn validators(&self) -> Result<Vec<ValidatorStats>, String> {
+        let conn = self.conn();
+        let mut statement = conn
+            .prepare(
+                "SELECT address, blocks_proposed, rewards, last_height FROM validators
+                 ORDER BY blocks_proposed DESC, address",
+            )
+            .map_err(db_error)?;
+        statement
+            .query_map([], validator_stats)
+            .and_then(Iterator::collect)
+            .map_err(db_error)
+    }
+
+    pub fn validator(&self, address: &Address) -> Result<Option<ValidatorStats>, String> {
+        self.conn()
+            .query_row(
+                "SELECT address, blocks_proposed, rewards, last_height FROM validators
+                 WHERE address = ?1",
+                [address],
+                validator_stats,
+            )
+            .optional()
+            .map_err(db_error)
+    }
+
+    /// Activity on the latest `days` days that had blocks, newest first.
+    pub fn daily_volumes(&self, days: u64) -> Result<Vec<DailyVolume>, String> {
+        let conn = self.conn();
+        let mut statement = conn
+            .prepare(
+                "SELECT day, blocks, transactions, volume, fees FROM daily_volumes
+                 ORDER BY day DESC LIMIT ?1",
+            )
+            .map_err(db_error)?;
+        statement
+            .query_map([days], daily_volume)
+            .and_then(Iterator::collect)
+            .map_err(db_error)
+    }
+}
+
+fn create_tables(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS blocks (
+            height INTEGER PRIMARY KEY,
+            hash BLOB NOT NULL UNIQUE,
+            previous_hash BLOB NOT NULL,
+            timestamp INTEGER NOT NULL,
+            proposer BLOB NOT NULL,
+            coinbase TEXT NOT NULL,
+            transactions INTEGER NOT NULL,
+            fees TEXT NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS blocks_proposer ON blocks (proposer, height);
+        CREATE TABLE IF NOT EXISTS transactions (
+            height INTEGER NOT NULL,
+            position INTEGER NOT NULL,
+            hash BLOB NOT NULL,
+            sender BLOB NOT NULL,
+            receiver BLOB NOT NULL,
+            amount TEXT NOT NULL,
+            fee TEXT NOT NULL,
+            nonce INTEGER NOT NULL,
+            kind TEXT NOT NULL,
+            PRIMARY KEY (height, position)
+        );
+        CREATE INDEX IF NOT EXISTS transactions_hash ON transactions (hash);
+        CREATE INDEX IF NOT EXISTS transactions_sender ON transactions (sender, height);
+        CREATE INDEX IF NOT EXISTS transactions_receiver ON transactions (receiver, height);
+        CREATE TABLE IF NOT EXISTS validators (
+            address BLOB PRIMARY KEY,
+            blocks_proposed INTEGER NOT NULL,
+            rewards TEXT NOT NULL,
+            last_height INTEGER NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS daily_volumes (
+            day TEXT PRIMARY KEY,
+            blocks INTEGER NOT NULL,
+            transactions INTEGER NOT NULL,
+            volume TEXT NOT NULL,
+            fees TEXT NOT NULL
+        );",
+    )
+}
+
+fn block_key(id: BlockId) -> (&'static str, rusqlite::types::Value) {
+    match id {
+        BlockId::Hash(hash) => ("hash", rusqlite::types::Value::Blob(hash.to_vec())),
+        BlockId::Height(height) => (
+            "height",
+            rusqlite::types::Value::Integer(height.min(i64::MAX as u64) as i64),
+        ),
+    }
+}
+
+fn block_summary(row: &Row) -> rusqlite::Result<BlockSummary> {
+    Ok(BlockSummary {
+        hash: row.get(0)?,
+        previous_hash: row.get(1)?,
+        height: row.get(2)?,
+        timestamp: row.get(3)?,
+        proposer: row.get(4)?,
+        coinbase: row.get(5)?,
+        transactions: row.get(6)?,
+        fees: row.get(7)?,
+    })
+}
+
+fn indexed_tx(row: &Row, timestamp: i64) -> rusqlite::Result<IndexedTx> {
+    let kind: String = row.get(8)?;
+    Ok(IndexedTx {
+        height: row.get(0)?,
+        position: row.get(1)?,
+        timestamp,
+        tx: TxRecord {
+            hash: row.get(2)?,
+            sender: row.get(3)?,
+            receiver: row.get(4)?,
+            amount: row.get(5)?,
+            fee: row.get(6)?,
+            nonce: row.get(7)?,
+            kind: kind.parse().map_err(|e: String| {
+                rusqlite::Error::FromSqlConversionFailure(8, rusqlite::types::Type::Text, e.into())
+            })?,
+        },
+    })
+}
+
+fn validator_stats(row: &Row) -> rusqlite::Result<ValidatorStats> {
+    Ok(ValidatorStats {
+        address: row.get(0)?,
+        blocks_proposed: row.get(1)?,
+        rewards: row.get(2)?,
+        last_height: row.get(3)?,
+    })
+}
+
+fn daily_volume(row: &Row) -> rusqlite::Result<DailyVolume> {
+    Ok(DailyVolume {
+        day: row.get(0)?,
+        blocks: row.get(1)?,
+        transactions: row.get(2)?,
+        volume: row.get(3)?,
+        fees: row.get(4)?,
+    })
+}
+
+fn write_daily_volume(conn: &Connection, volume: &DailyVolume) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO daily_volumes (day, blocks, transactions, volume, fees)
+         VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![
+            volume.day,
+            volume.blocks,
+            volume.transactions,
+            volume.volume,
+            volume.fees,
+        ],
+    )?;
+    Ok(())
+}
+
+fn db_error(e: rusqlite::Error) -> String {
+    format!("Database error: {}", e)
+}
@@ -0,0 +1,176 @@
+use serde_json::{Value, json};
+use smv_client::{Amount, Client, TxKind};
+use smv_indexer::store::{BlockRecord, Store, TxRecord};
+use smv_indexer::{Indexer, api};
+use smvblock::blockchain::User;
+use smvblock::node::{Node, NodeType};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::watch;
+
+async fn get(addr: SocketAddr, path: &str) -> (u16, Value) {
+    let request = format!(
+        "GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n",
+        path, addr
+    );
+    send(addr, request).await
+}
+
+async fn query(addr: SocketAddr, query: &str) -> Value {
+    let body = json!({ "query": query }).to_string();
+    let request = format!(
+        "POST /graphql HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        addr,
+        body.len(),
+        body
+    );
+    send(addr, request).await.1
+}
+
+async fn send(addr: SocketAddr, request: String) -> (u16, Value) {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+
+    let status = response[9..12].parse().unwrap();
+    let (_, body) = response.split_once("\r\n\r\n").unwrap();
+    (status, serde_json::from_str(body).unwrap())
+}
+
+#[tokio::test]
+async fn test_indexes_node_and_serves_explorer_api() {
+    let mut node = Node::new(NodeType::FullNode, true).unwrap();
+    let (sender, key) = User::generate(Amount::from_smv(100));
+    let (receiver, _) = User::generate(Amount::ZERO);
+    node.add_user(sender.clone()).await.unwrap();
+    node.add_user(receiver.clone()).await.unwrap();
+    node.stake(sender.address, Amount::from_smv(10))
+        .await
+        .unwrap();
+    let rpc = node
+        .start_rpc("127.0.0.1:0".parse().unwrap())
+        .await
+        .unwrap();
+    let client = Client::connect(&format!("http://{}", rpc)).unwrap();
+
+    let hash = client
+        .send(&key, receiver.address, Amount::from_smv(5), Amount::ZERO)
+        .await
+        .unwrap();
+    node.produce_block().await.unwrap();
+    let head = node.produce_block().await.unwrap();
+
+    let store = Arc::new(Store::open(None).unwrap());
+    let indexer = Indexer::new(client.clone(), store.clone());
+    indexer.catch_up(1).await.unwrap();
+    assert_eq!(store.tip().unwrap(), Some((1, head)));
+    // Already indexed blocks are not added twice.
+    indexer.catch_up(1).await.unwrap();
+
+    let (_shutdown, stopped) = watch::channel(false);
+    let balance = client.balance(&sender.address).await.unwrap().balance;
+    let addr = api::serve(store, client, "127.0.0.1:0".parse().unwrap(), stopped)
+        .await
+        .unwrap();
+
+    let (_, blocks) = get(addr, "/blocks?limit=1").await;
+    assert_eq!(blocks["blocks"][0]["height"], 1);
+    assert_eq!(blocks["next"], 1);
+    let (_, block) = get(addr, "/blocks/0").await;
+    assert_eq!(block["transactions"][0]["hash"], hex::encode(hash));
+
+    let path = format!("/addresses/{}/transactions", hex::encode(receiver.address));
+    let (_, history) = get(addr, &path).await;
+    assert_eq!(history["transactions"][0]["height"], 0);
+    assert_eq!(history["transactions"][0]["amount"], "5 SMV");
+
+    let (_, validators) = get(addr, "/validators").await;
+    assert_eq!(
+        validators["validators"][0]["address"],
+        hex::encode(sender.address)
+    );
+    assert_eq!(validators["validators"][0]["blocks_proposed"], 2);
+
+    let (_, volumes) = get(addr, "/volumes").await;
+    assert_eq!(volumes["days"][0]["transactions"], 1);
+    assert_eq!(volumes["days"][0]["volume"], "5 SMV");
+
+    let (status, _) = get(addr, &format!("/transactions/{}", hex::encode([7; 32]))).await;
+    assert_eq!(status, 404);
+    let (status, _) = get(addr, "/blocks/nonsense").await;
+    assert_eq!(status, 400);
+
+    let graph = query(
+        addr,
+        &format!(
+            r#"{{
+                head {{ height parent {{ transactions {{ hash sender {{ address }} }} }} }}
+                account(address: "{}") {{
+                    balance
+                    validator {{ blocksProposed blocks(first: 1) {{ blocks {{ height }} next }} }}
+                }}
+            }}"#,
+            hex::encode(sender.address)
+        ),
+    )
+    .await;
+    assert_eq!(graph["errors"], Value::Null, "{}", graph);
+    let data = &graph["data"];
+    assert_eq!(data["head"]["height"], 1);
+    let tx = &data["head"]["parent"]["transactions"][0];
+    assert_eq!(tx["hash"], hex::encode(hash));
+    assert_eq!(tx["sender"]["address"], hex::encode(sender.address));
+    assert_eq!(data["account"]["balance"], balance.to_string());
+    let validator = &data["account"]["validator"];
+    assert_eq!(validator["blocksProposed"], 2);
+    assert_eq!(validator["blocks"]["blocks"][0]["height"], 1);
+    assert_eq!(validator["blocks"]["next"], 1);
+}
+
+#[test]
+fn test_removed_blocks_undo_statistics() {
+    let store = Store::open(None).unwrap();
+    let block = |height: u64, previous_hash, amount| BlockRecord {
+        hash: [height as u8 + 1; 32],
+        previous_hash,
+        height,
+        timestamp: 1_767_225_600 + height as i64,
+        proposer: [9; 32],
+        coinbase: Amount::from_smv(1),
+        transactions: vec![TxRecord {
+            hash: [height as u8 + 100; 32],
+            sender: [1; 32],
+            receiver: [2; 32],
+            amount: Amount::from_smv(amount),
+            fee: Amount::from_base_units(10),
+            nonce: height,
+            kind: TxKind::Transfer,
+        }],
+    };
+    store.insert_block(&block(0, [0; 32], 3)).unwrap();
+    store.insert_block(&block(1, [1; 32], 4)).unwrap();
+    assert_eq!(
+        store.daily_volumes(1).unwrap()[0].volume,
+        Amount::from_smv(7)
+    );
+
+    assert_eq!(store.remove_tip().unwrap(), Some(1));
+    let volume = &store.daily_volumes(1).unwrap()[0];
+    assert_eq!((volume.blocks, volume.volume), (1, Amount::from_smv(3)));
+    let validator = &store.validators().unwrap()[0];
+    assert_eq!(validator.blocks_proposed, 1);
+    assert_eq!(validator.last_height, 0);
+    assert_eq!(
+        validator.rewards,
+        Amount::from_base_units(Amount::ONE_SMV.base_units() + 10)
+    );
+    assert!(store.address_transactions(&[2; 32], 0, 10).unwrap().len() == 1);
+
+    assert_eq!(store.remove_tip().unwrap(), Some(0));
+    assert!(store.validators().unwrap().is_empty());
+    assert!(store.daily_volumes(30).unwrap().is_empty());
+    assert_eq!(store.remove_tip().unwrap(), None);
+}
@@ -0,0 +1,392 @@
+//! Who may call which RPC methods, and from where.
+//!
+//! Methods fall into three roles: public reads of the chain, wallet
+//! methods that change what the node holds, and admin methods about the
+//! node itself. Each RPC listener serves some of the roles, so a node can
+//! answer public reads on one address and keep admin methods on a private
+//! one. When tokens or a JWT secret are configured, wallet and admin
+//! methods also need a credential granting that role or a higher one;
+//! public methods stay open. Without any configured, only callers on the
+//! node's own host get wallet and admin methods, so a listener bound to a
+//! public address exposes nothing but public reads.
+//!
+//! Beyond roles, a listener can allow or deny methods by name and limit
+//! how fast each caller may call, counting callers with a credential by
+//! that credential and everyone else by IP address.
+
+use crate::p2p::TokenBucket;
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use std::sync::Mutex;
+
+/// Callers a listener tracks before it forgets those that have been idle
+/// long enough to have their whole burst back.
+const MAX_TRACKED_CALLERS: usize = 10_000;
+
+/// What a method may do, from least to most privileged.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Role {
+    Public,
+    Wallet,
+    Admin,
+}
+
+impl Role {
+    pub const ALL: [Role; 3] = [Role::Public, Role::Wallet, Role::Admin];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Role::Public => "public",
+            Role::Wallet => "wallet",
+            Role::Admin => "admin",
+        }
+    }
+
+    /// The role `method` belongs to, or `None` for a method the node does
+    /// not have.
+    pub fn of_method(method: &str) -> Option<Role> {
+        match method {
+            "tx_submit" | "tx_submitRaw" | "account_watch" => Some(Role::Wallet),
+            "peers_list" => Some(Role::Admin),
+            "unsubscribe" => Some(Role::Public),
+            _ if method.starts_with("admin_") || method.starts_with("webhook_") => {
+                Some(Role::Admin)
+            }
+            _ if [
+                "chain_",
+                "tx_",
+                "account_",
+                "state_",
+                "stats_",
+                "subscribe_",
+            ]
+            .iter()
+            .any(|prefix| method.starts_with(prefix)) =>
+            {
+                Some(Role::Public)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Role {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "public" => Ok(Role::Public),
+            "wallet" => Ok(Role::Wallet),
+            "admin" => Ok(Role::Admin),
+            _ => Err(format!(
+                "Unknown role {:?}; expected public, wallet or admin",
+                s
+            )),
+        }
+    }
+}
+
+/// An RPC listener: where it binds and whose methods it serves, given as
+/// `ROLES@ADDR` such as `wallet,admin@127.0.0.1:8546`, or as a bare address
+/// to serve every role.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Listener {
+    pub addr: SocketAddr,
+    pub roles: Vec<Role>,
+}
+
+impl FromStr for Listener {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (roles, addr) = match s.split_once('@') {
+            Some((roles, addr)) => (
+                roles
+                    .split(',')
+                    .map(str::parse)
+                    .collect::<Result<Vec<_>, _>>()?,
+                addr,
+            ),
+            None => (Role::ALL.to_vec(), s),
+        };
+        let addr = addr
+            .trim()
+            .parse()
+            .map_err(|e| format!("Invalid listener address {:?}: {}", addr, e))?;
+        Ok(Listener { addr, roles })
+    }
+}
+
+/// A bearer token and the role it grants, given as `ROLE:TOKEN`.
+#[derive(Clone, PartialEq)]
+pub struct Token {
+    pub role: Role,
+    pub token: String,
+}
+
+impl FromStr for Token {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (role, token) = s.split_once(':').ok_or("Expected a token as ROLE:TOKEN")?;
+        if token.is_empty() {
+            return Err("Empty RPC token".to_string());
+        }
+        Ok(Token {
+            role: role.parse()?,
+            token: token.to_string(),
+        })
+    }
+}
+
+/// Keeps tokens out of logs and panics.
+impl fmt::Debug for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Token({}:<redacted>)", self.role)
+    }
+}
+
+/// How fast one caller may call: `burst` requests at once, refilled at
+/// `per_second`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RateLimit {
+    pub per_second: f64,
+    pub burst: f64,
+}
+
+/// How one RPC listener admits calls.
+#[derive(Clone, Debug)]
+pub struct RpcAccess {
+    /// Roles whose methods the listener serves.
+    pub roles: Vec<Role>,
+    /// Bearer tokens accepted, each granting a role.
+    pub tokens: Vec<Token>,
+    /// Secret that JWTs are signed with (HS256). A JWT grants the role in
+    /// its `role` claim until its `exp`, if it has one.
+    pub jwt_secret: Option<Vec<u8>>,
+    /// Origins browsers may call from, or `*` for any. Without any, no
+    /// CORS headers are sent and browsers refuse cross-origin calls.
+    pub cors_origins: Vec<String>,
+    /// Methods served, as names or prefixes ending in `*` such as
+    /// `chain_*`. Empty serves every method the roles allow.
+    pub allowed_methods: Vec<String>,
+    /// Methods refused even if allowed, in the same form.
+    pub denied_methods: Vec<String>,
+    /// How fast each caller may call; unlimited when `None`.
+    pub rate_limit: Option<RateLimit>,
+}
+
+impl Default for RpcAccess {
+    /// Every role, no credentials and no CORS: public methods for anyone,
+    /// the rest for local callers.
+    fn default() -> Self {
+        RpcAccess {
+            roles: Role::ALL.to_vec(),
+            tokens: Vec::new(),
+            jwt_secret: None,
+            cors_origins: Vec::new(),
+            allowed_methods: Vec::new(),
+            denied_methods: Vec::new(),
+            rate_limit: None,
+        }
+    }
+}
+
+impl RpcAccess {
+    /// Whether wallet and admin methods need a credential.
+    pub fn requires_auth(&self) -> bool {
+        !self.tokens.is_empty() || self.jwt_secret.is_some()
+    }
+
+    /// The role `credential`, a bearer token or a JWT, grants a caller at
+    /// `from`. Without one only public methods are granted, unless no
+    /// credentials are configured and the caller is on the loopback
+    /// interface, which is granted every method.
+    pub fn authenticate(&self, credential: Option<&str>, from: IpAddr) -> Result<Role, String> {
+        if !self.requires_auth() {
+            let local = from.to_canonical().is_loopback();
+            return Ok(if local { Role::Admin } else { Role::Public });
+        }
+        let Some(credential) = credential else {
+            return Ok(Role::Public);
+        };
+        // Digests compare in time independent of where the tokens differ.
+        let digest = Sha256::digest(credential.as_bytes());
+        if let Some(token) = self
+            .tokens
+            .iter()
+            .find(|token| Sha256::digest(token.token.as_bytes()) == digest)
+        {
+            return Ok(token.role);
+        }
+        match &self.jwt_secret {
+            Some(secret) if credential.matches('.').count() == 2 => verify_jwt(secret, credential),
+            _ => Err("Invalid RPC credentials".to_string()),
+        }
+    }
+
+    /// Whether a caller granted `granted` may call `method` here.
+    pub fn authorize(&self, granted: Role, method: &str) -> Result<(), AccessError> {
+        let listed = |patterns: &[String]| {
+            patterns
+                .iter()
+                .any(|pattern| match pattern.strip_suffix('*') {
+                    Some(prefix) => method.starts_with(prefix),
+                    None => pattern == method,
+                })
+        };
+        if (!self.allowed_methods.is_empty() && !listed(&self.allowed_methods))
+            || listed(&self.denied_methods)
+        {
+            return Err(AccessError::Filtered);
+        }
+        let Some(required) = Role::of_method(method) else {
+            // Left for the dispatcher to report as unknown.
+            return Ok(());
+        };
+        if !self.roles.contains(&required) {
+            return Err(AccessError::NotServed(required));
+        }
+        if granted < required {
+            return Err(AccessError::Unauthorized(required));
+        }
+        Ok(())
+    }
+}
+
+/// Why a call was refused.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AccessError {
+    /// The listener does not serve methods of this role.
+    NotServed(Role),
+    /// The caller's credential does not grant this role.
+    Unauthorized(Role),
+    /// The listener's allowlist or denylist excludes the method.
+    Filtered,
+}
+
+impl fmt::Display for AccessError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AccessError::NotServed(role) => {
+                write!(f, "This listener does not serve {} methods", role)
+            }
+            AccessError::Unauthorized(role) => {
+                write!(f, "This method needs a credential for the {} role", role)
+            }
+            AccessError::Filtered => f.write_str("This listener does not serve this method"),
+        }
+    }
+}
+
+/// Who a rate limit counts a call against.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum CallerKey {
+    Ip(IpAddr),
+    /// The digest of a credential, so the limiter holds no secrets.
+    Credential([u8; 32]),
+}
+
+impl CallerKey {
+    pub fn credential(credential: &str) -> Self {
+        CallerKey::Credential(Sha256::digest(credential.as_bytes()).into())
+    }
+}
+
+/// Each caller's remaining requests on one listener.
+#[derive(Debug)]
+pub struct CallLimiter {
+    limit: RateLimit,
+    buckets: Mutex<HashMap<CallerKey, TokenBucket>>,
+}
+
+impl CallLimiter {
+    pub fn new(limit: RateLimit) -> Self {
+        CallLimiter {
+            limit,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Takes one request from `caller`'s budget, or returns false if it
+    /// has none left.
+    pub fn allow(&self, caller: &CallerKey) -> bool {
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= MAX_TRACKED_CALLERS && !buckets.contains_key(caller) {
+            buckets.retain(|_, bucket| !bucket.is_full());
+        }
+        buckets
+            .entry(caller.clone())
+            .or_insert_with(|| TokenBucket::new(self.limit.burst, self.limit.per_second))
+            .try_take()
+    }
+}
+
+/// The role an HS256 JWT signed with `secret` grants.
+fn verify_jwt(secret: &[u8], jwt: &str) -> Result<Role, String> {
+    let invalid = |reason: &str| format!("Invalid JWT: {}", reason);
+    let (signed, signature) = jwt.rsplit_once('.').ok_or(invalid("malformed"))?;
+    let (header, claims) = signed.split_once('.').ok_or(invalid("malformed"))?;
+    let decode = |part: &str| -> Result<Value, String> {
+        let bytes = URL_SAFE_NO_PAD
+            .decode(part)
+            .map_err(|_| invalid("bad encoding"))?;
+        serde_json::from_slice(&bytes).map_err(|_| invalid("bad JSON"))
+    };
+    if decode(header)?["alg"] != "HS256" {
+        return Err(invalid("only HS256 is accepted"));
+    }
+
+    let signature = URL_SAFE_NO_PAD
+        .decode(signature)
+        .map_err(|_| invalid("bad encoding"))?;
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).map_err(|e| e.to_string())?;
+    mac.update(signed.as_bytes());
+    mac.verify_slice(&signature)
+        .map_err(|_| invalid("bad signature"))?;
+
+    let claims = decode(claims)?;
+    if let Some(exp) = claims.get("exp") {
+        let exp = exp.as_i64().ok_or(invalid("exp is not a number"))?;
+        if exp <= Utc::now().timestamp() {
+            return Err(invalid("expired"));
+        }
+    }
+    claims["role"]
+        .as_str()
+        .ok_or(invalid("no role claim"))?
+        .parse()
+}
+
+/// Signs an HS256 JWT granting `role` until `exp`, for tools that mint
+/// credentials for a node configured with `secret`.
+pub fn sign_jwt(secret: &[u8], role: Role, exp: Option<i64>) -> String {
+    let header = URL_SAFE_NO_PAD.encode(r#"{"alg":"HS256","typ":"JWT"}"#);
+    let mut claims = serde_json::json!({ "role": role.as_str() });
+    if let Some(exp) = exp {
+        claims["exp"] = exp.into();
+    }
+    let claims = URL_SAFE_NO_PAD.encode(claims.to_string());
+    let signed = format!("{}.{}", header, claims);
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC takes keys of any size");
+    mac.update(signed.as_bytes());
+    format!(
+        "{}.{}",
+        signed,
+        URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes())
+    )
+}
@@ -0,0 +1,196 @@
+use crate::error::BlockchainError;
+use bincode::{Decode, Encode};
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSql, ToSqlOutput, ValueRef};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// A quantity of SMV held as an integer number of base units, where one SMV
+/// is `10^DECIMALS` base units.
+///
+/// There are deliberately no arithmetic operators; every sum and difference
+/// goes through the checked methods so overflow is always handled.
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Deserialize,
+    Serialize,
+    Encode,
+    Decode,
+)]
+pub struct Amount(u128);
+
+impl Amount {
+    pub const DECIMALS: u32 = 8;
+    pub const SYMBOL: &'static str = "SMV";
+    pub const ZERO: Amount = Amount(0);
+    pub const ONE_SMV: Amount = Amount(10u128.pow(Self::DECIMALS));
+
+    pub const fn from_base_units(units: u128) -> Self {
+        Amount(units)
+    }
+
+    /// Whole SMV, e.g. `Amount::from_smv(3)` is 3.00000000 SMV.
+    pub const fn from_smv(smv: u64) -> Self {
+        Amount(smv as u128 * Self::ONE_SMV.0)
+    }
+
+    pub const fn base_units(self) -> u128 {
+        self.0
+    }
+
+    pub const fn is_zero(self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn to_be_bytes(self) -> [u8; 16] {
+        self.0.to_be_bytes()
+    }
+
+    pub fn checked_add(self, rhs: Amount) -> Result<Amount, BlockchainError> {
+        self.0
+            .checked_add(rhs.0)
+            .map(Amount)
+            .ok_or(BlockchainError::Overflow)
+    }
+
+    pub fn checked_sub(self, rhs: Amount) -> Result<Amount, BlockchainError> {
+        self.0
+            .checked_sub(rhs.0)
+            .map(Amount)
+            .ok_or(BlockchainError::Overflow)
+    }
+
+    pub fn saturating_add(self, rhs: Amount) -> Amount {
+        Amount(self.0.saturating_add(rhs.0))
+    }
+
+    pub fn saturating_sub(self, rhs: Amount) -> Amount {
+        Amount(self.0.saturating_sub(rhs.0))
+    }
+
+    /// `numerator / denominator` of this amount, rounded down, such as a
+    /// share of it in proportion to a stake. Zero if `denominator` is.
+    pub fn mul_div(self, numerator: u128, denominator: u128) -> Amount {
+        if denominator == 0 {
+            return Amount::ZERO;
+        }
+        // Split into whole and partial parts so fewer products overflow.
+        let (whole, part) = (self.0 / denominator, self.0 % denominator);
+        Amount(
+            whole
+                .saturating_mul(numerator)
+                .saturating_add(part.saturating_mul(numerator) / denominator),
+        )
+    }
+
+    /// Sums amounts, failing instead of wrapping if the total overflows.
+    pub fn checked_sum<I: IntoIterator<Item = Amount>>(
+        amounts: I,
+    ) -> Result<Amount, BlockchainError> {
+        amounts
+            .into_iter()
+            .try_fold(Amount::ZERO, |total, amount| total.checked_add(amount))
+    }
+}
+
+impl fmt::Display for Amount {
+    /// Formats as a decimal SMV value with trailing zeros dropped, e.g.
+    /// `1.25 SMV` or `3 SMV`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let whole = self.0 / Self::ONE_SMV.0;
+        let fraction = self.0 % Self::ONE_SMV.0;
+
+        if fraction == 0 {
+            return write!(f, "{} {}", whole, Self::SYMBOL);
+        }
+
+        let digits = format!("{:0width$}", fraction, width = Self::DECIMALS as usize);
+        write!(
+            f,
+            "{}.{} {}",
+            whole,
+            digits.trim_end_matches('0'),
+            Self::SYMBOL
+        )
+    }
+}
+
+impl FromStr for Amount {
+    type Err = String;
+
+    /// Parses a decimal SMV value such as `1.25`, `1.25 SMV` or `3`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let s = s.strip_suffix(Self::SYMBOL).unwrap_or(s).trim_end();
+        let invalid = || format!("Invalid amount: {}", s);
+
+        let (whole, fraction) = s.split_once('.').unwrap_or((s, ""));
+        if whole.is_empty() && fraction.is_empty() {
+            return Err(invalid());
+        }
+        if !whole
+            .chars()
+            .chain(fraction.chars())
+            .all(|c| c.is_ascii_digit())
+        {
+            return Err(invalid());
+        }
+        if fraction.len() > Self::DECIMALS as usize {
+            return Err(format!(
+                "Amount has more than {} decimal places: {}",
+                Self::DECIMALS,
+                s
+            ));
+        }
+
+        let whole: u128 = if whole.is_empty() {
+            0
+        } else {
+            whole.parse().map_err(|_| invalid())?
+        };
+        let fraction: u128 = if fraction.is_empty() {
+            0
+        } else {
+            let padded = format!("{:0<width$}", fraction, width = Self::DECIMALS as usize);
+            padded.parse().map_err(|_| invalid())?
+        };
+
+        whole
+            .checked_mul(Self::ONE_SMV.0)
+            .and_then(|units| units.checked_add(fraction))
+            .map(Amount)
+            .ok_or_else(|| format!("Amount out of range: {}", s))
+    }
+}
+
+/// Stored as the decimal string of base units, since SQLite integers cannot
+/// hold a u128.
+impl ToSql for Amount {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.0.to_string()))
+    }
+}
+
+impl FromSql for Amount {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value {
+            ValueRef::Integer(units) => u128::try_from(units)
+                .map(Amount)
+                .map_err(|_| FromSqlError::OutOfRange(units)),
+            ValueRef::Text(text) => std::str::from_utf8(text)
+                .ok()
+                .and_then(|text| text.parse().ok())
+                .map(Amount)
+                .ok_or(FromSqlError::InvalidType),
+            _ => Err(FromSqlError::InvalidType),
+        }
+    }
+}
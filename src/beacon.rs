@@ -0,0 +1,170 @@
+//! Randomness for proposer selection that no single validator controls.
+//! Each epoch a validator may commit to a secret with a `Commit`
+//! transaction, the hash of the secret and its address, and must reveal
+//! the secret with a `Reveal` transaction in the next epoch. The secrets
+//! revealed in an epoch, XORed together and hashed, seed the selection of
+//! slot proposers in the epoch after; see [`crate::liveness`].
+//!
+//! No one knows the seed before the last secret behind it is revealed, and
+//! a validator that withholds its secret to steer the seed is slashed at
+//! the end of the epoch it was due in, the penalty set by
+//! [`Beacon`](crate::params::Beacon). Epochs are those of
+//! [`Liveness::epoch_length`](crate::params::Liveness::epoch_length).
+//!
+//! A validator's secret for an epoch is derived from its signature over
+//! the epoch number, so it can be worked out again to reveal it rather
+//! than kept; see [`secret`].
+//!
+//! State snapshots carry accounts only, so a node started from one knows
+//! of the commitments and reveals made after it and no others.
+
+use crate::amount::Amount;
+use crate::blockchain::{Address, Hash, Transaction, TxData, TxKind, User};
+use crate::db::Database;
+use crate::staking;
+use bincode::{Decode, Encode};
+use ed25519_dalek::{Signer, SigningKey};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::ops::Range;
+
+/// Domain separator so a beacon secret is never a signature made for
+/// anything else.
+const BEACON_DOMAIN: &[u8] = b"smvblock-beacon";
+
+/// A `Commit` transaction: the commitment and the epoch it is made in, so
+/// a commitment left in the mempool past its epoch is dropped rather than
+/// counted against the next.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize, Encode, Decode)]
+pub struct Commit {
+    pub commitment: Hash,
+    pub epoch: u64,
+}
+
+/// The epoch the block at `height` belongs to.
+pub fn epoch(height: u64, epoch_length: u64) -> u64 {
+    height / epoch_length.max(1)
+}
+
+/// The heights of the blocks in `epoch`.
+pub fn epoch_heights(epoch: u64, epoch_length: u64) -> Range<u64> {
+    let epoch_length = epoch_length.max(1);
+    let start = epoch.saturating_mul(epoch_length);
+    start..start.saturating_add(epoch_length)
+}
+
+/// The secret the account `key` signs for commits to in `epoch`.
+pub fn secret(key: &SigningKey, epoch: u64) -> Hash {
+    let mut message = BEACON_DOMAIN.to_vec();
+    message.extend_from_slice(&epoch.to_be_bytes());
+    Sha256::digest(key.sign(&message).to_bytes()).into()
+}
+
+/// What `address` commits to revealing `secret`.
+pub fn commitment(address: &Address, secret: &Hash) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update(BEACON_DOMAIN);
+    hasher.update(address);
+    hasher.update(secret);
+    hasher.finalize().into()
+}
+
+/// The seed of proposer selection in `epoch`, from the secrets revealed in
+/// the epoch before it.
+pub fn seed(epoch: u64, reveals: &[Hash]) -> Hash {
+    let mut mixed = [0u8; 32];
+    for secret in reveals {
+        for (byte, other) in mixed.iter_mut().zip(secret) {
+            *byte ^= other;
+        }
+    }
+    let mut hasher = Sha256::new();
+    hasher.update(BEACON_DOMAIN);
+    hasher.update(epoch.to_be_bytes());
+    hasher.update(mixed);
+    hasher.finalize().into()
+}
+
+/// Checks a `Commit` or `Reveal` transaction from `sender` in the block at
+/// `height` against the commitments and reveals stored: a validator
+/// commits once an epoch, and reveals once the secret behind its
+/// commitment from the epoch before.
+pub(crate) fn check(
+    db: &Database,
+    sender: &User,
+    tx: &Transaction,
+    height: u64,
+    epoch_length: u64,
+) -> Result<(), String> {
+    let current = epoch(height, epoch_length);
+    let fetching = |_| "Error fetching beacon commitments".to_string();
+    let beacon = matches!(tx.payload.kind, TxKind::Commit | TxKind::Reveal);
+    if beacon && tx.payload.amount != Amount::ZERO {
+        return Err(format!(
+            "A {} transaction must have a zero amount",
+            tx.payload.kind.as_str()
+        ));
+    }
+    match tx.payload.data {
+        TxData::Commit(commit) => {
+            if commit.epoch != current {
+                return Err(format!(
+                    "The commitment is for epoch {}, not epoch {}",
+                    commit.epoch, current
+                ));
+            }
+            if !staking::is_validator(sender) {
+                return Err(format!(
+                    "{} is not a validator",
+                    hex::encode(sender.address)
+                ));
+            }
+            let heights = epoch_heights(current, epoch_length);
+            if db
+                .get_commitment(&sender.address, heights)
+                .map_err(fetching)?
+                .is_some()
+            {
+                return Err(format!(
+                    "{} already committed in epoch {}",
+                    hex::encode(sender.address),
+                    current
+                ));
+            }
+        }
+        TxData::Secret(secret) => {
+            let committed = match current.checked_sub(1) {
+                Some(previous) => db
+                    .get_commitment(&sender.address, epoch_heights(previous, epoch_length))
+                    .map_err(fetching)?,
+                None => None,
+            };
+            let Some(committed) = committed else {
+                return Err(format!(
+                    "{} made no commitment in the last epoch",
+                    hex::encode(sender.address)
+                ));
+            };
+            if db
+                .has_revealed(&sender.address, epoch_heights(current, epoch_length))
+                .map_err(fetching)?
+            {
+                return Err(format!(
+                    "{} already revealed in epoch {}",
+                    hex::encode(sender.address),
+                    current
+                ));
+            }
+            if commitment(&sender.address, &secret) != committed {
+                return Err("The secret does not match the commitment".to_string());
+            }
+        }
+        TxData::None
+        | TxData::Proposal(_)
+        | TxData::Ballot(_)
+        | TxData::Commission(_)
+        | TxData::Registration(_)
+        | TxData::ConsensusKey(_) => {}
+    }
+    Ok(())
+}
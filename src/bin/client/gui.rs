@@ -0,0 +1,423 @@
+//! The desktop wallet started by `client --gui`, built only with the `gui`
+//! feature. It shows the wallet's accounts, their history and the node's
+//! status, and sends transfers signed here, all through the node's RPC.
+//!
+//! RPC calls run on the tokio runtime and report back over a channel, so
+//! the window never waits on the node.
+
+use eframe::egui;
+use serde_json::Value;
+use smvblock::amount::Amount;
+use smvblock::blockchain::{Address, Hash};
+use smvblock::client::{Account, RpcClient};
+use smvblock::contacts::Contacts;
+use smvblock::wallet::Wallet;
+use std::sync::mpsc::{Receiver, Sender, channel};
+use std::time::{Duration, Instant};
+use tokio::runtime::Handle;
+
+/// How often balances, history and the node's status are fetched again.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Transactions shown in the history of the selected account.
+const HISTORY_LIMIT: u64 = 50;
+
+/// Fees offered in the send form besides a custom one.
+const FEE_PRESETS: [(&str, Amount); 3] = [
+    ("None", Amount::ZERO),
+    ("Low", Amount::from_base_units(10_000)),
+    ("High", Amount::from_base_units(100_000)),
+];
+
+/// Opens the wallet window, returning once it is closed.
+pub fn run(node: RpcClient, wallet: Wallet, contacts: Contacts) -> Result<(), String> {
+    let app = WalletApp::new(node, wallet, contacts, Handle::current())?;
+    eframe::run_native(
+        "smvblock wallet",
+        eframe::NativeOptions::default(),
+        Box::new(|_| Ok(Box::new(app))),
+    )
+    .map_err(|e| format!("Failed to open the wallet window: {}", e))
+}
+
+/// The answer to an RPC call made in the background.
+enum Update {
+    Balances(Vec<(Address, Result<Account, String>)>),
+    History(Address, Result<Vec<Value>, String>),
+    Status(Result<(Value, Vec<String>), String>),
+    Sent(Result<Hash, String>),
+}
+
+struct AccountRow {
+    name: String,
+    address: Address,
+    balance: Option<Result<Account, String>>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Fee {
+    Preset(usize),
+    Custom,
+}
+
+struct SendForm {
+    to: String,
+    amount: String,
+    fee: Fee,
+    custom_fee: String,
+    password: String,
+    sending: bool,
+    result: Option<Result<String, String>>,
+}
+
+struct WalletApp {
+    node: RpcClient,
+    wallet: Wallet,
+    contacts: Contacts,
+    runtime: Handle,
+    updates: (Sender<Update>, Receiver<Update>),
+    accounts: Vec<AccountRow>,
+    selected: usize,
+    history: Option<Result<Vec<Value>, String>>,
+    status: Option<Result<(Value, Vec<String>), String>>,
+    last_refresh: Option<Instant>,
+    send: SendForm,
+}
+
+impl WalletApp {
+    fn new(
+        node: RpcClient,
+        wallet: Wallet,
+        contacts: Contacts,
+        runtime: Handle,
+    ) -> Result<Self, String> {
+        let accounts = wallet
+            .list()?
+            .into_iter()
+            .map(|(name, address)| AccountRow {
+                name,
+                address,
+                balance: None,
+            })
+            .collect();
+        Ok(WalletApp {
+            node,
+            wallet,
+            contacts,
+            runtime,
+            updates: channel(),
+            accounts,
+            selected: 0,
+            history: None,
+            status: None,
+            last_refresh: None,
+            send: SendForm {
+                to: String::new(),
+                amount: String::new(),
+                fee: Fee::Preset(0),
+                custom_fee: String::new(),
+                password: String::new(),
+                sending: false,
+                result: None,
+            },
+        })
+    }
+
+    /// Runs `call` on the runtime and hands its result to the window.
+    fn spawn<F>(&self, ctx: &egui::Context, call: F)
+    where
+        F: Future<Output = Update> + Send + 'static,
+    {
+        let updates = self.updates.0.clone();
+        let ctx = ctx.clone();
+        self.runtime.spawn(async move {
+            let _ = updates.send(call.await);
+            ctx.request_repaint();
+        });
+    }
+
+    fn refresh(&mut self, ctx: &egui::Context) {
+        self.last_refresh = Some(Instant::now());
+        let node = self.node.clone();
+        let addresses: Vec<Address> = self.accounts.iter().map(|row| row.address).collect();
+        self.spawn(ctx, async move {
+            let mut balances = Vec::new();
+            for address in addresses {
+                balances.push((address, node.balance(&address).await.map_err(String::from)));
+            }
+            Update::Balances(balances)
+        });
+
+        let node = self.node.clone();
+        self.spawn(ctx, async move {
+            let status = async { Ok((node.status().await?, node.peers().await?)) };
+            Update::Status(status.await)
+        });
+
+        self.refresh_history(ctx);
+    }
+
+    fn refresh_history(&mut self, ctx: &egui::Context) {
+        let Some(row) = self.accounts.get(self.selected) else {
+            return;
+        };
+        let node = self.node.clone();
+        let address = row.address;
+        self.spawn(ctx, async move {
+            let history = node.history(&address, HISTORY_LIMIT).await;
+            Update::History(address, history.map_err(String::from))
+        });
+    }
+
+    fn apply(&mut self, update: Update) {
+        match update {
+            Update::Balances(balances) => {
+                for (address, balance) in balances {
+                    if let Some(row) = self.accounts.iter_mut().find(|row| row.address == address) {
+                        row.balance = Some(balance);
+                    }
+                }
+            }
+            // Answers for an account no longer selected are dropped.
+            Update::History(address, history) => {
+                if self.accounts.get(self.selected).map(|row| row.address) == Some(address) {
+                    self.history = Some(history);
+                }
+            }
+            Update::Status(status) => self.status = Some(status),
+            Update::Sent(result) => {
+                self.send.sending = false;
+                self.send.result = Some(result.map(|hash| format!("Sent {}", hex::encode(hash))));
+                if self.send.result.as_ref().is_some_and(Result::is_ok) {
+                    self.send.amount.clear();
+                }
+            }
+        }
+    }
+
+    fn fee(&self) -> Result<Amount, String> {
+        match self.send.fee {
+            Fee::Preset(i) => Ok(FEE_PRESETS[i].1),
+            Fee::Custom => self.send.custom_fee.parse(),
+        }
+    }
+
+    fn submit(&mut self, ctx: &egui::Context) {
+        let Some(row) = self.accounts.get(self.selected) else {
+            return;
+        };
+        let parsed = self.contacts.resolve(&self.send.to).and_then(|to| {
+            let amount: Amount = self.send.amount.parse()?;
+            Ok((to, amount, self.fee()?))
+        });
+        let (to, amount, fee) = match parsed {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                self.send.result = Some(Err(e));
+                return;
+            }
+        };
+        self.send.sending = true;
+        self.send.result = None;
+        let node = self.node.clone();
+        let wallet = self.wallet.clone();
+        let name = row.name.clone();
+        let password = std::mem::take(&mut self.send.password);
+        self.spawn(ctx, async move {
+            // Deriving the key from the password is deliberately slow.
+            let key = tokio::task::spawn_blocking(move || wallet.unlock(&name, &password)).await;
+            let sent = match key {
+                Ok(Ok(key)) => node
+                    .transfer(&key, to, amount, fee)
+                    .await
+                    .map_err(String::from),
+                Ok(Err(e)) => Err(e),
+                Err(e) => Err(e.to_string()),
+            };
+            Update::Sent(sent)
+        });
+    }
+
+    fn accounts_panel(&mut self, ui: &mut egui::Ui, ctx: &egui::Context) {
+        ui.heading("Accounts");
+        if self.accounts.is_empty() {
+            ui.label("No accounts yet. Create one with `client wallet create`.");
+            return;
+        }
+        let mut selected = self.selected;
+        egui::Grid::new("accounts").striped(true).show(ui, |ui| {
+            for (i, row) in self.accounts.iter().enumerate() {
+                ui.selectable_value(&mut selected, i, &row.name);
+                ui.monospace(short(&hex::encode(row.address)));
+                match &row.balance {
+                    None => ui.label("…"),
+                    Some(Ok(account)) => ui.label(account.balance.to_string()),
+                    Some(Err(e)) => ui.label(e).on_hover_text(e),
+                };
+                ui.end_row();
+            }
+        });
+        if selected != self.selected {
+            self.selected = selected;
+            self.history = None;
+            self.refresh_history(ctx);
+        }
+    }
+
+    fn send_panel(&mut self, ui: &mut egui::Ui, ctx: &egui::Context) {
+        let Some(row) = self.accounts.get(self.selected) else {
+            return;
+        };
+        ui.heading(format!("Send from {}", row.name));
+        egui::Grid::new("send").num_columns(2).show(ui, |ui| {
+            ui.label("To");
+            ui.text_edit_singleline(&mut self.send.to);
+            ui.end_row();
+            ui.label("Amount");
+            ui.text_edit_singleline(&mut self.send.amount);
+            ui.end_row();
+            ui.label("Fee");
+            ui.horizontal(|ui| {
+                for (i, (label, fee)) in FEE_PRESETS.iter().enumerate() {
+                    ui.radio_value(&mut self.send.fee, Fee::Preset(i), *label)
+                        .on_hover_text(fee.to_string());
+                }
+                ui.radio_value(&mut self.send.fee, Fee::Custom, "Custom");
+                if self.send.fee == Fee::Custom {
+                    ui.text_edit_singleline(&mut self.send.custom_fee);
+                }
+            });
+            ui.end_row();
+            ui.label("Password");
+            ui.add(egui::TextEdit::singleline(&mut self.send.password).password(true));
+            ui.end_row();
+        });
+        ui.horizontal(|ui| {
+            let send = ui.add_enabled(!self.send.sending, egui::Button::new("Send"));
+            if send.clicked() {
+                self.submit(ctx);
+            }
+            if self.send.sending {
+                ui.spinner();
+            }
+            match &self.send.result {
+                Some(Ok(message)) => ui.label(message),
+                Some(Err(e)) => ui.colored_label(ui.visuals().error_fg_color, e),
+                None => ui.label(""),
+            };
+        });
+    }
+
+    fn history_panel(&self, ui: &mut egui::Ui) {
+        ui.heading("History");
+        let transactions = match &self.history {
+            None => {
+                ui.spinner();
+                return;
+            }
+            Some(Err(e)) => {
+                ui.colored_label(ui.visuals().error_fg_color, e);
+                return;
+            }
+            Some(Ok(transactions)) if transactions.is_empty() => {
+                ui.label("No transactions yet.");
+                return;
+            }
+            Some(Ok(transactions)) => transactions,
+        };
+        let own = self
+            .accounts
+            .get(self.selected)
+            .map(|row| hex::encode(row.address));
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            egui::Grid::new("history").striped(true).show(ui, |ui| {
+                for header in ["Height", "Hash", "", "Counterparty", "Amount", "Fee"] {
+                    ui.strong(header);
+                }
+                ui.end_row();
+                for tx in transactions {
+                    let outgoing = tx["sender"].as_str() == own.as_deref();
+                    let counterparty = if outgoing {
+                        &tx["receiver"]
+                    } else {
+                        &tx["sender"]
+                    };
+                    ui.label(tx["block"]["height"].to_string());
+                    ui.monospace(short(tx["hash"].as_str().unwrap_or("")));
+                    ui.label(if outgoing { "to" } else { "from" });
+                    ui.monospace(short(counterparty.as_str().unwrap_or("")));
+                    ui.label(tx["amount"].as_str().unwrap_or("-"));
+                    ui.label(tx["fee"].as_str().unwrap_or("-"));
+                    ui.end_row();
+                }
+            });
+        });
+    }
+
+    fn status_panel(&self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.label(format!("Node {}", self.node));
+            ui.separator();
+            match &self.status {
+                None => {
+                    ui.spinner();
+                }
+                Some(Err(e)) => {
+                    ui.colored_label(ui.visuals().error_fg_color, e);
+                }
+                Some(Ok((status, peers))) => {
+                    ui.label(format!("Height {}", status["height"]));
+                    ui.separator();
+                    ui.label(format!("Finalized {}", status["finalized_height"]));
+                    ui.separator();
+                    ui.label(format!(
+                        "Sync {}",
+                        status["sync"]["state"].as_str().unwrap_or("unknown")
+                    ));
+                    ui.separator();
+                    ui.label(format!("Pending {}", status["pending_transactions"]));
+                    ui.separator();
+                    ui.label(format!("{} peers", peers.len()))
+                        .on_hover_text(peers.join("\n"));
+                }
+            }
+        });
+    }
+}
+
+impl eframe::App for WalletApp {
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        while let Ok(update) = self.updates.1.try_recv() {
+            self.apply(update);
+        }
+        if self
+            .last_refresh
+            .is_none_or(|last| last.elapsed() >= REFRESH_INTERVAL)
+        {
+            self.refresh(ctx);
+        }
+        ctx.request_repaint_after(REFRESH_INTERVAL);
+
+        egui::TopBottomPanel::bottom("status").show(ctx, |ui| self.status_panel(ui));
+        egui::SidePanel::left("accounts").show(ctx, |ui| {
+            self.accounts_panel(ui, ctx);
+            ui.separator();
+            if ui.button("Refresh").clicked() {
+                self.refresh(ctx);
+            }
+        });
+        egui::CentralPanel::default().show(ctx, |ui| {
+            self.send_panel(ui, ctx);
+            ui.separator();
+            self.history_panel(ui);
+        });
+    }
+}
+
+/// The start and end of a long hex string.
+fn short(hex: &str) -> String {
+    match (hex.get(..8), hex.get(hex.len().saturating_sub(6)..)) {
+        (Some(start), Some(end)) if hex.len() > 16 => format!("{}…{}", start, end),
+        _ => hex.to_string(),
+    }
+}
@@ -0,0 +1,854 @@
+#[cfg(feature = "gui")]
+mod gui;
+mod output;
+
+use clap::{ArgGroup, Parser, Subcommand};
+use output::{
+    Format, print_batch, print_block, print_proposals, print_stats, print_status,
+    print_transaction, print_validators, show,
+};
+use serde_json::{Value, json};
+use smvblock::amount::Amount;
+use smvblock::blockchain::{Address, Hash, Transaction, Transfer, TxData, TxKind};
+use smvblock::client::{BlockId, Payment, RpcClient, UnsignedTransfer, parse_hash};
+use smvblock::contacts::{Contacts, default_contacts_path};
+use smvblock::governance::{Ballot, Parameter, ParameterChange};
+use smvblock::signer::load_key;
+use smvblock::staking::{Commission, Registration};
+use smvblock::wallet::{Wallet, default_wallet_dir, read_password};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Talks to a running node over its JSON-RPC API.
+#[derive(Parser)]
+struct Args {
+    /// The node's RPC server, as `http://HOST:PORT`. Several separated by
+    /// commas are tried in turn when one cannot be reached.
+    #[arg(long, global = true, env = "SMVBLOCK_NODE")]
+    node: Option<RpcClient>,
+    /// Seconds to wait on a node before giving up on it.
+    #[arg(long, global = true, default_value_t = 10)]
+    rpc_timeout: u64,
+    /// Bearer token or JWT for nodes that require one for wallet or admin
+    /// methods.
+    #[arg(
+        long,
+        global = true,
+        env = "SMVBLOCK_RPC_TOKEN",
+        hide_env_values = true
+    )]
+    rpc_token: Option<String>,
+    /// Directory holding the wallet's keyfiles [default: ~/.smvblock/wallet].
+    #[arg(long, global = true, env = "SMVBLOCK_WALLET_DIR")]
+    wallet_dir: Option<PathBuf>,
+    /// The address book [default: ~/.smvblock/contacts.json].
+    #[arg(long, global = true, env = "SMVBLOCK_CONTACTS")]
+    contacts: Option<PathBuf>,
+    /// How to print results.
+    #[arg(long, global = true, value_enum, default_value_t = Format::Plain)]
+    output: Format,
+    /// Open the desktop wallet instead of running a command.
+    #[arg(long, conflicts_with = "output")]
+    gui: bool,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Show an account's balance and stake.
+    Balance {
+        /// Hex-encoded account address or contact name.
+        #[arg(long)]
+        address: String,
+    },
+    /// Show the nonce the account's next transaction must carry.
+    Nonce {
+        /// Hex-encoded account address or contact name.
+        #[arg(long)]
+        address: String,
+    },
+    /// Show a block and its transactions.
+    #[command(group(ArgGroup::new("block").required(true)))]
+    GetBlock {
+        #[arg(long, group = "block", value_parser = parse_hash)]
+        hash: Option<Hash>,
+        #[arg(long, group = "block")]
+        height: Option<u64>,
+    },
+    /// Show a transaction and whether it is confirmed.
+    GetTx {
+        #[arg(long, value_parser = parse_hash)]
+        hash: Hash,
+    },
+    /// Build, sign and broadcast transactions in separate steps, so the
+    /// signing key can stay on a machine that is never online.
+    #[command(subcommand)]
+    Tx(TxCommand),
+    /// Manage the accounts whose keys this client holds.
+    #[command(subcommand)]
+    Wallet(WalletCommand),
+    /// Name the accounts you pay, so commands take `alice` for an address.
+    #[command(subcommand)]
+    Contacts(ContactsCommand),
+    /// Sign a transfer from a wallet account and submit it to the node.
+    SendTx {
+        /// Name of the wallet account paying.
+        #[arg(long)]
+        from: String,
+        /// Hex-encoded address or contact name of the receiving account.
+        #[arg(long)]
+        to: String,
+        #[arg(long)]
+        amount: Amount,
+        #[arg(long, default_value_t = Amount::ZERO)]
+        fee: Amount,
+        #[command(flatten)]
+        wait: Wait,
+    },
+    /// Sign and submit a transfer from a wallet account for each payment
+    /// in a file, reporting how each one went.
+    SendBatch {
+        /// Name of the wallet account paying.
+        #[arg(long)]
+        from: String,
+        /// CSV with a header row naming the to, amount and optional fee
+        /// columns, or a JSON array of objects with those fields. Each `to`
+        /// is an address or contact name.
+        #[arg(long)]
+        file: PathBuf,
+        /// Fee for payments that do not give their own.
+        #[arg(long, default_value_t = Amount::ZERO)]
+        fee: Amount,
+    },
+    /// Show whether a transaction is pending, confirmed or finalized.
+    TxStatus {
+        #[arg(value_parser = parse_hash)]
+        hash: Hash,
+    },
+    /// Move part of a wallet account's balance into its stake.
+    Stake {
+        /// Name of the wallet account staking.
+        #[arg(long)]
+        from: String,
+        #[arg(long)]
+        amount: Amount,
+        #[arg(long, default_value_t = Amount::ZERO)]
+        fee: Amount,
+        #[command(flatten)]
+        wait: Wait,
+    },
+    /// Move part of a wallet account's stake back into its balance.
+    Unstake {
+        /// Name of the wallet account unstaking.
+        #[arg(long)]
+        from: String,
+        #[arg(long)]
+        amount: Amount,
+        #[arg(long, default_value_t = Amount::ZERO)]
+        fee: Amount,
+        #[command(flatten)]
+        wait: Wait,
+    },
+    /// Move part of a wallet account's balance into stake delegated to a
+    /// validator, which earns a share of its rewards less its commission.
+    Delegate {
+        /// Name of the wallet account delegating.
+        #[arg(long)]
+        from: String,
+        /// Hex-encoded address or contact name of the validator.
+        #[arg(long)]
+        to: String,
+        #[arg(long)]
+        amount: Amount,
+        #[arg(long, default_value_t = Amount::ZERO)]
+        fee: Amount,
+        #[command(flatten)]
+        wait: Wait,
+    },
+    /// Set the share of its block rewards a validating wallet account
+    /// keeps before sharing the rest with its delegators.
+    SetCommission {
+        /// Name of the wallet account validating.
+        #[arg(long)]
+        from: String,
+        /// Commission in basis points, at most 10000.
+        #[arg(long)]
+        bps: u16,
+        #[arg(long, default_value_t = Amount::ZERO)]
+        fee: Amount,
+        #[command(flatten)]
+        wait: Wait,
+    },
+    /// Return a wallet account's validator, jailed for missing too many
+    /// slots, to proposer selection.
+    Unjail {
+        /// Name of the wallet account validating.
+        #[arg(long)]
+        from: String,
+        #[arg(long, default_value_t = Amount::ZERO)]
+        fee: Amount,
+        #[command(flatten)]
+        wait: Wait,
+    },
+    /// Commit a validating wallet account to a secret for the randomness
+    /// beacon, to be revealed in the next epoch or be slashed.
+    BeaconCommit {
+        /// Name of the wallet account validating.
+        #[arg(long)]
+        from: String,
+        #[arg(long, default_value_t = Amount::ZERO)]
+        fee: Amount,
+        #[command(flatten)]
+        wait: Wait,
+    },
+    /// Reveal the beacon secret a wallet account committed to in the last
+    /// epoch.
+    BeaconReveal {
+        /// Name of the wallet account validating.
+        #[arg(long)]
+        from: String,
+        #[arg(long, default_value_t = Amount::ZERO)]
+        fee: Amount,
+        #[command(flatten)]
+        wait: Wait,
+    },
+    /// Register a wallet account as a validator that signs blocks and
+    /// finality votes with a consensus key of its own.
+    RegisterValidator {
+        /// Name of the wallet account validating.
+        #[arg(long)]
+        from: String,
+        /// Hex-encoded public key the validator's node signs with.
+        #[arg(long, value_parser = parse_hash)]
+        consensus_key: [u8; 32],
+        /// Commission in basis points, at most 10000.
+        #[arg(long, default_value_t = 0)]
+        bps: u16,
+        #[arg(long, default_value_t = Amount::ZERO)]
+        fee: Amount,
+        #[command(flatten)]
+        wait: Wait,
+    },
+    /// Replace the consensus key of a wallet account's validator, such as
+    /// one that was compromised, keeping its stake where it is.
+    RotateKey {
+        /// Name of the wallet account validating.
+        #[arg(long)]
+        from: String,
+        /// Hex-encoded public key the validator's node signs with from now
+        /// on.
+        #[arg(long, value_parser = parse_hash)]
+        consensus_key: [u8; 32],
+        #[arg(long, default_value_t = Amount::ZERO)]
+        fee: Amount,
+        #[command(flatten)]
+        wait: Wait,
+    },
+    /// List the validators, the stake delegated to them and their share
+    /// of the total.
+    Validators,
+    /// Show block times, throughput, fees and stake over the latest blocks.
+    Stats {
+        /// Number of latest blocks to measure.
+        #[arg(long)]
+        window: Option<u64>,
+    },
+    /// Propose changing a consensus parameter from a given height on, or
+    /// a grant out of the treasury paid to the proposer then.
+    Propose {
+        /// Name of the wallet account proposing.
+        #[arg(long)]
+        from: String,
+        /// block_time, block_reward, min_fee_per_byte, fee_burn,
+        /// fee_treasury or treasury_grant.
+        #[arg(long)]
+        parameter: Parameter,
+        /// Milliseconds for the block time, basis points for a share of
+        /// fees, an amount otherwise.
+        #[arg(long)]
+        value: String,
+        /// Height of the first block the change would apply to.
+        #[arg(long)]
+        at: u64,
+        #[arg(long, default_value_t = Amount::ZERO)]
+        fee: Amount,
+        #[command(flatten)]
+        wait: Wait,
+    },
+    /// Vote on a proposal with a wallet account's stake.
+    #[command(group(ArgGroup::new("choice").required(true).args(["approve", "reject"])))]
+    Vote {
+        /// Name of the wallet account voting.
+        #[arg(long)]
+        from: String,
+        /// ID of the proposal.
+        proposal: u64,
+        #[arg(long)]
+        approve: bool,
+        #[arg(long)]
+        reject: bool,
+        #[arg(long, default_value_t = Amount::ZERO)]
+        fee: Amount,
+        #[command(flatten)]
+        wait: Wait,
+    },
+    /// List parameter change proposals and the stake behind each side.
+    Proposals,
+}
+
+/// Options for waiting on a submitted transaction.
+#[derive(clap::Args)]
+struct Wait {
+    /// Wait until the transaction has this many confirmations.
+    #[arg(
+        long,
+        num_args = 0..=1,
+        default_missing_value = "1",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    wait: Option<u64>,
+    /// Seconds to wait for confirmations before giving up.
+    #[arg(long, default_value_t = 300, requires = "wait")]
+    wait_timeout: u64,
+}
+
+impl Wait {
+    /// Prints the hash of a submitted transaction, or where it ended up
+    /// once confirmed if asked to wait.
+    async fn report(&self, node: &RpcClient, hash: Hash, format: Format) -> Result<(), String> {
+        let Some(confirmations) = self.wait else {
+            let value = json!({ "hash": hex::encode(hash) });
+            show(format, &value, plain_field("hash"));
+            return Ok(());
+        };
+        if format == Format::Plain {
+            eprintln!(
+                "Sent {}, waiting for {} confirmations",
+                hex::encode(hash),
+                confirmations
+            );
+        }
+        let timeout = Duration::from_secs(self.wait_timeout);
+        let tx = node
+            .wait_for_confirmations(&hash, confirmations, timeout)
+            .await?;
+        show(format, &tx_status(&hash, Some(&tx)), print_status);
+        Ok(())
+    }
+}
+
+/// Where a transaction stands, from what `tx_get` says of it.
+fn tx_status(hash: &Hash, tx: Option<&Value>) -> Value {
+    let Some(tx) = tx else {
+        return json!({ "hash": hex::encode(hash), "status": "unknown" });
+    };
+    json!({
+        "hash": hex::encode(hash),
+        "status": tx["status"],
+        "confirmations": tx["confirmations"],
+        "block_hash": tx["block"]["block_hash"],
+        "height": tx["block"]["height"],
+    })
+}
+
+#[derive(Subcommand)]
+enum TxCommand {
+    /// Write an unsigned transfer as JSON.
+    Build {
+        /// Hex-encoded address or contact name of the paying account.
+        #[arg(long)]
+        from: String,
+        /// Hex-encoded address or contact name of the receiving account.
+        /// Stakes and unstakes are always to the sender.
+        #[arg(long, required_if_eq("kind", "transfer"))]
+        to: Option<String>,
+        #[arg(long)]
+        amount: Amount,
+        #[arg(long, default_value_t = Amount::ZERO)]
+        fee: Amount,
+        /// transfer, stake or unstake.
+        #[arg(long, default_value = "transfer")]
+        kind: TxKind,
+        /// Nonce to use instead of asking the node for the next one.
+        #[arg(long)]
+        nonce: Option<u64>,
+        /// File to write to instead of standard output.
+        #[arg(long)]
+        out: Option<PathBuf>,
+    },
+    /// Sign an unsigned transfer without contacting a node, writing the
+    /// signed transaction as hex.
+    Sign {
+        /// File holding the sender's signing key as 64 hex digits.
+        #[arg(long)]
+        key: PathBuf,
+        /// The unsigned transfer, or `-` for standard input.
+        input: PathBuf,
+        /// File to write to instead of standard output.
+        #[arg(long)]
+        out: Option<PathBuf>,
+    },
+    /// Submit a signed transaction to the node.
+    Broadcast {
+        /// The signed transaction, or `-` for standard input.
+        input: PathBuf,
+    },
+}
+
+/// The epoch of the node's next block.
+async fn beacon_epoch(node: &RpcClient) -> Result<u64, String> {
+    node.beacon().await?["epoch"]
+        .as_u64()
+        .ok_or("Malformed beacon from node".to_string())
+}
+
+fn read_input(path: &Path) -> Result<String, String> {
+    if path == Path::new("-") {
+        return std::io::read_to_string(std::io::stdin()).map_err(|e| e.to_string());
+    }
+    std::fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))
+}
+
+fn write_file(path: &Path, contents: &str) -> Result<(), String> {
+    std::fs::write(path, format!("{}\n", contents))
+        .map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+}
+
+/// Prints just the one field of a command's result as plain output.
+fn plain_field(name: &str) -> impl FnOnce(&Value) + '_ {
+    move |value| match &value[name] {
+        Value::String(s) => println!("{}", s),
+        other => println!("{}", other),
+    }
+}
+
+#[derive(Subcommand)]
+enum WalletCommand {
+    /// Generate a new account and store its key encrypted.
+    Create { name: String },
+    /// Store an existing signing key, read from a file of 64 hex digits.
+    Import {
+        name: String,
+        #[arg(long)]
+        key_file: PathBuf,
+    },
+    /// List the wallet's accounts with their addresses.
+    List,
+    /// Print the address of an account.
+    ShowAddress { name: String },
+}
+
+#[derive(Subcommand)]
+enum ContactsCommand {
+    /// Name an address.
+    Add {
+        name: String,
+        #[arg(value_parser = parse_hash)]
+        address: Address,
+    },
+    /// List the contacts with their addresses.
+    List,
+    /// Forget a contact.
+    Remove { name: String },
+}
+
+fn run_wallet(wallet: &Wallet, command: WalletCommand, format: Format) -> Result<(), String> {
+    let account = |name: &str, address| json!({ "name": name, "address": hex::encode(address) });
+    match command {
+        WalletCommand::Create { name } => {
+            let address = wallet.create(&name, &read_password(true)?)?;
+            show(format, &account(&name, address), plain_field("address"));
+        }
+        WalletCommand::Import { name, key_file } => {
+            let key = load_key(&key_file)?;
+            let address = wallet.import(&name, &key, &read_password(true)?)?;
+            show(format, &account(&name, address), plain_field("address"));
+        }
+        WalletCommand::List => {
+            let accounts: Vec<Value> = wallet
+                .list()?
+                .into_iter()
+                .map(|(name, address)| account(&name, address))
+                .collect();
+            show(format, &Value::from(accounts), |accounts| {
+                for account in accounts.as_array().into_iter().flatten() {
+                    println!(
+                        "{}  {}",
+                        account["address"].as_str().unwrap_or("-"),
+                        account["name"].as_str().unwrap_or("-")
+                    );
+                }
+            });
+        }
+        WalletCommand::ShowAddress { name } => {
+            let address = wallet.address(&name)?;
+            show(format, &account(&name, address), plain_field("address"));
+        }
+    }
+    Ok(())
+}
+
+fn run_contacts(
+    contacts: &Contacts,
+    command: ContactsCommand,
+    format: Format,
+) -> Result<(), String> {
+    let contact = |name: &str, address| json!({ "name": name, "address": hex::encode(address) });
+    match command {
+        ContactsCommand::Add { name, address } => {
+            contacts.add(&name, address)?;
+            show(format, &contact(&name, address), plain_field("address"));
+        }
+        ContactsCommand::List => {
+            let list: Vec<Value> = contacts
+                .list()?
+                .into_iter()
+                .map(|(name, address)| contact(&name, address))
+                .collect();
+            show(format, &Value::from(list), |list| {
+                for contact in list.as_array().into_iter().flatten() {
+                    println!(
+                        "{}  {}",
+                        contact["address"].as_str().unwrap_or("-"),
+                        contact["name"].as_str().unwrap_or("-")
+                    );
+                }
+            });
+        }
+        ContactsCommand::Remove { name } => {
+            let address = contacts.remove(&name)?;
+            show(format, &contact(&name, address), plain_field("address"));
+        }
+    }
+    Ok(())
+}
+
+async fn run(args: Args) -> Result<(), String> {
+    let wallet = Wallet::open(args.wallet_dir.unwrap_or_else(default_wallet_dir));
+    let contacts = Contacts::open(args.contacts.unwrap_or_else(default_contacts_path));
+    let format = args.output;
+    let timeout = Duration::from_secs(args.rpc_timeout);
+    let node = || {
+        let node = args
+            .node
+            .clone()
+            .ok_or("No node given; pass --node or set SMVBLOCK_NODE")?
+            .with_timeout(timeout);
+        Ok::<_, &str>(match args.rpc_token.clone() {
+            Some(token) => node.with_token(token),
+            None => node,
+        })
+    };
+    if args.gui {
+        #[cfg(feature = "gui")]
+        return gui::run(node()?, wallet, contacts);
+        #[cfg(not(feature = "gui"))]
+        return Err(
+            "This client was built without the wallet GUI; rebuild it with --features gui"
+                .to_string(),
+        );
+    }
+    let Some(command) = args.command else {
+        return Err("No command given; see --help".to_string());
+    };
+    match command {
+        Command::Balance { address } => {
+            let account = node()?.balance(&contacts.resolve(&address)?).await?;
+            let value = json!({
+                "address": hex::encode(account.address),
+                "balance": account.balance.to_string(),
+                "stake": account.stake.to_string(),
+            });
+            show(format, &value, |account| {
+                println!("Balance: {}", account["balance"].as_str().unwrap_or("-"));
+                println!("Stake:   {}", account["stake"].as_str().unwrap_or("-"));
+            });
+        }
+        Command::Nonce { address } => {
+            let address = contacts.resolve(&address)?;
+            let nonce = node()?.nonce(&address).await?;
+            let value = json!({ "address": hex::encode(address), "nonce": nonce });
+            show(format, &value, plain_field("nonce"));
+        }
+        Command::GetBlock { hash, height } => {
+            let id = match (hash, height) {
+                (Some(hash), _) => BlockId::Hash(hash),
+                (None, Some(height)) => BlockId::Height(height),
+                (None, None) => unreachable!("clap requires one of them"),
+            };
+            let block = node()?.block(id).await?.ok_or("No such block")?;
+            show(format, &block, print_block);
+        }
+        Command::GetTx { hash } => {
+            let tx = node()?
+                .transaction(&hash)
+                .await?
+                .ok_or("No such transaction")?;
+            show(format, &tx, print_transaction);
+        }
+        Command::SendTx {
+            from,
+            to,
+            amount,
+            fee,
+            wait,
+        } => {
+            let to = contacts.resolve(&to)?;
+            let key = wallet.unlock(&from, &read_password(false)?)?;
+            let node = node()?;
+            let hash = node.transfer(&key, to, amount, fee).await?;
+            wait.report(&node, hash, format).await?;
+        }
+        Command::SendBatch { from, file, fee } => {
+            let payments =
+                Payment::parse_batch(&read_input(&file)?, fee, |to| contacts.resolve(to))?;
+            let key = wallet.unlock(&from, &read_password(false)?)?;
+            let results = node()?.send_batch(&key, &payments).await?;
+            let rows: Vec<Value> = payments
+                .iter()
+                .enumerate()
+                .map(|(i, payment)| {
+                    let (hash, error) = match results.get(i) {
+                        Some(Ok(hash)) => (Some(hex::encode(hash)), None),
+                        Some(Err(e)) => (None, Some(e.to_string())),
+                        None => (None, Some("not sent".to_string())),
+                    };
+                    json!({
+                        "row": i + 1,
+                        "to": hex::encode(payment.receiver),
+                        "amount": payment.amount.to_string(),
+                        "fee": payment.fee.to_string(),
+                        "hash": hash,
+                        "error": error,
+                    })
+                })
+                .collect();
+            show(format, &Value::from(rows), print_batch);
+            let sent = results.iter().filter(|result| result.is_ok()).count();
+            if sent < payments.len() {
+                return Err(format!(
+                    "{} of {} payments failed",
+                    payments.len() - sent,
+                    payments.len()
+                ));
+            }
+        }
+        Command::Stake {
+            from,
+            amount,
+            fee,
+            wait,
+        } => {
+            let key = wallet.unlock(&from, &read_password(false)?)?;
+            let node = node()?;
+            let hash = node.stake(&key, amount, fee).await?;
+            wait.report(&node, hash, format).await?;
+        }
+        Command::Unstake {
+            from,
+            amount,
+            fee,
+            wait,
+        } => {
+            let key = wallet.unlock(&from, &read_password(false)?)?;
+            let node = node()?;
+            let hash = node.unstake(&key, amount, fee).await?;
+            wait.report(&node, hash, format).await?;
+        }
+        Command::Delegate {
+            from,
+            to,
+            amount,
+            fee,
+            wait,
+        } => {
+            let validator = contacts.resolve(&to)?;
+            let key = wallet.unlock(&from, &read_password(false)?)?;
+            let node = node()?;
+            let hash = node.delegate(&key, validator, amount, fee).await?;
+            wait.report(&node, hash, format).await?;
+        }
+        Command::SetCommission {
+            from,
+            bps,
+            fee,
+            wait,
+        } => {
+            let key = wallet.unlock(&from, &read_password(false)?)?;
+            let node = node()?;
+            let hash = node.set_commission(&key, Commission { bps }, fee).await?;
+            wait.report(&node, hash, format).await?;
+        }
+        Command::Unjail { from, fee, wait } => {
+            let key = wallet.unlock(&from, &read_password(false)?)?;
+            let node = node()?;
+            let hash = node.unjail(&key, fee).await?;
+            wait.report(&node, hash, format).await?;
+        }
+        Command::BeaconCommit { from, fee, wait } => {
+            let key = wallet.unlock(&from, &read_password(false)?)?;
+            let node = node()?;
+            let epoch = beacon_epoch(&node).await?;
+            let hash = node.commit(&key, epoch, fee).await?;
+            wait.report(&node, hash, format).await?;
+        }
+        Command::BeaconReveal { from, fee, wait } => {
+            let key = wallet.unlock(&from, &read_password(false)?)?;
+            let node = node()?;
+            let epoch = beacon_epoch(&node)
+                .await?
+                .checked_sub(1)
+                .ok_or("Nothing is committed to before the first epoch")?;
+            let hash = node.reveal(&key, epoch, fee).await?;
+            wait.report(&node, hash, format).await?;
+        }
+        Command::RegisterValidator {
+            from,
+            consensus_key,
+            bps,
+            fee,
+            wait,
+        } => {
+            let registration = Registration {
+                consensus_key,
+                commission: Commission { bps },
+            };
+            let key = wallet.unlock(&from, &read_password(false)?)?;
+            let node = node()?;
+            let hash = node.register_validator(&key, &registration, fee).await?;
+            wait.report(&node, hash, format).await?;
+        }
+        Command::RotateKey {
+            from,
+            consensus_key,
+            fee,
+            wait,
+        } => {
+            let key = wallet.unlock(&from, &read_password(false)?)?;
+            let node = node()?;
+            let hash = node.rotate_key(&key, consensus_key, fee).await?;
+            wait.report(&node, hash, format).await?;
+        }
+        Command::Validators => {
+            let validators = node()?.validators().await?;
+            show(format, &validators["validators"], print_validators);
+        }
+        Command::Stats { window } => {
+            let stats = node()?.stats(window).await?;
+            show(format, &stats, print_stats);
+        }
+        Command::Propose {
+            from,
+            parameter,
+            value,
+            at,
+            fee,
+            wait,
+        } => {
+            let change = ParameterChange {
+                parameter,
+                value: parameter.parse_value(&value)?,
+                activation_height: at,
+            };
+            let key = wallet.unlock(&from, &read_password(false)?)?;
+            let node = node()?;
+            let hash = node.propose(&key, &change, fee).await?;
+            wait.report(&node, hash, format).await?;
+        }
+        Command::Vote {
+            from,
+            proposal,
+            approve,
+            fee,
+            wait,
+            ..
+        } => {
+            let key = wallet.unlock(&from, &read_password(false)?)?;
+            let node = node()?;
+            let hash = node.vote(&key, &Ballot { proposal, approve }, fee).await?;
+            wait.report(&node, hash, format).await?;
+        }
+        Command::Proposals => {
+            let proposals = node()?.proposals().await?;
+            show(format, &proposals, print_proposals);
+        }
+        Command::TxStatus { hash } => {
+            let tx = node()?.transaction(&hash).await?;
+            show(format, &tx_status(&hash, tx.as_ref()), print_status);
+        }
+        Command::Wallet(command) => run_wallet(&wallet, command, format)?,
+        Command::Contacts(command) => run_contacts(&contacts, command, format)?,
+        Command::Tx(TxCommand::Build {
+            from,
+            to,
+            amount,
+            fee,
+            kind,
+            nonce,
+            out,
+        }) => {
+            let from = contacts.resolve(&from)?;
+            let to = to.map(|to| contacts.resolve(&to)).transpose()?;
+            let nonce = match nonce {
+                Some(nonce) => nonce,
+                None => node()?.nonce(&from).await?,
+            };
+            let unsigned = UnsignedTransfer {
+                sender: from,
+                transfer: Transfer {
+                    receiver: to.unwrap_or(from),
+                    amount,
+                    fee,
+                    nonce,
+                    kind,
+                    data: TxData::None,
+                },
+            }
+            .to_json();
+            let json = serde_json::to_string_pretty(&unsigned).map_err(|e| e.to_string())?;
+            match out {
+                Some(out) => write_file(&out, &json)?,
+                None => show(format, &unsigned, |_| println!("{}", json)),
+            }
+        }
+        Command::Tx(TxCommand::Sign { key, input, out }) => {
+            let json = read_input(&input)?;
+            let unsigned = serde_json::from_str(&json)
+                .map_err(|e| format!("Invalid transaction file: {}", e))?;
+            let tx = UnsignedTransfer::from_json(&unsigned)?.sign(&load_key(&key)?)?;
+            let raw = hex::encode(tx.to_bytes());
+            match out {
+                Some(out) => write_file(&out, &raw)?,
+                None => {
+                    let value = json!({ "hash": hex::encode(tx.hash()), "raw": raw });
+                    show(format, &value, plain_field("raw"));
+                }
+            }
+        }
+        Command::Tx(TxCommand::Broadcast { input }) => {
+            let raw = read_input(&input)?;
+            let bytes = hex::decode(raw.trim())
+                .map_err(|e| format!("Invalid signed transaction: {}", e))?;
+            let tx = Transaction::from_bytes(&bytes)?;
+            if !tx.verify() {
+                return Err("Transaction signature is invalid".to_string());
+            }
+            let hash = node()?.submit_raw(&bytes).await?;
+            show(
+                format,
+                &json!({ "hash": hex::encode(hash) }),
+                plain_field("hash"),
+            );
+        }
+    }
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() {
+    if let Err(e) = run(Args::parse()).await {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,288 @@
+//! How the client prints what a command returns. Every command produces a
+//! JSON value; `--output` picks whether that is printed as JSON, as a
+//! table, or in the command's own plain text.
+
+use clap::ValueEnum;
+use serde_json::Value;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum Format {
+    /// Text meant for people, laid out per command.
+    #[default]
+    Plain,
+    /// The value as pretty-printed JSON, for scripts.
+    Json,
+    /// Fields in aligned columns, and lists as one row per entry.
+    Table,
+}
+
+/// Prints `value` in `format`, with `plain` laying it out as text.
+pub fn show(format: Format, value: &Value, plain: impl FnOnce(&Value)) {
+    match format {
+        Format::Plain => plain(value),
+        Format::Json => println!(
+            "{}",
+            serde_json::to_string_pretty(value).expect("JSON values always serialize")
+        ),
+        Format::Table => print_table(value),
+    }
+}
+
+/// A field as text, with `-` for nothing.
+fn scalar(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Null => "-".to_string(),
+        other => other.to_string(),
+    }
+}
+
+fn print_table(value: &Value) {
+    match value {
+        Value::Array(rows) => print_rows(rows),
+        Value::Object(fields) => {
+            let mut rows = Vec::new();
+            let mut lists = Vec::new();
+            for (name, value) in fields {
+                match value {
+                    Value::Array(list) => lists.push((name, list)),
+                    Value::Object(inner) => rows.extend(
+                        inner
+                            .iter()
+                            .map(|(key, value)| (format!("{}.{}", name, key), scalar(value))),
+                    ),
+                    value => rows.push((name.clone(), scalar(value))),
+                }
+            }
+            let width = rows.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+            for (name, value) in rows {
+                println!("{:width$}  {}", name, value);
+            }
+            for (name, list) in lists {
+                println!();
+                println!("{}:", name);
+                print_rows(list);
+            }
+        }
+        value => println!("{}", scalar(value)),
+    }
+}
+
+/// One line per entry, with a header naming the columns when the entries
+/// are objects.
+fn print_rows(rows: &[Value]) {
+    let Some(Value::Object(first)) = rows.first() else {
+        for row in rows {
+            println!("{}", scalar(row));
+        }
+        return;
+    };
+    let columns: Vec<&String> = first.keys().collect();
+    let cells: Vec<Vec<String>> = rows
+        .iter()
+        .map(|row| {
+            columns
+                .iter()
+                .map(|column| scalar(&row[column.as_str()]))
+                .collect()
+        })
+        .collect();
+    let widths: Vec<usize> = columns
+        .iter()
+        .enumerate()
+        .map(|(i, column)| {
+            cells
+                .iter()
+                .map(|row| row[i].len())
+                .chain([column.len()])
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+    let line = |row: Vec<String>| {
+        let padded: Vec<String> = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:width$}", cell))
+            .collect();
+        println!("{}", padded.join("  ").trim_end());
+    };
+    line(columns.iter().map(|column| column.to_uppercase()).collect());
+    for row in cells {
+        line(row);
+    }
+}
+
+/// A string field of an RPC response, or `-` when missing.
+fn field(value: &Value, name: &str) -> String {
+    scalar(&value[name])
+}
+
+pub fn print_block(block: &Value) {
+    println!("Block {} {}", field(block, "height"), field(block, "hash"));
+    println!("  Previous:      {}", field(block, "previous_hash"));
+    let time = block["timestamp"]
+        .as_i64()
+        .and_then(|timestamp| chrono::DateTime::from_timestamp(timestamp, 0));
+    match time {
+        Some(time) => println!("  Time:          {}", time.to_rfc3339()),
+        None => println!("  Time:          -"),
+    }
+    println!("  Proposer:      {}", field(block, "proposer"));
+    println!("  Coinbase:      {}", field(block, "coinbase"));
+    println!("  State root:    {}", field(block, "state_root"));
+    let finality = if block["finalized"] == Value::Bool(true) {
+        " (finalized)"
+    } else {
+        ""
+    };
+    println!(
+        "  Confirmations: {}{}",
+        field(block, "confirmations"),
+        finality
+    );
+    let transactions = block["transactions"]
+        .as_array()
+        .cloned()
+        .unwrap_or_default();
+    println!("  Transactions:  {}", transactions.len());
+    for tx in &transactions {
+        println!(
+            "    {}  {} -> {}  {} (fee {})",
+            field(tx, "hash"),
+            field(tx, "sender"),
+            field(tx, "receiver"),
+            field(tx, "amount"),
+            field(tx, "fee")
+        );
+    }
+}
+
+pub fn print_transaction(tx: &Value) {
+    println!("Transaction {}", field(tx, "hash"));
+    match tx["confirmations"].as_u64() {
+        Some(confirmations) => println!(
+            "  Status:        {}, {} confirmations",
+            field(tx, "status"),
+            confirmations
+        ),
+        None => println!("  Status:        {}", field(tx, "status")),
+    }
+    println!("  From:          {}", field(tx, "sender"));
+    println!("  To:            {}", field(tx, "receiver"));
+    println!("  Amount:        {}", field(tx, "amount"));
+    println!("  Fee:           {}", field(tx, "fee"));
+    println!("  Nonce:         {}", field(tx, "nonce"));
+    let block = &tx["block"];
+    if !block.is_null() {
+        println!(
+            "  Block:         {} at height {}, index {}",
+            field(block, "block_hash"),
+            field(block, "height"),
+            field(block, "index")
+        );
+    }
+}
+
+/// One line saying where a transaction stands.
+pub fn print_status(status: &Value) {
+    match status["confirmations"].as_u64() {
+        Some(confirmations) => println!(
+            "{} in block {} at height {}, {} confirmations",
+            field(status, "status"),
+            field(status, "block_hash"),
+            field(status, "height"),
+            confirmations
+        ),
+        None => println!("{}", field(status, "status")),
+    }
+}
+
+/// Metrics over recent blocks, one per line.
+pub fn print_stats(stats: &Value) {
+    if stats.is_null() {
+        println!("No blocks yet");
+        return;
+    }
+    let rate = |name: &str| {
+        stats[name]
+            .as_f64()
+            .map_or("-".to_string(), |rate| format!("{:.2}", rate))
+    };
+    println!(
+        "Blocks {} to {} ({} blocks)",
+        field(stats, "from_height"),
+        field(stats, "to_height"),
+        field(stats, "blocks")
+    );
+    println!("Block interval: {}s", rate("average_block_interval"));
+    println!(
+        "Transactions:   {} ({}/s)",
+        field(stats, "transactions"),
+        rate("transactions_per_second")
+    );
+    println!("Fees:           {}", field(stats, "fees"));
+    println!("Rewards:        {}", field(stats, "rewards"));
+    println!(
+        "Validators:     {} of {} proposed, {:.2}% of {} staked",
+        field(stats, "active_validators"),
+        field(stats, "validators"),
+        stats["participation"].as_f64().unwrap_or(0.0) * 100.0,
+        field(stats, "total_stake")
+    );
+}
+
+pub fn print_proposals(proposals: &Value) {
+    for proposal in proposals.as_array().into_iter().flatten() {
+        println!(
+            "#{}  {} = {} at height {}  {}  for {}, against {} of {} staked",
+            field(proposal, "id"),
+            field(proposal, "parameter"),
+            field(proposal, "value"),
+            field(proposal, "activation_height"),
+            field(proposal, "status"),
+            field(proposal, "approve"),
+            field(proposal, "reject"),
+            field(proposal, "total_stake")
+        );
+    }
+}
+
+/// One line per payment of a batch, with its hash or why it failed.
+pub fn print_batch(rows: &Value) {
+    for row in rows.as_array().into_iter().flatten() {
+        match row["error"].as_str() {
+            Some(error) => println!(
+                "{}  {} -> {}  failed: {}",
+                field(row, "row"),
+                field(row, "amount"),
+                field(row, "to"),
+                error
+            ),
+            None => println!(
+                "{}  {} -> {}  {}",
+                field(row, "row"),
+                field(row, "amount"),
+                field(row, "to"),
+                field(row, "hash")
+            ),
+        }
+    }
+}
+
+pub fn print_validators(validators: &Value) {
+    for validator in validators.as_array().into_iter().flatten() {
+        println!(
+            "{}  {} + {} delegated  {:.2}%  commission {} bps  missed {}",
+            field(validator, "address"),
+            field(validator, "stake"),
+            field(validator, "delegated"),
+            validator["share"].as_f64().unwrap_or(0.0) * 100.0,
+            validator["commission_bps"].as_u64().unwrap_or(0),
+            validator["missed_slots"].as_u64().unwrap_or(0)
+        );
+        if let Some(key) = validator["consensus_key"].as_str() {
+            println!("    consensus key {}", key);
+        }
+    }
+}
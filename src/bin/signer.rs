@@ -0,0 +1,55 @@
+use clap::Parser;
+use smvblock::{
+    logging,
+    signer::{self, SignerEndpoint},
+};
+use std::path::PathBuf;
+use tokio::sync::watch;
+
+/// Holds a validator key and signs block proposals for a node, so the key
+/// never has to be loaded by the node itself.
+#[derive(Parser)]
+struct Args {
+    /// File holding the validator's hex-encoded 32-byte signing key.
+    #[arg(long)]
+    key: PathBuf,
+    /// Where to accept signing requests, as `http://HOST:PORT` or
+    /// `unix:PATH`.
+    #[arg(long)]
+    listen: SignerEndpoint,
+    /// Log filter such as `info`; `RUST_LOG` overrides it.
+    #[arg(long, default_value = "info")]
+    log_level: String,
+}
+
+#[tokio::main]
+async fn main() {
+    let args = Args::parse();
+    if let Err(e) = logging::init(&args.log_level, false) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+
+    let key = match signer::load_key(&args.key) {
+        Ok(key) => key,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let public_key = key.verifying_key().to_bytes();
+    let (shutdown, stopped) = watch::channel(false);
+    match signer::serve(key, &args.listen, stopped).await {
+        Ok(endpoint) => println!("Signing as {} on {}", hex::encode(public_key), endpoint),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+
+    let _ = tokio::signal::ctrl_c().await;
+    shutdown.send_replace(true);
+    if let SignerEndpoint::Unix(path) = &args.listen {
+        let _ = std::fs::remove_file(path);
+    }
+}
@@ -1,25 +1,296 @@
-use crate::db::Database;
+use crate::amount::Amount;
+use crate::beacon::{self, Commit};
+use crate::db::{Database, ReadPool};
+use crate::error::BlockchainError;
+use crate::events::{EventBus, NodeEvent};
+use crate::finality::{FinalityTracker, Vote, VoteOutcome};
+use crate::governance::{Ballot, BlockRules, ParameterChange, Parameters, Proposal, Tally};
+use crate::liveness;
+use crate::mempool::{Mempool, MempoolConfig, MempoolStats};
+use crate::monetary::MonetaryPolicy;
+use crate::p2p::MAX_FRAME_SIZE;
+use crate::params::{self, Beacon, ChainParams, Feature, FeeRouting, Liveness};
+use crate::staking::{self, Commission, Registration, Validator};
+use crate::verify::VerifiedBlock;
+use crate::webhooks::{self, Webhook};
 use bincode::config::standard;
 use bincode::{Decode, Encode, encode_to_vec};
 use chrono::{DateTime, Utc};
 use ed25519_dalek::ed25519::signature::{SignerMut, Verifier};
 use ed25519_dalek::{Signature, SigningKey, VerifyingKey};
-use libp2p::futures::lock::Mutex;
-use rand::distributions::WeightedIndex;
-use rand::prelude::Distribution;
+use futures::lock::Mutex;
 use rand::rngs::OsRng;
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSql, ToSqlOutput, ValueRef};
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+use tracing::{debug, error, info};
 
 pub type Hash = [u8; 32];
 pub type Address = [u8; 32];
 
+/// Number of most recent blocks whose median timestamp a new block must exceed.
+pub const MEDIAN_TIME_SPAN: usize = 11;
+
+/// How far ahead of the local clock, in seconds, a block timestamp may be.
+pub const MAX_FUTURE_DRIFT_SECS: i64 = 60;
+
+/// Most bytes of transactions one block may carry, so that it always fits
+/// in a single frame to peers along with its header.
+pub const MAX_BLOCK_BYTES: usize = MAX_FRAME_SIZE / 2;
+
+/// Domain separator so a proposer's block signature can never be replayed
+/// as a vote or transaction signature.
+const BLOCK_DOMAIN: &[u8] = b"smvblock-block";
+
+/// What a transaction does with its amount. Staking goes through
+/// transactions like any payment, so every node applies it at the same
+/// height.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, Encode, Decode, PartialEq, Eq)]
+pub enum TxKind {
+    /// Pays the amount to the receiver.
+    #[default]
+    Transfer,
+    /// Moves the amount from the sender's balance into their stake. The
+    /// receiver must be the sender.
+    Stake,
+    /// Moves the amount from the sender's stake back into their balance.
+    /// The receiver must be the sender.
+    Unstake,
+    /// Proposes the parameter change in its [`TxData::Proposal`].
+    Propose,
+    /// Votes on a proposal with the sender's stake, as its
+    /// [`TxData::Ballot`] says.
+    Vote,
+    /// Moves the amount from the sender's balance into their stake,
+    /// delegated to the receiver, which must be a validator; see
+    /// [`crate::staking`].
+    Delegate,
+    /// Sets the sender's commission as a validator to its
+    /// [`TxData::Commission`].
+    SetCommission,
+    /// Returns the sender, a validator jailed for missing too many slots,
+    /// to proposer selection; see [`crate::liveness`]. The receiver must be
+    /// the sender and the amount zero.
+    Unjail,
+    /// Registers the sender as a validator, with the consensus key and
+    /// commission of its [`TxData::Registration`].
+    RegisterValidator,
+    /// Replaces the sender's consensus key with its
+    /// [`TxData::ConsensusKey`].
+    RotateKey,
+    /// Commits the sender, a validator, to a secret for the randomness
+    /// beacon with its [`TxData::Commit`]; see [`crate::beacon`].
+    Commit,
+    /// Reveals the [`TxData::Secret`] the sender committed to in the epoch
+    /// before.
+    Reveal,
+}
+
+impl TxKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            TxKind::Transfer => "transfer",
+            TxKind::Stake => "stake",
+            TxKind::Unstake => "unstake",
+            TxKind::Propose => "propose",
+            TxKind::Vote => "vote",
+            TxKind::Delegate => "delegate",
+            TxKind::SetCommission => "set_commission",
+            TxKind::Unjail => "unjail",
+            TxKind::RegisterValidator => "register_validator",
+            TxKind::RotateKey => "rotate_key",
+            TxKind::Commit => "commit",
+            TxKind::Reveal => "reveal",
+        }
+    }
+
+    /// Whether the receiver must be the sender, as it must for moving
+    /// one's own stake and for the kinds that pay no one.
+    pub fn is_to_self(self) -> bool {
+        !matches!(self, TxKind::Transfer | TxKind::Delegate)
+    }
+
+    /// Whether transactions of this kind say what they do in their
+    /// [`TxData`], and so carry a zero amount and name their sender as the
+    /// receiver.
+    pub fn carries_data(self) -> bool {
+        !matches!(
+            self,
+            TxKind::Transfer | TxKind::Stake | TxKind::Unstake | TxKind::Delegate | TxKind::Unjail
+        )
+    }
+
+    /// The feature that introduced this kind, which blocks may only carry
+    /// once it is active.
+    pub fn feature(self) -> Option<Feature> {
+        match self {
+            TxKind::Transfer | TxKind::Stake | TxKind::Unstake => None,
+            TxKind::Propose | TxKind::Vote => Some(Feature::Governance),
+            TxKind::Delegate | TxKind::SetCommission => Some(Feature::Delegation),
+            TxKind::Unjail => Some(Feature::Liveness),
+            TxKind::RegisterValidator | TxKind::RotateKey => Some(Feature::Registration),
+            TxKind::Commit | TxKind::Reveal => Some(Feature::Beacon),
+        }
+    }
+}
+
+impl std::str::FromStr for TxKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "transfer" => Ok(TxKind::Transfer),
+            "stake" => Ok(TxKind::Stake),
+            "unstake" => Ok(TxKind::Unstake),
+            "propose" => Ok(TxKind::Propose),
+            "vote" => Ok(TxKind::Vote),
+            "delegate" => Ok(TxKind::Delegate),
+            "set_commission" => Ok(TxKind::SetCommission),
+            "unjail" => Ok(TxKind::Unjail),
+            "register_validator" => Ok(TxKind::RegisterValidator),
+            "rotate_key" => Ok(TxKind::RotateKey),
+            "commit" => Ok(TxKind::Commit),
+            "reveal" => Ok(TxKind::Reveal),
+            _ => Err(format!("Unknown transaction kind: {}", s)),
+        }
+    }
+}
+
+/// Stored as its position in the enum.
+impl ToSql for TxKind {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(*self as i64))
+    }
+}
+
+impl FromSql for TxKind {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value.as_i64()? {
+            0 => Ok(TxKind::Transfer),
+            1 => Ok(TxKind::Stake),
+            2 => Ok(TxKind::Unstake),
+            3 => Ok(TxKind::Propose),
+            4 => Ok(TxKind::Vote),
+            5 => Ok(TxKind::Delegate),
+            6 => Ok(TxKind::SetCommission),
+            7 => Ok(TxKind::Unjail),
+            8 => Ok(TxKind::RegisterValidator),
+            9 => Ok(TxKind::RotateKey),
+            10 => Ok(TxKind::Commit),
+            11 => Ok(TxKind::Reveal),
+            other => Err(FromSqlError::OutOfRange(other)),
+        }
+    }
+}
+
+/// What a transaction says beyond who it pays, for the kinds that do
+/// something other than move an amount; see [`TxKind::carries_data`]. Each
+/// variant belongs to one kind and is only accepted in blocks once that
+/// kind's feature is active.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, Encode, Decode, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TxData {
+    #[default]
+    None,
+    Proposal(ParameterChange),
+    Ballot(Ballot),
+    Commission(Commission),
+    Registration(Registration),
+    ConsensusKey([u8; 32]),
+    Commit(Commit),
+    Secret([u8; 32]),
+}
+
+impl TxData {
+    /// The kind of transaction that carries this, `None` for no data.
+    pub fn kind(&self) -> Option<TxKind> {
+        match self {
+            TxData::None => None,
+            TxData::Proposal(_) => Some(TxKind::Propose),
+            TxData::Ballot(_) => Some(TxKind::Vote),
+            TxData::Commission(_) => Some(TxKind::SetCommission),
+            TxData::Registration(_) => Some(TxKind::RegisterValidator),
+            TxData::ConsensusKey(_) => Some(TxKind::RotateKey),
+            TxData::Commit(_) => Some(TxKind::Commit),
+            TxData::Secret(_) => Some(TxKind::Reveal),
+        }
+    }
+
+    /// The feature that introduced this data, which blocks may only carry
+    /// once it is active.
+    pub fn feature(&self) -> Option<Feature> {
+        self.kind().and_then(TxKind::feature)
+    }
+
+    /// The encoding signed with the rest of the payload, or nothing if
+    /// there is no data.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            TxData::None => Vec::new(),
+            data => encode_to_vec(data, standard()).expect("Failed to serialize transaction data"),
+        }
+    }
+
+    /// Reads [`TxData::to_bytes`], refusing any bytes left over.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        if bytes.is_empty() {
+            return Ok(TxData::None);
+        }
+        let (data, read): (TxData, usize) = bincode::decode_from_slice(bytes, standard())
+            .map_err(|e| format!("Malformed transaction data: {}", e))?;
+        if read != bytes.len() {
+            return Err(format!(
+                "Malformed transaction data: {} trailing bytes",
+                bytes.len() - read
+            ));
+        }
+        Ok(data)
+    }
+
+    /// The consensus key a registration or rotation names.
+    pub fn consensus_key(&self) -> Option<[u8; 32]> {
+        match self {
+            TxData::Registration(registration) => Some(registration.consensus_key),
+            TxData::ConsensusKey(key) => Some(*key),
+            _ => None,
+        }
+    }
+}
+
+/// Stored as [`TxData::to_bytes`], or as NULL if there is none.
+impl ToSql for TxData {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        match self {
+            TxData::None => Ok(ToSqlOutput::from(rusqlite::types::Null)),
+            data => Ok(ToSqlOutput::from(data.to_bytes())),
+        }
+    }
+}
+
+impl FromSql for TxData {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value {
+            ValueRef::Null => Ok(TxData::None),
+            value => {
+                TxData::from_bytes(value.as_blob()?).map_err(|e| FromSqlError::Other(e.into()))
+            }
+        }
+    }
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize, Encode, Decode, PartialEq)]
 pub struct Transfer {
     pub receiver: Address,
-    pub amount: u64,
+    pub amount: Amount,
+    pub fee: Amount,
     pub nonce: u64,
+    pub kind: TxKind,
+    #[serde(default)]
+    pub data: TxData,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize, Encode, Decode, PartialEq)]
@@ -31,8 +302,20 @@ pub struct Transaction {
 }
 
 impl Transfer {
+    /// What the sender signs. Data is appended only if there is any, so
+    /// transactions without it sign and hash as they did before it existed.
     pub fn serialize(&self) -> Vec<u8> {
-        encode_to_vec(self, standard()).expect("Failed to serialize unsigned transaction")
+        let fields = (
+            &self.receiver,
+            &self.amount,
+            &self.fee,
+            self.nonce,
+            self.kind,
+        );
+        let mut encoded =
+            encode_to_vec(fields, standard()).expect("Failed to serialize unsigned transaction");
+        encoded.extend(self.data.to_bytes());
+        encoded
     }
 
     pub(crate) fn hash(&self) -> [u8; 32] {
@@ -44,6 +327,54 @@ impl Transfer {
     pub fn into_transaction(self, key: &SigningKey) -> Transaction {
         Transaction::sign(self, &mut key.clone())
     }
+
+    /// What the sender's balance pays: the amount and fee, or only the fee
+    /// when unstaking, since that amount comes out of the stake, or when the
+    /// amount carries something else.
+    pub fn balance_cost(&self) -> Result<Amount, BlockchainError> {
+        match self.kind {
+            TxKind::Transfer | TxKind::Stake | TxKind::Delegate => {
+                self.amount.checked_add(self.fee)
+            }
+            TxKind::Unstake
+            | TxKind::Propose
+            | TxKind::Vote
+            | TxKind::SetCommission
+            | TxKind::Unjail
+            | TxKind::RegisterValidator
+            | TxKind::RotateKey
+            | TxKind::Commit
+            | TxKind::Reveal => Ok(self.fee),
+        }
+    }
+
+    /// Checks the data is what the kind carries: nothing for a kind that
+    /// only moves an amount, and for any other its own variant alone.
+    pub fn check_data(&self) -> Result<(), BlockchainError> {
+        let expected = self.kind.carries_data().then_some(self.kind);
+        if self.data.kind() != expected {
+            return Err(BlockchainError::WrongData(self.kind));
+        }
+        Ok(())
+    }
+
+    /// What the sender's stake pays, which is nothing unless unstaking.
+    pub fn stake_cost(&self) -> Amount {
+        match self.kind {
+            TxKind::Unstake => self.amount,
+            TxKind::Transfer
+            | TxKind::Stake
+            | TxKind::Propose
+            | TxKind::Vote
+            | TxKind::Delegate
+            | TxKind::SetCommission
+            | TxKind::Unjail
+            | TxKind::RegisterValidator
+            | TxKind::RotateKey
+            | TxKind::Commit
+            | TxKind::Reveal => Amount::ZERO,
+        }
+    }
 }
 
 impl Transaction {
@@ -70,37 +401,166 @@ impl Transaction {
         verifying_key.verify(&message_hash, &signature).is_ok()
     }
 
+    /// Identifier of the transaction: the hash of its sender's key followed
+    /// by what they signed. The nonce keeps one sender's transactions apart,
+    /// and the key keeps two senders with the same payload apart.
+    pub fn hash(&self) -> Hash {
+        let mut hasher = Sha256::new();
+        hasher.update(self.sender_public_key);
+        hasher.update(self.payload.serialize());
+        hasher.finalize().into()
+    }
+
     pub fn sender_address(&self) -> Address {
         let mut hasher = Sha256::new();
-        hasher.update(&self.sender_public_key);
+        hasher.update(self.sender_public_key);
         hasher.finalize().into()
     }
+
+    /// Encoded size in bytes, used to rank transactions by fee-per-byte.
+    pub fn size(&self) -> usize {
+        self.to_bytes().len()
+    }
+
+    /// The canonical encoding, as signed transactions are passed around
+    /// outside the node and as merkle leaves hash them.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        encode_to_vec(self, standard()).expect("Failed to serialize transaction")
+    }
+
+    /// Reads the canonical encoding, refusing any bytes left over.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        let (tx, read): (Transaction, usize) = bincode::decode_from_slice(bytes, standard())
+            .map_err(|e| format!("Malformed transaction: {}", e))?;
+        if read != bytes.len() {
+            return Err(format!(
+                "Malformed transaction: {} trailing bytes",
+                bytes.len() - read
+            ));
+        }
+        Ok(tx)
+    }
 }
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
 pub struct User {
     pub address: Address,
     pub public_key: [u8; 32],
-    pub balance: u64,
-    pub stake: u64,
+    pub balance: Amount,
+    pub stake: Amount,
+    /// The validator the stake is delegated to, if any.
+    #[serde(default)]
+    pub delegate: Option<Address>,
+    /// The share of the block rewards a validator earns that it keeps
+    /// before sharing the rest with its delegators, in basis points.
+    #[serde(default)]
+    pub commission_bps: u16,
+    /// Slots the account was chosen to propose in this epoch that passed
+    /// without a block.
+    #[serde(default)]
+    pub missed_slots: u32,
+    /// Whether the account is left out of proposer selection for missing
+    /// too many slots, until it sends an `Unjail` transaction.
+    #[serde(default)]
+    pub jailed: bool,
 }
 
-#[derive(Clone, Debug, Deserialize, Encode, Serialize)]
-pub struct Block {
+/// The part of a block its hash commits to, plus the proposer's signature
+/// over that hash. Transactions are covered through `merkle_root`, whose
+/// leaves hash each one's [`Transaction::to_bytes`], so changing that
+/// encoding changes block hashes as surely as changing
+/// [`BlockHeader::canonical_bytes`] does.
+#[derive(Clone, Debug, Deserialize, Encode, Serialize, PartialEq)]
+pub struct BlockHeader {
     pub previous_hash: Hash,
     pub merkle_root: Hash,
-    pub nonce: u64,
+    pub state_root: Hash,
     pub timestamp: i64,
+    pub height: u64,
+    pub proposer: Address,
+    /// Newly minted amount credited to the proposer, on top of the fees.
+    pub coinbase: Amount,
+    /// Latest block the proposer had seen finalized, or all zeroes if none.
+    pub finalized_hash: Hash,
+    /// The proposer's signature over the block hash, or all zeroes for a
+    /// block produced without the proposer's key.
+    #[serde(with = "serde_big_array::BigArray")]
+    pub signature: [u8; 64],
+}
+
+#[derive(Clone, Debug, Deserialize, Encode, Serialize, PartialEq)]
+pub struct Block {
+    pub header: BlockHeader,
     pub transactions: Vec<Transaction>,
 }
 
-#[derive(Debug)]
+/// The hash and height of the chain's latest block, kept in memory so that
+/// reading it needs no database query. It is set while the database is
+/// still locked for the write that moved the head, so it never lags the
+/// stored chain.
+#[derive(Clone, Debug)]
+pub struct ChainHead {
+    head: watch::Sender<Option<(Hash, u64)>>,
+}
+
+impl ChainHead {
+    fn new(head: Option<(Hash, u64)>) -> Self {
+        ChainHead {
+            head: watch::channel(head).0,
+        }
+    }
+
+    /// The latest block's hash and height, or `None` for an empty chain.
+    pub fn get(&self) -> Option<(Hash, u64)> {
+        *self.head.borrow()
+    }
+
+    /// Hash of the latest block and the height the next one will have,
+    /// which is the number of blocks; zeroes for an empty chain.
+    pub fn tip(&self) -> (Hash, u64) {
+        self.get()
+            .map_or(([0u8; 32], 0), |(hash, height)| (hash, height + 1))
+    }
+
+    /// Notified whenever the head moves.
+    pub fn subscribe(&self) -> watch::Receiver<Option<(Hash, u64)>> {
+        self.head.subscribe()
+    }
+
+    fn set(&self, head: Option<(Hash, u64)>) {
+        self.head.send_replace(head);
+    }
+}
+
+/// How much of a block is checked before it is applied.
+#[derive(Clone, Copy, PartialEq)]
+enum Checks {
+    Full,
+    /// [`Block::verify`] already passed.
+    Contextual,
+    /// Vouched for by a checkpoint or accepted before; only executed.
+    None,
+    /// Produced by this node without a key, by hand from the REPL: checked
+    /// in full but for its proposer's signature and slot. Never used for
+    /// blocks from peers, which refuse such blocks in turn.
+    Local,
+}
+
+#[derive(Clone, Debug)]
 pub struct Blockchain {
     db: Arc<Mutex<Database>>,
+    /// Connections for lookups that need not wait on `db`; see
+    /// [`Blockchain::read`].
+    readers: Option<Arc<ReadPool>>,
+    head: ChainHead,
+    mempool: Arc<Mutex<Mempool>>,
+    finality: Arc<Mutex<FinalityTracker>>,
+    events: EventBus,
+    params: ChainParams,
 }
 
 impl User {
-    pub fn generate(initial_balance: u64) -> (Self, SigningKey) {
+    pub fn generate(initial_balance: Amount) -> (Self, SigningKey) {
         let mut csprng = OsRng;
         let private_key = SigningKey::generate(&mut csprng);
         let verifying_key = private_key.verifying_key();
@@ -113,249 +573,2320 @@ impl User {
             address: address.into(),
             public_key: verifying_key.to_bytes(),
             balance: initial_balance,
-            stake: 0,
+            stake: Amount::ZERO,
+            delegate: None,
+            commission_bps: 0,
+            missed_slots: 0,
+            jailed: false,
         };
 
         (user, private_key)
     }
 }
 
+/// One account in a state snapshot. The nonce is not part of the state
+/// root, so it is taken on trust from the peer serving the snapshot.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct SnapshotAccount {
+    pub user: User,
+    pub next_nonce: u64,
+}
+
+/// What the supply is made of. Everything allocated to accounts directly
+/// plus every coinbase, less what was burned, should be what accounts
+/// hold; anything else means coins were made or lost outside the rules.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Supply {
+    /// Given to accounts directly, as at genesis, rather than minted by
+    /// blocks. On a node started from a snapshot, whatever the snapshot
+    /// held beyond the coinbase so far.
+    pub allocated: Amount,
+    /// Minted as coinbase by the blocks on the chain.
+    pub emitted: Amount,
+    /// Taken out of the supply for good, such as by slashing.
+    pub burned: Amount,
+    /// Held in accounts, as balance or stake.
+    pub circulating: Amount,
+}
+
+impl Supply {
+    /// What accounts should hold, going by where the supply came from.
+    pub fn expected(&self) -> Result<Amount, BlockchainError> {
+        Ok(self
+            .allocated
+            .checked_add(self.emitted)?
+            .saturating_sub(self.burned))
+    }
+}
+
+/// A block's effect on one account's balance.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BalanceChange {
+    pub block_hash: Hash,
+    pub height: u64,
+    pub before: Amount,
+    pub after: Amount,
+}
+
+/// Where a confirmed transaction sits in the chain.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TransactionLocation {
+    pub block_hash: Hash,
+    pub height: u64,
+    /// Position among the block's transactions.
+    pub index: u32,
+}
+
+/// Shows a node that keeps only headers that a block includes a
+/// transaction: the hashes leading from it up to the block's merkle root.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct TransactionProof {
+    pub transaction: Transaction,
+    pub block_hash: Hash,
+    /// Position among the block's transactions.
+    pub index: u32,
+    /// How many transactions the block has, which the root commits to.
+    pub count: u32,
+    /// See [`merkle_proof`].
+    pub proof: Vec<Hash>,
+}
+
+/// What [`Blockchain::verify_chain`] found.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ChainReport {
+    pub blocks: u64,
+    /// Each problem found, naming the block it is in.
+    pub problems: Vec<String>,
+}
+
 pub fn derive_public_key(private_key: &SigningKey) -> VerifyingKey {
     private_key.verifying_key()
 }
 
+impl BlockHeader {
+    pub const ENCODED_LEN: usize = 32 + 32 + 32 + 8 + 8 + 32 + 16 + 32;
+
+    /// Canonical header encoding: the fields in declaration order, up to
+    /// but not including the signature, with no length prefixes or padding.
+    /// Hashes and addresses are written as their raw 32 bytes, `timestamp`
+    /// as a big-endian i64, `height` as a big-endian u64 and `coinbase` as
+    /// big-endian u128 base units.
+    pub fn canonical_bytes(&self) -> [u8; Self::ENCODED_LEN] {
+        let mut bytes = [0u8; Self::ENCODED_LEN];
+        bytes[0..32].copy_from_slice(&self.previous_hash);
+        bytes[32..64].copy_from_slice(&self.merkle_root);
+        bytes[64..96].copy_from_slice(&self.state_root);
+        bytes[96..104].copy_from_slice(&self.timestamp.to_be_bytes());
+        bytes[104..112].copy_from_slice(&self.height.to_be_bytes());
+        bytes[112..144].copy_from_slice(&self.proposer);
+        bytes[144..160].copy_from_slice(&self.coinbase.to_be_bytes());
+        bytes[160..192].copy_from_slice(&self.finalized_hash);
+        bytes
+    }
+
+    /// SHA-256 of the canonical header encoding.
+    pub fn hash(&self) -> Hash {
+        Sha256::digest(self.canonical_bytes()).into()
+    }
+
+    pub fn is_signed(&self) -> bool {
+        self.signature != [0u8; 64]
+    }
+
+    /// Whether the header carries `public_key`'s signature over its hash.
+    pub fn verify_signature(&self, public_key: &[u8; 32]) -> bool {
+        let Ok(key) = VerifyingKey::from_bytes(public_key) else {
+            return false;
+        };
+        let signature = Signature::from_bytes(&self.signature);
+        key.verify(&block_signing_bytes(&self.hash()), &signature)
+            .is_ok()
+    }
+}
+
 impl Block {
-    pub fn new(previous_hash: Hash, nonce: u64, transactions: Vec<Transaction>) -> Self {
+    /// Builds a block on top of `previous_hash`. The state root and
+    /// finalized hash are left zeroed, the coinbase empty and the block
+    /// unsigned; the producer fills them in.
+    pub fn new(
+        previous_hash: Hash,
+        height: u64,
+        proposer: Address,
+        transactions: Vec<Transaction>,
+    ) -> Self {
         let merkle_root = compute_merkle_root(&transactions);
         Block {
-            previous_hash,
-            merkle_root,
-            nonce,
-            timestamp: Utc::now().timestamp(),
+            header: BlockHeader {
+                previous_hash,
+                merkle_root,
+                state_root: [0u8; 32],
+                timestamp: Utc::now().timestamp(),
+                height,
+                proposer,
+                coinbase: Amount::ZERO,
+                finalized_hash: [0u8; 32],
+                signature: [0u8; 64],
+            },
             transactions,
         }
     }
 
     pub fn get_datetime(&self) -> DateTime<Utc> {
-        DateTime::from_timestamp(self.timestamp, 0).unwrap_or_else(|| Utc::now())
+        DateTime::from_timestamp(self.header.timestamp, 0).unwrap_or_else(Utc::now)
+    }
+
+    pub fn hash(&self) -> Hash {
+        self.header.hash()
     }
 
-    pub fn hash(&self) -> Result<Hash, String> {
-        let config = standard();
-        let encoded =
-            encode_to_vec(self, config).map_err(|_| "Error: Failed to encode block".to_string())?;
+    /// Signs the block as its proposer. The rest of the header must be
+    /// final, as the signature covers its hash.
+    pub fn sign(&mut self, key: &SigningKey) {
+        let signature = key.clone().sign(&block_signing_bytes(&self.hash()));
+        self.header.signature = signature.to_bytes();
+    }
 
-        let digest = Sha256::digest(&encoded);
-        Ok(digest.into())
+    /// Checks that do not depend on the rest of the chain: transaction
+    /// signatures, the merkle root, that no transaction is included twice,
+    /// the size limit and the timestamp drift limit.
+    pub fn verify(&self) -> Result<(), String> {
+        for tx in &self.transactions {
+            if !tx.verify() {
+                return Err("Invalid transaction in block".to_string());
+            }
+        }
+        self.verify_contents()
+    }
+
+    /// [`Block::verify`] short of the transaction signatures.
+    pub(crate) fn verify_contents(&self) -> Result<(), String> {
+        if self.header.merkle_root != compute_merkle_root(&self.transactions) {
+            return Err("Merkle root does not match transactions".to_string());
+        }
+        let mut seen = HashSet::with_capacity(self.transactions.len());
+        if !self.transactions.iter().all(|tx| seen.insert(tx.hash())) {
+            return Err("Block includes a transaction twice".to_string());
+        }
+        let size: usize = self.transactions.iter().map(|tx| tx.size()).sum();
+        if size > MAX_BLOCK_BYTES {
+            return Err(format!(
+                "Block carries more than {} bytes of transactions",
+                MAX_BLOCK_BYTES
+            ));
+        }
+
+        if self.header.timestamp > Utc::now().timestamp() + MAX_FUTURE_DRIFT_SECS {
+            return Err("Block timestamp is too far in the future".to_string());
+        }
+
+        Ok(())
+    }
+
+    pub fn total_fees(&self) -> Result<Amount, BlockchainError> {
+        Amount::checked_sum(self.transactions.iter().map(|tx| tx.payload.fee))
     }
 }
 
 impl Blockchain {
+    /// The chain stored in `db`, which must not be locked meanwhile, as
+    /// its head is read here.
     pub fn new(db: Arc<Mutex<Database>>) -> Self {
-        Blockchain { db }
+        Self::with_config(db, MempoolConfig::default(), ChainParams::default())
+    }
+
+    pub fn with_config(
+        db: Arc<Mutex<Database>>,
+        mempool_config: MempoolConfig,
+        params: ChainParams,
+    ) -> Self {
+        let (head, readers) = match db.try_lock() {
+            Some(db) => {
+                let head = db
+                    .get_latest_block()
+                    .ok()
+                    .flatten()
+                    .map(|block| (block.hash(), block.header.height));
+                // Test databases share a file each test replaces, so their
+                // reads stay on the test's own connection.
+                let readers = match db.is_test() {
+                    true => None,
+                    false => ReadPool::open(&db).ok().map(Arc::new),
+                };
+                (head, readers)
+            }
+            None => (None, None),
+        };
+        Blockchain {
+            db,
+            readers,
+            head: ChainHead::new(head),
+            mempool: Arc::new(Mutex::new(Mempool::with_config(mempool_config))),
+            finality: Arc::new(Mutex::new(FinalityTracker::new())),
+            events: EventBus::default(),
+            params,
+        }
+    }
+
+    pub fn monetary_policy(&self) -> &MonetaryPolicy {
+        &self.params.policy
+    }
+
+    pub fn chain_params(&self) -> &ChainParams {
+        &self.params
+    }
+
+    /// Validates blocks under `params` from now on. Every node on the
+    /// chain must use the same ones.
+    pub fn set_chain_params(&mut self, params: ChainParams) {
+        self.params = params;
+    }
+
+    /// Publishes the chain's events on `events` from now on.
+    pub fn set_event_bus(&mut self, events: EventBus) {
+        self.events = events;
+    }
+
+    /// The chain's latest block, as of the last block applied or undone.
+    pub fn chain_head(&self) -> &ChainHead {
+        &self.head
+    }
+
+    pub async fn total_supply(&self) -> Result<Amount, String> {
+        self.read(|db| db.get_total_supply())
+            .await
+            .map_err(|_| "Error fetching total supply".to_string())
+    }
+
+    /// Where the supply came from, to audit issuance against.
+    pub async fn supply(&self) -> Result<Supply, String> {
+        self.read(|db| db.get_supply())
+            .await
+            .map_err(|_| "Error fetching supply".to_string())
+    }
+
+    /// The `limit` accounts with the largest balances, or stakes, largest
+    /// first.
+    pub async fn top_accounts(&self, by_stake: bool, limit: usize) -> Result<Vec<User>, String> {
+        self.read(move |db| db.get_top_accounts(by_stake, limit))
+            .await
+            .map_err(|_| "Error fetching accounts".to_string())
+    }
+
+    /// Runs `read` on a connection from the read pool, off the async
+    /// threads and without waiting for the node's own connection, which
+    /// may be busy applying a block. Databases without a pool, in memory
+    /// or for tests, are read through the node's connection.
+    async fn read<R, F>(&self, read: F) -> Result<R, rusqlite::Error>
+    where
+        F: FnOnce(&Database) -> Result<R, rusqlite::Error> + Send + 'static,
+        R: Send + 'static,
+    {
+        match &self.readers {
+            Some(readers) => readers.read(read).await,
+            None => read(&*self.db.lock().await),
+        }
+    }
+
+    /// The most the block at `height` may mint under the monetary policy,
+    /// or under the reward governance set for it.
+    pub async fn block_reward(&self, height: u64) -> Result<Amount, String> {
+        let supply = self.total_supply().await?;
+        Ok(match self.parameters(height).await?.block_reward {
+            Some(reward) => reward.min(self.params.policy.max_supply.saturating_sub(supply)),
+            None => self.params.policy.block_reward(height, supply),
+        })
+    }
+
+    /// The governed parameters in effect for the block at `height`.
+    pub async fn parameters(&self, height: u64) -> Result<Parameters, String> {
+        let db = self.db.lock().await;
+        db.get_parameters(height)
+            .map_err(|_| "Error fetching parameters".to_string())
+    }
+
+    /// How the fees of the block at `height` are split: as configured, or
+    /// as governance set for it.
+    pub async fn fee_routing(&self, height: u64) -> Result<FeeRouting, String> {
+        Ok(self.params.fees.governed(&self.parameters(height).await?))
+    }
+
+    /// Length of the slot the block at `height` is proposed in: as
+    /// configured, or as governance set for it.
+    pub async fn block_time(&self, height: u64) -> Result<Duration, String> {
+        let parameters = self.parameters(height).await?;
+        Ok(parameters.block_time.unwrap_or(self.params.block_time))
+    }
+
+    /// Every parameter change proposed, newest first, with the stake
+    /// behind each side as it stands now.
+    pub async fn proposals(&self) -> Result<Vec<(Proposal, Tally)>, String> {
+        let db = self.db.lock().await;
+        let read = || -> Result<_, rusqlite::Error> {
+            db.get_proposals()?
+                .into_iter()
+                .map(|proposal| {
+                    let tally = db.get_tally(proposal.id)?;
+                    Ok((proposal, tally))
+                })
+                .collect()
+        };
+        read().map_err(|_| "Error fetching proposals".to_string())
     }
 
     pub async fn create_genesis_block(&self) -> Result<(), String> {
+        self.create_genesis_block_at(Utc::now().timestamp()).await
+    }
+
+    /// Creates the genesis block with a fixed timestamp, so that every
+    /// node building it from the same accounts gets the same hash.
+    pub async fn create_genesis_block_at(&self, timestamp: i64) -> Result<(), String> {
         let mut db = self.db.lock().await;
 
         if db.get_latest_block().map_err(|_| "DB error")?.is_some() {
             return Err("Genesis block already exists".to_string());
         }
 
-        let genesis_block = Block::new([0u8; 32], 0, vec![]);
+        let mut genesis_block = Block::new([0u8; 32], 0, [0u8; 32], vec![]);
+        genesis_block.header.timestamp = timestamp;
+        let users = db.get_users().map_err(|_| "DB error")?;
+        genesis_block.header.state_root = compute_state_root(&users);
 
         db.add_block(&genesis_block)
             .map_err(|_| "Failed to add genesis block".to_string())?;
+        self.head.set(Some((genesis_block.hash(), 0)));
 
-        println!("Genesis block created!");
+        info!(hash = %hex::encode(genesis_block.hash()), "created genesis block");
         Ok(())
     }
 
-    pub async fn add_block(&mut self, block: Block, proposer: Address) -> Result<(), String> {
-        let is_registered = {
+    pub async fn add_block(&mut self, block: Block) -> Result<(), String> {
+        self.add_block_with(block, Checks::Full).await
+    }
+
+    /// Adds an unsigned block this node produced itself; see
+    /// [`Node::produce_block`](crate::node::Node::produce_block).
+    pub async fn add_local_block(&mut self, block: Block) -> Result<(), String> {
+        self.add_block_with(block, Checks::Local).await
+    }
+
+    /// Adds a block whose context-free checks already passed, leaving
+    /// only those that depend on the chain.
+    pub async fn add_verified_block(&mut self, block: VerifiedBlock) -> Result<(), String> {
+        self.add_block_with(block.into_inner(), Checks::Contextual)
+            .await
+    }
+
+    /// Adds a block on the way to a trusted checkpoint. The checkpoint
+    /// vouches for it, so its signatures and state root are not checked;
+    /// it is still executed to build up the state.
+    pub async fn add_checkpointed_block(&mut self, block: Block) -> Result<(), String> {
+        self.add_block_with(block, Checks::None).await
+    }
+
+    #[tracing::instrument(
+        name = "block",
+        skip_all,
+        fields(height = block.header.height, hash = %hex::encode(block.hash()))
+    )]
+    async fn add_block_with(&mut self, block: Block, checks: Checks) -> Result<(), String> {
+        let result = self.apply_block(block, checks).await;
+        match &result {
+            Ok(()) => info!("accepted block"),
+            Err(e) => debug!(error = %e, "rejected block"),
+        }
+        result
+    }
+
+    async fn apply_block(&mut self, block: Block, checks: Checks) -> Result<(), String> {
+        let signing_key = {
             let db = self.db.lock().await;
-            db.get_user(&proposer)
+            let proposer = db
+                .get_user(&block.header.proposer)
                 .map_err(|_| "DB error".to_string())?
-                .is_some()
+                .ok_or("Proposer not found".to_string())?;
+            signing_key(&db, &proposer, block.header.height)?
         };
 
-        if !is_registered {
-            return Err("Proposer not found".to_string());
+        if matches!(checks, Checks::Full | Checks::Local) {
+            block.verify()?;
+        }
+        if matches!(checks, Checks::Full | Checks::Contextual) {
+            if !block.header.is_signed() {
+                return Err("Block is not signed by its proposer".to_string());
+            }
+            if !block.header.verify_signature(&signing_key) {
+                return Err("Invalid proposer signature".to_string());
+            }
+            self.check_proposer(&block).await?;
         }
 
-        for tx in &block.transactions {
-            if !tx.verify() {
-                return Err("Invalid transaction in block".to_string());
+        if block.header.coinbase > self.block_reward(block.header.height).await? {
+            return Err("Coinbase exceeds the allowed block reward".to_string());
+        }
+
+        if let Some(median) = self.median_time_past().await?
+            && block.header.timestamp <= median
+        {
+            return Err("Block timestamp is not after the median time past".to_string());
+        }
+
+        if block.header.finalized_hash != [0u8; 32] {
+            let db = self.db.lock().await;
+            let known = db
+                .get_block(&block.header.finalized_hash)
+                .map_err(|_| "DB error".to_string())?
+                .is_some();
+            if !known {
+                return Err("Finalized hash refers to an unknown block".to_string());
             }
         }
 
+        let (updated, burned) = self.execute_block(&block).await?;
+        if checks != Checks::None
+            && block.header.state_root != self.state_root_with(&updated).await?
+        {
+            return Err("State root does not match block execution".to_string());
+        }
+
+        // The block and every account it touches are written in a single
+        // database transaction, so a failure leaves no partial state behind.
         let mut db = self.db.lock().await;
-        db.add_block(&block)
+        // Checked again under the lock the head only moves under: another
+        // block may have been written while this one was being executed.
+        let (head, next_height) = self.head.tip();
+        if block.header.previous_hash != head || block.header.height != next_height {
+            return Err(BlockchainError::NotOnHead.into());
+        }
+        db.commit_block(&block, &updated, burned)
             .map_err(|_| "Error adding block".to_string())?;
+        self.head.set(Some((block.hash(), block.header.height)));
         drop(db);
+        self.mempool
+            .lock()
+            .await
+            .remove_included(&block.transactions);
+        self.promote_confirmed(&block.transactions).await;
+
+        self.events.publish(NodeEvent::BlockApplied(block));
+        for user in updated {
+            self.events.publish(NodeEvent::AccountChanged(user));
+        }
+        Ok(())
+    }
 
-        self.reward_validator(proposer).await?;
+    /// Switches the chain to `branch`, which must continue from the stored
+    /// block `fork_point`: the blocks above it are undone and the branch is
+    /// applied with full validation. If any branch block is rejected the
+    /// chain is put back as it was. Returns the undone blocks, oldest first;
+    /// their transactions that the branch did not include go back to the
+    /// mempool.
+    pub async fn reorganize(
+        &mut self,
+        fork_point: Hash,
+        branch: Vec<Block>,
+    ) -> Result<Vec<Block>, String> {
+        let old_head = self
+            .get_latest_block()
+            .await
+            .map_err(|_| "DB error".to_string())?
+            .ok_or("Chain has no blocks")?
+            .hash();
+        let new_head = branch.last().ok_or("Branch has no blocks")?.hash();
+        if self
+            .get_block(fork_point)
+            .await
+            .map_err(|_| "DB error".to_string())?
+            .is_none()
+        {
+            return Err("Fork point is not on the chain".to_string());
+        }
 
-        Ok(())
+        let mut reverted = Vec::new();
+        while self
+            .get_latest_block()
+            .await
+            .map_err(|_| "DB error")?
+            .map(|b| b.hash())
+            != Some(fork_point)
+        {
+            match self.revert_head().await {
+                Ok(block) => reverted.push(block),
+                Err(e) => {
+                    self.restore(&reverted).await;
+                    return Err(e);
+                }
+            }
+        }
+        reverted.reverse();
+
+        for (applied, block) in branch.into_iter().enumerate() {
+            if let Err(e) = self.apply_block(block, Checks::Full).await {
+                for _ in 0..applied {
+                    if let Err(e) = self.revert_head().await {
+                        error!(error = %e, "failed to undo a rejected branch");
+                    }
+                }
+                self.restore(&reverted).await;
+                return Err(e);
+            }
+        }
+
+        for block in &reverted {
+            for transaction in &block.transactions {
+                // Included on the branch or no longer valid otherwise.
+                let _ = self.add_transaction(transaction.clone()).await;
+            }
+        }
+        info!(
+            old_head = %hex::encode(old_head),
+            new_head = %hex::encode(new_head),
+            depth = reverted.len(),
+            "reorganized chain"
+        );
+        self.events.publish(NodeEvent::ReorgHappened {
+            old_head,
+            new_head,
+            depth: reverted.len(),
+        });
+        Ok(reverted)
     }
 
-    pub async fn get_block(&self, hash: Hash) -> Result<Option<Block>, rusqlite::Error> {
-        let db = self.db.lock().await;
-        db.get_block(&hash)
+    /// Undoes the latest block and returns it with its transactions.
+    async fn revert_head(&mut self) -> Result<Block, String> {
+        let mut db = self.db.lock().await;
+        let head = db
+            .get_latest_block()
+            .map_err(|_| "DB error".to_string())?
+            .ok_or("Chain has no blocks")?;
+        let hash = head.hash();
+        let transactions = db
+            .get_block_transactions(&hash)
+            .map_err(|_| "DB error".to_string())?;
+        let restored = db
+            .revert_block(&hash)
+            .map_err(|_| "Error reverting block".to_string())?
+            .ok_or("Block cannot be undone")?;
+        self.head.set(
+            head.header
+                .height
+                .checked_sub(1)
+                .map(|height| (head.header.previous_hash, height)),
+        );
+        drop(db);
+
+        for user in restored {
+            self.events.publish(NodeEvent::AccountChanged(user));
+        }
+        Ok(Block {
+            header: head.header,
+            transactions,
+        })
     }
 
-    pub async fn get_blocks(&self) -> Result<Vec<Block>, rusqlite::Error> {
-        let db = self.db.lock().await;
-        db.get_blocks()
+    /// Re-applies blocks undone by a reorganization that had to be
+    /// abandoned. They were accepted before, so they are not validated again.
+    async fn restore(&mut self, reverted: &[Block]) {
+        for block in reverted.iter().rev() {
+            if let Err(e) = self.apply_block(block.clone(), Checks::None).await {
+                error!(error = %e, "failed to restore the chain after a reorganization");
+                return;
+            }
+        }
     }
 
-    pub async fn add_transaction(&self, transaction: Transaction) -> Result<(), String> {
+    /// Median timestamp of the last [`MEDIAN_TIME_SPAN`] blocks, or `None`
+    /// before the first block.
+    pub async fn median_time_past(&self) -> Result<Option<i64>, String> {
         let db = self.db.lock().await;
-        db.add_transaction(&transaction, transaction.verify())
-            .map_err(|_| "Error: Failed to add transaction to the database".to_string())?;
-        Ok(())
+        let mut timestamps = db
+            .get_recent_timestamps(MEDIAN_TIME_SPAN)
+            .map_err(|_| "Error fetching block timestamps".to_string())?;
+
+        timestamps.sort_unstable();
+        Ok(timestamps.get(timestamps.len() / 2).copied())
     }
 
-    pub async fn get_transactions(&self) -> Result<Vec<Transaction>, rusqlite::Error> {
+    pub async fn get_latest_block(&self) -> Result<Option<Block>, rusqlite::Error> {
         let db = self.db.lock().await;
-        db.get_all_transactions()
+        db.get_latest_block()
     }
 
-    pub async fn select_validator(&self) -> Result<Address, String> {
+    /// Commitment to the current account state; see [`compute_state_root`].
+    pub async fn state_root(&self) -> Result<Hash, String> {
         let db = self.db.lock().await;
         let users = db
             .get_users()
             .map_err(|_| "Error fetching users".to_string())?;
-
-        let stakes: Vec<u64> = users.iter().map(|user| user.stake).collect();
-        let addresses: Vec<Address> = users.iter().map(|user| user.address).collect();
-
-        if stakes.iter().all(|&stake| stake == 0) {
-            return Err("No users with stakes available".to_string());
-        }
-
-        let dist = WeightedIndex::new(&stakes)
-            .map_err(|_| "Error creating weighted distribution".to_string())?;
-        let mut rng = rand::thread_rng();
-        let selected_index = dist.sample(&mut rng);
-
-        Ok(addresses[selected_index])
+        Ok(compute_state_root(&users))
     }
 
-    pub async fn reward_validator(&self, validator_address: Address) -> Result<(), String> {
+    /// The latest block's header and every account as of that block, read
+    /// together so they agree. `None` before the first block.
+    pub async fn snapshot(&self) -> Result<Option<(BlockHeader, Vec<SnapshotAccount>)>, String> {
         let db = self.db.lock().await;
+        let Some(head) = db
+            .get_latest_block()
+            .map_err(|_| "Error fetching latest block".to_string())?
+        else {
+            return Ok(None);
+        };
+        let accounts = db
+            .snapshot_accounts()
+            .map_err(|_| "Error reading accounts".to_string())?;
+        Ok(Some((head.header, accounts)))
+    }
 
-        let user = db
-            .get_user(&validator_address)
-            .map_err(|_| "Error fetching user".to_string())?;
+    /// Starts an empty chain from a snapshot taken at the last of `headers`,
+    /// which must run from genesis. The accounts must hash to that header's
+    /// state root. Blocks before it are stored without their transactions.
+    pub async fn install_snapshot(
+        &self,
+        headers: &[BlockHeader],
+        accounts: &[SnapshotAccount],
+    ) -> Result<(), String> {
+        let head = headers.last().ok_or("Snapshot has no headers")?;
+        let users: Vec<User> = accounts
+            .iter()
+            .map(|account| account.user.clone())
+            .collect();
+        if compute_state_root(&users) != head.state_root {
+            return Err("Snapshot does not match the header's state root".to_string());
+        }
 
-        if let Some(mut user) = user {
-            let total_stake = db.get_total_stake().unwrap() as f64;
-            let reward = (user.stake as f64 / total_stake).round() as u64;
-            user.balance += reward;
-            db.update_user(&user)
-                .map_err(|_| "Error updating user".to_string())?;
-            Ok(())
-        } else {
-            Err("Validator not found".to_string())
+        let mut db = self.db.lock().await;
+        if db
+            .get_latest_block()
+            .map_err(|_| "DB error".to_string())?
+            .is_some()
+        {
+            return Err("Chain already has blocks".to_string());
         }
+        db.install_snapshot(headers, accounts)
+            .map_err(|_| "Error installing snapshot".to_string())?;
+        self.head.set(Some((head.hash(), head.height)));
+        info!(
+            height = head.height,
+            accounts = accounts.len(),
+            "installed state snapshot"
+        );
+        Ok(())
+    }
+
+    /// Stores headers without their blocks, for nodes that keep only the
+    /// header chain. They must follow on from each other and from a stored
+    /// header; if that is below the head, they replace the headers above it
+    /// as long as they make a longer chain.
+    pub async fn add_headers(&self, headers: &[BlockHeader]) -> Result<(), String> {
+        let (Some(first), Some(last)) = (headers.first(), headers.last()) else {
+            return Ok(());
+        };
+        if headers.windows(2).any(|pair| {
+            pair[1].previous_hash != pair[0].hash() || pair[1].height != pair[0].height + 1
+        }) {
+            return Err("Headers do not follow on from each other".to_string());
+        }
+
+        let mut db = self.db.lock().await;
+        let (head, height) = self.head.tip();
+        if first.previous_hash == head && first.height == height {
+            db.add_headers(headers)
+                .map_err(|_| "Error storing headers".to_string())?;
+        } else {
+            let parent = db
+                .get_block(&first.previous_hash)
+                .map_err(|_| "DB error".to_string())?;
+            if parent.is_none_or(|parent| parent.header.height + 1 != first.height) {
+                return Err("Headers do not continue a stored header".to_string());
+            }
+            if last.height < height {
+                return Err("Headers do not make a longer chain".to_string());
+            }
+            db.replace_headers(first.height, headers)
+                .map_err(|_| "Error storing headers".to_string())?;
+            info!(
+                depth = height - first.height,
+                height = last.height,
+                "switched to a longer header chain"
+            );
+        }
+        self.head.set(Some((last.hash(), last.height)));
+        Ok(())
+    }
+
+    /// Follows the transactions of `address`, which nodes that keep only
+    /// headers find through proofs from their peers the next time sync runs.
+    /// Returns whether it was not watched already.
+    pub async fn watch_address(&self, address: &Address) -> Result<bool, String> {
+        let db = self.db.lock().await;
+        db.watch_address(address)
+            .map_err(|_| "Error watching address".to_string())
+    }
+
+    /// Registers `url` to be notified about `address`, with a fresh secret
+    /// to sign the notifications with; see [`crate::webhooks`].
+    /// Registering the same pair again returns the existing webhook.
+    pub async fn register_webhook(&self, address: &Address, url: &str) -> Result<Webhook, String> {
+        webhooks::check_url(url)?;
+        let db = self.db.lock().await;
+        db.add_webhook(address, url, &rand::random())
+            .map_err(|_| "Error registering webhook".to_string())
+    }
+
+    pub async fn webhooks(&self) -> Result<Vec<Webhook>, String> {
+        let db = self.db.lock().await;
+        db.get_webhooks()
+            .map_err(|_| "Error reading webhooks".to_string())
+    }
+
+    /// The webhooks registered for any of `addresses`.
+    pub async fn webhooks_for(&self, addresses: &[Address]) -> Result<Vec<Webhook>, String> {
+        let db = self.db.lock().await;
+        db.get_webhooks_for(addresses)
+            .map_err(|_| "Error reading webhooks".to_string())
+    }
+
+    /// Returns whether the webhook existed.
+    pub async fn remove_webhook(&self, id: u64) -> Result<bool, String> {
+        let db = self.db.lock().await;
+        db.delete_webhook(id)
+            .map_err(|_| "Error removing webhook".to_string())
+    }
+
+    /// Every watched address and the height its transactions have been
+    /// found up to, exclusive.
+    pub async fn watched_addresses(&self) -> Result<Vec<(Address, u64)>, String> {
+        let db = self.db.lock().await;
+        db.get_watched_addresses()
+            .map_err(|_| "Error reading watched addresses".to_string())
+    }
+
+    /// Proofs for the transactions of `addresses` in the blocks from
+    /// `from` up to `to`, at most about `limit` of them, and the height the
+    /// blocks were scanned up to. Blocks are scanned whole, so the last one
+    /// may take the proofs past `limit`. Nothing is scanned if the blocks
+    /// from `from` are not all stored whole.
+    pub async fn transaction_proofs(
+        &self,
+        addresses: &[Address],
+        from: u64,
+        to: u64,
+        limit: usize,
+    ) -> Result<(Vec<TransactionProof>, u64), String> {
+        let db = self.db.lock().await;
+        let (_, height) = self.head.tip();
+        let oldest = db.get_oldest_block().map_err(|_| "DB error".to_string())?;
+        if from < oldest {
+            return Ok((Vec::new(), from));
+        }
+        let to = to.min(height).max(from);
+
+        let mut found = db
+            .get_transactions_in_range(addresses, from, to, limit + 1)
+            .map_err(|_| "Error fetching transactions".to_string())?;
+        let mut scanned_to = to;
+        if found.len() > limit {
+            let last = found[limit.saturating_sub(1)].1.height;
+            found = db
+                .get_transactions_in_range(addresses, from, last + 1, usize::MAX)
+                .map_err(|_| "Error fetching transactions".to_string())?;
+            scanned_to = last + 1;
+        }
+
+        let mut proofs = Vec::with_capacity(found.len());
+        let mut block: Option<(Hash, Vec<Transaction>)> = None;
+        for (transaction, location) in found {
+            if block
+                .as_ref()
+                .is_none_or(|(hash, _)| *hash != location.block_hash)
+            {
+                let transactions = db
+                    .get_block_transactions(&location.block_hash)
+                    .map_err(|_| "Error fetching block".to_string())?;
+                block = Some((location.block_hash, transactions));
+            }
+            let (_, transactions) = block.as_ref().unwrap();
+            proofs.push(TransactionProof {
+                proof: merkle_proof(transactions, location.index as usize),
+                transaction,
+                block_hash: location.block_hash,
+                index: location.index,
+                count: transactions.len() as u32,
+            });
+        }
+        Ok((proofs, scanned_to))
+    }
+
+    /// Checks proofs a peer sent for the transactions of `addresses` in the
+    /// blocks from `from` up to `scanned_to`, and stores the transactions.
+    /// Every proof must be of a stored block in that range and of a signed
+    /// transaction of one of the addresses, or none are stored. Returns the
+    /// number of transactions that were new.
+    pub async fn add_proven_transactions(
+        &self,
+        addresses: &[Address],
+        from: u64,
+        scanned_to: u64,
+        proofs: &[TransactionProof],
+    ) -> Result<usize, String> {
+        let mut db = self.db.lock().await;
+        let (_, height) = self.head.tip();
+        if scanned_to > height {
+            return Err("Proofs go past the header chain".to_string());
+        }
+
+        let mut proven = Vec::with_capacity(proofs.len());
+        for proof in proofs {
+            let header = db
+                .get_block(&proof.block_hash)
+                .map_err(|_| "DB error".to_string())?
+                .ok_or("Proof is for a block not on the chain")?
+                .header;
+            if !(from..scanned_to).contains(&header.height) {
+                return Err("Proof is for a block outside the range asked for".to_string());
+            }
+            let transaction = &proof.transaction;
+            let root = &header.merkle_root;
+            if !verify_merkle_proof(transaction, proof.index, proof.count, &proof.proof, root) {
+                return Err("Merkle proof does not match the block".to_string());
+            }
+            if !transaction.verify() {
+                return Err("Proven transaction has an invalid signature".to_string());
+            }
+            if !addresses.contains(&transaction.payload.receiver)
+                && !addresses.contains(&transaction.sender_address())
+            {
+                return Err("Proven transaction is not of an address asked for".to_string());
+            }
+            let location = TransactionLocation {
+                block_hash: proof.block_hash,
+                height: header.height,
+                index: proof.index,
+            };
+            proven.push((transaction.clone(), location));
+        }
+
+        db.add_proven_transactions(&proven, addresses, scanned_to)
+            .map_err(|_| "Error storing proven transactions".to_string())
+    }
+
+    pub async fn get_block(&self, hash: Hash) -> Result<Option<Block>, rusqlite::Error> {
+        self.read(move |db| db.get_block(&hash)).await
+    }
+
+    /// Like [`Blockchain::get_block`], but with the block's transactions.
+    pub async fn get_full_block(&self, hash: Hash) -> Result<Option<Block>, rusqlite::Error> {
+        self.read(move |db| {
+            let Some(mut block) = db.get_block(&hash)? else {
+                return Ok(None);
+            };
+            block.transactions = db.get_block_transactions(&hash)?;
+            Ok(Some(block))
+        })
+        .await
+    }
+
+    pub async fn get_block_by_height(&self, height: u64) -> Result<Option<Block>, rusqlite::Error> {
+        self.read(move |db| db.get_block_by_height(height)).await
+    }
+
+    /// See [`Database::get_headers_range`].
+    pub async fn get_headers_range(
+        &self,
+        from: u64,
+        to: u64,
+        limit: usize,
+    ) -> Result<Vec<BlockHeader>, rusqlite::Error> {
+        self.read(move |db| db.get_headers_range(from, to, limit))
+            .await
+    }
+
+    pub async fn get_user(&self, address: &Address) -> Result<Option<User>, rusqlite::Error> {
+        let address = *address;
+        self.read(move |db| db.get_user(&address)).await
+    }
+
+    /// Looks a transaction up by hash, first in the mempool and then among
+    /// confirmed transactions. The flag tells whether it was confirmed.
+    pub async fn get_transaction(
+        &self,
+        hash: &Hash,
+    ) -> Result<Option<(Transaction, bool)>, String> {
+        let pending = self
+            .get_pending_transactions()
+            .await
+            .into_iter()
+            .find(|tx| &tx.hash() == hash);
+        if let Some(tx) = pending {
+            return Ok(Some((tx, false)));
+        }
+
+        let hash = *hash;
+        let confirmed = self
+            .read(move |db| db.get_transaction_by_hash(&hash))
+            .await
+            .map_err(|_| "Error fetching transaction".to_string())?;
+        Ok(confirmed.map(|tx| (tx, true)))
+    }
+
+    /// See [`Database::get_oldest_block`].
+    pub async fn oldest_block(&self) -> Result<u64, rusqlite::Error> {
+        self.read(|db| db.get_oldest_block()).await
+    }
+
+    /// See [`Database::prune_blocks`].
+    pub async fn prune_blocks(&self, height: u64) -> Result<usize, rusqlite::Error> {
+        let mut db = self.db.lock().await;
+        db.prune_blocks(height)
+    }
+
+    /// See [`Database::get_balance_at`].
+    pub async fn get_balance_at(
+        &self,
+        address: &Address,
+        height: u64,
+    ) -> Result<Option<Amount>, rusqlite::Error> {
+        let address = *address;
+        self.read(move |db| db.get_balance_at(&address, height))
+            .await
+    }
+
+    /// See [`Database::get_balance_changes`].
+    pub async fn get_balance_changes(
+        &self,
+        address: &Address,
+    ) -> Result<Vec<BalanceChange>, rusqlite::Error> {
+        let address = *address;
+        self.read(move |db| db.get_balance_changes(&address)).await
+    }
+
+    /// See [`Database::get_transaction_with_block`].
+    pub async fn get_transaction_with_block(
+        &self,
+        hash: &Hash,
+    ) -> Result<Option<(Transaction, TransactionLocation)>, rusqlite::Error> {
+        let hash = *hash;
+        self.read(move |db| db.get_transaction_with_block(&hash))
+            .await
+    }
+
+    /// See [`Database::get_transactions_by_address`].
+    pub async fn get_transactions_by_address(
+        &self,
+        address: &Address,
+        before: Option<TransactionLocation>,
+        limit: usize,
+    ) -> Result<Vec<(Transaction, TransactionLocation)>, rusqlite::Error> {
+        let address = *address;
+        self.read(move |db| db.get_transactions_by_address(&address, before, limit))
+            .await
+    }
+
+    /// Nonce the account's next transaction must carry, counting both
+    /// confirmed and pending transactions.
+    pub async fn account_nonce(&self, address: &Address) -> Result<u64, String> {
+        let confirmed = {
+            let db = self.db.lock().await;
+            let user = db
+                .get_user(address)
+                .map_err(|_| "Error fetching user".to_string())?
+                .ok_or("User not found".to_string())?;
+            db.get_next_nonce(&user.public_key)
+                .map_err(|_| "Error fetching nonce".to_string())?
+        };
+        Ok(self.next_nonce(address, confirmed).await)
+    }
+
+    /// Checks every stored block again: its hash, the link to its parent,
+    /// its merkle root and signatures, and that its transfers apply to the
+    /// accounts recorded before it. Finally the accounts are hashed against
+    /// the head's state root. Blocks kept as headers only, below the oldest
+    /// whole block or everywhere when `headers_only`, skip the body checks.
+    pub async fn verify_chain(&self, headers_only: bool) -> Result<ChainReport, String> {
+        let db = self.db.lock().await;
+        let stored = db
+            .get_stored_blocks()
+            .map_err(|e| format!("Error reading blocks: {}", e))?;
+        let oldest = db
+            .get_oldest_block()
+            .map_err(|e| format!("Error reading the oldest block: {}", e))?;
+        let mut report = ChainReport::default();
+        let mut previous: Option<(u64, Hash)> = None;
+        let mut head = None;
+
+        for (stored_hash, header) in stored {
+            report.blocks += 1;
+            let header = match header {
+                Ok(header) => header,
+                Err(e) => {
+                    report.problems.push(format!(
+                        "Block {} does not decode: {}",
+                        hex::encode(&stored_hash),
+                        e
+                    ));
+                    continue;
+                }
+            };
+            let hash = header.hash();
+            let name = format!("Block {} at height {}", hex::encode(hash), header.height);
+            if stored_hash != hash {
+                report.problems.push(format!(
+                    "{} is stored as {}",
+                    name,
+                    hex::encode(&stored_hash)
+                ));
+            }
+            match previous {
+                None if header.height == 0 && header.previous_hash != [0u8; 32] => {
+                    report
+                        .problems
+                        .push(format!("{} is a genesis block with a parent", name));
+                }
+                Some((height, _)) if height == header.height => {
+                    report
+                        .problems
+                        .push(format!("{} is a second block at its height", name));
+                }
+                Some((height, _)) if height + 1 != header.height => {
+                    report.problems.push(format!(
+                        "{} follows height {}; blocks in between are missing",
+                        name, height
+                    ));
+                }
+                Some((_, parent)) if header.previous_hash != parent => {
+                    report
+                        .problems
+                        .push(format!("{} does not link to the block below it", name));
+                }
+                _ => {}
+            }
+            previous = Some((header.height, hash));
+            head = Some(header.clone());
+
+            if header.is_signed() {
+                let key = db.get_user(&header.proposer).and_then(|proposer| {
+                    proposer
+                        .map(|proposer| {
+                            let key = db.get_validator_key(&proposer.address, header.height)?;
+                            Ok(key.unwrap_or(proposer.public_key))
+                        })
+                        .transpose()
+                });
+                match key {
+                    Ok(Some(key)) if header.verify_signature(&key) => {}
+                    Ok(Some(_)) => report
+                        .problems
+                        .push(format!("{} has an invalid proposer signature", name)),
+                    Ok(None) => report
+                        .problems
+                        .push(format!("{} was proposed by an unknown account", name)),
+                    Err(e) => report
+                        .problems
+                        .push(format!("{}: error reading its proposer: {}", name, e)),
+                }
+            }
+
+            if headers_only || header.height < oldest {
+                continue;
+            }
+            let transactions = match db.get_block_transactions(&hash) {
+                Ok(transactions) => transactions,
+                Err(e) => {
+                    report
+                        .problems
+                        .push(format!("{}: its transactions do not decode: {}", name, e));
+                    continue;
+                }
+            };
+            if header.merkle_root != compute_merkle_root(&transactions) {
+                report
+                    .problems
+                    .push(format!("{} does not match its stored transactions", name));
+                continue;
+            }
+            if !transactions.iter().all(Transaction::verify) {
+                report
+                    .problems
+                    .push(format!("{} holds an invalid transaction", name));
+            }
+            let block = Block {
+                header,
+                transactions,
+            };
+            if let Err(e) = replay_block(&db, &block, self.params.fees) {
+                report.problems.push(format!("{}: {}", name, e));
+            }
+        }
+
+        if let Some(head) = head {
+            let users = db
+                .get_users()
+                .map_err(|e| format!("Error reading accounts: {}", e))?;
+            if compute_state_root(&users) != head.state_root {
+                report.problems.push(
+                    "Accounts do not match the head block's state root; they were changed \
+                     outside a block since, or are corrupt"
+                        .to_string(),
+                );
+            }
+        }
+        Ok(report)
+    }
+
+    /// See [`Database::reindex`].
+    pub async fn reindex(&self) -> Result<usize, rusqlite::Error> {
+        let mut db = self.db.lock().await;
+        db.reindex()
+    }
+
+    pub async fn get_blocks(&self) -> Result<Vec<Block>, rusqlite::Error> {
+        self.read(|db| db.get_blocks()).await
+    }
+
+    /// Latest finalized block as `(hash, height)`, if any.
+    pub async fn latest_finalized(&self) -> Result<Option<(Hash, u64)>, String> {
+        let db = self.db.lock().await;
+        db.get_latest_finalized()
+            .map_err(|_| "Error fetching finalized block".to_string())
+    }
+
+    /// Counts a validator's vote towards finalizing a stored block, weighed
+    /// by the voter's current own and delegated stake. Once two thirds of
+    /// all stake has voted for the block it is marked final.
+    pub async fn add_vote(&self, vote: Vote) -> Result<VoteOutcome, String> {
+        if !vote.verify() {
+            return Err("Invalid vote signature".to_string());
+        }
+
+        let db = self.db.lock().await;
+        let block = db
+            .get_block(&vote.block_hash)
+            .map_err(|_| "DB error".to_string())?
+            .ok_or("Vote for unknown block".to_string())?;
+        if block.header.height != vote.height {
+            return Err("Vote height does not match block".to_string());
+        }
+
+        let users = db
+            .get_users()
+            .map_err(|_| "Error fetching users".to_string())?;
+        let keys = db
+            .get_validator_keys()
+            .map_err(|_| "Error fetching validator keys".to_string())?;
+        // Keyed by the voter each validator's votes come from: the hash of
+        // its consensus key, or its own address if it never registered one.
+        let stakes: HashMap<Address, Amount> = staking::validators(&users)
+            .into_iter()
+            .map(|validator| {
+                let voter = match keys.get(&validator.user.address) {
+                    Some(key) => Sha256::digest(key).into(),
+                    None => validator.user.address,
+                };
+                (voter, validator.weight())
+            })
+            .collect();
+        let finalized_height = db
+            .get_latest_finalized()
+            .map_err(|_| "Error fetching finalized block".to_string())?
+            .map(|(_, height)| height);
+
+        let block_hash = vote.block_hash;
+        let mut finality = self.finality.lock().await;
+        let outcome = finality.add_vote(vote, &stakes, finalized_height)?;
+
+        if outcome == VoteOutcome::Finalized {
+            db.mark_finalized(&block_hash)
+                .map_err(|_| "Error marking block final".to_string())?;
+        }
+        Ok(outcome)
+    }
+
+    /// Admits a signed transaction to the mempool if it is valid against the
+    /// current state: both accounts exist, the nonce is unused and follows
+    /// on from the sender's pending transactions (or replaces one), and the
+    /// sender can pay for it on top of everything else it has pending. The
+    /// RPC API, peers and the node itself all submit transactions here.
+    ///
+    /// A transaction a little ahead of the sender's next nonce waits in the
+    /// mempool's future queue instead, and is admitted once the ones before
+    /// it are, so wallets can send several without waiting on each.
+    pub async fn add_transaction(&self, transaction: Transaction) -> Result<(), BlockchainError> {
+        if !transaction.verify() {
+            return Err(BlockchainError::InvalidSignature);
+        }
+
+        let sender = transaction.sender_address();
+        let Transfer {
+            receiver,
+            nonce,
+            kind,
+            ..
+        } = transaction.payload;
+        if kind.is_to_self() && receiver != sender {
+            return Err(BlockchainError::WrongReceiver(kind));
+        }
+        transaction.payload.check_data()?;
+        let database = |reason: &str| BlockchainError::Database(reason.to_string());
+        let (account, confirmed) = {
+            let db = self.db.lock().await;
+            let account = db
+                .get_user(&sender)
+                .map_err(|_| database("Error fetching sender"))?
+                .ok_or(BlockchainError::UnknownSender)?;
+            if kind == TxKind::Transfer {
+                db.get_user(&receiver)
+                    .map_err(|_| database("Error fetching receiver"))?
+                    .ok_or(BlockchainError::UnknownReceiver)?;
+            }
+            let validator = match kind {
+                TxKind::Delegate => db
+                    .get_user(&receiver)
+                    .map_err(|_| database("Error fetching validator"))?,
+                _ => None,
+            };
+            staking::check(&account, validator.as_ref(), &transaction)
+                .map_err(BlockchainError::Staking)?;
+            // Checked against the next block's rules alone: the fee against
+            // the governed rate, proposals and votes against the chain.
+            let height = self.head.get().map_or(0, |(_, height)| height + 1);
+            let mut rules =
+                BlockRules::new(&db, &self.params, height).map_err(BlockchainError::Database)?;
+            let required = rules.parameters().required_fee(&transaction);
+            if transaction.payload.fee < required {
+                return Err(BlockchainError::FeeTooLow { required });
+            }
+            rules
+                .check(&db, &account, &transaction)
+                .map_err(BlockchainError::Governance)?;
+            rules
+                .check_key(&db, &account, &transaction)
+                .map_err(BlockchainError::Staking)?;
+            rules
+                .check_beacon(&db, &account, &transaction)
+                .map_err(BlockchainError::Beacon)?;
+            let confirmed = db
+                .get_next_nonce(&transaction.sender_public_key)
+                .map_err(|_| database("Error fetching nonce"))?;
+            (account, confirmed)
+        };
+
+        let mut mempool = self.mempool.lock().await;
+        if nonce < confirmed {
+            return Err(BlockchainError::NonceUsed);
+        }
+        let expected = mempool.next_nonce(&sender, confirmed);
+        if nonce > expected {
+            return mempool.park(transaction, expected);
+        }
+        self.admit(&mut mempool, &account, transaction)?;
+        self.promote_future(&mut mempool, &account, confirmed);
+        Ok(())
+    }
+
+    /// Adds a transaction that follows on from the sender's pending ones
+    /// if the sender can pay for it on top of them.
+    fn admit(
+        &self,
+        mempool: &mut Mempool,
+        account: &User,
+        transaction: Transaction,
+    ) -> Result<(), BlockchainError> {
+        let nonce = transaction.payload.nonce;
+        // Pending stakes and unstakes are not counted until confirmed, so
+        // neither can fund the other.
+        let spend = mempool
+            .pending_spend(&account.address, nonce)?
+            .checked_add(transaction.payload.balance_cost()?)?;
+        if account.balance < spend {
+            return Err(BlockchainError::InsufficientBalance);
+        }
+        let unstake = mempool
+            .pending_unstake(&account.address, nonce)?
+            .checked_add(transaction.payload.stake_cost())?;
+        if account.stake < unstake {
+            return Err(BlockchainError::InsufficientStake);
+        }
+
+        mempool.insert(transaction.clone())?;
+        self.events.publish(NodeEvent::TxAccepted(transaction));
+        Ok(())
+    }
+
+    /// Admits the sender's queued transactions that no longer wait on a
+    /// gap, in nonce order. One that cannot be admitted is dropped, and
+    /// those after it keep waiting.
+    fn promote_future(&self, mempool: &mut Mempool, account: &User, confirmed: u64) {
+        loop {
+            let next = mempool.next_nonce(&account.address, confirmed);
+            let Some(tx) = mempool.take_future(&account.address, next) else {
+                return;
+            };
+            if let Err(e) = self.admit(mempool, account, tx) {
+                debug!(nonce = next, error = %e, "dropped queued transaction");
+                return;
+            }
+        }
+    }
+
+    /// Admits queued transactions of the senders in `transactions` that a
+    /// block just confirmed their missing nonces for.
+    async fn promote_confirmed(&self, transactions: &[Transaction]) {
+        let senders: HashMap<Address, &[u8; 32]> = transactions
+            .iter()
+            .map(|tx| (tx.sender_address(), &tx.sender_public_key))
+            .collect();
+        let mut mempool = self.mempool.lock().await;
+        for (sender, public_key) in senders {
+            if !mempool.has_future(&sender) {
+                continue;
+            }
+            let db = self.db.lock().await;
+            let (Ok(Some(account)), Ok(confirmed)) =
+                (db.get_user(&sender), db.get_next_nonce(public_key))
+            else {
+                continue;
+            };
+            drop(db);
+            self.promote_future(&mut mempool, &account, confirmed);
+        }
+    }
+
+    /// Saves the pending transactions so they survive a restart. Those
+    /// waiting on an earlier nonce are not kept.
+    pub async fn persist_mempool(&self) -> Result<(), String> {
+        let pending = self.get_pending_transactions().await;
+        let mut db = self.db.lock().await;
+        db.save_mempool(&pending)
+            .map_err(|_| "Error saving mempool".to_string())
+    }
+
+    /// Re-queues the transactions saved by [`Blockchain::persist_mempool`],
+    /// dropping any that no longer fit, and returns how many were restored.
+    pub async fn restore_mempool(&self) -> Result<usize, String> {
+        let saved = {
+            let mut db = self.db.lock().await;
+            db.take_mempool()
+                .map_err(|_| "Error loading mempool".to_string())?
+        };
+
+        let mut restored = 0;
+        for tx in saved {
+            if self.add_transaction(tx).await.is_ok() {
+                restored += 1;
+            }
+        }
+        Ok(restored)
+    }
+
+    pub async fn get_pending_transactions(&self) -> Vec<Transaction> {
+        let mempool = self.mempool.lock().await;
+        mempool.pending()
+    }
+
+    pub async fn pending_count(&self) -> usize {
+        self.mempool.lock().await.len()
+    }
+
+    /// See [`Mempool::set_config`].
+    pub async fn set_mempool_config(&self, config: MempoolConfig) {
+        self.mempool.lock().await.set_config(config);
+    }
+
+    /// Transactions waiting in the mempool for an earlier nonce.
+    pub async fn queued_count(&self) -> usize {
+        self.mempool.lock().await.future_len()
+    }
+
+    pub async fn get_pending_transaction(
+        &self,
+        sender: &Address,
+        nonce: u64,
+    ) -> Option<Transaction> {
+        let mempool = self.mempool.lock().await;
+        mempool.get(sender, nonce).cloned()
+    }
+
+    /// Every pending transaction, highest fee-per-byte first, for a block
+    /// to be built from. They stay in the mempool until a block including
+    /// them is applied, so any the block leaves out, or a block that fails,
+    /// loses none of them.
+    pub async fn block_candidates(&self) -> Vec<Transaction> {
+        let mut mempool = self.mempool.lock().await;
+        mempool.evict_expired();
+        mempool.pending()
+    }
+
+    pub async fn mempool_stats(&self) -> MempoolStats {
+        let mempool = self.mempool.lock().await;
+        mempool.stats().clone()
+    }
+
+    pub async fn next_nonce(&self, sender: &Address, confirmed: u64) -> u64 {
+        let mempool = self.mempool.lock().await;
+        mempool.next_nonce(sender, confirmed)
+    }
+
+    pub async fn get_transactions(&self) -> Result<Vec<Transaction>, rusqlite::Error> {
+        let db = self.db.lock().await;
+        db.get_all_transactions()
+    }
+
+    /// Every account validating with stake of its own, with the stake
+    /// delegated to it, ordered by address.
+    pub async fn validators(&self) -> Result<Vec<Validator>, String> {
+        let db = self.db.lock().await;
+        let users = db
+            .get_users()
+            .map_err(|_| "Error fetching users".to_string())?;
+        Ok(staking::validators(&users))
+    }
+
+    /// Each registered validator's current consensus key; see
+    /// [`crate::staking`].
+    pub async fn validator_keys(&self) -> Result<HashMap<Address, [u8; 32]>, String> {
+        let db = self.db.lock().await;
+        db.get_validator_keys()
+            .map_err(|_| "Error fetching validator keys".to_string())
+    }
+
+    /// The account that blocks and votes signed with `key` now count for:
+    /// the validator that holds it as its consensus key, or else the
+    /// account the key belongs to unless that registered another. `None`
+    /// if the key signs for no one, such as one rotated out.
+    pub async fn validator_for_key(&self, key: &[u8; 32]) -> Result<Option<Address>, String> {
+        let db = self.db.lock().await;
+        let fetching = |_| "Error fetching validator keys".to_string();
+        let address = match db.get_key_owner(key).map_err(fetching)? {
+            Some(owner) => owner,
+            None => Sha256::digest(key).into(),
+        };
+        let current = db
+            .get_validator_key(&address, u64::MAX)
+            .map_err(fetching)?
+            .unwrap_or(*key);
+        Ok((current == *key).then_some(address))
+    }
+
+    /// The validator chosen to propose the block at `height` on top of
+    /// `previous_hash` in `slot`; see [`liveness::slot_proposer`].
+    pub async fn slot_proposer(
+        &self,
+        previous_hash: Hash,
+        height: u64,
+        slot: u64,
+    ) -> Result<Address, String> {
+        let db = self.db.lock().await;
+        let users = db
+            .get_users()
+            .map_err(|_| "Error fetching users".to_string())?;
+        let seed = slot_seed(&db, &self.params, &previous_hash, height)?;
+        liveness::slot_proposer(&staking::validators(&users), &seed, slot)
+            .ok_or("No users with stakes available".to_string())
+    }
+
+    /// Refuses a block from anyone but the validator picked to propose in
+    /// the slot its timestamp falls in, once slots are binding.
+    async fn check_proposer(&self, block: &Block) -> Result<(), String> {
+        let height = block.header.height;
+        if !self.params.forks.is_active(Feature::Liveness, height) {
+            return Ok(());
+        }
+        let slot = liveness::slot(block.header.timestamp, self.block_time(height).await?);
+        let expected = self
+            .slot_proposer(block.header.previous_hash, height, slot)
+            .await?;
+        if block.header.proposer != expected {
+            return Err("Proposer was not picked for the block's slot".to_string());
+        }
+        Ok(())
+    }
+
+    /// The seed of proposer selection in `epoch`; see [`crate::beacon`].
+    pub async fn beacon(&self, epoch: u64) -> Result<Hash, String> {
+        let db = self.db.lock().await;
+        beacon_seed(&db, epoch, self.params.liveness.epoch_length)
+    }
+
+    /// The validator whose slot it is now to propose on top of the head,
+    /// the same on every node with the same chain and clock.
+    pub async fn select_validator(&self) -> Result<Address, String> {
+        let (previous_hash, height) = self.head.tip();
+        let block_time = self.block_time(height).await?;
+        let slot = liveness::slot(Utc::now().timestamp(), block_time);
+        self.slot_proposer(previous_hash, height, slot).await
     }
 
     pub async fn slash_validator(
         &self,
         validator_address: Address,
-        penalty: u64,
+        penalty: Amount,
     ) -> Result<(), String> {
         let db = self.db.lock().await;
         let user = db
             .get_user(&validator_address)
             .map_err(|_| "Error fetching user".to_string())?;
 
-        if let Some(mut user) = user {
-            if user.stake < penalty {
-                user.stake = 0;
-            } else {
-                user.stake -= penalty;
+        if let Some(user) = user {
+            // Delegators lose the same fraction of their stake.
+            let delegators = db
+                .get_delegators(&validator_address)
+                .map_err(|_| "Error fetching delegators".to_string())?;
+            let mut slashed = Amount::ZERO;
+            let mut stake = user.stake;
+            for (address, share) in staking::share_penalty(&user, &delegators, penalty) {
+                let mut account = match delegators.iter().find(|d| d.address == address) {
+                    Some(delegator) => delegator.clone(),
+                    None => user.clone(),
+                };
+                account.stake = account.stake.saturating_sub(share);
+                if address == validator_address {
+                    stake = account.stake;
+                }
+                db.update_user(&account)
+                    .map_err(|_| "Error updating user".to_string())?;
+                slashed = slashed.checked_add(share)?;
             }
-
-            db.update_user(&user)
-                .map_err(|_| "Error updating user".to_string())?;
+            db.record_burn(slashed)
+                .map_err(|_| "Error recording the burn".to_string())?;
+            drop(db);
+            self.events.publish(NodeEvent::ValidatorSlashed {
+                address: validator_address,
+                penalty: slashed,
+                stake,
+            });
             Ok(())
         } else {
             Err("Validator not found".to_string())
         }
     }
 
-    pub async fn apply_block(&self, block: &Block) -> Result<(), String> {
+    /// Runs the block's transfers, the proposer reward, the treasury's
+    /// share of fees, the treasury grants due and the missed slots it
+    /// reveals against a working copy of the accounts they touch and
+    /// returns the updated accounts with the amount burned: fees credited
+    /// to no one and stake slashed from jailed validators. Nothing is
+    /// written, so a failing transaction leaves state untouched. Each
+    /// transaction must carry its sender's next nonce and not be confirmed
+    /// already, so none can be replayed.
+    pub async fn execute_block(&self, block: &Block) -> Result<(Vec<User>, Amount), String> {
         let db = self.db.lock().await;
+        let mut accounts: HashMap<Address, User> = HashMap::new();
+        let mut nonces: HashMap<Address, u64> = HashMap::new();
+        let mut seen = HashSet::new();
+        let height = block.header.height;
+        let mut rules = BlockRules::new(&db, &self.params, height)?;
 
         for tx in &block.transactions {
-            let Transfer {
-                receiver, amount, ..
-            } = tx.payload;
-            let sender_pub_key = tx.sender_public_key;
-            let sender_address: Address = Sha256::digest(sender_pub_key).into();
-
-            let mut sender = db
-                .get_user(&sender_address)
-                .map_err(|_| "Sender not found".to_string())?
-                .ok_or("Sender not found".to_string())?;
-
-            let mut receiver = db
-                .get_user(&receiver)
-                .map_err(|_| "Receiver not found".to_string())?
-                .ok_or("Receiver not found".to_string())?;
-
-            if sender.balance < amount {
+            let tx_hash = tx.hash();
+            if !seen.insert(tx_hash) {
+                return Err("Block includes a transaction twice".to_string());
+            }
+            let confirmed = db
+                .get_transaction_with_block(&tx_hash)
+                .map_err(|_| "Error fetching transaction".to_string())?
+                .is_some();
+            if confirmed {
                 return Err(format!(
-                    "Sender {} has insufficient balance",
-                    hex::encode(sender.address)
+                    "Transaction {} is already confirmed",
+                    hex::encode(tx_hash)
                 ));
             }
+            stage_transaction(&db, &mut accounts, &mut nonces, &mut rules, tx)?;
+        }
 
-            sender.balance -= amount;
-            receiver.balance += amount;
+        let fees = self
+            .params
+            .fees
+            .governed(rules.parameters())
+            .split(block.total_fees()?);
+        let proposer = load_account(&db, &accounts, block.header.proposer, "Proposer")?;
+        let reward = block.header.coinbase.checked_add(fees.proposer)?;
+        let delegators = load_delegators(&db, &accounts, proposer.address)?;
+        for (address, share) in staking::share_reward(&proposer, &delegators, reward) {
+            let mut account = load_account(&db, &accounts, address, "Delegator")?;
+            account.balance = account.balance.checked_add(share)?;
+            accounts.insert(address, account);
+        }
 
-            db.update_user(&sender).map_err(|e| e.to_string())?;
-            db.update_user(&receiver).map_err(|e| e.to_string())?;
+        if fees.treasury > Amount::ZERO {
+            let mut treasury = load_treasury(&db, &accounts)?;
+            treasury.balance = treasury.balance.checked_add(fees.treasury)?;
+            accounts.insert(treasury.address, treasury);
+        }
+        let grants = db
+            .get_grants(height)
+            .map_err(|_| "Error fetching treasury grants".to_string())?;
+        for (recipient, amount) in grants {
+            let mut treasury = load_treasury(&db, &accounts)?;
+            let Ok(balance) = treasury.balance.checked_sub(amount) else {
+                continue;
+            };
+            treasury.balance = balance;
+            accounts.insert(treasury.address, treasury);
+            let mut recipient = load_account(&db, &accounts, recipient, "Grant recipient")?;
+            recipient.balance = recipient.balance.checked_add(amount)?;
+            accounts.insert(recipient.address, recipient);
         }
 
-        Ok(())
+        let mut burned = fees.burned;
+        if self.params.forks.is_active(Feature::Liveness, height) {
+            let block_time = rules
+                .parameters()
+                .block_time
+                .unwrap_or(self.params.block_time);
+            let seed = slot_seed(&db, &self.params, &block.header.previous_hash, height)?;
+            let slashed = track_liveness(
+                &db,
+                &mut accounts,
+                block,
+                block_time,
+                &seed,
+                &self.params.liveness,
+            )?;
+            burned = burned.checked_add(slashed)?;
+        }
+        if self.params.forks.is_active(Feature::Beacon, height) {
+            let epoch_length = self.params.liveness.epoch_length;
+            let slashed =
+                track_reveals(&db, &mut accounts, block, epoch_length, &self.params.beacon)?;
+            burned = burned.checked_add(slashed)?;
+        }
+
+        Ok((accounts.into_values().collect(), burned))
+    }
+
+    /// The transactions from `candidates`, in order, that still apply to
+    /// the current state one after another and fit in
+    /// [`MAX_BLOCK_BYTES`]. Those from or to unknown accounts, out of nonce
+    /// order or that the sender can no longer pay for are left out, so one
+    /// stale transaction cannot spoil a block.
+    pub async fn select_transactions(&self, candidates: Vec<Transaction>) -> Vec<Transaction> {
+        let db = self.db.lock().await;
+        let mut accounts: HashMap<Address, User> = HashMap::new();
+        let mut nonces: HashMap<Address, u64> = HashMap::new();
+        let mut selected = Vec::new();
+        let height = self.head.get().map_or(0, |(_, height)| height + 1);
+        let mut rules = match BlockRules::new(&db, &self.params, height) {
+            Ok(rules) => rules,
+            Err(e) => {
+                debug!(error = %e, "left every transaction out of block");
+                return selected;
+            }
+        };
+
+        let mut size = 0;
+        for tx in candidates {
+            // Smaller ones further down may still fit.
+            if size + tx.size() > MAX_BLOCK_BYTES {
+                continue;
+            }
+            match stage_transaction(&db, &mut accounts, &mut nonces, &mut rules, &tx) {
+                Ok(()) => {
+                    size += tx.size();
+                    selected.push(tx);
+                }
+                Err(e) => debug!(error = %e, "left transaction out of block"),
+            }
+        }
+        selected
+    }
+
+    /// State root the chain would have after `block` is applied.
+    pub async fn state_root_after(&self, block: &Block) -> Result<Hash, String> {
+        let (updated, _) = self.execute_block(block).await?;
+        self.state_root_with(&updated).await
+    }
+
+    /// State root the chain would have with the `updated` accounts, as
+    /// [`Blockchain::execute_block`] returns them, written.
+    async fn state_root_with(&self, updated: &[User]) -> Result<Hash, String> {
+        let db = self.db.lock().await;
+        let mut users = db
+            .get_users()
+            .map_err(|_| "Error fetching users".to_string())?;
+
+        for user in &mut users {
+            if let Some(new) = updated.iter().find(|u| u.address == user.address) {
+                *user = new.clone();
+            }
+        }
+        // Such as the treasury, the first time it is paid.
+        for new in updated {
+            if !users.iter().any(|user| user.address == new.address) {
+                users.push(new.clone());
+            }
+        }
+        Ok(compute_state_root(&users))
     }
 }
 
-fn compute_merkle_root(transactions: &[Transaction]) -> Hash {
-    use sha2::Digest;
-    use sha2::Sha256;
+pub(crate) fn block_signing_bytes(block_hash: &Hash) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(BLOCK_DOMAIN.len() + 32);
+    bytes.extend_from_slice(BLOCK_DOMAIN);
+    bytes.extend_from_slice(block_hash);
+    bytes
+}
 
-    if transactions.is_empty() {
-        return Sha256::digest(b"").into();
+/// Applies one transfer to the working copy if it can follow the ones
+/// already staged, checking its nonce as well.
+fn stage_transaction(
+    db: &Database,
+    accounts: &mut HashMap<Address, User>,
+    nonces: &mut HashMap<Address, u64>,
+    rules: &mut BlockRules,
+    tx: &Transaction,
+) -> Result<(), String> {
+    let sender_address = tx.sender_address();
+    let expected = match nonces.get(&sender_address) {
+        Some(nonce) => *nonce,
+        None => db
+            .get_next_nonce(&tx.sender_public_key)
+            .map_err(|_| "Error fetching nonce".to_string())?,
+    };
+    if tx.payload.nonce != expected {
+        return Err(format!(
+            "Invalid nonce: expected {}, got {}",
+            expected, tx.payload.nonce
+        ));
     }
 
-    let mut hashes: Vec<Hash> = transactions
+    if tx.payload.kind == TxKind::Transfer {
+        load_account(db, accounts, tx.payload.receiver, "Receiver")?;
+    }
+    apply_transaction(db, accounts, rules, tx)?;
+    nonces.insert(sender_address, expected + 1);
+    Ok(())
+}
+
+/// Applies `tx` to the working copy of the accounts it touches, failing if
+/// the sender cannot pay for it or it breaks the block's `rules`.
+fn apply_transaction(
+    db: &Database,
+    accounts: &mut HashMap<Address, User>,
+    rules: &mut BlockRules,
+    tx: &Transaction,
+) -> Result<(), String> {
+    let Transfer {
+        receiver,
+        amount,
+        kind,
+        ..
+    } = tx.payload;
+    let mut sender = load_account(db, accounts, tx.sender_address(), "Sender")?;
+    if kind.is_to_self() && receiver != sender.address {
+        return Err(format!(
+            "A {} transaction must name its sender as the receiver",
+            kind.as_str()
+        ));
+    }
+    tx.payload.check_data()?;
+    sender.balance = sender
+        .balance
+        .checked_sub(tx.payload.balance_cost()?)
+        .map_err(|_| {
+            format!(
+                "Sender {} has insufficient balance",
+                hex::encode(sender.address)
+            )
+        })?;
+    sender.stake = sender
+        .stake
+        .checked_sub(tx.payload.stake_cost())
+        .map_err(|_| {
+            format!(
+                "Sender {} has insufficient stake",
+                hex::encode(sender.address)
+            )
+        })?;
+    let validator = match kind {
+        TxKind::Delegate => match accounts.get(&receiver) {
+            Some(validator) => Some(validator.clone()),
+            None => db
+                .get_user(&receiver)
+                .map_err(|_| "Error fetching validator".to_string())?,
+        },
+        _ => None,
+    };
+    staking::check(&sender, validator.as_ref(), tx)?;
+    // Checked last, once nothing else can fail but a transfer's receiver,
+    // so a transaction left out of a block leaves no proposal behind.
+    rules.check(db, &sender, tx)?;
+    rules.check_key(db, &sender, tx)?;
+    rules.check_beacon(db, &sender, tx)?;
+    match kind {
+        TxKind::Transfer | TxKind::Propose | TxKind::Vote => {}
+        TxKind::Stake => sender.stake = sender.stake.checked_add(amount)?,
+        TxKind::Unstake => {
+            sender.balance = sender.balance.checked_add(amount)?;
+            if sender.stake.is_zero() {
+                sender.delegate = None;
+            }
+        }
+        TxKind::Delegate => {
+            sender.stake = sender.stake.checked_add(amount)?;
+            sender.delegate = Some(receiver);
+        }
+        TxKind::Unjail => sender.jailed = false,
+        TxKind::SetCommission | TxKind::RegisterValidator => {
+            if let TxData::Commission(commission)
+            | TxData::Registration(Registration { commission, .. }) = tx.payload.data
+            {
+                sender.commission_bps = commission.bps;
+            }
+        }
+        // The key, commitment or secret is recorded with the block; see
+        // `Database::commit_block`.
+        TxKind::RotateKey | TxKind::Commit | TxKind::Reveal => {}
+    }
+    accounts.insert(sender.address, sender);
+
+    if kind == TxKind::Transfer {
+        // Loaded after the sender is written back so a self-transfer sees
+        // the debited balance.
+        let mut receiver = load_account(db, accounts, receiver, "Receiver")?;
+        receiver.balance = receiver.balance.checked_add(amount)?;
+        accounts.insert(receiver.address, receiver);
+    }
+    Ok(())
+}
+
+/// Applies the block's transfers, reward, fees routed under `fees` and
+/// treasury grants to the balances its undo records hold, which must cover
+/// every account it touched. Blocks without undo records, such as genesis,
+/// have nothing to check against.
+fn replay_block(db: &Database, block: &Block, fees: FeeRouting) -> Result<(), String> {
+    let undo = db
+        .get_block_undo(&block.hash())
+        .map_err(|e| format!("error reading its undo records: {}", e))?;
+    if undo.is_empty() {
+        return Ok(());
+    }
+    // Balance and stake of every account the block touches.
+    let mut accounts: HashMap<Address, (Amount, Amount)> = undo
+        .into_iter()
+        .map(|(address, balance, stake)| (address, (balance, stake)))
+        .collect();
+    fn account(
+        accounts: &mut HashMap<Address, (Amount, Amount)>,
+        address: Address,
+    ) -> Result<&mut (Amount, Amount), String> {
+        accounts.get_mut(&address).ok_or(format!(
+            "touches {} but has no undo record for it",
+            hex::encode(address)
+        ))
+    }
+
+    for tx in &block.transactions {
+        let sender_address = tx.sender_address();
+        let (balance, stake) = account(&mut accounts, sender_address)?;
+        *balance = balance
+            .checked_sub(tx.payload.balance_cost()?)
+            .map_err(|_| format!("spends more than {} had", hex::encode(sender_address)))?;
+        *stake = stake.checked_sub(tx.payload.stake_cost()).map_err(|_| {
+            format!(
+                "unstakes more than {} had staked",
+                hex::encode(sender_address)
+            )
+        })?;
+        match tx.payload.kind {
+            TxKind::Stake | TxKind::Delegate => *stake = stake.checked_add(tx.payload.amount)?,
+            TxKind::Unstake => *balance = balance.checked_add(tx.payload.amount)?,
+            TxKind::Transfer => {
+                let (balance, _) = account(&mut accounts, tx.payload.receiver)?;
+                *balance = balance.checked_add(tx.payload.amount)?;
+            }
+            TxKind::Propose
+            | TxKind::Vote
+            | TxKind::SetCommission
+            | TxKind::Unjail
+            | TxKind::RegisterValidator
+            | TxKind::RotateKey
+            | TxKind::Commit
+            | TxKind::Reveal => {}
+        }
+    }
+    let height = block.header.height;
+    let parameters = db
+        .get_parameters(height)
+        .map_err(|e| format!("error reading its parameters: {}", e))?;
+    let fees = fees.governed(&parameters).split(block.total_fees()?);
+    // Shares of the reward paid to delegators are credited to the proposer
+    // here, and stake slashed from jailed validators is left alone. Both
+    // come after every transaction, so that hides no overspend.
+    let reward = block.header.coinbase.checked_add(fees.proposer)?;
+    let (balance, _) = account(&mut accounts, block.header.proposer)?;
+    *balance = balance.checked_add(reward)?;
+    if fees.treasury > Amount::ZERO {
+        let (balance, _) = account(&mut accounts, params::TREASURY)?;
+        *balance = balance.checked_add(fees.treasury)?;
+    }
+    let grants = db
+        .get_grants(height)
+        .map_err(|e| format!("error reading its treasury grants: {}", e))?;
+    for (recipient, amount) in grants {
+        let Some((treasury, _)) = accounts.get_mut(&params::TREASURY) else {
+            continue;
+        };
+        let Ok(left) = treasury.checked_sub(amount) else {
+            continue;
+        };
+        *treasury = left;
+        let (balance, _) = account(&mut accounts, recipient)?;
+        *balance = balance.checked_add(amount)?;
+    }
+    Ok(())
+}
+
+/// Charges the slots that passed between `block` and its parent to the
+/// validators picked for them by `seed` and, at the end of an epoch, jails
+/// those that missed too many and slashes them and their delegators.
+/// Returns the stake slashed.
+fn track_liveness(
+    db: &Database,
+    accounts: &mut HashMap<Address, User>,
+    block: &Block,
+    block_time: Duration,
+    seed: &Hash,
+    rules: &Liveness,
+) -> Result<Amount, String> {
+    let Some(parent) = db
+        .get_block(&block.header.previous_hash)
+        .map_err(|_| "Error fetching the parent block".to_string())?
+    else {
+        return Ok(Amount::ZERO);
+    };
+    let missed = liveness::missed_slots(
+        parent.header.timestamp,
+        block.header.timestamp,
+        block_time,
+        rules.epoch_length,
+    );
+    let ends_epoch = liveness::ends_epoch(block.header.height, rules.epoch_length);
+    if missed.is_empty() && !ends_epoch {
+        return Ok(Amount::ZERO);
+    }
+    // The state the missed slots' proposers were picked from.
+    let users = db
+        .get_users()
+        .map_err(|_| "Error fetching users".to_string())?;
+    let validators = staking::validators(&users);
+    for slot in missed {
+        let Some(address) = liveness::slot_proposer(&validators, seed, slot) else {
+            break;
+        };
+        let mut validator = load_account(db, accounts, address, "Validator")?;
+        validator.missed_slots = validator.missed_slots.saturating_add(1);
+        accounts.insert(address, validator);
+    }
+    if !ends_epoch {
+        return Ok(Amount::ZERO);
+    }
+
+    let mut absent: Vec<User> = users
+        .into_iter()
+        .filter(|user| !accounts.contains_key(&user.address))
+        .chain(accounts.values().cloned())
+        .filter(|user| user.missed_slots > 0)
+        .collect();
+    absent.sort_by_key(|user| user.address);
+    let jailed = liveness::to_jail(&absent, &validators, rules.max_missed);
+    // Every count starts over with the next epoch.
+    for mut user in absent {
+        user.missed_slots = 0;
+        accounts.insert(user.address, user);
+    }
+    let mut slashed = Amount::ZERO;
+    for address in jailed {
+        let mut validator = load_account(db, accounts, address, "Validator")?;
+        validator.jailed = true;
+        accounts.insert(address, validator.clone());
+        let penalty = slash(db, accounts, &validator, |weight| rules.penalty(weight))?;
+        slashed = slashed.checked_add(penalty)?;
+    }
+    Ok(slashed)
+}
+
+/// At the end of an epoch, slashes the validators that committed to a
+/// beacon secret in the epoch before and have not revealed it by the end
+/// of `block`, and their delegators. Returns the stake slashed.
+fn track_reveals(
+    db: &Database,
+    accounts: &mut HashMap<Address, User>,
+    block: &Block,
+    epoch_length: u64,
+    rules: &Beacon,
+) -> Result<Amount, String> {
+    let height = block.header.height;
+    let current = beacon::epoch(height, epoch_length);
+    let previous = match current.checked_sub(1) {
+        Some(previous) if liveness::ends_epoch(height, epoch_length) => previous,
+        _ => return Ok(Amount::ZERO),
+    };
+    let fetching = |_| "Error fetching beacon commitments".to_string();
+    let commitments = db
+        .get_commitments(beacon::epoch_heights(previous, epoch_length))
+        .map_err(fetching)?;
+    let mut revealed: HashSet<Address> = db
+        .get_reveals(beacon::epoch_heights(current, epoch_length))
+        .map_err(fetching)?
+        .into_iter()
+        .map(|(address, _)| address)
+        .collect();
+    revealed.extend(
+        block
+            .transactions
+            .iter()
+            .filter(|tx| tx.payload.kind == TxKind::Reveal)
+            .map(Transaction::sender_address),
+    );
+
+    let mut slashed = Amount::ZERO;
+    for (address, _) in commitments {
+        if revealed.contains(&address) {
+            continue;
+        }
+        let validator = load_account(db, accounts, address, "Validator")?;
+        let penalty = slash(db, accounts, &validator, |weight| rules.penalty(weight))?;
+        slashed = slashed.checked_add(penalty)?;
+    }
+    Ok(slashed)
+}
+
+/// Takes from `validator` and its delegators, by stake, the penalty for
+/// their weight in all. Returns the stake slashed.
+fn slash(
+    db: &Database,
+    accounts: &mut HashMap<Address, User>,
+    validator: &User,
+    penalty: impl FnOnce(Amount) -> Amount,
+) -> Result<Amount, String> {
+    let delegators = load_delegators(db, accounts, validator.address)?;
+    let weight = delegators
         .iter()
-        .map(|tx| {
-            let encoded = bincode::encode_to_vec(tx, bincode::config::standard())
-                .expect("failed to encode transaction");
-            Sha256::digest(&encoded).into()
-        })
+        .fold(validator.stake, |weight, delegator| {
+            weight.saturating_add(delegator.stake)
+        });
+    let mut slashed = Amount::ZERO;
+    for (address, share) in staking::share_penalty(validator, &delegators, penalty(weight)) {
+        let mut account = load_account(db, accounts, address, "Delegator")?;
+        account.stake = account.stake.saturating_sub(share);
+        accounts.insert(address, account);
+        slashed = slashed.checked_add(share)?;
+    }
+    Ok(slashed)
+}
+
+/// The seed slot proposers for the block at `height` on top of
+/// `previous_hash` are picked by: the beacon of its epoch once that is
+/// active, the parent's hash before.
+fn slot_seed(
+    db: &Database,
+    params: &ChainParams,
+    previous_hash: &Hash,
+    height: u64,
+) -> Result<Hash, String> {
+    if !params.forks.is_active(Feature::Beacon, height) {
+        return Ok(*previous_hash);
+    }
+    let epoch_length = params.liveness.epoch_length;
+    beacon_seed(db, beacon::epoch(height, epoch_length), epoch_length)
+}
+
+/// The beacon of `epoch`, from the secrets revealed in the epoch before.
+fn beacon_seed(db: &Database, epoch: u64, epoch_length: u64) -> Result<Hash, String> {
+    let reveals = match epoch.checked_sub(1) {
+        Some(previous) => db
+            .get_reveals(beacon::epoch_heights(previous, epoch_length))
+            .map_err(|_| "Error fetching beacon reveals".to_string())?,
+        None => Vec::new(),
+    };
+    let secrets: Vec<Hash> = reveals.into_iter().map(|(_, secret)| secret).collect();
+    Ok(beacon::seed(epoch, &secrets))
+}
+
+/// The accounts delegating to `validator`, as the working copy has them,
+/// ordered by address.
+fn load_delegators(
+    db: &Database,
+    accounts: &HashMap<Address, User>,
+    validator: Address,
+) -> Result<Vec<User>, String> {
+    let mut delegators: Vec<User> = db
+        .get_delegators(&validator)
+        .map_err(|_| "Error fetching delegators".to_string())?
+        .into_iter()
+        .filter(|user| !accounts.contains_key(&user.address))
         .collect();
+    delegators.extend(
+        accounts
+            .values()
+            .filter(|user| user.delegate == Some(validator))
+            .cloned(),
+    );
+    delegators.sort_by_key(|user| user.address);
+    Ok(delegators)
+}
 
-    while hashes.len() > 1 {
-        if hashes.len() % 2 != 0 {
-            hashes.push(hashes.last().unwrap().clone()); // make even
+/// The treasury from the working copy or the database, or an empty one
+/// if it has never been paid.
+fn load_treasury(db: &Database, accounts: &HashMap<Address, User>) -> Result<User, String> {
+    if let Some(user) = accounts.get(&params::TREASURY) {
+        return Ok(user.clone());
+    }
+    Ok(db
+        .get_user(&params::TREASURY)
+        .map_err(|_| "Error fetching treasury".to_string())?
+        .unwrap_or_else(params::empty_treasury))
+}
+
+/// The key `proposer` signs its block at `height` with: its consensus key
+/// then, or its account key if it had none.
+fn signing_key(db: &Database, proposer: &User, height: u64) -> Result<[u8; 32], String> {
+    let key = db
+        .get_validator_key(&proposer.address, height)
+        .map_err(|_| "Error fetching validator keys".to_string())?;
+    Ok(key.unwrap_or(proposer.public_key))
+}
+
+/// Fetches an account from the working copy, falling back to the database.
+fn load_account(
+    db: &Database,
+    accounts: &HashMap<Address, User>,
+    address: Address,
+    role: &str,
+) -> Result<User, String> {
+    if let Some(user) = accounts.get(&address) {
+        return Ok(user.clone());
+    }
+
+    db.get_user(&address)
+        .map_err(|_| format!("Error fetching {}", role.to_lowercase()))?
+        .ok_or(format!("{} not found", role))
+}
+
+/// Hashes every account, ordered by address, as
+/// `address || public_key || balance || stake`, with amounts as big-endian
+/// u128 base units. Accounts with any staking state add
+/// `delegate || commission || missed_slots || jailed`, with no delegate as
+/// zeros, the commission as a big-endian u16, missed slots as a big-endian
+/// u32 and jailed as one byte, so roots of chains without any are
+/// unchanged.
+pub fn compute_state_root(users: &[User]) -> Hash {
+    let mut users: Vec<&User> = users.iter().collect();
+    users.sort_by_key(|user| user.address);
+
+    let mut hasher = Sha256::new();
+    for user in users {
+        hasher.update(user.address);
+        hasher.update(user.public_key);
+        hasher.update(user.balance.to_be_bytes());
+        hasher.update(user.stake.to_be_bytes());
+        if user.delegate.is_some()
+            || user.commission_bps != 0
+            || user.missed_slots != 0
+            || user.jailed
+        {
+            hasher.update(user.delegate.unwrap_or([0u8; 32]));
+            hasher.update(user.commission_bps.to_be_bytes());
+            hasher.update(user.missed_slots.to_be_bytes());
+            hasher.update([user.jailed as u8]);
         }
+    }
+    hasher.finalize().into()
+}
 
-        hashes = hashes
-            .chunks(2)
-            .map(|pair| {
-                let mut hasher = Sha256::new();
-                hasher.update(&pair[0]);
-                hasher.update(&pair[1]);
-                hasher.finalize().into()
-            })
-            .collect();
+/// The merkle root of `transactions`: the hash of their count, as a
+/// big-endian u64, and the root of a binary tree over their leaf hashes in
+/// which the last node of an odd level is paired with itself. Committing to
+/// the count keeps a list ending in a repeated pair from sharing a root
+/// with the list without the repeat.
+pub fn compute_merkle_root(transactions: &[Transaction]) -> Hash {
+    let mut hashes: Vec<Hash> = transactions.iter().map(merkle_leaf).collect();
+    if hashes.is_empty() {
+        hashes.push(Sha256::digest(b"").into());
+    }
+    while hashes.len() > 1 {
+        hashes = merkle_level(hashes);
+    }
+
+    merkle_commit(transactions.len() as u64, &hashes[0])
+}
+
+/// The hashes that, together with the transaction at `index`, lead up to
+/// the merkle root of `transactions`, from the bottom of the tree.
+pub fn merkle_proof(transactions: &[Transaction], index: usize) -> Vec<Hash> {
+    let mut hashes: Vec<Hash> = transactions.iter().map(merkle_leaf).collect();
+    let mut index = index;
+    let mut siblings = Vec::new();
+    while hashes.len() > 1 && index < hashes.len() {
+        // The last node of an odd level is paired with itself.
+        siblings.push(*hashes.get(index ^ 1).unwrap_or(&hashes[index]));
+        hashes = merkle_level(hashes);
+        index /= 2;
     }
+    siblings
+}
 
-    hashes[0]
+/// Whether `proof`, from [`merkle_proof`], shows that `transaction` is at
+/// `index` of the `count` transactions of a block with merkle root `root`.
+pub fn verify_merkle_proof(
+    transaction: &Transaction,
+    index: u32,
+    count: u32,
+    proof: &[Hash],
+    root: &Hash,
+) -> bool {
+    if index >= count {
+        return false;
+    }
+    let mut hash = merkle_leaf(transaction);
+    let mut index = index;
+    let mut width = count;
+    for sibling in proof {
+        if width <= 1 {
+            return false;
+        }
+        hash = if !index.is_multiple_of(2) {
+            merkle_parent(sibling, &hash)
+        } else if index == width - 1 {
+            // The last node of an odd level is paired with itself.
+            if *sibling != hash {
+                return false;
+            }
+            merkle_parent(&hash, &hash)
+        } else {
+            merkle_parent(&hash, sibling)
+        };
+        index /= 2;
+        width = width.div_ceil(2);
+    }
+    width == 1 && merkle_commit(count.into(), &hash) == *root
+}
+
+fn merkle_leaf(transaction: &Transaction) -> Hash {
+    Sha256::digest(transaction.to_bytes()).into()
+}
+
+fn merkle_commit(count: u64, tree_root: &Hash) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update(count.to_be_bytes());
+    hasher.update(tree_root);
+    hasher.finalize().into()
+}
+
+fn merkle_parent(left: &Hash, right: &Hash) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
+
+/// The level of the tree above `hashes`.
+fn merkle_level(mut hashes: Vec<Hash>) -> Vec<Hash> {
+    if !hashes.len().is_multiple_of(2) {
+        hashes.push(*hashes.last().unwrap()); // make even
+    }
+    hashes
+        .chunks(2)
+        .map(|pair| merkle_parent(&pair[0], &pair[1]))
+        .collect()
 }
@@ -0,0 +1,804 @@
+//! A client for a node's JSON-RPC API over HTTP, behind the `client`
+//! binary, so accounts and the chain can be looked at without a database
+//! of one's own.
+
+use crate::amount::Amount;
+use crate::beacon::{self, Commit};
+use crate::blockchain::{Address, Hash, Transaction, Transfer, TxData, TxKind};
+use crate::error::RpcError;
+use crate::governance::{Ballot, ParameterChange};
+use crate::staking::{Commission, Registration};
+use crate::wallet::key_address;
+use ed25519_dalek::SigningKey;
+use serde_json::{Value, json};
+use std::fmt;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+/// A node that has not answered by now is treated as down.
+const RPC_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How often a transaction being waited on is looked up again.
+const CONFIRMATION_POLL: Duration = Duration::from_secs(1);
+
+/// An account as the node reports it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Account {
+    pub address: Address,
+    pub balance: Amount,
+    pub stake: Amount,
+}
+
+/// How a block is looked up.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BlockId {
+    Hash(Hash),
+    Height(u64),
+}
+
+/// The RPC servers of one or more nodes, given as `http://HOST:PORT`
+/// separated by commas. Calls go to the first that answers.
+#[derive(Clone, PartialEq)]
+pub struct RpcClient {
+    endpoints: Vec<SocketAddr>,
+    timeout: Duration,
+    token: Option<String>,
+}
+
+impl FromStr for RpcClient {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let endpoints = s
+            .split(',')
+            .map(|endpoint| {
+                let endpoint = endpoint.trim();
+                endpoint
+                    .strip_prefix("http://")
+                    .unwrap_or(endpoint)
+                    .trim_end_matches('/')
+                    .parse()
+                    .map_err(|e| format!("Invalid node address {:?}: {}", endpoint, e))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(RpcClient::with_endpoints(endpoints))
+    }
+}
+
+/// Keeps the token out of logs and panics.
+impl fmt::Debug for RpcClient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RpcClient")
+            .field("endpoints", &self.endpoints)
+            .field("timeout", &self.timeout)
+            .field("token", &self.token.as_ref().map(|_| "<redacted>"))
+            .finish()
+    }
+}
+
+impl fmt::Display for RpcClient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, addr) in self.endpoints.iter().enumerate() {
+            if i > 0 {
+                write!(f, ",")?;
+            }
+            write!(f, "http://{}", addr)?;
+        }
+        Ok(())
+    }
+}
+
+impl RpcClient {
+    pub fn new(addr: SocketAddr) -> Self {
+        RpcClient::with_endpoints(vec![addr])
+    }
+
+    /// A client for several nodes, tried in the order given.
+    pub fn with_endpoints(endpoints: Vec<SocketAddr>) -> Self {
+        RpcClient {
+            endpoints,
+            timeout: RPC_TIMEOUT,
+            token: None,
+        }
+    }
+
+    /// The nodes' RPC servers, in the order they are tried.
+    pub fn endpoints(&self) -> &[SocketAddr] {
+        &self.endpoints
+    }
+
+    /// How long each attempt to reach a node and hear back may take.
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Sets how long each attempt to reach a node and hear back may take.
+    pub fn with_timeout(self, timeout: Duration) -> Self {
+        RpcClient { timeout, ..self }
+    }
+
+    /// Sends `token`, a bearer token or JWT, with every call, for nodes
+    /// that require one for wallet or admin methods.
+    pub fn with_token(self, token: String) -> Self {
+        RpcClient {
+            token: Some(token),
+            ..self
+        }
+    }
+
+    pub fn token(&self) -> Option<&str> {
+        self.token.as_deref()
+    }
+
+    /// Calls `method` and returns its result, or the error the node
+    /// answered with.
+    ///
+    /// A node that cannot be connected to never saw the request, so the
+    /// next endpoint is tried. One that goes quiet after the request was
+    /// sent may have acted on it, so only calls that change nothing are
+    /// retried elsewhere; a submission is reported as unreachable instead.
+    pub async fn call(&self, method: &str, params: Value) -> Result<Value, RpcError> {
+        let body =
+            json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params }).to_string();
+        let idempotent = !matches!(method, "tx_submitRaw" | "tx_submit");
+        let mut failures = Vec::new();
+        for addr in &self.endpoints {
+            let stream = match tokio::time::timeout(self.timeout, TcpStream::connect(addr)).await {
+                Ok(Ok(stream)) => stream,
+                Ok(Err(e)) => {
+                    failures.push(format!("http://{}: {}", addr, e));
+                    continue;
+                }
+                Err(_) => {
+                    failures.push(format!("http://{}: timed out connecting", addr));
+                    continue;
+                }
+            };
+            match self.exchange(stream, addr, &body).await {
+                Err(e) if idempotent => failures.push(format!("http://{}: {}", addr, e)),
+                Err(e) => {
+                    return Err(RpcError::Unreachable(format!(
+                        "http://{}: {}; not retried elsewhere as {} may have been applied",
+                        addr, e, method
+                    )));
+                }
+                Ok(response) => return parse_response(method, &response),
+            }
+        }
+        if failures.is_empty() {
+            return Err(RpcError::Unreachable("No node endpoints given".to_string()));
+        }
+        Err(RpcError::Unreachable(format!(
+            "Failed to reach a node: {}",
+            failures.join("; ")
+        )))
+    }
+
+    /// Sends `body` over `stream` and reads the whole response.
+    async fn exchange(
+        &self,
+        mut stream: TcpStream,
+        addr: &SocketAddr,
+        body: &str,
+    ) -> Result<String, String> {
+        let authorization = self
+            .token
+            .as_ref()
+            .map(|token| format!("Authorization: Bearer {}\r\n", token))
+            .unwrap_or_default();
+        let request = format!(
+            "POST / HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\n{}Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+            addr,
+            authorization,
+            body.len(),
+            body
+        );
+        let round_trip = async {
+            stream
+                .write_all(request.as_bytes())
+                .await
+                .map_err(|e| e.to_string())?;
+            let mut response = Vec::new();
+            stream
+                .read_to_end(&mut response)
+                .await
+                .map_err(|e| e.to_string())?;
+            Ok::<_, String>(response)
+        };
+        let response = tokio::time::timeout(self.timeout, round_trip)
+            .await
+            .map_err(|_| "timed out waiting for a response".to_string())??;
+        if response.is_empty() {
+            return Err("connection closed without a response".to_string());
+        }
+        Ok(String::from_utf8_lossy(&response).into_owned())
+    }
+
+    /// The account at `address`, which the node must know.
+    pub async fn balance(&self, address: &Address) -> Result<Account, RpcError> {
+        let account = self
+            .call("account_getBalance", json!([hex::encode(address)]))
+            .await?;
+        let field = |name| amount_field(&account, name).map_err(RpcError::InvalidResponse);
+        Ok(Account {
+            address: *address,
+            balance: field("balance")?,
+            stake: field("stake")?,
+        })
+    }
+
+    /// The nonce the next transaction from `address` must carry, counting
+    /// those waiting in the node's mempool.
+    pub async fn nonce(&self, address: &Address) -> Result<u64, RpcError> {
+        self.call("account_getNonce", json!([hex::encode(address)]))
+            .await?
+            .as_u64()
+            .ok_or(RpcError::InvalidResponse(
+                "Invalid nonce from node".to_string(),
+            ))
+    }
+
+    /// The block with its transactions, as `chain_getBlock` gives it, or
+    /// `None` if the node has no such block.
+    pub async fn block(&self, id: BlockId) -> Result<Option<Value>, RpcError> {
+        let block = match id {
+            BlockId::Hash(hash) => self.call("chain_getBlock", json!([hex::encode(hash)])),
+            BlockId::Height(height) => self.call("chain_getBlockByHeight", json!([height])),
+        }
+        .await?;
+        Ok((!block.is_null()).then_some(block))
+    }
+
+    /// The transaction with its status, as `tx_get` gives it, or `None` if
+    /// the node has not seen it.
+    pub async fn transaction(&self, hash: &Hash) -> Result<Option<Value>, RpcError> {
+        let tx = self.call("tx_get", json!([hex::encode(hash)])).await?;
+        Ok((!tx.is_null()).then_some(tx))
+    }
+
+    /// The node's view of the chain, sync and mempool, as `chain_getStatus`
+    /// gives it.
+    pub async fn status(&self) -> Result<Value, RpcError> {
+        self.call("chain_getStatus", json!([])).await
+    }
+
+    /// Addresses of the peers the node is connected to.
+    pub async fn peers(&self) -> Result<Vec<String>, RpcError> {
+        serde_json::from_value(self.call("peers_list", json!([])).await?)
+            .map_err(|e| RpcError::InvalidResponse(format!("Invalid peer list from node: {}", e)))
+    }
+
+    /// The newest confirmed transactions to or from `address`, up to
+    /// `limit`, each with the block it is in.
+    pub async fn history(&self, address: &Address, limit: u64) -> Result<Vec<Value>, RpcError> {
+        let mut page = self
+            .call(
+                "account_getTransactions",
+                json!([hex::encode(address), limit]),
+            )
+            .await?;
+        match page["transactions"].take() {
+            Value::Array(transactions) => Ok(transactions),
+            _ => Err(RpcError::InvalidResponse(
+                "Invalid transaction list from node".to_string(),
+            )),
+        }
+    }
+
+    /// Waits until the transaction is in a block with at least
+    /// `confirmations` confirmations, returning it as `tx_get` gives it.
+    /// Fails if the node forgets the transaction or `timeout` passes first.
+    pub async fn wait_for_confirmations(
+        &self,
+        hash: &Hash,
+        confirmations: u64,
+        timeout: Duration,
+    ) -> Result<Value, String> {
+        let deadline = tokio::time::Instant::now() + timeout;
+        loop {
+            let tx = self.transaction(hash).await?.ok_or(format!(
+                "Transaction {} was dropped by the node",
+                hex::encode(hash)
+            ))?;
+            if tx["confirmations"]
+                .as_u64()
+                .is_some_and(|confirmed| confirmed >= confirmations)
+            {
+                return Ok(tx);
+            }
+            if tokio::time::Instant::now() + CONFIRMATION_POLL > deadline {
+                return Err(format!(
+                    "Timed out waiting for {} to get {} confirmations",
+                    hex::encode(hash),
+                    confirmations
+                ));
+            }
+            tokio::time::sleep(CONFIRMATION_POLL).await;
+        }
+    }
+
+    /// Hands a signed transaction to the node's mempool, returning its hash.
+    pub async fn submit(&self, tx: &Transaction) -> Result<Hash, RpcError> {
+        self.submit_raw(&tx.to_bytes()).await
+    }
+
+    /// Submits a transaction already in its canonical encoding, as
+    /// `client tx sign` writes it.
+    pub async fn submit_raw(&self, bytes: &[u8]) -> Result<Hash, RpcError> {
+        let hash = self
+            .call("tx_submitRaw", json!([hex::encode(bytes)]))
+            .await?;
+        hash.as_str()
+            .and_then(|hash| parse_hash(hash).ok())
+            .ok_or(RpcError::InvalidResponse(
+                "Invalid transaction hash from node".to_string(),
+            ))
+    }
+
+    /// Sends `amount` from the account `key` signs for, taking the nonce
+    /// from the node and signing here.
+    pub async fn transfer(
+        &self,
+        key: &SigningKey,
+        receiver: Address,
+        amount: Amount,
+        fee: Amount,
+    ) -> Result<Hash, RpcError> {
+        self.send(key, TxKind::Transfer, receiver, amount, fee)
+            .await
+    }
+
+    /// Moves `amount` of the account's balance into its stake.
+    pub async fn stake(
+        &self,
+        key: &SigningKey,
+        amount: Amount,
+        fee: Amount,
+    ) -> Result<Hash, RpcError> {
+        self.send(key, TxKind::Stake, key_address(key), amount, fee)
+            .await
+    }
+
+    /// Moves `amount` of the account's stake back into its balance.
+    pub async fn unstake(
+        &self,
+        key: &SigningKey,
+        amount: Amount,
+        fee: Amount,
+    ) -> Result<Hash, RpcError> {
+        self.send(key, TxKind::Unstake, key_address(key), amount, fee)
+            .await
+    }
+
+    /// Moves `amount` of the account's balance into its stake, delegated
+    /// to `validator`.
+    pub async fn delegate(
+        &self,
+        key: &SigningKey,
+        validator: Address,
+        amount: Amount,
+        fee: Amount,
+    ) -> Result<Hash, RpcError> {
+        self.send(key, TxKind::Delegate, validator, amount, fee)
+            .await
+    }
+
+    /// Sets the commission the account charges its delegators as a
+    /// validator.
+    pub async fn set_commission(
+        &self,
+        key: &SigningKey,
+        commission: Commission,
+        fee: Amount,
+    ) -> Result<Hash, RpcError> {
+        self.send_data(
+            key,
+            TxKind::SetCommission,
+            TxData::Commission(commission),
+            fee,
+        )
+        .await
+    }
+
+    /// Returns the account `key` signs for, a validator jailed for missing
+    /// slots, to proposer selection.
+    pub async fn unjail(&self, key: &SigningKey, fee: Amount) -> Result<Hash, RpcError> {
+        self.send(key, TxKind::Unjail, key_address(key), Amount::ZERO, fee)
+            .await
+    }
+
+    /// Registers the account `key` signs for as a validator signing blocks
+    /// with `registration`'s consensus key.
+    pub async fn register_validator(
+        &self,
+        key: &SigningKey,
+        registration: &Registration,
+        fee: Amount,
+    ) -> Result<Hash, RpcError> {
+        let data = TxData::Registration(*registration);
+        self.send_data(key, TxKind::RegisterValidator, data, fee)
+            .await
+    }
+
+    /// Replaces the consensus key of the validator `key` signs for with
+    /// `consensus_key`.
+    pub async fn rotate_key(
+        &self,
+        key: &SigningKey,
+        consensus_key: [u8; 32],
+        fee: Amount,
+    ) -> Result<Hash, RpcError> {
+        let data = TxData::ConsensusKey(consensus_key);
+        self.send_data(key, TxKind::RotateKey, data, fee).await
+    }
+
+    /// Commits the validator `key` signs for to its beacon secret for
+    /// `epoch`, which must be that of the next block.
+    pub async fn commit(
+        &self,
+        key: &SigningKey,
+        epoch: u64,
+        fee: Amount,
+    ) -> Result<Hash, RpcError> {
+        let secret = beacon::secret(key, epoch);
+        let commit = Commit {
+            commitment: beacon::commitment(&key_address(key), &secret),
+            epoch,
+        };
+        self.send_data(key, TxKind::Commit, TxData::Commit(commit), fee)
+            .await
+    }
+
+    /// Reveals the beacon secret the validator `key` signs for committed to
+    /// in `epoch`, which must be the one before that of the next block.
+    pub async fn reveal(
+        &self,
+        key: &SigningKey,
+        epoch: u64,
+        fee: Amount,
+    ) -> Result<Hash, RpcError> {
+        let secret = beacon::secret(key, epoch);
+        self.send_data(key, TxKind::Reveal, TxData::Secret(secret), fee)
+            .await
+    }
+
+    /// Proposes `change` from the account `key` signs for.
+    pub async fn propose(
+        &self,
+        key: &SigningKey,
+        change: &ParameterChange,
+        fee: Amount,
+    ) -> Result<Hash, RpcError> {
+        self.send_data(key, TxKind::Propose, TxData::Proposal(*change), fee)
+            .await
+    }
+
+    /// Votes on a proposal with the stake of the account `key` signs for.
+    pub async fn vote(
+        &self,
+        key: &SigningKey,
+        ballot: &Ballot,
+        fee: Amount,
+    ) -> Result<Hash, RpcError> {
+        self.send_data(key, TxKind::Vote, TxData::Ballot(*ballot), fee)
+            .await
+    }
+
+    async fn send(
+        &self,
+        key: &SigningKey,
+        kind: TxKind,
+        receiver: Address,
+        amount: Amount,
+        fee: Amount,
+    ) -> Result<Hash, RpcError> {
+        let transfer = Transfer {
+            receiver,
+            amount,
+            fee,
+            nonce: 0,
+            kind,
+            data: TxData::None,
+        };
+        self.sign_and_submit(key, transfer).await
+    }
+
+    /// Sends a transaction that says what it does in `data`, from the
+    /// account `key` signs for to itself and moving no amount.
+    async fn send_data(
+        &self,
+        key: &SigningKey,
+        kind: TxKind,
+        data: TxData,
+        fee: Amount,
+    ) -> Result<Hash, RpcError> {
+        let transfer = Transfer {
+            receiver: key_address(key),
+            amount: Amount::ZERO,
+            fee,
+            nonce: 0,
+            kind,
+            data,
+        };
+        self.sign_and_submit(key, transfer).await
+    }
+
+    /// Signs `transfer` at the account's next nonce and submits it.
+    async fn sign_and_submit(
+        &self,
+        key: &SigningKey,
+        transfer: Transfer,
+    ) -> Result<Hash, RpcError> {
+        let nonce = self.nonce(&key_address(key)).await?;
+        let tx = Transfer { nonce, ..transfer }.into_transaction(key);
+        self.submit(&tx).await
+    }
+
+    /// Signs and submits one transfer per payment from the account `key`
+    /// signs for, numbering nonces on from the account's next one. Each
+    /// payment gets its own result; a rejected payment does not use up its
+    /// nonce, so the ones after it are not left waiting on a gap.
+    ///
+    /// A payment the node could not be reached for may or may not have
+    /// been taken, so nothing after it is sent and the results end there.
+    pub async fn send_batch(
+        &self,
+        key: &SigningKey,
+        payments: &[Payment],
+    ) -> Result<Vec<Result<Hash, RpcError>>, RpcError> {
+        let mut nonce = self.nonce(&key_address(key)).await?;
+        let mut results = Vec::with_capacity(payments.len());
+        for payment in payments {
+            let tx = Transfer {
+                receiver: payment.receiver,
+                amount: payment.amount,
+                fee: payment.fee,
+                nonce,
+                kind: TxKind::Transfer,
+                data: TxData::None,
+            }
+            .into_transaction(key);
+            let result = self.submit(&tx).await;
+            let stop = matches!(result, Err(RpcError::Unreachable(_)));
+            if result.is_ok() {
+                nonce += 1;
+            }
+            results.push(result);
+            if stop {
+                break;
+            }
+        }
+        Ok(results)
+    }
+
+    /// Every staked account with its stake and share of the total, largest
+    /// first, as `chain_getValidators` gives them.
+    pub async fn validators(&self) -> Result<Value, RpcError> {
+        self.call("chain_getValidators", json!([])).await
+    }
+
+    /// The epoch of the next block and the beacon seeding proposer
+    /// selection in it, as `chain_getBeacon` gives them.
+    pub async fn beacon(&self) -> Result<Value, RpcError> {
+        self.call("chain_getBeacon", json!([])).await
+    }
+
+    /// Metrics over the latest `window` blocks, or the node's default
+    /// window, as `stats_get` gives them; null before the first block.
+    pub async fn stats(&self, window: Option<u64>) -> Result<Value, RpcError> {
+        self.call("stats_get", json!([window])).await
+    }
+
+    /// Every parameter change proposed, newest first, with its status and
+    /// the stake behind each side, as `state_proposals` gives them.
+    pub async fn proposals(&self) -> Result<Value, RpcError> {
+        self.call("state_proposals", json!([])).await
+    }
+
+    /// The governed parameters the next block is held to, as
+    /// `state_parameters` gives them.
+    pub async fn parameters(&self) -> Result<Value, RpcError> {
+        self.call("state_parameters", json!([])).await
+    }
+}
+
+/// One transfer of a batch, as `client send-batch` reads them from a file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Payment {
+    pub receiver: Address,
+    pub amount: Amount,
+    pub fee: Amount,
+}
+
+impl Payment {
+    /// Reads a batch of payments, either a JSON array of objects with `to`,
+    /// `amount` and optionally `fee`, or CSV whose header row names those
+    /// columns. Payments without a fee of their own pay `default_fee`, and
+    /// `resolve` turns each `to` into an address.
+    pub fn parse_batch(
+        text: &str,
+        default_fee: Amount,
+        resolve: impl Fn(&str) -> Result<Address, String>,
+    ) -> Result<Vec<Payment>, String> {
+        if text.trim_start().starts_with('[') {
+            let rows: Vec<Value> =
+                serde_json::from_str(text).map_err(|e| format!("Invalid payments: {}", e))?;
+            return rows
+                .iter()
+                .enumerate()
+                .map(|(i, row)| {
+                    let field = |name| match row.get(name) {
+                        Some(Value::String(s)) => Some(s.clone()),
+                        Some(Value::Number(n)) => Some(n.to_string()),
+                        _ => None,
+                    };
+                    Payment::from_fields(
+                        field("to"),
+                        field("amount"),
+                        field("fee"),
+                        default_fee,
+                        &resolve,
+                    )
+                    .map_err(|e| format!("Payment {}: {}", i + 1, e))
+                })
+                .collect();
+        }
+
+        let mut lines = text
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty() && !line.starts_with('#'));
+        let (_, header) = lines.next().ok_or("No payments given")?;
+        let columns: Vec<String> = header
+            .split(',')
+            .map(|column| column.trim().to_lowercase())
+            .collect();
+        let column = |name: &str| columns.iter().position(|column| column == name);
+        let (to, amount, fee) = (column("to"), column("amount"), column("fee"));
+        if to.is_none() || amount.is_none() {
+            return Err("The header row must name the to and amount columns".to_string());
+        }
+        lines
+            .map(|(i, line)| {
+                let cells: Vec<&str> = line.split(',').map(str::trim).collect();
+                let cell = |index: Option<usize>| {
+                    index
+                        .and_then(|index| cells.get(index))
+                        .filter(|cell| !cell.is_empty())
+                        .map(|cell| cell.to_string())
+                };
+                Payment::from_fields(cell(to), cell(amount), cell(fee), default_fee, &resolve)
+                    .map_err(|e| format!("Line {}: {}", i + 1, e))
+            })
+            .collect()
+    }
+
+    fn from_fields(
+        to: Option<String>,
+        amount: Option<String>,
+        fee: Option<String>,
+        default_fee: Amount,
+        resolve: &impl Fn(&str) -> Result<Address, String>,
+    ) -> Result<Payment, String> {
+        Ok(Payment {
+            receiver: resolve(&to.ok_or("Missing to")?)?,
+            amount: amount.ok_or("Missing amount")?.parse()?,
+            fee: match fee {
+                Some(fee) => fee.parse()?,
+                None => default_fee,
+            },
+        })
+    }
+}
+
+/// A transfer built without its key, as `client tx build` writes it, to be
+/// carried to wherever the sender's key is kept and signed there.
+#[derive(Clone, Debug, PartialEq)]
+pub struct UnsignedTransfer {
+    pub sender: Address,
+    pub transfer: Transfer,
+}
+
+impl UnsignedTransfer {
+    pub fn to_json(&self) -> Value {
+        json!({
+            "sender": hex::encode(self.sender),
+            "receiver": hex::encode(self.transfer.receiver),
+            "amount": self.transfer.amount.to_string(),
+            "fee": self.transfer.fee.to_string(),
+            "nonce": self.transfer.nonce,
+            "kind": self.transfer.kind.as_str(),
+            "data": self.transfer.data,
+        })
+    }
+
+    pub fn from_json(value: &Value) -> Result<Self, String> {
+        let hash = |field: &str| {
+            parse_hash(
+                value
+                    .get(field)
+                    .and_then(Value::as_str)
+                    .ok_or(format!("Missing {} in transaction", field))?,
+            )
+        };
+        Ok(UnsignedTransfer {
+            sender: hash("sender")?,
+            transfer: Transfer {
+                receiver: hash("receiver")?,
+                amount: amount_field(value, "amount")?,
+                fee: amount_field(value, "fee")?,
+                nonce: value
+                    .get("nonce")
+                    .and_then(Value::as_u64)
+                    .ok_or("Missing nonce in transaction")?,
+                kind: match value.get("kind").and_then(Value::as_str) {
+                    Some(kind) => kind.parse()?,
+                    None => TxKind::Transfer,
+                },
+                data: match value.get("data") {
+                    Some(data) => serde_json::from_value(data.clone())
+                        .map_err(|e| format!("Invalid data in transaction: {}", e))?,
+                    None => TxData::None,
+                },
+            },
+        })
+    }
+
+    /// Signs with `key`, which must be the sender's.
+    pub fn sign(self, key: &SigningKey) -> Result<Transaction, String> {
+        if key_address(key) != self.sender {
+            return Err(format!(
+                "Key is for {}, not the sender {}",
+                hex::encode(key_address(key)),
+                hex::encode(self.sender)
+            ));
+        }
+        Ok(self.transfer.into_transaction(key))
+    }
+}
+
+/// Reads a hex-encoded 32-byte hash or address, such as one given on the
+/// command line.
+pub fn parse_hash(s: &str) -> Result<Hash, String> {
+    hex::decode(s.trim())
+        .map_err(|e| format!("Invalid hex: {}", e))?
+        .try_into()
+        .map_err(|_| "Expected 32 bytes of hex".to_string())
+}
+
+/// The result of an HTTP response to a call of `method`, or the error the
+/// node answered with.
+fn parse_response(method: &str, response: &str) -> Result<Value, RpcError> {
+    let (head, body) = response
+        .split_once("\r\n\r\n")
+        .ok_or(RpcError::InvalidResponse(
+            "Malformed response from node".to_string(),
+        ))?;
+    let mut response: Value = serde_json::from_str(body).map_err(|e| {
+        let status = head.lines().next().unwrap_or_default();
+        RpcError::InvalidResponse(format!("Invalid response from node ({}): {}", status, e))
+    })?;
+    if let Some(error) = response.get("error") {
+        return Err(RpcError::Rejected {
+            method: method.to_string(),
+            code: error.get("code").and_then(Value::as_i64).unwrap_or(0),
+            message: error
+                .get("message")
+                .and_then(Value::as_str)
+                .unwrap_or("unknown error")
+                .to_string(),
+        });
+    }
+    Ok(response["result"].take())
+}
+
+fn amount_field(value: &Value, field: &str) -> Result<Amount, String> {
+    value
+        .get(field)
+        .and_then(Value::as_str)
+        .ok_or(format!("Missing {} in response", field))?
+        .parse()
+}
@@ -0,0 +1,107 @@
+//! The client's address book, naming the accounts it pays so commands can
+//! take `alice` where they would take a 64-digit address.
+//!
+//! Contacts are kept as one JSON object of names to hex addresses.
+
+use crate::blockchain::Address;
+use crate::client::parse_hash;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+
+/// File the address book is kept in unless given another.
+pub fn default_contacts_path() -> PathBuf {
+    crate::db::default_data_dir().join("contacts.json")
+}
+
+/// A file of named addresses.
+#[derive(Clone, Debug)]
+pub struct Contacts {
+    path: PathBuf,
+}
+
+impl Contacts {
+    pub fn open(path: impl Into<PathBuf>) -> Self {
+        Contacts { path: path.into() }
+    }
+
+    /// Every contact by name, in name order.
+    pub fn list(&self) -> Result<Vec<(String, Address)>, String> {
+        Ok(self.read()?.into_iter().collect())
+    }
+
+    /// Names `address`. A name already in use is never overwritten.
+    pub fn add(&self, name: &str, address: Address) -> Result<(), String> {
+        let valid = !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+            && parse_hash(name).is_err();
+        if !valid {
+            return Err(format!("Invalid contact name: {:?}", name));
+        }
+        let mut contacts = self.read()?;
+        if contacts.contains_key(name) {
+            return Err(format!("Contact {} already exists", name));
+        }
+        contacts.insert(name.to_string(), address);
+        self.write(&contacts)
+    }
+
+    /// Forgets the contact under `name`, returning its address.
+    pub fn remove(&self, name: &str) -> Result<Address, String> {
+        let mut contacts = self.read()?;
+        let address = contacts
+            .remove(name)
+            .ok_or(format!("No contact named {}", name))?;
+        self.write(&contacts)?;
+        Ok(address)
+    }
+
+    /// The address `s` stands for: itself if it is a hex address, or else
+    /// the contact of that name.
+    pub fn resolve(&self, s: &str) -> Result<Address, String> {
+        if let Ok(address) = parse_hash(s) {
+            return Ok(address);
+        }
+        self.read()?
+            .remove(s.trim())
+            .ok_or(format!("{:?} is neither an address nor a contact", s))
+    }
+
+    fn read(&self) -> Result<BTreeMap<String, Address>, String> {
+        let json = match fs::read(&self.path) {
+            Ok(json) => json,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(BTreeMap::new()),
+            Err(e) => return Err(format!("Failed to read {}: {}", self.path.display(), e)),
+        };
+        let contacts: BTreeMap<String, String> = serde_json::from_slice(&json)
+            .map_err(|e| format!("Invalid contacts file {}: {}", self.path.display(), e))?;
+        contacts
+            .into_iter()
+            .map(|(name, address)| {
+                let address = parse_hash(&address)
+                    .map_err(|e| format!("Contact {} has an invalid address: {}", name, e))?;
+                Ok((name, address))
+            })
+            .collect()
+    }
+
+    fn write(&self, contacts: &BTreeMap<String, Address>) -> Result<(), String> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)
+                .map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+        }
+        let contacts: BTreeMap<&String, String> = contacts
+            .iter()
+            .map(|(name, address)| (name, hex::encode(address)))
+            .collect();
+        let json = serde_json::to_vec_pretty(&contacts).map_err(|e| e.to_string())?;
+        // Written aside and renamed so an interrupted write cannot lose the
+        // existing contacts.
+        let temp = self.path.with_extension("json.tmp");
+        fs::write(&temp, json)
+            .and_then(|_| fs::rename(&temp, &self.path))
+            .map_err(|e| format!("Failed to write {}: {}", self.path.display(), e))
+    }
+}
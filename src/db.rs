@@ -1,137 +1,596 @@
-use crate::blockchain::{Address, Block, Transaction, Transfer, User};
-use rusqlite::{Connection, OptionalExtension, Result};
-use std::path::PathBuf;
+use crate::amount::Amount;
+use crate::blockchain::{
+    Address, BalanceChange, Block, BlockHeader, Hash, SnapshotAccount, Supply, Transaction,
+    TransactionLocation, Transfer, TxData, User,
+};
+use crate::error::BlockchainError;
+use crate::governance::{Parameter, ParameterChange, Parameters, Proposal, ProposalStatus, Tally};
+use crate::p2p::PeerRecord;
+use crate::webhooks::Webhook;
+use chrono::Utc;
+use lru::LruCache;
+use rusqlite::backup::Backup;
+use rusqlite::{Connection, MAIN_DB, OptionalExtension, Result, Row, TransactionBehavior};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::net::SocketAddr;
+use std::num::NonZeroUsize;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::sync::Semaphore;
+
+/// SQLite's name for a database that is never written to disk.
+const IN_MEMORY: &str = ":memory:";
+
+/// Heights are stored as SQLite's signed 64-bit integers.
+const MAX_HEIGHT: u64 = i64::MAX as u64;
+
+/// Read-only connections in a [`ReadPool`].
+const READERS: usize = 4;
+
+/// How long a statement waits for another connection's write lock before
+/// failing with `SQLITE_BUSY`.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Pages a backup copies at a time, and how long it then steps aside so
+/// writers are not held up for the whole copy.
+const BACKUP_STEP_PAGES: i32 = 256;
+const BACKUP_STEP_PAUSE: Duration = Duration::from_millis(10);
+
+/// Accounts kept in memory, so validating and applying transfers between
+/// active accounts does not read them from SQLite each time.
+const ACCOUNT_CACHE: NonZeroUsize = NonZeroUsize::new(10_000).unwrap();
+
+/// Most recently stored blocks kept in memory, without their transactions.
+const BLOCK_CACHE: NonZeroUsize = NonZeroUsize::new(1024).unwrap();
+
+const BLOCK_COLUMNS: &str = "previous_hash, merkle_root, state_root, timestamp, height, proposer, coinbase, finalized_hash, signature";
+
+const USER_COLUMNS: &str =
+    "address, public_key, balance, stake, delegate, commission, missed_slots, jailed";
+
+const PROPOSAL_COLUMNS: &str =
+    "id, proposer, parameter, value, activation_height, height, accepted";
 
 pub struct Database {
     path: PathBuf,
     conn: Connection,
     test: bool,
+    cache: Cache,
+}
+
+/// Read-only connections to a database file, each lent to one blocking
+/// task at a time, so reads run alongside the node's own connection
+/// rather than queueing behind the block it is applying. In WAL mode a
+/// read sees the database as last committed.
+pub struct ReadPool {
+    idle: std::sync::Mutex<Vec<Database>>,
+    permits: Semaphore,
+}
+
+impl ReadPool {
+    /// Opens [`READERS`] read-only connections to `database`'s file. Fails
+    /// for an in-memory database, which no other connection can see.
+    pub fn open(database: &Database) -> Result<Self> {
+        let idle = (0..READERS)
+            .map(|_| database.try_clone_read_only())
+            .collect::<Result<Vec<_>>>()?;
+        Ok(ReadPool {
+            idle: std::sync::Mutex::new(idle),
+            permits: Semaphore::new(READERS),
+        })
+    }
+
+    /// Runs `read` on an idle connection on the blocking thread pool,
+    /// waiting for one if every connection is busy.
+    pub async fn read<R, F>(&self, read: F) -> Result<R>
+    where
+        F: FnOnce(&Database) -> Result<R> + Send + 'static,
+        R: Send + 'static,
+    {
+        let permit = self.permits.acquire().await.expect("never closed");
+        let database = self
+            .idle
+            .lock()
+            .unwrap()
+            .pop()
+            .expect("a permit is held for each idle connection");
+        let task = tokio::task::spawn_blocking(move || {
+            // Another connection may have written since this one last read.
+            database.cache.clear();
+            let result = read(&database);
+            (database, result)
+        });
+        match task.await {
+            Ok((database, result)) => {
+                self.idle.lock().unwrap().push(database);
+                result
+            }
+            Err(e) => {
+                // The connection went down with the read, and its permit
+                // with it.
+                permit.forget();
+                std::panic::resume_unwind(e.into_panic())
+            }
+        }
+    }
+}
+
+impl std::fmt::Debug for ReadPool {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ReadPool")
+            .field("idle", &self.permits.available_permits())
+            .finish()
+    }
+}
+
+/// Accounts and recent blocks as last read or written through this
+/// connection. Writes from other connections are not seen here, so they
+/// must not touch accounts or blocks.
+struct Cache {
+    accounts: RefCell<LruCache<Address, User>>,
+    blocks: RefCell<LruCache<Hash, Block>>,
+    /// Hash of the block stored last at each cached height.
+    heights: RefCell<LruCache<u64, Hash>>,
+}
+
+impl Cache {
+    fn new() -> Self {
+        Cache {
+            accounts: RefCell::new(LruCache::new(ACCOUNT_CACHE)),
+            blocks: RefCell::new(LruCache::new(BLOCK_CACHE)),
+            heights: RefCell::new(LruCache::new(BLOCK_CACHE)),
+        }
+    }
+
+    fn put_block(&self, block: &Block) {
+        let hash = block.hash();
+        self.blocks.borrow_mut().put(
+            hash,
+            Block {
+                header: block.header.clone(),
+                transactions: vec![],
+            },
+        );
+        self.heights.borrow_mut().put(block.header.height, hash);
+    }
+
+    fn clear_blocks(&self) {
+        self.blocks.borrow_mut().clear();
+        self.heights.borrow_mut().clear();
+    }
+
+    fn clear(&self) {
+        self.accounts.borrow_mut().clear();
+        self.clear_blocks();
+    }
 }
 
 impl Database {
     pub fn new(path: Option<&str>, test: bool) -> Result<Self> {
         let path = if test {
-            let test_path = dirs::home_dir().unwrap().join(".smvblock/test.db");
+            let test_path = default_data_dir().join("test.db");
             if test_path.exists() {
-                let _ = std::fs::rename(&test_path, &test_path.with_extension("bak"));
+                let _ = std::fs::rename(&test_path, test_path.with_extension("bak"));
+            }
+            // A leftover log would be replayed into the fresh database.
+            for log in ["db-wal", "db-shm"] {
+                let _ = std::fs::remove_file(test_path.with_extension(log));
             }
             test_path
         } else {
             path.map(PathBuf::from)
-                .unwrap_or_else(|| dirs::home_dir().unwrap().join(".smvblock/temp.db"))
+                .unwrap_or_else(|| default_data_dir().join("temp.db"))
         };
 
-        let conn = Connection::open(&path)?;
+        let mut conn = open(&path)?;
+        migrate(&mut conn)?;
 
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS users (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                address BLOB NOT NULL,
-                public_key BLOB NOT NULL,
-                balance INTEGER NOT NULL,
-                stake INTEGER NOT NULL
-            )",
-            [],
-        )?;
+        Ok(Database {
+            path,
+            conn,
+            test,
+            cache: Cache::new(),
+        })
+    }
 
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS blocks (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                previous_hash BLOB NOT NULL,
-                merkle_root BLOB NOT NULL,
-                nonce INTEGER NOT NULL,
-                timestamp INTEGER NOT NULL
-            )",
-            [],
-        )?;
+    /// A database that lives only as long as this value, for tests and
+    /// throwaway nodes. It shares nothing with other databases, unlike test
+    /// databases, which all use the same file.
+    pub fn in_memory() -> Result<Self> {
+        let path = PathBuf::from(IN_MEMORY);
+        let mut conn = open(&path)?;
+        migrate(&mut conn)?;
+        Ok(Database {
+            path,
+            conn,
+            test: true,
+            cache: Cache::new(),
+        })
+    }
 
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS transactions (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                tx_hash BLOB NOT NULL,
-                receiver BLOB NOT NULL,
-                amount INTEGER NOT NULL,
-                nonce INTEGER NOT NULL,
-                sender_public_key BLOB NOT NULL,
-                signature BLOB NOT NULL,
-                verified BOOLEAN NOT NULL
-            )",
-            [],
-        )?;
+    /// Opens another connection to the same file, configured the same way,
+    /// for work that should not wait on this one.
+    pub fn try_clone(&self) -> Result<Self> {
+        if self.path.as_os_str() == IN_MEMORY {
+            return Err(rusqlite::Error::InvalidPath(self.path.clone()));
+        }
+        Ok(Database {
+            path: self.path.clone(),
+            conn: open(&self.path)?,
+            test: self.test,
+            cache: Cache::new(),
+        })
+    }
+
+    /// Opens another connection to the same file that refuses to write,
+    /// for [`ReadPool`].
+    fn try_clone_read_only(&self) -> Result<Self> {
+        let database = self.try_clone()?;
+        database.conn.pragma_update(None, "query_only", true)?;
+        Ok(database)
+    }
+
+    /// Copies the database to `dest` with SQLite's online backup API, so
+    /// it can run while the node keeps writing. An existing file at `dest`
+    /// is overwritten.
+    pub fn backup(&self, dest: &Path) -> Result<()> {
+        let mut dest = Connection::open(dest)?;
+        let backup = Backup::new(&self.conn, &mut dest)?;
+        backup.run_to_completion(BACKUP_STEP_PAGES, BACKUP_STEP_PAUSE, None)
+    }
+
+    /// Replaces everything in the database with the backup at `src`, then
+    /// brings the backup's schema up to date.
+    pub fn restore(&mut self, src: &Path) -> Result<()> {
+        // Opening a missing file would restore an empty database.
+        if !src.is_file() {
+            return Err(rusqlite::Error::InvalidPath(src.to_path_buf()));
+        }
+        self.cache.clear();
+        self.conn
+            .restore(MAIN_DB, src, None::<fn(rusqlite::backup::Progress)>)?;
+        migrate(&mut self.conn)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
 
-        Ok(Database { path, conn, test })
+    pub fn is_test(&self) -> bool {
+        self.test
     }
 
     pub fn add_block(&mut self, block: &Block) -> Result<()> {
+        self.commit_block(block, &[], Amount::ZERO)
+    }
+
+    /// Stores a block together with the accounts it updated, creating
+    /// those not stored yet, and the fees it burned, all or nothing.
+    pub fn commit_block(
+        &mut self,
+        block: &Block,
+        updated_users: &[User],
+        burned: Amount,
+    ) -> Result<()> {
         let transaction = self.conn.transaction()?;
 
         transaction.execute(
-            "INSERT INTO blocks (previous_hash, merkle_root, nonce, timestamp) VALUES (?1, ?2, ?3, ?4)",
+            "INSERT INTO blocks (hash, previous_hash, merkle_root, state_root, timestamp, height, proposer, coinbase, finalized_hash, signature, burned)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
             rusqlite::params![
-                block.previous_hash,
-                block.merkle_root,
-                block.nonce,
-                block.timestamp,
+                block.hash(),
+                block.header.previous_hash,
+                block.header.merkle_root,
+                block.header.state_root,
+                block.header.timestamp,
+                block.header.height,
+                block.header.proposer,
+                block.header.coinbase,
+                block.header.finalized_hash,
+                block.header.signature,
+                burned,
             ],
         )?;
 
-        for tx in &block.transactions {
-            let tx_hash = tx.payload.hash();
+        for (index, tx) in block.transactions.iter().enumerate() {
+            let tx_hash = tx.hash();
             transaction.execute(
-                "INSERT INTO transactions (tx_hash, receiver, amount, nonce, sender_public_key, signature, verified)
-                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                "INSERT INTO transactions (tx_hash, receiver, amount, fee, nonce, sender_public_key, signature, verified, block_hash, block_height, tx_index, kind, data)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
                 rusqlite::params![
                     tx_hash,
                     tx.payload.receiver,
                     tx.payload.amount,
+                    tx.payload.fee,
                     tx.payload.nonce,
                     tx.sender_public_key,
                     tx.signature,
                     true,
+                    block.hash(),
+                    block.header.height,
+                    index as u32,
+                    tx.payload.kind,
+                    tx.payload.data,
+                ],
+            )?;
+        }
+
+        for user in updated_users {
+            let existed = transaction.execute(
+                "INSERT INTO block_undo
+                     (block_hash, address, balance, stake, delegate, commission, missed_slots, jailed)
+                 SELECT ?1, address, balance, stake, delegate, commission, missed_slots, jailed
+                 FROM users WHERE address = ?2",
+                rusqlite::params![block.hash(), user.address],
+            )? > 0;
+            if !existed {
+                transaction.execute(
+                    "INSERT INTO block_undo (block_hash, address, balance, stake, created)
+                     VALUES (?1, ?2, ?3, ?3, 1)",
+                    rusqlite::params![block.hash(), user.address, Amount::ZERO],
+                )?;
+                transaction.execute(
+                    "INSERT INTO users (address, public_key, balance, stake) VALUES (?1, ?2, ?3, ?3)",
+                    rusqlite::params![user.address, user.public_key, Amount::ZERO],
+                )?;
+            }
+            transaction.execute(
+                "UPDATE users SET balance = ?1, stake = ?2, delegate = ?3, commission = ?4,
+                     missed_slots = ?5, jailed = ?6
+                 WHERE address = ?7",
+                rusqlite::params![
+                    user.balance,
+                    user.stake,
+                    user.delegate,
+                    user.commission_bps,
+                    user.missed_slots,
+                    user.jailed,
+                    user.address
                 ],
             )?;
         }
+        record_governance(&transaction, block)?;
+        record_validator_keys(&transaction, block)?;
+        record_beacon(&transaction, block)?;
 
         transaction.commit()?;
+        let mut accounts = self.cache.accounts.borrow_mut();
+        for user in updated_users {
+            accounts.pop(&user.address);
+        }
+        drop(accounts);
+        self.cache.put_block(block);
         Ok(())
     }
 
-    pub fn get_block(&self, hash: &[u8]) -> Result<Option<Block>> {
-        let mut stmt = self.conn.prepare(
-            "SELECT previous_hash, merkle_root, nonce, timestamp FROM blocks WHERE previous_hash = ?1",
+    /// Undoes a block stored by [`Database::commit_block`]: restores the
+    /// accounts it updated, deletes those it created and deletes it and its
+    /// transactions, all or nothing. Returns the restored accounts, or
+    /// `None` if the block has no undo records, such as one stored without
+    /// its transactions.
+    pub fn revert_block(&mut self, block_hash: &[u8]) -> Result<Option<Vec<User>>> {
+        let transaction = self.conn.transaction()?;
+
+        let undo = {
+            let mut stmt = transaction.prepare(
+                "SELECT address, balance, stake, delegate, commission, missed_slots, jailed, created
+                 FROM block_undo WHERE block_hash = ?1",
+            )?;
+            stmt.query_map(rusqlite::params![block_hash], |row| {
+                Ok((
+                    row.get::<_, Address>(0)?,
+                    row.get::<_, Amount>(1)?,
+                    row.get::<_, Amount>(2)?,
+                    row.get::<_, Option<Address>>(3)?,
+                    row.get::<_, u16>(4)?,
+                    row.get::<_, u32>(5)?,
+                    row.get::<_, bool>(6)?,
+                    row.get::<_, bool>(7)?,
+                ))
+            })?
+            .collect::<Result<Vec<_>, _>>()?
+        };
+        if undo.is_empty() {
+            return Ok(None);
+        }
+
+        for (address, balance, stake, delegate, commission, missed_slots, jailed, created) in &undo
+        {
+            if *created {
+                transaction.execute(
+                    "DELETE FROM users WHERE address = ?1",
+                    rusqlite::params![address],
+                )?;
+            } else {
+                transaction.execute(
+                    "UPDATE users SET balance = ?1, stake = ?2, delegate = ?3, commission = ?4,
+                         missed_slots = ?5, jailed = ?6
+                     WHERE address = ?7",
+                    rusqlite::params![
+                        balance,
+                        stake,
+                        delegate,
+                        commission,
+                        missed_slots,
+                        jailed,
+                        address
+                    ],
+                )?;
+            }
+        }
+        transaction.execute(
+            "DELETE FROM block_undo WHERE block_hash = ?1",
+            rusqlite::params![block_hash],
+        )?;
+        transaction.execute(
+            "DELETE FROM transactions WHERE block_hash = ?1",
+            rusqlite::params![block_hash],
+        )?;
+        // The proposals the block decided are undecided again.
+        transaction.execute(
+            "UPDATE proposals SET accepted = NULL
+             WHERE activation_height = (SELECT height + 1 FROM blocks WHERE hash = ?1)",
+            rusqlite::params![block_hash],
+        )?;
+        transaction.execute(
+            "DELETE FROM proposal_votes WHERE block_hash = ?1",
+            rusqlite::params![block_hash],
+        )?;
+        transaction.execute(
+            "DELETE FROM proposals WHERE block_hash = ?1",
+            rusqlite::params![block_hash],
+        )?;
+        transaction.execute(
+            "DELETE FROM validator_keys WHERE block_hash = ?1",
+            rusqlite::params![block_hash],
+        )?;
+        transaction.execute(
+            "DELETE FROM beacon_commitments WHERE block_hash = ?1",
+            rusqlite::params![block_hash],
+        )?;
+        transaction.execute(
+            "DELETE FROM beacon_reveals WHERE block_hash = ?1",
+            rusqlite::params![block_hash],
+        )?;
+        transaction.execute(
+            "DELETE FROM blocks WHERE hash = ?1",
+            rusqlite::params![block_hash],
         )?;
+        transaction.commit()?;
+        let mut accounts = self.cache.accounts.borrow_mut();
+        for (address, ..) in &undo {
+            accounts.pop(address);
+        }
+        drop(accounts);
+        // The block below is now the last at its height, if any is.
+        self.cache.clear_blocks();
+
+        let mut users = Vec::new();
+        for (address, ..) in undo {
+            users.extend(self.get_user(&address)?);
+        }
+        Ok(Some(users))
+    }
+
+    pub fn get_block(&self, hash: &[u8]) -> Result<Option<Block>> {
+        if let Ok(hash) = Hash::try_from(hash)
+            && let Some(block) = self.cache.blocks.borrow_mut().get(&hash)
+        {
+            return Ok(Some(block.clone()));
+        }
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {} FROM blocks WHERE hash = ?1",
+            BLOCK_COLUMNS
+        ))?;
 
         let block = stmt
-            .query_row(rusqlite::params![hash], |row| {
-                Ok(Block {
-                    previous_hash: row.get(0)?,
-                    merkle_root: row.get(1)?,
-                    nonce: row.get(2)?,
-                    timestamp: row.get(3)?,
-                    transactions: vec![],
-                })
-            })
+            .query_row(rusqlite::params![hash], block_from_row)
             .optional()?;
 
+        if let Some(block) = &block {
+            self.cache
+                .blocks
+                .borrow_mut()
+                .put(block.hash(), block.clone());
+        }
         Ok(block)
     }
 
-    pub fn get_blocks(&self) -> Result<Vec<Block>> {
-        let mut stmt = self
-            .conn
-            .prepare("SELECT previous_hash, merkle_root, nonce, timestamp FROM blocks")?;
+    pub fn get_block_by_height(&self, height: u64) -> Result<Option<Block>> {
+        let hash = self.cache.heights.borrow_mut().get(&height).copied();
+        if let Some(block) =
+            hash.and_then(|hash| self.cache.blocks.borrow_mut().get(&hash).cloned())
+        {
+            return Ok(Some(block));
+        }
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {} FROM blocks WHERE height = ?1 ORDER BY id DESC LIMIT 1",
+            BLOCK_COLUMNS
+        ))?;
+
+        let block = stmt
+            .query_row(rusqlite::params![height], block_from_row)
+            .optional()?;
+        if let Some(block) = &block {
+            self.cache.put_block(block);
+        }
+        Ok(block)
+    }
+
+    /// Headers of the blocks from height `from` up to but excluding `to`, in
+    /// height order and at most `limit` of them. Paging through the chain
+    /// goes on from one past the last height returned.
+    pub fn get_headers_range(&self, from: u64, to: u64, limit: usize) -> Result<Vec<BlockHeader>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {} FROM blocks WHERE height >= ?1 AND height < ?2 ORDER BY height, id LIMIT ?3",
+            BLOCK_COLUMNS
+        ))?;
+
+        let headers = stmt
+            .query_map(
+                rusqlite::params![from.min(MAX_HEIGHT), to.min(MAX_HEIGHT), limit],
+                |row| block_from_row(row).map(|block| block.header),
+            )?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(headers)
+    }
+
+    /// Transactions included in the block `block_hash`, in block order.
+    pub fn get_block_transactions(&self, block_hash: &[u8]) -> Result<Vec<Transaction>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT receiver, amount, fee, nonce, sender_public_key, signature, kind, data FROM transactions
+             WHERE block_hash = ?1 ORDER BY id",
+        )?;
+
+        let transactions = stmt
+            .query_map(rusqlite::params![block_hash], transaction_from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(transactions)
+    }
+
+    /// Every block's stored hash and header, in height order. A row that
+    /// no longer decodes comes back as its error, so one corrupt block
+    /// does not hide the rest.
+    pub fn get_stored_blocks(&self) -> Result<Vec<(Vec<u8>, Result<BlockHeader>)>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {}, hash FROM blocks ORDER BY height, id",
+            BLOCK_COLUMNS
+        ))?;
 
         let blocks = stmt
             .query_map([], |row| {
-                Ok(Block {
-                    previous_hash: row.get(0)?,
-                    merkle_root: row.get(1)?,
-                    nonce: row.get(2)?,
-                    timestamp: row.get(3)?,
-                    transactions: vec![],
-                })
+                Ok((row.get(9)?, block_from_row(row).map(|block| block.header)))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(blocks)
+    }
+
+    /// Accounts as they were before the block `block_hash`, as
+    /// `(address, balance, stake)`.
+    pub fn get_block_undo(&self, block_hash: &[u8]) -> Result<Vec<(Address, Amount, Amount)>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT address, balance, stake FROM block_undo WHERE block_hash = ?1")?;
+
+        let undo = stmt
+            .query_map(rusqlite::params![block_hash], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?))
             })?
             .collect::<Result<Vec<_>, _>>()?;
+        Ok(undo)
+    }
+
+    pub fn get_blocks(&self) -> Result<Vec<Block>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {} FROM blocks ORDER BY height, id",
+            BLOCK_COLUMNS
+        ))?;
+
+        let blocks = stmt
+            .query_map([], block_from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
 
         Ok(blocks)
     }
@@ -145,18 +604,21 @@ impl Database {
     }
 
     pub fn add_transaction(&self, transaction: &Transaction, verified: bool) -> Result<()> {
-        let tx_hash = transaction.payload.hash();
+        let tx_hash = transaction.hash();
         self.conn.execute(
-            "INSERT INTO transactions (tx_hash, receiver, amount, nonce, sender_public_key, signature, verified) 
-             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            "INSERT INTO transactions (tx_hash, receiver, amount, fee, nonce, sender_public_key, signature, verified, kind, data)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
             rusqlite::params![
                 tx_hash,
                 transaction.payload.receiver,
                 transaction.payload.amount,
+                transaction.payload.fee,
                 transaction.payload.nonce,
                 transaction.sender_public_key,
                 transaction.signature,
                 verified,
+                transaction.payload.kind,
+                transaction.payload.data,
             ],
         )?;
         Ok(())
@@ -171,71 +633,139 @@ impl Database {
     }
 
     pub fn get_all_transactions(&self) -> Result<Vec<Transaction>> {
-        let query =
-            "SELECT receiver, amount, nonce, sender_public_key, signature FROM transactions";
+        let query = "SELECT receiver, amount, fee, nonce, sender_public_key, signature, kind, data FROM transactions";
 
-        let mut stmt = self.conn.prepare(&query)?;
+        let mut stmt = self.conn.prepare(query)?;
         let transactions = stmt
-            .query_map([], |row| {
-                Ok(Transaction {
-                    sender_public_key: row.get(3)?,
-                    signature: row.get(4)?,
-                    payload: Transfer {
-                        receiver: row.get(0)?,
-                        amount: row.get(1)?,
-                        nonce: row.get(2)?,
-                    },
-                })
-            })?
+            .query_map([], transaction_from_row)?
             .collect::<Result<Vec<_>, _>>()?;
         Ok(transactions)
     }
 
     fn get_transactions(&self, verified: bool) -> Result<Vec<Transaction>> {
         let query = format!(
-            "SELECT receiver, amount, nonce, sender_public_key, signature FROM transactions WHERE verified = {}",
+            "SELECT receiver, amount, fee, nonce, sender_public_key, signature, kind, data FROM transactions WHERE verified = {}",
             verified
         );
 
         let mut stmt = self.conn.prepare(&query)?;
         let transactions = stmt
-            .query_map([], |row| {
-                Ok(Transaction {
-                    sender_public_key: row.get(3)?,
-                    signature: row.get(4)?,
-                    payload: Transfer {
-                        receiver: row.get(0)?,
-                        amount: row.get(1)?,
-                        nonce: row.get(2)?,
-                    },
-                })
-            })?
+            .query_map([], transaction_from_row)?
             .collect::<Result<Vec<_>, _>>()?;
         Ok(transactions)
     }
 
     pub fn get_transaction_by_hash(&self, tx_hash: &[u8]) -> Result<Option<Transaction>> {
         let mut stmt = self.conn.prepare(
-            "SELECT receiver, amount, nonce, sender_public_key, signature FROM transactions WHERE tx_hash = ?1",
+            "SELECT receiver, amount, fee, nonce, sender_public_key, signature, kind, data FROM transactions WHERE tx_hash = ?1",
         )?;
 
         let transaction = stmt
-            .query_row(rusqlite::params![tx_hash], |row| {
-                Ok(Transaction {
-                    sender_public_key: row.get(3)?,
-                    signature: row.get(4)?,
-                    payload: Transfer {
-                        receiver: row.get(0)?,
-                        amount: row.get(1)?,
-                        nonce: row.get(2)?,
-                    },
-                })
-            })
+            .query_row(rusqlite::params![tx_hash], transaction_from_row)
             .optional()?;
 
         Ok(transaction)
     }
 
+    /// The fees and stake the block `hash` burned, as it was committed with.
+    pub fn get_block_burned(&self, hash: &Hash) -> Result<Option<Amount>> {
+        self.conn
+            .query_row(
+                "SELECT burned FROM blocks WHERE hash = ?1",
+                rusqlite::params![hash],
+                |row| row.get(0),
+            )
+            .optional()
+    }
+
+    /// The confirmed transaction `tx_hash` and where it was included.
+    pub fn get_transaction_with_block(
+        &self,
+        tx_hash: &[u8],
+    ) -> Result<Option<(Transaction, TransactionLocation)>> {
+        self.conn
+            .query_row(
+                "SELECT receiver, amount, fee, nonce, sender_public_key, signature, kind, data, block_hash, block_height, tx_index
+                 FROM transactions WHERE tx_hash = ?1 AND block_hash IS NOT NULL
+                 ORDER BY id DESC LIMIT 1",
+                rusqlite::params![tx_hash],
+                located_transaction_from_row,
+            )
+            .optional()
+    }
+
+    /// Confirmed transactions sent or received by `address`, newest first
+    /// and at most `limit` of them. Given the location of the last one of a
+    /// page as `before`, returns the page after it.
+    pub fn get_transactions_by_address(
+        &self,
+        address: &Address,
+        before: Option<TransactionLocation>,
+        limit: usize,
+    ) -> Result<Vec<(Transaction, TransactionLocation)>> {
+        let (height, index) = before.map_or((MAX_HEIGHT, u32::MAX), |location| {
+            (location.height, location.index)
+        });
+        let mut stmt = self.conn.prepare(
+            "SELECT receiver, amount, fee, nonce, sender_public_key, signature, kind, data, block_hash, block_height, tx_index
+             FROM transactions
+             WHERE block_hash IS NOT NULL
+               AND (receiver = ?1 OR sender_public_key IN (
+                   SELECT public_key FROM users WHERE address = ?1
+                   UNION SELECT public_key FROM watched_addresses WHERE address = ?1))
+               AND (block_height, tx_index) < (?2, ?3)
+             ORDER BY block_height DESC, tx_index DESC
+             LIMIT ?4",
+        )?;
+
+        let transactions = stmt
+            .query_map(
+                rusqlite::params![address, height, index, limit],
+                located_transaction_from_row,
+            )?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(transactions)
+    }
+
+    /// Confirmed transactions sent or received by any of `addresses` in
+    /// blocks from height `from` up to but excluding `to`, in chain order
+    /// and at most `limit` of them.
+    pub fn get_transactions_in_range(
+        &self,
+        addresses: &[Address],
+        from: u64,
+        to: u64,
+        limit: usize,
+    ) -> Result<Vec<(Transaction, TransactionLocation)>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT receiver, amount, fee, nonce, sender_public_key, signature, kind, data, block_hash, block_height, tx_index
+             FROM transactions
+             WHERE block_hash IS NOT NULL
+               AND (receiver = ?1 OR sender_public_key IN (SELECT public_key FROM users WHERE address = ?1))
+               AND block_height >= ?2 AND block_height < ?3
+             ORDER BY block_height, tx_index
+             LIMIT ?4",
+        )?;
+
+        let mut found = BTreeMap::new();
+        for address in addresses {
+            let transactions = stmt.query_map(
+                rusqlite::params![
+                    address,
+                    from.min(MAX_HEIGHT),
+                    to.min(MAX_HEIGHT),
+                    (limit as u64).min(MAX_HEIGHT)
+                ],
+                located_transaction_from_row,
+            )?;
+            for transaction in transactions {
+                let (tx, location) = transaction?;
+                found.insert((location.height, location.index), (tx, location));
+            }
+        }
+        Ok(found.into_values().take(limit).collect())
+    }
+
     pub fn update_transaction_verified(&self, tx_hash: &[u8], verified: bool) -> Result<()> {
         self.conn.execute(
             "UPDATE transactions SET verified = ?1 WHERE tx_hash = ?2",
@@ -244,101 +774,844 @@ impl Database {
         Ok(())
     }
 
-    pub fn add_user(&self, user: &User) -> Result<()> {
+    /// Replaces the saved mempool with `transactions`.
+    pub fn save_mempool(&mut self, transactions: &[Transaction]) -> Result<()> {
+        let transaction = self.conn.transaction()?;
+        transaction.execute("DELETE FROM mempool", [])?;
+        for tx in transactions {
+            transaction.execute(
+                "INSERT INTO mempool (receiver, amount, fee, nonce, sender_public_key, signature, kind, data)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                rusqlite::params![
+                    tx.payload.receiver,
+                    tx.payload.amount,
+                    tx.payload.fee,
+                    tx.payload.nonce,
+                    tx.sender_public_key,
+                    tx.signature,
+                    tx.payload.kind,
+                    tx.payload.data,
+                ],
+            )?;
+        }
+        transaction.commit()
+    }
+
+    /// Loads and clears the saved mempool.
+    pub fn take_mempool(&mut self) -> Result<Vec<Transaction>> {
+        let transaction = self.conn.transaction()?;
+        let transactions = {
+            let mut stmt = transaction.prepare(
+                "SELECT receiver, amount, fee, nonce, sender_public_key, signature, kind, data FROM mempool ORDER BY id",
+            )?;
+            stmt.query_map([], transaction_from_row)?
+                .collect::<Result<Vec<_>, _>>()?
+        };
+        transaction.execute("DELETE FROM mempool", [])?;
+        transaction.commit()?;
+        Ok(transactions)
+    }
+
+    pub fn save_peer(&self, peer: &PeerRecord) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO peers (address, node_type, node_id, last_seen, successes, failures)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+             ON CONFLICT(address) DO UPDATE SET node_type = ?2, node_id = ?3, last_seen = ?4, successes = ?5, failures = ?6",
+            rusqlite::params![
+                peer.addr.to_string(),
+                peer.node_type.map(|node_type| node_type.as_str()),
+                peer.node_id,
+                peer.last_seen,
+                peer.successes,
+                peer.failures,
+            ],
+        )?;
+        Ok(())
+    }
+
+    pub fn delete_peer(&self, addr: &SocketAddr) -> Result<()> {
+        self.conn.execute(
+            "DELETE FROM peers WHERE address = ?1",
+            rusqlite::params![addr.to_string()],
+        )?;
+        Ok(())
+    }
+
+    /// Every saved peer; rows that no longer parse are skipped.
+    pub fn load_peers(&self) -> Result<Vec<PeerRecord>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT address, node_type, node_id, last_seen, successes, failures FROM peers",
+        )?;
+        let rows = stmt
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, Option<String>>(1)?,
+                    row.get(2)?,
+                    row.get(3)?,
+                    row.get(4)?,
+                    row.get(5)?,
+                ))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(rows
+            .into_iter()
+            .filter_map(
+                |(addr, node_type, node_id, last_seen, successes, failures)| {
+                    Some(PeerRecord {
+                        addr: addr.parse().ok()?,
+                        node_type: node_type.and_then(|t| t.parse().ok()),
+                        node_id,
+                        last_seen,
+                        successes,
+                        failures,
+                    })
+                },
+            )
+            .collect())
+    }
+
+    pub fn get_node_key(&self) -> Result<Option<[u8; 32]>> {
+        self.conn
+            .query_row(
+                "SELECT secret_key FROM node_identity WHERE id = 1",
+                [],
+                |row| row.get(0),
+            )
+            .optional()
+    }
+
+    pub fn save_node_key(&self, secret_key: &[u8; 32]) -> Result<()> {
         self.conn.execute(
-            "INSERT INTO users (address, public_key, balance, stake) VALUES (?1, ?2, ?3, ?4)",
-            rusqlite::params![user.address, user.public_key, user.balance, user.stake],
+            "INSERT OR REPLACE INTO node_identity (id, secret_key) VALUES (1, ?1)",
+            rusqlite::params![secret_key],
         )?;
         Ok(())
     }
 
+    /// Adds an account with what it holds, which counts as allocated
+    /// rather than emitted supply.
+    pub fn add_user(&self, user: &User) -> Result<()> {
+        let transaction = self.conn.unchecked_transaction()?;
+        insert_user(&transaction, user)?;
+        add_to_metadata(&transaction, "allocated", user.balance)?;
+        add_to_metadata(&transaction, "allocated", user.stake)?;
+        transaction.commit()
+    }
+
+    /// Records that `amount` was given to an account outside any block.
+    pub fn record_allocation(&self, amount: Amount) -> Result<()> {
+        add_to_metadata(&self.conn, "allocated", amount)
+    }
+
     pub fn get_users(&self) -> Result<Vec<User>> {
         let mut stmt = self
             .conn
-            .prepare("SELECT address, public_key, balance, stake FROM users")?;
+            .prepare(&format!("SELECT {} FROM users", USER_COLUMNS))?;
         let users = stmt
-            .query_map([], |row| {
-                Ok(User {
-                    address: row.get(0)?,
-                    public_key: row.get(1)?,
-                    balance: row.get(2)?,
-                    stake: row.get(3)?,
-                })
-            })?
+            .query_map([], user_from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(users)
+    }
+
+    /// The accounts delegating their stake to `validator`, ordered by
+    /// address.
+    pub fn get_delegators(&self, validator: &Address) -> Result<Vec<User>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {} FROM users WHERE delegate = ?1 ORDER BY address",
+            USER_COLUMNS
+        ))?;
+        let users = stmt
+            .query_map([validator], user_from_row)?
             .collect::<Result<Vec<_>, _>>()?;
         Ok(users)
     }
 
     pub fn get_user(&self, address: &Address) -> Result<Option<User>> {
-        let mut stmt = self
-            .conn
-            .prepare("SELECT address, public_key, balance, stake FROM users WHERE address = ?1")?;
+        if let Some(user) = self.cache.accounts.borrow_mut().get(address) {
+            return Ok(Some(user.clone()));
+        }
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {} FROM users WHERE address = ?1",
+            USER_COLUMNS
+        ))?;
 
         let user = stmt
-            .query_row(rusqlite::params![address], |row| {
-                Ok(User {
-                    address: row.get(0)?,
-                    public_key: row.get(1)?,
-                    balance: row.get(2)?,
-                    stake: row.get(3)?,
-                })
-            })
+            .query_row(rusqlite::params![address], user_from_row)
             .optional()?;
 
+        if let Some(user) = &user {
+            self.cache
+                .accounts
+                .borrow_mut()
+                .put(user.address, user.clone());
+        }
         Ok(user)
     }
 
-    pub fn get_latest_block(&self) -> Result<Option<Block>> {
+    /// The account's balance once the block at `height` was applied, read
+    /// back from the undo records of later blocks. `None` for an unknown
+    /// account.
+    pub fn get_balance_at(&self, address: &Address, height: u64) -> Result<Option<Amount>> {
+        let undone = self
+            .conn
+            .query_row(
+                "SELECT block_undo.balance FROM block_undo
+                 JOIN blocks ON blocks.hash = block_undo.block_hash
+                 WHERE block_undo.address = ?1 AND blocks.height > ?2
+                 ORDER BY blocks.height LIMIT 1",
+                rusqlite::params![address, height.min(MAX_HEIGHT)],
+                |row| row.get(0),
+            )
+            .optional()?;
+        match undone {
+            Some(balance) => Ok(Some(balance)),
+            None => Ok(self.get_user(address)?.map(|user| user.balance)),
+        }
+    }
+
+    /// Every block that changed the account's balance, oldest first.
+    /// Changes made outside blocks, such as by installing a snapshot, are
+    /// folded into the next block's `before`.
+    pub fn get_balance_changes(&self, address: &Address) -> Result<Vec<BalanceChange>> {
+        let Some(user) = self.get_user(address)? else {
+            return Ok(Vec::new());
+        };
         let mut stmt = self.conn.prepare(
-            "SELECT previous_hash, merkle_root, nonce, timestamp FROM blocks ORDER BY id DESC LIMIT 1",
+            "SELECT block_undo.block_hash, blocks.height, block_undo.balance FROM block_undo
+             JOIN blocks ON blocks.hash = block_undo.block_hash
+             WHERE block_undo.address = ?1
+             ORDER BY blocks.height",
         )?;
+        let undo = stmt
+            .query_map(rusqlite::params![address], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+            })?
+            .collect::<Result<Vec<(Hash, u64, Amount)>, _>>()?;
 
-        let block = stmt
-            .query_row([], |row| {
-                Ok(Block {
-                    previous_hash: row.get(0)?,
-                    merkle_root: row.get(1)?,
-                    nonce: row.get(2)?,
-                    timestamp: row.get(3)?,
-                    transactions: vec![],
-                })
+        // Each block's balance afterwards is what the next one found.
+        let afters = undo
+            .iter()
+            .skip(1)
+            .map(|(_, _, balance)| *balance)
+            .chain([user.balance]);
+        Ok(undo
+            .iter()
+            .zip(afters)
+            .filter(|((_, _, before), after)| before != after)
+            .map(|(&(block_hash, height, before), after)| BalanceChange {
+                block_hash,
+                height,
+                before,
+                after,
             })
-            .optional()?;
-
-        Ok(block)
+            .collect())
     }
 
-    pub fn get_latest_nonce(&self, sender_public_key: &[u8]) -> Result<u64> {
-        let mut stmt = self
-            .conn
-            .prepare("SELECT MAX(nonce) FROM transactions WHERE sender_public_key = ?1")?;
+    pub fn get_latest_block(&self) -> Result<Option<Block>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {} FROM blocks ORDER BY id DESC LIMIT 1",
+            BLOCK_COLUMNS
+        ))?;
 
-        let max_nonce: Option<u64> =
-            stmt.query_row(rusqlite::params![sender_public_key], |row| row.get(0))?;
+        let block = stmt.query_row([], block_from_row).optional()?;
 
-        Ok(max_nonce.unwrap_or(0))
+        Ok(block)
     }
 
-    pub fn update_user(&self, user: &User) -> Result<()> {
+    pub fn mark_finalized(&self, hash: &[u8]) -> Result<()> {
         self.conn.execute(
-            "UPDATE users SET balance = ?1, stake = ?2 WHERE address = ?3",
-            rusqlite::params![user.balance, user.stake, user.address],
+            "UPDATE blocks SET finalized = 1 WHERE hash = ?1",
+            rusqlite::params![hash],
         )?;
         Ok(())
     }
 
-    pub fn delete_user(&self, address: &[u8]) -> Result<()> {
-        self.conn.execute(
+    /// Hash and height of the highest block marked final.
+    pub fn get_latest_finalized(&self) -> Result<Option<(Hash, u64)>> {
+        self.conn
+            .query_row(
+                "SELECT hash, height FROM blocks WHERE finalized = 1 ORDER BY height DESC LIMIT 1",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()
+    }
+
+    pub fn get_recent_timestamps(&self, count: usize) -> Result<Vec<i64>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT timestamp FROM blocks ORDER BY id DESC LIMIT ?1")?;
+
+        let timestamps = stmt
+            .query_map(rusqlite::params![count], |row| row.get(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(timestamps)
+    }
+
+    pub fn get_latest_nonce(&self, sender_public_key: &[u8]) -> Result<u64> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT MAX(nonce) FROM transactions WHERE sender_public_key = ?1")?;
+
+        let max_nonce: Option<u64> =
+            stmt.query_row(rusqlite::params![sender_public_key], |row| row.get(0))?;
+
+        Ok(max_nonce.unwrap_or(0))
+    }
+
+    /// The nonce the sender's next transaction must carry, i.e. one past the
+    /// highest nonce stored for that public key, or the nonce a snapshot
+    /// recorded for it if that is higher.
+    pub fn get_next_nonce(&self, sender_public_key: &[u8]) -> Result<u64> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT MAX(nonce) FROM transactions WHERE sender_public_key = ?1")?;
+
+        let max_nonce: Option<u64> =
+            stmt.query_row(rusqlite::params![sender_public_key], |row| row.get(0))?;
+
+        let snapshot_nonce: Option<u64> = self
+            .conn
+            .query_row(
+                "SELECT next_nonce FROM account_nonces WHERE public_key = ?1",
+                rusqlite::params![sender_public_key],
+                |row| row.get(0),
+            )
+            .optional()?;
+
+        Ok(max_nonce
+            .map_or(0, |nonce| nonce + 1)
+            .max(snapshot_nonce.unwrap_or(0)))
+    }
+
+    /// Every account, ordered by address, with the nonce its next
+    /// transaction must carry.
+    pub fn snapshot_accounts(&self) -> Result<Vec<SnapshotAccount>> {
+        let mut users = self.get_users()?;
+        users.sort_by_key(|user| user.address);
+        users
+            .into_iter()
+            .map(|user| {
+                let next_nonce = self.get_next_nonce(&user.public_key)?;
+                Ok(SnapshotAccount { user, next_nonce })
+            })
+            .collect()
+    }
+
+    /// Replaces all accounts with `accounts` and stores `headers`, which
+    /// must run from genesis to the block the snapshot was taken at, without
+    /// their transactions. All or nothing.
+    pub fn install_snapshot(
+        &mut self,
+        headers: &[BlockHeader],
+        accounts: &[SnapshotAccount],
+    ) -> Result<()> {
+        self.cache.clear();
+        let transaction = self.conn.transaction()?;
+
+        transaction.execute("DELETE FROM users", [])?;
+        transaction.execute("DELETE FROM account_nonces", [])?;
+        for SnapshotAccount { user, next_nonce } in accounts {
+            insert_user(&transaction, user)?;
+            transaction.execute(
+                "INSERT INTO account_nonces (public_key, next_nonce) VALUES (?1, ?2)",
+                rusqlite::params![user.public_key, next_nonce],
+            )?;
+        }
+
+        // The snapshot's blocks came without their transactions.
+        insert_headers(&transaction, headers)?;
+        // Burns before the snapshot cannot be told apart from allocations.
+        let allocated = holdings(&transaction)?.saturating_sub(emitted(&transaction)?);
+        set_metadata_amount(&transaction, "allocated", allocated)?;
+        set_metadata_amount(&transaction, "burned", Amount::ZERO)?;
+
+        transaction.commit()?;
+        Ok(())
+    }
+
+    /// Stores `headers` without their transactions, for nodes that keep
+    /// only the header chain. The first must follow on from the latest
+    /// stored block.
+    pub fn add_headers(&mut self, headers: &[BlockHeader]) -> Result<()> {
+        self.cache.clear_blocks();
+        let transaction = self.conn.transaction()?;
+        insert_headers(&transaction, headers)?;
+        transaction.commit()?;
+        Ok(())
+    }
+
+    /// Replaces the stored headers from `height` up with `headers`, all or
+    /// nothing. Transactions proven to be in the blocks replaced are
+    /// dropped, and watched addresses are scanned again from `height`.
+    pub fn replace_headers(&mut self, height: u64, headers: &[BlockHeader]) -> Result<()> {
+        let height = height.min(MAX_HEIGHT);
+        self.cache.clear_blocks();
+        let transaction = self.conn.transaction()?;
+        transaction.execute(
+            "DELETE FROM transactions WHERE block_height >= ?1",
+            rusqlite::params![height],
+        )?;
+        transaction.execute(
+            "DELETE FROM blocks WHERE height >= ?1",
+            rusqlite::params![height],
+        )?;
+        transaction.execute(
+            "UPDATE watched_addresses SET scanned_height = MIN(scanned_height, ?1)",
+            rusqlite::params![height],
+        )?;
+        insert_headers(&transaction, headers)?;
+        transaction.commit()?;
+        Ok(())
+    }
+
+    /// Starts watching `address`, from the first block. Returns whether it
+    /// was not watched already.
+    pub fn watch_address(&self, address: &Address) -> Result<bool> {
+        let added = self.conn.execute(
+            "INSERT OR IGNORE INTO watched_addresses (address) VALUES (?1)",
+            rusqlite::params![address],
+        )?;
+        Ok(added > 0)
+    }
+
+    /// Every watched address, with the height its transactions have been
+    /// found up to, exclusive.
+    pub fn get_watched_addresses(&self) -> Result<Vec<(Address, u64)>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT address, scanned_height FROM watched_addresses ORDER BY address")?;
+        let watched = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(watched)
+    }
+
+    /// Registers `url` for notifications about `address`, signed with
+    /// `secret`. Registering the same pair again changes nothing and returns
+    /// the existing webhook.
+    pub fn add_webhook(&self, address: &Address, url: &str, secret: &[u8; 32]) -> Result<Webhook> {
+        self.conn.execute(
+            "INSERT OR IGNORE INTO webhooks (address, url, secret) VALUES (?1, ?2, ?3)",
+            rusqlite::params![address, url, secret],
+        )?;
+        self.conn.query_row(
+            "SELECT id, address, url, secret FROM webhooks WHERE address = ?1 AND url = ?2",
+            rusqlite::params![address, url],
+            webhook_from_row,
+        )
+    }
+
+    pub fn get_webhooks(&self) -> Result<Vec<Webhook>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT id, address, url, secret FROM webhooks ORDER BY id")?;
+        let webhooks = stmt
+            .query_map([], webhook_from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(webhooks)
+    }
+
+    /// The webhooks registered for any of `addresses`.
+    pub fn get_webhooks_for(&self, addresses: &[Address]) -> Result<Vec<Webhook>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, address, url, secret FROM webhooks WHERE address = ?1 ORDER BY id",
+        )?;
+        let mut webhooks = Vec::new();
+        for address in addresses {
+            for webhook in stmt.query_map([address], webhook_from_row)? {
+                webhooks.push(webhook?);
+            }
+        }
+        Ok(webhooks)
+    }
+
+    /// Returns whether the webhook existed.
+    pub fn delete_webhook(&self, id: u64) -> Result<bool> {
+        let deleted = self
+            .conn
+            .execute("DELETE FROM webhooks WHERE id = ?1", [id])?;
+        Ok(deleted > 0)
+    }
+
+    /// Stores transactions proven to be in stored blocks, skipping any
+    /// stored already, and records that `addresses` have been scanned up to
+    /// `scanned_to`. All or nothing. Returns the number stored.
+    pub fn add_proven_transactions(
+        &mut self,
+        proven: &[(Transaction, TransactionLocation)],
+        addresses: &[Address],
+        scanned_to: u64,
+    ) -> Result<usize> {
+        let transaction = self.conn.transaction()?;
+        let mut added = 0;
+        for (tx, location) in proven {
+            added += transaction.execute(
+                "INSERT INTO transactions (tx_hash, receiver, amount, fee, nonce, sender_public_key, signature, verified, block_hash, block_height, tx_index, kind, data)
+                 SELECT ?1, ?2, ?3, ?4, ?5, ?6, ?7, 1, ?8, ?9, ?10, ?11, ?12
+                 WHERE NOT EXISTS (SELECT 1 FROM transactions WHERE tx_hash = ?1)",
+                rusqlite::params![
+                    tx.hash(),
+                    tx.payload.receiver,
+                    tx.payload.amount,
+                    tx.payload.fee,
+                    tx.payload.nonce,
+                    tx.sender_public_key,
+                    tx.signature,
+                    location.block_hash,
+                    location.height,
+                    location.index,
+                    tx.payload.kind,
+                    tx.payload.data,
+                ],
+            )?;
+            // There are no accounts to look a sender's key up in, so it is
+            // kept for when the address's transactions are listed.
+            transaction.execute(
+                "UPDATE watched_addresses SET public_key = ?1 WHERE address = ?2",
+                rusqlite::params![tx.sender_public_key, tx.sender_address()],
+            )?;
+        }
+        for address in addresses {
+            transaction.execute(
+                "UPDATE watched_addresses SET scanned_height = MAX(scanned_height, ?1)
+                 WHERE address = ?2",
+                rusqlite::params![scanned_to.min(MAX_HEIGHT), address],
+            )?;
+        }
+        transaction.commit()?;
+        Ok(added)
+    }
+
+    /// Lowest height from which every block is stored whole, with its
+    /// transactions.
+    pub fn get_oldest_block(&self) -> Result<u64> {
+        oldest_block(&self.conn)
+    }
+
+    /// Drops the transactions and undo records of blocks below `height`,
+    /// keeping their headers and the current state. Such blocks can no
+    /// longer be served to peers or reverted. Returns the number of
+    /// transactions dropped.
+    pub fn prune_blocks(&mut self, height: u64) -> Result<usize> {
+        let height = height.min(MAX_HEIGHT);
+        let transaction = self.conn.transaction()?;
+        if height <= oldest_block(&transaction)? {
+            return Ok(0);
+        }
+
+        // Nonces are otherwise read off the stored transactions, and must
+        // not go back to where they could be replayed.
+        transaction.execute(
+            "INSERT INTO account_nonces (public_key, next_nonce)
+             SELECT sender_public_key, MAX(nonce) + 1 FROM transactions
+             WHERE block_height < ?1 GROUP BY sender_public_key
+             ON CONFLICT(public_key) DO UPDATE SET next_nonce = MAX(next_nonce, excluded.next_nonce)",
+            rusqlite::params![height],
+        )?;
+        let pruned = transaction.execute(
+            "DELETE FROM transactions WHERE block_height < ?1",
+            rusqlite::params![height],
+        )?;
+        transaction.execute(
+            "DELETE FROM block_undo WHERE block_hash IN (SELECT hash FROM blocks WHERE height < ?1)",
+            rusqlite::params![height],
+        )?;
+        set_oldest_block(&transaction, height)?;
+
+        transaction.commit()?;
+        Ok(pruned)
+    }
+
+    /// Rebuilds what is derived from the stored blocks: each confirmed
+    /// transaction's height and position, undo records of blocks that are
+    /// gone, and every index. Returns the number of transactions placed.
+    pub fn reindex(&mut self) -> Result<usize> {
+        let transaction = self.conn.transaction()?;
+        let placed = transaction.execute(TRANSACTION_POSITIONS, [])?;
+        transaction.execute(
+            "DELETE FROM block_undo WHERE block_hash NOT IN (SELECT hash FROM blocks)",
+            [],
+        )?;
+        transaction.execute_batch("REINDEX")?;
+        transaction.commit()?;
+        Ok(placed)
+    }
+
+    pub fn update_user(&self, user: &User) -> Result<()> {
+        self.cache.accounts.borrow_mut().pop(&user.address);
+        self.conn.execute(
+            "UPDATE users SET balance = ?1, stake = ?2, delegate = ?3, commission = ?4,
+                 missed_slots = ?5, jailed = ?6
+             WHERE address = ?7",
+            rusqlite::params![
+                user.balance,
+                user.stake,
+                user.delegate,
+                user.commission_bps,
+                user.missed_slots,
+                user.jailed,
+                user.address
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// The metadata stored under `key`, as text.
+    pub fn get_metadata(&self, key: &str) -> Result<Option<String>> {
+        self.conn
+            .query_row(
+                "SELECT CAST(value AS TEXT) FROM metadata WHERE key = ?1",
+                [key],
+                |row| row.get(0),
+            )
+            .optional()
+    }
+
+    pub fn set_metadata(&self, key: &str, value: &str) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO metadata (key, value) VALUES (?1, ?2)
+             ON CONFLICT(key) DO UPDATE SET value = excluded.value",
+            rusqlite::params![key, value],
+        )?;
+        Ok(())
+    }
+
+    /// Records that `amount` left the supply for good.
+    pub fn record_burn(&self, amount: Amount) -> Result<()> {
+        add_to_metadata(&self.conn, "burned", amount)
+    }
+
+    /// What makes up the supply, see [`Supply`].
+    pub fn get_supply(&self) -> Result<Supply> {
+        Ok(Supply {
+            allocated: metadata_amount(&self.conn, "allocated")?,
+            emitted: emitted(&self.conn)?,
+            burned: metadata_amount(&self.conn, "burned")?
+                .checked_add(fees_burned(&self.conn)?)
+                .map_err(amount_error)?,
+            circulating: holdings(&self.conn)?,
+        })
+    }
+
+    /// The `limit` accounts holding the most, by balance or by stake,
+    /// richest first.
+    pub fn get_top_accounts(&self, by_stake: bool, limit: usize) -> Result<Vec<User>> {
+        // Amounts are decimal text without leading zeros, so longer is
+        // larger and equally long ones compare as text; both indexed.
+        let column = if by_stake { "stake" } else { "balance" };
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {1} FROM users
+             ORDER BY length({0}) DESC, {0} DESC, address LIMIT ?1",
+            column, USER_COLUMNS
+        ))?;
+        let users = stmt
+            .query_map([limit], user_from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(users)
+    }
+
+    pub fn delete_user(&self, address: &[u8]) -> Result<()> {
+        if let Ok(address) = Address::try_from(address) {
+            self.cache.accounts.borrow_mut().pop(&address);
+        }
+        self.conn.execute(
             "DELETE FROM users WHERE address = ?1",
             rusqlite::params![address],
         )?;
         Ok(())
     }
 
-    pub fn get_total_stake(&self) -> Result<u64> {
-        let mut stmt = self.conn.prepare("SELECT SUM(stake) FROM users")?;
-        let total_stake: u64 = stmt.query_row([], |row| row.get(0))?;
-        Ok(total_stake)
+    pub fn get_total_stake(&self) -> Result<Amount> {
+        total_stake(&self.conn)
+    }
+
+    /// The ID the next proposal included will get.
+    pub fn next_proposal_id(&self) -> Result<u64> {
+        self.conn.query_row(
+            "SELECT COALESCE(MAX(id), 0) + 1 FROM proposals",
+            [],
+            |row| row.get(0),
+        )
+    }
+
+    pub fn get_proposal(&self, id: u64) -> Result<Option<Proposal>> {
+        self.conn
+            .query_row(
+                &format!("SELECT {} FROM proposals WHERE id = ?1", PROPOSAL_COLUMNS),
+                [id],
+                proposal_from_row,
+            )
+            .optional()
+    }
+
+    /// Every proposal, newest first.
+    pub fn get_proposals(&self) -> Result<Vec<Proposal>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {} FROM proposals ORDER BY id DESC",
+            PROPOSAL_COLUMNS
+        ))?;
+        let proposals = stmt
+            .query_map([], proposal_from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(proposals)
+    }
+
+    pub fn has_voted(&self, proposal: u64, voter: &Address) -> Result<bool> {
+        self.conn
+            .query_row(
+                "SELECT 1 FROM proposal_votes WHERE proposal = ?1 AND voter = ?2",
+                rusqlite::params![proposal, voter],
+                |_| Ok(()),
+            )
+            .optional()
+            .map(|found| found.is_some())
+    }
+
+    /// The stake behind each side of a proposal as it stands now.
+    pub fn get_tally(&self, proposal: u64) -> Result<Tally> {
+        tally(&self.conn, proposal)
+    }
+
+    /// The governed parameters in effect at `height`: the value of each
+    /// accepted proposal taking effect by then, later ones overriding
+    /// earlier ones.
+    pub fn get_parameters(&self, height: u64) -> Result<Parameters> {
+        let mut stmt = self.conn.prepare(
+            "SELECT parameter, value FROM proposals
+             WHERE accepted = 1 AND activation_height <= ?1
+             ORDER BY activation_height, id",
+        )?;
+        let mut parameters = Parameters::default();
+        let mut rows = stmt.query([height])?;
+        while let Some(row) = rows.next()? {
+            let (parameter, value) = parameter_from_row(row, 0)?;
+            parameters.apply(parameter, value);
+        }
+        Ok(parameters)
+    }
+
+    /// The treasury grants accepted to be paid at `height`, each as its
+    /// proposer and amount, in the order they were proposed.
+    pub fn get_grants(&self, height: u64) -> Result<Vec<(Address, Amount)>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT proposer, parameter, value FROM proposals
+             WHERE accepted = 1 AND activation_height = ?1 AND parameter = ?2
+             ORDER BY id",
+        )?;
+        let grants = stmt
+            .query_map(
+                rusqlite::params![height, Parameter::TreasuryGrant.code()],
+                |row| {
+                    let (_, value) = parameter_from_row(row, 1)?;
+                    Ok((row.get(0)?, Amount::from_base_units(value)))
+                },
+            )?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(grants)
+    }
+
+    /// The consensus key `address` signs blocks with at `height`: the last
+    /// it registered or rotated to below that height, if any.
+    pub fn get_validator_key(&self, address: &Address, height: u64) -> Result<Option<[u8; 32]>> {
+        self.conn
+            .query_row(
+                "SELECT public_key FROM validator_keys
+                 WHERE address = ?1 AND height < ?2
+                 ORDER BY height DESC, position DESC LIMIT 1",
+                rusqlite::params![address, clamp(height)],
+                |row| row.get(0),
+            )
+            .optional()
+    }
+
+    /// Each registered validator's current consensus key.
+    pub fn get_validator_keys(&self) -> Result<HashMap<Address, [u8; 32]>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT address, public_key FROM validator_keys ORDER BY height, position")?;
+        let keys = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<HashMap<_, _>, _>>()?;
+        Ok(keys)
+    }
+
+    /// The account that registered `key` as a consensus key, whether or
+    /// not it has rotated away from it since.
+    pub fn get_key_owner(&self, key: &[u8; 32]) -> Result<Option<Address>> {
+        self.conn
+            .query_row(
+                "SELECT address FROM validator_keys WHERE public_key = ?1 LIMIT 1",
+                [key],
+                |row| row.get(0),
+            )
+            .optional()
+    }
+
+    /// The commitment `address` made to a beacon secret in a block at one
+    /// of `heights`, if any.
+    pub fn get_commitment(&self, address: &Address, heights: Range<u64>) -> Result<Option<Hash>> {
+        self.conn
+            .query_row(
+                "SELECT commitment FROM beacon_commitments
+                 WHERE address = ?1 AND height >= ?2 AND height < ?3 LIMIT 1",
+                rusqlite::params![address, clamp(heights.start), clamp(heights.end)],
+                |row| row.get(0),
+            )
+            .optional()
+    }
+
+    /// Every commitment made to a beacon secret in a block at one of
+    /// `heights`, ordered by address.
+    pub fn get_commitments(&self, heights: Range<u64>) -> Result<Vec<(Address, Hash)>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT address, commitment FROM beacon_commitments
+             WHERE height >= ?1 AND height < ?2 ORDER BY address",
+        )?;
+        let commitments = stmt
+            .query_map(
+                rusqlite::params![clamp(heights.start), clamp(heights.end)],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(commitments)
+    }
+
+    /// Every beacon secret revealed in a block at one of `heights`, with
+    /// the account that revealed it, ordered by address.
+    pub fn get_reveals(&self, heights: Range<u64>) -> Result<Vec<(Address, Hash)>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT address, secret FROM beacon_reveals
+             WHERE height >= ?1 AND height < ?2 ORDER BY address",
+        )?;
+        let reveals = stmt
+            .query_map(
+                rusqlite::params![clamp(heights.start), clamp(heights.end)],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(reveals)
+    }
+
+    /// Whether `address` revealed a beacon secret in a block at one of
+    /// `heights`.
+    pub fn has_revealed(&self, address: &Address, heights: Range<u64>) -> Result<bool> {
+        self.conn
+            .query_row(
+                "SELECT 1 FROM beacon_reveals
+                 WHERE address = ?1 AND height >= ?2 AND height < ?3",
+                rusqlite::params![address, clamp(heights.start), clamp(heights.end)],
+                |_| Ok(()),
+            )
+            .optional()
+            .map(|found| found.is_some())
+    }
+
+    /// Everything in circulation: the sum of all balances and stakes.
+    pub fn get_total_supply(&self) -> Result<Amount> {
+        holdings(&self.conn)
     }
 
     pub fn close(self) -> Result<(), rusqlite::Error> {
@@ -348,3 +1621,852 @@ impl Database {
         }
     }
 }
+
+/// Opens a connection in WAL mode, where readers and the writer do not block
+/// each other, waiting out other connections' writes instead of failing.
+/// Directory the node keeps its data in unless given another.
+pub fn default_data_dir() -> PathBuf {
+    dirs::home_dir().unwrap().join(".smvblock")
+}
+
+/// Where a node on the chain `chain_id` keeps its database under
+/// `data_dir`. Each chain gets a directory of its own, so a devnet and the
+/// main network never share blocks, peers or the node identity.
+pub fn database_path(data_dir: &Path, chain_id: &str) -> Result<PathBuf, String> {
+    let mut components = Path::new(chain_id).components();
+    match (components.next(), components.next()) {
+        (Some(std::path::Component::Normal(_)), None) => {}
+        _ => {
+            return Err(format!(
+                "Chain ID {} is not usable as a directory",
+                chain_id
+            ));
+        }
+    }
+    Ok(data_dir.join(chain_id).join("chain.db"))
+}
+
+fn open(path: &Path) -> Result<Connection> {
+    let conn = Connection::open(path)?;
+    conn.pragma_update_and_check(None, "journal_mode", "WAL", |row| row.get::<_, String>(0))?;
+    // Durable at every checkpoint; a crash loses at most the last commits.
+    conn.pragma_update(None, "synchronous", "NORMAL")?;
+    conn.busy_timeout(BUSY_TIMEOUT)?;
+    Ok(conn)
+}
+
+/// Schema changes in the order they were made. Each runs once per database,
+/// in its own transaction, and is recorded in `schema_migrations` by its
+/// position in this list, starting at 1. Only append to it.
+const MIGRATIONS: &[fn(&rusqlite::Transaction) -> Result<()>] = &[
+    create_tables,
+    add_late_columns,
+    index_blocks,
+    index_transactions,
+    index_block_undo,
+    create_metadata,
+    create_watched_addresses,
+    add_transaction_kinds,
+    create_webhooks,
+    track_supply,
+    create_proposals,
+    route_fees,
+    add_delegation,
+    track_liveness,
+    create_validator_keys,
+    create_beacon,
+    unique_transactions,
+    add_transaction_data,
+    rehash_transactions,
+];
+
+/// A height as SQLite stores it, the greatest it can for those beyond.
+fn clamp(height: u64) -> u64 {
+    height.min(i64::MAX as u64)
+}
+
+/// Brings the schema up to date, refusing databases written by a newer
+/// version of the node.
+fn migrate(conn: &mut Connection) -> Result<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS schema_migrations (
+            version INTEGER PRIMARY KEY,
+            applied_at INTEGER NOT NULL
+        )",
+        [],
+    )?;
+    for (index, migration) in MIGRATIONS.iter().enumerate() {
+        let version = index as u32 + 1;
+        // Taking the write lock first keeps two processes opening the same
+        // file from applying a migration twice.
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        if schema_version(&tx)? >= version {
+            continue;
+        }
+        migration(&tx)?;
+        tx.execute(
+            "INSERT INTO schema_migrations (version, applied_at) VALUES (?1, ?2)",
+            rusqlite::params![version, Utc::now().timestamp()],
+        )?;
+        tx.commit()?;
+    }
+
+    let version = schema_version(conn)?;
+    if version as usize > MIGRATIONS.len() {
+        return Err(rusqlite::Error::SqliteFailure(
+            rusqlite::ffi::Error::new(rusqlite::ffi::SQLITE_ERROR),
+            Some(format!(
+                "Database schema version {} is newer than this node supports ({})",
+                version,
+                MIGRATIONS.len()
+            )),
+        ));
+    }
+    Ok(())
+}
+
+fn schema_version(conn: &Connection) -> Result<u32> {
+    conn.query_row(
+        "SELECT COALESCE(MAX(version), 0) FROM schema_migrations",
+        [],
+        |row| row.get(0),
+    )
+}
+
+fn create_tables(tx: &rusqlite::Transaction) -> Result<()> {
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS users (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            address BLOB NOT NULL,
+            public_key BLOB NOT NULL,
+            balance TEXT NOT NULL,
+            stake TEXT NOT NULL
+        )",
+        [],
+    )?;
+
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS blocks (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            hash BLOB NOT NULL,
+            previous_hash BLOB NOT NULL,
+            merkle_root BLOB NOT NULL,
+            state_root BLOB NOT NULL,
+            timestamp INTEGER NOT NULL,
+            height INTEGER NOT NULL,
+            proposer BLOB NOT NULL,
+            coinbase TEXT NOT NULL,
+            finalized_hash BLOB NOT NULL,
+            signature BLOB NOT NULL DEFAULT (zeroblob(64)),
+            finalized BOOLEAN NOT NULL DEFAULT 0
+        )",
+        [],
+    )?;
+
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS transactions (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            tx_hash BLOB NOT NULL,
+            receiver BLOB NOT NULL,
+            amount TEXT NOT NULL,
+            fee TEXT NOT NULL DEFAULT '0',
+            nonce INTEGER NOT NULL,
+            sender_public_key BLOB NOT NULL,
+            signature BLOB NOT NULL,
+            verified BOOLEAN NOT NULL,
+            block_hash BLOB
+        )",
+        [],
+    )?;
+
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS mempool (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            receiver BLOB NOT NULL,
+            amount TEXT NOT NULL,
+            fee TEXT NOT NULL,
+            nonce INTEGER NOT NULL,
+            sender_public_key BLOB NOT NULL,
+            signature BLOB NOT NULL
+        )",
+        [],
+    )?;
+
+    // Each block's accounts as they were before it, so it can be undone.
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS block_undo (
+            block_hash BLOB NOT NULL,
+            address BLOB NOT NULL,
+            balance TEXT NOT NULL,
+            stake TEXT NOT NULL
+        )",
+        [],
+    )?;
+
+    // Nonces carried over from a snapshot, for accounts whose earlier
+    // transactions this node never stored.
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS account_nonces (
+            public_key BLOB PRIMARY KEY,
+            next_nonce INTEGER NOT NULL
+        )",
+        [],
+    )?;
+
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS peers (
+            address TEXT PRIMARY KEY,
+            node_type TEXT,
+            node_id BLOB,
+            last_seen INTEGER NOT NULL,
+            successes INTEGER NOT NULL,
+            failures INTEGER NOT NULL
+        )",
+        [],
+    )?;
+
+    // The key this node identifies itself to peers with, generated on
+    // first start.
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS node_identity (
+            id INTEGER PRIMARY KEY CHECK (id = 1),
+            secret_key BLOB NOT NULL
+        )",
+        [],
+    )?;
+    Ok(())
+}
+
+/// Columns added before migrations existed, for databases created back
+/// then; the tables `create_tables` makes already have them.
+fn add_late_columns(tx: &rusqlite::Transaction) -> Result<()> {
+    add_column(tx, "transactions", "fee", "TEXT NOT NULL DEFAULT '0'")?;
+    add_column(tx, "transactions", "block_hash", "BLOB")?;
+    add_column(
+        tx,
+        "blocks",
+        "signature",
+        "BLOB NOT NULL DEFAULT (zeroblob(64))",
+    )?;
+    add_column(tx, "blocks", "finalized", "BOOLEAN NOT NULL DEFAULT 0")?;
+    add_column(tx, "peers", "node_id", "BLOB")
+}
+
+fn index_blocks(tx: &rusqlite::Transaction) -> Result<()> {
+    tx.execute_batch(
+        "CREATE INDEX IF NOT EXISTS blocks_hash ON blocks (hash);
+         CREATE INDEX IF NOT EXISTS blocks_height ON blocks (height);",
+    )
+}
+
+/// Sets each confirmed transaction's height and position from its block.
+/// Transactions are stored in block order.
+const TRANSACTION_POSITIONS: &str = "UPDATE transactions SET
+     block_height = (SELECT height FROM blocks WHERE blocks.hash = transactions.block_hash),
+     tx_index = (SELECT COUNT(*) FROM transactions AS earlier
+                 WHERE earlier.block_hash = transactions.block_hash
+                   AND earlier.id < transactions.id)
+ WHERE block_hash IS NOT NULL";
+
+/// Records where each confirmed transaction sits and indexes transactions
+/// by hash, account and block.
+fn index_transactions(tx: &rusqlite::Transaction) -> Result<()> {
+    add_column(tx, "transactions", "block_height", "INTEGER")?;
+    add_column(tx, "transactions", "tx_index", "INTEGER")?;
+    tx.execute(TRANSACTION_POSITIONS, [])?;
+    tx.execute_batch(
+        "CREATE INDEX IF NOT EXISTS transactions_hash ON transactions (tx_hash);
+         CREATE INDEX IF NOT EXISTS transactions_sender ON transactions (sender_public_key);
+         CREATE INDEX IF NOT EXISTS transactions_receiver ON transactions (receiver);
+         CREATE INDEX IF NOT EXISTS transactions_block ON transactions (block_hash);",
+    )
+}
+
+fn index_block_undo(tx: &rusqlite::Transaction) -> Result<()> {
+    tx.execute_batch(
+        "CREATE INDEX IF NOT EXISTS block_undo_address ON block_undo (address);
+         CREATE INDEX IF NOT EXISTS block_undo_block ON block_undo (block_hash);",
+    )
+}
+
+fn create_metadata(tx: &rusqlite::Transaction) -> Result<()> {
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS metadata (
+            key TEXT PRIMARY KEY,
+            value
+        )",
+        [],
+    )?;
+    Ok(())
+}
+
+/// Addresses a node without blocks follows the transactions of. The public
+/// key is learned from the first transaction the address is found to send.
+fn create_watched_addresses(tx: &rusqlite::Transaction) -> Result<()> {
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS watched_addresses (
+            address BLOB PRIMARY KEY,
+            public_key BLOB,
+            scanned_height INTEGER NOT NULL DEFAULT 0
+        )",
+        [],
+    )?;
+    Ok(())
+}
+
+/// What each transaction does, as a [`TxKind`](crate::blockchain::TxKind). Those stored before
+/// staking went through transactions were all transfers.
+fn add_transaction_kinds(tx: &rusqlite::Transaction) -> Result<()> {
+    add_column(tx, "transactions", "kind", "INTEGER NOT NULL DEFAULT 0")?;
+    add_column(tx, "mempool", "kind", "INTEGER NOT NULL DEFAULT 0")
+}
+
+/// Callback URLs told about activity on an address, each with the secret
+/// its notifications are signed with.
+fn create_webhooks(tx: &rusqlite::Transaction) -> Result<()> {
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS webhooks (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            address BLOB NOT NULL,
+            url TEXT NOT NULL,
+            secret BLOB NOT NULL,
+            UNIQUE (address, url)
+        )",
+        [],
+    )?;
+    Ok(())
+}
+
+/// Indexes accounts by what they hold, and records what accounts were
+/// allocated directly so issuance can be audited. Nothing was burned
+/// before, so the allocation is whatever coinbase does not account for.
+fn track_supply(tx: &rusqlite::Transaction) -> Result<()> {
+    tx.execute_batch(
+        "CREATE INDEX IF NOT EXISTS users_balance ON users (length(balance), balance);
+         CREATE INDEX IF NOT EXISTS users_stake ON users (length(stake), stake);",
+    )?;
+    let allocated = holdings(tx)?.saturating_sub(emitted(tx)?);
+    set_metadata_amount(tx, "allocated", allocated)
+}
+
+/// Parameter changes proposed on chain, each decided once the block before
+/// its activation height is applied, and the stakers' votes on them. Both
+/// are removed with the block that included them.
+fn create_proposals(tx: &rusqlite::Transaction) -> Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE IF NOT EXISTS proposals (
+            id INTEGER PRIMARY KEY,
+            block_hash BLOB NOT NULL,
+            height INTEGER NOT NULL,
+            proposer BLOB NOT NULL,
+            parameter INTEGER NOT NULL,
+            value TEXT NOT NULL,
+            activation_height INTEGER NOT NULL,
+            accepted INTEGER
+        );
+        CREATE INDEX IF NOT EXISTS proposals_activation ON proposals (activation_height);
+        CREATE TABLE IF NOT EXISTS proposal_votes (
+            proposal INTEGER NOT NULL,
+            voter BLOB NOT NULL,
+            approve INTEGER NOT NULL,
+            block_hash BLOB NOT NULL,
+            PRIMARY KEY (proposal, voter)
+        );
+        CREATE INDEX IF NOT EXISTS proposal_votes_block ON proposal_votes (block_hash);",
+    )
+}
+
+/// Records the fees each block burned, and marks the undo records of
+/// accounts a block created, which undoing it deletes.
+fn route_fees(tx: &rusqlite::Transaction) -> Result<()> {
+    add_column(tx, "blocks", "burned", "TEXT NOT NULL DEFAULT '0'")?;
+    add_column(tx, "block_undo", "created", "INTEGER NOT NULL DEFAULT 0")
+}
+
+/// Who each account delegates its stake to and the commission each
+/// validator charges, kept in undo records too so reverting a block
+/// restores them.
+fn add_delegation(tx: &rusqlite::Transaction) -> Result<()> {
+    add_column(tx, "users", "delegate", "BLOB")?;
+    add_column(tx, "users", "commission", "INTEGER NOT NULL DEFAULT 0")?;
+    add_column(tx, "block_undo", "delegate", "BLOB")?;
+    add_column(tx, "block_undo", "commission", "INTEGER NOT NULL DEFAULT 0")?;
+    tx.execute_batch("CREATE INDEX IF NOT EXISTS users_delegate ON users (delegate);")
+}
+
+/// The slots each validator missed this epoch and whether it is jailed for
+/// missing too many, kept in undo records too.
+fn track_liveness(tx: &rusqlite::Transaction) -> Result<()> {
+    add_column(tx, "users", "missed_slots", "INTEGER NOT NULL DEFAULT 0")?;
+    add_column(tx, "users", "jailed", "INTEGER NOT NULL DEFAULT 0")?;
+    add_column(
+        tx,
+        "block_undo",
+        "missed_slots",
+        "INTEGER NOT NULL DEFAULT 0",
+    )?;
+    add_column(tx, "block_undo", "jailed", "INTEGER NOT NULL DEFAULT 0")
+}
+
+/// The consensus keys validators registered or rotated to, each with the
+/// height and position of the transaction that did, so a block is checked
+/// against the key in effect when it was signed. Removed with the block
+/// that included them.
+fn create_validator_keys(tx: &rusqlite::Transaction) -> Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE IF NOT EXISTS validator_keys (
+            address BLOB NOT NULL,
+            public_key BLOB NOT NULL,
+            height INTEGER NOT NULL,
+            position INTEGER NOT NULL,
+            block_hash BLOB NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS validator_keys_address ON validator_keys (address, height);
+        CREATE INDEX IF NOT EXISTS validator_keys_key ON validator_keys (public_key);
+        CREATE INDEX IF NOT EXISTS validator_keys_block ON validator_keys (block_hash);",
+    )
+}
+
+/// The commitments validators made to beacon secrets and the secrets they
+/// revealed, each with the height of the block that included it. Both are
+/// removed with that block.
+fn create_beacon(tx: &rusqlite::Transaction) -> Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE IF NOT EXISTS beacon_commitments (
+            address BLOB NOT NULL,
+            height INTEGER NOT NULL,
+            commitment BLOB NOT NULL,
+            block_hash BLOB NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS beacon_commitments_height ON beacon_commitments (height);
+        CREATE INDEX IF NOT EXISTS beacon_commitments_block ON beacon_commitments (block_hash);
+        CREATE TABLE IF NOT EXISTS beacon_reveals (
+            address BLOB NOT NULL,
+            height INTEGER NOT NULL,
+            secret BLOB NOT NULL,
+            block_hash BLOB NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS beacon_reveals_height ON beacon_reveals (height);
+        CREATE INDEX IF NOT EXISTS beacon_reveals_block ON beacon_reveals (block_hash);",
+    )
+}
+
+/// Makes a transaction hash unique across the table, so a transaction is
+/// stored at most once. Of copies stored before, the first confirmed one
+/// is kept, or failing that the first stored.
+fn unique_transactions(tx: &rusqlite::Transaction) -> Result<()> {
+    tx.execute_batch(
+        "DELETE FROM transactions WHERE EXISTS (
+             SELECT 1 FROM transactions AS kept
+             WHERE kept.tx_hash = transactions.tx_hash
+               AND ((kept.block_hash IS NULL) < (transactions.block_hash IS NULL)
+                    OR ((kept.block_hash IS NULL) = (transactions.block_hash IS NULL)
+                        AND kept.id < transactions.id)));
+         DROP INDEX IF EXISTS transactions_hash;
+         CREATE UNIQUE INDEX IF NOT EXISTS transactions_hash ON transactions (tx_hash);",
+    )
+}
+
+/// What proposals, votes, commissions, registrations, key rotations and
+/// beacon commitments and reveals say, in place of the receiver and amount
+/// they were packed into before; see [`TxData`]. Rows stored before have
+/// none.
+fn add_transaction_data(tx: &rusqlite::Transaction) -> Result<()> {
+    add_column(tx, "transactions", "data", "BLOB")?;
+    add_column(tx, "mempool", "data", "BLOB")
+}
+
+/// Recomputes every stored transaction's hash now that it covers the
+/// sender's key as well as the payload.
+fn rehash_transactions(tx: &rusqlite::Transaction) -> Result<()> {
+    let rows = {
+        let mut stmt = tx.prepare(
+            "SELECT receiver, amount, fee, nonce, sender_public_key, signature, kind, data, id FROM transactions",
+        )?;
+        stmt.query_map([], |row| {
+            Ok((transaction_from_row(row)?, row.get::<_, i64>(8)?))
+        })?
+        .collect::<Result<Vec<_>>>()?
+    };
+    let mut update = tx.prepare("UPDATE transactions SET tx_hash = ?1 WHERE id = ?2")?;
+    for (transaction, id) in rows {
+        update.execute(rusqlite::params![transaction.hash(), id])?;
+    }
+    Ok(())
+}
+
+/// Stores the beacon commitments and reveals `block` makes.
+fn record_beacon(conn: &Connection, block: &Block) -> Result<()> {
+    let hash = block.hash();
+    for tx in &block.transactions {
+        let (table, value) = match tx.payload.data {
+            TxData::Commit(commit) => (
+                "beacon_commitments (address, height, commitment, block_hash)",
+                commit.commitment,
+            ),
+            TxData::Secret(secret) => (
+                "beacon_reveals (address, height, secret, block_hash)",
+                secret,
+            ),
+            _ => continue,
+        };
+        conn.execute(
+            &format!("INSERT INTO {} VALUES (?1, ?2, ?3, ?4)", table),
+            rusqlite::params![tx.sender_address(), block.header.height, value, hash],
+        )?;
+    }
+    Ok(())
+}
+
+/// Stores the consensus keys `block` registers or rotates to.
+fn record_validator_keys(conn: &Connection, block: &Block) -> Result<()> {
+    let hash = block.hash();
+    for (position, tx) in block.transactions.iter().enumerate() {
+        if let Some(key) = tx.payload.data.consensus_key() {
+            conn.execute(
+                "INSERT INTO validator_keys (address, public_key, height, position, block_hash)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+                rusqlite::params![
+                    tx.sender_address(),
+                    key,
+                    block.header.height,
+                    position as u32,
+                    hash
+                ],
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Stores the proposals and votes `block` makes, then decides the
+/// proposals taking effect at the next height by the stake behind them
+/// once the block is applied.
+fn record_governance(conn: &Connection, block: &Block) -> Result<()> {
+    let hash = block.hash();
+    for tx in &block.transactions {
+        match tx.payload.data {
+            TxData::Proposal(change) => {
+                conn.execute(
+                    "INSERT INTO proposals (id, block_hash, height, proposer, parameter, value, activation_height)
+                     VALUES ((SELECT COALESCE(MAX(id), 0) + 1 FROM proposals), ?1, ?2, ?3, ?4, ?5, ?6)",
+                    rusqlite::params![
+                        hash,
+                        block.header.height,
+                        tx.sender_address(),
+                        change.parameter.code(),
+                        change.value.to_string(),
+                        change.activation_height,
+                    ],
+                )?;
+            }
+            TxData::Ballot(ballot) => {
+                conn.execute(
+                    "INSERT INTO proposal_votes (proposal, voter, approve, block_hash)
+                     VALUES (?1, ?2, ?3, ?4)",
+                    rusqlite::params![ballot.proposal, tx.sender_address(), ballot.approve, hash],
+                )?;
+            }
+            TxData::None
+            | TxData::Commission(_)
+            | TxData::Registration(_)
+            | TxData::ConsensusKey(_)
+            | TxData::Commit(_)
+            | TxData::Secret(_) => {}
+        }
+    }
+
+    let mut stmt = conn.prepare("SELECT id FROM proposals WHERE activation_height = ?1")?;
+    let deciding = stmt
+        .query_map([block.header.height + 1], |row| row.get::<_, u64>(0))?
+        .collect::<Result<Vec<_>, _>>()?;
+    for id in deciding {
+        let accepted = tally(conn, id)?.passes();
+        conn.execute(
+            "UPDATE proposals SET accepted = ?1 WHERE id = ?2",
+            rusqlite::params![accepted, id],
+        )?;
+    }
+    Ok(())
+}
+
+fn tally(conn: &Connection, proposal: u64) -> Result<Tally> {
+    let mut stmt = conn.prepare(
+        "SELECT v.approve, u.stake FROM proposal_votes v
+         JOIN users u ON u.address = v.voter
+         WHERE v.proposal = ?1",
+    )?;
+    let votes = stmt
+        .query_map([proposal], |row| {
+            Ok((row.get::<_, bool>(0)?, row.get::<_, Amount>(1)?))
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+    let side = |approve: bool| {
+        Amount::checked_sum(
+            votes
+                .iter()
+                .filter(|(vote, _)| *vote == approve)
+                .map(|(_, stake)| *stake),
+        )
+        .map_err(amount_error)
+    };
+    Ok(Tally {
+        approve: side(true)?,
+        reject: side(false)?,
+        total: total_stake(conn)?,
+    })
+}
+
+fn total_stake(conn: &Connection) -> Result<Amount> {
+    // Amounts are stored as text, so they are summed here rather than by SQLite.
+    let mut stmt = conn.prepare("SELECT stake FROM users")?;
+    let stakes = stmt
+        .query_map([], |row| row.get::<_, Amount>(0))?
+        .collect::<Result<Vec<_>, _>>()?;
+    Amount::checked_sum(stakes).map_err(amount_error)
+}
+
+/// The sum of all balances and stakes.
+fn holdings(conn: &Connection) -> Result<Amount> {
+    let mut stmt = conn.prepare("SELECT balance, stake FROM users")?;
+    let holdings = stmt
+        .query_map([], |row| {
+            Ok([row.get::<_, Amount>(0)?, row.get::<_, Amount>(1)?])
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+    Amount::checked_sum(holdings.into_iter().flatten()).map_err(amount_error)
+}
+
+/// The coinbase of every stored block.
+fn emitted(conn: &Connection) -> Result<Amount> {
+    let mut stmt = conn.prepare("SELECT coinbase FROM blocks")?;
+    let coinbases = stmt
+        .query_map([], |row| row.get::<_, Amount>(0))?
+        .collect::<Result<Vec<_>, _>>()?;
+    Amount::checked_sum(coinbases).map_err(amount_error)
+}
+
+/// The fees burned by every stored block.
+fn fees_burned(conn: &Connection) -> Result<Amount> {
+    let mut stmt = conn.prepare("SELECT burned FROM blocks")?;
+    let burned = stmt
+        .query_map([], |row| row.get::<_, Amount>(0))?
+        .collect::<Result<Vec<_>, _>>()?;
+    Amount::checked_sum(burned).map_err(amount_error)
+}
+
+fn metadata_amount(conn: &Connection, key: &str) -> Result<Amount> {
+    let amount = conn
+        .query_row("SELECT value FROM metadata WHERE key = ?1", [key], |row| {
+            row.get(0)
+        })
+        .optional()?;
+    Ok(amount.unwrap_or(Amount::ZERO))
+}
+
+fn set_metadata_amount(conn: &Connection, key: &str, amount: Amount) -> Result<()> {
+    conn.execute(
+        "INSERT INTO metadata (key, value) VALUES (?1, ?2)
+         ON CONFLICT(key) DO UPDATE SET value = excluded.value",
+        rusqlite::params![key, amount],
+    )?;
+    Ok(())
+}
+
+fn add_to_metadata(conn: &Connection, key: &str, amount: Amount) -> Result<()> {
+    let total = metadata_amount(conn, key)?
+        .checked_add(amount)
+        .map_err(amount_error)?;
+    set_metadata_amount(conn, key, total)
+}
+
+fn amount_error(err: BlockchainError) -> rusqlite::Error {
+    rusqlite::Error::ToSqlConversionFailure(Box::new(err))
+}
+
+/// Stores headers, which must run on from the stored chain, as blocks
+/// without their transactions.
+fn insert_headers(tx: &rusqlite::Transaction, headers: &[BlockHeader]) -> Result<()> {
+    for header in headers {
+        tx.execute(
+            "INSERT INTO blocks (hash, previous_hash, merkle_root, state_root, timestamp, height, proposer, coinbase, finalized_hash, signature)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+            rusqlite::params![
+                header.hash(),
+                header.previous_hash,
+                header.merkle_root,
+                header.state_root,
+                header.timestamp,
+                header.height,
+                header.proposer,
+                header.coinbase,
+                header.finalized_hash,
+                header.signature,
+            ],
+        )?;
+    }
+    if let Some(last) = headers.last() {
+        set_oldest_block(tx, last.height + 1)?;
+    }
+    Ok(())
+}
+
+fn webhook_from_row(row: &Row) -> Result<Webhook> {
+    Ok(Webhook {
+        id: row.get(0)?,
+        address: row.get(1)?,
+        url: row.get(2)?,
+        secret: row.get(3)?,
+    })
+}
+
+fn oldest_block(conn: &Connection) -> Result<u64> {
+    let oldest: Option<u64> = conn
+        .query_row(
+            "SELECT value FROM metadata WHERE key = 'oldest_block'",
+            [],
+            |row| row.get(0),
+        )
+        .optional()?;
+    Ok(oldest.unwrap_or(0))
+}
+
+fn set_oldest_block(conn: &Connection, height: u64) -> Result<()> {
+    conn.execute(
+        "INSERT INTO metadata (key, value) VALUES ('oldest_block', ?1)
+         ON CONFLICT(key) DO UPDATE SET value = MAX(value, excluded.value)",
+        rusqlite::params![height.min(MAX_HEIGHT)],
+    )?;
+    Ok(())
+}
+
+fn add_column(
+    tx: &rusqlite::Transaction,
+    table: &str,
+    column: &str,
+    definition: &str,
+) -> Result<()> {
+    let exists: bool = tx.query_row(
+        "SELECT COUNT(*) > 0 FROM pragma_table_info(?1) WHERE name = ?2",
+        rusqlite::params![table, column],
+        |row| row.get(0),
+    )?;
+    if !exists {
+        tx.execute(
+            &format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition),
+            [],
+        )?;
+    }
+    Ok(())
+}
+
+fn insert_user(conn: &Connection, user: &User) -> Result<()> {
+    conn.execute(
+        &format!(
+            "INSERT INTO users ({}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            USER_COLUMNS
+        ),
+        rusqlite::params![
+            user.address,
+            user.public_key,
+            user.balance,
+            user.stake,
+            user.delegate,
+            user.commission_bps,
+            user.missed_slots,
+            user.jailed
+        ],
+    )?;
+    Ok(())
+}
+
+/// An account read from the columns in [`USER_COLUMNS`].
+fn user_from_row(row: &Row) -> Result<User> {
+    Ok(User {
+        address: row.get(0)?,
+        public_key: row.get(1)?,
+        balance: row.get(2)?,
+        stake: row.get(3)?,
+        delegate: row.get(4)?,
+        commission_bps: row.get(5)?,
+        missed_slots: row.get(6)?,
+        jailed: row.get(7)?,
+    })
+}
+
+fn proposal_from_row(row: &Row) -> Result<Proposal> {
+    let (parameter, value) = parameter_from_row(row, 2)?;
+    Ok(Proposal {
+        id: row.get(0)?,
+        proposer: row.get(1)?,
+        change: ParameterChange {
+            parameter,
+            value,
+            activation_height: row.get(4)?,
+        },
+        height: row.get(5)?,
+        status: match row.get::<_, Option<bool>>(6)? {
+            None => ProposalStatus::Voting,
+            Some(true) => ProposalStatus::Accepted,
+            Some(false) => ProposalStatus::Rejected,
+        },
+    })
+}
+
+/// A parameter and its value, stored as its code and decimal text from
+/// column `index` on.
+fn parameter_from_row(row: &Row, index: usize) -> Result<(Parameter, u128)> {
+    let code: u8 = row.get(index)?;
+    let parameter = Parameter::from_code(code)
+        .ok_or(rusqlite::Error::IntegralValueOutOfRange(index, code as i64))?;
+    let value: String = row.get(index + 1)?;
+    let value = value.parse().map_err(|e| {
+        rusqlite::Error::FromSqlConversionFailure(
+            index + 1,
+            rusqlite::types::Type::Text,
+            Box::new(e),
+        )
+    })?;
+    Ok((parameter, value))
+}
+
+fn transaction_from_row(row: &Row) -> Result<Transaction> {
+    Ok(Transaction {
+        sender_public_key: row.get(4)?,
+        signature: row.get(5)?,
+        payload: Transfer {
+            receiver: row.get(0)?,
+            amount: row.get(1)?,
+            fee: row.get(2)?,
+            nonce: row.get(3)?,
+            kind: row.get(6)?,
+            data: row.get(7)?,
+        },
+    })
+}
+
+fn located_transaction_from_row(row: &Row) -> Result<(Transaction, TransactionLocation)> {
+    let location = TransactionLocation {
+        block_hash: row.get(8)?,
+        height: row.get(9)?,
+        index: row.get(10)?,
+    };
+    Ok((transaction_from_row(row)?, location))
+}
+
+fn block_from_row(row: &Row) -> Result<Block> {
+    Ok(Block {
+        header: BlockHeader {
+            previous_hash: row.get(0)?,
+            merkle_root: row.get(1)?,
+            state_root: row.get(2)?,
+            timestamp: row.get(3)?,
+            height: row.get(4)?,
+            proposer: row.get(5)?,
+            coinbase: row.get(6)?,
+            finalized_hash: row.get(7)?,
+            signature: row.get(8)?,
+        },
+        transactions: vec![],
+    })
+}
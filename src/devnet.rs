@@ -0,0 +1,481 @@
+//! A local network of validating nodes in one process, for trying out and
+//! testing consensus and sync. Its chaos mode kills and restarts nodes,
+//! disturbs their links and splits the network apart, then checks that
+//! every node comes back to the same head.
+//!
+//! Keys are derived from a fixed seed, so the genesis block and the named
+//! accounts funded in it are the same on every run.
+
+use crate::amount::Amount;
+use crate::blockchain::{Address, Blockchain, Hash, User};
+use crate::db::Database;
+use crate::node::{Node, NodeType};
+use crate::p2p::{LinkFaults, NodeId};
+use crate::params::ChainParams;
+use crate::signer::BlockSigner;
+use crate::sync;
+use ed25519_dalek::SigningKey;
+use futures::lock::Mutex;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::Instant;
+use tracing::info;
+
+/// Chain ID devnet nodes run on.
+pub const DEVNET_CHAIN_ID: &str = "devnet";
+
+/// Seed devnet keys are derived from unless another is given.
+pub const DEVNET_SEED: u64 = 0;
+
+/// Accounts funded in the devnet genesis block.
+pub const DEVNET_ACCOUNTS: [&str; 6] = ["alice", "bob", "carol", "dave", "erin", "frank"];
+
+/// Timestamp of the devnet genesis block, 2026-01-01.
+const DEVNET_GENESIS_TIME: i64 = 1_767_225_600;
+
+/// File in the devnet directory listing the named accounts and their keys.
+pub const DEVNET_KEYS_FILE: &str = "devnet-keys.json";
+
+#[derive(Clone, Debug)]
+pub struct DevnetConfig {
+    /// Nodes in the network, each validating with a key of its own.
+    pub nodes: usize,
+    pub block_time: Duration,
+    /// Where the nodes keep their databases.
+    pub dir: PathBuf,
+    /// Derives every key, validators' included.
+    pub seed: u64,
+    /// Names of the accounts funded at genesis.
+    pub accounts: Vec<String>,
+    /// What each named account holds at genesis.
+    pub account_balance: Amount,
+}
+
+impl Default for DevnetConfig {
+    fn default() -> Self {
+        DevnetConfig {
+            nodes: 4,
+            block_time: Duration::from_secs(1),
+            dir: std::env::temp_dir().join(format!("smvblock-devnet-{}", rand::random::<u64>())),
+            seed: DEVNET_SEED,
+            accounts: DEVNET_ACCOUNTS
+                .iter()
+                .map(|name| name.to_string())
+                .collect(),
+            account_balance: Amount::from_smv(1_000_000),
+        }
+    }
+}
+
+/// What [`Devnet::run_chaos`] does to the network.
+#[derive(Clone, Debug)]
+pub struct ChaosConfig {
+    /// How long to keep causing trouble.
+    pub duration: Duration,
+    /// How long each fault lasts before it is undone and the next begins.
+    pub fault_time: Duration,
+    /// Links made faulty drop this share of messages...
+    pub drop_rate: f64,
+    /// ...and hold each one up to this long.
+    pub max_delay: Duration,
+    /// How long the nodes get to agree on a head once the faults stop.
+    pub settle_time: Duration,
+    /// Picks the faults, so a failing run can be repeated.
+    pub seed: u64,
+}
+
+impl Default for ChaosConfig {
+    fn default() -> Self {
+        ChaosConfig {
+            duration: Duration::from_secs(30),
+            fault_time: Duration::from_secs(3),
+            drop_rate: 0.2,
+            max_delay: Duration::from_millis(500),
+            settle_time: Duration::from_secs(60),
+            seed: rand::random(),
+        }
+    }
+}
+
+/// One fault [`Devnet::run_chaos`] caused.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ChaosEvent {
+    /// The node was killed and restarted once the fault was over.
+    Restart(usize),
+    /// The network was split into groups that cannot reach each other.
+    Partition(Vec<Vec<usize>>),
+    /// The node's outgoing messages were dropped and delayed.
+    FaultyLinks(usize),
+}
+
+/// An account funded in the devnet genesis block.
+#[derive(Clone, Debug)]
+pub struct DevnetAccount {
+    pub name: String,
+    pub key: SigningKey,
+}
+
+impl DevnetAccount {
+    pub fn address(&self) -> Address {
+        Sha256::digest(self.key.verifying_key().to_bytes()).into()
+    }
+}
+
+/// The key `seed` derives for `name`.
+pub fn devnet_key(seed: u64, name: &str) -> SigningKey {
+    let mut hasher = Sha256::new();
+    hasher.update(b"smvblock-devnet");
+    hasher.update(seed.to_be_bytes());
+    hasher.update(name.as_bytes());
+    SigningKey::from_bytes(&hasher.finalize().into())
+}
+
+pub struct Devnet {
+    config: DevnetConfig,
+    accounts: Vec<DevnetAccount>,
+    /// The validator key of each node.
+    validators: Vec<SigningKey>,
+    /// Each node's identity, which it keeps across restarts.
+    ids: Vec<NodeId>,
+    /// `None` while the node is killed.
+    nodes: Vec<Option<Node>>,
+    /// Nodes each node currently refuses.
+    blocked: Vec<HashSet<NodeId>>,
+}
+
+impl Devnet {
+    /// Starts `config.nodes` connected nodes sharing a genesis block in
+    /// which every node's validator holds an equal stake and the named
+    /// accounts are funded. The accounts' keys are written to
+    /// [`DEVNET_KEYS_FILE`] in `config.dir`.
+    pub async fn start(config: DevnetConfig) -> Result<Self, String> {
+        std::fs::create_dir_all(&config.dir)
+            .map_err(|e| format!("Failed to create {}: {}", config.dir.display(), e))?;
+        let validators: Vec<SigningKey> = (0..config.nodes)
+            .map(|index| devnet_key(config.seed, &format!("validator-{}", index)))
+            .collect();
+        let accounts: Vec<DevnetAccount> = config
+            .accounts
+            .iter()
+            .map(|name| DevnetAccount {
+                name: name.clone(),
+                key: devnet_key(config.seed, name),
+            })
+            .collect();
+
+        // Every node starts from a copy of the same genesis database, made
+        // before any node has generated its identity into it.
+        let genesis = Database::new(config.dir.join("genesis.db").to_str(), false)
+            .map_err(|e| format!("Failed to create the genesis database: {}", e))?;
+        for key in &validators {
+            let public_key = key.verifying_key().to_bytes();
+            let user = User {
+                address: Sha256::digest(public_key).into(),
+                public_key,
+                balance: Amount::from_smv(1000),
+                stake: Amount::from_smv(100),
+                delegate: None,
+                commission_bps: 0,
+                missed_slots: 0,
+                jailed: false,
+            };
+            genesis
+                .add_user(&user)
+                .map_err(|e| format!("Failed to add a validator: {}", e))?;
+        }
+        for account in &accounts {
+            let user = User {
+                address: account.address(),
+                public_key: account.key.verifying_key().to_bytes(),
+                balance: config.account_balance,
+                stake: Amount::ZERO,
+                delegate: None,
+                commission_bps: 0,
+                missed_slots: 0,
+                jailed: false,
+            };
+            genesis
+                .add_user(&user)
+                .map_err(|e| format!("Failed to add account {}: {}", account.name, e))?;
+        }
+        let genesis = Arc::new(Mutex::new(genesis));
+        Blockchain::new(genesis.clone())
+            .create_genesis_block_at(DEVNET_GENESIS_TIME)
+            .await?;
+        write_keys_file(
+            &config.dir.join(DEVNET_KEYS_FILE),
+            &accounts,
+            config.account_balance,
+        )?;
+        for account in &accounts {
+            info!(name = %account.name, address = %hex::encode(account.address()), "devnet account");
+        }
+        for index in 0..config.nodes {
+            genesis
+                .lock()
+                .await
+                .backup(&node_path(&config, index))
+                .map_err(|e| format!("Failed to copy the genesis database: {}", e))?;
+        }
+
+        let mut devnet = Devnet {
+            nodes: vec![None; config.nodes],
+            ids: Vec::new(),
+            blocked: vec![HashSet::new(); config.nodes],
+            config,
+            accounts,
+            validators,
+        };
+        for index in 0..devnet.config.nodes {
+            let node = devnet.launch(index).await?;
+            devnet.ids.push(node.p2p.identity());
+            devnet.nodes[index] = Some(node);
+        }
+        devnet.connect_all().await;
+        info!(nodes = devnet.config.nodes, "devnet started");
+        Ok(devnet)
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// The accounts funded at genesis, in the order they were named.
+    pub fn accounts(&self) -> &[DevnetAccount] {
+        &self.accounts
+    }
+
+    pub fn account(&self, name: &str) -> Option<&DevnetAccount> {
+        self.accounts.iter().find(|account| account.name == name)
+    }
+
+    /// The node at `index`, unless it is killed.
+    pub fn node(&self, index: usize) -> Option<&Node> {
+        self.nodes.get(index)?.as_ref()
+    }
+
+    /// Opens the node's database and starts it validating.
+    async fn launch(&self, index: usize) -> Result<Node, String> {
+        let path = node_path(&self.config, index);
+        let database = Database::new(path.to_str(), false)
+            .map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+        let mut node = Node::with_database(NodeType::FullNode, database);
+        node.p2p.set_chain_id(DEVNET_CHAIN_ID);
+        node.blockchain.set_chain_params(ChainParams {
+            block_time: self.config.block_time,
+            ..ChainParams::default()
+        });
+        node.start_network("127.0.0.1:0".parse().unwrap()).await?;
+        node.start_validator(BlockSigner::Local(self.validators[index].clone()));
+        Ok(node)
+    }
+
+    /// Dials every pair of running nodes not yet connected. Pairs that
+    /// refuse each other are left apart.
+    async fn connect_all(&self) {
+        for (index, node) in self.nodes.iter().enumerate() {
+            let Some(node) = node else { continue };
+            for (other, peer) in self.nodes.iter().enumerate().skip(index + 1) {
+                let Some(peer) = peer else { continue };
+                if self.blocked[index].contains(&self.ids[other]) {
+                    continue;
+                }
+                if let Some(addr) = peer.p2p.advertised_addr().await {
+                    let _ = node.connect(addr).await;
+                }
+            }
+        }
+    }
+
+    /// Stops the node as if its process died; its database stays.
+    pub async fn kill(&mut self, index: usize) -> Result<(), String> {
+        let node = self.nodes[index]
+            .take()
+            .ok_or(format!("Node {} is not running", index))?;
+        node.shutdown().await?;
+        info!(index, "killed devnet node");
+        Ok(())
+    }
+
+    /// Starts a killed node again from its database and reconnects it.
+    pub async fn restart(&mut self, index: usize) -> Result<(), String> {
+        if self.nodes[index].is_some() {
+            return Err(format!("Node {} is already running", index));
+        }
+        let node = self.launch(index).await?;
+        node.p2p
+            .set_blocked_peers(self.blocked[index].clone())
+            .await;
+        self.nodes[index] = Some(node);
+        self.connect_all().await;
+        info!(index, "restarted devnet node");
+        Ok(())
+    }
+
+    /// Splits the network so nodes in different `groups` cannot reach
+    /// each other. Nodes in no group are cut off from everyone.
+    pub async fn partition(&mut self, groups: &[Vec<usize>]) {
+        for index in 0..self.len() {
+            let group = groups.iter().find(|group| group.contains(&index));
+            self.blocked[index] = (0..self.len())
+                .filter(|other| *other != index && !group.is_some_and(|g| g.contains(other)))
+                .map(|other| self.ids[other])
+                .collect();
+            if let Some(node) = self.node(index) {
+                node.p2p
+                    .set_blocked_peers(self.blocked[index].clone())
+                    .await;
+            }
+        }
+    }
+
+    /// Makes the node's outgoing links faulty, or sound again with the
+    /// default [`LinkFaults`].
+    pub fn set_link_faults(&self, index: usize, faults: LinkFaults) {
+        if let Some(node) = self.node(index) {
+            node.p2p.set_link_faults(faults);
+        }
+    }
+
+    /// Ends partitions and link faults and reconnects every running node.
+    pub async fn heal(&mut self) {
+        self.blocked = vec![HashSet::new(); self.len()];
+        for node in self.nodes.iter().flatten() {
+            node.p2p.set_blocked_peers(HashSet::new()).await;
+            node.p2p.set_link_faults(LinkFaults::default());
+        }
+        self.connect_all().await;
+    }
+
+    /// The head block of each running node.
+    pub fn heads(&self) -> Vec<(usize, Hash)> {
+        self.nodes
+            .iter()
+            .enumerate()
+            .filter_map(|(index, node)| {
+                Some((index, sync::chain_tip(&node.as_ref()?.blockchain).0))
+            })
+            .collect()
+    }
+
+    /// Waits until every running node has the same head, and returns it.
+    pub async fn wait_for_convergence(&self, timeout: Duration) -> Result<Hash, String> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let heads = self.heads();
+            if let Some((_, head)) = heads.first()
+                && heads.iter().all(|(_, other)| other == head)
+            {
+                return Ok(*head);
+            }
+            if Instant::now() >= deadline {
+                let heads: Vec<String> = heads
+                    .iter()
+                    .map(|(index, head)| format!("node {} at {}", index, hex::encode(head)))
+                    .collect();
+                return Err(format!("Nodes did not converge: {}", heads.join(", ")));
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    }
+
+    /// Causes one fault after another for `config.duration`: killing a
+    /// node, splitting the network or making a node's links faulty. Each
+    /// is undone before the next. Afterwards every node must reach the
+    /// same head within `config.settle_time`. Returns the faults caused,
+    /// or an error listing them when the nodes disagree.
+    pub async fn run_chaos(&mut self, config: ChaosConfig) -> Result<Vec<ChaosEvent>, String> {
+        info!(seed = config.seed, "starting chaos");
+        let mut rng = StdRng::seed_from_u64(config.seed);
+        let mut events = Vec::new();
+        let deadline = Instant::now() + config.duration;
+        while Instant::now() < deadline {
+            let index = rng.gen_range(0..self.len());
+            let event = match rng.gen_range(0..3) {
+                0 => ChaosEvent::Restart(index),
+                1 => {
+                    let mut order: Vec<usize> = (0..self.len()).collect();
+                    order.shuffle(&mut rng);
+                    let split = rng.gen_range(1..self.len().max(2));
+                    let (left, right) = order.split_at(split.min(order.len()));
+                    ChaosEvent::Partition(vec![left.to_vec(), right.to_vec()])
+                }
+                _ => ChaosEvent::FaultyLinks(index),
+            };
+            info!(?event, "causing fault");
+
+            match &event {
+                ChaosEvent::Restart(index) => self.kill(*index).await?,
+                ChaosEvent::Partition(groups) => self.partition(groups).await,
+                ChaosEvent::FaultyLinks(index) => self.set_link_faults(
+                    *index,
+                    LinkFaults {
+                        drop_rate: config.drop_rate,
+                        max_delay: config.max_delay,
+                    },
+                ),
+            }
+            events.push(event.clone());
+            tokio::time::sleep(config.fault_time).await;
+            if let ChaosEvent::Restart(index) = event {
+                self.restart(index).await?;
+            }
+            self.heal().await;
+        }
+
+        match self.wait_for_convergence(config.settle_time).await {
+            Ok(head) => {
+                info!(head = %hex::encode(head), "nodes converged after chaos");
+                Ok(events)
+            }
+            Err(e) => Err(format!(
+                "{} after seed {} caused {:?}",
+                e, config.seed, events
+            )),
+        }
+    }
+
+    /// Stops every node. Their databases are left in the devnet directory.
+    pub async fn shutdown(self) -> Result<(), String> {
+        for node in self.nodes.into_iter().flatten() {
+            node.shutdown().await?;
+        }
+        Ok(())
+    }
+}
+
+fn node_path(config: &DevnetConfig, index: usize) -> PathBuf {
+    config.dir.join(format!("node-{}.db", index))
+}
+
+fn write_keys_file(path: &Path, accounts: &[DevnetAccount], balance: Amount) -> Result<(), String> {
+    let accounts: Vec<_> = accounts
+        .iter()
+        .map(|account| {
+            json!({
+                "name": account.name,
+                "address": hex::encode(account.address()),
+                "public_key": hex::encode(account.key.verifying_key().to_bytes()),
+                "secret_key": hex::encode(account.key.to_bytes()),
+                "balance": balance.to_string(),
+            })
+        })
+        .collect();
+    let contents = serde_json::to_string_pretty(&json!({
+        "chain_id": DEVNET_CHAIN_ID,
+        "accounts": accounts,
+    }))
+    .map_err(|e| e.to_string())?;
+    std::fs::write(path, contents).map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+}
@@ -0,0 +1,164 @@
+use crate::amount::Amount;
+use crate::blockchain::TxKind;
+use std::fmt;
+
+/// Why the chain refused a transaction, or failed to account for one.
+#[derive(Clone, Debug, PartialEq)]
+pub enum BlockchainError {
+    /// A balance, stake or supply computation exceeded the range of `Amount`.
+    Overflow,
+    InsufficientBalance,
+    InsufficientStake,
+    InvalidSignature,
+    /// A stake, unstake or other transaction that pays no one named
+    /// someone other than its sender as the receiver.
+    WrongReceiver(TxKind),
+    /// The transaction's data is not what its kind carries; see
+    /// [`Transfer::check_data`](crate::blockchain::Transfer::check_data).
+    WrongData(TxKind),
+    UnknownSender,
+    UnknownReceiver,
+    NonceUsed,
+    /// The nonce would leave a gap after the sender's pending transactions.
+    NonceGap {
+        expected: u64,
+        got: u64,
+    },
+    /// A replacement for a pending transaction did not pay a higher fee.
+    Underpriced,
+    MempoolFull,
+    /// The fee is below what the node's mempool asks for a transaction of
+    /// this size.
+    FeeTooLow {
+        required: Amount,
+    },
+    /// The sender already has as many pending transactions as it may.
+    TooManyPending {
+        limit: usize,
+    },
+    /// A proposal or vote breaks the governance rules, or the sender may
+    /// not make it.
+    Governance(String),
+    /// A stake, delegation or commission breaks the delegation rules.
+    Staking(String),
+    /// A beacon commitment or reveal breaks the beacon rules; see
+    /// [`crate::beacon`].
+    Beacon(String),
+    /// The node could not read or write its database.
+    Database(String),
+    /// By the time a block was to be written, the chain's head was no
+    /// longer its parent: another block moved it first.
+    NotOnHead,
+}
+
+impl BlockchainError {
+    /// A stable code for the error in RPC responses, from the range JSON-RPC
+    /// leaves to servers, so callers can tell failures apart without
+    /// matching on messages.
+    pub fn code(&self) -> i64 {
+        match self {
+            BlockchainError::Database(_) => -32000,
+            BlockchainError::Overflow => -32001,
+            BlockchainError::InsufficientBalance => -32002,
+            BlockchainError::InsufficientStake => -32003,
+            BlockchainError::InvalidSignature => -32004,
+            BlockchainError::WrongReceiver(_) => -32005,
+            BlockchainError::UnknownSender => -32006,
+            BlockchainError::UnknownReceiver => -32007,
+            BlockchainError::NonceUsed => -32008,
+            BlockchainError::NonceGap { .. } => -32009,
+            BlockchainError::Underpriced => -32010,
+            BlockchainError::MempoolFull => -32011,
+            BlockchainError::FeeTooLow { .. } => -32012,
+            BlockchainError::TooManyPending { .. } => -32013,
+            BlockchainError::Governance(_) => -32014,
+            BlockchainError::Staking(_) => -32015,
+            BlockchainError::Beacon(_) => -32016,
+            BlockchainError::WrongData(_) => -32017,
+            BlockchainError::NotOnHead => -32018,
+        }
+    }
+}
+
+impl fmt::Display for BlockchainError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BlockchainError::Overflow => write!(f, "Arithmetic overflow"),
+            BlockchainError::InsufficientBalance => write!(f, "Insufficient balance"),
+            BlockchainError::InsufficientStake => write!(f, "Insufficient stake"),
+            BlockchainError::InvalidSignature => write!(f, "Invalid transaction signature"),
+            BlockchainError::WrongReceiver(kind) => write!(
+                f,
+                "A {} transaction must name its sender as the receiver",
+                kind.as_str()
+            ),
+            BlockchainError::WrongData(kind) => {
+                write!(f, "A {} transaction carries the wrong data", kind.as_str())
+            }
+            BlockchainError::UnknownSender => write!(f, "Sender not found"),
+            BlockchainError::UnknownReceiver => write!(f, "Receiver not found"),
+            BlockchainError::NonceUsed => write!(f, "Nonce already used"),
+            BlockchainError::NonceGap { expected, got } => {
+                write!(f, "Invalid nonce: expected {}, got {}", expected, got)
+            }
+            BlockchainError::Underpriced => write!(f, "Replacement transaction underpriced"),
+            BlockchainError::MempoolFull => write!(f, "Mempool is full"),
+            BlockchainError::FeeTooLow { required } => {
+                write!(f, "Fee too low: this node requires at least {}", required)
+            }
+            BlockchainError::TooManyPending { limit } => write!(
+                f,
+                "Sender already has {} pending transactions, the most allowed",
+                limit
+            ),
+            BlockchainError::Governance(reason) => write!(f, "{}", reason),
+            BlockchainError::Staking(reason) => write!(f, "{}", reason),
+            BlockchainError::Beacon(reason) => write!(f, "{}", reason),
+            BlockchainError::Database(reason) => write!(f, "{}", reason),
+            BlockchainError::NotOnHead => write!(f, "Block does not extend the chain head"),
+        }
+    }
+}
+
+impl std::error::Error for BlockchainError {}
+
+impl From<BlockchainError> for String {
+    fn from(err: BlockchainError) -> Self {
+        err.to_string()
+    }
+}
+
+/// Why a call to a node's RPC server failed.
+#[derive(Clone, Debug, PartialEq)]
+pub enum RpcError {
+    /// No node could be reached, or none answered in time.
+    Unreachable(String),
+    /// A node answered and refused the call.
+    Rejected {
+        method: String,
+        code: i64,
+        message: String,
+    },
+    /// A node answered with something that is not a valid response.
+    InvalidResponse(String),
+}
+
+impl fmt::Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RpcError::Unreachable(reason) => write!(f, "{}", reason),
+            RpcError::Rejected {
+                method, message, ..
+            } => write!(f, "{} failed: {}", method, message),
+            RpcError::InvalidResponse(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+impl std::error::Error for RpcError {}
+
+impl From<RpcError> for String {
+    fn from(err: RpcError) -> Self {
+        err.to_string()
+    }
+}
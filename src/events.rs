@@ -0,0 +1,72 @@
+//! The node's event bus. Components publish what happened to them and
+//! whoever cares, such as RPC subscriptions, gossip relay or logging,
+//! subscribes, so no component reads another's state to find out.
+
+use crate::amount::Amount;
+use crate::blockchain::{Address, Block, Hash, Transaction, User};
+use crate::p2p::NodeId;
+use crate::sync::SyncState;
+use std::net::SocketAddr;
+use tokio::sync::broadcast;
+
+/// Events buffered per subscriber before the slowest ones start missing some.
+const EVENT_CAPACITY: usize = 1024;
+
+/// Something that happened in the node, published as it happens.
+#[derive(Clone, Debug)]
+pub enum NodeEvent {
+    /// The block was added on top of the chain, whether produced here,
+    /// synced or announced by a peer.
+    BlockApplied(Block),
+    /// The transaction was accepted into the mempool.
+    TxAccepted(Transaction),
+    /// An account's state after a block touched it, or after such a block
+    /// was undone.
+    AccountChanged(User),
+    /// A validator lost `penalty` of its stake, leaving `stake`.
+    ValidatorSlashed {
+        address: Address,
+        penalty: Amount,
+        stake: Amount,
+    },
+    /// The chain switched from `old_head` to a branch ending at `new_head`,
+    /// undoing the `depth` blocks above where the two forked.
+    ReorgHappened {
+        old_head: Hash,
+        new_head: Hash,
+        depth: usize,
+    },
+    /// A peer completed its handshake.
+    PeerConnected { addr: SocketAddr, node_id: NodeId },
+    /// A peer that had completed its handshake went away.
+    PeerDisconnected { addr: SocketAddr, node_id: NodeId },
+    /// Sync started, moved on or caught up.
+    SyncProgress(SyncState),
+}
+
+/// Shared by every component of a node; clones publish to the same
+/// subscribers.
+#[derive(Clone, Debug)]
+pub struct EventBus {
+    sender: broadcast::Sender<NodeEvent>,
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        EventBus {
+            sender: broadcast::channel(EVENT_CAPACITY).0,
+        }
+    }
+}
+
+impl EventBus {
+    pub fn publish(&self, event: NodeEvent) {
+        // Sending only fails when nobody is subscribed.
+        let _ = self.sender.send(event);
+    }
+
+    /// Receives every event published from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<NodeEvent> {
+        self.sender.subscribe()
+    }
+}
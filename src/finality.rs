@@ -0,0 +1,124 @@
+use crate::amount::Amount;
+use crate::blockchain::{Address, Hash};
+use ed25519_dalek::ed25519::signature::{SignerMut, Verifier};
+use ed25519_dalek::{Signature, SigningKey, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+
+/// Domain separator so a vote signature can never be replayed as a
+/// transaction signature or vice versa.
+const VOTE_DOMAIN: &[u8] = b"smvblock-vote";
+
+/// A validator's signed statement that `block_hash` at `height` is valid.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct Vote {
+    pub block_hash: Hash,
+    pub height: u64,
+    pub public_key: [u8; 32],
+    #[serde(with = "serde_big_array::BigArray")]
+    pub signature: [u8; 64],
+}
+
+impl Vote {
+    pub fn sign(block_hash: Hash, height: u64, key: &SigningKey) -> Self {
+        let signature = key.clone().sign(&signing_bytes(&block_hash, height));
+        Vote {
+            block_hash,
+            height,
+            public_key: key.verifying_key().to_bytes(),
+            signature: signature.to_bytes(),
+        }
+    }
+
+    pub fn verify(&self) -> bool {
+        let Ok(key) = VerifyingKey::from_bytes(&self.public_key) else {
+            return false;
+        };
+        let signature = Signature::from_bytes(&self.signature);
+        key.verify(&signing_bytes(&self.block_hash, self.height), &signature)
+            .is_ok()
+    }
+
+    pub fn voter(&self) -> Address {
+        Sha256::digest(self.public_key).into()
+    }
+}
+
+fn signing_bytes(block_hash: &Hash, height: u64) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(VOTE_DOMAIN.len() + 40);
+    bytes.extend_from_slice(VOTE_DOMAIN);
+    bytes.extend_from_slice(block_hash);
+    bytes.extend_from_slice(&height.to_be_bytes());
+    bytes
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum VoteOutcome {
+    /// The voter had already voted for this block.
+    Duplicate,
+    Recorded,
+    /// This vote pushed the block over the finality threshold.
+    Finalized,
+}
+
+/// Collects votes per block until validators holding at least two thirds
+/// of the total stake have voted for it.
+#[derive(Debug, Default)]
+pub struct FinalityTracker {
+    votes: HashMap<Hash, HashMap<Address, Vote>>,
+}
+
+impl FinalityTracker {
+    pub fn new() -> Self {
+        FinalityTracker::default()
+    }
+
+    /// Records an already verified vote. `stakes` maps validators to their
+    /// current stake and is used to weigh every vote cast for the block;
+    /// votes at or below `finalized_height` are refused.
+    pub fn add_vote(
+        &mut self,
+        vote: Vote,
+        stakes: &HashMap<Address, Amount>,
+        finalized_height: Option<u64>,
+    ) -> Result<VoteOutcome, String> {
+        if let Some(height) = finalized_height
+            && vote.height <= height
+        {
+            return Err("Vote is for a block at or below the finalized height".to_string());
+        }
+
+        let voter = vote.voter();
+        if stakes.get(&voter).is_none_or(|stake| stake.is_zero()) {
+            return Err("Voter has no stake".to_string());
+        }
+
+        let (block_hash, height) = (vote.block_hash, vote.height);
+        let votes = self.votes.entry(block_hash).or_default();
+        if votes.contains_key(&voter) {
+            return Ok(VoteOutcome::Duplicate);
+        }
+        votes.insert(voter, vote);
+
+        let voted = Amount::checked_sum(votes.keys().filter_map(|v| stakes.get(v).copied()))?;
+        let total = Amount::checked_sum(stakes.values().copied())?;
+
+        if voted.base_units().saturating_mul(3) < total.base_units().saturating_mul(2) {
+            return Ok(VoteOutcome::Recorded);
+        }
+
+        // Votes at or below the new finalized height can no longer matter.
+        self.votes.retain(|_, votes| {
+            votes
+                .values()
+                .next()
+                .is_some_and(|vote| vote.height > height)
+        });
+        Ok(VoteOutcome::Finalized)
+    }
+
+    pub fn vote_count(&self, block_hash: &Hash) -> usize {
+        self.votes.get(block_hash).map_or(0, HashMap::len)
+    }
+}
@@ -0,0 +1,442 @@
+//! Changing consensus parameters on chain. Any account may propose a new
+//! value for a [`Parameter`] that takes effect at a height of its choosing,
+//! and stakers vote on it until then. Votes are weighed by the voters'
+//! stake just before that height: a change backed by more than two thirds
+//! of all stake is accepted and applies from that block on, so operators
+//! need not upgrade in lockstep to change it.
+//!
+//! Proposals and votes are transactions, like stakes are. Neither moves an
+//! amount; what they say is their [`TxData`], a [`ParameterChange`] or a
+//! [`Ballot`].
+//!
+//! Accepted [`Parameter::TreasuryGrant`]s are the only way coins leave the
+//! treasury: each pays its proposer out of it at its activation height.
+//!
+//! State snapshots carry accounts only, so a node started from one knows
+//! of the proposals made after it and no others.
+
+use crate::amount::Amount;
+use crate::beacon;
+use crate::blockchain::{Address, Transaction, TxData, TxKind, User};
+use crate::db::Database;
+use crate::params::{ChainParams, MAX_FEE_BPS};
+use crate::staking;
+use bincode::{Decode, Encode};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+/// Fewest blocks between a proposal and the height it would take effect
+/// at, so stakers have time to vote on it.
+pub const MIN_VOTING_PERIOD: u64 = 10;
+
+/// Most blocks ahead a proposal may take effect.
+pub const MAX_VOTING_PERIOD: u64 = 1_000_000;
+
+/// Longest block time that may be proposed, in milliseconds.
+pub const MAX_BLOCK_TIME_MILLIS: u128 = 3_600_000;
+
+/// A consensus parameter proposals can change, or a grant out of the
+/// treasury.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize, Serialize, Encode, Decode)]
+#[serde(rename_all = "snake_case")]
+pub enum Parameter {
+    /// Length of a block production slot, in milliseconds.
+    BlockTime,
+    /// Coinbase each block may mint, in base units, in place of the
+    /// emission curve. The supply cap still applies.
+    BlockReward,
+    /// Least fee per byte of a transaction, in base units.
+    MinFeePerByte,
+    /// Share of each block's fees burned, in basis points.
+    FeeBurn,
+    /// Share of each block's fees paid to the treasury, in basis points.
+    /// Whatever the burn leaves of the whole, at most.
+    FeeTreasury,
+    /// Not a parameter as such: an amount, in base units, paid out of the
+    /// treasury to the proposer at the activation height. A grant the
+    /// treasury cannot cover then is not paid.
+    TreasuryGrant,
+}
+
+impl Parameter {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Parameter::BlockTime => "block_time",
+            Parameter::BlockReward => "block_reward",
+            Parameter::MinFeePerByte => "min_fee_per_byte",
+            Parameter::FeeBurn => "fee_burn",
+            Parameter::FeeTreasury => "fee_treasury",
+            Parameter::TreasuryGrant => "treasury_grant",
+        }
+    }
+
+    /// Stored as its position in the enum.
+    pub fn code(self) -> u8 {
+        self as u8
+    }
+
+    pub fn from_code(code: u8) -> Option<Self> {
+        match code {
+            0 => Some(Parameter::BlockTime),
+            1 => Some(Parameter::BlockReward),
+            2 => Some(Parameter::MinFeePerByte),
+            3 => Some(Parameter::FeeBurn),
+            4 => Some(Parameter::FeeTreasury),
+            5 => Some(Parameter::TreasuryGrant),
+            _ => None,
+        }
+    }
+
+    /// Reads a value for the parameter: milliseconds for the block time,
+    /// basis points for a share of fees, an amount such as `0.5 SMV`
+    /// otherwise.
+    pub fn parse_value(self, s: &str) -> Result<u128, String> {
+        let number = |suffix: &str, unit: &str| {
+            let s = s.trim();
+            s.strip_suffix(suffix)
+                .unwrap_or(s)
+                .trim_end()
+                .parse()
+                .map_err(|_| format!("Invalid {} in {}: {}", self.as_str(), unit, s))
+        };
+        match self {
+            Parameter::BlockTime => number("ms", "milliseconds"),
+            Parameter::FeeBurn | Parameter::FeeTreasury => number("bps", "basis points"),
+            Parameter::BlockReward | Parameter::MinFeePerByte | Parameter::TreasuryGrant => {
+                s.parse::<Amount>().map(Amount::base_units)
+            }
+        }
+    }
+
+    /// The value as [`parse_value`](Self::parse_value) reads it.
+    pub fn format_value(self, value: u128) -> String {
+        match self {
+            Parameter::BlockTime => format!("{} ms", value),
+            Parameter::FeeBurn | Parameter::FeeTreasury => format!("{} bps", value),
+            Parameter::BlockReward | Parameter::MinFeePerByte | Parameter::TreasuryGrant => {
+                Amount::from_base_units(value).to_string()
+            }
+        }
+    }
+
+    fn check_value(self, value: u128) -> Result<(), String> {
+        if self == Parameter::BlockTime && !(1..=MAX_BLOCK_TIME_MILLIS).contains(&value) {
+            return Err(format!(
+                "Block time must be between 1 and {} ms",
+                MAX_BLOCK_TIME_MILLIS
+            ));
+        }
+        if matches!(self, Parameter::FeeBurn | Parameter::FeeTreasury)
+            && value > MAX_FEE_BPS as u128
+        {
+            return Err(format!(
+                "A share of fees is at most {} basis points",
+                MAX_FEE_BPS
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl std::str::FromStr for Parameter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "block_time" => Ok(Parameter::BlockTime),
+            "block_reward" => Ok(Parameter::BlockReward),
+            "min_fee_per_byte" => Ok(Parameter::MinFeePerByte),
+            "fee_burn" => Ok(Parameter::FeeBurn),
+            "fee_treasury" => Ok(Parameter::FeeTreasury),
+            "treasury_grant" => Ok(Parameter::TreasuryGrant),
+            _ => Err(format!("Unknown parameter: {}", s)),
+        }
+    }
+}
+
+/// What a proposal asks for: `parameter` set to `value` from the block at
+/// `activation_height` on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize, Encode, Decode)]
+pub struct ParameterChange {
+    pub parameter: Parameter,
+    pub value: u128,
+    pub activation_height: u64,
+}
+
+impl ParameterChange {
+    /// Checks the value is one the parameter may take.
+    pub fn check(&self) -> Result<(), String> {
+        self.parameter.check_value(self.value)
+    }
+}
+
+/// A staker's vote for or against proposal `proposal`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize, Encode, Decode)]
+pub struct Ballot {
+    pub proposal: u64,
+    pub approve: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProposalStatus {
+    /// Its activation height is yet to come.
+    Voting,
+    Accepted,
+    Rejected,
+}
+
+impl ProposalStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ProposalStatus::Voting => "voting",
+            ProposalStatus::Accepted => "accepted",
+            ProposalStatus::Rejected => "rejected",
+        }
+    }
+}
+
+/// A proposal on chain. IDs count up from 1 in the order proposals were
+/// included.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Proposal {
+    pub id: u64,
+    pub proposer: Address,
+    pub change: ParameterChange,
+    /// Height of the block that included it.
+    pub height: u64,
+    pub status: ProposalStatus,
+}
+
+/// Stake behind each side of a proposal, out of all stake.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Tally {
+    pub approve: Amount,
+    pub reject: Amount,
+    pub total: Amount,
+}
+
+impl Tally {
+    /// Whether more than two thirds of all stake approves.
+    pub fn passes(&self) -> bool {
+        self.approve.base_units().saturating_mul(3) > self.total.base_units().saturating_mul(2)
+    }
+}
+
+/// The governed parameters in effect at some height. Those never changed
+/// are `None`, leaving the node's own setting in place.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Parameters {
+    pub block_time: Option<Duration>,
+    pub block_reward: Option<Amount>,
+    pub min_fee_per_byte: Option<Amount>,
+    pub fee_burn_bps: Option<u16>,
+    pub fee_treasury_bps: Option<u16>,
+}
+
+impl Parameters {
+    pub fn apply(&mut self, parameter: Parameter, value: u128) {
+        match parameter {
+            Parameter::BlockTime => {
+                self.block_time = Some(Duration::from_millis(value.min(u64::MAX as u128) as u64))
+            }
+            Parameter::BlockReward => self.block_reward = Some(Amount::from_base_units(value)),
+            Parameter::MinFeePerByte => {
+                self.min_fee_per_byte = Some(Amount::from_base_units(value))
+            }
+            Parameter::FeeBurn => self.fee_burn_bps = Some(bps(value)),
+            Parameter::FeeTreasury => self.fee_treasury_bps = Some(bps(value)),
+            // Paid once rather than kept in effect.
+            Parameter::TreasuryGrant => {}
+        }
+    }
+
+    /// The least fee `tx` may pay under the governed fee rate.
+    pub fn required_fee(&self, tx: &Transaction) -> Amount {
+        let rate = self.min_fee_per_byte.unwrap_or(Amount::ZERO);
+        Amount::from_base_units(rate.base_units().saturating_mul(tx.size() as u128))
+    }
+}
+
+fn bps(value: u128) -> u16 {
+    value.min(MAX_FEE_BPS as u128) as u16
+}
+
+/// Checks the transactions of a block at some height against the rules in
+/// effect there, keeping track of the proposals, votes, consensus keys and
+/// beacon commitments and reveals made earlier in the block.
+pub(crate) struct BlockRules<'a> {
+    params: &'a ChainParams,
+    height: u64,
+    parameters: Parameters,
+    next_id: u64,
+    /// Activation heights of the proposals made in this block.
+    proposed: HashMap<u64, u64>,
+    voted: HashSet<(u64, Address)>,
+    /// Consensus keys registered or rotated to in this block, and the
+    /// accounts that did.
+    keys: HashSet<[u8; 32]>,
+    keyed: HashSet<Address>,
+    /// Accounts that committed to or revealed a beacon secret in this
+    /// block.
+    committed: HashSet<Address>,
+    revealed: HashSet<Address>,
+}
+
+impl<'a> BlockRules<'a> {
+    pub(crate) fn new(db: &Database, params: &'a ChainParams, height: u64) -> Result<Self, String> {
+        Ok(BlockRules {
+            params,
+            height,
+            parameters: db
+                .get_parameters(height)
+                .map_err(|_| "Error fetching parameters".to_string())?,
+            next_id: db
+                .next_proposal_id()
+                .map_err(|_| "Error fetching proposals".to_string())?,
+            proposed: HashMap::new(),
+            voted: HashSet::new(),
+            keys: HashSet::new(),
+            keyed: HashSet::new(),
+            committed: HashSet::new(),
+            revealed: HashSet::new(),
+        })
+    }
+
+    /// The governed parameters in effect at the block's height.
+    pub(crate) fn parameters(&self) -> &Parameters {
+        &self.parameters
+    }
+
+    /// Checks `tx` from `sender`, whose stake it votes with, and records
+    /// what it proposes or votes for.
+    pub(crate) fn check(
+        &mut self,
+        db: &Database,
+        sender: &User,
+        tx: &Transaction,
+    ) -> Result<(), String> {
+        let required = self.parameters.required_fee(tx);
+        if tx.payload.fee < required {
+            return Err(format!("Fee too low: at least {} is required", required));
+        }
+        if let Some(feature) = tx.payload.kind.feature() {
+            self.params.forks.require(feature, self.height)?;
+        }
+        if let Some(feature) = tx.payload.data.feature() {
+            self.params.forks.require(feature, self.height)?;
+        }
+        let governs = matches!(tx.payload.kind, TxKind::Propose | TxKind::Vote);
+        if governs && tx.payload.amount != Amount::ZERO {
+            return Err(format!(
+                "A {} transaction must have a zero amount",
+                tx.payload.kind.as_str()
+            ));
+        }
+        match tx.payload.data {
+            TxData::Proposal(change) => {
+                change.check()?;
+                let earliest = self.height.saturating_add(MIN_VOTING_PERIOD);
+                let latest = self.height.saturating_add(MAX_VOTING_PERIOD);
+                if !(earliest..=latest).contains(&change.activation_height) {
+                    return Err(format!(
+                        "Proposal must take effect between heights {} and {}",
+                        earliest, latest
+                    ));
+                }
+                self.proposed.insert(self.next_id, change.activation_height);
+                self.next_id += 1;
+            }
+            TxData::Ballot(ballot) => {
+                if sender.stake == Amount::ZERO {
+                    return Err("Only accounts with stake may vote".to_string());
+                }
+                let activation_height = match self.proposed.get(&ballot.proposal) {
+                    Some(height) => *height,
+                    None => {
+                        db.get_proposal(ballot.proposal)
+                            .map_err(|_| "Error fetching proposal".to_string())?
+                            .ok_or(format!("No proposal {}", ballot.proposal))?
+                            .change
+                            .activation_height
+                    }
+                };
+                if self.height >= activation_height {
+                    return Err(format!("Voting on proposal {} has closed", ballot.proposal));
+                }
+                let voted = !self.voted.insert((ballot.proposal, sender.address))
+                    || db
+                        .has_voted(ballot.proposal, &sender.address)
+                        .map_err(|_| "Error fetching votes".to_string())?;
+                if voted {
+                    return Err(format!(
+                        "{} already voted on proposal {}",
+                        hex::encode(sender.address),
+                        ballot.proposal
+                    ));
+                }
+            }
+            TxData::None
+            | TxData::Commission(_)
+            | TxData::Registration(_)
+            | TxData::ConsensusKey(_)
+            | TxData::Commit(_)
+            | TxData::Secret(_) => {}
+        }
+        Ok(())
+    }
+
+    /// Checks the consensus key `tx` from `sender` registers or rotates
+    /// to, if any, and records it: an account changes its key once a block
+    /// at most, and no two accounts take the same key.
+    pub(crate) fn check_key(
+        &mut self,
+        db: &Database,
+        sender: &User,
+        tx: &Transaction,
+    ) -> Result<(), String> {
+        let Some(key) = tx.payload.data.consensus_key() else {
+            return Ok(());
+        };
+        if self.keyed.contains(&sender.address) {
+            return Err(format!(
+                "{} already changed its consensus key in this block",
+                hex::encode(sender.address)
+            ));
+        }
+        if self.keys.contains(&key) {
+            return Err("The key is already registered in this block".to_string());
+        }
+        staking::check_key(db, sender, tx)?;
+        self.keyed.insert(sender.address);
+        self.keys.insert(key);
+        Ok(())
+    }
+
+    /// Checks the beacon commitment or reveal `tx` from `sender` makes, if
+    /// any, and records it: an account commits and reveals once a block
+    /// at most, as it does once an epoch.
+    pub(crate) fn check_beacon(
+        &mut self,
+        db: &Database,
+        sender: &User,
+        tx: &Transaction,
+    ) -> Result<(), String> {
+        let made = match tx.payload.kind {
+            TxKind::Commit => &mut self.committed,
+            TxKind::Reveal => &mut self.revealed,
+            _ => return Ok(()),
+        };
+        if made.contains(&sender.address) {
+            return Err(format!(
+                "{} already made a {} in this block",
+                hex::encode(sender.address),
+                tx.payload.kind.as_str()
+            ));
+        }
+        let epoch_length = self.params.liveness.epoch_length;
+        beacon::check(db, sender, tx, self.height, epoch_length)?;
+        made.insert(sender.address);
+        Ok(())
+    }
+}
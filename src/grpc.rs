@@ -0,0 +1,277 @@
+//! The node's gRPC API, defined in `proto/smvblock.proto`. It serves the
+//! same chain the JSON-RPC server does, through the same [`RpcContext`],
+//! for integrators who want generated, typed clients and a block stream.
+//!
+//! Transactions refused by the mempool fail with `FAILED_PRECONDITION`,
+//! carrying the JSON-RPC error code in the `smv-error-code` metadata so
+//! both APIs report a refusal the same way.
+
+use crate::amount::Amount;
+use crate::blockchain::{Block, BlockHeader, Hash, Transfer, TxData, TxKind};
+use crate::error::BlockchainError;
+use crate::events::NodeEvent;
+use crate::rpc::RpcContext;
+use crate::sync::SyncState;
+use proto::node_server::{Node, NodeServer};
+use std::net::SocketAddr;
+use tokio::net::TcpListener;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{mpsc, watch};
+use tokio_stream::wrappers::{ReceiverStream, TcpListenerStream};
+use tonic::{Request, Response, Status};
+
+/// Types generated from `proto/smvblock.proto`.
+pub mod proto {
+    tonic::include_proto!("smvblock.v1");
+}
+
+/// Blocks a subscriber may fall behind by before it misses some.
+const SUBSCRIPTION_BUFFER: usize = 64;
+
+/// Serves the gRPC API on `addr` in the background until `shutdown` turns
+/// true. Returns the bound address.
+pub async fn serve(
+    context: RpcContext,
+    addr: SocketAddr,
+    mut shutdown: watch::Receiver<bool>,
+) -> Result<SocketAddr, String> {
+    let listener = TcpListener::bind(addr)
+        .await
+        .map_err(|e| format!("Failed to bind gRPC server on {}: {}", addr, e))?;
+    let local_addr = listener
+        .local_addr()
+        .map_err(|e| format!("Failed to read gRPC address: {}", e))?;
+
+    tokio::spawn(async move {
+        let stopped = async move {
+            let _ = shutdown.wait_for(|stopped| *stopped).await;
+        };
+        if let Err(e) = tonic::transport::Server::builder()
+            .add_service(NodeServer::new(GrpcService { context }))
+            .serve_with_incoming_shutdown(TcpListenerStream::new(listener), stopped)
+            .await
+        {
+            tracing::error!(error = %e, "gRPC server stopped");
+        }
+    });
+
+    Ok(local_addr)
+}
+
+struct GrpcService {
+    context: RpcContext,
+}
+
+#[tonic::async_trait]
+impl Node for GrpcService {
+    async fn get_status(
+        &self,
+        _: Request<proto::GetStatusRequest>,
+    ) -> Result<Response<proto::Status>, Status> {
+        let chain = &self.context.blockchain;
+        let head = chain.chain_head().get();
+        let finalized = chain.latest_finalized().await.map_err(Status::internal)?;
+        let syncing = matches!(*self.context.sync_state.borrow(), SyncState::Syncing { .. });
+        Ok(Response::new(proto::Status {
+            height: head.map(|(_, height)| height),
+            latest_hash: head.map_or(Vec::new(), |(hash, _)| hash.to_vec()),
+            finalized_height: finalized.map(|(_, height)| height),
+            finalized_hash: finalized.map_or(Vec::new(), |(hash, _)| hash.to_vec()),
+            syncing,
+            pending_transactions: chain.pending_count().await as u64,
+            peers: self.context.p2p.peers().await.len() as u64,
+            version: crate::p2p::NODE_VERSION.to_string(),
+        }))
+    }
+
+    async fn get_account(
+        &self,
+        request: Request<proto::GetAccountRequest>,
+    ) -> Result<Response<proto::Account>, Status> {
+        let address =
+            hash(&request.get_ref().address, "address").map_err(Status::invalid_argument)?;
+        let chain = &self.context.blockchain;
+        let user = chain
+            .get_user(&address)
+            .await
+            .map_err(|e| Status::internal(format!("Database error: {}", e)))?
+            .ok_or_else(|| Status::not_found("Account not found"))?;
+        let nonce = chain
+            .account_nonce(&address)
+            .await
+            .map_err(Status::internal)?;
+        Ok(Response::new(proto::Account {
+            address: user.address.to_vec(),
+            balance: user.balance.base_units().to_string(),
+            stake: user.stake.base_units().to_string(),
+            nonce,
+        }))
+    }
+
+    async fn get_block(
+        &self,
+        request: Request<proto::GetBlockRequest>,
+    ) -> Result<Response<proto::Block>, Status> {
+        let chain = &self.context.blockchain;
+        let block = match &request.get_ref().id {
+            Some(proto::get_block_request::Id::Height(height)) => {
+                chain.get_block_by_height(*height).await
+            }
+            Some(proto::get_block_request::Id::Hash(bytes)) => {
+                let hash = hash(bytes, "hash").map_err(Status::invalid_argument)?;
+                chain.get_full_block(hash).await
+            }
+            None => return Err(Status::invalid_argument("Expected a height or hash")),
+        }
+        .map_err(|e| Status::internal(format!("Database error: {}", e)))?
+        .ok_or_else(|| Status::not_found("Block not found"))?;
+        Ok(Response::new(block_message(&block)))
+    }
+
+    async fn submit_transaction(
+        &self,
+        request: Request<proto::Transaction>,
+    ) -> Result<Response<proto::SubmitTransactionResponse>, Status> {
+        let tx = transaction(request.into_inner()).map_err(Status::invalid_argument)?;
+        let hash = tx.hash();
+        self.context
+            .blockchain
+            .add_transaction(tx)
+            .await
+            .map_err(refused)?;
+        Ok(Response::new(proto::SubmitTransactionResponse {
+            hash: hash.to_vec(),
+        }))
+    }
+
+    type SubscribeBlocksStream = ReceiverStream<Result<proto::Block, Status>>;
+
+    async fn subscribe_blocks(
+        &self,
+        _: Request<proto::SubscribeBlocksRequest>,
+    ) -> Result<Response<Self::SubscribeBlocksStream>, Status> {
+        let mut events = self.context.events.subscribe();
+        let (sender, receiver) = mpsc::channel(SUBSCRIPTION_BUFFER);
+        tokio::spawn(async move {
+            loop {
+                let block = match events.recv().await {
+                    Ok(NodeEvent::BlockApplied(block)) => block,
+                    Ok(_) => continue,
+                    // A slow client misses blocks rather than stalling the node.
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => break,
+                };
+                if sender.send(Ok(block_message(&block))).await.is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(receiver)))
+    }
+}
+
+fn hash(bytes: &[u8], name: &str) -> Result<Hash, String> {
+    bytes
+        .try_into()
+        .map_err(|_| format!("Expected a 32-byte {}", name))
+}
+
+fn amount(units: &str, name: &str) -> Result<Amount, String> {
+    units
+        .parse()
+        .map(Amount::from_base_units)
+        .map_err(|_| format!("Invalid {}: {}", name, units))
+}
+
+/// A mempool refusal, with its JSON-RPC error code attached.
+fn refused(err: BlockchainError) -> Status {
+    let mut status = Status::failed_precondition(err.to_string());
+    status
+        .metadata_mut()
+        .insert("smv-error-code", err.code().into());
+    status
+}
+
+fn transaction(tx: proto::Transaction) -> Result<crate::blockchain::Transaction, String> {
+    let kind = match proto::TxKind::try_from(tx.kind) {
+        Ok(proto::TxKind::Transfer) => TxKind::Transfer,
+        Ok(proto::TxKind::Stake) => TxKind::Stake,
+        Ok(proto::TxKind::Unstake) => TxKind::Unstake,
+        Ok(proto::TxKind::Propose) => TxKind::Propose,
+        Ok(proto::TxKind::Vote) => TxKind::Vote,
+        Ok(proto::TxKind::Delegate) => TxKind::Delegate,
+        Ok(proto::TxKind::SetCommission) => TxKind::SetCommission,
+        Ok(proto::TxKind::Unjail) => TxKind::Unjail,
+        Ok(proto::TxKind::RegisterValidator) => TxKind::RegisterValidator,
+        Ok(proto::TxKind::RotateKey) => TxKind::RotateKey,
+        Ok(proto::TxKind::Commit) => TxKind::Commit,
+        Ok(proto::TxKind::Reveal) => TxKind::Reveal,
+        Err(_) => return Err("Unknown transaction kind".to_string()),
+    };
+    Ok(crate::blockchain::Transaction {
+        payload: Transfer {
+            receiver: hash(&tx.receiver, "receiver")?,
+            amount: amount(&tx.amount, "amount")?,
+            fee: amount(&tx.fee, "fee")?,
+            nonce: tx.nonce,
+            kind,
+            data: TxData::from_bytes(&tx.data)?,
+        },
+        sender_public_key: hash(&tx.sender_public_key, "sender public key")?,
+        signature: tx
+            .signature
+            .as_slice()
+            .try_into()
+            .map_err(|_| "Expected a 64-byte signature".to_string())?,
+    })
+}
+
+fn transaction_message(tx: &crate::blockchain::Transaction) -> proto::Transaction {
+    let kind = match tx.payload.kind {
+        TxKind::Transfer => proto::TxKind::Transfer,
+        TxKind::Stake => proto::TxKind::Stake,
+        TxKind::Unstake => proto::TxKind::Unstake,
+        TxKind::Propose => proto::TxKind::Propose,
+        TxKind::Vote => proto::TxKind::Vote,
+        TxKind::Delegate => proto::TxKind::Delegate,
+        TxKind::SetCommission => proto::TxKind::SetCommission,
+        TxKind::Unjail => proto::TxKind::Unjail,
+        TxKind::RegisterValidator => proto::TxKind::RegisterValidator,
+        TxKind::RotateKey => proto::TxKind::RotateKey,
+        TxKind::Commit => proto::TxKind::Commit,
+        TxKind::Reveal => proto::TxKind::Reveal,
+    };
+    proto::Transaction {
+        receiver: tx.payload.receiver.to_vec(),
+        amount: tx.payload.amount.base_units().to_string(),
+        fee: tx.payload.fee.base_units().to_string(),
+        nonce: tx.payload.nonce,
+        kind: kind.into(),
+        sender_public_key: tx.sender_public_key.to_vec(),
+        signature: tx.signature.to_vec(),
+        hash: tx.hash().to_vec(),
+        data: tx.payload.data.to_bytes(),
+    }
+}
+
+fn header_message(header: &BlockHeader) -> proto::BlockHeader {
+    proto::BlockHeader {
+        hash: header.hash().to_vec(),
+        previous_hash: header.previous_hash.to_vec(),
+        merkle_root: header.merkle_root.to_vec(),
+        state_root: header.state_root.to_vec(),
+        timestamp: header.timestamp,
+        height: header.height,
+        proposer: header.proposer.to_vec(),
+        coinbase: header.coinbase.base_units().to_string(),
+        finalized_hash: header.finalized_hash.to_vec(),
+        signature: header.signature.to_vec(),
+    }
+}
+
+fn block_message(block: &Block) -> proto::Block {
+    proto::Block {
+        header: Some(header_message(&block.header)),
+        transactions: block.transactions.iter().map(transaction_message).collect(),
+    }
+}
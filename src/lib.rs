@@ -1,4 +1,33 @@
+pub mod access;
+pub mod amount;
+pub mod beacon;
 pub mod blockchain;
+pub mod client;
+pub mod contacts;
 pub mod db;
+pub mod devnet;
+pub mod error;
+pub mod events;
+pub mod finality;
+pub mod governance;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod liveness;
+pub mod logging;
+pub mod mempool;
+pub mod monetary;
 pub mod node;
+pub mod noise;
 pub mod p2p;
+pub mod params;
+pub mod proxy;
+pub mod rpc;
+pub mod signer;
+pub mod simulation;
+pub mod staking;
+pub mod stats;
+pub mod store;
+pub mod sync;
+pub mod verify;
+pub mod wallet;
+pub mod webhooks;
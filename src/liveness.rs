@@ -0,0 +1,88 @@
+//! Keeping validators that stop proposing out of the way. Each slot has
+//! one proposer, picked from the validators by weight with a seed from
+//! [`crate::beacon`]; a validator whose node is down keeps being picked and
+//! the chain stalls for its slots.
+//!
+//! A block records no slot, so the slots that passed without a block are
+//! counted from the gap between its timestamp and its parent's, and
+//! charged to the validators that were picked for them. At the end of an
+//! epoch the validators that missed more than
+//! [`Liveness::max_missed`](crate::params::Liveness::max_missed) slots are
+//! jailed, left out of selection, and lose a small share of their and their
+//! delegators' stake. A jailed validator returns with an `Unjail`
+//! transaction once its node is back.
+
+use crate::blockchain::{Address, Hash, User};
+use crate::staking::Validator;
+use sha2::{Digest, Sha256};
+use std::ops::Range;
+use std::time::Duration;
+
+/// The slot a block stamped `timestamp`, in seconds, was proposed in.
+pub fn slot(timestamp: i64, block_time: Duration) -> u64 {
+    let slot_millis = block_time.as_millis().max(1) as u64;
+    (timestamp.max(0) as u64).saturating_mul(1_000) / slot_millis
+}
+
+/// The slots that passed without a block between a parent stamped
+/// `parent_timestamp` and its child stamped `timestamp`, the first `limit`
+/// of them at most.
+pub fn missed_slots(
+    parent_timestamp: i64,
+    timestamp: i64,
+    block_time: Duration,
+    limit: u64,
+) -> Range<u64> {
+    let first = slot(parent_timestamp, block_time) + 1;
+    let end = slot(timestamp, block_time).max(first);
+    first..end.min(first.saturating_add(limit))
+}
+
+/// The validator chosen to propose in `slot`: a pick weighted by own and
+/// delegated stake, seeded by `seed` and the slot, so every node with the
+/// same state agrees on it. `None` if no one has weight.
+pub fn slot_proposer(validators: &[Validator], seed: &Hash, slot: u64) -> Option<Address> {
+    let total: u128 = validators
+        .iter()
+        .map(|validator| validator.weight().base_units())
+        .sum();
+    if total == 0 {
+        return None;
+    }
+
+    let mut hasher = Sha256::new();
+    hasher.update(seed);
+    hasher.update(slot.to_be_bytes());
+    let seed: Hash = hasher.finalize().into();
+    let mut target = u128::from_be_bytes(seed[..16].try_into().unwrap()) % total;
+    for validator in validators {
+        let weight = validator.weight().base_units();
+        if target < weight {
+            return Some(validator.user.address);
+        }
+        target -= weight;
+    }
+    unreachable!("target is below the total weight")
+}
+
+/// Whether the block at `height` is the last of its epoch, which tallies
+/// the slots missed in it.
+pub fn ends_epoch(height: u64, epoch_length: u64) -> bool {
+    epoch_length > 0 && (height + 1).is_multiple_of(epoch_length)
+}
+
+/// The accounts among `absent` that missed more than `max_missed` slots
+/// and are jailed for it. None are if that would jail every one of
+/// `validators`, as no one would be left to propose the `Unjail`
+/// transactions.
+pub fn to_jail(absent: &[User], validators: &[Validator], max_missed: u32) -> Vec<Address> {
+    let jailed: Vec<Address> = absent
+        .iter()
+        .filter(|user| !user.jailed && user.missed_slots > max_missed)
+        .map(|user| user.address)
+        .collect();
+    let remaining = validators
+        .iter()
+        .any(|validator| !jailed.contains(&validator.user.address));
+    if remaining { jailed } else { Vec::new() }
+}
@@ -0,0 +1,20 @@
+use tracing_subscriber::EnvFilter;
+
+/// Installs the global subscriber, writing to stderr. `RUST_LOG` takes
+/// precedence over `level`, which accepts the same directives, e.g.
+/// `info,smvblock::p2p=debug`.
+pub fn init(level: &str, json: bool) -> Result<(), String> {
+    let filter = EnvFilter::try_from_default_env()
+        .or_else(|_| EnvFilter::try_new(level))
+        .map_err(|e| format!("Invalid log filter: {}", e))?;
+
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr);
+    let result = if json {
+        builder.json().try_init()
+    } else {
+        builder.try_init()
+    };
+    result.map_err(|e| format!("Failed to initialize logging: {}", e))
+}
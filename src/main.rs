@@ -1,111 +1,1453 @@
-use clap::{Parser, Subcommand};
+use clap::error::ErrorKind;
+use clap::{CommandFactory, Parser, Subcommand};
 use ed25519_dalek::SigningKey;
-use rustyline::Editor;
+use futures::lock::Mutex;
+use rustyline::completion::Completer;
 use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::validate::Validator;
+use rustyline::{Editor, Helper};
+use serde_json::Value;
 use smvblock::{
-    blockchain::User,
-    node::{Node, NodeType},
+    access::{Listener, RateLimit, Role, RpcAccess, Token},
+    amount::Amount,
+    blockchain::{Address, Block, Blockchain, Hash, Transaction, TxKind, User},
+    client::{BlockId, RpcClient, parse_hash},
+    db::{self, Database},
+    devnet::{DEVNET_KEYS_FILE, Devnet, DevnetConfig},
+    error::RpcError,
+    logging,
+    mempool::MempoolConfig,
+    node::{BackupConfig, Node, NodeType},
+    p2p::{ConnectionLimits, DEFAULT_CHAIN_ID, DiscoveryConfig},
+    params::{ChainParams, Feature, FeeRouting, ForkSchedule},
+    proxy::Socks5Proxy,
+    signer::{self, BlockSigner, RemoteSigner, SignerEndpoint},
+    sync::PRUNE_DEPTH,
+    wallet::{Wallet, key_address, read_password},
+    webhooks::WebhookConfig,
 };
 use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
 
-fn decode_address(hex_str: &str) -> [u8; 32] {
-    let bytes = hex::decode(hex_str).expect("Invalid hex string");
-    bytes.try_into().expect("Expected 32-byte address")
+#[derive(Parser)]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// Directory to keep the node's data in, with a subdirectory for each
+    /// chain ID; `~/.smvblock` when omitted.
+    #[arg(long, env = "SMVBLOCK_DATA_DIR")]
+    data_dir: Option<PathBuf>,
+    /// Address to accept peer connections on; may be given more than once,
+    /// for example as `0.0.0.0:4001` and `[::]:4001`.
+    #[arg(long, default_value = "127.0.0.1:0")]
+    listen: Vec<SocketAddr>,
+    /// Address other nodes can reach this one on, advertised to peers in
+    /// place of the first listen address.
+    #[arg(long, conflicts_with = "no_listen")]
+    external_addr: Option<SocketAddr>,
+    /// Accept no connections and only dial out, so peers never learn an
+    /// address to reach this node on.
+    #[arg(long, conflicts_with_all = ["listen", "mdns"])]
+    no_listen: bool,
+    /// Dial peers through a SOCKS5 proxy such as Tor, as
+    /// `socks5://HOST:PORT`.
+    #[arg(long)]
+    proxy: Option<Socks5Proxy>,
+    /// Peer to connect to on startup; may be given more than once.
+    #[arg(long = "peer")]
+    peers: Vec<SocketAddr>,
+    /// Outbound connections peer discovery keeps open.
+    #[arg(long, default_value_t = DiscoveryConfig::default().outbound_target)]
+    outbound_peers: usize,
+    /// Chain to join; peers on any other are disconnected.
+    #[arg(long, default_value = DEFAULT_CHAIN_ID)]
+    chain_id: String,
+    /// Most peers accepted from other nodes.
+    #[arg(long, default_value_t = ConnectionLimits::default().max_inbound)]
+    max_inbound: usize,
+    /// Most connections opened to other nodes.
+    #[arg(long, default_value_t = ConnectionLimits::default().max_outbound)]
+    max_outbound: usize,
+    /// Node ID of a seed or validator that is always let in, outside the
+    /// limits; may be given more than once.
+    #[arg(long = "reserved-peer", value_parser = parse_node_id)]
+    reserved_peers: Vec<[u8; 32]>,
+    /// Do not offer compression to peers.
+    #[arg(long)]
+    no_compression: bool,
+    /// Bytes per second each peer may send before the node slows down
+    /// reading from it.
+    #[arg(long)]
+    max_peer_bandwidth: Option<u64>,
+    /// What the node keeps: `full` keeps every block, `pruned` only the
+    /// latest ones and the current state, `light` only block headers. A
+    /// `seed` keeps headers too and crawls the network for peers to hand
+    /// out.
+    #[arg(long, default_value = "full")]
+    node_type: NodeType,
+    /// Blocks a pruned node keeps whole.
+    #[arg(long, default_value_t = PRUNE_DEPTH)]
+    keep_blocks: u64,
+    /// Replay every block from genesis instead of starting from a peer's
+    /// state snapshot.
+    #[arg(long)]
+    no_snapshot: bool,
+    /// Trusted block as `HEIGHT:HASH`; sync rejects chains without it and
+    /// skips full validation of the blocks up to it.
+    #[arg(long, value_parser = parse_checkpoint)]
+    checkpoint: Option<(u64, [u8; 32])>,
+    /// Activate a consensus feature at a height, as `FEATURE=HEIGHT`;
+    /// repeatable. Unscheduled features are active from genesis. Every
+    /// node on the chain must give the same schedule.
+    #[arg(long = "fork", value_parser = parse_fork)]
+    forks: Vec<(Feature, u64)>,
+    /// Basis points of each block's fees to burn, unless governance sets
+    /// otherwise. Every node on the chain must give the same.
+    #[arg(long, default_value_t = 0)]
+    fee_burn: u16,
+    /// Basis points of each block's fees to pay to the treasury, unless
+    /// governance sets otherwise. Every node on the chain must give the
+    /// same.
+    #[arg(long, default_value_t = 0)]
+    fee_treasury: u16,
+    /// Find peers on the local network over mDNS; pair with a `--listen`
+    /// address other machines can reach.
+    #[arg(long)]
+    mdns: bool,
+    /// Propose blocks automatically in the slots this validator is chosen
+    /// for.
+    #[arg(long, requires = "signer")]
+    validator: bool,
+    /// File holding the validator's hex-encoded 32-byte signing key.
+    #[arg(long, group = "signer")]
+    validator_key: Option<PathBuf>,
+    /// Remote signer holding the validator key instead, as
+    /// `http://HOST:PORT` or `unix:PATH`.
+    #[arg(long, group = "signer")]
+    remote_signer: Option<SignerEndpoint>,
+    /// Length of a block production slot, in seconds. Missed slots are
+    /// counted by it, so every node on the chain must use the same.
+    #[arg(long, default_value_t = 5)]
+    block_time: u64,
+    /// Fee, in SMV, transactions must pay per byte of their encoding to
+    /// enter the mempool and be relayed.
+    #[arg(long, default_value_t = MempoolConfig::default().min_fee_per_byte)]
+    min_relay_fee: Amount,
+    /// Most pending transactions the mempool holds for one sender.
+    #[arg(long, default_value_t = MempoolConfig::default().max_pending_per_sender)]
+    max_pending_per_sender: usize,
+    /// Directory to back the database up into while running; disabled
+    /// when omitted.
+    #[arg(long)]
+    backup_dir: Option<PathBuf>,
+    /// Time between backups, in seconds.
+    #[arg(long, default_value_t = 3600)]
+    backup_interval: u64,
+    /// Backups kept in the backup directory; older ones are deleted.
+    #[arg(long, default_value_t = 24)]
+    backup_keep: usize,
+    /// Address to serve the JSON-RPC API on; disabled when omitted.
+    #[arg(long)]
+    rpc_addr: Option<SocketAddr>,
+    /// A further JSON-RPC listener serving only some roles' methods, as
+    /// `ROLES@ADDR` such as `wallet,admin@127.0.0.1:8546`. Roles are public,
+    /// wallet and admin. Repeatable.
+    #[arg(long = "rpc-listener")]
+    rpc_listeners: Vec<Listener>,
+    /// A bearer token as `ROLE:TOKEN`. Once any token or a JWT secret is
+    /// set, wallet and admin methods need a credential granting their role;
+    /// until then only callers on this host may call them.
+    #[arg(
+        long = "rpc-token",
+        env = "SMVBLOCK_RPC_TOKENS",
+        value_delimiter = ',',
+        hide_env_values = true
+    )]
+    rpc_tokens: Vec<Token>,
+    /// File holding the secret HS256 JWTs are signed with; a JWT grants the
+    /// role in its `role` claim.
+    #[arg(long)]
+    rpc_jwt_secret_file: Option<PathBuf>,
+    /// An origin browsers may call the RPC server from, or `*` for any.
+    /// Repeatable.
+    #[arg(long = "rpc-cors-origin")]
+    rpc_cors_origins: Vec<String>,
+    /// JSON-RPC methods to serve, as names or prefixes ending in `*` such
+    /// as `chain_*`; every other method is refused on every listener.
+    #[arg(long = "rpc-allow-method", value_delimiter = ',')]
+    rpc_allowed_methods: Vec<String>,
+    /// JSON-RPC methods to refuse on every listener, in the same form.
+    #[arg(long = "rpc-deny-method", value_delimiter = ',')]
+    rpc_denied_methods: Vec<String>,
+    /// JSON-RPC requests per second each caller may make, counted by
+    /// credential for callers presenting one and by IP address otherwise.
+    #[arg(long, value_parser = parse_rate)]
+    rpc_rate_limit: Option<f64>,
+    /// Requests a caller may make at once before `--rpc-rate-limit` applies;
+    /// defaults to one second's worth.
+    #[arg(long, requires = "rpc_rate_limit", value_parser = parse_rate)]
+    rpc_rate_burst: Option<f64>,
+    /// Address to serve the gRPC API on; disabled when omitted.
+    #[cfg(feature = "grpc")]
+    #[arg(long)]
+    grpc_addr: Option<SocketAddr>,
+    /// Log filter such as `info` or `warn,smvblock::p2p=debug`; `RUST_LOG`
+    /// overrides it.
+    #[arg(long, default_value = "info")]
+    log_level: String,
+    /// Emit logs as JSON lines.
+    #[arg(long)]
+    log_json: bool,
+    /// REPL commands to run instead of prompting, separated by `;`.
+    #[arg(long)]
+    exec: Option<String>,
 }
 
-#[tokio::main]
-async fn main() {
-    let mut node = Node::new(NodeType::FullNode, true).unwrap();
-    let mut users: HashMap<String, (User, SigningKey)> = HashMap::new();
-    let mut rl = Editor::<(), rustyline::history::FileHistory>::new().unwrap();
-
-    println!("Welcome to smvblock REPL.");
-    println!("Type `help` for commands, `exit` to quit.");
-
-    loop {
-        let line = rl.readline("smvblock> ");
-        match line {
-            Ok(input) => {
-                let input = input.trim();
-                rl.add_history_entry(input);
-
-                if input == "exit" {
-                    println!("Exiting smvblock REPL.");
-                    break;
-                } else if input == "help" {
-                    println!("Commands:");
-                    println!("  add-user <balance>");
-                    println!("  stake <address> <amount>");
-                    println!("  transact <from> <to> <amount>");
-                    println!("  produce-block");
-                    println!("  show-users");
-                    println!("  exit");
-                } else if input.starts_with("add-user ") {
-                    let parts: Vec<&str> = input.split_whitespace().collect();
-                    if parts.len() != 2 {
-                        println!("Usage: add-user <balance>");
-                        continue;
-                    }
-                    let balance: u64 = parts[1].parse().expect("Invalid balance");
-                    let (user, pk) = User::generate(balance);
-                    let addr_hex = hex::encode(user.address);
-
-                    node.add_user(user.clone()).await.unwrap();
-                    users.insert(addr_hex.clone(), (user, pk));
-
-                    println!("Added user with address: {}", addr_hex);
-                } else if input.starts_with("stake ") {
-                    let parts: Vec<&str> = input.split_whitespace().collect();
-                    if parts.len() != 3 {
-                        println!("Usage: stake <address> <amount>");
-                        continue;
-                    }
-                    let address = decode_address(parts[1]);
-                    let amount: u64 = parts[2].parse().expect("Invalid amount");
-
-                    node.stake(address, amount).await.unwrap();
-                    println!("Staked {} tokens for {}", amount, parts[1]);
-                } else if input.starts_with("transact ") {
-                    let parts: Vec<&str> = input.split_whitespace().collect();
-                    if parts.len() != 4 {
-                        println!("Usage: transact <from> <to> <amount>");
-                        continue;
+#[derive(Subcommand)]
+enum Command {
+    /// Run the node and the REPL commands in a script, one per line, then
+    /// exit; fails on the first command that does.
+    Run { script: PathBuf },
+    /// Maintain the node's database instead of running the node.
+    Db {
+        #[command(subcommand)]
+        command: DbCommand,
+    },
+    /// Run a local network of validators with funded, named accounts
+    /// until interrupted.
+    Devnet {
+        /// Validating nodes to run.
+        #[arg(long, default_value_t = 4)]
+        nodes: usize,
+        /// Length of a block production slot, in milliseconds.
+        #[arg(long, default_value_t = 1000)]
+        block_time: u64,
+        /// Directory for the nodes' databases and the account keys; a new
+        /// temporary one when omitted.
+        #[arg(long)]
+        dir: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand)]
+enum DbCommand {
+    /// Copy the database to a file; safe while the node is running.
+    Backup { path: PathBuf },
+    /// Replace the database with a backup; stop the node first.
+    Restore { path: PathBuf },
+    /// Check every stored block and the accounts for corruption.
+    Verify,
+    /// Rebuild the indexes and what else is derived from the stored blocks.
+    Reindex,
+}
+
+/// Starts a devnet, prints its accounts and RPC addresses, and runs it until
+/// interrupted.
+async fn run_devnet(nodes: usize, block_time: u64, dir: Option<PathBuf>) -> Result<(), String> {
+    let mut config = DevnetConfig {
+        nodes,
+        block_time: Duration::from_millis(block_time.max(1)),
+        ..DevnetConfig::default()
+    };
+    if let Some(dir) = dir {
+        config.dir = dir;
+    }
+    let keys_file = config.dir.join(DEVNET_KEYS_FILE);
+    let devnet = Devnet::start(config).await?;
+
+    println!("Accounts, with keys in {}:", keys_file.display());
+    for account in devnet.accounts() {
+        println!("  {:<8} {}", account.name, hex::encode(account.address()));
+    }
+    for index in 0..devnet.len() {
+        if let Some(node) = devnet.node(index) {
+            let rpc_addr = node.start_rpc("127.0.0.1:0".parse().unwrap()).await?;
+            println!("Node {} serving JSON-RPC on {}", index, rpc_addr);
+        }
+    }
+
+    let _ = tokio::signal::ctrl_c().await;
+    devnet.shutdown().await
+}
+
+/// Opens the database of the chain `chain_id` under `data_dir`, creating
+/// its directory on first use.
+fn open_database(data_dir: Option<PathBuf>, chain_id: &str) -> Result<Database, String> {
+    let data_dir = data_dir.unwrap_or_else(db::default_data_dir);
+    let path = db::database_path(&data_dir, chain_id)?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)
+            .map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+    }
+    Database::new(path.to_str(), false)
+        .map_err(|e| format!("Failed to open {}: {}", path.display(), e))
+}
+
+/// Runs a `db` subcommand against the database the node uses.
+async fn run_db_command(
+    command: DbCommand,
+    database: Database,
+    node_type: NodeType,
+) -> Result<(), String> {
+    let database = Arc::new(Mutex::new(database));
+    let blockchain = Blockchain::new(database.clone());
+    match command {
+        DbCommand::Backup { path } => {
+            database
+                .lock()
+                .await
+                .backup(&path)
+                .map_err(|e| format!("Backup failed: {}", e))?;
+            println!("Backed up the database to {}", path.display());
+        }
+        DbCommand::Restore { path } => {
+            database
+                .lock()
+                .await
+                .restore(&path)
+                .map_err(|e| format!("Restore failed: {}", e))?;
+            println!("Restored the database from {}", path.display());
+        }
+        DbCommand::Verify => {
+            let headers_only = matches!(node_type, NodeType::LightNode | NodeType::SeedNode);
+            let report = blockchain.verify_chain(headers_only).await?;
+            for problem in &report.problems {
+                println!("{}", problem);
+            }
+            if !report.problems.is_empty() {
+                return Err(format!(
+                    "Found {} problems in {} blocks",
+                    report.problems.len(),
+                    report.blocks
+                ));
+            }
+            println!("Checked {} blocks; no problems found", report.blocks);
+        }
+        DbCommand::Reindex => {
+            let placed = blockchain
+                .reindex()
+                .await
+                .map_err(|e| format!("Reindex failed: {}", e))?;
+            println!("Reindexed the database and placed {} transactions", placed);
+        }
+    }
+    Ok(())
+}
+
+/// Blocks `show-chain` prints when not given a range.
+const SHOW_CHAIN_DEFAULT: u64 = 10;
+
+/// How long `produce-block` waits for a remote node's validators.
+const REMOTE_BLOCK_WAIT: Duration = Duration::from_secs(60);
+
+fn print_transaction(tx: &Transaction) {
+    let kind = match tx.payload.kind {
+        TxKind::Transfer => String::new(),
+        kind => format!(" ({})", kind.as_str()),
+    };
+    println!(
+        "    {}  {} -> {}  {}{} fee {} nonce {}",
+        hex::encode(tx.hash()),
+        hex::encode(tx.sender_address()),
+        hex::encode(tx.payload.receiver),
+        tx.payload.amount,
+        kind,
+        tx.payload.fee,
+        tx.payload.nonce
+    );
+}
+
+fn print_block(block: &Block) {
+    let header = &block.header;
+    println!("Block {} {}", header.height, hex::encode(block.hash()));
+    println!("  Previous:     {}", hex::encode(header.previous_hash));
+    println!("  Time:         {}", block.get_datetime().to_rfc3339());
+    println!("  Proposer:     {}", hex::encode(header.proposer));
+    println!("  Coinbase:     {}", header.coinbase);
+    println!("  Transactions: {}", block.transactions.len());
+    for tx in &block.transactions {
+        print_transaction(tx);
+    }
+}
+
+/// A block as a remote node's RPC server gives it, printed like
+/// [`print_block`] prints a local one.
+fn print_remote_block(block: &Value) {
+    let field = |name: &str| match &block[name] {
+        Value::String(s) => s.clone(),
+        Value::Null => "-".to_string(),
+        value => value.to_string(),
+    };
+    println!("Block {} {}", field("height"), field("hash"));
+    println!("  Previous:     {}", field("previous_hash"));
+    let time = block["timestamp"]
+        .as_i64()
+        .and_then(|timestamp| chrono::DateTime::from_timestamp(timestamp, 0));
+    match time {
+        Some(time) => println!("  Time:         {}", time.to_rfc3339()),
+        None => println!("  Time:         -"),
+    }
+    println!("  Proposer:     {}", field("proposer"));
+    println!("  Coinbase:     {}", field("coinbase"));
+    let transactions = block["transactions"]
+        .as_array()
+        .cloned()
+        .unwrap_or_default();
+    println!("  Transactions: {}", transactions.len());
+    for tx in &transactions {
+        let kind = match tx["kind"].as_str() {
+            Some("transfer") | None => String::new(),
+            Some(kind) => format!(" ({})", kind),
+        };
+        println!(
+            "    {}  {} -> {}  {}{} fee {} nonce {}",
+            tx["hash"].as_str().unwrap_or("-"),
+            tx["sender"].as_str().unwrap_or("-"),
+            tx["receiver"].as_str().unwrap_or("-"),
+            tx["amount"].as_str().unwrap_or("-"),
+            kind,
+            tx["fee"].as_str().unwrap_or("-"),
+            tx["nonce"]
+        );
+    }
+}
+
+/// The block at `height` with its transactions.
+async fn full_block_at(node: &Node, height: u64) -> Result<Option<Block>, String> {
+    let Some(block) = node
+        .blockchain
+        .get_block_by_height(height)
+        .await
+        .map_err(|e| e.to_string())?
+    else {
+        return Ok(None);
+    };
+    node.blockchain
+        .get_full_block(block.hash())
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// A line typed at the REPL, parsed with the command as its first word.
+#[derive(Parser)]
+#[command(
+    multicall = true,
+    about = None,
+    long_about = None,
+    after_help = "Accounts can be given by address or saved wallet name."
+)]
+struct ReplLine {
+    #[command(subcommand)]
+    command: ReplCommand,
+}
+
+/// The REPL's commands. Accounts can be given by address or by the name
+/// of a saved wallet account.
+#[derive(Subcommand)]
+enum ReplCommand {
+    /// Create an account with a balance and keep its key for this session.
+    AddUser { balance: Amount },
+    /// Move part of an account's balance into its stake.
+    Stake { account: String, amount: Amount },
+    /// Send a transfer signed with a key held in this session.
+    Transact {
+        from: String,
+        to: String,
+        amount: Amount,
+        fee: Option<Amount>,
+    },
+    /// Replace a pending transaction with the same one at a higher fee.
+    BumpFee {
+        from: String,
+        nonce: u64,
+        fee: Amount,
+    },
+    /// Produce a block from the pending transactions.
+    ProduceBlock,
+    /// Produce blocks in the background every few seconds, when there are
+    /// transactions pending.
+    AutoProduce {
+        seconds: u64,
+        /// Produce blocks even when no transactions are pending.
+        #[arg(long)]
+        always: bool,
+    },
+    /// Stop producing blocks in the background.
+    StopAutoProduce,
+    /// Vote for a block, the latest one unless given.
+    Attest {
+        account: String,
+        #[arg(value_parser = parse_hash)]
+        block_hash: Option<Hash>,
+    },
+    /// List every account with its balance and stake.
+    ShowUsers,
+    /// Show how many transactions the mempool has turned away.
+    MempoolStats,
+    /// Show a block and its transactions, the latest one unless given.
+    ShowBlock {
+        /// Height or hash of the block.
+        block: Option<String>,
+    },
+    /// Show a range of blocks, the last ten unless given.
+    ShowChain { from: Option<u64>, to: Option<u64> },
+    /// List the pending transactions.
+    ShowMempool,
+    /// Keep account keys encrypted in the data directory across sessions.
+    #[command(subcommand)]
+    Wallet(ReplWalletCommand),
+    /// Drive a running node over its RPC server instead of the local one.
+    Connect {
+        /// The node's RPC address, as http://HOST:PORT.
+        url: String,
+    },
+    /// Go back to the local node.
+    Disconnect,
+    /// Leave the REPL and shut the node down.
+    Exit,
+}
+
+#[derive(Subcommand)]
+enum ReplWalletCommand {
+    /// Save the key of an account in this session under a name.
+    Save { account: String, name: String },
+    /// Load a saved key into this session.
+    Load { name: String },
+    /// List the saved accounts.
+    List,
+}
+
+/// Whether the REPL goes on after a command.
+enum Flow {
+    Continue,
+    Exit,
+}
+
+/// Blocks produced in the background on a timer, until stopped.
+struct AutoProducer {
+    stop: watch::Sender<bool>,
+    task: JoinHandle<()>,
+}
+
+impl AutoProducer {
+    fn start(mut node: Node, interval: Duration, always: bool) -> Self {
+        let (stop, mut stopped) = watch::channel(false);
+        let task = tokio::spawn(async move {
+            let mut timer = tokio::time::interval_at(Instant::now() + interval, interval);
+            loop {
+                tokio::select! {
+                    _ = timer.tick() => {}
+                    _ = stopped.changed() => break,
+                }
+                if !always && node.blockchain.pending_count().await == 0 {
+                    continue;
+                }
+                match node.produce_block().await {
+                    Ok(hash) => println!("Produced block: {}", hex::encode(hash)),
+                    Err(e) => eprintln!("Failed to produce block: {}", e),
+                }
+            }
+        });
+        AutoProducer { stop, task }
+    }
+
+    /// Stops the timer, letting a block being produced finish so its
+    /// transactions are not lost.
+    async fn stop(self) {
+        self.stop.send_replace(true);
+        let _ = self.task.await;
+    }
+}
+
+/// Completes the REPL's commands, and accounts in their arguments.
+struct ReplHelper {
+    /// Addresses with keys in the session and saved wallet names, kept up
+    /// to date by the session.
+    accounts: Arc<std::sync::Mutex<Vec<String>>>,
+}
+
+impl Completer for ReplHelper {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _: &rustyline::Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        let line = &line[..pos];
+        let start = line.rfind(char::is_whitespace).map_or(0, |i| i + 1);
+        let word = &line[start..];
+        let before: Vec<&str> = line[..start].split_whitespace().collect();
+        let command = ReplLine::command();
+        let candidates: Vec<String> = match before.as_slice() {
+            [] => command
+                .get_subcommands()
+                .map(|command| command.get_name().to_string())
+                .collect(),
+            [name]
+                if command
+                    .find_subcommand(name)
+                    .is_some_and(|c| c.has_subcommands()) =>
+            {
+                command
+                    .find_subcommand(name)
+                    .into_iter()
+                    .flat_map(|command| command.get_subcommands())
+                    .map(|command| command.get_name().to_string())
+                    .collect()
+            }
+            _ => self
+                .accounts
+                .lock()
+                .map(|accounts| accounts.clone())
+                .unwrap_or_default(),
+        };
+        let mut matches: Vec<String> = candidates
+            .into_iter()
+            .filter(|candidate| candidate.starts_with(word))
+            .collect();
+        matches.sort();
+        Ok((start, matches))
+    }
+}
+
+impl Hinter for ReplHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ReplHelper {}
+
+impl Validator for ReplHelper {}
+
+impl Helper for ReplHelper {}
+
+/// What the REPL keeps between commands: the node it drives and the keys
+/// of the accounts added or loaded so far.
+struct Session {
+    node: Node,
+    users: HashMap<String, (User, SigningKey)>,
+    wallet: Wallet,
+    /// What account arguments complete to at the prompt.
+    completions: Arc<std::sync::Mutex<Vec<String>>>,
+    auto_producer: Option<AutoProducer>,
+    /// The node commands go to instead of the local one, once connected.
+    remote: Option<RpcClient>,
+}
+
+impl Session {
+    fn new(node: Node, wallet: Wallet) -> Self {
+        let session = Session {
+            node,
+            users: HashMap::new(),
+            wallet,
+            completions: Arc::default(),
+            auto_producer: None,
+            remote: None,
+        };
+        session.update_completions();
+        session
+    }
+
+    fn update_completions(&self) {
+        let mut accounts: Vec<String> = self.users.keys().cloned().collect();
+        if let Ok(saved) = self.wallet.list() {
+            accounts.extend(saved.into_iter().map(|(name, _)| name));
+        }
+        if let Ok(mut completions) = self.completions.lock() {
+            *completions = accounts;
+        }
+    }
+
+    /// Stops producing blocks in the background, returning whether it was.
+    async fn stop_auto_produce(&mut self) -> bool {
+        match self.auto_producer.take() {
+            Some(producer) => {
+                producer.stop().await;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// An account given as a hex address or the name of a saved wallet
+    /// account.
+    fn resolve_account(&self, account: &str) -> Result<Address, String> {
+        parse_hash(account).or_else(|_| self.wallet.address(account))
+    }
+
+    /// The key of an account added or loaded in this session.
+    fn key(&self, account: &str) -> Result<SigningKey, String> {
+        let address = self.resolve_account(account)?;
+        self.users
+            .get(&hex::encode(address))
+            .map(|(_, key)| key.clone())
+            .ok_or(format!(
+                "No key for {} in this session; load it with `wallet load`",
+                account
+            ))
+    }
+
+    /// The account `key` signs for, which must exist on the chain commands
+    /// go to.
+    async fn user_for(&self, key: &SigningKey) -> Result<User, String> {
+        let address = key_address(key);
+        let missing = || {
+            format!(
+                "Account {} does not exist on this chain",
+                hex::encode(address)
+            )
+        };
+        match &self.remote {
+            Some(rpc) => match rpc.balance(&address).await {
+                Ok(account) => Ok(User {
+                    address,
+                    public_key: key.verifying_key().to_bytes(),
+                    balance: account.balance,
+                    stake: account.stake,
+                    delegate: None,
+                    commission_bps: 0,
+                    missed_slots: 0,
+                    jailed: false,
+                }),
+                Err(RpcError::Rejected { .. }) => Err(missing()),
+                Err(e) => Err(e.into()),
+            },
+            None => self
+                .node
+                .blockchain
+                .get_user(&address)
+                .await
+                .map_err(|e| e.to_string())?
+                .ok_or_else(missing),
+        }
+    }
+
+    /// Parses and runs one line, failing with what went wrong or how the
+    /// command is used.
+    async fn execute(&mut self, input: &str) -> Result<Flow, String> {
+        let words: Vec<&str> = input.split_whitespace().collect();
+        if words.is_empty() {
+            return Ok(Flow::Continue);
+        }
+        let name = words[0];
+        let command = match ReplLine::try_parse_from(words) {
+            Ok(line) => line.command,
+            Err(e) if matches!(e.kind(), ErrorKind::DisplayHelp | ErrorKind::DisplayVersion) => {
+                let _ = e.print();
+                return Ok(Flow::Continue);
+            }
+            Err(e) => {
+                // Without clap's prefix and its pointer to `--help`, as the
+                // REPL reports errors its own way.
+                let message = e.render().to_string();
+                let message = message
+                    .split("\n\nFor more information")
+                    .next()
+                    .unwrap_or_default();
+                return Err(message.trim_start_matches("error: ").trim_end().to_string());
+            }
+        };
+        let flow = self.run(name, command).await;
+        self.update_completions();
+        flow
+    }
+
+    async fn run(&mut self, name: &str, command: ReplCommand) -> Result<Flow, String> {
+        let node = &mut self.node;
+        match command {
+            ReplCommand::Exit => {
+                println!("Exiting smvblock REPL.");
+                return Ok(Flow::Exit);
+            }
+            ReplCommand::Connect { url } => {
+                let rpc: RpcClient = url.parse()?;
+                let status = rpc.status().await?;
+                self.stop_auto_produce().await;
+                match status["height"].as_u64() {
+                    Some(height) => println!("Connected to {} at height {}", rpc, height),
+                    None => println!("Connected to {} with no blocks yet", rpc),
+                }
+                self.remote = Some(rpc);
+            }
+            ReplCommand::Disconnect => {
+                let rpc = self.remote.take().ok_or("Not connected to a remote node")?;
+                println!("Disconnected from {}; back on the local node", rpc);
+            }
+            ReplCommand::Wallet(ReplWalletCommand::Save { account, name }) => {
+                let key = self.key(&account)?;
+                let address = self.wallet.import(&name, &key, &read_password(true)?)?;
+                println!("Saved {} as {}", hex::encode(address), name);
+            }
+            ReplCommand::Wallet(ReplWalletCommand::Load { name }) => {
+                let key = self.wallet.unlock(&name, &read_password(false)?)?;
+                let address = key_address(&key);
+                let user = self.user_for(&key).await?;
+                self.users.insert(hex::encode(address), (user, key));
+                println!("Loaded {} as {}", hex::encode(address), name);
+            }
+            ReplCommand::Wallet(ReplWalletCommand::List) => {
+                for (name, address) in self.wallet.list()? {
+                    let address = hex::encode(address);
+                    let loaded = if self.users.contains_key(&address) {
+                        " (loaded)"
+                    } else {
+                        ""
+                    };
+                    println!("{}  {}{}", address, name, loaded);
+                }
+            }
+            command if self.remote.is_some() => return self.run_remote(name, command).await,
+            ReplCommand::AddUser { balance } => {
+                let (user, pk) = User::generate(balance);
+                let addr_hex = hex::encode(user.address);
+
+                node.add_user(user.clone())
+                    .await
+                    .map_err(|e| e.to_string())?;
+                self.users.insert(addr_hex.clone(), (user, pk));
+
+                println!("Added user with address: {}", addr_hex);
+            }
+            ReplCommand::Stake { account, amount } => {
+                let address = self.resolve_account(&account)?;
+
+                self.node.stake(address, amount).await?;
+                println!("Staked {} for {}", amount, account);
+            }
+            ReplCommand::Transact {
+                from,
+                to,
+                amount,
+                fee,
+            } => {
+                let receiver = self.resolve_account(&to)?;
+                let fee = fee.unwrap_or(Amount::ZERO);
+                let pk = self.key(&from)?;
+
+                self.node
+                    .send_transaction_with_fee(pk, receiver, amount, fee)
+                    .await?;
+                println!("Sent {} from {} to {}", amount, from, to);
+            }
+            ReplCommand::BumpFee { from, nonce, fee } => {
+                let pk = self.key(&from)?;
+
+                self.node.bump_fee(pk, nonce, fee).await?;
+                println!(
+                    "Replaced transaction {} from {} with fee {}",
+                    nonce, from, fee
+                );
+            }
+            ReplCommand::ProduceBlock => {
+                let hash = node.produce_block().await?;
+                println!("Produced block: {}", hex::encode(hash));
+            }
+            ReplCommand::AutoProduce { seconds, always } => {
+                self.stop_auto_produce().await;
+                let node = self.node.clone();
+                let seconds = seconds.max(1);
+                let interval = Duration::from_secs(seconds);
+                self.auto_producer = Some(AutoProducer::start(node, interval, always));
+                let when = if always {
+                    ""
+                } else {
+                    " with transactions pending"
+                };
+                println!("Producing a block every {}s{}", seconds, when);
+            }
+            ReplCommand::StopAutoProduce => {
+                if !self.stop_auto_produce().await {
+                    return Err("Blocks are not being produced automatically".to_string());
+                }
+                println!("Stopped producing blocks automatically");
+            }
+            ReplCommand::Attest {
+                account,
+                block_hash,
+            } => {
+                let key = self.key(&account)?;
+                let block_hash = match block_hash {
+                    Some(hash) => hash,
+                    None => self
+                        .node
+                        .blockchain
+                        .get_latest_block()
+                        .await
+                        .map_err(|e| e.to_string())?
+                        .ok_or("No blocks to attest to")?
+                        .hash(),
+                };
+
+                let outcome = self.node.attest(&key, block_hash).await?;
+                println!("Vote for {}: {:?}", hex::encode(block_hash), outcome);
+            }
+            ReplCommand::ShowUsers => {
+                let all_users = node.get_users().await.map_err(|e| e.to_string())?;
+                for user in all_users {
+                    println!(
+                        "User: {}, Balance: {}, Stake: {}",
+                        hex::encode(user.address),
+                        user.balance,
+                        user.stake
+                    );
+                }
+            }
+            ReplCommand::ShowBlock { block } => {
+                let block = match block {
+                    None => match node.blockchain.chain_head().get() {
+                        Some((_, height)) => full_block_at(node, height).await?,
+                        None => None,
+                    },
+                    Some(block) => match block.parse::<u64>() {
+                        Ok(height) => full_block_at(node, height).await?,
+                        Err(_) => node
+                            .blockchain
+                            .get_full_block(parse_hash(&block)?)
+                            .await
+                            .map_err(|e| e.to_string())?,
+                    },
+                };
+                print_block(&block.ok_or("No such block")?);
+            }
+            ReplCommand::ShowChain { from, to } => {
+                let Some((_, tip)) = node.blockchain.chain_head().get() else {
+                    return Err("No blocks yet".to_string());
+                };
+                let from = from.unwrap_or(tip.saturating_sub(SHOW_CHAIN_DEFAULT - 1));
+                let to = to.map_or(tip, |to| to.min(tip));
+                if from > to {
+                    return Err(format!("No blocks from {} to {}", from, to));
+                }
+                for height in from..=to {
+                    match full_block_at(node, height).await? {
+                        Some(block) => print_block(&block),
+                        None => println!("Block {} is not stored", height),
                     }
+                }
+            }
+            ReplCommand::ShowMempool => {
+                let pending = node.blockchain.get_pending_transactions().await;
+                println!("Pending transactions: {}", pending.len());
+                for tx in &pending {
+                    print_transaction(tx);
+                }
+                let queued = node.blockchain.queued_count().await;
+                if queued > 0 {
+                    println!("Waiting on earlier nonces: {}", queued);
+                }
+            }
+            ReplCommand::MempoolStats => {
+                let stats = node.blockchain.mempool_stats().await;
+                println!(
+                    "Evicted (expired): {}, Evicted (low fee): {}, Rejected (full): {}",
+                    stats.evicted_expired, stats.evicted_low_fee, stats.rejected_full
+                );
+            }
+        }
+        Ok(Flow::Continue)
+    }
 
-                    let from = parts[1];
-                    let to = decode_address(parts[2]);
-                    let amount: u64 = parts[3].parse().expect("Invalid amount");
-
-                    let (_, key) = users.get(from).expect("Sender not found");
-                    let pk = key.clone();
-
-                    node.send_transaction(pk, to, amount).await.unwrap();
-                    println!("Sent {} tokens from {} to {}", amount, from, parts[2]);
-                } else if input == "produce-block" {
-                    let hash = node.produce_block().await.unwrap();
-                    println!("Produced block: {}", hex::encode(hash));
-                } else if input == "show-users" {
-                    let all_users = node.get_users().await.unwrap();
-                    for user in all_users {
+    /// Runs `command`, called `name`, against the remote node. Accounts
+    /// there are created by its genesis, and blocks are produced by its
+    /// validators, so what only the local node can do is refused.
+    async fn run_remote(&mut self, name: &str, command: ReplCommand) -> Result<Flow, String> {
+        let Some(rpc) = self.remote.clone() else {
+            return Err("Not connected to a remote node".to_string());
+        };
+        match command {
+            ReplCommand::AddUser { .. } => {
+                return Err(
+                    "A remote node's accounts cannot be created here; load one with `wallet load`"
+                        .to_string(),
+                );
+            }
+            ReplCommand::Stake { account, amount } => {
+                let key = self.key(&account)?;
+                let hash = rpc.stake(&key, amount, Amount::ZERO).await?;
+                println!(
+                    "Submitted stake of {} for {}: {}",
+                    amount,
+                    account,
+                    hex::encode(hash)
+                );
+            }
+            ReplCommand::Transact {
+                from,
+                to,
+                amount,
+                fee,
+            } => {
+                let receiver = self.resolve_account(&to)?;
+                let key = self.key(&from)?;
+                let fee = fee.unwrap_or(Amount::ZERO);
+                let hash = rpc.transfer(&key, receiver, amount, fee).await?;
+                println!(
+                    "Sent {} from {} to {}: {}",
+                    amount,
+                    from,
+                    to,
+                    hex::encode(hash)
+                );
+            }
+            ReplCommand::ProduceBlock => {
+                // The node's validators propose blocks on their own slots;
+                // all that can be done from here is wait for the next one.
+                let height = rpc.status().await?["height"].as_u64();
+                println!("Waiting for {} to produce a block...", rpc);
+                let deadline = Instant::now() + REMOTE_BLOCK_WAIT;
+                loop {
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                    let status = rpc.status().await?;
+                    if status["height"].as_u64() > height {
                         println!(
-                            "User: {}, Balance: {}, Stake: {}",
-                            hex::encode(user.address),
-                            user.balance,
-                            user.stake
+                            "Produced block: {}",
+                            status["latest_hash"].as_str().unwrap_or("-")
                         );
+                        break;
+                    }
+                    if Instant::now() >= deadline {
+                        return Err(format!(
+                            "No block in {}s; is the node a validator?",
+                            REMOTE_BLOCK_WAIT.as_secs()
+                        ));
                     }
-                } else {
-                    println!("Unknown command. Type `help`.");
                 }
             }
-            Err(ReadlineError::Interrupted) | Err(ReadlineError::Eof) => {
-                println!("\nExiting smvblock REPL.");
-                break;
+            ReplCommand::ShowUsers => {
+                for address in self.users.keys() {
+                    let account = rpc.balance(&parse_hash(address)?).await?;
+                    println!(
+                        "User: {}, Balance: {}, Stake: {}",
+                        address, account.balance, account.stake
+                    );
+                }
             }
+            ReplCommand::ShowBlock { block } => {
+                let id = match block {
+                    None => BlockId::Height(
+                        rpc.status().await?["height"]
+                            .as_u64()
+                            .ok_or("No such block")?,
+                    ),
+                    Some(block) => match block.parse::<u64>() {
+                        Ok(height) => BlockId::Height(height),
+                        Err(_) => BlockId::Hash(parse_hash(&block)?),
+                    },
+                };
+                print_remote_block(&rpc.block(id).await?.ok_or("No such block")?);
+            }
+            ReplCommand::ShowChain { from, to } => {
+                let tip = rpc.status().await?["height"]
+                    .as_u64()
+                    .ok_or("No blocks yet")?;
+                let from = from.unwrap_or(tip.saturating_sub(SHOW_CHAIN_DEFAULT - 1));
+                let to = to.map_or(tip, |to| to.min(tip));
+                if from > to {
+                    return Err(format!("No blocks from {} to {}", from, to));
+                }
+                for height in from..=to {
+                    match rpc.block(BlockId::Height(height)).await? {
+                        Some(block) => print_remote_block(&block),
+                        None => println!("Block {} is not stored", height),
+                    }
+                }
+            }
+            ReplCommand::ShowMempool => {
+                let status = rpc.status().await?;
+                println!(
+                    "Pending transactions: {}",
+                    status["pending_transactions"].as_u64().unwrap_or(0)
+                );
+            }
+            _ => {
+                return Err(format!(
+                    "`{}` only works on the local node; `disconnect` to go back to it",
+                    name
+                ));
+            }
+        }
+        Ok(Flow::Continue)
+    }
+
+    /// Reads commands from the terminal until `exit` or end of input.
+    async fn interact(&mut self) {
+        let mut rl = match Editor::<ReplHelper, rustyline::history::FileHistory>::new() {
+            Ok(rl) => rl,
             Err(e) => {
-                eprintln!("Error: {:?}", e);
-                break;
+                eprintln!("Failed to open the terminal: {}", e);
+                return;
+            }
+        };
+        rl.set_helper(Some(ReplHelper {
+            accounts: self.completions.clone(),
+        }));
+
+        println!("Welcome to smvblock REPL.");
+        println!("Type `help` for commands, `exit` to quit.");
+
+        loop {
+            let prompt = match &self.remote {
+                Some(rpc) => format!("smvblock {}> ", rpc),
+                None => "smvblock> ".to_string(),
+            };
+            match rl.readline(&prompt) {
+                Ok(input) => {
+                    let input = input.trim();
+                    let _ = rl.add_history_entry(input);
+                    match self.execute(input).await {
+                        Ok(Flow::Continue) => {}
+                        Ok(Flow::Exit) => break,
+                        Err(e) => println!("Error: {}", e),
+                    }
+                }
+                Err(ReadlineError::Interrupted) | Err(ReadlineError::Eof) => {
+                    println!("\nExiting smvblock REPL.");
+                    break;
+                }
+                Err(e) => {
+                    eprintln!("Error: {:?}", e);
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Runs `commands` in order, stopping at the first that fails, which
+    /// is reported as the `unit` (line or command) it was counting from 1.
+    /// Blank commands and those starting with `#` are skipped.
+    async fn run_script<'a>(
+        &mut self,
+        unit: &str,
+        commands: impl IntoIterator<Item = &'a str>,
+    ) -> Result<(), String> {
+        for (number, command) in (1..).zip(commands) {
+            let command = command.trim();
+            if command.is_empty() || command.starts_with('#') {
+                continue;
+            }
+            match self.execute(command).await {
+                Ok(Flow::Continue) => {}
+                Ok(Flow::Exit) => break,
+                Err(e) => {
+                    return Err(format!("{} {} ({}) failed: {}", unit, number, command, e));
+                }
             }
         }
+        Ok(())
+    }
+}
+
+fn parse_node_id(s: &str) -> Result<[u8; 32], String> {
+    hex::decode(s)
+        .map_err(|e| format!("Invalid node ID: {}", e))?
+        .try_into()
+        .map_err(|_| "Expected a 32-byte node ID".to_string())
+}
+
+fn parse_checkpoint(s: &str) -> Result<(u64, [u8; 32]), String> {
+    let (height, hash) = s.split_once(':').ok_or("Expected HEIGHT:HASH")?;
+    let height = height
+        .parse()
+        .map_err(|e| format!("Invalid height: {}", e))?;
+    let hash = hex::decode(hash)
+        .map_err(|e| format!("Invalid hash: {}", e))?
+        .try_into()
+        .map_err(|_| "Expected a 32-byte hash".to_string())?;
+    Ok((height, hash))
+}
+
+fn parse_fork(s: &str) -> Result<(Feature, u64), String> {
+    let (feature, height) = s.split_once('=').ok_or("Expected FEATURE=HEIGHT")?;
+    let height = height
+        .parse()
+        .map_err(|e| format!("Invalid height: {}", e))?;
+    Ok((feature.parse()?, height))
+}
+
+fn parse_rate(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(rate) if rate.is_finite() && rate > 0.0 => Ok(rate),
+        _ => Err(format!("Expected a positive number, got {:?}", s)),
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let args = Args::parse();
+    if args.exec.is_some() && args.command.is_some() {
+        eprintln!("--exec cannot be combined with a subcommand");
+        std::process::exit(2);
+    }
+    if let Some(Command::Devnet {
+        nodes,
+        block_time,
+        dir,
+    }) = args.command
+    {
+        if let Err(e) = logging::init(&args.log_level, args.log_json) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        if let Err(e) = run_devnet(nodes, block_time, dir).await {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    let data_dir = args.data_dir.clone().unwrap_or_else(db::default_data_dir);
+    let script = match &args.command {
+        Some(Command::Run { script }) => Some(script.clone()),
+        _ => None,
+    };
+    let database = match open_database(args.data_dir, &args.chain_id) {
+        Ok(database) => database,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    if let Some(Command::Db { command }) = args.command {
+        if let Err(e) = run_db_command(command, database, args.node_type).await {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    if let Err(e) = logging::init(&args.log_level, args.log_json) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+
+    let mut node = Node::with_database(args.node_type, database);
+    node.set_prune_depth(args.keep_blocks);
+    node.p2p.set_compression(!args.no_compression);
+    node.p2p.set_peer_bandwidth(args.max_peer_bandwidth);
+    node.p2p.set_chain_id(args.chain_id);
+    node.p2p.set_proxy(args.proxy);
+    node.p2p.set_external_addr(args.external_addr);
+    node.p2p.set_connection_limits(ConnectionLimits {
+        max_inbound: args.max_inbound,
+        max_outbound: args.max_outbound,
+        reserved: args.reserved_peers.into_iter().collect(),
+    });
+    if args.no_snapshot {
+        node.set_snapshot_distance(None);
+    }
+    node.set_checkpoint(args.checkpoint);
+    let mut forks = ForkSchedule::default();
+    for (feature, height) in args.forks {
+        forks.set(feature, height);
+    }
+    let fees = match FeeRouting::new(args.fee_burn, args.fee_treasury) {
+        Ok(fees) => fees,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    node.blockchain.set_chain_params(ChainParams {
+        forks,
+        fees,
+        block_time: Duration::from_secs(args.block_time.max(1)),
+        ..ChainParams::default()
+    });
+    node.blockchain
+        .set_mempool_config(MempoolConfig {
+            min_fee_per_byte: args.min_relay_fee,
+            max_pending_per_sender: args.max_pending_per_sender,
+            ..MempoolConfig::default()
+        })
+        .await;
+
+    let restored = match node.blockchain.restore_mempool().await {
+        Ok(restored) => restored,
+        Err(e) => {
+            eprintln!("Failed to restore the mempool: {}", e);
+            std::process::exit(1);
+        }
+    };
+    if restored > 0 {
+        println!("Restored {} pending transactions", restored);
+    }
+
+    if args.no_listen {
+        node.start_outbound_network();
+        println!(
+            "Not listening for peers; dialing out as node {}",
+            hex::encode(node.p2p.identity())
+        );
+    } else {
+        let listen_addrs = match node.start_network_on(&args.listen).await {
+            Ok(listen_addrs) => listen_addrs,
+            Err(e) => {
+                eprintln!("Failed to listen for peers: {}", e);
+                std::process::exit(1);
+            }
+        };
+        for listen_addr in listen_addrs {
+            println!("Listening for peers on {}", listen_addr);
+        }
+        if let Some(external_addr) = args.external_addr {
+            println!("Advertising {} to peers", external_addr);
+        }
+        println!("Running as node {}", hex::encode(node.p2p.identity()));
+    }
+    if let Some(proxy) = args.proxy {
+        println!("Dialing peers through {}", proxy);
+    }
+    let jwt_secret = match args.rpc_jwt_secret_file.map(std::fs::read_to_string) {
+        Some(Ok(secret)) => Some(secret.trim().as_bytes().to_vec()),
+        Some(Err(e)) => {
+            eprintln!("Failed to read the JWT secret: {}", e);
+            std::process::exit(1);
+        }
+        None => None,
+    };
+    let access = RpcAccess {
+        roles: Role::ALL.to_vec(),
+        tokens: args.rpc_tokens,
+        jwt_secret,
+        cors_origins: args.rpc_cors_origins,
+        allowed_methods: args.rpc_allowed_methods,
+        denied_methods: args.rpc_denied_methods,
+        rate_limit: args.rpc_rate_limit.map(|per_second| RateLimit {
+            per_second,
+            burst: args.rpc_rate_burst.unwrap_or(per_second).max(1.0),
+        }),
+    };
+    if let Some(rpc_addr) = args.rpc_addr {
+        let rpc_addr = match node.start_rpc_with(rpc_addr, access.clone()).await {
+            Ok(rpc_addr) => rpc_addr,
+            Err(e) => {
+                eprintln!("Failed to serve JSON-RPC on {}: {}", rpc_addr, e);
+                std::process::exit(1);
+            }
+        };
+        println!("Serving JSON-RPC on {}", rpc_addr);
+    }
+    for listener in args.rpc_listeners {
+        let roles = listener
+            .roles
+            .iter()
+            .map(|role| role.as_str())
+            .collect::<Vec<_>>()
+            .join(", ");
+        let access = RpcAccess {
+            roles: listener.roles,
+            ..access.clone()
+        };
+        let addr = match node.start_rpc_with(listener.addr, access).await {
+            Ok(addr) => addr,
+            Err(e) => {
+                eprintln!("Failed to serve JSON-RPC on {}: {}", listener.addr, e);
+                std::process::exit(1);
+            }
+        };
+        println!("Serving JSON-RPC ({}) on {}", roles, addr);
+    }
+    #[cfg(feature = "grpc")]
+    if let Some(grpc_addr) = args.grpc_addr {
+        let grpc_addr = match node.start_grpc(grpc_addr).await {
+            Ok(addr) => addr,
+            Err(e) => {
+                eprintln!("Failed to serve gRPC on {}: {}", grpc_addr, e);
+                std::process::exit(1);
+            }
+        };
+        println!("Serving gRPC on {}", grpc_addr);
+    }
+    // Registered over RPC but kept in the database, so delivered either way.
+    node.start_webhooks(WebhookConfig::default());
+    if let Some(dir) = args.backup_dir {
+        let config = BackupConfig {
+            dir: dir.clone(),
+            interval: Duration::from_secs(args.backup_interval.max(1)),
+            keep: args.backup_keep,
+        };
+        match node.start_backups(config).await {
+            Ok(()) => println!("Backing up the database to {}", dir.display()),
+            Err(e) => println!("Error: {}", e),
+        }
+    }
+    for peer in args.peers {
+        match node.connect(peer).await {
+            Ok(()) => println!("Connected to {}", peer),
+            Err(e) => {
+                println!("Error: {}; retrying in the background", e);
+                node.retry_connect(peer);
+            }
+        }
+    }
+
+    node.start_discovery(DiscoveryConfig {
+        outbound_target: args.outbound_peers,
+        mdns: args.mdns,
+        ..DiscoveryConfig::default()
+    });
+
+    if args.validator {
+        let signer = match (&args.validator_key, args.remote_signer) {
+            (Some(path), _) => signer::load_key(path).map(BlockSigner::Local),
+            (None, Some(endpoint)) => RemoteSigner::connect(endpoint)
+                .await
+                .map(BlockSigner::Remote),
+            (None, None) => Err("--validator needs a key or a remote signer".to_string()),
+        };
+        let signer = match signer {
+            Ok(signer) => signer,
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        };
+        let key = signer.public_key();
+        match node.blockchain.validator_for_key(&key).await {
+            Ok(Some(address)) => println!("Validating as {}", hex::encode(address)),
+            Ok(None) => println!(
+                "The signing key {} was rotated out and signs for no validator",
+                hex::encode(key)
+            ),
+            Err(e) => eprintln!("Failed to look up the signing key: {}", e),
+        }
+        node.start_validator(signer);
+    }
+
+    // The prompt blocks on stdin, so a termination signal shuts the node
+    // down from a separate task and exits from there.
+    let signalled = node.clone();
+    tokio::spawn(async move {
+        if let Err(e) = signalled.start().await {
+            eprintln!("Error during shutdown: {}", e);
+        }
+        std::process::exit(0);
+    });
+    let mut session = Session::new(node, Wallet::open(data_dir.join("wallet")));
+    let result = match (script, args.exec) {
+        (Some(path), _) => match std::fs::read_to_string(&path) {
+            Ok(script) => session.run_script("Line", script.lines()).await,
+            Err(e) => Err(format!("Failed to read {}: {}", path.display(), e)),
+        },
+        (None, Some(exec)) => session.run_script("Command", exec.split(';')).await,
+        (None, None) => {
+            session.interact().await;
+            Ok(())
+        }
+    };
+    session.stop_auto_produce().await;
+    if let Err(e) = session.node.shutdown().await {
+        eprintln!("Error during shutdown: {}", e);
+    }
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        std::process::exit(1);
     }
 }
@@ -0,0 +1,473 @@
+use crate::amount::Amount;
+use crate::blockchain::{Address, Transaction};
+use crate::error::BlockchainError;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet, BinaryHeap, HashMap};
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Debug)]
+pub struct MempoolConfig {
+    pub max_transactions: usize,
+    pub max_bytes: usize,
+    pub ttl: Duration,
+    /// How far past a sender's next nonce a transaction may be queued to
+    /// wait for the ones before it.
+    pub max_future_per_sender: usize,
+    /// Most transactions queued behind a nonce gap, all senders together.
+    pub max_future: usize,
+    /// Fee every transaction must pay per byte of its encoding to be
+    /// admitted, and so relayed.
+    pub min_fee_per_byte: Amount,
+    /// Most pending transactions one sender may have.
+    pub max_pending_per_sender: usize,
+}
+
+impl Default for MempoolConfig {
+    fn default() -> Self {
+        MempoolConfig {
+            max_transactions: 5_000,
+            max_bytes: 4 * 1024 * 1024,
+            ttl: Duration::from_secs(3 * 60 * 60),
+            max_future_per_sender: 16,
+            max_future: 1_000,
+            min_fee_per_byte: Amount::ZERO,
+            max_pending_per_sender: 64,
+        }
+    }
+}
+
+/// Counters describing what the pool has dropped since it was created.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MempoolStats {
+    pub evicted_expired: u64,
+    pub evicted_low_fee: u64,
+    pub rejected_full: u64,
+}
+
+#[derive(Debug)]
+struct Entry {
+    tx: Transaction,
+    size: usize,
+    added_at: Instant,
+}
+
+impl Entry {
+    fn new(tx: Transaction) -> Self {
+        Entry {
+            size: tx.size(),
+            tx,
+            added_at: Instant::now(),
+        }
+    }
+
+    fn rate(&self) -> FeeRate {
+        FeeRate {
+            fee: self.tx.payload.fee.base_units(),
+            size: self.size,
+        }
+    }
+}
+
+/// A fee per byte, compared without dividing.
+#[derive(Clone, Copy, Debug)]
+struct FeeRate {
+    fee: u128,
+    size: usize,
+}
+
+impl Ord for FeeRate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let lhs = self.fee.saturating_mul(other.size as u128);
+        let rhs = other.fee.saturating_mul(self.size as u128);
+        lhs.cmp(&rhs)
+    }
+}
+
+impl PartialOrd for FeeRate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for FeeRate {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for FeeRate {}
+
+#[derive(Debug, Default)]
+pub struct Mempool {
+    config: MempoolConfig,
+    pending: HashMap<Address, BTreeMap<u64, Entry>>,
+    /// Transactions waiting for an earlier nonce of their sender. They are
+    /// neither counted nor mined until [`Mempool::take_future`] hands them
+    /// back for admission.
+    future: HashMap<Address, BTreeMap<u64, Entry>>,
+    bytes: usize,
+    /// The highest pending nonce of every sender, the only transactions
+    /// that can be evicted without leaving a gap, cheapest first.
+    tips: BTreeSet<(FeeRate, Address, u64)>,
+    stats: MempoolStats,
+}
+
+impl Mempool {
+    pub fn new() -> Self {
+        Mempool::default()
+    }
+
+    pub fn with_config(config: MempoolConfig) -> Self {
+        Mempool {
+            config,
+            ..Mempool::default()
+        }
+    }
+
+    /// Applies `config` to transactions admitted from now on; those
+    /// already pending stay.
+    pub fn set_config(&mut self, config: MempoolConfig) {
+        self.config = config;
+    }
+
+    /// Refuses a transaction paying less than the minimum fee for its size.
+    fn check_fee(&self, tx: &Transaction) -> Result<(), BlockchainError> {
+        let required = self
+            .config
+            .min_fee_per_byte
+            .base_units()
+            .saturating_mul(tx.size() as u128);
+        if tx.payload.fee.base_units() < required {
+            return Err(BlockchainError::FeeTooLow {
+                required: Amount::from_base_units(required),
+            });
+        }
+        Ok(())
+    }
+
+    /// Adds a transaction to the pool. A transaction with the same sender and
+    /// nonce as a pending one replaces it only if it pays a strictly higher
+    /// fee; the replaced transaction is returned.
+    ///
+    /// When the pool is full, the cheapest transactions that no other pending
+    /// transaction depends on are evicted to make room, unless the incoming
+    /// transaction is itself the cheapest.
+    ///
+    /// Transactions below the minimum fee, and new ones from a sender
+    /// already at its pending limit, are refused.
+    pub fn insert(&mut self, tx: Transaction) -> Result<Option<Transaction>, BlockchainError> {
+        self.evict_expired();
+        self.check_fee(&tx)?;
+
+        let sender = tx.sender_address();
+        let nonce = tx.payload.nonce;
+
+        match self.get(&sender, nonce) {
+            Some(existing) if tx.payload.fee <= existing.payload.fee => {
+                return Err(BlockchainError::Underpriced);
+            }
+            Some(_) => {}
+            None => {
+                let pending = self.pending.get(&sender).map_or(0, BTreeMap::len);
+                if pending >= self.config.max_pending_per_sender {
+                    return Err(BlockchainError::TooManyPending {
+                        limit: self.config.max_pending_per_sender,
+                    });
+                }
+            }
+        }
+
+        let entry = Entry::new(tx);
+
+        let replaced = self.remove(&sender, nonce);
+        if let Err(e) = self.make_room(&entry) {
+            if let Some(replaced) = replaced {
+                self.push(replaced);
+            }
+            self.stats.rejected_full += 1;
+            return Err(e);
+        }
+
+        self.push(entry);
+        Ok(replaced.map(|entry| entry.tx))
+    }
+
+    pub fn len(&self) -> usize {
+        self.pending.values().map(BTreeMap::len).sum()
+    }
+
+    /// Queues a transaction whose nonce is past `expected`, the next one
+    /// its sender can use, until the transactions in between arrive. Those
+    /// too far ahead, or arriving when the queue is full, are refused with
+    /// the gap. A queued transaction is replaced only by one paying more.
+    pub fn park(&mut self, tx: Transaction, expected: u64) -> Result<(), BlockchainError> {
+        self.evict_expired();
+        self.check_fee(&tx)?;
+
+        let sender = tx.sender_address();
+        let nonce = tx.payload.nonce;
+        let gap = BlockchainError::NonceGap {
+            expected,
+            got: nonce,
+        };
+        if nonce.saturating_sub(expected) > self.config.max_future_per_sender as u64 {
+            return Err(gap);
+        }
+        match self.future.get(&sender).and_then(|queue| queue.get(&nonce)) {
+            Some(existing) if tx.payload.fee <= existing.tx.payload.fee => {
+                return Err(BlockchainError::Underpriced);
+            }
+            Some(_) => {}
+            None if self.future_len() >= self.config.max_future => return Err(gap),
+            None => {}
+        }
+
+        let entry = Entry::new(tx);
+        self.future.entry(sender).or_default().insert(nonce, entry);
+        Ok(())
+    }
+
+    /// Removes the queued transaction with the sender's `nonce`, now that
+    /// it no longer waits on a gap.
+    pub fn take_future(&mut self, sender: &Address, nonce: u64) -> Option<Transaction> {
+        let queue = self.future.get_mut(sender)?;
+        let entry = queue.remove(&nonce)?;
+        if queue.is_empty() {
+            self.future.remove(sender);
+        }
+        Some(entry.tx)
+    }
+
+    /// Transactions queued behind a nonce gap.
+    pub fn future_len(&self) -> usize {
+        self.future.values().map(BTreeMap::len).sum()
+    }
+
+    pub fn has_future(&self, sender: &Address) -> bool {
+        self.future.contains_key(sender)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// Total encoded size of all pending transactions.
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    pub fn stats(&self) -> &MempoolStats {
+        &self.stats
+    }
+
+    pub fn get(&self, sender: &Address, nonce: u64) -> Option<&Transaction> {
+        self.pending.get(sender)?.get(&nonce).map(|entry| &entry.tx)
+    }
+
+    /// The nonce the sender's next transaction should use, given the next
+    /// nonce already confirmed on chain.
+    pub fn next_nonce(&self, sender: &Address, confirmed: u64) -> u64 {
+        self.pending
+            .get(sender)
+            .and_then(|queue| queue.keys().next_back())
+            .map_or(confirmed, |&nonce| confirmed.max(nonce + 1))
+    }
+
+    /// What the sender's pending transactions other than the one with
+    /// `nonce` would take from its balance, amounts and fees together.
+    pub fn pending_spend(&self, sender: &Address, nonce: u64) -> Result<Amount, BlockchainError> {
+        let mut spend = Amount::ZERO;
+        for entry in self.others(sender, nonce) {
+            spend = spend.checked_add(entry.tx.payload.balance_cost()?)?;
+        }
+        Ok(spend)
+    }
+
+    /// What the sender's pending transactions other than the one with
+    /// `nonce` would take from its stake.
+    pub fn pending_unstake(&self, sender: &Address, nonce: u64) -> Result<Amount, BlockchainError> {
+        Amount::checked_sum(
+            self.others(sender, nonce)
+                .map(|entry| entry.tx.payload.stake_cost()),
+        )
+    }
+
+    fn others(&self, sender: &Address, nonce: u64) -> impl Iterator<Item = &Entry> {
+        self.pending
+            .get(sender)
+            .into_iter()
+            .flat_map(move |queue| queue.iter().filter(move |(pending, _)| **pending != nonce))
+            .map(|(_, entry)| entry)
+    }
+
+    /// Pending transactions in the order they would be included in a block.
+    pub fn pending(&self) -> Vec<Transaction> {
+        let mut queues: HashMap<&Address, _> = self
+            .pending
+            .iter()
+            .map(|(sender, queue)| (sender, queue.values().peekable()))
+            .collect();
+        // Each sender's transactions must stay in nonce order, so only the
+        // lowest pending nonce of every sender competes on fee at each step.
+        let mut heads: BinaryHeap<(FeeRate, &Address)> = queues
+            .iter_mut()
+            .filter_map(|(sender, queue)| queue.peek().map(|entry| (entry.rate(), *sender)))
+            .collect();
+        let mut ordered = Vec::with_capacity(self.len());
+        while let Some((_, sender)) = heads.pop() {
+            let queue = queues.get_mut(sender).expect("every head has a queue");
+            let entry = queue.next().expect("a head is pending");
+            ordered.push(entry.tx.clone());
+            if let Some(next) = queue.peek() {
+                heads.push((next.rate(), sender));
+            }
+        }
+        ordered
+    }
+
+    /// Removes and returns every pending transaction, highest fee-per-byte
+    /// first. Expired transactions are dropped rather than returned.
+    pub fn drain(&mut self) -> Vec<Transaction> {
+        self.evict_expired();
+        let ordered = self.pending();
+        self.pending.clear();
+        self.tips.clear();
+        self.bytes = 0;
+        ordered
+    }
+
+    /// Drops pending and queued transactions made stale by a block: those
+    /// with the same sender and a nonce no higher than one the block
+    /// included.
+    pub fn remove_included(&mut self, transactions: &[Transaction]) {
+        for tx in transactions {
+            let sender = tx.sender_address();
+            if let Some(queue) = self.future.get_mut(&sender) {
+                *queue = queue.split_off(&(tx.payload.nonce + 1));
+                if queue.is_empty() {
+                    self.future.remove(&sender);
+                }
+            }
+            self.untip(&sender);
+            let Some(queue) = self.pending.get_mut(&sender) else {
+                continue;
+            };
+            let kept = queue.split_off(&(tx.payload.nonce + 1));
+            let removed = std::mem::replace(queue, kept);
+            self.bytes -= removed.values().map(|entry| entry.size).sum::<usize>();
+            if queue.is_empty() {
+                self.pending.remove(&sender);
+            }
+            self.retip(&sender);
+        }
+    }
+
+    /// Drops every transaction that has been pending longer than the TTL,
+    /// together with the sender's higher nonces: a replacement restarts its
+    /// own clock only, and those after an expired nonce could never follow
+    /// it.
+    pub fn evict_expired(&mut self) {
+        let ttl = self.config.ttl;
+        let mut evicted = 0;
+        let mut freed = 0;
+
+        let senders: Vec<Address> = self.pending.keys().copied().collect();
+        for sender in senders {
+            let queue = &self.pending[&sender];
+            let Some(expired) = first_expired(queue, ttl) else {
+                continue;
+            };
+            self.untip(&sender);
+            let queue = self.pending.get_mut(&sender).expect("sender is pending");
+            let removed = queue.split_off(&expired);
+            evicted += removed.len() as u64;
+            freed += removed.values().map(|entry| entry.size).sum::<usize>();
+            if queue.is_empty() {
+                self.pending.remove(&sender);
+            }
+            self.retip(&sender);
+        }
+        for queue in self.future.values_mut() {
+            if let Some(expired) = first_expired(queue, ttl) {
+                evicted += queue.split_off(&expired).len() as u64;
+            }
+        }
+        self.future.retain(|_, queue| !queue.is_empty());
+
+        self.bytes -= freed;
+        self.stats.evicted_expired += evicted;
+    }
+
+    fn make_room(&mut self, incoming: &Entry) -> Result<(), BlockchainError> {
+        while self.len() + 1 > self.config.max_transactions
+            || self.bytes + incoming.size > self.config.max_bytes
+        {
+            // Only the highest nonce of a sender can go without leaving a
+            // gap behind it, and never one the incoming transaction follows.
+            let incoming_sender = incoming.tx.sender_address();
+            let cheapest = self
+                .tips
+                .iter()
+                .find(|(_, sender, nonce)| {
+                    *sender != incoming_sender || *nonce > incoming.tx.payload.nonce
+                })
+                .copied();
+
+            match cheapest {
+                Some((rate, sender, nonce)) if rate < incoming.rate() => {
+                    self.remove(&sender, nonce);
+                    self.stats.evicted_low_fee += 1;
+                }
+                _ => return Err(BlockchainError::MempoolFull),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn push(&mut self, entry: Entry) {
+        let sender = entry.tx.sender_address();
+        self.untip(&sender);
+        self.bytes += entry.size;
+        self.pending
+            .entry(sender)
+            .or_default()
+            .insert(entry.tx.payload.nonce, entry);
+        self.retip(&sender);
+    }
+
+    fn remove(&mut self, sender: &Address, nonce: u64) -> Option<Entry> {
+        self.untip(sender);
+        let queue = self.pending.get_mut(sender)?;
+        let entry = queue.remove(&nonce);
+        if queue.is_empty() {
+            self.pending.remove(sender);
+        }
+        self.retip(sender);
+        let entry = entry?;
+        self.bytes -= entry.size;
+        Some(entry)
+    }
+
+    /// Takes the sender's highest nonce out of [`Mempool::tips`] before its
+    /// queue changes; [`Mempool::retip`] puts the new one back.
+    fn untip(&mut self, sender: &Address) {
+        if let Some((nonce, entry)) = self.pending.get(sender).and_then(|q| q.iter().next_back()) {
+            self.tips.remove(&(entry.rate(), *sender, *nonce));
+        }
+    }
+
+    fn retip(&mut self, sender: &Address) {
+        if let Some((nonce, entry)) = self.pending.get(sender).and_then(|q| q.iter().next_back()) {
+            self.tips.insert((entry.rate(), *sender, *nonce));
+        }
+    }
+}
+
+/// The lowest nonce in `queue` that has waited longer than `ttl`.
+fn first_expired(queue: &BTreeMap<u64, Entry>, ttl: Duration) -> Option<u64> {
+    queue
+        .iter()
+        .find(|(_, entry)| entry.added_at.elapsed() >= ttl)
+        .map(|(nonce, _)| *nonce)
+}
@@ -0,0 +1,68 @@
+use crate::amount::Amount;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum EmissionCurve {
+    /// `initial_reward`, halved every `interval` blocks until it reaches zero.
+    Halving {
+        initial_reward: Amount,
+        interval: u64,
+    },
+    /// A yearly issuance of `annual_rate_bps` basis points of the current
+    /// supply, spread evenly over `blocks_per_year` blocks.
+    FixedInflation {
+        annual_rate_bps: u32,
+        blocks_per_year: u64,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct MonetaryPolicy {
+    pub max_supply: Amount,
+    pub curve: EmissionCurve,
+}
+
+impl Default for MonetaryPolicy {
+    fn default() -> Self {
+        MonetaryPolicy {
+            max_supply: Amount::from_smv(21_000_000),
+            curve: EmissionCurve::Halving {
+                initial_reward: Amount::from_smv(1),
+                interval: 210_000,
+            },
+        }
+    }
+}
+
+impl MonetaryPolicy {
+    /// Reward the curve schedules for the block at `height`, ignoring the
+    /// supply cap.
+    pub fn scheduled_reward(&self, height: u64, supply: Amount) -> Amount {
+        match self.curve {
+            EmissionCurve::Halving {
+                initial_reward,
+                interval,
+            } => {
+                let halvings = height / interval.max(1);
+                let units = initial_reward
+                    .base_units()
+                    .checked_shr(halvings.min(u32::MAX as u64) as u32)
+                    .unwrap_or(0);
+                Amount::from_base_units(units)
+            }
+            EmissionCurve::FixedInflation {
+                annual_rate_bps,
+                blocks_per_year,
+            } => {
+                let yearly = (supply.base_units() / 10_000).saturating_mul(annual_rate_bps as u128);
+                Amount::from_base_units(yearly / blocks_per_year.max(1) as u128)
+            }
+        }
+    }
+
+    /// The most a block at `height` may mint given the current supply: the
+    /// scheduled reward, cut short so the supply never exceeds `max_supply`.
+    pub fn block_reward(&self, height: u64, supply: Amount) -> Amount {
+        let remaining = self.max_supply.saturating_sub(supply);
+        self.scheduled_reward(height, supply).min(remaining)
+    }
+}
@@ -1,40 +1,223 @@
-use crate::blockchain::{Address, Block, Blockchain, Transfer, User};
+use crate::access::RpcAccess;
+use crate::amount::Amount;
+use crate::beacon;
+use crate::blockchain::{
+    Address, Block, BlockHeader, Blockchain, Hash, Transfer, TxData, TxKind, User,
+};
 use crate::db::Database;
-use crate::p2p::P2P;
+use crate::events::{EventBus, NodeEvent};
+use crate::finality::{Vote, VoteOutcome};
+use crate::liveness;
+use crate::p2p::{DiscoveryConfig, Message, P2P};
+use crate::params::Feature;
+use crate::rpc::{self, RpcContext};
+use crate::signer::BlockSigner;
+use crate::sync::{self, PRUNE_DEPTH, SNAPSHOT_DISTANCE, STATUS_INTERVAL, SyncManager, SyncState};
+use crate::wallet::key_address;
+use crate::webhooks::{self, WebhookConfig};
+use chrono::Utc;
 use ed25519_dalek::SigningKey;
-use libp2p::futures::lock::Mutex;
+use futures::lock::Mutex;
+use rand::rngs::OsRng;
+use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{broadcast, watch};
+use tracing::{debug, info, warn};
 
+/// Announced blocks whose bodies are being fetched at once; further
+/// announcements are ignored until some arrive.
+const MAX_PENDING_BODIES: usize = 64;
+
+/// Block hashes remembered for gossip deduplication. Blocks and
+/// transactions are remembered apart, so a burst of transactions cannot
+/// push out the blocks.
+pub(crate) const SEEN_BLOCKS: usize = 2_000;
+
+/// Transaction hashes remembered for gossip deduplication.
+const SEEN_TRANSACTIONS: usize = 10_000;
+
+/// The most recently seen hashes, forgetting the oldest past `capacity`.
 #[derive(Debug)]
+pub(crate) struct SeenHashes {
+    hashes: HashSet<Hash>,
+    order: VecDeque<Hash>,
+    capacity: usize,
+}
+
+impl SeenHashes {
+    pub(crate) fn new(capacity: usize) -> Self {
+        SeenHashes {
+            hashes: HashSet::new(),
+            order: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    pub(crate) fn contains(&self, hash: &Hash) -> bool {
+        self.hashes.contains(hash)
+    }
+
+    /// Records `hash`, returning whether it was new.
+    pub(crate) fn insert(&mut self, hash: Hash) -> bool {
+        if !self.hashes.insert(hash) {
+            return false;
+        }
+        self.order.push_back(hash);
+        if self.order.len() > self.capacity
+            && let Some(oldest) = self.order.pop_front()
+        {
+            self.hashes.remove(&oldest);
+        }
+        true
+    }
+}
+
+/// Where and how often the node backs up its database while running.
+#[derive(Clone, Debug)]
+pub struct BackupConfig {
+    pub dir: PathBuf,
+    pub interval: Duration,
+    /// Backups kept in `dir`; older ones are deleted.
+    pub keep: usize,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
 pub enum NodeType {
+    /// Keeps every block, and so can serve any of them.
     FullNode,
+    /// Follows the header chain only.
     LightNode,
+    /// Keeps the current state but only the most recent blocks whole.
+    PrunedNode,
+    /// Hands out the addresses of peers it has recently found alive,
+    /// crawling the network to keep them fresh. Follows the header chain
+    /// only.
+    SeedNode,
 }
 
-#[derive(Debug)]
+impl NodeType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            NodeType::FullNode => "full",
+            NodeType::LightNode => "light",
+            NodeType::PrunedNode => "pruned",
+            NodeType::SeedNode => "seed",
+        }
+    }
+}
+
+impl FromStr for NodeType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "full" => Ok(NodeType::FullNode),
+            "light" => Ok(NodeType::LightNode),
+            "pruned" => Ok(NodeType::PrunedNode),
+            "seed" => Ok(NodeType::SeedNode),
+            _ => Err(format!("Unknown node type: {}", s)),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
 pub struct Node {
     pub node_type: NodeType,
     pub blockchain: Blockchain,
     pub p2p: P2P,
     pub database: Arc<Mutex<Database>>,
+    /// Shared by the chain, the network and sync.
+    events: EventBus,
+    shutdown: watch::Sender<bool>,
+    /// Published by sync once the network starts.
+    sync_state: watch::Sender<SyncState>,
+    snapshot_distance: Option<u64>,
+    prune_depth: u64,
+    checkpoint: Option<(u64, Hash)>,
 }
 
 impl Node {
     pub fn new(node_type: NodeType, test_node: bool) -> Result<Self, String> {
         let database = Database::new(None, test_node)
             .map_err(|_| "Failed to initialize database".to_string())?;
+        Ok(Node::with_database(node_type, database))
+    }
+
+    pub fn with_database(node_type: NodeType, database: Database) -> Self {
+        let identity = node_identity(&database);
         let database = Arc::new(Mutex::new(database));
 
-        let blockchain = Blockchain::new(database.clone());
-        let p2p = P2P::new(database.clone());
+        let events = EventBus::default();
+        let mut blockchain = Blockchain::new(database.clone());
+        blockchain.set_event_bus(events.clone());
+        let mut p2p = P2P::with_identity(database.clone(), node_type, identity);
+        p2p.set_event_bus(events.clone());
 
-        Ok(Node {
+        Node {
             node_type,
             blockchain,
             p2p,
             database,
-        })
+            events,
+            shutdown: watch::channel(false).0,
+            sync_state: watch::channel(SyncState::default()).0,
+            snapshot_distance: Some(SNAPSHOT_DISTANCE),
+            prune_depth: PRUNE_DEPTH,
+            checkpoint: None,
+        }
+    }
+
+    /// How far behind an empty chain must be for the node to start from a
+    /// peer's state snapshot instead of replaying every block; `None` always
+    /// replays. Takes effect when the network starts.
+    pub fn set_snapshot_distance(&mut self, distance: Option<u64>) {
+        self.snapshot_distance = distance;
+    }
+
+    /// How many of the latest blocks a pruned node keeps whole. Takes effect
+    /// when the network starts.
+    pub fn set_prune_depth(&mut self, depth: u64) {
+        self.prune_depth = depth;
+    }
+
+    /// Trusts the chain through block `hash` at `height` when syncing: peers
+    /// whose chains disagree with it are ignored, and blocks up to it are
+    /// not fully validated. Takes effect when the network starts.
+    pub fn set_checkpoint(&mut self, checkpoint: Option<(u64, Hash)>) {
+        self.checkpoint = checkpoint;
+    }
+
+    /// Receives every event the node's components publish from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<NodeEvent> {
+        self.events.subscribe()
+    }
+
+    /// How far sync has caught up with peers; always synced before the
+    /// network starts.
+    pub fn sync_state(&self) -> SyncState {
+        *self.sync_state.borrow()
+    }
+
+    /// Runs until SIGINT or SIGTERM arrives, then shuts the node down.
+    pub async fn start(&self) -> Result<(), String> {
+        shutdown_signal().await;
+        self.shutdown().await
+    }
+
+    /// Stops the RPC server and the peer listener, says goodbye to peers and
+    /// saves the mempool so the next start can restore it. Blocks are
+    /// committed atomically as they are added, so the chain needs no flush.
+    pub async fn shutdown(&self) -> Result<(), String> {
+        info!("shutting down");
+        self.shutdown.send_replace(true);
+        self.p2p.shutdown().await;
+        self.blockchain.persist_mempool().await
     }
 
     pub async fn add_user(&self, user: User) -> Result<(), rusqlite::Error> {
@@ -47,7 +230,7 @@ impl Node {
         db.get_users()
     }
 
-    pub async fn stake(&self, user_address: Address, amount: u64) -> Result<(), String> {
+    pub async fn stake(&self, user_address: Address, amount: Amount) -> Result<(), String> {
         let db = self.database.lock().await;
         let user = db
             .get_user(&user_address)
@@ -58,8 +241,8 @@ impl Node {
                 return Err("Insufficient balance to stake".to_string());
             }
 
-            user.balance -= amount;
-            user.stake += amount;
+            user.balance = user.balance.checked_sub(amount)?;
+            user.stake = user.stake.checked_add(amount)?;
 
             db.update_user(&user)
                 .map_err(|_| "Error updating user".to_string())?;
@@ -69,7 +252,7 @@ impl Node {
         }
     }
 
-    pub async fn unstake(&self, user_address: Address, amount: u64) -> Result<(), String> {
+    pub async fn unstake(&self, user_address: Address, amount: Amount) -> Result<(), String> {
         let db = self.database.lock().await;
         let user = db
             .get_user(&user_address)
@@ -80,8 +263,8 @@ impl Node {
                 return Err("Insufficient stake to unstake".to_string());
             }
 
-            user.stake -= amount;
-            user.balance += amount;
+            user.stake = user.stake.checked_sub(amount)?;
+            user.balance = user.balance.checked_add(amount)?;
 
             db.update_user(&user)
                 .map_err(|_| "Error updating user".to_string())?;
@@ -94,7 +277,7 @@ impl Node {
     pub async fn reward_validator(
         &self,
         validator_address: Address,
-        reward: u64,
+        reward: Amount,
     ) -> Result<(), String> {
         let db = self.database.lock().await;
         let user = db
@@ -102,9 +285,12 @@ impl Node {
             .map_err(|_| "Error fetching user".to_string())?;
 
         if let Some(mut user) = user {
-            user.balance += reward;
+            user.balance = user.balance.checked_add(reward)?;
             db.update_user(&user)
                 .map_err(|_| "Error updating user".to_string())?;
+            // Minted outside any block, so not part of the emissions.
+            db.record_allocation(reward)
+                .map_err(|_| "Error recording the reward".to_string())?;
             Ok(())
         } else {
             Err("Validator not found".to_string())
@@ -114,33 +300,29 @@ impl Node {
     pub async fn slash_validator(
         &self,
         validator_address: Address,
-        penalty: u64,
+        penalty: Amount,
     ) -> Result<(), String> {
-        let db = self.database.lock().await;
-        let user = db
-            .get_user(&validator_address)
-            .map_err(|_| "Error fetching user".to_string())?;
-
-        if let Some(mut user) = user {
-            if user.stake < penalty {
-                user.stake = 0;
-            } else {
-                user.stake -= penalty;
-            }
-
-            db.update_user(&user)
-                .map_err(|_| "Error updating user".to_string())?;
-            Ok(())
-        } else {
-            Err("Validator not found".to_string())
-        }
+        self.blockchain
+            .slash_validator(validator_address, penalty)
+            .await
     }
 
     pub async fn send_transaction(
         &self,
         sender_private_key: SigningKey,
         receiver: Address,
-        amount: u64,
+        amount: Amount,
+    ) -> Result<(), String> {
+        self.send_transaction_with_fee(sender_private_key, receiver, amount, Amount::ZERO)
+            .await
+    }
+
+    pub async fn send_transaction_with_fee(
+        &self,
+        sender_private_key: SigningKey,
+        receiver: Address,
+        amount: Amount,
+        fee: Amount,
     ) -> Result<(), String> {
         let db = self.database.lock().await;
 
@@ -156,56 +338,702 @@ impl Node {
             .map_err(|_| "Sender not found".to_string())?
             .ok_or("Sender not found".to_string())?;
 
-        if sender.balance < amount {
+        if sender.balance < amount.checked_add(fee)? {
             return Err("Insufficient balance".to_string());
         }
 
-        let nonce = db.get_latest_nonce(&sender_address).unwrap();
+        let confirmed_nonce = db
+            .get_next_nonce(sender_public_key.as_bytes())
+            .map_err(|_| "Error fetching nonce".to_string())?;
+        drop(db);
+
+        let nonce = self
+            .blockchain
+            .next_nonce(&sender_address, confirmed_nonce)
+            .await;
 
         let transfer = Transfer {
             receiver,
             amount,
+            fee,
             nonce,
+            kind: TxKind::Transfer,
+            data: TxData::None,
         };
 
         let tx = transfer.into_transaction(&sender_private_key);
+        Ok(self.blockchain.add_transaction(tx).await?)
+    }
 
-        drop(db);
-        self.blockchain.add_transaction(tx).await
+    /// Re-signs a pending transaction with a higher fee so that it replaces
+    /// the original in the mempool.
+    pub async fn bump_fee(
+        &self,
+        sender_private_key: SigningKey,
+        nonce: u64,
+        fee: Amount,
+    ) -> Result<(), String> {
+        let sender_public_key = crate::blockchain::derive_public_key(&sender_private_key);
+        let sender_address: [u8; 32] = Sha256::digest(sender_public_key).into();
+
+        let pending = self
+            .blockchain
+            .get_pending_transaction(&sender_address, nonce)
+            .await
+            .ok_or("No pending transaction with that nonce".to_string())?;
+
+        let transfer = Transfer {
+            fee,
+            ..pending.payload
+        };
+
+        let tx = transfer.into_transaction(&sender_private_key);
+        Ok(self.blockchain.add_transaction(tx).await?)
     }
 
-    pub async fn produce_block(&mut self) -> Result<[u8; 32], String> {
-        let transactions = self
+    /// Signs a vote for a stored block with the validator's key, counts it
+    /// locally and broadcasts it to peers.
+    pub async fn attest(
+        &self,
+        validator_key: &SigningKey,
+        block_hash: Hash,
+    ) -> Result<VoteOutcome, String> {
+        let block = self
             .blockchain
-            .get_transactions()
+            .get_block(block_hash)
+            .await
+            .map_err(|_| "Failed to fetch block".to_string())?
+            .ok_or("Block not found".to_string())?;
+
+        let vote = Vote::sign(block_hash, block.header.height, validator_key);
+        let outcome = self.blockchain.add_vote(vote.clone()).await?;
+        if outcome != VoteOutcome::Duplicate {
+            self.p2p.broadcast(Message::Vote(vote), None).await;
+        }
+        Ok(outcome)
+    }
+
+    /// Listens for peers on `addr` and spawns the tasks that handle their
+    /// messages and announce accepted blocks. Returns the bound address.
+    pub async fn start_network(&self, addr: SocketAddr) -> Result<SocketAddr, String> {
+        let local_addr = self.p2p.listen(addr).await?;
+        self.run_network();
+        Ok(local_addr)
+    }
+
+    /// Like [`Node::start_network`], listening on every one of `addrs`.
+    /// Returns the bound addresses in the same order.
+    pub async fn start_network_on(&self, addrs: &[SocketAddr]) -> Result<Vec<SocketAddr>, String> {
+        let mut bound = Vec::with_capacity(addrs.len());
+        for addr in addrs {
+            bound.push(self.p2p.listen(*addr).await?);
+        }
+        self.run_network();
+        Ok(bound)
+    }
+
+    /// Takes part in the network without accepting connections, so the node
+    /// is only reachable through the peers it dials.
+    pub fn start_outbound_network(&self) {
+        self.run_network();
+    }
+
+    fn run_network(&self) {
+        let seen_blocks = Arc::new(Mutex::new(SeenHashes::new(SEEN_BLOCKS)));
+        let seen_transactions = Arc::new(Mutex::new(SeenHashes::new(SEEN_TRANSACTIONS)));
+        self.relay_chain_events(seen_blocks.clone(), seen_transactions.clone());
+        self.log_sync_progress();
+
+        let p2p = self.p2p.clone();
+        let blockchain = self.blockchain.clone();
+        let mut sync = SyncManager::new(blockchain.clone(), p2p.clone(), seen_blocks.clone());
+        // Light and seed nodes follow the header chain without keeping its
+        // blocks.
+        let headers_only = matches!(self.node_type, NodeType::LightNode | NodeType::SeedNode);
+        sync.set_headers_only(headers_only);
+        sync.set_snapshot_distance(self.snapshot_distance);
+        sync.set_prune_depth(
+            matches!(self.node_type, NodeType::PrunedNode).then_some(self.prune_depth),
+        );
+        sync.set_checkpoint(self.checkpoint);
+        sync.set_state_sender(self.sync_state.clone());
+        sync.set_event_bus(self.events.clone());
+        let mut shutdown = self.shutdown.subscribe();
+        tokio::spawn(async move {
+            // Headers announced to us whose bodies have been requested.
+            let mut pending: HashMap<Hash, BlockHeader> = HashMap::new();
+            let mut status_interval = tokio::time::interval(STATUS_INTERVAL);
+            loop {
+                let (peer, message) = tokio::select! {
+                    Some(inbound) = p2p.next_message() => inbound,
+                    _ = status_interval.tick() => {
+                        if let Ok(status) = sync.status().await {
+                            p2p.broadcast(status, None).await;
+                        }
+                        sync.tick().await;
+                        continue;
+                    }
+                    _ = shutdown.changed() => break,
+                    else => break,
+                };
+                match message {
+                    // A newly connected peer; tell it where our chain is.
+                    Message::Hello { .. } => {
+                        if let Ok(status) = sync.status().await {
+                            p2p.send_to(peer, status).await;
+                        }
+                    }
+                    message @ (Message::Status { .. }
+                    | Message::GetHeaders { .. }
+                    | Message::Headers(_)
+                    | Message::GetBlocks(_)
+                    | Message::Blocks(_)
+                    | Message::GetSnapshot { .. }
+                    | Message::SnapshotChunk { .. }
+                    | Message::GetTransactionProofs { .. }
+                    | Message::TransactionProofs { .. }) => sync.handle(peer, message).await,
+                    Message::Vote(vote) => match blockchain.add_vote(vote.clone()).await {
+                        // Relay only votes that were new to us, so gossip dies out.
+                        Ok(VoteOutcome::Duplicate) => {}
+                        Ok(outcome) => {
+                            if outcome == VoteOutcome::Finalized {
+                                info!(hash = %hex::encode(vote.block_hash), height = vote.height, "block finalized");
+                            }
+                            p2p.broadcast(Message::Vote(vote), Some(peer)).await;
+                        }
+                        Err(e) => warn!(%peer, error = %e, "rejected vote"),
+                    },
+                    Message::NewBlock { header } => {
+                        let hash = header.hash();
+                        if seen_blocks.lock().await.contains(&hash)
+                            || pending.contains_key(&hash)
+                            || pending.len() >= MAX_PENDING_BODIES
+                            || sync.conflicts_with_checkpoint(&header)
+                        {
+                            continue;
+                        }
+                        if !matches!(blockchain.get_block(hash).await, Ok(None)) {
+                            continue;
+                        }
+                        // Too far ahead to follow block by block, on a
+                        // branch whose start we lack, or wanted only for its
+                        // header.
+                        let (_, height) = sync::chain_tip(&blockchain);
+                        if headers_only
+                            || header.height > height
+                            || !sync.has_block(header.previous_hash).await
+                        {
+                            sync.note_height(peer, header.height + 1).await;
+                            continue;
+                        }
+                        pending.insert(hash, header);
+                        p2p.send_to(peer, Message::GetBlockBody { hash }).await;
+                    }
+                    Message::NewTransaction(transaction) => {
+                        if !seen_transactions.lock().await.insert(transaction.hash()) {
+                            continue;
+                        }
+                        // Accepted transactions are relayed through the chain
+                        // events.
+                        if let Err(e) = blockchain.add_transaction(transaction).await {
+                            debug!(%peer, error = %e, "ignored gossiped transaction");
+                        }
+                    }
+                    Message::GetBlockBody { hash } => {
+                        if let Ok(Some(block)) = blockchain.get_full_block(hash).await {
+                            let body = Message::BlockBody {
+                                hash,
+                                transactions: block.transactions,
+                            };
+                            p2p.send_to(peer, body).await;
+                        }
+                    }
+                    Message::BlockBody { hash, transactions } => {
+                        let Some(header) = pending.remove(&hash) else {
+                            continue;
+                        };
+                        let block = Block {
+                            header,
+                            transactions,
+                        };
+                        // Accepted blocks are re-announced through the chain
+                        // events, like our own.
+                        if let Err(e) = sync.accept_block(block).await {
+                            debug!(%peer, error = %e, "ignored announced block");
+                            // Not fetched again when re-announced; sync
+                            // still takes it if its branch turns out longer.
+                            seen_blocks.lock().await.insert(hash);
+                        }
+                    }
+                    // Handled by the connection itself.
+                    Message::GetPeers
+                    | Message::Peers(_)
+                    | Message::Goodbye
+                    | Message::Disconnect(_) => {}
+                }
+            }
+        });
+    }
+
+    /// Announces every block the chain accepts and every transaction the
+    /// mempool accepts, local or received, to all peers until shutdown.
+    /// Blocks caught up on through sync are already marked seen and are
+    /// left for peers to sync themselves.
+    fn relay_chain_events(
+        &self,
+        seen_blocks: Arc<Mutex<SeenHashes>>,
+        seen_transactions: Arc<Mutex<SeenHashes>>,
+    ) {
+        let p2p = self.p2p.clone();
+        let mut events = self.events.subscribe();
+        let mut shutdown = self.shutdown.subscribe();
+        tokio::spawn(async move {
+            loop {
+                let event = tokio::select! {
+                    event = events.recv() => event,
+                    _ = shutdown.changed() => break,
+                };
+                match event {
+                    Ok(NodeEvent::BlockApplied(block)) => {
+                        if !seen_blocks.lock().await.insert(block.hash()) {
+                            continue;
+                        }
+                        let announcement = Message::NewBlock {
+                            header: block.header,
+                        };
+                        p2p.broadcast(announcement, None).await;
+                    }
+                    Ok(NodeEvent::TxAccepted(transaction)) => {
+                        // Peers echoing it back are then ignored.
+                        seen_transactions.lock().await.insert(transaction.hash());
+                        p2p.broadcast(Message::NewTransaction(transaction), None)
+                            .await;
+                    }
+                    // The new branch's blocks come through as `BlockApplied`.
+                    Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => {}
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        });
+    }
+
+    /// Logs when sync falls behind peers and when it catches up again.
+    fn log_sync_progress(&self) {
+        let mut events = self.events.subscribe();
+        let mut shutdown = self.shutdown.subscribe();
+        tokio::spawn(async move {
+            let mut syncing = false;
+            loop {
+                let event = tokio::select! {
+                    event = events.recv() => event,
+                    _ = shutdown.changed() => break,
+                };
+                match event {
+                    Ok(NodeEvent::SyncProgress(SyncState::Syncing { current, target }))
+                        if !syncing =>
+                    {
+                        syncing = true;
+                        info!(current, target, "syncing with peers");
+                    }
+                    Ok(NodeEvent::SyncProgress(SyncState::Synced)) if syncing => {
+                        syncing = false;
+                        info!("caught up with peers");
+                    }
+                    Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => {}
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        });
+    }
+
+    /// Serves the JSON-RPC API on `addr`, separately from the P2P port.
+    /// Returns the bound address.
+    pub async fn start_rpc(&self, addr: SocketAddr) -> Result<SocketAddr, String> {
+        self.start_rpc_with(addr, RpcAccess::default()).await
+    }
+
+    /// Serves JSON-RPC on `addr` until shutdown, admitting calls as
+    /// `access` says. Returns the bound address.
+    pub async fn start_rpc_with(
+        &self,
+        addr: SocketAddr,
+        access: RpcAccess,
+    ) -> Result<SocketAddr, String> {
+        rpc::serve(self.rpc_context(), addr, access, self.shutdown.subscribe()).await
+    }
+
+    fn rpc_context(&self) -> RpcContext {
+        RpcContext {
+            blockchain: self.blockchain.clone(),
+            p2p: self.p2p.clone(),
+            sync_state: self.sync_state.subscribe(),
+            events: self.events.clone(),
+        }
+    }
+
+    /// Serves the gRPC API on `addr` until shutdown, returning the bound
+    /// address.
+    #[cfg(feature = "grpc")]
+    pub async fn start_grpc(&self, addr: SocketAddr) -> Result<SocketAddr, String> {
+        crate::grpc::serve(self.rpc_context(), addr, self.shutdown.subscribe()).await
+    }
+
+    /// Notifies registered webhooks about their addresses until shutdown;
+    /// see [`crate::webhooks`].
+    pub fn start_webhooks(&self, config: WebhookConfig) {
+        webhooks::spawn(
+            self.blockchain.clone(),
+            self.events.subscribe(),
+            self.shutdown.subscribe(),
+            config,
+        );
+    }
+
+    /// Periodically exchanges peer lists and dials newly learned peers.
+    pub fn start_discovery(&self, config: DiscoveryConfig) {
+        self.p2p.start_discovery(config);
+    }
+
+    pub async fn connect(&self, addr: SocketAddr) -> Result<(), String> {
+        self.p2p.connect(addr).await
+    }
+
+    /// Keeps dialing `addr` in the background after a failed
+    /// [`Node::connect`], see [`P2P::retry_connect`].
+    pub fn retry_connect(&self, addr: SocketAddr) {
+        self.p2p.retry_connect(addr);
+    }
+
+    /// Backs up the database every `config.interval` until shutdown, on a
+    /// connection of its own so the node keeps working meanwhile.
+    pub async fn start_backups(&self, config: BackupConfig) -> Result<(), String> {
+        std::fs::create_dir_all(&config.dir)
+            .map_err(|e| format!("Failed to create {}: {}", config.dir.display(), e))?;
+        let mut database = self
+            .database
+            .lock()
             .await
-            .map_err(|_| "Failed to fetch transactions".to_string())?;
+            .try_clone()
+            .map_err(|e| format!("Cannot back up this database: {}", e))?;
+        let mut shutdown = self.shutdown.subscribe();
+        tokio::spawn(async move {
+            let start = tokio::time::Instant::now() + config.interval;
+            let mut timer = tokio::time::interval_at(start, config.interval);
+            loop {
+                tokio::select! {
+                    _ = timer.tick() => {}
+                    _ = shutdown.changed() => break,
+                }
+                let config = config.clone();
+                let backup = tokio::task::spawn_blocking(move || {
+                    let result = rotate_backup(&database, &config.dir, config.keep);
+                    (database, result)
+                })
+                .await;
+                match backup {
+                    Ok((returned, result)) => {
+                        database = returned;
+                        match result {
+                            Ok(path) => info!(path = %path.display(), "backed up database"),
+                            Err(e) => warn!(error = %e, "database backup failed"),
+                        }
+                    }
+                    Err(e) => {
+                        warn!(error = %e, "database backup panicked");
+                        break;
+                    }
+                }
+            }
+        });
+        Ok(())
+    }
 
-        let blocks = self
+    /// Proposes blocks as the validator `signer` signs for until shutdown.
+    /// Each block time, as the chain is configured or governance set it for
+    /// the next block, starts a new slot; if the validator is the slot's
+    /// proposer on top of the current head it produces a signed block,
+    /// which is announced like any other. The validator is the account
+    /// `signer`'s key signs for, looked up each slot so a key registered or
+    /// rotated to on chain takes over from the next block on.
+    ///
+    /// A validator whose node signs with its account key also commits to a
+    /// beacon secret each epoch and reveals it in the next; see
+    /// [`crate::beacon`]. One signing with a consensus key of its own cannot
+    /// sign its account's transactions, and takes part through the client.
+    pub fn start_validator(&self, signer: BlockSigner) {
+        let node = self.clone();
+        let mut shutdown = self.shutdown.subscribe();
+        tokio::spawn(async move {
+            let mut last_slot = None;
+            let mut beacon_epoch = None;
+            loop {
+                let (_, height) = sync::chain_tip(&node.blockchain);
+                let block_time = match node.blockchain.block_time(height).await {
+                    Ok(block_time) => block_time,
+                    Err(e) => {
+                        warn!(error = %e, "failed to read the governed block time");
+                        node.blockchain.chain_params().block_time
+                    }
+                };
+                let slot_millis = block_time.as_millis().max(1) as i64;
+                // Wakes at the start of the next slot.
+                let now = Utc::now().timestamp_millis();
+                let wait = Duration::from_millis((slot_millis - now % slot_millis) as u64);
+                tokio::select! {
+                    _ = tokio::time::sleep(wait) => {}
+                    _ = shutdown.changed() => break,
+                }
+                // The slot the block would be stamped in, which peers hold
+                // its proposer to.
+                let slot = liveness::slot(Utc::now().timestamp(), block_time);
+                if last_slot == Some(slot) {
+                    continue;
+                }
+                last_slot = Some(slot);
+
+                let address = match node
+                    .blockchain
+                    .validator_for_key(&signer.public_key())
+                    .await
+                {
+                    Ok(Some(address)) => address,
+                    Ok(None) => {
+                        debug!(slot, "the signing key was rotated out");
+                        continue;
+                    }
+                    Err(e) => {
+                        warn!(slot, error = %e, "failed to look up the signing key");
+                        continue;
+                    }
+                };
+                let (previous_hash, height) = sync::chain_tip(&node.blockchain);
+                if let BlockSigner::Local(key) = &signer
+                    && key_address(key) == address
+                {
+                    let epoch_length = node.blockchain.chain_params().liveness.epoch_length;
+                    let epoch = beacon::epoch(height, epoch_length);
+                    if beacon_epoch != Some(epoch) {
+                        beacon_epoch = Some(epoch);
+                        node.take_part_in_beacon(key, height).await;
+                    }
+                }
+                match node
+                    .blockchain
+                    .slot_proposer(previous_hash, height, slot)
+                    .await
+                {
+                    Ok(proposer) if proposer == address => {}
+                    Ok(_) => continue,
+                    Err(e) => {
+                        debug!(slot, error = %e, "no proposer for slot");
+                        continue;
+                    }
+                }
+                if let Err(e) = node.build_block(address, Some(&signer)).await {
+                    warn!(slot, error = %e, "failed to produce block");
+                }
+            }
+        });
+    }
+
+    /// Reveals the beacon secret the validator account `key` signs for
+    /// committed to in the epoch before that of the block at `height`, if
+    /// it did, and commits to one for that epoch.
+    async fn take_part_in_beacon(&self, key: &SigningKey, height: u64) {
+        if !self
             .blockchain
-            .get_blocks()
+            .chain_params()
+            .forks
+            .is_active(Feature::Beacon, height)
+        {
+            return;
+        }
+        let address = key_address(key);
+        let epoch = beacon::epoch(height, self.blockchain.chain_params().liveness.epoch_length);
+        if let Some(previous) = epoch.checked_sub(1) {
+            let secret = beacon::secret(key, previous);
+            if let Err(e) = self
+                .submit(key, height, TxKind::Reveal, TxData::Secret(secret))
+                .await
+            {
+                debug!(epoch, error = %e, "no beacon secret revealed");
+            }
+        }
+        let commit = beacon::Commit {
+            commitment: beacon::commitment(&address, &beacon::secret(key, epoch)),
+            epoch,
+        };
+        if let Err(e) = self
+            .submit(key, height, TxKind::Commit, TxData::Commit(commit))
             .await
-            .map_err(|_| "Failed to fetch blocks".to_string())?;
+        {
+            warn!(epoch, error = %e, "failed to commit to a beacon secret");
+        }
+    }
 
-        let previous_hash = blocks
-            .last()
-            .map(|b| b.hash().unwrap_or([0u8; 32]))
-            .unwrap_or([0u8; 32]);
+    /// Signs a transaction of `kind` carrying `data` from the account `key`
+    /// signs for at its next nonce, paying the fee the block at `height`
+    /// requires, and submits it to the mempool.
+    async fn submit(
+        &self,
+        key: &SigningKey,
+        height: u64,
+        kind: TxKind,
+        data: TxData,
+    ) -> Result<(), String> {
+        let nonce = self.blockchain.account_nonce(&key_address(key)).await?;
+        let parameters = self.blockchain.parameters(height).await?;
+        // The fee adds to the size it is charged on.
+        let mut fee = Amount::ZERO;
+        loop {
+            let tx = Transfer {
+                receiver: key_address(key),
+                amount: Amount::ZERO,
+                fee,
+                nonce,
+                kind,
+                data,
+            }
+            .into_transaction(key);
+            let required = parameters.required_fee(&tx);
+            if required <= fee {
+                return Ok(self.blockchain.add_transaction(tx).await?);
+            }
+            fee = required;
+        }
+    }
 
-        let nonce = blocks.len() as u64;
+    /// Produces a block from the mempool for the validator whose slot it
+    /// is, unsigned since the validator's key is not at hand. Only this
+    /// node accepts it; peers refuse unsigned blocks.
+    pub async fn produce_block(&mut self) -> Result<[u8; 32], String> {
         let proposer = self.blockchain.select_validator().await?;
-        let block = Block::new(previous_hash, nonce, transactions);
+        self.build_block(proposer, None).await
+    }
+
+    /// Produces a block from the mempool signed by `signer` for the
+    /// validator it signs for, as [`Node::start_validator`] does in each of
+    /// its slots. Peers refuse it unless the slot is that validator's.
+    pub async fn produce_signed_block(&mut self, signer: &BlockSigner) -> Result<Hash, String> {
+        let proposer = self
+            .blockchain
+            .validator_for_key(&signer.public_key())
+            .await?
+            .ok_or("The signing key is not a validator's".to_string())?;
+        self.build_block(proposer, Some(signer)).await
+    }
 
-        self.blockchain.apply_block(&block).await?;
-        self.blockchain.add_block(block.clone(), proposer).await?;
+    async fn build_block(
+        &self,
+        proposer: Address,
+        signer: Option<&BlockSigner>,
+    ) -> Result<Hash, String> {
+        // Peers would reject blocks stamped by a clock far from theirs.
+        self.p2p.check_clock().await?;
+        let mut blockchain = self.blockchain.clone();
+        let (previous_hash, height) = sync::chain_tip(&blockchain);
 
-        let hash = block
-            .hash()
-            .map_err(|e| format!("Block hashing failed: {}", e))?;
-        println!(
-            "Block successfully produced with hash: {}",
-            hex::encode(hash)
-        );
+        let candidates = blockchain.block_candidates().await;
+        let transactions = blockchain.select_transactions(candidates).await;
+        let mut block = Block::new(previous_hash, height, proposer, transactions);
+        block.header.coinbase = blockchain.block_reward(height).await?;
+        if let Some((finalized_hash, _)) = blockchain.latest_finalized().await? {
+            block.header.finalized_hash = finalized_hash;
+        }
+
+        // Blocks produced within the same second still need to move past the
+        // median time of their predecessors.
+        if let Some(median) = blockchain.median_time_past().await? {
+            block.header.timestamp = block.header.timestamp.max(median + 1);
+        }
+
+        block.header.state_root = blockchain.state_root_after(&block).await?;
+        match signer {
+            Some(signer) => {
+                signer.sign(&mut block).await?;
+                blockchain.add_block(block.clone()).await?;
+            }
+            None => blockchain.add_local_block(block.clone()).await?,
+        }
+
+        let hash = block.hash();
+        info!(hash = %hex::encode(hash), height, "produced block");
         Ok(hash)
     }
 }
+
+/// Writes a timestamped backup into `dir`, then deletes all but the newest
+/// `keep` backups there.
+fn rotate_backup(database: &Database, dir: &Path, keep: usize) -> Result<PathBuf, String> {
+    let name = format!("smvblock-{}.db", Utc::now().format("%Y%m%d-%H%M%S%.3f"));
+    let path = dir.join(name);
+    // Written under another name first, so a failed backup is never taken
+    // for a complete one.
+    let partial = path.with_extension("db.partial");
+    database
+        .backup(&partial)
+        .map_err(|e| format!("Failed to write {}: {}", partial.display(), e))?;
+    std::fs::rename(&partial, &path)
+        .map_err(|e| format!("Failed to move {}: {}", partial.display(), e))?;
+
+    let mut backups: Vec<PathBuf> = std::fs::read_dir(dir)
+        .map_err(|e| format!("Failed to read {}: {}", dir.display(), e))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with("smvblock-") && name.ends_with(".db"))
+        })
+        .collect();
+    // The timestamps sort in the order they were taken.
+    backups.sort();
+    let stale = backups.len().saturating_sub(keep.max(1));
+    for old in &backups[..stale] {
+        if let Err(e) = std::fs::remove_file(old) {
+            warn!(path = %old.display(), error = %e, "failed to delete old backup");
+        }
+    }
+    Ok(path)
+}
+
+/// The key this node identifies itself to peers with, generated and saved
+/// on first start so peers know it by the same ID across restarts.
+fn node_identity(database: &Database) -> SigningKey {
+    match database.get_node_key() {
+        Ok(Some(secret_key)) => SigningKey::from_bytes(&secret_key),
+        Ok(None) => {
+            let key = SigningKey::generate(&mut OsRng);
+            if let Err(e) = database.save_node_key(&key.to_bytes()) {
+                warn!(error = %e, "failed to save node identity");
+            }
+            key
+        }
+        Err(e) => {
+            warn!(error = %e, "failed to load node identity, using a temporary one");
+            SigningKey::generate(&mut OsRng)
+        }
+    }
+}
+
+/// Resolves on Ctrl-C, or on SIGTERM where that exists.
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{SignalKind, signal};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = terminate.recv() => {}
+                }
+            }
+            Err(_) => {
+                let _ = tokio::signal::ctrl_c().await;
+            }
+        }
+    }
+
+    #[cfg(not(unix))]
+    let _ = tokio::signal::ctrl_c().await;
+}
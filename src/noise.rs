@@ -0,0 +1,181 @@
+use snow::{Builder, StatelessTransportState};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+
+/// Mutual authentication with static keys exchanged during the handshake.
+const NOISE_PARAMS: &str = "Noise_XX_25519_ChaChaPoly_SHA256";
+
+/// Largest Noise message, and so the largest frame on the wire.
+const MAX_FRAME_LEN: usize = 65535;
+const TAG_LEN: usize = 16;
+/// Plaintext carried by one full transport frame.
+const MAX_CHUNK_LEN: usize = MAX_FRAME_LEN - TAG_LEN;
+
+/// A peer that has not finished the handshake by now is dropped.
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+fn builder() -> Result<Builder<'static>, String> {
+    let params = NOISE_PARAMS
+        .parse()
+        .map_err(|e| format!("Invalid noise parameters: {}", e))?;
+    Ok(Builder::new(params))
+}
+
+/// Runs a Noise XX handshake over `stream`, returning the encrypted halves
+/// and the remote's static public key.
+pub async fn handshake(
+    stream: TcpStream,
+    initiator: bool,
+    private_key: &[u8],
+) -> Result<(NoiseReader, NoiseWriter, Vec<u8>), String> {
+    tokio::time::timeout(
+        HANDSHAKE_TIMEOUT,
+        run_handshake(stream, initiator, private_key),
+    )
+    .await
+    .map_err(|_| "Noise handshake timed out".to_string())?
+}
+
+async fn run_handshake(
+    mut stream: TcpStream,
+    initiator: bool,
+    private_key: &[u8],
+) -> Result<(NoiseReader, NoiseWriter, Vec<u8>), String> {
+    let builder = builder()?.local_private_key(private_key);
+    let mut state = if initiator {
+        builder.build_initiator()
+    } else {
+        builder.build_responder()
+    }
+    .map_err(|e| format!("Failed to start noise handshake: {}", e))?;
+
+    let mut buf = vec![0u8; MAX_FRAME_LEN];
+    let mut payload = vec![0u8; MAX_FRAME_LEN];
+    while !state.is_handshake_finished() {
+        if state.is_my_turn() {
+            let len = state
+                .write_message(&[], &mut buf)
+                .map_err(|e| format!("Noise handshake failed: {}", e))?;
+            write_noise_frame(&mut stream, &buf[..len]).await?;
+        } else {
+            let frame = read_noise_frame(&mut stream)
+                .await?
+                .ok_or("Connection closed during noise handshake")?;
+            state
+                .read_message(&frame, &mut payload)
+                .map_err(|e| format!("Noise handshake failed: {}", e))?;
+        }
+    }
+
+    let remote_static = state
+        .get_remote_static()
+        .ok_or("Peer sent no static key")?
+        .to_vec();
+    let transport = Arc::new(
+        state
+            .into_stateless_transport_mode()
+            .map_err(|e| format!("Noise handshake failed: {}", e))?,
+    );
+
+    let (read, write) = stream.into_split();
+    let reader = NoiseReader {
+        read,
+        transport: transport.clone(),
+        nonce: 0,
+        plaintext: Vec::new(),
+    };
+    let writer = NoiseWriter {
+        write,
+        transport,
+        nonce: 0,
+    };
+    Ok((reader, writer, remote_static))
+}
+
+async fn write_noise_frame<W: AsyncWriteExt + Unpin>(
+    write: &mut W,
+    frame: &[u8],
+) -> Result<(), String> {
+    write
+        .write_all(&(frame.len() as u16).to_be_bytes())
+        .await
+        .map_err(|e| e.to_string())?;
+    write.write_all(frame).await.map_err(|e| e.to_string())
+}
+
+/// Reads one length-prefixed frame, or `None` if the stream ended cleanly.
+async fn read_noise_frame<R: AsyncReadExt + Unpin>(
+    read: &mut R,
+) -> Result<Option<Vec<u8>>, String> {
+    let mut len = [0u8; 2];
+    match read.read_exact(&mut len).await {
+        Ok(_) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.to_string()),
+    }
+    let mut frame = vec![0u8; u16::from_be_bytes(len) as usize];
+    read.read_exact(&mut frame)
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(Some(frame))
+}
+
+/// Receiving half of an encrypted connection.
+pub struct NoiseReader {
+    read: OwnedReadHalf,
+    transport: Arc<StatelessTransportState>,
+    nonce: u64,
+    /// Decrypted bytes not yet returned by [`NoiseReader::read_exact`].
+    plaintext: Vec<u8>,
+}
+
+impl NoiseReader {
+    /// Fills `buf` with decrypted bytes. Returns `Ok(false)` if the stream
+    /// ended first and an error if a frame fails to decrypt.
+    pub async fn read_exact(&mut self, buf: &mut [u8]) -> Result<bool, String> {
+        loop {
+            if self.plaintext.len() >= buf.len() {
+                buf.copy_from_slice(&self.plaintext[..buf.len()]);
+                self.plaintext.drain(..buf.len());
+                return Ok(true);
+            }
+
+            let Some(frame) = read_noise_frame(&mut self.read).await? else {
+                return Ok(false);
+            };
+            let mut chunk = vec![0u8; frame.len()];
+            let len = self
+                .transport
+                .read_message(self.nonce, &frame, &mut chunk)
+                .map_err(|e| format!("Failed to decrypt frame: {}", e))?;
+            self.nonce += 1;
+            self.plaintext.extend_from_slice(&chunk[..len]);
+        }
+    }
+}
+
+/// Sending half of an encrypted connection.
+pub struct NoiseWriter {
+    write: OwnedWriteHalf,
+    transport: Arc<StatelessTransportState>,
+    nonce: u64,
+}
+
+impl NoiseWriter {
+    /// Encrypts and sends `data`, split across as many frames as needed.
+    pub async fn write_all(&mut self, data: &[u8]) -> Result<(), String> {
+        let mut frame = vec![0u8; MAX_FRAME_LEN];
+        for chunk in data.chunks(MAX_CHUNK_LEN) {
+            let len = self
+                .transport
+                .write_message(self.nonce, chunk, &mut frame)
+                .map_err(|e| format!("Failed to encrypt frame: {}", e))?;
+            self.nonce += 1;
+            write_noise_frame(&mut self.write, &frame[..len]).await?;
+        }
+        Ok(())
+    }
+}
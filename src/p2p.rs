@@ -1,14 +1,1811 @@
+//! Peer networking over plain TCP. Each connection runs a Noise XX
+//! handshake keyed by the node identity (see [`crate::noise`]), then carries
+//! length-prefixed, versioned bincode frames, optionally snappy compressed.
+//! Peers are found through `GetPeers`/`Peers` exchange and remembered in the
+//! database.
+
+use crate::blockchain::{
+    Address, Block, BlockHeader, Hash, MAX_FUTURE_DRIFT_SECS, SnapshotAccount, Transaction,
+    TransactionProof,
+};
 use crate::db::Database;
-use libp2p::futures::lock::Mutex;
-use std::sync::Arc;
+use crate::events::{EventBus, NodeEvent};
+use crate::finality::Vote;
+use crate::node::NodeType;
+use crate::noise::{self, NoiseReader, NoiseWriter};
+use crate::proxy::Socks5Proxy;
+use crate::sync::SyncState;
+use bincode::config::standard;
+use chrono::Utc;
+use ed25519_dalek::{SigningKey, VerifyingKey};
+use futures::future::join_all;
+use futures::lock::Mutex;
+use mdns_sd::{ServiceDaemon, ServiceEvent, ServiceInfo};
+use rand::rngs::OsRng;
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc::{self, Receiver, Sender};
+use tokio::sync::{Notify, watch};
+use tokio::task::JoinHandle;
+use tracing::{Instrument, debug, info, info_span, warn};
+
+/// How long shutdown waits for goodbye messages to be written.
+const GOODBYE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Messages queued for a peer before it is taken to have stalled and is
+/// dropped, so a peer that stops reading cannot pile up broadcasts.
+pub const PEER_QUEUE: usize = 1024;
+
+/// Messages from all peers waiting for the node to handle them. Readers
+/// wait when it is full, which holds peers back through TCP flow control.
+const INBOUND_QUEUE: usize = 4096;
+
+/// Most addresses accepted from, or sent in, a single `Peers` message.
+const MAX_PEERS_PER_MESSAGE: usize = 100;
+
+/// How long a seed node's crawler stays connected to a peer it probes,
+/// for the peer's `Hello` and `Peers` to arrive.
+const PROBE_LINGER: Duration = Duration::from_secs(2);
+
+/// Seed nodes only hand out addresses found alive this recently.
+const FRESH_PEER_AGE: Duration = Duration::from_secs(3 * 60 * 60);
+
+/// How far the local clock may be from the peers' before the node stops
+/// producing blocks, in milliseconds. Half the drift blocks are allowed, so
+/// that our blocks are not rejected as coming from the future, nor are our
+/// peers' rejected here.
+pub const MAX_CLOCK_OFFSET_MILLIS: i64 = MAX_FUTURE_DRIFT_SECS * 1000 / 2;
+
+/// Peers whose clocks are needed before the local clock is judged by them.
+const MIN_CLOCK_SAMPLES: usize = 3;
+
+/// A failed dial counts this many times as much as a successful one when
+/// ranking addresses; an address is forgotten once its score drops below
+/// [`MIN_PEER_SCORE`].
+const DIAL_FAILURE_PENALTY: i64 = 2;
+const MIN_PEER_SCORE: i64 = -4;
+
+/// Service type nodes advertise over mDNS.
+const MDNS_SERVICE_TYPE: &str = "_smvblock._tcp.local.";
+
+/// Largest frame accepted from a peer, header bytes included; a peer
+/// announcing a longer one is disconnected before anything is buffered.
+/// Decompressed payloads are held to the same limit.
+pub const MAX_FRAME_SIZE: usize = 1024 * 1024;
+
+/// Leads every frame so incompatible peers are told apart from garbage.
+pub const PROTOCOL_VERSION: u8 = 12;
+
+/// This build's version, reported to peers and RPC clients.
+pub const NODE_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Chain nodes join unless configured otherwise.
+pub const DEFAULT_CHAIN_ID: &str = "smvblock";
+
+/// Payloads smaller than this are sent as is even when compression was
+/// negotiated; they gain little and cost a pass through the compressor.
+pub const COMPRESSION_THRESHOLD: usize = 512;
+
+/// Frame flag marking a snappy compressed payload.
+const FLAG_SNAPPY: u8 = 1;
+
+/// A node's identity key, which peers know it by whatever address it
+/// connects from.
+pub type NodeId = [u8; 32];
+
+/// Payload compression a node can offer in its `Hello`.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+pub enum Compression {
+    Snappy,
+}
+
+/// Everything peers exchange over a Noise encrypted connection, one
+/// message per frame: a big-endian `u32` length, [`PROTOCOL_VERSION`], a
+/// flags byte and the bincode encoded message, compressed if flagged.
+///
+/// Variants are encoded by position and fields in order, so nodes of the
+/// same protocol version can talk only while new variants are added at the
+/// end and existing ones are left as they are. Any other change needs a new
+/// [`PROTOCOL_VERSION`]; `tests/wire.rs` pins the current encoding.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub enum Message {
+    /// First message on every connection: the sender's identity key, which
+    /// must match the static key it completed the Noise handshake with, the
+    /// chain it is on and where it accepts peers, if anywhere.
+    Hello {
+        identity: NodeId,
+        chain_id: String,
+        /// Hash of the sender's first block, unless its chain is empty.
+        /// Peers whose genesis differs are on another chain.
+        genesis: Option<Hash>,
+        listen_addr: Option<SocketAddr>,
+        node_type: NodeType,
+        /// Compression the sender can decode; either side may compress
+        /// once both offered the same one.
+        compression: Vec<Compression>,
+        /// Unix time in milliseconds when the sender sent this, which
+        /// peers check their clocks against.
+        timestamp: i64,
+    },
+    GetPeers,
+    Peers(Vec<SocketAddr>),
+    Vote(Vote),
+    /// The sender accepted this block; peers missing it ask for the body.
+    NewBlock {
+        header: BlockHeader,
+    },
+    GetBlockBody {
+        hash: Hash,
+    },
+    BlockBody {
+        hash: Hash,
+        transactions: Vec<Transaction>,
+    },
+    /// A transaction the sender accepted into its mempool.
+    NewTransaction(Transaction),
+    /// The sender's chain: how many blocks it has, which is also the height
+    /// of its next block, and the hash of its latest block. Whole blocks
+    /// can be asked of it from `oldest_block` up. The rest describes the
+    /// sender for monitoring and does not affect sync.
+    Status {
+        height: u64,
+        head: Hash,
+        oldest_block: u64,
+        finalized_height: Option<u64>,
+        peers: u32,
+        pending_transactions: u32,
+        sync: SyncState,
+        version: String,
+        /// Seconds since the sender started.
+        uptime: u64,
+    },
+    /// Up to `count` consecutive headers starting at `from_height`.
+    GetHeaders {
+        from_height: u64,
+        count: u64,
+    },
+    Headers(Vec<BlockHeader>),
+    /// Full blocks for the given hashes, answered in the same order.
+    GetBlocks(Vec<Hash>),
+    Blocks(Vec<Block>),
+    /// One chunk of the state snapshot taken at block `hash`, or at the
+    /// receiver's latest block if `hash` is `None`.
+    GetSnapshot {
+        hash: Option<Hash>,
+        index: u32,
+    },
+    /// Accounts `index` of `total` chunks, ordered by address, as of the
+    /// block `hash` at `height`. A `total` of zero means the sender has no
+    /// snapshot for that block.
+    SnapshotChunk {
+        hash: Hash,
+        height: u64,
+        index: u32,
+        total: u32,
+        accounts: Vec<SnapshotAccount>,
+    },
+    /// Proofs for the transactions sent or received by `addresses` in the
+    /// blocks from `from_height` up to `to_height`, asked by nodes that keep
+    /// only headers.
+    GetTransactionProofs {
+        addresses: Vec<Address>,
+        from_height: u64,
+        to_height: u64,
+    },
+    /// Every such transaction in the blocks from `from_height` up to
+    /// `scanned_to`, which may stop short of `to_height`. A `scanned_to` of
+    /// `from_height` means the sender does not have those blocks whole.
+    TransactionProofs {
+        proofs: Vec<TransactionProof>,
+        scanned_to: u64,
+    },
+    /// The sender is shutting down and closing the connection.
+    Goodbye,
+    /// The sender is closing the connection for `reason`.
+    Disconnect(DisconnectReason),
+}
+
+/// Why a peer was turned away.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+pub enum DisconnectReason {
+    /// The receiver has no free connection slot for this direction.
+    TooManyPeers,
+    /// The two nodes are already connected.
+    DuplicateConnection,
+    /// The receiver is the sender itself, reached through one of its own
+    /// addresses.
+    SelfConnection,
+    /// The receiver refuses to talk to the sender.
+    Blocked,
+}
+
+impl Message {
+    /// Name of the variant, used to rate limit each kind separately.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Message::Hello { .. } => "hello",
+            Message::GetPeers => "get_peers",
+            Message::Peers(_) => "peers",
+            Message::Vote(_) => "vote",
+            Message::NewBlock { .. } => "new_block",
+            Message::GetBlockBody { .. } => "get_block_body",
+            Message::NewTransaction(_) => "new_transaction",
+            Message::Status { .. } => "status",
+            Message::GetHeaders { .. } => "get_headers",
+            Message::Headers(_) => "headers",
+            Message::GetBlocks(_) => "get_blocks",
+            Message::Blocks(_) => "blocks",
+            Message::GetSnapshot { .. } => "get_snapshot",
+            Message::SnapshotChunk { .. } => "snapshot_chunk",
+            Message::GetTransactionProofs { .. } => "get_transaction_proofs",
+            Message::TransactionProofs { .. } => "transaction_proofs",
+            Message::BlockBody { .. } => "block_body",
+            Message::Goodbye => "goodbye",
+            Message::Disconnect(_) => "disconnect",
+        }
+    }
+
+    /// Burst size and sustained messages per second a peer may send.
+    fn rate_limit(&self) -> (f64, f64) {
+        match self {
+            Message::Hello { .. } => (2.0, 0.1),
+            Message::GetPeers | Message::Peers(_) => (5.0, 0.5),
+            Message::Vote(_) => (200.0, 50.0),
+            Message::NewTransaction(_) => (200.0, 100.0),
+            Message::NewBlock { .. } | Message::GetBlockBody { .. } | Message::BlockBody { .. } => {
+                (20.0, 5.0)
+            }
+            Message::Status { .. } => (5.0, 1.0),
+            Message::GetHeaders { .. }
+            | Message::Headers(_)
+            | Message::GetBlocks(_)
+            | Message::Blocks(_)
+            | Message::GetSnapshot { .. }
+            | Message::SnapshotChunk { .. }
+            | Message::GetTransactionProofs { .. }
+            | Message::TransactionProofs { .. } => (20.0, 10.0),
+            Message::Goodbye | Message::Disconnect(_) => (1.0, 0.1),
+        }
+    }
+}
+
+type Inbound = (SocketAddr, Message);
+
+/// Messages and frame bytes exchanged of one message kind.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct KindTraffic {
+    pub messages_sent: u64,
+    pub bytes_sent: u64,
+    pub messages_received: u64,
+    pub bytes_received: u64,
+}
 
+/// Frame bytes exchanged with a peer, or with all of them, in total and by
+/// message kind. Frames that did not decode count as `malformed`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TrafficStats {
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    pub kinds: BTreeMap<&'static str, KindTraffic>,
+}
+
+impl TrafficStats {
+    fn record_sent(&mut self, kind: &'static str, bytes: usize) {
+        let kind = self.kinds.entry(kind).or_default();
+        kind.messages_sent += 1;
+        kind.bytes_sent += bytes as u64;
+        self.bytes_sent += bytes as u64;
+    }
+
+    fn record_received(&mut self, kind: &'static str, bytes: usize) {
+        let kind = self.kinds.entry(kind).or_default();
+        kind.messages_received += 1;
+        kind.bytes_received += bytes as u64;
+        self.bytes_received += bytes as u64;
+    }
+}
+
+/// Encodes `message` as a complete frame, length prefix included,
+/// compressing large payloads with `compression` if given.
+pub fn encode_frame(
+    message: &Message,
+    compression: Option<Compression>,
+) -> Result<Vec<u8>, String> {
+    let mut payload = bincode::serde::encode_to_vec(message, standard())
+        .map_err(|e| format!("Failed to encode message: {}", e))?;
+    if payload.len() > MAX_FRAME_SIZE - 2 {
+        return Err(format!("Message exceeds {} bytes", MAX_FRAME_SIZE));
+    }
+
+    let mut flags = 0;
+    if compression == Some(Compression::Snappy) && payload.len() >= COMPRESSION_THRESHOLD {
+        let compressed = snap::raw::Encoder::new()
+            .compress_vec(&payload)
+            .map_err(|e| format!("Failed to compress message: {}", e))?;
+        if compressed.len() < payload.len() {
+            payload = compressed;
+            flags |= FLAG_SNAPPY;
+        }
+    }
+
+    let len = payload.len() + 2;
+    let mut frame = Vec::with_capacity(len + 4);
+    frame.extend_from_slice(&(len as u32).to_be_bytes());
+    frame.push(PROTOCOL_VERSION);
+    frame.push(flags);
+    frame.extend_from_slice(&payload);
+    Ok(frame)
+}
+
+/// Reads one frame and returns its decompressed message payload, or `None`
+/// at end of stream. Oversized frames, unknown versions or flags and
+/// payloads that decompress past [`MAX_FRAME_SIZE`] are errors.
+pub async fn read_frame(reader: &mut NoiseReader) -> Result<Option<Vec<u8>>, String> {
+    Ok(read_sized_frame(reader).await?.map(|(payload, _)| payload))
+}
+
+/// [`read_frame`], also returning the frame's size on the wire.
+async fn read_sized_frame(reader: &mut NoiseReader) -> Result<Option<(Vec<u8>, usize)>, String> {
+    let mut len = [0u8; 4];
+    if !reader.read_exact(&mut len).await? {
+        return Ok(None);
+    }
+    let len = u32::from_be_bytes(len) as usize;
+    if !(2..=MAX_FRAME_SIZE).contains(&len) {
+        return Err(format!("Invalid frame length {}", len));
+    }
+
+    let mut frame = vec![0u8; len];
+    if !reader.read_exact(&mut frame).await? {
+        return Err("Connection closed mid-frame".to_string());
+    }
+    if frame[0] != PROTOCOL_VERSION {
+        return Err(format!("Unsupported protocol version {}", frame[0]));
+    }
+    let payload = &frame[2..];
+    let size = len + 4;
+    match frame[1] {
+        0 => Ok(Some((payload.to_vec(), size))),
+        FLAG_SNAPPY => {
+            let len = snap::raw::decompress_len(payload).map_err(|e| e.to_string())?;
+            if len > MAX_FRAME_SIZE {
+                return Err(format!(
+                    "Decompressed message exceeds {} bytes",
+                    MAX_FRAME_SIZE
+                ));
+            }
+            snap::raw::Decoder::new()
+                .decompress_vec(payload)
+                .map(|payload| Some((payload, size)))
+                .map_err(|e| format!("Failed to decompress message: {}", e))
+        }
+        flags => Err(format!("Unknown frame flags {}", flags)),
+    }
+}
+
+/// Decodes a message payload as [`read_frame`] returns it.
+pub fn decode_message(payload: &[u8]) -> Result<Message, String> {
+    // Without a limit, a length prefix claiming gigabytes is allocated
+    // before the payload is found to be short.
+    bincode::serde::decode_from_slice(payload, standard().with_limit::<MAX_FRAME_SIZE>())
+        .map(|(message, _)| message)
+        .map_err(|e| e.to_string())
+}
+
+/// Token bucket refilled continuously at `rate` tokens per second.
 #[derive(Debug)]
+pub(crate) struct TokenBucket {
+    tokens: f64,
+    capacity: f64,
+    rate: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    pub(crate) fn new(capacity: f64, rate: f64) -> Self {
+        TokenBucket {
+            tokens: capacity,
+            capacity,
+            rate,
+            updated: Instant::now(),
+        }
+    }
+
+    pub(crate) fn try_take(&mut self) -> bool {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.updated = now;
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+
+    /// Takes `amount` tokens, going into debt if there are too few, and
+    /// returns how long until the debt is paid off.
+    fn take(&mut self, amount: f64) -> Duration {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity) - amount;
+        self.updated = now;
+        if self.tokens >= 0.0 {
+            return Duration::ZERO;
+        }
+        Duration::from_secs_f64(-self.tokens / self.rate)
+    }
+
+    /// Whether the bucket has refilled, so forgetting it loses nothing.
+    pub(crate) fn is_full(&self) -> bool {
+        self.tokens + self.updated.elapsed().as_secs_f64() * self.rate >= self.capacity
+    }
+}
+
+/// Per message kind rate limits for a single peer.
+#[derive(Debug, Default)]
+struct RateLimiter {
+    buckets: HashMap<&'static str, TokenBucket>,
+}
+
+impl RateLimiter {
+    fn allow(&mut self, message: &Message) -> bool {
+        self.buckets
+            .entry(message.kind())
+            .or_insert_with(|| {
+                let (capacity, rate) = message.rate_limit();
+                TokenBucket::new(capacity, rate)
+            })
+            .try_take()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct DiscoveryConfig {
+    /// How often connected peers are asked for their peers.
+    pub interval: Duration,
+    /// Outbound connections discovery dials up to.
+    pub outbound_target: usize,
+    /// Also advertise and browse for nodes on the local network over mDNS.
+    /// Only useful when listening on an address other machines can reach.
+    pub mdns: bool,
+    /// Known addresses a seed node probes each round, see [`P2P::crawl`].
+    pub crawl_probes: usize,
+}
+
+impl Default for DiscoveryConfig {
+    fn default() -> Self {
+        DiscoveryConfig {
+            interval: Duration::from_secs(30),
+            outbound_target: 8,
+            mdns: false,
+            crawl_probes: 16,
+        }
+    }
+}
+
+/// How long to wait before dialing again an address that keeps failing:
+/// doubling from `initial` with each failure in a row, up to `max`. Each
+/// wait is cut short by a random part of up to half, so nodes that lost a
+/// peer together do not all dial it again at once.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Backoff {
+    pub initial: Duration,
+    pub max: Duration,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Backoff {
+            initial: Duration::from_secs(1),
+            max: Duration::from_secs(5 * 60),
+        }
+    }
+}
+
+impl Backoff {
+    /// The wait after `failures` failed dials in a row.
+    pub fn delay(&self, failures: u32) -> Duration {
+        let doublings = failures.saturating_sub(1).min(31);
+        let delay = self.initial.saturating_mul(1 << doublings).min(self.max);
+        delay.mul_f64(1.0 - rand::random::<f64>() / 2.0)
+    }
+}
+
+/// How many peers a node keeps connected. Reserved peers, such as seeds
+/// and fellow validators, are always let in and do not take up a slot.
+#[derive(Clone, Debug)]
+pub struct ConnectionLimits {
+    pub max_inbound: usize,
+    pub max_outbound: usize,
+    pub reserved: HashSet<NodeId>,
+}
+
+/// Faults injected into every message this node sends, to test how the
+/// network copes with bad links. Handshake and goodbye messages always go
+/// through.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LinkFaults {
+    /// Chance of silently dropping a message, from 0 to 1.
+    pub drop_rate: f64,
+    /// Each message waits up to this long before it is sent, holding up
+    /// the ones behind it.
+    pub max_delay: Duration,
+}
+
+impl LinkFaults {
+    fn always_sent(message: &Message) -> bool {
+        matches!(
+            message,
+            Message::Hello { .. } | Message::Goodbye | Message::Disconnect(_)
+        )
+    }
+}
+
+impl Default for ConnectionLimits {
+    fn default() -> Self {
+        ConnectionLimits {
+            max_inbound: 32,
+            max_outbound: 16,
+            reserved: HashSet::new(),
+        }
+    }
+}
+
+/// What the address book remembers about a peer address.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PeerRecord {
+    pub addr: SocketAddr,
+    pub node_type: Option<NodeType>,
+    /// The node last found at this address.
+    pub node_id: Option<NodeId>,
+    /// Unix time the peer was last connected, or 0 if never.
+    pub last_seen: i64,
+    pub successes: u32,
+    pub failures: u32,
+}
+
+impl PeerRecord {
+    pub fn new(addr: SocketAddr) -> Self {
+        PeerRecord {
+            addr,
+            node_type: None,
+            node_id: None,
+            last_seen: 0,
+            successes: 0,
+            failures: 0,
+        }
+    }
+
+    pub fn score(&self) -> i64 {
+        self.successes as i64 - self.failures as i64 * DIAL_FAILURE_PENALTY
+    }
+}
+
+#[derive(Debug)]
+struct Peer {
+    sender: Sender<Message>,
+    writer: JoinHandle<()>,
+    /// Wakes the reader to drop a peer that stalled.
+    kick: Arc<Notify>,
+    outbound: bool,
+    /// The peer's identity key, once its `Hello` has been checked.
+    identity: Option<NodeId>,
+    /// Compression agreed on through `Hello`, read by the writer task.
+    compression: Arc<OnceLock<Option<Compression>>>,
+    /// The address the peer accepts connections on, once known.
+    listen_addr: Option<SocketAddr>,
+    traffic: Arc<Mutex<TrafficStats>>,
+}
+
+#[derive(Clone, Debug)]
 pub struct P2P {
     db: Arc<Mutex<Database>>,
+    node_type: NodeType,
+    /// Signs as this node; its X25519 form is the Noise static key, so
+    /// completing a handshake proves ownership of the identity.
+    identity: Arc<SigningKey>,
+    /// Whether to offer compression to peers.
+    compression: bool,
+    /// Bytes per second each peer may send before we slow down reading
+    /// from it.
+    peer_bandwidth: Option<u64>,
+    /// Peers on any other chain are disconnected.
+    chain_id: String,
+    limits: Arc<ConnectionLimits>,
+    /// Outbound connections go through this proxy when set.
+    proxy: Option<Socks5Proxy>,
+    backoff: Backoff,
+    /// Failed dials in a row to each address that is failing, and when it
+    /// may be dialed again.
+    dial_failures: Arc<Mutex<HashMap<SocketAddr, (u32, Instant)>>>,
+    /// Inbound connections still in the Noise handshake, which count
+    /// against the inbound limit too.
+    handshaking: Arc<AtomicUsize>,
+    peers: Arc<Mutex<HashMap<SocketAddr, Peer>>>,
+    /// Where each greeted peer is connected from, by node ID; a node has at
+    /// most one connection.
+    peer_ids: Arc<Mutex<HashMap<NodeId, SocketAddr>>>,
+    /// Address book of peers learned through peer exchange or dialed,
+    /// mirrored to the database.
+    known: Arc<Mutex<HashMap<SocketAddr, PeerRecord>>>,
+    /// Every address bound by [`P2P::listen`], in order.
+    listen_addrs: Arc<Mutex<Vec<SocketAddr>>>,
+    /// The address other nodes reach us on, when the bound ones are not.
+    external_addr: Option<SocketAddr>,
+    /// How far ahead of ours each greeted peer's clock was, in
+    /// milliseconds, going by its `Hello`.
+    clock_offsets: Arc<Mutex<HashMap<SocketAddr, i64>>>,
+    /// Nodes refused and disconnected, such as those across a simulated
+    /// network partition.
+    blocked: Arc<Mutex<HashSet<NodeId>>>,
+    faults: watch::Sender<LinkFaults>,
+    /// Traffic with every peer since startup, including those gone.
+    traffic: Arc<Mutex<TrafficStats>>,
+    started: Instant,
+    events: EventBus,
+    inbound_tx: Sender<Inbound>,
+    inbound_rx: Arc<Mutex<Receiver<Inbound>>>,
+    shutdown: watch::Sender<bool>,
 }
 
 impl P2P {
-    pub fn new(db: Arc<Mutex<Database>>) -> Self {
-        P2P { db }
+    /// A network endpoint with a fresh identity, forgotten when it stops.
+    pub fn new(db: Arc<Mutex<Database>>, node_type: NodeType) -> Self {
+        Self::with_identity(db, node_type, SigningKey::generate(&mut OsRng))
+    }
+
+    /// A network endpoint that identifies itself to peers with `identity`.
+    pub fn with_identity(
+        db: Arc<Mutex<Database>>,
+        node_type: NodeType,
+        identity: SigningKey,
+    ) -> Self {
+        let (inbound_tx, inbound_rx) = mpsc::channel(INBOUND_QUEUE);
+        P2P {
+            db,
+            node_type,
+            identity: Arc::new(identity),
+            compression: true,
+            peer_bandwidth: None,
+            chain_id: DEFAULT_CHAIN_ID.to_string(),
+            limits: Arc::new(ConnectionLimits::default()),
+            proxy: None,
+            backoff: Backoff::default(),
+            dial_failures: Arc::new(Mutex::new(HashMap::new())),
+            handshaking: Arc::new(AtomicUsize::new(0)),
+            peers: Arc::new(Mutex::new(HashMap::new())),
+            peer_ids: Arc::new(Mutex::new(HashMap::new())),
+            known: Arc::new(Mutex::new(HashMap::new())),
+            listen_addrs: Arc::new(Mutex::new(Vec::new())),
+            external_addr: None,
+            clock_offsets: Arc::new(Mutex::new(HashMap::new())),
+            blocked: Arc::new(Mutex::new(HashSet::new())),
+            faults: watch::channel(LinkFaults::default()).0,
+            traffic: Arc::new(Mutex::new(TrafficStats::default())),
+            started: Instant::now(),
+            events: EventBus::default(),
+            inbound_tx,
+            inbound_rx: Arc::new(Mutex::new(inbound_rx)),
+            shutdown: watch::channel(false).0,
+        }
+    }
+
+    /// Accepts peer connections on `addr` in the background and returns the
+    /// address actually bound, which differs from `addr` for port 0. May be
+    /// called again to listen on more addresses, such as an IPv4 and an IPv6
+    /// one on the same port.
+    pub async fn listen(&self, addr: SocketAddr) -> Result<SocketAddr, String> {
+        let listener = bind(addr).map_err(|e| format!("Failed to listen on {}: {}", addr, e))?;
+        let local_addr = listener
+            .local_addr()
+            .map_err(|e| format!("Failed to read listen address: {}", e))?;
+        self.listen_addrs.lock().await.push(local_addr);
+
+        let p2p = self.clone();
+        let mut shutdown = self.shutdown.subscribe();
+        tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    accepted = listener.accept() => match accepted {
+                        Ok((stream, peer)) => {
+                            // Reserved peers may be among those connecting,
+                            // so sockets are only dropped unheard once their
+                            // slots are taken as well.
+                            let capacity = p2p.limits.max_inbound + p2p.limits.reserved.len();
+                            if p2p.inbound_connections().await >= capacity {
+                                debug!(%peer, "inbound connections full, dropping");
+                                continue;
+                            }
+                            // Handshake off the accept loop so a slow peer
+                            // cannot hold up others.
+                            let p2p = p2p.clone();
+                            p2p.handshaking.fetch_add(1, Ordering::SeqCst);
+                            tokio::spawn(async move {
+                                let handshake = noise::handshake(stream, false, &p2p.noise_key()).await;
+                                p2p.handshaking.fetch_sub(1, Ordering::SeqCst);
+                                match handshake {
+                                    Ok((reader, writer, remote)) => {
+                                        p2p.add_peer(reader, writer, remote, peer, false).await
+                                    }
+                                    Err(e) => debug!(%peer, error = %e, "inbound handshake failed"),
+                                }
+                            });
+                        }
+                        Err(_) => break,
+                    },
+                    _ = shutdown.changed() => break,
+                }
+            }
+        });
+
+        Ok(local_addr)
+    }
+
+    pub async fn connect(&self, addr: SocketAddr) -> Result<(), String> {
+        if *self.shutdown.borrow() {
+            return Err("Network is shutting down".to_string());
+        }
+        if self.is_self(addr).await {
+            return Err(format!("{} is our own listen address", addr));
+        }
+        if self.is_connected(addr).await {
+            return Err(format!("Already connected to {}", addr));
+        }
+        if !self.is_reserved_addr(addr).await
+            && self.connection_count(true).await >= self.limits.max_outbound
+        {
+            return Err("Outbound connection limit reached".to_string());
+        }
+        let stream = match self.proxy {
+            Some(proxy) => proxy.connect(addr).await,
+            None => TcpStream::connect(addr).await.map_err(|e| e.to_string()),
+        };
+        let connected = match stream {
+            Ok(stream) => noise::handshake(stream, true, &self.noise_key()).await,
+            Err(e) => Err(e),
+        };
+        let (reader, writer, remote) = match connected {
+            Ok(connected) => connected,
+            Err(e) => {
+                self.record_dial(addr, false).await;
+                return Err(format!("Failed to connect to {}: {}", addr, e));
+            }
+        };
+        self.record_dial(addr, true).await;
+        self.add_peer(reader, writer, remote, addr, true).await;
+        Ok(())
+    }
+
+    /// Keeps dialing `addr` in the background after a failed attempt,
+    /// waiting longer after each failure as set by [`P2P::set_backoff`],
+    /// until it is connected or the network shuts down.
+    pub fn retry_connect(&self, addr: SocketAddr) {
+        let p2p = self.clone();
+        let mut shutdown = self.shutdown.subscribe();
+        tokio::spawn(async move {
+            let mut failures = 1;
+            loop {
+                tokio::select! {
+                    _ = tokio::time::sleep(p2p.backoff.delay(failures)) => {}
+                    _ = shutdown.changed() => return,
+                }
+                if p2p.is_connected(addr).await {
+                    return;
+                }
+                match p2p.connect(addr).await {
+                    Ok(()) => {
+                        info!(%addr, failures, "connected after retrying");
+                        return;
+                    }
+                    Err(e) => debug!(%addr, error = %e, "retry failed"),
+                }
+                failures = failures.saturating_add(1);
+            }
+        });
+    }
+
+    /// Sets how dials that keep failing are spaced out, both by
+    /// [`P2P::retry_connect`] and by discovery.
+    pub fn set_backoff(&mut self, backoff: Backoff) {
+        self.backoff = backoff;
+    }
+
+    /// Enables or disables offering compression to peers connected from now
+    /// on. Compression is on by default.
+    pub fn set_compression(&mut self, enabled: bool) {
+        self.compression = enabled;
+    }
+
+    /// Caps what each peer connected from now on may send, in bytes per
+    /// second averaged over a second; a peer sending faster is read from
+    /// more slowly. `None` lifts the cap.
+    pub fn set_peer_bandwidth(&mut self, bytes_per_sec: Option<u64>) {
+        self.peer_bandwidth = bytes_per_sec;
+    }
+
+    /// Traffic with each connected peer since it connected.
+    pub async fn peer_traffic(&self) -> Vec<(SocketAddr, TrafficStats)> {
+        let traffic: Vec<_> = self
+            .peers
+            .lock()
+            .await
+            .iter()
+            .map(|(addr, peer)| (*addr, peer.traffic.clone()))
+            .collect();
+        let mut stats = Vec::with_capacity(traffic.len());
+        for (addr, traffic) in traffic {
+            stats.push((addr, traffic.lock().await.clone()));
+        }
+        stats
+    }
+
+    /// Traffic with all peers since startup.
+    pub async fn total_traffic(&self) -> TrafficStats {
+        self.traffic.lock().await.clone()
+    }
+
+    /// Routes connections opened from now on through `proxy`, or directly
+    /// when `None`.
+    pub fn set_proxy(&mut self, proxy: Option<Socks5Proxy>) {
+        self.proxy = proxy;
+    }
+
+    /// Advertises `addr` to peers connected from now on instead of the
+    /// first listen address, for a node behind NAT or a port forward.
+    pub fn set_external_addr(&mut self, addr: Option<SocketAddr>) {
+        self.external_addr = addr;
+    }
+
+    /// The address peers are told to reach us on: the external address if
+    /// set, otherwise the first one listened on.
+    pub async fn advertised_addr(&self) -> Option<SocketAddr> {
+        match self.external_addr {
+            Some(addr) => Some(addr),
+            None => self.listen_addrs.lock().await.first().copied(),
+        }
+    }
+
+    /// Sets how many peers to keep connected, from now on.
+    pub fn set_connection_limits(&mut self, limits: ConnectionLimits) {
+        self.limits = Arc::new(limits);
+    }
+
+    /// Connected peers in one direction, reserved peers aside.
+    async fn connection_count(&self, outbound: bool) -> usize {
+        let peers = self.peers.lock().await;
+        peers
+            .values()
+            .filter(|peer| peer.outbound == outbound)
+            .filter(|peer| {
+                !peer
+                    .identity
+                    .is_some_and(|id| self.limits.reserved.contains(&id))
+            })
+            .count()
+    }
+
+    /// Inbound connections of any kind, including those still handshaking.
+    async fn inbound_connections(&self) -> usize {
+        let connected = self
+            .peers
+            .lock()
+            .await
+            .values()
+            .filter(|peer| !peer.outbound)
+            .count();
+        connected + self.handshaking.load(Ordering::SeqCst)
+    }
+
+    /// Whether the address book last found a reserved node at `addr`.
+    async fn is_reserved_addr(&self, addr: SocketAddr) -> bool {
+        let known = self.known.lock().await;
+        known
+            .get(&addr)
+            .and_then(|record| record.node_id)
+            .is_some_and(|id| self.limits.reserved.contains(&id))
+    }
+
+    /// Publishes peers connecting and disconnecting on `events`.
+    pub fn set_event_bus(&mut self, events: EventBus) {
+        self.events = events;
+    }
+
+    /// Sets the chain this node is on for peers connected from now on; peers
+    /// announcing another are disconnected.
+    pub fn set_chain_id(&mut self, chain_id: impl Into<String>) {
+        self.chain_id = chain_id.into();
+    }
+
+    /// Hash of the first stored block, if any.
+    async fn genesis(&self) -> Option<Hash> {
+        match self.db.lock().await.get_block_by_height(0) {
+            Ok(block) => block.map(|block| block.hash()),
+            Err(e) => {
+                warn!(error = %e, "failed to read genesis block");
+                None
+            }
+        }
+    }
+
+    /// Compression offered to peers, in order of preference.
+    fn supported_compression(&self) -> Vec<Compression> {
+        if self.compression {
+            vec![Compression::Snappy]
+        } else {
+            Vec::new()
+        }
+    }
+
+    /// How long since this endpoint was created, which is when its node
+    /// started.
+    pub fn uptime(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    /// How far ahead of the local clock the peers' clocks are, in
+    /// milliseconds: the median of what connected peers' `Hello`s showed.
+    /// `None` until enough peers have been heard from to tell.
+    pub async fn clock_offset(&self) -> Option<i64> {
+        median_offset(&*self.clock_offsets.lock().await)
+    }
+
+    /// Fails if the local clock is too far from the peers' for blocks made
+    /// with it to be accepted, or for theirs to be accepted here.
+    pub async fn check_clock(&self) -> Result<(), String> {
+        match self.clock_offset().await {
+            Some(offset) if offset.abs() > MAX_CLOCK_OFFSET_MILLIS => Err(format!(
+                "Local clock is {:.1}s {} peers'",
+                offset.abs() as f64 / 1000.0,
+                if offset > 0 { "behind" } else { "ahead of" }
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Notes how far ahead of ours the clock of the peer at `addr` is, or
+    /// forgets it if `None`, and warns when the clocks part or agree again.
+    async fn note_clock_offset(&self, addr: SocketAddr, offset: Option<i64>) {
+        let mut offsets = self.clock_offsets.lock().await;
+        let skewed = |offsets: &HashMap<SocketAddr, i64>| {
+            median_offset(offsets).is_some_and(|offset| offset.abs() > MAX_CLOCK_OFFSET_MILLIS)
+        };
+        let was_skewed = skewed(&offsets);
+        match offset {
+            Some(offset) => offsets.insert(addr, offset),
+            None => offsets.remove(&addr),
+        };
+        match (was_skewed, skewed(&offsets)) {
+            (false, true) => warn!(
+                offset_ms = median_offset(&offsets),
+                "local clock is off from the peers', not producing blocks until it is fixed"
+            ),
+            (true, false) => info!("local clock agrees with the peers' again"),
+            _ => {}
+        }
+    }
+
+    /// This node's public identity key.
+    pub fn identity(&self) -> NodeId {
+        self.identity.verifying_key().to_bytes()
+    }
+
+    /// The identity key a connected peer authenticated with.
+    pub async fn peer_identity(&self, addr: SocketAddr) -> Option<NodeId> {
+        self.peers.lock().await.get(&addr)?.identity
+    }
+
+    /// Where the node `id` is connected from, if it is connected.
+    pub async fn peer_addr(&self, id: &NodeId) -> Option<SocketAddr> {
+        self.peer_ids.lock().await.get(id).copied()
+    }
+
+    fn noise_key(&self) -> [u8; 32] {
+        self.identity.to_scalar_bytes()
+    }
+
+    /// Fills the address book from the database, so a restarted node can
+    /// rejoin without a live seed.
+    pub async fn load_peers(&self) -> Result<usize, String> {
+        let records = {
+            let db = self.db.lock().await;
+            db.load_peers()
+                .map_err(|_| "Error loading peers".to_string())?
+        };
+        let mut known = self.known.lock().await;
+        let count = records.len();
+        for record in records {
+            known.insert(record.addr, record);
+        }
+        Ok(count)
+    }
+
+    async fn record_dial(&self, addr: SocketAddr, success: bool) {
+        let mut failures = self.dial_failures.lock().await;
+        if success {
+            failures.remove(&addr);
+        } else {
+            let (count, retry_at) = failures.entry(addr).or_insert((0, Instant::now()));
+            *count = count.saturating_add(1);
+            *retry_at = Instant::now() + self.backoff.delay(*count);
+        }
+        drop(failures);
+
+        let mut known = self.known.lock().await;
+        let record = known.entry(addr).or_insert_with(|| PeerRecord::new(addr));
+        if success {
+            record.successes = record.successes.saturating_add(1);
+            record.last_seen = Utc::now().timestamp();
+        } else {
+            record.failures = record.failures.saturating_add(1);
+        }
+
+        let db = self.db.lock().await;
+        let result = if record.score() < MIN_PEER_SCORE {
+            known.remove(&addr);
+            db.delete_peer(&addr)
+        } else {
+            db.save_peer(record)
+        };
+        if let Err(e) = result {
+            warn!(%addr, error = %e, "failed to save peer");
+        }
+    }
+
+    pub async fn peers(&self) -> Vec<SocketAddr> {
+        let peers = self.peers.lock().await;
+        peers.keys().copied().collect()
+    }
+
+    /// Addresses learned through peer exchange, best scored first.
+    pub async fn known_peers(&self) -> Vec<SocketAddr> {
+        let known = self.known.lock().await;
+        let mut records: Vec<&PeerRecord> = known.values().collect();
+        records.sort_by(|a, b| {
+            b.score()
+                .cmp(&a.score())
+                .then(b.last_seen.cmp(&a.last_seen))
+                .then(a.addr.cmp(&b.addr))
+        });
+        records.into_iter().map(|record| record.addr).collect()
+    }
+
+    /// Loads the saved address book, then runs [`P2P::discover`] every
+    /// `config.interval` until shutdown, with mDNS alongside if enabled.
+    /// Seed nodes also [`P2P::crawl`] each round.
+    pub fn start_discovery(&self, config: DiscoveryConfig) {
+        if config.mdns {
+            let p2p = self.clone();
+            tokio::spawn(async move {
+                if let Err(e) = p2p.run_mdns(config.outbound_target).await {
+                    warn!(error = %e, "mDNS discovery stopped");
+                }
+            });
+        }
+
+        let p2p = self.clone();
+        let mut shutdown = self.shutdown.subscribe();
+        tokio::spawn(async move {
+            if let Err(e) = p2p.load_peers().await {
+                warn!(error = %e, "starting discovery without saved peers");
+            }
+            let mut interval = tokio::time::interval(config.interval);
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {
+                        p2p.discover(config.outbound_target).await;
+                        if p2p.node_type == NodeType::SeedNode {
+                            p2p.crawl(config.crawl_probes).await;
+                        }
+                    }
+                    _ = shutdown.changed() => break,
+                }
+            }
+        });
+    }
+
+    /// One round of peer exchange: asks every peer for its peers, then dials
+    /// the best scored addresses learned so far until `outbound_target`
+    /// outbound connections are open. Answers arrive asynchronously, so what
+    /// this round learns is dialed on the next.
+    pub async fn discover(&self, outbound_target: usize) {
+        self.broadcast(Message::GetPeers, None).await;
+        self.dial_known(outbound_target).await;
+    }
+
+    /// One round of crawling, for seed nodes: dials up to `probes` known
+    /// addresses that are not connected or backing off, those found alive
+    /// least recently first, asks each for its peers and hangs up again. Answering marks
+    /// an address alive, and addresses that keep failing are forgotten.
+    pub async fn crawl(&self, probes: usize) {
+        let skipped = self.undialable_addrs().await;
+        let mut candidates: Vec<(i64, SocketAddr)> = self
+            .known
+            .lock()
+            .await
+            .values()
+            .filter(|record| !skipped.contains(&record.addr))
+            .map(|record| (record.last_seen, record.addr))
+            .collect();
+        candidates.sort();
+        candidates.truncate(probes);
+
+        join_all(candidates.into_iter().map(|(_, addr)| self.probe(addr))).await;
+    }
+
+    async fn probe(&self, addr: SocketAddr) {
+        // Failures are recorded against the address by `connect`.
+        if let Err(e) = self.connect(addr).await {
+            debug!(%addr, error = %e, "probe failed");
+            return;
+        }
+        self.send_to(addr, Message::GetPeers).await;
+        tokio::time::sleep(PROBE_LINGER).await;
+        self.disconnect(addr).await;
+    }
+
+    /// Says goodbye to the peer `addr` and drops the connection.
+    pub async fn disconnect(&self, addr: SocketAddr) {
+        if let Some(peer) = self.peers.lock().await.remove(&addr) {
+            // The writer finishes once the goodbye is written.
+            let _ = peer.sender.try_send(Message::Goodbye);
+        }
+    }
+
+    /// Addresses connected peers are connected from or listen on.
+    async fn connected_addrs(&self) -> HashSet<SocketAddr> {
+        let peers = self.peers.lock().await;
+        peers
+            .iter()
+            .flat_map(|(addr, peer)| [Some(*addr), peer.listen_addr])
+            .flatten()
+            .collect()
+    }
+
+    /// Addresses not to dial now: those connected, and those that failed
+    /// and are waiting out their backoff.
+    async fn undialable_addrs(&self) -> HashSet<SocketAddr> {
+        let now = Instant::now();
+        let mut addrs = self.connected_addrs().await;
+        addrs.extend(
+            self.dial_failures
+                .lock()
+                .await
+                .iter()
+                .filter(|(_, (_, retry_at))| *retry_at > now)
+                .map(|(addr, _)| *addr),
+        );
+        addrs
+    }
+
+    /// Dials the best scored known addresses not yet connected until
+    /// `outbound_target` outbound connections are open. Addresses that
+    /// failed recently are left until their backoff is over.
+    async fn dial_known(&self, outbound_target: usize) {
+        let skipped = self.undialable_addrs().await;
+        let outbound = self
+            .peers
+            .lock()
+            .await
+            .values()
+            .filter(|peer| peer.outbound)
+            .count();
+
+        let candidates: Vec<SocketAddr> = self
+            .known_peers()
+            .await
+            .into_iter()
+            .filter(|addr| !skipped.contains(addr))
+            .take(outbound_target.saturating_sub(outbound))
+            .collect();
+
+        for addr in candidates {
+            // Failures are recorded against the address by `connect`.
+            if let Err(e) = self.connect(addr).await {
+                debug!(%addr, error = %e, "discovery dial failed");
+            }
+        }
+    }
+
+    /// Advertises this node on the local network and dials nodes found there
+    /// until shutdown.
+    async fn run_mdns(&self, outbound_target: usize) -> Result<(), String> {
+        let listen_addr = self
+            .advertised_addr()
+            .await
+            .ok_or("Not listening for peers")?;
+        let id = hex::encode(&self.identity()[..16]);
+
+        let daemon = ServiceDaemon::new().map_err(|e| format!("Failed to start mDNS: {}", e))?;
+        let service = ServiceInfo::new(
+            MDNS_SERVICE_TYPE,
+            &id,
+            &format!("{}.local.", id),
+            "",
+            listen_addr.port(),
+            &[("id", id.as_str())][..],
+        )
+        .map_err(|e| format!("Failed to describe mDNS service: {}", e))?
+        .enable_addr_auto();
+        daemon
+            .register(service)
+            .map_err(|e| format!("Failed to register mDNS service: {}", e))?;
+        let events = daemon
+            .browse(MDNS_SERVICE_TYPE)
+            .map_err(|e| format!("Failed to browse mDNS: {}", e))?;
+        info!(%listen_addr, "advertising over mDNS");
+
+        let mut shutdown = self.shutdown.subscribe();
+        loop {
+            tokio::select! {
+                event = events.recv_async() => match event {
+                    Ok(ServiceEvent::ServiceResolved(info)) => {
+                        if info.get_property_val_str("id") == Some(id.as_str()) {
+                            continue;
+                        }
+                        let port = info.get_port();
+                        let addrs: Vec<SocketAddr> = info
+                            .get_addresses()
+                            .iter()
+                            .map(|ip| SocketAddr::new(*ip, port))
+                            .collect();
+                        debug!(?addrs, "found peer over mDNS");
+                        self.learn(addrs).await;
+                        self.dial_known(outbound_target).await;
+                    }
+                    Ok(_) => {}
+                    Err(_) => break,
+                },
+                _ = shutdown.changed() => break,
+            }
+        }
+
+        let _ = daemon.shutdown();
+        Ok(())
+    }
+
+    /// Whether a connected peer is connected from, or listens on, `addr`.
+    async fn is_connected(&self, addr: SocketAddr) -> bool {
+        let peers = self.peers.lock().await;
+        peers.contains_key(&addr) || peers.values().any(|peer| peer.listen_addr == Some(addr))
+    }
+
+    async fn is_self(&self, addr: SocketAddr) -> bool {
+        if self.external_addr == Some(addr) {
+            return true;
+        }
+        self.listen_addrs
+            .lock()
+            .await
+            .iter()
+            .any(|own| *own == addr || (own.ip().is_unspecified() && own.port() == addr.port()))
+    }
+
+    /// Adds addresses vouched for by a peer to the address book.
+    async fn learn(&self, addrs: impl IntoIterator<Item = SocketAddr>) {
+        for addr in addrs.into_iter().take(MAX_PEERS_PER_MESSAGE) {
+            if addr.port() == 0 || addr.ip().is_unspecified() || self.is_self(addr).await {
+                continue;
+            }
+            let mut known = self.known.lock().await;
+            if known.contains_key(&addr) {
+                continue;
+            }
+            let record = PeerRecord::new(addr);
+            if let Err(e) = self.db.lock().await.save_peer(&record) {
+                warn!(%addr, error = %e, "failed to save peer");
+            }
+            known.insert(addr, record);
+        }
+    }
+
+    /// Listen addresses of connected peers other than `requester`.
+    async fn shareable_peers(&self, requester: SocketAddr) -> Vec<SocketAddr> {
+        let peers = self.peers.lock().await;
+        peers
+            .iter()
+            .filter(|(addr, _)| **addr != requester)
+            .filter_map(|(_, peer)| peer.listen_addr)
+            .take(MAX_PEERS_PER_MESSAGE)
+            .collect()
+    }
+
+    /// Addresses found alive within [`FRESH_PEER_AGE`], other than the
+    /// requester's, as a seed node hands them out: sampled at random a
+    /// network at a time, so that no one network fills the answer.
+    async fn sample_fresh_peers(&self, requester: SocketAddr) -> Vec<SocketAddr> {
+        let requester_listen = self
+            .peers
+            .lock()
+            .await
+            .get(&requester)
+            .and_then(|peer| peer.listen_addr);
+        let fresh_since = Utc::now().timestamp() - FRESH_PEER_AGE.as_secs() as i64;
+        let mut groups: HashMap<Vec<u8>, Vec<SocketAddr>> = HashMap::new();
+        for record in self.known.lock().await.values() {
+            if record.last_seen >= fresh_since
+                && record.addr != requester
+                && Some(record.addr) != requester_listen
+            {
+                groups
+                    .entry(network_group(&record.addr))
+                    .or_default()
+                    .push(record.addr);
+            }
+        }
+
+        let mut rng = rand::thread_rng();
+        let mut groups: Vec<Vec<SocketAddr>> = groups.into_values().collect();
+        for group in &mut groups {
+            group.shuffle(&mut rng);
+        }
+        groups.shuffle(&mut rng);
+        let mut sample = Vec::new();
+        for round in 0.. {
+            let taken = sample.len();
+            sample.extend(groups.iter().filter_map(|group| group.get(round)));
+            if sample.len() == taken || sample.len() >= MAX_PEERS_PER_MESSAGE {
+                break;
+            }
+        }
+        sample.truncate(MAX_PEERS_PER_MESSAGE);
+        sample
+    }
+
+    /// Notes a connected peer's listen address, identity and type in the
+    /// address book.
+    async fn record_hello(&self, addr: SocketAddr, node_id: NodeId, node_type: NodeType) {
+        let mut known = self.known.lock().await;
+        let record = known.entry(addr).or_insert_with(|| PeerRecord::new(addr));
+        if record.node_id.is_some_and(|known_id| known_id != node_id) {
+            debug!(%addr, "address now belongs to a different node");
+        }
+        record.node_id = Some(node_id);
+        record.node_type = Some(node_type);
+        record.last_seen = Utc::now().timestamp();
+
+        if let Err(e) = self.db.lock().await.save_peer(record) {
+            warn!(%addr, error = %e, "failed to save peer");
+        }
+    }
+
+    /// Sends `message` to the connected peer `addr`, if still connected.
+    pub async fn send_to(&self, addr: SocketAddr, message: Message) {
+        let mut peers = self.peers.lock().await;
+        if peers.get(&addr).is_some_and(|peer| !peer.queue(message)) {
+            drop_stalled(&mut peers, addr);
+        }
+    }
+
+    /// Sends `message` to every connected peer except `skip`, usually the
+    /// peer it was received from.
+    pub async fn broadcast(&self, message: Message, skip: Option<SocketAddr>) {
+        let mut peers = self.peers.lock().await;
+        let stalled: Vec<SocketAddr> = peers
+            .iter()
+            .filter(|(addr, peer)| Some(**addr) != skip && !peer.queue(message.clone()))
+            .map(|(addr, _)| *addr)
+            .collect();
+        for addr in stalled {
+            drop_stalled(&mut peers, addr);
+        }
+    }
+
+    /// Stops accepting connections, says goodbye to every peer and waits
+    /// briefly for those messages to be written before dropping the links.
+    pub async fn shutdown(&self) {
+        self.shutdown.send_replace(true);
+
+        let peers: Vec<Peer> = self
+            .peers
+            .lock()
+            .await
+            .drain()
+            .map(|(_, peer)| peer)
+            .collect();
+        for peer in peers {
+            let _ = peer.sender.try_send(Message::Goodbye);
+            // Dropping the sender lets the writer finish once it is drained.
+            drop(peer.sender);
+            let _ = tokio::time::timeout(GOODBYE_TIMEOUT, peer.writer).await;
+        }
+    }
+
+    /// Refuses the nodes in `ids` from now on, in place of those refused
+    /// before, and disconnects those connected.
+    pub async fn set_blocked_peers(&self, ids: HashSet<NodeId>) {
+        let mut peers = self.peers.lock().await;
+        let addrs: Vec<SocketAddr> = peers
+            .iter()
+            .filter(|(_, peer)| peer.identity.is_some_and(|id| ids.contains(&id)))
+            .map(|(addr, _)| *addr)
+            .collect();
+        for addr in addrs {
+            if let Some(peer) = peers.remove(&addr) {
+                debug!(%addr, "disconnecting blocked peer");
+                let _ = peer
+                    .sender
+                    .try_send(Message::Disconnect(DisconnectReason::Blocked));
+            }
+        }
+        *self.blocked.lock().await = ids;
+    }
+
+    /// Injects `faults` into everything sent from now on, to every peer.
+    pub fn set_link_faults(&self, faults: LinkFaults) {
+        self.faults.send_replace(faults);
+    }
+
+    /// Waits for the next message from any peer.
+    pub async fn next_message(&self) -> Option<(SocketAddr, Message)> {
+        let mut inbound = self.inbound_rx.lock().await;
+        inbound.recv().await
+    }
+
+    async fn add_peer(
+        &self,
+        mut reader: NoiseReader,
+        mut noise_writer: NoiseWriter,
+        remote_static: Vec<u8>,
+        addr: SocketAddr,
+        outbound: bool,
+    ) {
+        let (outbound_tx, mut outbound_rx) = mpsc::channel::<Message>(PEER_QUEUE);
+        let compression = Arc::new(OnceLock::new());
+
+        let traffic = Arc::new(Mutex::new(TrafficStats::default()));
+
+        let negotiated = compression.clone();
+        let faults = self.faults.subscribe();
+        let sent = traffic.clone();
+        let total = self.traffic.clone();
+        let writer = tokio::spawn(async move {
+            while let Some(message) = outbound_rx.recv().await {
+                let LinkFaults {
+                    drop_rate,
+                    max_delay,
+                } = faults.borrow().clone();
+                if !LinkFaults::always_sent(&message) {
+                    if rand::random::<f64>() < drop_rate {
+                        continue;
+                    }
+                    if !max_delay.is_zero() {
+                        tokio::time::sleep(max_delay.mul_f64(rand::random())).await;
+                    }
+                }
+                let frame = match encode_frame(&message, negotiated.get().copied().flatten()) {
+                    Ok(frame) => frame,
+                    Err(e) => {
+                        warn!(error = %e, "dropping outbound message");
+                        continue;
+                    }
+                };
+                if noise_writer.write_all(&frame).await.is_err() {
+                    break;
+                }
+                sent.lock().await.record_sent(message.kind(), frame.len());
+                total.lock().await.record_sent(message.kind(), frame.len());
+            }
+        });
+        let hello = Message::Hello {
+            identity: self.identity(),
+            chain_id: self.chain_id.clone(),
+            genesis: self.genesis().await,
+            listen_addr: self.advertised_addr().await,
+            node_type: self.node_type,
+            compression: self.supported_compression(),
+            timestamp: Utc::now().timestamp_millis(),
+        };
+        let _ = outbound_tx.try_send(hello);
+
+        let kick = Arc::new(Notify::new());
+        let peer = Peer {
+            sender: outbound_tx,
+            writer,
+            kick: kick.clone(),
+            outbound,
+            identity: None,
+            compression,
+            // A dialed peer is known to accept connections where we dialed.
+            listen_addr: outbound.then_some(addr),
+            traffic: traffic.clone(),
+        };
+        self.peers.lock().await.insert(addr, peer);
+
+        let span = info_span!("peer", %addr);
+        span.in_scope(|| info!("peer connected"));
+
+        let p2p = self.clone();
+        tokio::spawn(
+            async move {
+                let mut limiter = RateLimiter::default();
+                let mut bandwidth = p2p
+                    .peer_bandwidth
+                    .map(|rate| TokenBucket::new(rate as f64, rate as f64));
+                // The peer's identity, once its `Hello` has been checked.
+                let mut node_id = None;
+                loop {
+                    let frame = tokio::select! {
+                        frame = read_sized_frame(&mut reader) => frame,
+                        _ = kick.notified() => {
+                            warn!("peer stopped reading, disconnecting");
+                            break;
+                        }
+                    };
+                    let (payload, size) = match frame {
+                        Ok(Some(frame)) => frame,
+                        Ok(None) => break,
+                        Err(e) => {
+                            warn!(error = %e, "disconnecting peer");
+                            break;
+                        }
+                    };
+                    let message = decode_message(&payload);
+                    let kind = message.as_ref().map_or("malformed", Message::kind);
+                    traffic.lock().await.record_received(kind, size);
+                    p2p.traffic.lock().await.record_received(kind, size);
+                    if let Some(bandwidth) = &mut bandwidth {
+                        // Not reading holds the peer back through TCP flow
+                        // control.
+                        let wait = bandwidth.take(size as f64);
+                        if !wait.is_zero() {
+                            debug!(?wait, "peer is over its bandwidth, slowing down");
+                            tokio::time::sleep(wait).await;
+                        }
+                    }
+                    if let Ok(message) = &message
+                        && !limiter.allow(message)
+                    {
+                        warn!(
+                            kind = message.kind(),
+                            "peer exceeded rate limit, disconnecting"
+                        );
+                        break;
+                    }
+                    if node_id.is_none() && !matches!(message, Ok(Message::Hello { .. })) {
+                        warn!("peer spoke before saying hello, disconnecting");
+                        break;
+                    }
+                    match message {
+                        Ok(Message::Goodbye) => {
+                            debug!("peer said goodbye");
+                            break;
+                        }
+                        Ok(Message::Disconnect(reason)) => {
+                            debug!(?reason, "peer disconnected us");
+                            break;
+                        }
+                        Ok(Message::Hello {
+                            identity,
+                            chain_id,
+                            genesis,
+                            listen_addr,
+                            node_type,
+                            compression,
+                            timestamp,
+                        }) => {
+                            if node_id.is_some() {
+                                continue;
+                            }
+                            let clock_offset =
+                                timestamp.saturating_sub(Utc::now().timestamp_millis());
+                            if !identity_matches(&identity, &remote_static) {
+                                warn!("hello identity does not match handshake key, disconnecting");
+                                break;
+                            }
+                            if chain_id != p2p.chain_id {
+                                warn!(%chain_id, "peer is on another chain, disconnecting");
+                                break;
+                            }
+                            // A node with no blocks yet can follow either.
+                            if let (Some(theirs), Some(ours)) = (genesis, p2p.genesis().await)
+                                && theirs != ours
+                            {
+                                warn!("peer has a different genesis block, disconnecting");
+                                break;
+                            }
+                            if p2p.blocked.lock().await.contains(&identity) {
+                                debug!("peer is blocked, disconnecting");
+                                p2p.send_to(addr, Message::Disconnect(DisconnectReason::Blocked))
+                                    .await;
+                                break;
+                            }
+                            if identity == p2p.identity() {
+                                debug!("connected to ourselves, disconnecting");
+                                p2p.send_to(
+                                    addr,
+                                    Message::Disconnect(DisconnectReason::SelfConnection),
+                                )
+                                .await;
+                                break;
+                            }
+                            let existing = p2p.peer_ids.lock().await.get(&identity).copied();
+                            if let Some(existing) = existing {
+                                let existing_outbound = p2p
+                                    .peers
+                                    .lock()
+                                    .await
+                                    .get(&existing)
+                                    .map(|peer| peer.outbound);
+                                // A second connection the same way is refused.
+                                // Of an inbound and an outbound one, both ends
+                                // keep the one dialed by the lower node ID.
+                                let keep_new = existing_outbound.is_some_and(|existing_outbound| {
+                                    existing_outbound != outbound
+                                        && outbound == (p2p.identity() < identity)
+                                });
+                                if !keep_new {
+                                    debug!("node is already connected, disconnecting");
+                                    p2p.send_to(
+                                        addr,
+                                        Message::Disconnect(DisconnectReason::DuplicateConnection),
+                                    )
+                                    .await;
+                                    break;
+                                }
+                                if let Some(old) = p2p.peers.lock().await.remove(&existing) {
+                                    debug!(%existing, "replacing duplicate connection");
+                                    let _ = old.sender.try_send(Message::Disconnect(
+                                        DisconnectReason::DuplicateConnection,
+                                    ));
+                                }
+                            } else if !p2p.limits.reserved.contains(&identity) {
+                                // The count includes this peer, which has no
+                                // identity until its hello is accepted.
+                                let limit = if outbound {
+                                    p2p.limits.max_outbound
+                                } else {
+                                    p2p.limits.max_inbound
+                                };
+                                if p2p.connection_count(outbound).await > limit {
+                                    info!("no free connection slot, disconnecting");
+                                    p2p.send_to(
+                                        addr,
+                                        Message::Disconnect(DisconnectReason::TooManyPeers),
+                                    )
+                                    .await;
+                                    break;
+                                }
+                            }
+                            p2p.peer_ids.lock().await.insert(identity, addr);
+                            node_id = Some(identity);
+                            p2p.events.publish(NodeEvent::PeerConnected {
+                                addr,
+                                node_id: identity,
+                            });
+                            // Only the port is taken on trust: whatever
+                            // address a peer claims, it is reachable where it
+                            // connected from.
+                            let listen_addr =
+                                listen_addr.map(|listen| SocketAddr::new(addr.ip(), listen.port()));
+                            let listen_addr = {
+                                let mut peers = p2p.peers.lock().await;
+                                let Some(peer) = peers.get_mut(&addr) else {
+                                    continue;
+                                };
+                                peer.identity = Some(identity);
+                                let offered = p2p.supported_compression();
+                                let _ = peer
+                                    .compression
+                                    .set(offered.into_iter().find(|c| compression.contains(c)));
+                                if !peer.outbound {
+                                    peer.listen_addr = listen_addr;
+                                }
+                                peer.listen_addr
+                            };
+                            if let Some(listen_addr) = listen_addr {
+                                p2p.record_hello(listen_addr, identity, node_type).await;
+                            }
+                            p2p.note_clock_offset(addr, Some(clock_offset)).await;
+                            // Lets the node greet the peer with its own state.
+                            let hello = Message::Hello {
+                                identity,
+                                chain_id,
+                                genesis,
+                                listen_addr,
+                                node_type,
+                                compression,
+                                timestamp,
+                            };
+                            let _ = p2p.inbound_tx.send((addr, hello)).await;
+                        }
+                        Ok(Message::GetPeers) => {
+                            let peers = if p2p.node_type == NodeType::SeedNode {
+                                p2p.sample_fresh_peers(addr).await
+                            } else {
+                                p2p.shareable_peers(addr).await
+                            };
+                            p2p.send_to(addr, Message::Peers(peers)).await;
+                        }
+                        Ok(Message::Peers(addrs)) => p2p.learn(addrs).await,
+                        Ok(message) => {
+                            let _ = p2p.inbound_tx.send((addr, message)).await;
+                        }
+                        Err(e) => warn!(error = %e, "dropping malformed message"),
+                    }
+                }
+                p2p.peers.lock().await.remove(&addr);
+                if let Some(node_id) = node_id {
+                    p2p.note_clock_offset(addr, None).await;
+                    let mut peer_ids = p2p.peer_ids.lock().await;
+                    // A connection that replaced this one owns the entry now.
+                    if peer_ids.get(&node_id) == Some(&addr) {
+                        peer_ids.remove(&node_id);
+                    }
+                    p2p.events
+                        .publish(NodeEvent::PeerDisconnected { addr, node_id });
+                }
+                info!("peer disconnected");
+            }
+            .instrument(span),
+        );
+    }
+}
+
+impl Peer {
+    /// Queues `message` for the writer. False if the queue is full, the
+    /// peer having stopped reading.
+    fn queue(&self, message: Message) -> bool {
+        !matches!(
+            self.sender.try_send(message),
+            Err(mpsc::error::TrySendError::Full(_))
+        )
+    }
+}
+
+/// Drops the peer `addr`, whose queue is full: its writer is stopped where
+/// it is and its reader woken to close the connection.
+fn drop_stalled(peers: &mut HashMap<SocketAddr, Peer>, addr: SocketAddr) {
+    if let Some(peer) = peers.remove(&addr) {
+        warn!(%addr, "peer queue is full, disconnecting");
+        peer.writer.abort();
+        peer.kick.notify_one();
+    }
+}
+
+/// The median of the clock offsets, once there are enough of them.
+fn median_offset(offsets: &HashMap<SocketAddr, i64>) -> Option<i64> {
+    if offsets.len() < MIN_CLOCK_SAMPLES {
+        return None;
+    }
+    let mut offsets: Vec<i64> = offsets.values().copied().collect();
+    offsets.sort_unstable();
+    Some(offsets[offsets.len() / 2])
+}
+
+/// The network `addr` is in, taken as its IPv4 /16 or IPv6 /32, as a
+/// stand-in for who runs the node there.
+fn network_group(addr: &SocketAddr) -> Vec<u8> {
+    match addr.ip().to_canonical() {
+        IpAddr::V4(ip) => ip.octets()[..2].to_vec(),
+        IpAddr::V6(ip) => ip.octets()[..4].to_vec(),
+    }
+}
+
+/// Whether `remote_static`, the key a peer completed the Noise handshake
+/// with, is the X25519 form of the ed25519 `identity` it claims.
+fn identity_matches(identity: &NodeId, remote_static: &[u8]) -> bool {
+    VerifyingKey::from_bytes(identity)
+        .is_ok_and(|key| key.to_montgomery().as_bytes().as_slice() == remote_static)
+}
+
+/// Binds a listening socket. IPv6 sockets accept IPv6 only, so `[::]` and
+/// `0.0.0.0` can be listened on side by side with the same port.
+fn bind(addr: SocketAddr) -> std::io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(true)?;
     }
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+    TcpListener::from_std(socket.into())
 }
@@ -0,0 +1,265 @@
+//! The consensus rules a chain runs under, which every node on it must
+//! agree on. Changes to those rules are rolled out as [`Feature`]s, each
+//! switched on at a height in the [`ForkSchedule`]: nodes upgrade ahead of
+//! it, and the network changes behavior together when the chain reaches
+//! it rather than splitting between old and new nodes.
+
+use crate::amount::Amount;
+use crate::blockchain::{Address, User};
+use crate::governance::Parameters;
+use crate::monetary::MonetaryPolicy;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// Length of a block production slot unless configured otherwise.
+pub const DEFAULT_BLOCK_TIME: Duration = Duration::from_secs(5);
+
+/// Basis points in the whole of a block's fees.
+pub const MAX_FEE_BPS: u16 = 10_000;
+
+/// The account the treasury's share of fees goes to. No key controls it:
+/// it pays out only the grants governance accepts, see
+/// [`crate::governance::Parameter::TreasuryGrant`].
+pub const TREASURY: Address = *b"treasury\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0";
+
+/// The treasury account before it first receives anything. It is only
+/// stored once it does, so chains that route no fees never hold it.
+pub fn empty_treasury() -> User {
+    User {
+        address: TREASURY,
+        public_key: [0u8; 32],
+        balance: Amount::ZERO,
+        stake: Amount::ZERO,
+        delegate: None,
+        commission_bps: 0,
+        missed_slots: 0,
+        jailed: false,
+    }
+}
+
+/// A change to the consensus rules that takes effect at a fork height.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Feature {
+    /// `Propose` and `Vote` transactions; see [`crate::governance`].
+    Governance,
+    /// `Delegate` and `SetCommission` transactions; see
+    /// [`crate::staking`].
+    Delegation,
+    /// Missed slots counted against validators, which are jailed for
+    /// missing too many, and `Unjail` transactions; see
+    /// [`crate::liveness`].
+    Liveness,
+    /// `RegisterValidator` and `RotateKey` transactions, which give a
+    /// validator a consensus key of its own; see [`crate::staking`].
+    Registration,
+    /// `Commit` and `Reveal` transactions, whose secrets seed proposer
+    /// selection in place of the parent block's hash; see
+    /// [`crate::beacon`].
+    Beacon,
+}
+
+impl Feature {
+    pub const ALL: [Feature; 5] = [
+        Feature::Governance,
+        Feature::Delegation,
+        Feature::Liveness,
+        Feature::Registration,
+        Feature::Beacon,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Feature::Governance => "governance",
+            Feature::Delegation => "delegation",
+            Feature::Liveness => "liveness",
+            Feature::Registration => "registration",
+            Feature::Beacon => "beacon",
+        }
+    }
+}
+
+impl std::str::FromStr for Feature {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Feature::ALL
+            .into_iter()
+            .find(|feature| feature.as_str() == s)
+            .ok_or(format!("Unknown feature: {}", s))
+    }
+}
+
+/// The height each feature activates at. Features not scheduled otherwise
+/// are active from genesis, as on a new chain.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ForkSchedule {
+    activations: BTreeMap<Feature, u64>,
+}
+
+impl ForkSchedule {
+    /// Activates `feature` from the block at `height` on.
+    pub fn set(&mut self, feature: Feature, height: u64) {
+        self.activations.insert(feature, height);
+    }
+
+    pub fn activation_height(&self, feature: Feature) -> u64 {
+        self.activations.get(&feature).copied().unwrap_or(0)
+    }
+
+    /// Whether the block at `height` follows the rules of `feature`.
+    pub fn is_active(&self, feature: Feature, height: u64) -> bool {
+        height >= self.activation_height(feature)
+    }
+
+    /// Fails unless `feature` is active for the block at `height`.
+    pub fn require(&self, feature: Feature, height: u64) -> Result<(), String> {
+        if self.is_active(feature, height) {
+            return Ok(());
+        }
+        Err(format!(
+            "The {} feature is not active until height {}",
+            feature.as_str(),
+            self.activation_height(feature)
+        ))
+    }
+}
+
+/// Where a block's fees go, in basis points of the total: some are burned,
+/// some go to the [`TREASURY`] and the proposer keeps the rest. Routing
+/// nothing, the default, leaves every fee to the proposer.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FeeRouting {
+    pub burn_bps: u16,
+    pub treasury_bps: u16,
+}
+
+/// A block's fees, split by [`FeeRouting::split`]. The parts add up to the
+/// fees exactly.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FeeSplit {
+    pub burned: Amount,
+    pub treasury: Amount,
+    pub proposer: Amount,
+}
+
+impl FeeRouting {
+    /// Fails if the shares add up to more than the whole.
+    pub fn new(burn_bps: u16, treasury_bps: u16) -> Result<Self, String> {
+        if burn_bps as u32 + treasury_bps as u32 > MAX_FEE_BPS as u32 {
+            return Err(format!(
+                "Burned and treasury shares add up to more than {} basis points",
+                MAX_FEE_BPS
+            ));
+        }
+        Ok(FeeRouting {
+            burn_bps,
+            treasury_bps,
+        })
+    }
+
+    /// This routing with the shares governance set in `parameters` in
+    /// place of the configured ones.
+    pub fn governed(self, parameters: &Parameters) -> Self {
+        FeeRouting {
+            burn_bps: parameters.fee_burn_bps.unwrap_or(self.burn_bps),
+            treasury_bps: parameters.fee_treasury_bps.unwrap_or(self.treasury_bps),
+        }
+    }
+
+    /// Splits `fees`, rounding the burned and treasury shares down. The
+    /// burn comes first: where the shares add up to more than the whole,
+    /// the treasury gets what is left of it.
+    pub fn split(&self, fees: Amount) -> FeeSplit {
+        let burn_bps = self.burn_bps.min(MAX_FEE_BPS);
+        let treasury_bps = self.treasury_bps.min(MAX_FEE_BPS - burn_bps);
+        let share = |bps: u16| fees.mul_div(bps as u128, MAX_FEE_BPS as u128);
+        let burned = share(burn_bps);
+        let treasury = share(treasury_bps);
+        FeeSplit {
+            burned,
+            treasury,
+            proposer: fees.saturating_sub(burned).saturating_sub(treasury),
+        }
+    }
+}
+
+/// When validators are jailed for missing their slots; see
+/// [`crate::liveness`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Liveness {
+    /// Blocks in an epoch, at the end of which missed slots are tallied and
+    /// the secrets committed in the epoch before must have been revealed;
+    /// see [`crate::beacon`].
+    pub epoch_length: u64,
+    /// Slots a validator may miss in an epoch without being jailed.
+    pub max_missed: u32,
+    /// Stake a jailed validator and its delegators lose, in basis points.
+    pub penalty_bps: u16,
+}
+
+impl Liveness {
+    /// What a validator jailed with `weight` staked, its own and
+    /// delegated, loses in all.
+    pub fn penalty(&self, weight: Amount) -> Amount {
+        weight.mul_div(self.penalty_bps.min(10_000) as u128, 10_000)
+    }
+}
+
+impl Default for Liveness {
+    fn default() -> Self {
+        Liveness {
+            epoch_length: 1_000,
+            max_missed: 100,
+            penalty_bps: 100,
+        }
+    }
+}
+
+/// What a validator loses for not revealing the secret it committed to;
+/// see [`crate::beacon`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Beacon {
+    /// Stake the validator and its delegators lose, in basis points.
+    pub penalty_bps: u16,
+}
+
+impl Beacon {
+    /// What a validator with `weight` staked, its own and delegated, loses
+    /// in all.
+    pub fn penalty(&self, weight: Amount) -> Amount {
+        weight.mul_div(self.penalty_bps.min(10_000) as u128, 10_000)
+    }
+}
+
+impl Default for Beacon {
+    fn default() -> Self {
+        Beacon { penalty_bps: 100 }
+    }
+}
+
+/// Everything about a chain's consensus rules that is configured rather
+/// than fixed in code.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChainParams {
+    pub policy: MonetaryPolicy,
+    pub forks: ForkSchedule,
+    pub fees: FeeRouting,
+    /// Length of a block production slot, unless governance set another.
+    /// Missed slots are counted by it, so every node must agree on it.
+    pub block_time: Duration,
+    pub liveness: Liveness,
+    pub beacon: Beacon,
+}
+
+impl Default for ChainParams {
+    fn default() -> Self {
+        ChainParams {
+            policy: MonetaryPolicy::default(),
+            forks: ForkSchedule::default(),
+            fees: FeeRouting::default(),
+            block_time: DEFAULT_BLOCK_TIME,
+            liveness: Liveness::default(),
+            beacon: Beacon::default(),
+        }
+    }
+}
@@ -0,0 +1,125 @@
+//! Dialing peers through a SOCKS5 proxy such as Tor, so they see the
+//! proxy's address instead of ours.
+
+use std::fmt;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::str::FromStr;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+const SOCKS_VERSION: u8 = 5;
+const NO_AUTHENTICATION: u8 = 0;
+const CONNECT: u8 = 1;
+const IPV4: u8 = 1;
+const DOMAIN_NAME: u8 = 3;
+const IPV6: u8 = 4;
+
+/// A SOCKS5 proxy without authentication, given as `socks5://HOST:PORT`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Socks5Proxy {
+    addr: SocketAddr,
+}
+
+impl Socks5Proxy {
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Opens a connection to `target` through the proxy.
+    pub async fn connect(&self, target: SocketAddr) -> Result<TcpStream, String> {
+        let mut stream = TcpStream::connect(self.addr)
+            .await
+            .map_err(|e| format!("Failed to reach proxy at {}: {}", self.addr, e))?;
+        self.negotiate(&mut stream, target)
+            .await
+            .map_err(|e| format!("Proxy at {} failed: {}", self.addr, e))?;
+        Ok(stream)
+    }
+
+    async fn negotiate(&self, stream: &mut TcpStream, target: SocketAddr) -> Result<(), String> {
+        stream
+            .write_all(&[SOCKS_VERSION, 1, NO_AUTHENTICATION])
+            .await
+            .map_err(|e| e.to_string())?;
+        let mut choice = [0u8; 2];
+        stream
+            .read_exact(&mut choice)
+            .await
+            .map_err(|e| e.to_string())?;
+        if choice != [SOCKS_VERSION, NO_AUTHENTICATION] {
+            return Err("proxy requires authentication".to_string());
+        }
+
+        let mut request = vec![SOCKS_VERSION, CONNECT, 0];
+        match target {
+            SocketAddr::V4(addr) => {
+                request.push(IPV4);
+                request.extend_from_slice(&addr.ip().octets());
+            }
+            SocketAddr::V6(addr) => {
+                request.push(IPV6);
+                request.extend_from_slice(&addr.ip().octets());
+            }
+        }
+        request.extend_from_slice(&target.port().to_be_bytes());
+        stream
+            .write_all(&request)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        let mut reply = [0u8; 4];
+        stream
+            .read_exact(&mut reply)
+            .await
+            .map_err(|e| e.to_string())?;
+        if reply[0] != SOCKS_VERSION {
+            return Err("not a SOCKS5 proxy".to_string());
+        }
+        if reply[1] != 0 {
+            return Err(format!("connection refused with code {}", reply[1]));
+        }
+        // The address the proxy bound for us, which we have no use for.
+        let bound_len = match reply[3] {
+            IPV4 => 4,
+            IPV6 => 16,
+            DOMAIN_NAME => {
+                let mut len = [0u8; 1];
+                stream
+                    .read_exact(&mut len)
+                    .await
+                    .map_err(|e| e.to_string())?;
+                len[0] as usize
+            }
+            other => return Err(format!("unknown address type {}", other)),
+        };
+        let mut bound = vec![0u8; bound_len + 2];
+        stream
+            .read_exact(&mut bound)
+            .await
+            .map_err(|e| e.to_string())?;
+        Ok(())
+    }
+}
+
+impl FromStr for Socks5Proxy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let host = s
+            .strip_prefix("socks5://")
+            .ok_or(format!("Unknown proxy {}; expected socks5://HOST:PORT", s))?
+            .trim_end_matches('/');
+        let addr = host
+            .to_socket_addrs()
+            .map_err(|e| format!("Invalid proxy address {}: {}", host, e))?
+            .next()
+            .ok_or(format!("Proxy host {} did not resolve", host))?;
+        Ok(Socks5Proxy { addr })
+    }
+}
+
+impl fmt::Display for Socks5Proxy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "socks5://{}", self.addr)
+    }
+}
@@ -0,0 +1,1064 @@
+use crate::access::{AccessError, CallLimiter, CallerKey, Role, RpcAccess};
+use crate::amount::Amount;
+use crate::beacon;
+use crate::blockchain::{
+    Address, Block, BlockHeader, Blockchain, Hash, Transaction, TransactionLocation, TxData, User,
+};
+use crate::error::BlockchainError;
+use crate::events::{EventBus, NodeEvent};
+use crate::governance::{ParameterChange, Parameters, Proposal, Tally};
+use crate::p2p::{NODE_VERSION, P2P, TrafficStats};
+use crate::params::{Feature, TREASURY};
+use crate::staking::Validator;
+use crate::stats::{self, ChainStats};
+use crate::sync::SyncState;
+use crate::webhooks::Webhook;
+use axum::Router;
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{ConnectInfo, Query, State};
+use axum::http::{HeaderMap, HeaderValue, Method, header};
+use axum::response::Response;
+use axum::routing::{get, post};
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::watch;
+use tower_http::cors::{AllowOrigin, CorsLayer};
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const SERVER_ERROR: i64 = -32000;
+const UNAUTHORIZED: i64 = -32030;
+const RATE_LIMITED: i64 = -32031;
+
+/// Most headers `chain_getHeaders` returns at once.
+const MAX_HEADERS_PAGE: u64 = 100;
+/// Most transactions `account_getTransactions` returns at once.
+const MAX_TRANSACTIONS_PAGE: u64 = 100;
+/// Most accounts `state_topAccounts` returns.
+const MAX_TOP_ACCOUNTS: u64 = 100;
+
+#[derive(Debug)]
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl RpcError {
+    fn invalid_params(message: impl Into<String>) -> Self {
+        RpcError {
+            code: INVALID_PARAMS,
+            message: message.into(),
+        }
+    }
+
+    fn server(message: impl Into<String>) -> Self {
+        RpcError {
+            code: SERVER_ERROR,
+            message: message.into(),
+        }
+    }
+}
+
+impl From<BlockchainError> for RpcError {
+    fn from(err: BlockchainError) -> Self {
+        RpcError {
+            code: err.code(),
+            message: err.to_string(),
+        }
+    }
+}
+
+/// What the RPC handlers need from the node.
+#[derive(Clone, Debug)]
+pub struct RpcContext {
+    pub blockchain: Blockchain,
+    pub p2p: P2P,
+    pub sync_state: watch::Receiver<SyncState>,
+    pub events: EventBus,
+}
+
+/// A listener's context with its access rules.
+#[derive(Clone, Debug)]
+struct ListenerState {
+    context: RpcContext,
+    access: Arc<RpcAccess>,
+    limiter: Option<Arc<CallLimiter>>,
+}
+
+impl ListenerState {
+    /// The caller presenting `credential` from `peer`. A rate limit counts
+    /// against an accepted credential, so callers sharing an address keep
+    /// their own budgets, and against the address otherwise.
+    fn caller(&self, peer: SocketAddr, credential: Option<&str>) -> Caller<'_> {
+        let granted = self.access.authenticate(credential, peer.ip());
+        let key = match credential {
+            Some(credential) if granted.is_ok() && self.access.requires_auth() => {
+                CallerKey::credential(credential)
+            }
+            _ => CallerKey::Ip(peer.ip()),
+        };
+        Caller {
+            access: &self.access,
+            limiter: self.limiter.as_deref(),
+            key,
+            granted,
+        }
+    }
+}
+
+/// Who is calling: the role their credential grants, or why it was
+/// refused.
+struct Caller<'a> {
+    access: &'a RpcAccess,
+    limiter: Option<&'a CallLimiter>,
+    key: CallerKey,
+    granted: Result<Role, String>,
+}
+
+impl Caller<'_> {
+    fn check(&self, method: &str) -> Result<(), RpcError> {
+        // Refused calls count too, so guessing credentials is as slow as
+        // anything else.
+        if let Some(limiter) = self.limiter
+            && !limiter.allow(&self.key)
+        {
+            return Err(RpcError {
+                code: RATE_LIMITED,
+                message: "Too many requests; slow down".to_string(),
+            });
+        }
+        let granted = self.granted.clone().map_err(|message| RpcError {
+            code: UNAUTHORIZED,
+            message,
+        })?;
+        self.access.authorize(granted, method).map_err(|e| {
+            let code = match e {
+                AccessError::NotServed(_) | AccessError::Filtered => METHOD_NOT_FOUND,
+                AccessError::Unauthorized(_) => UNAUTHORIZED,
+            };
+            RpcError {
+                code,
+                message: e.to_string(),
+            }
+        })
+    }
+}
+
+/// A stream a WebSocket client has subscribed to.
+#[derive(Clone, Debug, PartialEq)]
+enum Subscription {
+    NewHeads,
+    PendingTransactions,
+    AccountChanges(Address),
+}
+
+/// Serves JSON-RPC 2.0 over HTTP POST on `addr`, and over WebSocket with
+/// subscriptions on `/ws`, in the background until `shutdown` turns true.
+/// Only methods of the roles `access` lists, and that its allowlist and
+/// denylist let through, are served. Returns the bound address.
+pub async fn serve(
+    context: RpcContext,
+    addr: SocketAddr,
+    access: RpcAccess,
+    mut shutdown: watch::Receiver<bool>,
+) -> Result<SocketAddr, String> {
+    let listener = TcpListener::bind(addr)
+        .await
+        .map_err(|e| format!("Failed to bind RPC server on {}: {}", addr, e))?;
+    let local_addr = listener
+        .local_addr()
+        .map_err(|e| format!("Failed to read RPC address: {}", e))?;
+
+    let cors = cors_layer(&access.cors_origins)?;
+    let mut router = Router::new()
+        .route("/", post(handle_http))
+        .route("/ws", get(handle_ws))
+        .with_state(ListenerState {
+            context,
+            limiter: access
+                .rate_limit
+                .map(|limit| Arc::new(CallLimiter::new(limit))),
+            access: Arc::new(access),
+        });
+    if let Some(cors) = cors {
+        router = router.layer(cors);
+    }
+    let service = router.into_make_service_with_connect_info::<SocketAddr>();
+    tokio::spawn(async move {
+        let stopped = async move {
+            let _ = shutdown.wait_for(|stopped| *stopped).await;
+        };
+        if let Err(e) = axum::serve(listener, service)
+            .with_graceful_shutdown(stopped)
+            .await
+        {
+            tracing::error!(error = %e, "RPC server stopped");
+        }
+    });
+
+    Ok(local_addr)
+}
+
+/// Lets browsers on `origins` call the server, or on any origin for `*`.
+fn cors_layer(origins: &[String]) -> Result<Option<CorsLayer>, String> {
+    if origins.is_empty() {
+        return Ok(None);
+    }
+    let allow_origin = if origins.iter().any(|origin| origin == "*") {
+        AllowOrigin::any()
+    } else {
+        let origins = origins
+            .iter()
+            .map(|origin| {
+                HeaderValue::from_str(origin)
+                    .map_err(|_| format!("Invalid CORS origin {:?}", origin))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        AllowOrigin::list(origins)
+    };
+    Ok(Some(
+        CorsLayer::new()
+            .allow_origin(allow_origin)
+            .allow_methods([Method::GET, Method::POST])
+            .allow_headers([header::CONTENT_TYPE, header::AUTHORIZATION]),
+    ))
+}
+
+/// The bearer credential in an `Authorization` header, if any.
+fn bearer(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+        .map(str::trim)
+}
+
+async fn handle_http(
+    State(state): State<ListenerState>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    body: String,
+) -> axum::Json<Value> {
+    let caller = state.caller(peer, bearer(&headers));
+    let context = &state.context;
+    let response = match serde_json::from_str::<Value>(&body) {
+        Ok(Value::Array(requests)) if !requests.is_empty() => {
+            let mut responses = Vec::with_capacity(requests.len());
+            for request in requests {
+                responses.push(handle_request(context, &caller, request).await);
+            }
+            Value::Array(responses)
+        }
+        Ok(request) => handle_request(context, &caller, request).await,
+        Err(e) => error_response(
+            Value::Null,
+            RpcError {
+                code: PARSE_ERROR,
+                message: e.to_string(),
+            },
+        ),
+    };
+    axum::Json(response)
+}
+
+/// Browsers cannot set headers on a WebSocket, so the credential may also
+/// come as `/ws?token=...`.
+async fn handle_ws(
+    State(state): State<ListenerState>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Query(query): Query<HashMap<String, String>>,
+    upgrade: WebSocketUpgrade,
+) -> Response {
+    let credential = bearer(&headers)
+        .or(query.get("token").map(String::as_str))
+        .map(str::to_string);
+    upgrade.on_upgrade(move |socket| run_socket(state, peer, credential, socket))
+}
+
+/// Answers requests on one WebSocket and pushes a notification for every
+/// node event matching one of its subscriptions.
+async fn run_socket(
+    state: ListenerState,
+    peer: SocketAddr,
+    credential: Option<String>,
+    mut socket: WebSocket,
+) {
+    let context = &state.context;
+    let caller = state.caller(peer, credential.as_deref());
+    let mut events = context.events.subscribe();
+    let mut subscriptions: HashMap<u64, Subscription> = HashMap::new();
+    let mut next_id = 0u64;
+
+    loop {
+        tokio::select! {
+            message = socket.recv() => {
+                let text = match message {
+                    Some(Ok(Message::Text(text))) => text,
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                    Some(Ok(_)) => continue,
+                };
+                let response = match serde_json::from_str::<Value>(text.as_str()) {
+                    Ok(request) => {
+                        handle_ws_request(context, &caller, &mut subscriptions, &mut next_id, request).await
+                    }
+                    Err(e) => error_response(
+                        Value::Null,
+                        RpcError {
+                            code: PARSE_ERROR,
+                            message: e.to_string(),
+                        },
+                    ),
+                };
+                if socket.send(Message::Text(response.to_string().into())).await.is_err() {
+                    break;
+                }
+            }
+            event = events.recv() => {
+                let event = match event {
+                    Ok(event) => event,
+                    // A slow client misses events rather than stalling the node.
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => break,
+                };
+                for (id, subscription) in &subscriptions {
+                    let Some(result) = notification(subscription, &event) else {
+                        continue;
+                    };
+                    let message = json!({
+                        "jsonrpc": "2.0",
+                        "method": "subscription",
+                        "params": { "subscription": id, "result": result },
+                    });
+                    if socket.send(Message::Text(message.to_string().into())).await.is_err() {
+                        return;
+                    }
+                }
+            }
+        }
+    }
+}
+
+async fn handle_ws_request(
+    context: &RpcContext,
+    caller: &Caller<'_>,
+    subscriptions: &mut HashMap<u64, Subscription>,
+    next_id: &mut u64,
+    request: Value,
+) -> Value {
+    let id = request.get("id").cloned().unwrap_or(Value::Null);
+    let params = request.get("params").cloned().unwrap_or(json!([]));
+    let method = request.get("method").and_then(Value::as_str);
+    if let Some(method) =
+        method.filter(|method| method.starts_with("subscribe_") || *method == "unsubscribe")
+        && let Err(e) = caller.check(method)
+    {
+        return error_response(id, e);
+    }
+
+    let subscription = match method {
+        Some("subscribe_newHeads") => Subscription::NewHeads,
+        Some("subscribe_pendingTransactions") => Subscription::PendingTransactions,
+        Some("subscribe_accountChanges") => match hash_param(&params, 0) {
+            Ok(address) => Subscription::AccountChanges(address),
+            Err(e) => return error_response(id, e),
+        },
+        Some("unsubscribe") => {
+            let removed = params
+                .get(0)
+                .and_then(Value::as_u64)
+                .is_some_and(|subscription| subscriptions.remove(&subscription).is_some());
+            return json!({ "jsonrpc": "2.0", "id": id, "result": removed });
+        }
+        _ => return handle_request(context, caller, request).await,
+    };
+
+    *next_id += 1;
+    subscriptions.insert(*next_id, subscription);
+    json!({ "jsonrpc": "2.0", "id": id, "result": *next_id })
+}
+
+fn notification(subscription: &Subscription, event: &NodeEvent) -> Option<Value> {
+    match (subscription, event) {
+        (Subscription::NewHeads, NodeEvent::BlockApplied(block)) => {
+            Some(header_json(&block.header))
+        }
+        (Subscription::PendingTransactions, NodeEvent::TxAccepted(tx)) => {
+            Some(transaction_json(tx))
+        }
+        (Subscription::AccountChanges(address), NodeEvent::AccountChanged(user))
+            if &user.address == address =>
+        {
+            Some(account_json(user))
+        }
+        _ => None,
+    }
+}
+
+async fn handle_request(context: &RpcContext, caller: &Caller<'_>, request: Value) -> Value {
+    let id = request.get("id").cloned().unwrap_or(Value::Null);
+    let method = match (request.get("jsonrpc"), request.get("method")) {
+        (Some(Value::String(version)), Some(Value::String(method))) if version == "2.0" => method,
+        _ => {
+            return error_response(
+                id,
+                RpcError {
+                    code: INVALID_REQUEST,
+                    message: "Invalid JSON-RPC 2.0 request".to_string(),
+                },
+            );
+        }
+    };
+    let params = request.get("params").cloned().unwrap_or(json!([]));
+    if let Err(e) = caller.check(method) {
+        return error_response(id, e);
+    }
+
+    match dispatch(context, method, &params).await {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(e) => error_response(id, e),
+    }
+}
+
+fn error_response(id: Value, error: RpcError) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": { "code": error.code, "message": error.message },
+    })
+}
+
+async fn dispatch(context: &RpcContext, method: &str, params: &Value) -> Result<Value, RpcError> {
+    let chain = &context.blockchain;
+    match method {
+        "chain_getStatus" => {
+            let head = chain.chain_head().get();
+            let finalized = chain.latest_finalized().await.map_err(RpcError::server)?;
+            let height = head.map(|(_, height)| height);
+            let sync_state = *context.sync_state.borrow();
+            // Sync counts blocks, so the latest heights are one less.
+            let (sync, network_height) = match sync_state {
+                SyncState::Syncing { current, target } => (
+                    json!({
+                        "state": "syncing",
+                        "current": current.checked_sub(1),
+                        "target": target - 1,
+                    }),
+                    Some(target - 1),
+                ),
+                SyncState::Synced => (json!({ "state": "synced" }), height),
+            };
+            Ok(json!({
+                "height": height,
+                "latest_hash": head.map(|(hash, _)| hex::encode(hash)),
+                "finalized_height": finalized.map(|(_, height)| height),
+                "finalized_hash": finalized.map(|(hash, _)| hex::encode(hash)),
+                "network_height": network_height,
+                "sync": sync,
+                "pending_transactions": chain.pending_count().await,
+                "queued_transactions": chain.queued_count().await,
+                "peers": context.p2p.peers().await.len(),
+                "version": NODE_VERSION,
+                "uptime": context.p2p.uptime().as_secs(),
+                "clock_offset_ms": context.p2p.clock_offset().await,
+            }))
+        }
+        "chain_getBlock" => {
+            let hash = hash_param(params, 0)?;
+            full_block_json(chain, hash).await
+        }
+        "chain_getBlockByHeight" => {
+            let height = params
+                .get(0)
+                .and_then(Value::as_u64)
+                .ok_or_else(|| RpcError::invalid_params("Expected a block height"))?;
+            let block = chain
+                .get_block_by_height(height)
+                .await
+                .map_err(server_error)?;
+            match block {
+                Some(block) => full_block_json(chain, block.hash()).await,
+                None => Ok(Value::Null),
+            }
+        }
+        "chain_getValidators" => {
+            let mut validators = chain.validators().await.map_err(RpcError::server)?;
+            validators.sort_by(|a, b| {
+                b.weight()
+                    .cmp(&a.weight())
+                    .then(a.user.address.cmp(&b.user.address))
+            });
+            let total = Amount::checked_sum(validators.iter().map(Validator::weight))
+                .map_err(|e| RpcError::server(e.to_string()))?;
+            let keys = chain.validator_keys().await.map_err(RpcError::server)?;
+            Ok(json!({
+                "total_stake": total.to_string(),
+                "validators": validators
+                    .iter()
+                    .map(|validator| {
+                        json!({
+                            "address": hex::encode(validator.user.address),
+                            "stake": validator.user.stake.to_string(),
+                            "delegated": validator.delegated.to_string(),
+                            "commission_bps": validator.user.commission_bps,
+                            "missed_slots": validator.user.missed_slots,
+                            "consensus_key": keys.get(&validator.user.address).map(hex::encode),
+                            "share": validator.weight().base_units() as f64
+                                / total.base_units() as f64,
+                        })
+                    })
+                    .collect::<Vec<_>>(),
+            }))
+        }
+        "chain_getBeacon" => {
+            let height = chain.chain_head().get().map_or(0, |(_, head)| head + 1);
+            let params = chain.chain_params();
+            let epoch_length = params.liveness.epoch_length;
+            let epoch = beacon::epoch(height, epoch_length);
+            let seed = chain.beacon(epoch).await.map_err(RpcError::server)?;
+            Ok(json!({
+                "height": height,
+                "epoch": epoch,
+                "epoch_length": epoch_length,
+                "seed": hex::encode(seed),
+                "active": params.forks.is_active(Feature::Beacon, height),
+            }))
+        }
+        "chain_getForks" => {
+            let height = chain.chain_head().get().map_or(0, |(_, head)| head + 1);
+            let forks = &chain.chain_params().forks;
+            Ok(json!(
+                Feature::ALL
+                    .iter()
+                    .map(|feature| {
+                        json!({
+                            "feature": feature.as_str(),
+                            "activation_height": forks.activation_height(*feature),
+                            "active": forks.is_active(*feature, height),
+                        })
+                    })
+                    .collect::<Vec<_>>()
+            ))
+        }
+        "chain_getHeaders" => {
+            let from = params
+                .get(0)
+                .and_then(Value::as_u64)
+                .ok_or_else(|| RpcError::invalid_params("Expected a starting height"))?;
+            let limit = params
+                .get(1)
+                .and_then(Value::as_u64)
+                .map_or(MAX_HEADERS_PAGE, |limit| limit.min(MAX_HEADERS_PAGE));
+            let headers = chain
+                .get_headers_range(from, u64::MAX, limit as usize)
+                .await
+                .map_err(server_error)?;
+            // Where the next page starts, when this one came back full.
+            let next = (headers.len() as u64 == limit && limit > 0)
+                .then(|| headers.last().map(|header| header.height + 1))
+                .flatten();
+            Ok(json!({
+                "headers": headers.iter().map(header_json).collect::<Vec<_>>(),
+                "next": next,
+            }))
+        }
+        "stats_get" => {
+            let window = match params.get(0) {
+                None | Some(Value::Null) => stats::DEFAULT_WINDOW,
+                Some(window) => window
+                    .as_u64()
+                    .ok_or_else(|| RpcError::invalid_params("Expected a number of blocks"))?,
+            };
+            let stats = stats::chain_stats(chain, window)
+                .await
+                .map_err(RpcError::server)?;
+            Ok(stats.as_ref().map_or(Value::Null, stats_json))
+        }
+        "state_topAccounts" => {
+            let limit = params
+                .get(0)
+                .and_then(Value::as_u64)
+                .map_or(25, |limit| limit.min(MAX_TOP_ACCOUNTS));
+            let by_stake = match params.get(1).and_then(Value::as_str) {
+                None | Some("balance") => false,
+                Some("stake") => true,
+                Some(other) => {
+                    return Err(RpcError::invalid_params(format!(
+                        "Expected to rank by balance or stake, got {:?}",
+                        other
+                    )));
+                }
+            };
+            let accounts = chain
+                .top_accounts(by_stake, limit as usize)
+                .await
+                .map_err(RpcError::server)?;
+            let circulating = chain.total_supply().await.map_err(RpcError::server)?;
+            Ok(json!(
+                accounts
+                    .iter()
+                    .map(|user| {
+                        let mut json = account_json(user);
+                        let held = user
+                            .balance
+                            .base_units()
+                            .saturating_add(user.stake.base_units());
+                        json["share"] = json!(held as f64 / circulating.base_units().max(1) as f64);
+                        json
+                    })
+                    .collect::<Vec<_>>()
+            ))
+        }
+        "state_totalSupply" => {
+            let supply = chain.supply().await.map_err(RpcError::server)?;
+            let expected = supply
+                .expected()
+                .map_err(|e| RpcError::server(e.to_string()))?;
+            Ok(json!({
+                "allocated": supply.allocated.to_string(),
+                "emitted": supply.emitted.to_string(),
+                "burned": supply.burned.to_string(),
+                "expected": expected.to_string(),
+                "circulating": supply.circulating.to_string(),
+                "consistent": expected == supply.circulating,
+                "max_supply": chain.monetary_policy().max_supply.to_string(),
+            }))
+        }
+        "state_parameters" => {
+            let height = chain.chain_head().get().map_or(0, |(_, head)| head + 1);
+            let parameters = chain.parameters(height).await.map_err(RpcError::server)?;
+            let fees = chain.chain_params().fees.governed(&parameters);
+            let mut json = parameters_json(&parameters);
+            json["height"] = json!(height);
+            // The shares in effect, governed or configured.
+            json["fee_routing"] = json!({
+                "burn_bps": fees.burn_bps,
+                "treasury_bps": fees.treasury_bps,
+                "treasury": hex::encode(TREASURY),
+            });
+            let liveness = &chain.chain_params().liveness;
+            json["liveness"] = json!({
+                "epoch_length": liveness.epoch_length,
+                "max_missed": liveness.max_missed,
+                "penalty_bps": liveness.penalty_bps,
+            });
+            json["beacon"] = json!({
+                "penalty_bps": chain.chain_params().beacon.penalty_bps,
+            });
+            Ok(json)
+        }
+        "state_proposals" => {
+            let proposals = chain.proposals().await.map_err(RpcError::server)?;
+            Ok(json!(
+                proposals
+                    .iter()
+                    .map(|(proposal, tally)| proposal_json(proposal, tally))
+                    .collect::<Vec<_>>()
+            ))
+        }
+        // `tx_submit` is the older name, kept for existing callers.
+        "tx_submitRaw" | "tx_submit" => {
+            let bytes = params
+                .get(0)
+                .and_then(Value::as_str)
+                .and_then(|tx| hex::decode(tx.trim().trim_start_matches("0x")).ok())
+                .ok_or_else(|| RpcError::invalid_params("Expected a hex-encoded transaction"))?;
+            let tx = Transaction::from_bytes(&bytes).map_err(RpcError::invalid_params)?;
+            let hash = tx.hash();
+            chain.add_transaction(tx).await?;
+            Ok(json!(hex::encode(hash)))
+        }
+        "tx_get" => {
+            let hash = hash_param(params, 0)?;
+            let found = chain
+                .get_transaction(&hash)
+                .await
+                .map_err(RpcError::server)?;
+            let Some((tx, confirmed)) = found else {
+                return Ok(Value::Null);
+            };
+            let mut json = transaction_json(&tx);
+            json["confirmed"] = json!(confirmed);
+            json["status"] = json!(if confirmed { "confirmed" } else { "pending" });
+            if confirmed
+                && let Some((_, location)) = chain
+                    .get_transaction_with_block(&hash)
+                    .await
+                    .map_err(server_error)?
+            {
+                let (confirmations, finalized) = confirmations(chain, location.height).await?;
+                json["block"] = location_json(&location);
+                json["confirmations"] = json!(confirmations);
+                if finalized {
+                    json["status"] = json!("finalized");
+                }
+            }
+            Ok(json)
+        }
+        "account_getBalanceAt" => {
+            let address = hash_param(params, 0)?;
+            let height = params
+                .get(1)
+                .and_then(Value::as_u64)
+                .ok_or_else(|| RpcError::invalid_params("Expected a block height"))?;
+            let balance = chain
+                .get_balance_at(&address, height)
+                .await
+                .map_err(server_error)?
+                .ok_or_else(|| RpcError::server("Account not found"))?;
+            Ok(json!({ "height": height, "balance": balance.to_string() }))
+        }
+        "account_getBalanceChanges" => {
+            let address = hash_param(params, 0)?;
+            let changes = chain
+                .get_balance_changes(&address)
+                .await
+                .map_err(server_error)?;
+            Ok(json!(
+                changes
+                    .iter()
+                    .map(|change| json!({
+                        "block_hash": hex::encode(change.block_hash),
+                        "height": change.height,
+                        "before": change.before.to_string(),
+                        "after": change.after.to_string(),
+                    }))
+                    .collect::<Vec<_>>()
+            ))
+        }
+        "account_getTransactions" => {
+            let address = hash_param(params, 0)?;
+            let limit = params
+                .get(1)
+                .and_then(Value::as_u64)
+                .map_or(MAX_TRANSACTIONS_PAGE, |limit| {
+                    limit.min(MAX_TRANSACTIONS_PAGE)
+                });
+            let before = match params.get(2) {
+                None | Some(Value::Null) => None,
+                Some(cursor) => Some(location_param(cursor)?),
+            };
+            let transactions = chain
+                .get_transactions_by_address(&address, before, limit as usize)
+                .await
+                .map_err(server_error)?;
+            // Passed back as the third parameter for the next page.
+            let next = (transactions.len() as u64 == limit && limit > 0)
+                .then(|| {
+                    transactions
+                        .last()
+                        .map(|(_, location)| location_json(location))
+                })
+                .flatten();
+            Ok(json!({
+                "transactions": transactions
+                    .iter()
+                    .map(|(tx, location)| {
+                        let mut json = transaction_json(tx);
+                        json["block"] = location_json(location);
+                        json
+                    })
+                    .collect::<Vec<_>>(),
+                "next": next,
+            }))
+        }
+        "account_watch" => {
+            let address = hash_param(params, 0)?;
+            let added = chain
+                .watch_address(&address)
+                .await
+                .map_err(RpcError::server)?;
+            Ok(json!(added))
+        }
+        "account_getBalance" => {
+            let address = hash_param(params, 0)?;
+            let user = chain
+                .get_user(&address)
+                .await
+                .map_err(server_error)?
+                .ok_or_else(|| RpcError::server("Account not found"))?;
+            Ok(account_json(&user))
+        }
+        "account_getNonce" => {
+            let address = hash_param(params, 0)?;
+            let nonce = chain
+                .account_nonce(&address)
+                .await
+                .map_err(RpcError::server)?;
+            Ok(json!(nonce))
+        }
+        "peers_list" => {
+            let peers = context.p2p.peers().await;
+            Ok(json!(
+                peers.iter().map(ToString::to_string).collect::<Vec<_>>()
+            ))
+        }
+        "admin_peerStats" => {
+            let mut peers = Vec::new();
+            for (addr, traffic) in context.p2p.peer_traffic().await {
+                let mut json = traffic_json(&traffic);
+                json["addr"] = json!(addr.to_string());
+                json["node_id"] = json!(context.p2p.peer_identity(addr).await.map(hex::encode));
+                peers.push(json);
+            }
+            Ok(json!({
+                "total": traffic_json(&context.p2p.total_traffic().await),
+                "peers": peers,
+            }))
+        }
+        "webhook_register" => {
+            let address = hash_param(params, 0)?;
+            let url = params
+                .get(1)
+                .and_then(Value::as_str)
+                .ok_or_else(|| RpcError::invalid_params("Expected a callback URL"))?;
+            let webhook = chain
+                .register_webhook(&address, url)
+                .await
+                .map_err(RpcError::invalid_params)?;
+            // The only time the secret is given out.
+            let mut json = webhook_json(&webhook);
+            json["secret"] = json!(hex::encode(webhook.secret));
+            Ok(json)
+        }
+        "webhook_list" => {
+            let webhooks = chain.webhooks().await.map_err(RpcError::server)?;
+            Ok(json!(webhooks.iter().map(webhook_json).collect::<Vec<_>>()))
+        }
+        "webhook_remove" => {
+            let id = params
+                .get(0)
+                .and_then(Value::as_u64)
+                .ok_or_else(|| RpcError::invalid_params("Expected a webhook ID"))?;
+            let removed = chain.remove_webhook(id).await.map_err(RpcError::server)?;
+            Ok(json!(removed))
+        }
+        _ => Err(RpcError {
+            code: METHOD_NOT_FOUND,
+            message: format!("Unknown method: {}", method),
+        }),
+    }
+}
+
+/// The stored block `hash` with its transactions, how many blocks confirm
+/// it and whether it is final, or null for an unknown block. A block kept
+/// as a header only comes without transactions.
+async fn full_block_json(chain: &Blockchain, hash: Hash) -> Result<Value, RpcError> {
+    let Some(block) = chain.get_full_block(hash).await.map_err(server_error)? else {
+        return Ok(Value::Null);
+    };
+    let (confirmations, finalized) = confirmations(chain, block.header.height).await?;
+    let mut json = block_json(&block);
+    json["confirmations"] = json!(confirmations);
+    json["finalized"] = json!(finalized);
+    Ok(json)
+}
+
+/// Blocks on the chain from `height` up to the head, that one included,
+/// and whether the block at `height` is final.
+async fn confirmations(chain: &Blockchain, height: u64) -> Result<(u64, bool), RpcError> {
+    let head = chain.chain_head().get().map_or(0, |(_, head)| head);
+    let finalized = chain
+        .latest_finalized()
+        .await
+        .map_err(RpcError::server)?
+        .is_some_and(|(_, finalized)| height <= finalized);
+    Ok(((head + 1).saturating_sub(height), finalized))
+}
+
+fn server_error(e: rusqlite::Error) -> RpcError {
+    RpcError::server(format!("Database error: {}", e))
+}
+
+/// Reads a hex-encoded 32-byte hash or address from positional `params`.
+fn hash_param(params: &Value, index: usize) -> Result<Hash, RpcError> {
+    params
+        .get(index)
+        .and_then(Value::as_str)
+        .and_then(|s| hex::decode(s).ok())
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| RpcError::invalid_params("Expected a 32-byte hex string"))
+}
+
+/// A transaction location as given by [`location_json`].
+fn location_param(value: &Value) -> Result<TransactionLocation, RpcError> {
+    let invalid = || RpcError::invalid_params("Expected a transaction location");
+    let block_hash = value
+        .get("block_hash")
+        .and_then(Value::as_str)
+        .and_then(|s| hex::decode(s).ok())
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(invalid)?;
+    let height = value
+        .get("height")
+        .and_then(Value::as_u64)
+        .ok_or_else(invalid)?;
+    let index = value
+        .get("index")
+        .and_then(Value::as_u64)
+        .and_then(|index| u32::try_from(index).ok())
+        .ok_or_else(invalid)?;
+    Ok(TransactionLocation {
+        block_hash,
+        height,
+        index,
+    })
+}
+
+fn location_json(location: &TransactionLocation) -> Value {
+    json!({
+        "block_hash": hex::encode(location.block_hash),
+        "height": location.height,
+        "index": location.index,
+    })
+}
+
+fn header_json(header: &BlockHeader) -> Value {
+    json!({
+        "hash": hex::encode(header.hash()),
+        "previous_hash": hex::encode(header.previous_hash),
+        "merkle_root": hex::encode(header.merkle_root),
+        "state_root": hex::encode(header.state_root),
+        "timestamp": header.timestamp,
+        "height": header.height,
+        "proposer": hex::encode(header.proposer),
+        "coinbase": header.coinbase.to_string(),
+        "finalized_hash": hex::encode(header.finalized_hash),
+    })
+}
+
+fn block_json(block: &Block) -> Value {
+    let mut json = header_json(&block.header);
+    json["transactions"] = block.transactions.iter().map(transaction_json).collect();
+    json
+}
+
+fn account_json(user: &User) -> Value {
+    json!({
+        "address": hex::encode(user.address),
+        "balance": user.balance.to_string(),
+        "stake": user.stake.to_string(),
+        "delegate": user.delegate.map(hex::encode),
+        "commission_bps": user.commission_bps,
+        "missed_slots": user.missed_slots,
+        "jailed": user.jailed,
+    })
+}
+
+/// Governed values, or null for those left to each node's setting.
+fn parameters_json(parameters: &Parameters) -> Value {
+    json!({
+        "block_time": parameters
+            .block_time
+            .map(|time| time.as_millis() as u64),
+        "block_reward": parameters.block_reward.map(|reward| reward.to_string()),
+        "min_fee_per_byte": parameters.min_fee_per_byte.map(|fee| fee.to_string()),
+        "fee_burn": parameters.fee_burn_bps,
+        "fee_treasury": parameters.fee_treasury_bps,
+    })
+}
+
+fn change_json(change: &ParameterChange) -> Value {
+    json!({
+        "parameter": change.parameter.as_str(),
+        "value": change.parameter.format_value(change.value),
+        "activation_height": change.activation_height,
+    })
+}
+
+fn proposal_json(proposal: &Proposal, tally: &Tally) -> Value {
+    let mut json = change_json(&proposal.change);
+    json["id"] = json!(proposal.id);
+    json["proposer"] = json!(hex::encode(proposal.proposer));
+    json["height"] = json!(proposal.height);
+    json["status"] = json!(proposal.status.as_str());
+    json["approve"] = json!(tally.approve.to_string());
+    json["reject"] = json!(tally.reject.to_string());
+    json["total_stake"] = json!(tally.total.to_string());
+    json
+}
+
+fn traffic_json(traffic: &TrafficStats) -> Value {
+    let kinds: serde_json::Map<String, Value> = traffic
+        .kinds
+        .iter()
+        .map(|(kind, stats)| {
+            let stats = json!({
+                "messages_sent": stats.messages_sent,
+                "bytes_sent": stats.bytes_sent,
+                "messages_received": stats.messages_received,
+                "bytes_received": stats.bytes_received,
+            });
+            (kind.to_string(), stats)
+        })
+        .collect();
+    json!({
+        "bytes_sent": traffic.bytes_sent,
+        "bytes_received": traffic.bytes_received,
+        "messages": kinds,
+    })
+}
+
+fn stats_json(stats: &ChainStats) -> Value {
+    json!({
+        "from_height": stats.from_height,
+        "to_height": stats.to_height,
+        "blocks": stats.blocks,
+        "transactions": stats.transactions,
+        "average_block_interval": stats.average_block_interval,
+        "transactions_per_second": stats.transactions_per_second,
+        "fees": stats.fees.to_string(),
+        "rewards": stats.rewards.to_string(),
+        "total_stake": stats.total_stake.to_string(),
+        "active_stake": stats.active_stake.to_string(),
+        "validators": stats.validators,
+        "active_validators": stats.active_validators,
+        "participation": stats.participation(),
+    })
+}
+
+fn webhook_json(webhook: &Webhook) -> Value {
+    json!({
+        "id": webhook.id,
+        "address": hex::encode(webhook.address),
+        "url": webhook.url,
+    })
+}
+
+pub(crate) fn transaction_json(tx: &Transaction) -> Value {
+    let mut json = json!({
+        "hash": hex::encode(tx.hash()),
+        "sender": hex::encode(tx.sender_address()),
+        "receiver": hex::encode(tx.payload.receiver),
+        "amount": tx.payload.amount.to_string(),
+        "fee": tx.payload.fee.to_string(),
+        "nonce": tx.payload.nonce,
+        "kind": tx.payload.kind.as_str(),
+    });
+    match tx.payload.data {
+        TxData::None => {}
+        TxData::Proposal(change) => json["proposal"] = change_json(&change),
+        TxData::Ballot(ballot) => {
+            json["vote"] = json!({ "proposal": ballot.proposal, "approve": ballot.approve });
+        }
+        TxData::Commission(commission) => json["commission_bps"] = json!(commission.bps),
+        TxData::Registration(registration) => {
+            json["consensus_key"] = json!(hex::encode(registration.consensus_key));
+            json["commission_bps"] = json!(registration.commission.bps);
+        }
+        TxData::ConsensusKey(key) => json["consensus_key"] = json!(hex::encode(key)),
+        TxData::Commit(commit) => {
+            json["commitment"] = json!(hex::encode(commit.commitment));
+            json["epoch"] = json!(commit.epoch);
+        }
+        TxData::Secret(secret) => json["secret"] = json!(hex::encode(secret)),
+    }
+    json
+}
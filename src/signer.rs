@@ -0,0 +1,314 @@
+//! Signing block proposals, either with a validator key loaded into the
+//! node or through a remote signer, so the key can stay on a separate
+//! machine.
+//!
+//! A remote signer speaks a small HTTP API, over TCP or a Unix socket:
+//! `GET /public_key` answers `{"public_key": HEX}` and `POST /sign_block`
+//! with `{"hash": HEX, "height": N}` answers `{"signature": HEX}`.
+
+use crate::blockchain::{Block, Hash, block_signing_bytes};
+use axum::Router;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::routing::{get, post};
+use ed25519_dalek::SigningKey;
+use ed25519_dalek::ed25519::signature::SignerMut;
+use futures::lock::Mutex;
+use serde_json::{Value, json};
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::watch;
+
+/// A remote signer that has not answered by now is treated as down.
+const SIGNER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Reads a signing key stored as 64 hex digits.
+pub fn load_key(path: &Path) -> Result<SigningKey, String> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let bytes: [u8; 32] = hex::decode(contents.trim())
+        .map_err(|e| format!("Invalid signing key: {}", e))?
+        .try_into()
+        .map_err(|_| "Expected a 32-byte signing key".to_string())?;
+    Ok(SigningKey::from_bytes(&bytes))
+}
+
+/// Where a remote signer listens: `http://HOST:PORT` or `unix:PATH`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SignerEndpoint {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+}
+
+impl FromStr for SignerEndpoint {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(addr) = s.strip_prefix("http://") {
+            let addr = addr
+                .trim_end_matches('/')
+                .parse()
+                .map_err(|e| format!("Invalid signer address: {}", e))?;
+            Ok(SignerEndpoint::Tcp(addr))
+        } else if let Some(path) = s.strip_prefix("unix:") {
+            Ok(SignerEndpoint::Unix(PathBuf::from(path)))
+        } else {
+            Err(format!(
+                "Unknown signer endpoint {}; expected http://HOST:PORT or unix:PATH",
+                s
+            ))
+        }
+    }
+}
+
+impl fmt::Display for SignerEndpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SignerEndpoint::Tcp(addr) => write!(f, "http://{}", addr),
+            SignerEndpoint::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+/// Signs the blocks a validator proposes.
+#[derive(Clone, Debug)]
+pub enum BlockSigner {
+    Local(SigningKey),
+    Remote(RemoteSigner),
+}
+
+impl BlockSigner {
+    pub fn public_key(&self) -> [u8; 32] {
+        match self {
+            BlockSigner::Local(key) => key.verifying_key().to_bytes(),
+            BlockSigner::Remote(remote) => remote.public_key,
+        }
+    }
+
+    /// Fills in the block's signature. The rest of the header must be final.
+    pub async fn sign(&self, block: &mut Block) -> Result<(), String> {
+        match self {
+            BlockSigner::Local(key) => block.sign(key),
+            BlockSigner::Remote(remote) => {
+                let signature = remote.sign_block(block.hash(), block.header.height).await?;
+                block.header.signature = signature;
+                if !block.header.verify_signature(&remote.public_key) {
+                    return Err("Remote signer returned an invalid signature".to_string());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A signer holding the validator key on the far side of a
+/// [`SignerEndpoint`].
+#[derive(Clone, Debug)]
+pub struct RemoteSigner {
+    endpoint: SignerEndpoint,
+    public_key: [u8; 32],
+}
+
+impl RemoteSigner {
+    /// Asks the signer at `endpoint` for the public key it signs with.
+    pub async fn connect(endpoint: SignerEndpoint) -> Result<Self, String> {
+        let response = request(&endpoint, "GET", "/public_key", None).await?;
+        let public_key = hex_field(&response, "public_key")
+            .map_err(|e| format!("Bad response from signer: {}", e))?;
+        Ok(RemoteSigner {
+            endpoint,
+            public_key,
+        })
+    }
+
+    async fn sign_block(&self, hash: Hash, height: u64) -> Result<[u8; 64], String> {
+        let body = json!({ "hash": hex::encode(hash), "height": height });
+        let response = request(&self.endpoint, "POST", "/sign_block", Some(body)).await?;
+        hex_field(&response, "signature").map_err(|e| format!("Bad response from signer: {}", e))
+    }
+}
+
+fn hex_field<const N: usize>(response: &Value, field: &str) -> Result<[u8; N], String> {
+    let hex_str = response
+        .get(field)
+        .and_then(Value::as_str)
+        .ok_or(format!("Missing {}", field))?;
+    hex::decode(hex_str)
+        .map_err(|e| format!("Invalid {}: {}", field, e))?
+        .try_into()
+        .map_err(|_| format!("Invalid {} length", field))
+}
+
+/// Sends one HTTP/1.1 request and returns the JSON body of a successful
+/// response.
+async fn request(
+    endpoint: &SignerEndpoint,
+    method: &str,
+    path: &str,
+    body: Option<Value>,
+) -> Result<Value, String> {
+    let body = body.map(|body| body.to_string()).unwrap_or_default();
+    let request = format!(
+        "{} {} HTTP/1.1\r\nHost: signer\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        method,
+        path,
+        body.len(),
+        body
+    );
+
+    let round_trip = async {
+        match endpoint {
+            SignerEndpoint::Tcp(addr) => {
+                let stream = TcpStream::connect(addr)
+                    .await
+                    .map_err(|e| format!("Failed to reach signer at {}: {}", endpoint, e))?;
+                exchange(stream, &request).await
+            }
+            #[cfg(unix)]
+            SignerEndpoint::Unix(path) => {
+                let stream = tokio::net::UnixStream::connect(path)
+                    .await
+                    .map_err(|e| format!("Failed to reach signer at {}: {}", endpoint, e))?;
+                exchange(stream, &request).await
+            }
+            #[cfg(not(unix))]
+            SignerEndpoint::Unix(_) => {
+                Err("Unix socket signers are not supported on this platform".to_string())
+            }
+        }
+    };
+    let response = tokio::time::timeout(SIGNER_TIMEOUT, round_trip)
+        .await
+        .map_err(|_| format!("Signer at {} timed out", endpoint))??;
+
+    let (head, body) = response
+        .split_once("\r\n\r\n")
+        .ok_or("Malformed response from signer")?;
+    let status = head.split_whitespace().nth(1).unwrap_or_default();
+    if status != "200" {
+        return Err(format!("Signer refused: {} {}", status, body.trim()));
+    }
+    serde_json::from_str(body).map_err(|e| format!("Invalid response from signer: {}", e))
+}
+
+async fn exchange<S: AsyncRead + AsyncWrite + Unpin>(
+    mut stream: S,
+    request: &str,
+) -> Result<String, String> {
+    stream
+        .write_all(request.as_bytes())
+        .await
+        .map_err(|e| e.to_string())?;
+    let mut response = Vec::new();
+    stream
+        .read_to_end(&mut response)
+        .await
+        .map_err(|e| e.to_string())?;
+    String::from_utf8(response).map_err(|_| "Signer response is not UTF-8".to_string())
+}
+
+/// The key being served and the last block it signed.
+#[derive(Debug)]
+struct SignerState {
+    key: SigningKey,
+    last_signed: Option<(u64, Hash)>,
+}
+
+/// Serves `key` as a remote signer on `endpoint` in the background until
+/// `shutdown` turns true. It never signs below the last height it signed,
+/// nor a second block at that height, so a misbehaving node cannot get it
+/// to equivocate. Returns the bound endpoint.
+pub async fn serve(
+    key: SigningKey,
+    endpoint: &SignerEndpoint,
+    mut shutdown: watch::Receiver<bool>,
+) -> Result<SignerEndpoint, String> {
+    let state = Arc::new(Mutex::new(SignerState {
+        key,
+        last_signed: None,
+    }));
+    let router = Router::new()
+        .route("/public_key", get(handle_public_key))
+        .route("/sign_block", post(handle_sign_block))
+        .with_state(state);
+    let stopped = async move {
+        let _ = shutdown.wait_for(|stopped| *stopped).await;
+    };
+
+    match endpoint {
+        SignerEndpoint::Tcp(addr) => {
+            let listener = TcpListener::bind(addr)
+                .await
+                .map_err(|e| format!("Failed to bind signer on {}: {}", addr, e))?;
+            let local_addr = listener
+                .local_addr()
+                .map_err(|e| format!("Failed to read signer address: {}", e))?;
+            tokio::spawn(async move {
+                if let Err(e) = axum::serve(listener, router)
+                    .with_graceful_shutdown(stopped)
+                    .await
+                {
+                    tracing::error!(error = %e, "signer stopped");
+                }
+            });
+            Ok(SignerEndpoint::Tcp(local_addr))
+        }
+        #[cfg(unix)]
+        SignerEndpoint::Unix(path) => {
+            let listener = tokio::net::UnixListener::bind(path)
+                .map_err(|e| format!("Failed to bind signer on {}: {}", path.display(), e))?;
+            tokio::spawn(async move {
+                if let Err(e) = axum::serve(listener, router)
+                    .with_graceful_shutdown(stopped)
+                    .await
+                {
+                    tracing::error!(error = %e, "signer stopped");
+                }
+            });
+            Ok(endpoint.clone())
+        }
+        #[cfg(not(unix))]
+        SignerEndpoint::Unix(_) => {
+            Err("Unix socket signers are not supported on this platform".to_string())
+        }
+    }
+}
+
+async fn handle_public_key(State(state): State<Arc<Mutex<SignerState>>>) -> axum::Json<Value> {
+    let public_key = state.lock().await.key.verifying_key().to_bytes();
+    axum::Json(json!({ "public_key": hex::encode(public_key) }))
+}
+
+async fn handle_sign_block(
+    State(state): State<Arc<Mutex<SignerState>>>,
+    body: String,
+) -> Result<axum::Json<Value>, (StatusCode, String)> {
+    let request: Value =
+        serde_json::from_str(&body).map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+    let hash: Hash = hex_field(&request, "hash").map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    let height = request
+        .get("height")
+        .and_then(Value::as_u64)
+        .ok_or((StatusCode::BAD_REQUEST, "Missing height".to_string()))?;
+
+    let mut state = state.lock().await;
+    if let Some((last_height, last_hash)) = state.last_signed
+        && (height < last_height || (height == last_height && hash != last_hash))
+    {
+        return Err((
+            StatusCode::CONFLICT,
+            format!("Already signed a block at height {}", last_height),
+        ));
+    }
+    let signature = state.key.sign(&block_signing_bytes(&hash));
+    state.last_signed = Some((height, hash));
+    Ok(axum::Json(
+        json!({ "signature": hex::encode(signature.to_bytes()) }),
+    ))
+}
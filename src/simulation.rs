@@ -0,0 +1,628 @@
+//! A deterministic simulation of consensus, for finding the fault
+//! schedules under which validators disagree. Virtual nodes run the real
+//! chain, fork choice and finality code against in-memory databases, but
+//! exchange their messages through an event queue in virtual time instead
+//! of sockets, so a whole run takes as long as the work it does.
+//!
+//! The network delays, drops and partitions messages as configured, with
+//! every choice drawn from one seeded RNG: the same config always plays
+//! out the same way, and a failing seed can be replayed.
+//!
+//! Validators vote as their chain grows, never for a block that conflicts
+//! with their last vote until they have finalized past it. As nodes
+//! finalize blocks the simulation checks that no two ever finalize
+//! different blocks at the same height. Finality needs two thirds of the
+//! stake, so a partition that leaves no side with that much stops it, and
+//! it stays stopped after the partition heals: the validators on the
+//! losing side remain locked on their own branch.
+
+use crate::amount::Amount;
+use crate::blockchain::{Address, Block, Blockchain, Hash, User};
+use crate::db::Database;
+use crate::devnet::devnet_key;
+use crate::finality::{Vote, VoteOutcome};
+use crate::liveness;
+use crate::node::{NodeType, SEEN_BLOCKS, SeenHashes};
+use crate::p2p::{Message, P2P};
+use crate::params::ChainParams;
+use crate::sync::{self, SyncManager};
+use ed25519_dalek::SigningKey;
+use futures::lock::Mutex;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use sha2::{Digest, Sha256};
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BTreeMap, BinaryHeap, HashMap};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+/// Timestamp virtual time starts from, 2026-01-01. It lies in the past so
+/// that blocks are never stamped too far in the future.
+const SIMULATION_EPOCH: i64 = 1_767_225_600;
+
+#[derive(Clone, Debug)]
+pub struct SimulationConfig {
+    /// Nodes in the network, each validating with an equal stake.
+    pub validators: usize,
+    pub slot_time: Duration,
+    /// Virtual time the simulation runs for.
+    pub duration: Duration,
+    /// Each message arrives after a delay drawn from this range...
+    pub min_latency: Duration,
+    pub max_latency: Duration,
+    /// ...unless it is lost, with this chance from 0 to 1.
+    pub loss: f64,
+    pub partitions: Vec<Partition>,
+    /// Validators vote for the block this far below their head, so that
+    /// short forks are settled before they vote.
+    pub vote_depth: u64,
+    /// Derives the validators' keys and draws every delay and loss.
+    pub seed: u64,
+}
+
+impl Default for SimulationConfig {
+    fn default() -> Self {
+        SimulationConfig {
+            validators: 4,
+            slot_time: Duration::from_secs(1),
+            duration: Duration::from_secs(60),
+            min_latency: Duration::from_millis(20),
+            max_latency: Duration::from_millis(200),
+            loss: 0.0,
+            partitions: Vec::new(),
+            vote_depth: 2,
+            seed: 0,
+        }
+    }
+}
+
+/// A split of the network from `start` until `end`: nodes in different
+/// groups cannot reach each other, and nodes in no group reach no one.
+#[derive(Clone, Debug)]
+pub struct Partition {
+    pub start: Duration,
+    pub end: Duration,
+    pub groups: Vec<Vec<usize>>,
+}
+
+impl Partition {
+    fn separates(&self, now: u64, from: usize, to: usize) -> bool {
+        if now < millis(self.start) || now >= millis(self.end) {
+            return false;
+        }
+        !self
+            .groups
+            .iter()
+            .any(|group| group.contains(&from) && group.contains(&to))
+    }
+}
+
+/// Two nodes finalized different blocks at the same height.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SafetyViolation {
+    /// Virtual time it was found at.
+    pub at: Duration,
+    pub node: usize,
+    pub height: u64,
+    /// The block finalized first, and the one `node` finalized instead.
+    pub finalized: Hash,
+    pub conflicting: Hash,
+}
+
+/// How a [`Simulation`] ended.
+#[derive(Clone, Debug)]
+pub struct SimulationReport {
+    pub seed: u64,
+    /// Each node's head as `(hash, height)`.
+    pub heads: Vec<(Hash, u64)>,
+    /// Each node's latest finalized height.
+    pub finalized: Vec<Option<u64>>,
+    /// Each node's latest finalized height when the last partition ended.
+    pub finalized_after_faults: Vec<Option<u64>>,
+    pub violations: Vec<SafetyViolation>,
+    pub messages_sent: u64,
+    pub messages_lost: u64,
+}
+
+impl SimulationReport {
+    /// Fails if any two nodes finalized conflicting blocks.
+    pub fn check_safety(&self) -> Result<(), String> {
+        match self.violations.first() {
+            None => Ok(()),
+            Some(violation) => Err(format!(
+                "Seed {}: node {} finalized {} at height {} over {} after {:?} ({} violations)",
+                self.seed,
+                violation.node,
+                hex::encode(violation.conflicting),
+                violation.height,
+                hex::encode(violation.finalized),
+                violation.at,
+                self.violations.len()
+            )),
+        }
+    }
+
+    /// Fails unless every node finalized at least `blocks` more blocks
+    /// after the last partition ended.
+    pub fn check_liveness(&self, blocks: u64) -> Result<(), String> {
+        for (node, (before, after)) in self
+            .finalized_after_faults
+            .iter()
+            .zip(&self.finalized)
+            .enumerate()
+        {
+            let progress = after.unwrap_or(0).saturating_sub(before.unwrap_or(0));
+            if after.is_none() || progress < blocks {
+                return Err(format!(
+                    "Seed {}: node {} finalized {} blocks after the faults, not {} ({:?} to {:?})",
+                    self.seed, node, progress, blocks, before, after
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+enum EventKind {
+    /// Every validator chosen for the slot proposes.
+    Slot,
+    Deliver {
+        from: usize,
+        to: usize,
+        message: Box<Message>,
+    },
+    /// The last partition ends.
+    Healed,
+}
+
+/// Events run in time order, and those due at the same time in the order
+/// they were scheduled.
+#[derive(Debug)]
+struct Event {
+    at: u64,
+    seq: u64,
+    kind: EventKind,
+}
+
+impl PartialEq for Event {
+    fn eq(&self, other: &Self) -> bool {
+        (self.at, self.seq) == (other.at, other.seq)
+    }
+}
+
+impl Eq for Event {}
+
+impl PartialOrd for Event {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Event {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.at, self.seq).cmp(&(other.at, other.seq))
+    }
+}
+
+struct VirtualNode {
+    key: SigningKey,
+    address: Address,
+    blockchain: Blockchain,
+    sync: SyncManager,
+    /// Blocks waiting for an ancestor to arrive.
+    orphans: HashMap<Hash, Block>,
+    /// The last block this validator voted for, as `(hash, height)`.
+    lock: Option<(Hash, u64)>,
+    /// Finalized height up to which the node's chain has been checked.
+    checked: Option<u64>,
+}
+
+/// A network of virtual validators, see the module documentation.
+pub struct Simulation {
+    config: SimulationConfig,
+    nodes: Vec<VirtualNode>,
+    queue: BinaryHeap<Reverse<Event>>,
+    now: u64,
+    seq: u64,
+    rng: StdRng,
+    /// The block each height was first finalized with, on any node.
+    finalized: BTreeMap<u64, Hash>,
+    finalized_after_faults: Option<Vec<Option<u64>>>,
+    violations: Vec<SafetyViolation>,
+    messages_sent: u64,
+    messages_lost: u64,
+}
+
+impl Simulation {
+    /// Sets up `config.validators` nodes sharing a genesis block in which
+    /// each validator holds the same stake.
+    pub async fn new(config: SimulationConfig) -> Result<Self, String> {
+        let keys: Vec<SigningKey> = (0..config.validators)
+            .map(|index| devnet_key(config.seed, &format!("validator-{}", index)))
+            .collect();
+        let mut nodes = Vec::with_capacity(keys.len());
+        for key in keys.iter().cloned() {
+            let database =
+                Database::in_memory().map_err(|e| format!("Failed to create a database: {}", e))?;
+            for key in &keys {
+                let public_key = key.verifying_key().to_bytes();
+                let user = User {
+                    address: Sha256::digest(public_key).into(),
+                    public_key,
+                    balance: Amount::from_smv(1000),
+                    stake: Amount::from_smv(100),
+                    delegate: None,
+                    commission_bps: 0,
+                    missed_slots: 0,
+                    jailed: false,
+                };
+                database
+                    .add_user(&user)
+                    .map_err(|e| format!("Failed to add a validator: {}", e))?;
+            }
+            let database = Arc::new(Mutex::new(database));
+            let mut blockchain = Blockchain::new(database.clone());
+            blockchain.set_chain_params(ChainParams {
+                block_time: config.slot_time,
+                ..ChainParams::default()
+            });
+            blockchain.create_genesis_block_at(SIMULATION_EPOCH).await?;
+            // Never started, so it opens no sockets; the simulation carries
+            // the messages instead.
+            let p2p = P2P::with_identity(database, NodeType::FullNode, key.clone());
+            let seen = Arc::new(Mutex::new(SeenHashes::new(SEEN_BLOCKS)));
+            nodes.push(VirtualNode {
+                address: Sha256::digest(key.verifying_key().to_bytes()).into(),
+                key,
+                sync: SyncManager::new(blockchain.clone(), p2p, seen),
+                blockchain,
+                orphans: HashMap::new(),
+                lock: None,
+                checked: None,
+            });
+        }
+
+        let mut simulation = Simulation {
+            rng: StdRng::seed_from_u64(config.seed),
+            config,
+            nodes,
+            queue: BinaryHeap::new(),
+            now: 0,
+            seq: 0,
+            finalized: BTreeMap::new(),
+            finalized_after_faults: None,
+            violations: Vec::new(),
+            messages_sent: 0,
+            messages_lost: 0,
+        };
+        let slot_time = millis(simulation.config.slot_time).max(1);
+        let duration = millis(simulation.config.duration);
+        for slot in 1..=duration / slot_time {
+            simulation.schedule(slot * slot_time, EventKind::Slot);
+        }
+        let healed = simulation
+            .config
+            .partitions
+            .iter()
+            .map(|partition| millis(partition.end))
+            .max();
+        if let Some(healed) = healed {
+            simulation.schedule(healed, EventKind::Healed);
+        }
+        Ok(simulation)
+    }
+
+    /// Runs the simulation for its configured duration.
+    pub async fn run(mut self) -> Result<SimulationReport, String> {
+        info!(
+            seed = self.config.seed,
+            nodes = self.nodes.len(),
+            "starting simulation"
+        );
+        let duration = millis(self.config.duration);
+        while let Some(Reverse(event)) = self.queue.pop() {
+            if event.at > duration {
+                break;
+            }
+            self.now = event.at;
+            match event.kind {
+                EventKind::Slot => {
+                    for index in 0..self.nodes.len() {
+                        self.propose(index).await?;
+                    }
+                }
+                EventKind::Deliver { from, to, message } => {
+                    self.deliver(from, to, *message).await?;
+                    self.after_change(to).await?;
+                }
+                EventKind::Healed => {
+                    self.finalized_after_faults = Some(self.finalized_heights().await?);
+                }
+            }
+        }
+
+        let mut heads = Vec::with_capacity(self.nodes.len());
+        for node in &self.nodes {
+            let (hash, next_height) = sync::chain_tip(&node.blockchain);
+            heads.push((hash, next_height.saturating_sub(1)));
+        }
+        let finalized = self.finalized_heights().await?;
+        let report = SimulationReport {
+            seed: self.config.seed,
+            heads,
+            finalized_after_faults: self
+                .finalized_after_faults
+                .unwrap_or_else(|| vec![None; finalized.len()]),
+            finalized,
+            violations: self.violations,
+            messages_sent: self.messages_sent,
+            messages_lost: self.messages_lost,
+        };
+        info!(
+            seed = report.seed,
+            finalized = ?report.finalized,
+            violations = report.violations.len(),
+            "simulation finished"
+        );
+        Ok(report)
+    }
+
+    fn schedule(&mut self, at: u64, kind: EventKind) {
+        self.seq += 1;
+        self.queue.push(Reverse(Event {
+            at,
+            seq: self.seq,
+            kind,
+        }));
+    }
+
+    /// Puts `message` on the wire, to arrive after a random delay unless a
+    /// partition or bad luck stops it.
+    fn send(&mut self, from: usize, to: usize, message: Message) {
+        self.messages_sent += 1;
+        let partitioned = self
+            .config
+            .partitions
+            .iter()
+            .any(|partition| partition.separates(self.now, from, to));
+        if partitioned || self.rng.gen_bool(self.config.loss.clamp(0.0, 1.0)) {
+            self.messages_lost += 1;
+            return;
+        }
+        let min = millis(self.config.min_latency);
+        let max = millis(self.config.max_latency).max(min);
+        let latency = self.rng.gen_range(min..=max);
+        let message = Box::new(message);
+        self.schedule(self.now + latency, EventKind::Deliver { from, to, message });
+    }
+
+    /// Sends `message` to every node but `from` and `except`.
+    fn broadcast(&mut self, from: usize, message: Message, except: Option<usize>) {
+        for to in 0..self.nodes.len() {
+            if to != from && Some(to) != except {
+                self.send(from, to, message.clone());
+            }
+        }
+    }
+
+    /// Builds, signs and announces a block on top of the node's head if
+    /// it is the proposer there of the slot the block is stamped in, the
+    /// way a validating node does.
+    async fn propose(&mut self, index: usize) -> Result<(), String> {
+        let node = &mut self.nodes[index];
+        let (previous_hash, height) = sync::chain_tip(&node.blockchain);
+        let mut timestamp = SIMULATION_EPOCH + (self.now / 1000) as i64;
+        if let Some(median) = node.blockchain.median_time_past().await? {
+            timestamp = timestamp.max(median + 1);
+        }
+        let slot = liveness::slot(timestamp, node.blockchain.block_time(height).await?);
+        if node
+            .blockchain
+            .slot_proposer(previous_hash, height, slot)
+            .await?
+            != node.address
+        {
+            return Ok(());
+        }
+
+        let mut block = Block::new(previous_hash, height, node.address, Vec::new());
+        block.header.timestamp = timestamp;
+        block.header.coinbase = node.blockchain.block_reward(height).await?;
+        if let Some((finalized_hash, _)) = node.blockchain.latest_finalized().await? {
+            block.header.finalized_hash = finalized_hash;
+        }
+        block.header.state_root = node.blockchain.state_root_after(&block).await?;
+        block.sign(&node.key);
+        node.sync.accept_block(block.clone()).await?;
+        debug!(node = index, slot, height, "proposed block");
+
+        self.broadcast(index, Message::Blocks(vec![block]), None);
+        self.after_change(index).await
+    }
+
+    async fn deliver(&mut self, from: usize, to: usize, message: Message) -> Result<(), String> {
+        match message {
+            Message::Blocks(blocks) => {
+                for block in blocks {
+                    self.receive_block(from, to, block).await;
+                }
+            }
+            Message::GetBlocks(hashes) => {
+                let mut blocks = Vec::new();
+                for hash in hashes {
+                    if let Ok(Some(block)) = self.nodes[to].blockchain.get_block(hash).await {
+                        blocks.push(block);
+                    }
+                }
+                if !blocks.is_empty() {
+                    self.send(to, from, Message::Blocks(blocks));
+                }
+            }
+            Message::Vote(vote) => match self.nodes[to].blockchain.add_vote(vote.clone()).await {
+                Ok(VoteOutcome::Duplicate) => {}
+                // Relayed like a node relays the votes new to it.
+                Ok(_) => self.broadcast(to, Message::Vote(vote), Some(from)),
+                Err(e) => debug!(node = to, error = %e, "rejected vote"),
+            },
+            message => return Err(format!("Unexpected message in simulation: {:?}", message)),
+        }
+        Ok(())
+    }
+
+    /// Imports a block a peer sent, and then any blocks that were waiting
+    /// on it. A block whose parent is missing waits while the peer is
+    /// asked for the earliest block missing below it.
+    async fn receive_block(&mut self, from: usize, to: usize, block: Block) {
+        let mut ready = vec![block];
+        while let Some(block) = ready.pop() {
+            let node = &mut self.nodes[to];
+            let hash = block.hash();
+            if node.sync.has_block(hash).await {
+                continue;
+            }
+            let parent = block.header.previous_hash;
+            if !node.sync.has_block(parent).await {
+                let mut missing = parent;
+                while let Some(orphan) = node.orphans.get(&missing) {
+                    missing = orphan.header.previous_hash;
+                }
+                node.orphans.insert(hash, block);
+                self.send(to, from, Message::GetBlocks(vec![missing]));
+                continue;
+            }
+            match node.sync.accept_block(block.clone()).await {
+                Ok(()) => {
+                    let children: Vec<Hash> = node
+                        .orphans
+                        .iter()
+                        .filter(|(_, orphan)| orphan.header.previous_hash == hash)
+                        .map(|(child, _)| *child)
+                        .collect();
+                    ready.extend(
+                        children
+                            .iter()
+                            .filter_map(|child| node.orphans.remove(child)),
+                    );
+                    self.broadcast(to, Message::Blocks(vec![block]), Some(from));
+                }
+                Err(e) => debug!(node = to, error = %e, "rejected block"),
+            }
+        }
+    }
+
+    /// Votes if the validator may, then checks what the node finalized
+    /// against every other node.
+    async fn after_change(&mut self, index: usize) -> Result<(), String> {
+        if let Some(vote) = self.next_vote(index).await? {
+            let node = &mut self.nodes[index];
+            node.lock = Some((vote.block_hash, vote.height));
+            if let Err(e) = node.blockchain.add_vote(vote.clone()).await {
+                debug!(node = index, error = %e, "own vote rejected");
+            }
+            self.broadcast(index, Message::Vote(vote), None);
+        }
+        self.check_finalized(index).await
+    }
+
+    /// A vote for the block `config.vote_depth` below the node's head, if
+    /// it is above the validator's last vote and does not conflict with
+    /// it. A validator whose last vote fell off the chain waits until the
+    /// node finalizes past it, which shows that block can no longer be
+    /// finalized; voting sooner could help finalize two conflicting blocks.
+    async fn next_vote(&self, index: usize) -> Result<Option<Vote>, String> {
+        let node = &self.nodes[index];
+        let (_, next_height) = sync::chain_tip(&node.blockchain);
+        let Some(height) = next_height.checked_sub(self.config.vote_depth + 1) else {
+            return Ok(None);
+        };
+        if height == 0 {
+            return Ok(None);
+        }
+        if let Some((locked, locked_height)) = node.lock {
+            if height <= locked_height {
+                return Ok(None);
+            }
+            let on_chain = self
+                .block_at(index, locked_height)
+                .await?
+                .map(|block| block.hash());
+            let finalized_past = node
+                .blockchain
+                .latest_finalized()
+                .await?
+                .is_some_and(|(_, finalized)| finalized >= locked_height);
+            if on_chain != Some(locked) && !finalized_past {
+                return Ok(None);
+            }
+        }
+        let block = self
+            .block_at(index, height)
+            .await?
+            .ok_or("Chain is missing a block".to_string())?;
+        Ok(Some(Vote::sign(block.hash(), height, &node.key)))
+    }
+
+    async fn block_at(&self, index: usize, height: u64) -> Result<Option<Block>, String> {
+        self.nodes[index]
+            .blockchain
+            .get_block_by_height(height)
+            .await
+            .map_err(|_| "DB error".to_string())
+    }
+
+    /// Compares every block below the node's latest finalized one that has
+    /// not been checked yet with what other nodes finalized there.
+    async fn check_finalized(&mut self, index: usize) -> Result<(), String> {
+        let node = &mut self.nodes[index];
+        let Some((mut hash, mut height)) = node.blockchain.latest_finalized().await? else {
+            return Ok(());
+        };
+        if node.checked.is_some_and(|checked| checked >= height) {
+            return Ok(());
+        }
+        let checked = node.checked.replace(height);
+        while height > 0 && checked.is_none_or(|checked| height > checked) {
+            match self.finalized.get(&height) {
+                Some(finalized) if *finalized != hash => {
+                    let violation = SafetyViolation {
+                        at: Duration::from_millis(self.now),
+                        node: index,
+                        height,
+                        finalized: *finalized,
+                        conflicting: hash,
+                    };
+                    warn!(?violation, "conflicting blocks finalized");
+                    self.violations.push(violation);
+                }
+                Some(_) => {}
+                None => {
+                    self.finalized.insert(height, hash);
+                }
+            }
+            let block = node
+                .blockchain
+                .get_block(hash)
+                .await
+                .map_err(|_| "DB error".to_string())?
+                .ok_or("Finalized block is missing".to_string())?;
+            hash = block.header.previous_hash;
+            height -= 1;
+        }
+        Ok(())
+    }
+
+    async fn finalized_heights(&self) -> Result<Vec<Option<u64>>, String> {
+        let mut heights = Vec::with_capacity(self.nodes.len());
+        for node in &self.nodes {
+            let finalized = node.blockchain.latest_finalized().await?;
+            heights.push(finalized.map(|(_, height)| height));
+        }
+        Ok(heights)
+    }
+}
+
+fn millis(duration: Duration) -> u64 {
+    duration.as_millis() as u64
+}
@@ -0,0 +1,313 @@
+//! Staking through a validator. An account either validates with stake of
+//! its own or delegates its stake to one validator with a `Delegate`
+//! transaction, so holders too small to run a node still take part. A
+//! delegator's stake adds to its validator's weight in proposer selection
+//! and finality rather than counting on its own.
+//!
+//! Validators keep a share of each block reward they earn, their
+//! commission, set with a `SetCommission` transaction; the rest is shared
+//! out between them and their delegators by stake. A slashed validator's
+//! delegators lose the same fraction of their stake as it does.
+//!
+//! Delegators still vote on governance proposals with their own stake.
+//!
+//! A validator may register a consensus key with a `RegisterValidator`
+//! transaction, and replace it with a `RotateKey` transaction, to sign its
+//! blocks and finality votes with a key other than its account's. A
+//! compromised block-signing key is then rotated out without moving the
+//! stake. Validators that never register sign with their account key.
+
+use crate::amount::Amount;
+use crate::blockchain::{Address, Transaction, TxData, TxKind, User};
+use crate::db::Database;
+use bincode::{Decode, Encode};
+use ed25519_dalek::VerifyingKey;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+
+/// Commission in basis points of a block reward, all of it at most.
+pub const MAX_COMMISSION_BPS: u16 = 10_000;
+
+/// A validator's commission, set by a `SetCommission` transaction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize, Encode, Decode)]
+pub struct Commission {
+    pub bps: u16,
+}
+
+impl Commission {
+    /// Checks the commission is at most all of the reward.
+    pub fn check(&self) -> Result<(), String> {
+        if self.bps > MAX_COMMISSION_BPS {
+            return Err(format!(
+                "Commission is at most {} basis points",
+                MAX_COMMISSION_BPS
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// A `RegisterValidator` transaction: the key the validator signs blocks
+/// and finality votes with and its commission. Registering pays only the
+/// fee.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize, Encode, Decode)]
+pub struct Registration {
+    pub consensus_key: [u8; 32],
+    pub commission: Commission,
+}
+
+/// A validating account and the stake delegated to it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Validator {
+    pub user: User,
+    pub delegated: Amount,
+}
+
+impl Validator {
+    /// What the validator weighs in selection and finality: its own stake
+    /// and all delegated to it.
+    pub fn weight(&self) -> Amount {
+        self.user.stake.saturating_add(self.delegated)
+    }
+}
+
+/// Whether `user` validates: it has stake of its own, delegated to no one,
+/// and is not jailed; see [`crate::liveness`].
+pub fn is_validator(user: &User) -> bool {
+    user.stake > Amount::ZERO && user.delegate.is_none() && !user.jailed
+}
+
+/// Every validator among `users` with the stake delegated to it, ordered
+/// by address. Stake delegated to an account that no longer validates
+/// counts for no one until its delegators move it.
+pub fn validators(users: &[User]) -> Vec<Validator> {
+    let mut validators: BTreeMap<Address, Validator> = users
+        .iter()
+        .filter(|user| is_validator(user))
+        .map(|user| {
+            let validator = Validator {
+                user: user.clone(),
+                delegated: Amount::ZERO,
+            };
+            (user.address, validator)
+        })
+        .collect();
+    for user in users {
+        if let Some(validator) = user.delegate.and_then(|to| validators.get_mut(&to)) {
+            validator.delegated = validator.delegated.saturating_add(user.stake);
+        }
+    }
+    validators.into_values().collect()
+}
+
+/// Checks a staking transaction from `sender`, after its costs are taken,
+/// against the delegation rules. `validator` is the receiver of a
+/// `Delegate` transaction, if it exists.
+pub(crate) fn check(
+    sender: &User,
+    validator: Option<&User>,
+    tx: &Transaction,
+) -> Result<(), String> {
+    let kind = tx.payload.kind;
+    match kind {
+        TxKind::Stake => {
+            if let Some(delegate) = sender.delegate {
+                return Err(format!(
+                    "{} delegates to {}; add to that with a delegate transaction",
+                    hex::encode(sender.address),
+                    hex::encode(delegate)
+                ));
+            }
+        }
+        TxKind::Delegate => {
+            let receiver = tx.payload.receiver;
+            if receiver == sender.address {
+                return Err("An account cannot delegate to itself".to_string());
+            }
+            if !validator.is_some_and(is_validator) {
+                return Err(format!("{} is not a validator", hex::encode(receiver)));
+            }
+            match sender.delegate {
+                None if sender.stake > Amount::ZERO => {
+                    return Err(format!(
+                        "{} validates with stake of its own; unstake it first",
+                        hex::encode(sender.address)
+                    ));
+                }
+                Some(delegate) if delegate != receiver => {
+                    return Err(format!(
+                        "{} already delegates to {}; unstake first",
+                        hex::encode(sender.address),
+                        hex::encode(delegate)
+                    ));
+                }
+                _ => {}
+            }
+        }
+        TxKind::SetCommission => {
+            if let TxData::Commission(commission) = tx.payload.data {
+                commission.check()?;
+            }
+            if tx.payload.amount != Amount::ZERO {
+                return Err("A set_commission transaction must have a zero amount".to_string());
+            }
+            if sender.delegate.is_some() {
+                return Err("Only validators charge commission".to_string());
+            }
+        }
+        TxKind::RegisterValidator => {
+            if let TxData::Registration(registration) = tx.payload.data {
+                registration.commission.check()?;
+                check_key_encoding(&registration.consensus_key)?;
+            }
+            if tx.payload.amount != Amount::ZERO {
+                return Err("A register_validator transaction must have a zero amount".to_string());
+            }
+            if sender.delegate.is_some() {
+                return Err("An account that delegates cannot register as a validator".to_string());
+            }
+        }
+        TxKind::RotateKey => {
+            if tx.payload.amount != Amount::ZERO {
+                return Err("A rotate_key transaction must have a zero amount".to_string());
+            }
+            if let TxData::ConsensusKey(key) = tx.payload.data {
+                check_key_encoding(&key)?;
+            }
+        }
+        TxKind::Unjail => {
+            if tx.payload.amount != Amount::ZERO {
+                return Err("An unjail transaction must have a zero amount".to_string());
+            }
+            if !sender.jailed {
+                return Err(format!("{} is not jailed", hex::encode(sender.address)));
+            }
+        }
+        TxKind::Transfer
+        | TxKind::Unstake
+        | TxKind::Propose
+        | TxKind::Vote
+        | TxKind::Commit
+        | TxKind::Reveal => {}
+    }
+    Ok(())
+}
+
+/// Checks the consensus key a `RegisterValidator` or `RotateKey`
+/// transaction from `sender` names against those registered: registering
+/// needs the sender to have no key yet and rotating needs one, and the key
+/// must be no other account's, registered or its own.
+pub(crate) fn check_key(db: &Database, sender: &User, tx: &Transaction) -> Result<(), String> {
+    let kind = tx.payload.kind;
+    let Some(key) = tx.payload.data.consensus_key() else {
+        return Ok(());
+    };
+    let fetching = |_| "Error fetching validator keys".to_string();
+    let registered = db
+        .get_validator_key(&sender.address, u64::MAX)
+        .map_err(fetching)?;
+    match (kind, registered) {
+        (TxKind::RegisterValidator, Some(_)) => {
+            return Err(format!(
+                "{} is already registered; rotate its key instead",
+                hex::encode(sender.address)
+            ));
+        }
+        (TxKind::RotateKey, None) => {
+            return Err(format!(
+                "{} has no consensus key to rotate; register first",
+                hex::encode(sender.address)
+            ));
+        }
+        _ => {}
+    }
+    if let Some(owner) = db.get_key_owner(&key).map_err(fetching)?
+        && owner != sender.address
+    {
+        return Err(format!("The key is registered to {}", hex::encode(owner)));
+    }
+    let account: Address = Sha256::digest(key).into();
+    let other = account != sender.address
+        && db
+            .get_user(&account)
+            .map_err(|_| "Error fetching user".to_string())?
+            .is_some();
+    if other {
+        return Err(format!(
+            "The key is the account key of {}",
+            hex::encode(account)
+        ));
+    }
+    Ok(())
+}
+
+fn check_key_encoding(key: &[u8; 32]) -> Result<(), String> {
+    VerifyingKey::from_bytes(key)
+        .map(|_| ())
+        .map_err(|_| "The consensus key is not a valid public key".to_string())
+}
+
+/// Splits `reward`, earned by `validator`, between it and `delegators`,
+/// those delegating to it: the validator's commission first, then the
+/// rest by stake. Rounding leftovers go to the validator. Returns what each
+/// account is owed, the validator's first.
+pub fn share_reward(
+    validator: &User,
+    delegators: &[User],
+    reward: Amount,
+) -> Vec<(Address, Amount)> {
+    let commission = reward.mul_div(
+        validator.commission_bps.min(MAX_COMMISSION_BPS) as u128,
+        MAX_COMMISSION_BPS as u128,
+    );
+    let rest = reward.saturating_sub(commission);
+    let weight = total_stake(validator, delegators);
+    let mut shares: Vec<(Address, Amount)> = delegators
+        .iter()
+        .map(|delegator| {
+            let share = rest.mul_div(delegator.stake.base_units(), weight);
+            (delegator.address, share)
+        })
+        .filter(|(_, share)| !share.is_zero())
+        .collect();
+    let paid = shares
+        .iter()
+        .fold(Amount::ZERO, |paid, (_, share)| paid.saturating_add(*share));
+    shares.insert(0, (validator.address, reward.saturating_sub(paid)));
+    shares
+}
+
+/// Splits `penalty`, slashed from `validator`, between it and
+/// `delegators` by stake, at most everything they have staked. Returns
+/// the stake each account loses, the validator's first.
+pub fn share_penalty(
+    validator: &User,
+    delegators: &[User],
+    penalty: Amount,
+) -> Vec<(Address, Amount)> {
+    let weight = total_stake(validator, delegators);
+    let penalty = penalty.min(Amount::from_base_units(weight));
+    let mut shares: Vec<(Address, Amount)> = delegators
+        .iter()
+        .map(|delegator| {
+            let share = penalty.mul_div(delegator.stake.base_units(), weight);
+            (delegator.address, share)
+        })
+        .filter(|(_, share)| !share.is_zero())
+        .collect();
+    let taken = shares.iter().fold(Amount::ZERO, |taken, (_, share)| {
+        taken.saturating_add(*share)
+    });
+    let own = penalty.saturating_sub(taken).min(validator.stake);
+    shares.insert(0, (validator.address, own));
+    shares
+}
+
+fn total_stake(validator: &User, delegators: &[User]) -> u128 {
+    delegators
+        .iter()
+        .fold(validator.stake.base_units(), |total, delegator| {
+            total.saturating_add(delegator.stake.base_units())
+        })
+}
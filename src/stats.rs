@@ -0,0 +1,105 @@
+//! Rolling metrics over the latest blocks, for dashboards.
+
+use crate::amount::Amount;
+use crate::blockchain::{Address, Blockchain};
+use crate::staking::Validator;
+use std::collections::HashSet;
+
+/// Blocks measured when no window is asked for.
+pub const DEFAULT_WINDOW: u64 = 100;
+/// Most blocks one measurement reads.
+pub const MAX_WINDOW: u64 = 1000;
+
+/// What the latest blocks did and who staked meanwhile.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChainStats {
+    /// Height of the oldest block measured.
+    pub from_height: u64,
+    /// Height of the newest block measured, the head.
+    pub to_height: u64,
+    pub blocks: u64,
+    pub transactions: u64,
+    /// Mean seconds between consecutive blocks, given two or more.
+    pub average_block_interval: Option<f64>,
+    /// Transactions over the seconds the blocks span, given they span any.
+    pub transactions_per_second: Option<f64>,
+    pub fees: Amount,
+    /// Coinbase paid to proposers.
+    pub rewards: Amount,
+    /// Own and delegated stake of every validator now.
+    pub total_stake: Amount,
+    /// Own and delegated stake of the validators that proposed one of the
+    /// blocks.
+    pub active_stake: Amount,
+    pub validators: u64,
+    pub active_validators: u64,
+}
+
+impl ChainStats {
+    /// Share of the stake that proposed blocks, from 0 to 1.
+    pub fn participation(&self) -> f64 {
+        if self.total_stake == Amount::ZERO {
+            return 0.0;
+        }
+        self.active_stake.base_units() as f64 / self.total_stake.base_units() as f64
+    }
+}
+
+/// Measures the latest `window` blocks, at most [`MAX_WINDOW`], or
+/// returns `None` before the first block. Blocks a pruned node keeps as
+/// headers only count as having no transactions.
+pub async fn chain_stats(chain: &Blockchain, window: u64) -> Result<Option<ChainStats>, String> {
+    let Some((_, head)) = chain.chain_head().get() else {
+        return Ok(None);
+    };
+    let window = window.clamp(1, MAX_WINDOW);
+    let from = (head + 1).saturating_sub(window);
+    let headers = chain
+        .get_headers_range(from, head + 1, window as usize)
+        .await
+        .map_err(|e| format!("Database error: {}", e))?;
+    let (Some(first), Some(last)) = (headers.first(), headers.last()) else {
+        return Ok(None);
+    };
+
+    let mut transactions = 0u64;
+    let mut fees = Amount::ZERO;
+    let mut rewards = Amount::ZERO;
+    let mut proposers: HashSet<Address> = HashSet::new();
+    for header in &headers {
+        let block = chain
+            .get_full_block(header.hash())
+            .await
+            .map_err(|e| format!("Database error: {}", e))?
+            .ok_or("Block vanished while measuring")?;
+        transactions += block.transactions.len() as u64;
+        fees = fees.checked_add(block.total_fees()?)?;
+        rewards = rewards.checked_add(header.coinbase)?;
+        proposers.insert(header.proposer);
+    }
+
+    let validators = chain.validators().await?;
+    let total_stake = Amount::checked_sum(validators.iter().map(Validator::weight))?;
+    let active: Vec<_> = validators
+        .iter()
+        .filter(|validator| proposers.contains(&validator.user.address))
+        .collect();
+    let active_stake = Amount::checked_sum(active.iter().map(|validator| validator.weight()))?;
+
+    let blocks = headers.len() as u64;
+    let span = (last.timestamp - first.timestamp).max(0) as f64;
+    Ok(Some(ChainStats {
+        from_height: first.height,
+        to_height: last.height,
+        blocks,
+        transactions,
+        average_block_interval: (blocks > 1).then(|| span / (blocks - 1) as f64),
+        transactions_per_second: (span > 0.0).then(|| transactions as f64 / span),
+        fees,
+        rewards,
+        total_stake,
+        active_stake,
+        validators: validators.len() as u64,
+        active_validators: active.len() as u64,
+    }))
+}
@@ -0,0 +1,301 @@
+//! Where the chain's blocks, headers, transactions, accounts and metadata
+//! are kept, behind [`ChainStore`]. [`Database`] keeps them in SQLite, for
+//! nodes; [`MemoryStore`] keeps them in maps, for tests and throwaway
+//! tools that need no file. Governance, staking and beacon records, peers
+//! and webhooks are only kept by the SQLite database, so
+//! [`Blockchain`](crate::blockchain::Blockchain) still works on a
+//! [`Database`] directly.
+
+use crate::amount::Amount;
+use crate::blockchain::{
+    Address, Block, BlockHeader, Hash, Transaction, TransactionLocation, User,
+};
+use crate::db::Database;
+use std::collections::{BTreeMap, HashMap};
+
+/// Storage for a chain, whatever it is backed by.
+pub trait ChainStore {
+    /// Stores `block` with its transactions, the accounts it updated,
+    /// creating those not stored yet, and what it burned, all or nothing. A
+    /// transaction is stored once only, so a block including one stored
+    /// already fails.
+    fn put_block(&mut self, block: &Block, updated: &[User], burned: Amount) -> Result<(), String>;
+
+    /// Undoes [`ChainStore::put_block`]: restores the accounts the block
+    /// updated, deletes those it created and deletes it and its
+    /// transactions. `false`, leaving the block in place, if it updated no
+    /// accounts to restore.
+    fn remove_block(&mut self, hash: &Hash) -> Result<bool, String>;
+
+    /// The block `hash` with its transactions.
+    fn block(&self, hash: &Hash) -> Result<Option<Block>, String>;
+
+    /// What the block `hash` burned, as [`ChainStore::put_block`] was
+    /// told.
+    fn burned(&self, hash: &Hash) -> Result<Option<Amount>, String>;
+
+    /// The block stored last at `height`, with its transactions.
+    fn block_at(&self, height: u64) -> Result<Option<Block>, String>;
+
+    /// Header of the block stored last.
+    fn head(&self) -> Result<Option<BlockHeader>, String>;
+
+    /// Headers of the blocks from height `from` up to but excluding `to`,
+    /// in height order and at most `limit` of them.
+    fn headers(&self, from: u64, to: u64, limit: usize) -> Result<Vec<BlockHeader>, String>;
+
+    /// The stored transaction `hash` and where it was included.
+    fn transaction(
+        &self,
+        hash: &Hash,
+    ) -> Result<Option<(Transaction, TransactionLocation)>, String>;
+
+    fn account(&self, address: &Address) -> Result<Option<User>, String>;
+
+    /// Every account, ordered by address.
+    fn accounts(&self) -> Result<Vec<User>, String>;
+
+    /// Adds an account not stored yet, outside any block.
+    fn add_account(&mut self, user: &User) -> Result<(), String>;
+
+    fn metadata(&self, key: &str) -> Result<Option<String>, String>;
+
+    fn set_metadata(&mut self, key: &str, value: &str) -> Result<(), String>;
+}
+
+impl ChainStore for Database {
+    fn put_block(&mut self, block: &Block, updated: &[User], burned: Amount) -> Result<(), String> {
+        self.commit_block(block, updated, burned)
+            .map_err(|e| e.to_string())
+    }
+
+    fn remove_block(&mut self, hash: &Hash) -> Result<bool, String> {
+        let restored = self.revert_block(hash).map_err(|e| e.to_string())?;
+        Ok(restored.is_some())
+    }
+
+    fn block(&self, hash: &Hash) -> Result<Option<Block>, String> {
+        let Some(mut block) = self.get_block(hash).map_err(|e| e.to_string())? else {
+            return Ok(None);
+        };
+        block.transactions = self
+            .get_block_transactions(hash)
+            .map_err(|e| e.to_string())?;
+        Ok(Some(block))
+    }
+
+    fn burned(&self, hash: &Hash) -> Result<Option<Amount>, String> {
+        self.get_block_burned(hash).map_err(|e| e.to_string())
+    }
+
+    fn block_at(&self, height: u64) -> Result<Option<Block>, String> {
+        let block = self
+            .get_block_by_height(height)
+            .map_err(|e| e.to_string())?;
+        match block {
+            Some(block) => ChainStore::block(self, &block.hash()),
+            None => Ok(None),
+        }
+    }
+
+    fn head(&self) -> Result<Option<BlockHeader>, String> {
+        let block = self.get_latest_block().map_err(|e| e.to_string())?;
+        Ok(block.map(|block| block.header))
+    }
+
+    fn headers(&self, from: u64, to: u64, limit: usize) -> Result<Vec<BlockHeader>, String> {
+        self.get_headers_range(from, to, limit)
+            .map_err(|e| e.to_string())
+    }
+
+    fn transaction(
+        &self,
+        hash: &Hash,
+    ) -> Result<Option<(Transaction, TransactionLocation)>, String> {
+        self.get_transaction_with_block(hash)
+            .map_err(|e| e.to_string())
+    }
+
+    fn account(&self, address: &Address) -> Result<Option<User>, String> {
+        self.get_user(address).map_err(|e| e.to_string())
+    }
+
+    fn accounts(&self) -> Result<Vec<User>, String> {
+        let mut users = self.get_users().map_err(|e| e.to_string())?;
+        users.sort_by_key(|user| user.address);
+        Ok(users)
+    }
+
+    fn add_account(&mut self, user: &User) -> Result<(), String> {
+        if ChainStore::account(self, &user.address)?.is_some() {
+            return Err(account_exists(&user.address));
+        }
+        self.add_user(user).map_err(|e| e.to_string())
+    }
+
+    fn metadata(&self, key: &str) -> Result<Option<String>, String> {
+        self.get_metadata(key).map_err(|e| e.to_string())
+    }
+
+    fn set_metadata(&mut self, key: &str, value: &str) -> Result<(), String> {
+        Database::set_metadata(self, key, value).map_err(|e| e.to_string())
+    }
+}
+
+/// A [`ChainStore`] that keeps everything in memory and nothing once
+/// dropped.
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    blocks: HashMap<Hash, Block>,
+    /// Hashes of the stored blocks in the order they were stored.
+    order: Vec<Hash>,
+    transactions: HashMap<Hash, (Transaction, TransactionLocation)>,
+    burned: HashMap<Hash, Amount>,
+    accounts: BTreeMap<Address, User>,
+    /// What each block's accounts held before it, `None` for those it
+    /// created.
+    undo: HashMap<Hash, Vec<(Address, Option<User>)>>,
+    metadata: HashMap<String, String>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        MemoryStore::default()
+    }
+}
+
+impl ChainStore for MemoryStore {
+    fn put_block(&mut self, block: &Block, updated: &[User], burned: Amount) -> Result<(), String> {
+        let hash = block.hash();
+        if self.blocks.contains_key(&hash) {
+            return Err(format!("Block {} is already stored", hex::encode(hash)));
+        }
+        let mut located = HashMap::new();
+        for (index, tx) in block.transactions.iter().enumerate() {
+            let tx_hash = tx.hash();
+            if self.transactions.contains_key(&tx_hash) || located.contains_key(&tx_hash) {
+                return Err(format!(
+                    "Transaction {} is already stored",
+                    hex::encode(tx_hash)
+                ));
+            }
+            let location = TransactionLocation {
+                block_hash: hash,
+                height: block.header.height,
+                index: index as u32,
+            };
+            located.insert(tx_hash, (tx.clone(), location));
+        }
+
+        if !updated.is_empty() {
+            let undo = updated
+                .iter()
+                .map(|user| (user.address, self.accounts.get(&user.address).cloned()))
+                .collect();
+            self.undo.insert(hash, undo);
+        }
+        for user in updated {
+            self.accounts.insert(user.address, user.clone());
+        }
+        self.transactions.extend(located);
+        self.burned.insert(hash, burned);
+        self.blocks.insert(hash, block.clone());
+        self.order.push(hash);
+        Ok(())
+    }
+
+    fn remove_block(&mut self, hash: &Hash) -> Result<bool, String> {
+        let Some(undo) = self.undo.remove(hash) else {
+            return Ok(false);
+        };
+        for (address, before) in undo {
+            match before {
+                Some(user) => self.accounts.insert(address, user),
+                None => self.accounts.remove(&address),
+            };
+        }
+        self.burned.remove(hash);
+        if let Some(block) = self.blocks.remove(hash) {
+            for tx in &block.transactions {
+                self.transactions.remove(&tx.hash());
+            }
+        }
+        self.order.retain(|stored| stored != hash);
+        Ok(true)
+    }
+
+    fn block(&self, hash: &Hash) -> Result<Option<Block>, String> {
+        Ok(self.blocks.get(hash).cloned())
+    }
+
+    fn burned(&self, hash: &Hash) -> Result<Option<Amount>, String> {
+        Ok(self.burned.get(hash).copied())
+    }
+
+    fn block_at(&self, height: u64) -> Result<Option<Block>, String> {
+        let block = self
+            .order
+            .iter()
+            .rev()
+            .map(|hash| &self.blocks[hash])
+            .find(|block| block.header.height == height);
+        Ok(block.cloned())
+    }
+
+    fn head(&self) -> Result<Option<BlockHeader>, String> {
+        let head = self.order.last().map(|hash| &self.blocks[hash]);
+        Ok(head.map(|block| block.header.clone()))
+    }
+
+    fn headers(&self, from: u64, to: u64, limit: usize) -> Result<Vec<BlockHeader>, String> {
+        let mut headers: Vec<(usize, &BlockHeader)> = self
+            .order
+            .iter()
+            .enumerate()
+            .map(|(stored, hash)| (stored, &self.blocks[hash].header))
+            .filter(|(_, header)| (from..to).contains(&header.height))
+            .collect();
+        headers.sort_by_key(|(stored, header)| (header.height, *stored));
+        Ok(headers
+            .into_iter()
+            .take(limit)
+            .map(|(_, header)| header.clone())
+            .collect())
+    }
+
+    fn transaction(
+        &self,
+        hash: &Hash,
+    ) -> Result<Option<(Transaction, TransactionLocation)>, String> {
+        Ok(self.transactions.get(hash).cloned())
+    }
+
+    fn account(&self, address: &Address) -> Result<Option<User>, String> {
+        Ok(self.accounts.get(address).cloned())
+    }
+
+    fn accounts(&self) -> Result<Vec<User>, String> {
+        Ok(self.accounts.values().cloned().collect())
+    }
+
+    fn add_account(&mut self, user: &User) -> Result<(), String> {
+        if self.accounts.contains_key(&user.address) {
+            return Err(account_exists(&user.address));
+        }
+        self.accounts.insert(user.address, user.clone());
+        Ok(())
+    }
+
+    fn metadata(&self, key: &str) -> Result<Option<String>, String> {
+        Ok(self.metadata.get(key).cloned())
+    }
+
+    fn set_metadata(&mut self, key: &str, value: &str) -> Result<(), String> {
+        self.metadata.insert(key.to_string(), value.to_string());
+        Ok(())
+    }
+}
+
+fn account_exists(address: &Address) -> String {
+    format!("Account {} is already stored", hex::encode(address))
+}
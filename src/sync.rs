@@ -0,0 +1,1292 @@
+use crate::blockchain::{
+    Address, Block, BlockHeader, Blockchain, Hash, SnapshotAccount, TransactionProof,
+    compute_merkle_root,
+};
+use crate::events::{EventBus, NodeEvent};
+use crate::node::SeenHashes;
+use crate::p2p::{MAX_FRAME_SIZE, Message, NODE_VERSION, P2P};
+use crate::verify::{VerifiedBlock, verify_blocks};
+use futures::lock::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+use tracing::{debug, info, warn};
+
+/// Most headers asked for, or served, in one request.
+pub const HEADERS_BATCH: u64 = 512;
+
+/// Most blocks served in one request.
+pub const SYNC_BATCH: u64 = 128;
+
+/// Blocks asked of one peer at a time while downloading bodies.
+const BODIES_PER_REQUEST: usize = 16;
+
+/// Bodies are only fetched this far past the chain tip, so blocks that
+/// arrive out of order cannot pile up without bound.
+const DOWNLOAD_WINDOW: u64 = 1024;
+
+/// Headers kept ahead of the chain tip before waiting for bodies to catch up.
+const MAX_PENDING_HEADERS: usize = 8192;
+
+/// A new node at least this many blocks behind starts from a state
+/// snapshot instead of replaying every block.
+pub const SNAPSHOT_DISTANCE: u64 = 1000;
+
+/// Blocks a pruned node keeps whole by default, enough to follow any
+/// reorganization that does not undo a finalized block.
+pub const PRUNE_DEPTH: u64 = 1024;
+
+/// Accounts sent in one snapshot chunk.
+const SNAPSHOT_CHUNK: usize = 1000;
+
+/// Failed snapshot downloads before falling back to replaying blocks.
+const SNAPSHOT_ATTEMPTS: u32 = 3;
+
+/// Blocks kept off the main chain in case their branch overtakes it;
+/// forks deeper than this are not followed.
+const MAX_SIDE_BLOCKS: usize = 1024;
+
+/// A peer that has not answered a sync request by now is given up on and
+/// the next request may go to someone else.
+pub const SYNC_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How often the node tells its peers where its chain is.
+pub const STATUS_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Room left in a `Blocks` frame for everything but the transactions.
+const BLOCKS_RESPONSE_BUDGET: usize = MAX_FRAME_SIZE / 2;
+
+/// Most addresses transaction proofs are asked for, or served, at once.
+pub const PROOF_ADDRESSES: usize = 256;
+
+/// Transaction proofs served in one response, give or take the rest of the
+/// last block's.
+const PROOFS_BATCH: usize = 256;
+
+/// Whether the chain has caught up with the longest one peers report.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq)]
+pub enum SyncState {
+    /// The chain has `current` blocks of the `target` the best peer has.
+    Syncing { current: u64, target: u64 },
+    #[default]
+    Synced,
+}
+
+#[derive(Debug)]
+struct Request {
+    peer: SocketAddr,
+    sent: Instant,
+}
+
+#[derive(Debug)]
+struct ProofRequest {
+    peer: SocketAddr,
+    sent: Instant,
+    addresses: Vec<Address>,
+    from_height: u64,
+    to_height: u64,
+}
+
+#[derive(Debug)]
+struct SnapshotDownload {
+    peer: SocketAddr,
+    sent: Instant,
+    /// The block the snapshot was taken at, once the first chunk arrived.
+    hash: Option<Hash>,
+    height: u64,
+    total: u32,
+    accounts: Vec<SnapshotAccount>,
+    received: u32,
+}
+
+impl SnapshotDownload {
+    fn is_complete(&self) -> bool {
+        self.total > 0 && self.received == self.total
+    }
+}
+
+#[derive(Debug)]
+struct BodyRequest {
+    sent: Instant,
+    /// Heights of the blocks asked for, in the order they were asked.
+    heights: Vec<u64>,
+}
+
+/// Catches the chain up with peers that are ahead of it. The header chain
+/// is fetched and checked first, from the best peer; the blocks for it are
+/// then downloaded from every peer that has them at once, and imported in
+/// order as they arrive.
+///
+/// Blocks that do not extend the head are kept as side branches. The
+/// longest chain wins: once a branch is longer than the chain the node
+/// reorganizes onto it, as long as that undoes no finalized block.
+///
+/// Nodes that only follow headers store the header chain instead, and ask
+/// peers that keep the blocks for proofs of the transactions of the
+/// addresses they watch.
+#[derive(Debug)]
+pub struct SyncManager {
+    blockchain: Blockchain,
+    p2p: P2P,
+    /// The height each peer's next block will have, as last reported.
+    peer_heights: HashMap<SocketAddr, u64>,
+    /// The lowest block each peer serves whole, as last reported.
+    peer_oldest: HashMap<SocketAddr, u64>,
+    /// Checked headers past the chain tip, the first one extending it.
+    headers: VecDeque<BlockHeader>,
+    header_request: Option<Request>,
+    proof_request: Option<ProofRequest>,
+    body_requests: HashMap<SocketAddr, BodyRequest>,
+    /// Blocks received ahead of the chain tip, by height.
+    downloaded: BTreeMap<u64, Block>,
+    /// Valid blocks on branches that have not overtaken the chain, by hash.
+    side_blocks: HashMap<Hash, Block>,
+    /// Where to ask for headers from while looking for the block a peer's
+    /// chain forked from ours at.
+    fork_search: Option<u64>,
+    /// Stop after the header chain and leave the blocks alone.
+    headers_only: bool,
+    /// Keep only this many of the latest blocks whole.
+    prune_depth: Option<u64>,
+    /// Gossip deduplication, so synced blocks are not announced again.
+    seen: Arc<Mutex<SeenHashes>>,
+    /// Trusted block hash at a height, see [`SyncManager::set_checkpoint`].
+    checkpoint: Option<(u64, Hash)>,
+    /// Start an empty chain from a snapshot when this far behind.
+    snapshot_distance: Option<u64>,
+    snapshot: Option<SnapshotDownload>,
+    snapshot_failures: u32,
+    /// The snapshot last served to peers, kept so that all its chunks come
+    /// from the same state.
+    served_snapshot: Option<(BlockHeader, Vec<SnapshotAccount>)>,
+    /// Where [`SyncState`] changes are published.
+    state: watch::Sender<SyncState>,
+    events: EventBus,
+}
+
+impl SyncManager {
+    pub(crate) fn new(blockchain: Blockchain, p2p: P2P, seen: Arc<Mutex<SeenHashes>>) -> Self {
+        SyncManager {
+            blockchain,
+            p2p,
+            peer_heights: HashMap::new(),
+            peer_oldest: HashMap::new(),
+            headers: VecDeque::new(),
+            header_request: None,
+            proof_request: None,
+            body_requests: HashMap::new(),
+            downloaded: BTreeMap::new(),
+            side_blocks: HashMap::new(),
+            fork_search: None,
+            headers_only: false,
+            prune_depth: None,
+            seen,
+            checkpoint: None,
+            snapshot_distance: None,
+            snapshot: None,
+            snapshot_failures: 0,
+            served_snapshot: None,
+            state: watch::channel(SyncState::default()).0,
+            events: EventBus::default(),
+        }
+    }
+
+    /// Publishes sync progress on `events`.
+    pub fn set_event_bus(&mut self, events: EventBus) {
+        self.events = events;
+    }
+
+    /// Publishes the sync state to `state` from now on.
+    pub fn set_state_sender(&mut self, state: watch::Sender<SyncState>) {
+        self.state = state;
+    }
+
+    /// Lets an empty chain start from a peer's state snapshot when at least
+    /// `distance` blocks behind, or never if `None`.
+    pub fn set_snapshot_distance(&mut self, distance: Option<u64>) {
+        self.snapshot_distance = distance;
+    }
+
+    /// Trusts the chain through block `hash` at `height`: header chains that
+    /// disagree with it are rejected, and blocks up to it are imported
+    /// without checking their signatures or state roots.
+    pub fn set_checkpoint(&mut self, checkpoint: Option<(u64, Hash)>) {
+        self.checkpoint = checkpoint;
+    }
+
+    /// Only follows the header chain, for nodes that do not keep blocks.
+    pub fn set_headers_only(&mut self, headers_only: bool) {
+        self.headers_only = headers_only;
+    }
+
+    /// Drops the transactions of all but the latest `depth` blocks as the
+    /// chain grows, or keeps every block if `None`.
+    pub fn set_prune_depth(&mut self, depth: Option<u64>) {
+        self.prune_depth = depth;
+    }
+
+    /// The latest header known to extend the chain, if sync is ahead of it.
+    pub fn best_header(&self) -> Option<&BlockHeader> {
+        self.headers.back()
+    }
+
+    /// The height of the longest chain known, ours or a peer's.
+    pub fn network_height(&self, height: u64) -> u64 {
+        let best_header = self.headers.back().map_or(0, |header| header.height + 1);
+        self.peer_heights
+            .values()
+            .copied()
+            .chain([best_header, height])
+            .max()
+            .unwrap_or(height)
+    }
+
+    fn sync_state(&self, height: u64) -> SyncState {
+        let target = self.network_height(height);
+        if target > height {
+            SyncState::Syncing {
+                current: height,
+                target,
+            }
+        } else {
+            SyncState::Synced
+        }
+    }
+
+    /// This node's `Status` message.
+    pub async fn status(&self) -> Result<Message, String> {
+        let (head, height) = chain_tip(&self.blockchain);
+        let oldest_block = if self.headers_only {
+            height
+        } else {
+            self.blockchain
+                .oldest_block()
+                .await
+                .map_err(|e| e.to_string())?
+        };
+        let finalized = self.blockchain.latest_finalized().await?;
+        Ok(Message::Status {
+            height,
+            head,
+            oldest_block,
+            finalized_height: finalized.map(|(_, height)| height),
+            peers: self.p2p.peers().await.len() as u32,
+            pending_transactions: self.blockchain.pending_count().await as u32,
+            sync: self.sync_state(height),
+            version: NODE_VERSION.to_string(),
+            uptime: self.p2p.uptime().as_secs(),
+        })
+    }
+
+    /// Notes that `peer` has blocks up to `height` exclusive and starts
+    /// syncing from it if it is ahead.
+    pub async fn note_height(&mut self, peer: SocketAddr, height: u64) {
+        let known = self.peer_heights.entry(peer).or_default();
+        *known = (*known).max(height);
+        self.maybe_request().await;
+    }
+
+    /// Handles the sync messages; anything else is ignored.
+    pub async fn handle(&mut self, peer: SocketAddr, message: Message) {
+        match message {
+            Message::Status {
+                height,
+                oldest_block,
+                ..
+            } => {
+                self.peer_oldest.insert(peer, oldest_block);
+                self.note_height(peer, height).await
+            }
+            Message::GetHeaders { from_height, count } => {
+                let headers = self.serve_headers(from_height, count).await;
+                self.p2p.send_to(peer, Message::Headers(headers)).await;
+            }
+            Message::GetBlocks(hashes) => {
+                let blocks = self.serve_blocks(&hashes).await;
+                self.p2p.send_to(peer, Message::Blocks(blocks)).await;
+            }
+            Message::GetSnapshot { hash, index } => {
+                let chunk = self.serve_snapshot(hash, index).await;
+                self.p2p.send_to(peer, chunk).await;
+            }
+            Message::GetTransactionProofs {
+                addresses,
+                from_height,
+                to_height,
+            } => {
+                let proofs = self.serve_proofs(&addresses, from_height, to_height).await;
+                self.p2p.send_to(peer, proofs).await;
+            }
+            Message::Headers(headers) => self.on_headers(peer, headers).await,
+            Message::TransactionProofs { proofs, scanned_to } => {
+                self.on_proofs(peer, proofs, scanned_to).await
+            }
+            Message::Blocks(blocks) => self.on_blocks(peer, blocks).await,
+            Message::SnapshotChunk {
+                hash,
+                height,
+                index,
+                total,
+                accounts,
+            } => {
+                self.on_snapshot_chunk(peer, hash, height, index, total, accounts)
+                    .await
+            }
+            _ => {}
+        }
+    }
+
+    /// Drops requests peers never answered, then asks again. The blocks a
+    /// peer was asked for go to someone else.
+    pub async fn tick(&mut self) {
+        if let Some(request) = &self.header_request
+            && request.sent.elapsed() > SYNC_TIMEOUT
+        {
+            debug!(peer = %request.peer, "header request timed out");
+            self.peer_heights.remove(&request.peer);
+            self.header_request = None;
+        }
+        if let Some(request) = &self.proof_request
+            && request.sent.elapsed() > SYNC_TIMEOUT
+        {
+            debug!(peer = %request.peer, "proof request timed out");
+            self.peer_heights.remove(&request.peer);
+            self.proof_request = None;
+        }
+        let expired: Vec<SocketAddr> = self
+            .body_requests
+            .iter()
+            .filter(|(_, request)| request.sent.elapsed() > SYNC_TIMEOUT)
+            .map(|(peer, _)| *peer)
+            .collect();
+        for peer in expired {
+            debug!(%peer, "block request timed out");
+            self.peer_heights.remove(&peer);
+            self.body_requests.remove(&peer);
+        }
+        if let Some(download) = &self.snapshot
+            && download.sent.elapsed() > SYNC_TIMEOUT
+        {
+            debug!(peer = %download.peer, "snapshot download timed out");
+            self.abandon_snapshot(true);
+        }
+
+        self.prune().await;
+
+        let connected = self.p2p.peers().await;
+        self.peer_heights.retain(|peer, _| connected.contains(peer));
+        self.peer_oldest.retain(|peer, _| connected.contains(peer));
+        self.body_requests
+            .retain(|peer, _| connected.contains(peer));
+        self.maybe_request().await;
+    }
+
+    async fn prune(&self) {
+        let Some(depth) = self.prune_depth else {
+            return;
+        };
+        let (_, height) = chain_tip(&self.blockchain);
+        match self
+            .blockchain
+            .prune_blocks(height.saturating_sub(depth))
+            .await
+        {
+            Ok(0) => {}
+            Ok(pruned) => debug!(
+                pruned,
+                below = height.saturating_sub(depth),
+                "pruned old blocks"
+            ),
+            Err(e) => warn!(error = %e, "failed to prune old blocks"),
+        }
+    }
+
+    async fn maybe_request(&mut self) {
+        self.install_snapshot().await;
+        self.trim().await;
+        let (head, height) = chain_tip(&self.blockchain);
+        let from_snapshot = self.wants_snapshot(height);
+        self.request_headers(head, height, from_snapshot).await;
+        if from_snapshot {
+            self.request_snapshot().await;
+        } else if self.headers_only {
+            self.request_proofs(height).await;
+        } else {
+            self.request_bodies().await;
+        }
+        let state = self.sync_state(height);
+        let changed = self.state.send_if_modified(|current| {
+            let changed = *current != state;
+            *current = state;
+            changed
+        });
+        if changed {
+            self.events.publish(NodeEvent::SyncProgress(state));
+        }
+    }
+
+    /// Whether an empty chain should wait for a snapshot rather than
+    /// download every block.
+    fn wants_snapshot(&self, height: u64) -> bool {
+        let Some(distance) = self.snapshot_distance else {
+            return false;
+        };
+        !self.headers_only
+            && height == 0
+            && self
+                .peer_heights
+                .values()
+                .any(|peer_height| *peer_height >= distance)
+    }
+
+    /// Forgets headers for blocks the node already has, and everything if
+    /// the headers no longer continue from a block it has.
+    async fn trim(&mut self) {
+        while let Some(first) = self.headers.front()
+            && self.has_block(first.hash()).await
+        {
+            self.headers.pop_front();
+        }
+        let Some(first) = self.headers.front() else {
+            self.downloaded.clear();
+            return;
+        };
+        self.downloaded = self.downloaded.split_off(&first.height);
+        if !self.continues_known_block(first).await {
+            debug!("chain moved off the synced headers");
+            self.headers.clear();
+            self.downloaded.clear();
+            self.body_requests.clear();
+        }
+    }
+
+    /// Whether the block is on the chain or on a side branch.
+    pub(crate) async fn has_block(&self, hash: Hash) -> bool {
+        self.side_blocks.contains_key(&hash)
+            || matches!(self.blockchain.get_block(hash).await, Ok(Some(_)))
+    }
+
+    /// Whether `header` can follow on from a block the node has, including
+    /// the first block of an empty chain.
+    async fn continues_known_block(&self, header: &BlockHeader) -> bool {
+        let (head, height) = chain_tip(&self.blockchain);
+        (header.previous_hash == head && header.height == height)
+            || self.has_block(header.previous_hash).await
+    }
+
+    /// Asks the best peer for the headers after ours. Bodies keep the
+    /// header chain from running far ahead, unless none are being fetched.
+    async fn request_headers(&mut self, head: Hash, height: u64, from_snapshot: bool) {
+        let unbounded = self.headers_only || from_snapshot;
+        if self.header_request.is_some()
+            || (!unbounded && self.headers.len() >= MAX_PENDING_HEADERS)
+        {
+            return;
+        }
+        let (_, tip_height) = self.header_tip(head, height);
+        let from_height = match self.fork_search {
+            Some(from_height) if self.headers.is_empty() => from_height,
+            _ => tip_height,
+        };
+        let Some((&peer, _)) = self
+            .peer_heights
+            .iter()
+            .filter(|(_, peer_height)| **peer_height > from_height)
+            .max_by_key(|(_, peer_height)| **peer_height)
+        else {
+            return;
+        };
+
+        debug!(%peer, from_height, "requesting headers");
+        self.header_request = Some(Request {
+            peer,
+            sent: Instant::now(),
+        });
+        let request = Message::GetHeaders {
+            from_height,
+            count: HEADERS_BATCH,
+        };
+        self.p2p.send_to(peer, request).await;
+    }
+
+    /// Hands out the blocks for the headers nobody has been asked for yet,
+    /// a few to each idle peer that has them.
+    async fn request_bodies(&mut self) {
+        for (peer, heights, hashes) in self.assign_bodies() {
+            debug!(%peer, from_height = heights[0], count = heights.len(), "requesting blocks");
+            self.body_requests.insert(
+                peer,
+                BodyRequest {
+                    sent: Instant::now(),
+                    heights,
+                },
+            );
+            self.p2p.send_to(peer, Message::GetBlocks(hashes)).await;
+        }
+    }
+
+    fn assign_bodies(&self) -> Vec<(SocketAddr, Vec<u64>, Vec<Hash>)> {
+        let Some(first) = self.headers.front() else {
+            return Vec::new();
+        };
+        let base = first.height;
+        let in_flight: HashSet<u64> = self
+            .body_requests
+            .values()
+            .flat_map(|request| request.heights.iter().copied())
+            .collect();
+        let mut wanted = self
+            .headers
+            .iter()
+            .map(|header| header.height)
+            .take_while(|height| *height < base + DOWNLOAD_WINDOW)
+            .filter(|height| !in_flight.contains(height) && !self.downloaded.contains_key(height))
+            .peekable();
+
+        let mut idle: Vec<(SocketAddr, u64)> = self
+            .peer_heights
+            .iter()
+            .filter(|(peer, _)| !self.body_requests.contains_key(peer))
+            .map(|(peer, height)| (*peer, *height))
+            .collect();
+        // Best peers first, so the earliest blocks go to those most
+        // likely to have them.
+        idle.sort_by_key(|(_, height)| std::cmp::Reverse(*height));
+
+        let mut requests = Vec::new();
+        for (peer, peer_height) in idle {
+            // Peers that have not said otherwise are taken to keep every
+            // block.
+            let oldest = self.peer_oldest.get(&peer).copied().unwrap_or(0);
+            let mut heights = Vec::new();
+            while heights.len() < BODIES_PER_REQUEST
+                && let Some(height) =
+                    wanted.next_if(|height| (oldest..peer_height).contains(height))
+            {
+                heights.push(height);
+            }
+            if heights.is_empty() {
+                continue;
+            }
+            let hashes = heights
+                .iter()
+                .map(|height| self.headers[(height - base) as usize].hash())
+                .collect();
+            requests.push((peer, heights, hashes));
+        }
+        requests
+    }
+
+    /// Hash and height the next header must follow on from.
+    fn header_tip(&self, head: Hash, height: u64) -> (Hash, u64) {
+        match self.headers.back() {
+            Some(header) => (header.hash(), header.height + 1),
+            None => (head, height),
+        }
+    }
+
+    async fn on_headers(&mut self, peer: SocketAddr, headers: Vec<BlockHeader>) {
+        if self
+            .header_request
+            .take_if(|request| request.peer == peer)
+            .is_none()
+        {
+            return;
+        }
+        self.trim().await;
+
+        if headers.is_empty() || headers.len() as u64 > HEADERS_BATCH {
+            // The peer claimed to be ahead but has nothing to show for it.
+            self.peer_heights.remove(&peer);
+        } else if let Err(e) = self.extend_headers(headers).await {
+            warn!(%peer, error = %e, "peer sent a bad header chain");
+            self.peer_heights.remove(&peer);
+            self.fork_search = None;
+            // The headers queued so far may be of a branch the peers have
+            // since left; the next request starts over from our head.
+            self.headers.clear();
+            self.downloaded.clear();
+            self.body_requests.clear();
+        } else if self.headers_only {
+            self.store_headers(peer).await;
+        }
+        self.maybe_request().await;
+    }
+
+    /// Stores the checked headers once they make a longer chain than the
+    /// stored one, for nodes that keep nothing but headers.
+    async fn store_headers(&mut self, peer: SocketAddr) {
+        let (_, height) = chain_tip(&self.blockchain);
+        if self
+            .headers
+            .back()
+            .is_none_or(|header| header.height < height)
+        {
+            return;
+        }
+        let headers: Vec<BlockHeader> = self.headers.drain(..).collect();
+        match self.blockchain.add_headers(&headers).await {
+            Ok(()) => debug!(%peer, height = headers[headers.len() - 1].height, "stored headers"),
+            Err(e) => {
+                warn!(%peer, error = %e, "could not store the header chain");
+                self.peer_heights.remove(&peer);
+            }
+        }
+    }
+
+    /// Asks a peer that keeps the blocks for proofs of the transactions of
+    /// the watched addresses in the stored headers' blocks, starting with
+    /// those scanned the least far.
+    async fn request_proofs(&mut self, height: u64) {
+        if self.proof_request.is_some() {
+            return;
+        }
+        let mut behind = match self.blockchain.watched_addresses().await {
+            Ok(watched) => watched,
+            Err(e) => {
+                warn!(error = %e, "could not read the watched addresses");
+                return;
+            }
+        };
+        behind.retain(|(_, scanned)| *scanned < height);
+        behind.sort_by_key(|(_, scanned)| *scanned);
+        behind.truncate(PROOF_ADDRESSES);
+        let Some(&(_, from_height)) = behind.first() else {
+            return;
+        };
+        // Only peers known to have every block from there on can say
+        // which of them hold the addresses' transactions.
+        let Some(peer) = self
+            .peer_heights
+            .iter()
+            .filter(|(peer, peer_height)| {
+                **peer_height >= height
+                    && self
+                        .peer_oldest
+                        .get(peer)
+                        .is_some_and(|oldest| *oldest <= from_height)
+            })
+            .max_by_key(|(_, peer_height)| **peer_height)
+            .map(|(peer, _)| *peer)
+        else {
+            return;
+        };
+
+        let addresses: Vec<Address> = behind.into_iter().map(|(address, _)| address).collect();
+        debug!(%peer, from_height, addresses = addresses.len(), "requesting transaction proofs");
+        self.proof_request = Some(ProofRequest {
+            peer,
+            sent: Instant::now(),
+            addresses: addresses.clone(),
+            from_height,
+            to_height: height,
+        });
+        let request = Message::GetTransactionProofs {
+            addresses,
+            from_height,
+            to_height: height,
+        };
+        self.p2p.send_to(peer, request).await;
+    }
+
+    async fn on_proofs(
+        &mut self,
+        peer: SocketAddr,
+        proofs: Vec<TransactionProof>,
+        scanned_to: u64,
+    ) {
+        let Some(request) = self.proof_request.take_if(|request| request.peer == peer) else {
+            return;
+        };
+
+        if scanned_to <= request.from_height || scanned_to > request.to_height {
+            // The peer no longer has the blocks, or claims to have scanned
+            // blocks we did not ask about.
+            debug!(%peer, "peer could not prove the transactions asked for");
+            self.peer_heights.remove(&peer);
+        } else {
+            match self
+                .blockchain
+                .add_proven_transactions(
+                    &request.addresses,
+                    request.from_height,
+                    scanned_to,
+                    &proofs,
+                )
+                .await
+            {
+                Ok(0) => {}
+                Ok(added) => info!(%peer, added, scanned_to, "found watched transactions"),
+                Err(e) => {
+                    warn!(%peer, error = %e, "peer sent a bad transaction proof");
+                    self.peer_heights.remove(&peer);
+                }
+            }
+        }
+        self.maybe_request().await;
+    }
+
+    /// Appends `headers` if each one follows on from the one before it.
+    /// The first batch may fork off below the head; if it forks off below
+    /// where it starts, headers are asked for from further back.
+    async fn extend_headers(&mut self, mut headers: Vec<BlockHeader>) -> Result<(), String> {
+        let (mut previous, mut next_height) = match self.headers.back() {
+            Some(header) => (header.hash(), header.height + 1),
+            None => {
+                let start = headers[0].height;
+                let mut known = 0;
+                for header in &headers {
+                    if !self.has_block(header.hash()).await {
+                        break;
+                    }
+                    known += 1;
+                }
+                headers.drain(..known);
+                let Some(first) = headers.first() else {
+                    // Still short of the fork; look further on.
+                    if self.fork_search.is_some() {
+                        self.fork_search = Some(start + known as u64);
+                    }
+                    return Ok(());
+                };
+                let (head, height) = chain_tip(&self.blockchain);
+                let extends_head = first.previous_hash == head && first.height == height;
+                // The known prefix may end on a side branch rather than on
+                // the chain.
+                let parent_height = match self.side_blocks.get(&first.previous_hash) {
+                    Some(parent) => Some(parent.header.height),
+                    None => match self.blockchain.get_block(first.previous_hash).await {
+                        Ok(Some(parent)) => Some(parent.header.height),
+                        _ => None,
+                    },
+                };
+                let parent = match parent_height {
+                    Some(parent_height) if parent_height + 1 == first.height => {
+                        (first.previous_hash, first.height)
+                    }
+                    _ if extends_head => (head, height),
+                    _ => {
+                        let floor = self
+                            .blockchain
+                            .latest_finalized()
+                            .await?
+                            .map_or(0, |(_, height)| height);
+                        if start <= floor {
+                            return Err(
+                                "Header chain does not include our finalized chain".to_string()
+                            );
+                        }
+                        self.fork_search = Some(start.saturating_sub(HEADERS_BATCH).max(floor));
+                        return Ok(());
+                    }
+                };
+                self.fork_search = None;
+                parent
+            }
+        };
+        for header in &headers {
+            if header.previous_hash != previous {
+                return Err("Header does not follow the one before it".to_string());
+            }
+            if header.height != next_height {
+                return Err("Header height is out of sequence".to_string());
+            }
+            if self.conflicts_with_checkpoint(header) {
+                return Err("Header chain conflicts with the checkpoint".to_string());
+            }
+            previous = header.hash();
+            next_height += 1;
+        }
+        debug!(height = next_height - 1, "extended header chain");
+        self.headers.extend(headers);
+        Ok(())
+    }
+
+    async fn on_blocks(&mut self, peer: SocketAddr, blocks: Vec<Block>) {
+        let Some(request) = self.body_requests.remove(&peer) else {
+            return;
+        };
+
+        // Anything the peer left out is asked of someone else. It no longer
+        // has those blocks, perhaps having moved to another branch, so it
+        // is not asked again until it reports its height anew.
+        if blocks.len() < request.heights.len() {
+            self.peer_heights.remove(&peer);
+        }
+        for (height, block) in request.heights.into_iter().zip(blocks) {
+            let Some(header) = self
+                .headers
+                .front()
+                .and_then(|first| height.checked_sub(first.height))
+                .and_then(|index| self.headers.get(index as usize))
+            else {
+                continue;
+            };
+            // Bodies must belong to the headers that were asked for.
+            if block.header != *header
+                || block.header.merkle_root != compute_merkle_root(&block.transactions)
+            {
+                warn!(%peer, "peer sent a block that does not match its header");
+                self.peer_heights.remove(&peer);
+                break;
+            }
+            self.downloaded.insert(height, block);
+        }
+
+        self.import_downloaded(peer).await;
+        self.maybe_request().await;
+    }
+
+    /// Whether `header` is at the checkpoint's height but is not the
+    /// checkpointed block.
+    pub fn conflicts_with_checkpoint(&self, header: &BlockHeader) -> bool {
+        self.checkpoint
+            .is_some_and(|(height, hash)| header.height == height && header.hash() != hash)
+    }
+
+    /// Whether the block at `height` is vouched for by the checkpoint, which
+    /// is only once the header chain has been checked against it.
+    fn below_checkpoint(&self, height: u64) -> bool {
+        self.checkpoint.is_some_and(|(checkpoint, _)| {
+            height <= checkpoint
+                && self
+                    .headers
+                    .back()
+                    .is_some_and(|header| header.height >= checkpoint)
+        })
+    }
+
+    /// Applies the downloaded blocks that follow on from the chain tip.
+    /// Those past the checkpoint are checked in parallel while the ones
+    /// before them are applied.
+    async fn import_downloaded(&mut self, peer: SocketAddr) {
+        let mut ready = Vec::new();
+        while let Some(entry) = self.downloaded.first_entry()
+            && self
+                .headers
+                .get(ready.len())
+                .is_some_and(|header| header.height == *entry.key())
+        {
+            ready.push(entry.remove());
+        }
+        let mut seen = self.seen.lock().await;
+        for block in &ready {
+            seen.insert(block.hash());
+        }
+        drop(seen);
+
+        let unchecked = ready
+            .split_off(ready.partition_point(|block| self.below_checkpoint(block.header.height)));
+        let mut imported = 0;
+        let mut result = Ok(());
+        for block in ready {
+            result = self.import(block).await;
+            if result.is_err() {
+                break;
+            }
+            imported += 1;
+        }
+        if result.is_ok() {
+            let mut verified = verify_blocks(unchecked);
+            while let Some(block) = verified.recv().await {
+                result = match block {
+                    Ok(block) => self.import_verified(block).await,
+                    Err(e) => Err(e),
+                };
+                if result.is_err() {
+                    break;
+                }
+                imported += 1;
+            }
+        }
+        self.headers.drain(..imported);
+
+        if let Err(e) = result {
+            // The headers led to a block the chain will not take, so
+            // start again from whoever is ahead.
+            warn!(%peer, error = %e, "sync stopped at an invalid block");
+            self.peer_heights.remove(&peer);
+            self.headers.clear();
+            self.downloaded.clear();
+            self.body_requests.clear();
+        }
+        if imported > 0 {
+            let (_, height) = chain_tip(&self.blockchain);
+            info!(%peer, imported, height, "synced blocks");
+        }
+    }
+
+    /// Takes a block announced by a peer: applies it if it extends the
+    /// head, otherwise keeps it on a side branch and reorganizes onto that
+    /// branch if it is now the longest.
+    pub async fn accept_block(&mut self, block: Block) -> Result<(), String> {
+        self.import(block).await?;
+        self.maybe_request().await;
+        Ok(())
+    }
+
+    /// Imports a block whose context-free checks passed.
+    async fn import_verified(&mut self, block: VerifiedBlock) -> Result<(), String> {
+        let (head, next_height) = chain_tip(&self.blockchain);
+        if block.block().header.previous_hash == head && block.block().header.height == next_height
+        {
+            return self.blockchain.add_verified_block(block).await;
+        }
+        self.import(block.into_inner()).await
+    }
+
+    async fn import(&mut self, block: Block) -> Result<(), String> {
+        let (head, next_height) = chain_tip(&self.blockchain);
+        if block.header.previous_hash == head && block.header.height == next_height {
+            return if self.below_checkpoint(block.header.height) {
+                self.blockchain.add_checkpointed_block(block).await
+            } else {
+                self.blockchain.add_block(block).await
+            };
+        }
+
+        let hash = block.hash();
+        if self.has_block(hash).await {
+            return Ok(());
+        }
+        if self.conflicts_with_checkpoint(&block.header) {
+            return Err("Block conflicts with the checkpoint".to_string());
+        }
+        let parent_height = match self.side_blocks.get(&block.header.previous_hash) {
+            Some(parent) => Some(parent.header.height),
+            None => self
+                .blockchain
+                .get_block(block.header.previous_hash)
+                .await
+                .map_err(|_| "DB error".to_string())?
+                .map(|parent| parent.header.height),
+        };
+        if parent_height.map(|height| height + 1) != Some(block.header.height) {
+            return Err("Block does not continue a known block".to_string());
+        }
+        block.verify()?;
+        self.prune_side_blocks().await?;
+        if self.side_blocks.len() >= MAX_SIDE_BLOCKS {
+            return Err("Too many blocks off the main chain".to_string());
+        }
+
+        let height = block.header.height;
+        self.side_blocks.insert(hash, block);
+        // Ties keep the current head.
+        if height >= next_height {
+            self.reorganize(hash).await?;
+        }
+        Ok(())
+    }
+
+    /// Switches the chain to the side branch ending at `tip`.
+    async fn reorganize(&mut self, tip: Hash) -> Result<(), String> {
+        let mut branch = Vec::new();
+        let mut fork_point = tip;
+        while let Some(block) = self.side_blocks.get(&fork_point) {
+            fork_point = block.header.previous_hash;
+            branch.push(block.clone());
+        }
+        branch.reverse();
+
+        let fork_height = branch[0].header.height.checked_sub(1);
+        let finalized = self.blockchain.latest_finalized().await?;
+        if let Some((_, finalized_height)) = finalized
+            && fork_height.is_none_or(|height| height < finalized_height)
+        {
+            return Err("Branch would undo a finalized block".to_string());
+        }
+
+        let mut seen = self.seen.lock().await;
+        for block in &branch {
+            seen.insert(block.hash());
+        }
+        drop(seen);
+
+        let hashes: Vec<Hash> = branch.iter().map(Block::hash).collect();
+        let result = self.blockchain.reorganize(fork_point, branch).await;
+        for hash in hashes {
+            self.side_blocks.remove(&hash);
+        }
+        // The old chain becomes a side branch, should it come back.
+        for block in result? {
+            if self.side_blocks.len() < MAX_SIDE_BLOCKS {
+                self.side_blocks.insert(block.hash(), block);
+            }
+        }
+        Ok(())
+    }
+
+    /// Drops side blocks that could only win by undoing a finalized block.
+    async fn prune_side_blocks(&mut self) -> Result<(), String> {
+        if let Some((_, finalized_height)) = self.blockchain.latest_finalized().await? {
+            self.side_blocks
+                .retain(|_, block| block.header.height > finalized_height);
+        }
+        Ok(())
+    }
+
+    /// Asks the best peer for its latest snapshot, unless one is already
+    /// on its way.
+    async fn request_snapshot(&mut self) {
+        if self.snapshot.is_some() {
+            return;
+        }
+        let Some((&peer, _)) = self
+            .peer_heights
+            .iter()
+            .max_by_key(|(_, peer_height)| **peer_height)
+        else {
+            return;
+        };
+
+        debug!(%peer, "requesting state snapshot");
+        self.snapshot = Some(SnapshotDownload {
+            peer,
+            sent: Instant::now(),
+            hash: None,
+            height: 0,
+            total: 0,
+            accounts: Vec::new(),
+            received: 0,
+        });
+        let request = Message::GetSnapshot {
+            hash: None,
+            index: 0,
+        };
+        self.p2p.send_to(peer, request).await;
+    }
+
+    async fn on_snapshot_chunk(
+        &mut self,
+        peer: SocketAddr,
+        hash: Hash,
+        height: u64,
+        index: u32,
+        total: u32,
+        accounts: Vec<SnapshotAccount>,
+    ) {
+        let Some(download) = self.snapshot.as_mut().filter(|d| d.peer == peer) else {
+            return;
+        };
+        if total == 0 {
+            // The peer has moved on from the snapshot we were fetching.
+            debug!(%peer, "peer has no snapshot for us");
+            self.abandon_snapshot(false);
+            return;
+        }
+
+        let last = download.accounts.last().map(|account| account.user.address);
+        let ordered = accounts
+            .iter()
+            .map(|account| Some(account.user.address))
+            .try_fold(last, |previous, address| {
+                (address > previous).then_some(address)
+            })
+            .is_some();
+        let consistent = download.hash.is_none_or(|expected| expected == hash)
+            && (download.total == 0 || download.total == total)
+            && index == download.received
+            && index < total
+            && accounts.len() <= SNAPSHOT_CHUNK;
+        if !ordered || !consistent {
+            warn!(%peer, "peer sent a malformed snapshot chunk");
+            self.abandon_snapshot(true);
+            return;
+        }
+
+        download.hash = Some(hash);
+        download.height = height;
+        download.total = total;
+        download.accounts.extend(accounts);
+        download.received += 1;
+        download.sent = Instant::now();
+        if !download.is_complete() {
+            let request = Message::GetSnapshot {
+                hash: Some(hash),
+                index: download.received,
+            };
+            self.p2p.send_to(peer, request).await;
+            return;
+        }
+        self.maybe_request().await;
+    }
+
+    /// Installs a fully downloaded snapshot once the header chain reaches
+    /// the block it was taken at.
+    async fn install_snapshot(&mut self) {
+        let Some(download) = self.snapshot.as_ref().filter(|d| d.is_complete()) else {
+            return;
+        };
+        let Some(end) = self
+            .headers
+            .iter()
+            .position(|header| header.height == download.height)
+        else {
+            let behind = self
+                .headers
+                .back()
+                .is_none_or(|header| header.height < download.height);
+            if !behind {
+                self.abandon_snapshot(true);
+            }
+            return;
+        };
+        let headers: Vec<BlockHeader> = self.headers.range(..=end).cloned().collect();
+        if Some(headers[end].hash()) != download.hash || headers[0].height != 0 {
+            warn!(peer = %download.peer, "snapshot is not from our header chain");
+            self.abandon_snapshot(true);
+            return;
+        }
+
+        match self
+            .blockchain
+            .install_snapshot(&headers, &download.accounts)
+            .await
+        {
+            Ok(()) => {
+                self.snapshot = None;
+                self.snapshot_distance = None;
+            }
+            Err(e) => {
+                warn!(peer = %download.peer, error = %e, "rejected state snapshot");
+                self.abandon_snapshot(true);
+            }
+        }
+    }
+
+    /// Drops the snapshot download, and the peer serving it if it
+    /// misbehaved. Enough failures and sync falls back to blocks.
+    fn abandon_snapshot(&mut self, penalize: bool) {
+        let Some(download) = self.snapshot.take() else {
+            return;
+        };
+        if penalize {
+            self.peer_heights.remove(&download.peer);
+        }
+        self.snapshot_failures += 1;
+        if self.snapshot_failures >= SNAPSHOT_ATTEMPTS {
+            warn!("giving up on snapshot sync, replaying blocks instead");
+            self.snapshot_distance = None;
+        }
+    }
+
+    /// Chunk `index` of the snapshot at `hash`, or at our latest block.
+    /// Snapshots are only taken of the latest block, and the last one is
+    /// kept until a peer asks for a newer one.
+    async fn serve_snapshot(&mut self, hash: Option<Hash>, index: u32) -> Message {
+        let head = self.blockchain.chain_head().get().map(|(head, _)| head);
+        let wanted = hash.or(head).unwrap_or_default();
+        let cached = self
+            .served_snapshot
+            .as_ref()
+            .is_some_and(|(header, _)| header.hash() == wanted);
+        if !cached
+            && head == Some(wanted)
+            && let Ok(Some(snapshot)) = self.blockchain.snapshot().await
+        {
+            self.served_snapshot = Some(snapshot);
+        }
+
+        let unavailable = Message::SnapshotChunk {
+            hash: wanted,
+            height: 0,
+            index,
+            total: 0,
+            accounts: Vec::new(),
+        };
+        let Some((header, accounts)) = &self.served_snapshot else {
+            return unavailable;
+        };
+        if header.hash() != wanted {
+            return unavailable;
+        }
+        let mut chunks = accounts.chunks(SNAPSHOT_CHUNK);
+        let total = chunks.len().max(1) as u32;
+        if index >= total {
+            return unavailable;
+        }
+        Message::SnapshotChunk {
+            hash: wanted,
+            height: header.height,
+            index,
+            total,
+            accounts: chunks.nth(index as usize).unwrap_or_default().to_vec(),
+        }
+    }
+
+    async fn serve_headers(&self, from_height: u64, count: u64) -> Vec<BlockHeader> {
+        let count = count.min(HEADERS_BATCH);
+        let headers = self
+            .blockchain
+            .get_headers_range(
+                from_height,
+                from_height.saturating_add(count),
+                count as usize,
+            )
+            .await
+            .unwrap_or_default();
+        // Stop at the first gap, as peers expect a contiguous run.
+        headers
+            .into_iter()
+            .enumerate()
+            .take_while(|(i, header)| header.height == from_height + *i as u64)
+            .map(|(_, header)| header)
+            .collect()
+    }
+
+    async fn serve_proofs(
+        &self,
+        addresses: &[Address],
+        from_height: u64,
+        to_height: u64,
+    ) -> Message {
+        let nothing = Message::TransactionProofs {
+            proofs: Vec::new(),
+            scanned_to: from_height,
+        };
+        if addresses.len() > PROOF_ADDRESSES {
+            return nothing;
+        }
+        match self
+            .blockchain
+            .transaction_proofs(addresses, from_height, to_height, PROOFS_BATCH)
+            .await
+        {
+            Ok((proofs, scanned_to)) => Message::TransactionProofs { proofs, scanned_to },
+            Err(e) => {
+                warn!(error = %e, "could not prove transactions");
+                nothing
+            }
+        }
+    }
+
+    async fn serve_blocks(&self, hashes: &[Hash]) -> Vec<Block> {
+        let mut blocks = Vec::new();
+        let mut size = 0;
+        for hash in hashes.iter().take(SYNC_BATCH as usize) {
+            let Ok(Some(block)) = self.blockchain.get_full_block(*hash).await else {
+                break;
+            };
+            // Blocks from before a snapshot came without their transactions.
+            if block.header.merkle_root != compute_merkle_root(&block.transactions) {
+                break;
+            }
+            size += block.transactions.iter().map(|tx| tx.size()).sum::<usize>();
+            // Always answer with at least one block so sync makes progress.
+            if size > BLOCKS_RESPONSE_BUDGET && !blocks.is_empty() {
+                break;
+            }
+            blocks.push(block);
+        }
+        blocks
+    }
+}
+
+/// Hash of the latest block and the height the next one will have.
+pub fn chain_tip(blockchain: &Blockchain) -> (Hash, u64) {
+    blockchain.chain_head().tip()
+}
@@ -0,0 +1,61 @@
+//! Checks on downloaded blocks ahead of applying them. Blocks arrive
+//! decoded from the peer tasks; here their context-free checks run off the
+//! sync task with every transaction signature spread across the rayon
+//! pool, and the blocks come back in order over a channel, so one batch is
+//! applied while the next is still being checked.
+
+use crate::blockchain::{Block, Transaction};
+use rayon::prelude::*;
+use tokio::sync::mpsc;
+
+/// Blocks checked together before any of them is handed over.
+const VERIFY_BATCH: usize = 16;
+
+/// A block that passed [`Block::verify`], so applying it only needs the
+/// checks that depend on the chain.
+#[derive(Clone, Debug)]
+pub struct VerifiedBlock(Block);
+
+impl VerifiedBlock {
+    /// Runs [`Block::verify`], checking the transaction signatures in
+    /// parallel.
+    pub fn new(block: Block) -> Result<Self, String> {
+        if !block.transactions.par_iter().all(Transaction::verify) {
+            return Err("Invalid transaction in block".to_string());
+        }
+        block.verify_contents()?;
+        Ok(VerifiedBlock(block))
+    }
+
+    pub fn block(&self) -> &Block {
+        &self.0
+    }
+
+    pub fn into_inner(self) -> Block {
+        self.0
+    }
+}
+
+/// Checks `blocks` in the background, yielding each in order as a
+/// [`VerifiedBlock`] or the reason it failed. Nothing follows a failed
+/// block, and checking stops once the receiver is dropped.
+pub fn verify_blocks(blocks: Vec<Block>) -> mpsc::Receiver<Result<VerifiedBlock, String>> {
+    let (sender, receiver) = mpsc::channel(VERIFY_BATCH);
+    tokio::task::spawn_blocking(move || {
+        let mut blocks = blocks.into_iter();
+        loop {
+            let batch: Vec<Block> = blocks.by_ref().take(VERIFY_BATCH).collect();
+            if batch.is_empty() {
+                return;
+            }
+            let checked: Vec<_> = batch.into_par_iter().map(VerifiedBlock::new).collect();
+            for result in checked {
+                let failed = result.is_err();
+                if sender.blocking_send(result).is_err() || failed {
+                    return;
+                }
+            }
+        }
+    });
+    receiver
+}
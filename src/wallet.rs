@@ -0,0 +1,225 @@
+//! Accounts kept by the `client` binary, each in a keyfile of its own
+//! whose signing key is encrypted under a password, so transactions are
+//! signed on the client's machine and the node never sees a key.
+//!
+//! A keyfile is JSON holding the account's address in the clear and its
+//! key sealed with ChaCha20-Poly1305 under a key derived from the password
+//! with Argon2id.
+
+use crate::blockchain::Address;
+use argon2::{Argon2, Params};
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use ed25519_dalek::SigningKey;
+use rand::RngCore;
+use rand::rngs::OsRng;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+
+/// Keyfile layout written by this version.
+const KEYFILE_VERSION: u32 = 1;
+
+/// Directory wallets are kept in unless given another.
+pub fn default_wallet_dir() -> PathBuf {
+    crate::db::default_data_dir().join("wallet")
+}
+
+/// The wallet password, from `SMVBLOCK_WALLET_PASSWORD` or asked for on
+/// the terminal, twice when a new keyfile is being written.
+pub fn read_password(confirm: bool) -> Result<String, String> {
+    if let Ok(password) = std::env::var("SMVBLOCK_WALLET_PASSWORD") {
+        return Ok(password);
+    }
+    let password = rpassword::prompt_password("Wallet password: ").map_err(|e| e.to_string())?;
+    if confirm {
+        let again = rpassword::prompt_password("Repeat password: ").map_err(|e| e.to_string())?;
+        if again != password {
+            return Err("Passwords do not match".to_string());
+        }
+    }
+    Ok(password)
+}
+
+/// The address of the account `key` signs for.
+pub fn key_address(key: &SigningKey) -> Address {
+    Sha256::digest(key.verifying_key().to_bytes()).into()
+}
+
+#[derive(Deserialize, Serialize)]
+struct Keyfile {
+    version: u32,
+    address: String,
+    kdf: KdfParams,
+    nonce: String,
+    ciphertext: String,
+}
+
+/// Argon2id settings a keyfile was sealed with, kept so files stay
+/// readable if the defaults change.
+#[derive(Deserialize, Serialize)]
+struct KdfParams {
+    salt: String,
+    memory_kib: u32,
+    iterations: u32,
+    parallelism: u32,
+}
+
+impl KdfParams {
+    fn generate() -> Self {
+        let mut salt = [0u8; 16];
+        OsRng.fill_bytes(&mut salt);
+        KdfParams {
+            salt: hex::encode(salt),
+            memory_kib: Params::DEFAULT_M_COST,
+            iterations: Params::DEFAULT_T_COST,
+            parallelism: Params::DEFAULT_P_COST,
+        }
+    }
+
+    fn derive(&self, password: &str) -> Result<Key, String> {
+        let salt = hex::decode(&self.salt).map_err(|e| format!("Invalid salt: {}", e))?;
+        let params = Params::new(self.memory_kib, self.iterations, self.parallelism, Some(32))
+            .map_err(|e| format!("Invalid key derivation settings: {}", e))?;
+        let mut key = Key::default();
+        Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params)
+            .hash_password_into(password.as_bytes(), &salt, &mut key)
+            .map_err(|e| format!("Failed to derive key: {}", e))?;
+        Ok(key)
+    }
+}
+
+/// A directory of keyfiles, one per named account.
+#[derive(Clone, Debug)]
+pub struct Wallet {
+    dir: PathBuf,
+}
+
+impl Wallet {
+    pub fn open(dir: impl Into<PathBuf>) -> Self {
+        Wallet { dir: dir.into() }
+    }
+
+    /// Generates a new account under `name`, returning its address.
+    pub fn create(&self, name: &str, password: &str) -> Result<Address, String> {
+        self.import(name, &SigningKey::generate(&mut OsRng), password)
+    }
+
+    /// Stores `key` under `name`. An account already under that name is
+    /// never overwritten.
+    pub fn import(&self, name: &str, key: &SigningKey, password: &str) -> Result<Address, String> {
+        let path = self.keyfile_path(name)?;
+        let address = key_address(key);
+        let kdf = KdfParams::generate();
+        let mut nonce = [0u8; 12];
+        OsRng.fill_bytes(&mut nonce);
+        let ciphertext = ChaCha20Poly1305::new(&kdf.derive(password)?)
+            .encrypt(Nonce::from_slice(&nonce), key.to_bytes().as_slice())
+            .map_err(|_| "Failed to encrypt key".to_string())?;
+        let keyfile = Keyfile {
+            version: KEYFILE_VERSION,
+            address: hex::encode(address),
+            kdf,
+            nonce: hex::encode(nonce),
+            ciphertext: hex::encode(ciphertext),
+        };
+
+        fs::create_dir_all(&self.dir)
+            .map_err(|e| format!("Failed to create {}: {}", self.dir.display(), e))?;
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut file = options.open(&path).map_err(|e| match e.kind() {
+            std::io::ErrorKind::AlreadyExists => format!("Wallet {} already exists", name),
+            _ => format!("Failed to create {}: {}", path.display(), e),
+        })?;
+        let json = serde_json::to_vec_pretty(&keyfile).map_err(|e| e.to_string())?;
+        file.write_all(&json)
+            .and_then(|_| file.sync_all())
+            .map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+        Ok(address)
+    }
+
+    /// Every account in the wallet by name, in name order.
+    pub fn list(&self) -> Result<Vec<(String, Address)>, String> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(format!("Failed to read {}: {}", self.dir.display(), e)),
+        };
+        let mut accounts = Vec::new();
+        for entry in entries {
+            let path = entry.map_err(|e| e.to_string())?.path();
+            if path.extension().is_none_or(|ext| ext != "json") {
+                continue;
+            }
+            let Some(name) = path.file_stem().and_then(|stem| stem.to_str()) else {
+                continue;
+            };
+            accounts.push((name.to_string(), self.address(name)?));
+        }
+        accounts.sort();
+        Ok(accounts)
+    }
+
+    /// The address of the account under `name`, which needs no password.
+    pub fn address(&self, name: &str) -> Result<Address, String> {
+        let keyfile = self.read(name)?;
+        crate::client::parse_hash(&keyfile.address)
+    }
+
+    /// Decrypts the signing key of the account under `name`.
+    pub fn unlock(&self, name: &str, password: &str) -> Result<SigningKey, String> {
+        let keyfile = self.read(name)?;
+        let nonce: [u8; 12] = hex::decode(&keyfile.nonce)
+            .ok()
+            .and_then(|nonce| nonce.try_into().ok())
+            .ok_or(format!("Wallet {} has an invalid nonce", name))?;
+        let ciphertext = hex::decode(&keyfile.ciphertext)
+            .map_err(|e| format!("Wallet {} is corrupt: {}", name, e))?;
+        let plaintext = ChaCha20Poly1305::new(&keyfile.kdf.derive(password)?)
+            .decrypt(Nonce::from_slice(&nonce), ciphertext.as_slice())
+            .map_err(|_| format!("Wrong password for wallet {}", name))?;
+        let bytes: [u8; 32] = plaintext
+            .try_into()
+            .map_err(|_| format!("Wallet {} does not hold a 32-byte key", name))?;
+        let key = SigningKey::from_bytes(&bytes);
+        if hex::encode(key_address(&key)) != keyfile.address {
+            return Err(format!("Wallet {} key does not match its address", name));
+        }
+        Ok(key)
+    }
+
+    fn read(&self, name: &str) -> Result<Keyfile, String> {
+        let path = self.keyfile_path(name)?;
+        let json = fs::read(&path).map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => format!("No wallet named {}", name),
+            _ => format!("Failed to read {}: {}", path.display(), e),
+        })?;
+        let keyfile: Keyfile = serde_json::from_slice(&json)
+            .map_err(|e| format!("Invalid keyfile {}: {}", path.display(), e))?;
+        if keyfile.version != KEYFILE_VERSION {
+            return Err(format!(
+                "Wallet {} has unsupported keyfile version {}",
+                name, keyfile.version
+            ));
+        }
+        Ok(keyfile)
+    }
+
+    /// Names become file names, so only plain ones are accepted.
+    fn keyfile_path(&self, name: &str) -> Result<PathBuf, String> {
+        let valid = !name.is_empty()
+            && !name.starts_with('.')
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+        if !valid {
+            return Err(format!("Invalid wallet name: {:?}", name));
+        }
+        Ok(self.dir.join(format!("{}.json", name)))
+    }
+}
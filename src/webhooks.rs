@@ -0,0 +1,255 @@
+//! Push notifications for watched addresses. An operator registers an
+//! address with a callback URL, and whenever a block applied on top of the
+//! chain sends funds from it or to it, or the address is slashed, the node
+//! POSTs a JSON notification there:
+//!
+//! ```json
+//! {"id": "…", "webhook": 1, "event": "received", "address": "…",
+//!  "height": 12, "block_hash": "…", "transaction": {…}}
+//! ```
+//!
+//! Each body is signed with the webhook's secret as HMAC-SHA256 in the
+//! [`SIGNATURE_HEADER`] header, `sha256=` and the hex digest, so receivers
+//! can tell the node sent it. Deliveries that fail or get a non-2xx answer
+//! are retried with backoff, carrying the same `id`. Deliveries are
+//! not ordered, and a block later undone by a reorganization is not
+//! retracted, so receivers wanting certainty should wait for the block's
+//! confirmations.
+
+use crate::amount::Amount;
+use crate::blockchain::{Address, Block, Blockchain};
+use crate::events::NodeEvent;
+use crate::p2p::Backoff;
+use hmac::{Hmac, Mac};
+use serde_json::{Value, json};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::time::Duration;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::sync::watch;
+use tracing::{debug, warn};
+
+/// Header carrying a notification's signature.
+pub const SIGNATURE_HEADER: &str = "x-smvblock-signature";
+
+/// A callback URL told about activity on one address.
+#[derive(Clone, PartialEq)]
+pub struct Webhook {
+    pub id: u64,
+    pub address: Address,
+    pub url: String,
+    /// Key the notifications are signed with.
+    pub secret: [u8; 32],
+}
+
+/// Keeps the secret out of logs.
+impl fmt::Debug for Webhook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Webhook")
+            .field("id", &self.id)
+            .field("address", &hex::encode(self.address))
+            .field("url", &self.url)
+            .finish_non_exhaustive()
+    }
+}
+
+/// How notifications are delivered.
+#[derive(Clone, Debug)]
+pub struct WebhookConfig {
+    /// Tries per notification before it is dropped.
+    pub attempts: u32,
+    /// How long one try may take.
+    pub timeout: Duration,
+    /// Wait between tries.
+    pub backoff: Backoff,
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        WebhookConfig {
+            attempts: 8,
+            timeout: Duration::from_secs(10),
+            backoff: Backoff::default(),
+        }
+    }
+}
+
+/// Checks `url` is one notifications can be POSTed to.
+pub fn check_url(url: &str) -> Result<(), String> {
+    let parsed = reqwest::Url::parse(url).map_err(|e| format!("Invalid URL {:?}: {}", url, e))?;
+    match parsed.scheme() {
+        "http" | "https" if parsed.host().is_some() => Ok(()),
+        _ => Err(format!("Expected an http or https URL, got {:?}", url)),
+    }
+}
+
+/// The signature header value for `body` under `secret`.
+pub fn sign(secret: &[u8; 32], body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC takes keys of any size");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// Delivers notifications for `chain`'s events until `shutdown` turns
+/// true.
+pub(crate) fn spawn(
+    chain: Blockchain,
+    mut events: broadcast::Receiver<NodeEvent>,
+    mut shutdown: watch::Receiver<bool>,
+    config: WebhookConfig,
+) {
+    tokio::spawn(async move {
+        let client = reqwest::Client::new();
+        loop {
+            let event = tokio::select! {
+                event = events.recv() => event,
+                _ = shutdown.changed() => break,
+            };
+            let event = match event {
+                Ok(event) => event,
+                Err(RecvError::Lagged(missed)) => {
+                    warn!(missed, "webhooks fell behind the node's events");
+                    continue;
+                }
+                Err(RecvError::Closed) => break,
+            };
+            let notifications = match notifications(&chain, &event).await {
+                Ok(notifications) => notifications,
+                Err(e) => {
+                    warn!(error = %e, "failed to look up webhooks");
+                    continue;
+                }
+            };
+            for (webhook, payload) in notifications {
+                let client = client.clone();
+                let config = config.clone();
+                tokio::spawn(async move { deliver(&client, &webhook, payload, &config).await });
+            }
+        }
+    });
+}
+
+/// The notifications `event` calls for, each with the webhook it goes to.
+async fn notifications(
+    chain: &Blockchain,
+    event: &NodeEvent,
+) -> Result<Vec<(Webhook, Value)>, String> {
+    match event {
+        NodeEvent::BlockApplied(block) => {
+            let mut addresses: Vec<Address> = block
+                .transactions
+                .iter()
+                .flat_map(|tx| [tx.sender_address(), tx.payload.receiver])
+                .collect();
+            addresses.sort();
+            addresses.dedup();
+            let webhooks = chain.webhooks_for(&addresses).await?;
+            Ok(block_notifications(block, &webhooks))
+        }
+        NodeEvent::ValidatorSlashed {
+            address,
+            penalty,
+            stake,
+        } => {
+            let webhooks = chain.webhooks_for(&[*address]).await?;
+            Ok(webhooks
+                .into_iter()
+                .map(|webhook| {
+                    let payload = slash_payload(&webhook, *penalty, *stake);
+                    (webhook, payload)
+                })
+                .collect())
+        }
+        _ => Ok(Vec::new()),
+    }
+}
+
+fn block_notifications(block: &Block, webhooks: &[Webhook]) -> Vec<(Webhook, Value)> {
+    let mut notifications = Vec::new();
+    for tx in &block.transactions {
+        let sender = tx.sender_address();
+        for webhook in webhooks {
+            let event = if webhook.address == sender {
+                "sent"
+            } else if webhook.address == tx.payload.receiver {
+                "received"
+            } else {
+                continue;
+            };
+            let payload = json!({
+                "id": delivery_id(webhook, event, &tx.hash()),
+                "webhook": webhook.id,
+                "event": event,
+                "address": hex::encode(webhook.address),
+                "height": block.header.height,
+                "block_hash": hex::encode(block.hash()),
+                "transaction": crate::rpc::transaction_json(tx),
+            });
+            notifications.push((webhook.clone(), payload));
+        }
+    }
+    notifications
+}
+
+fn slash_payload(webhook: &Webhook, penalty: Amount, stake: Amount) -> Value {
+    json!({
+        // Nothing identifies a slashing, so any id unique to it will do.
+        "id": hex::encode(rand::random::<[u8; 32]>()),
+        "webhook": webhook.id,
+        "event": "slashed",
+        "address": hex::encode(webhook.address),
+        "penalty": penalty.to_string(),
+        "stake": stake.to_string(),
+    })
+}
+
+/// Derived from what the notification is about, so a block applied again
+/// after a reorganization gives the same id as before.
+fn delivery_id(webhook: &Webhook, event: &str, subject: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(webhook.id.to_be_bytes());
+    hasher.update(event.as_bytes());
+    hasher.update(subject);
+    hex::encode(hasher.finalize())
+}
+
+/// POSTs `payload` to the webhook until it answers 2xx or the tries run
+/// out.
+async fn deliver(
+    client: &reqwest::Client,
+    webhook: &Webhook,
+    payload: Value,
+    config: &WebhookConfig,
+) {
+    let body = payload.to_string();
+    let signature = sign(&webhook.secret, body.as_bytes());
+    for attempt in 1..=config.attempts {
+        let response = client
+            .post(&webhook.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header(SIGNATURE_HEADER, &signature)
+            .body(body.clone())
+            .timeout(config.timeout)
+            .send()
+            .await;
+        let error = match response {
+            Ok(response) if response.status().is_success() => {
+                debug!(webhook = webhook.id, "delivered notification");
+                return;
+            }
+            Ok(response) => format!("answered {}", response.status()),
+            Err(e) => e.to_string(),
+        };
+        debug!(webhook = webhook.id, attempt, error = %error, "webhook delivery failed");
+        if attempt < config.attempts {
+            tokio::time::sleep(config.backoff.delay(attempt)).await;
+        } else {
+            warn!(
+                webhook = webhook.id,
+                url = %webhook.url,
+                error = %error,
+                "gave up delivering a notification"
+            );
+        }
+    }
+}
@@ -0,0 +1,188 @@
+use serde_json::{Value, json};
+use smvblock::{
+    access::{AccessError, CallLimiter, CallerKey, RateLimit, Role, RpcAccess, Token, sign_jwt},
+    amount::Amount,
+    blockchain::User,
+    client::RpcClient,
+    error::RpcError,
+    node::{Node, NodeType},
+};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+/// The raw response to an HTTP request, headers included.
+async fn request(addr: SocketAddr, request: String) -> String {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    response
+}
+
+fn rejected_code(result: Result<Value, RpcError>) -> i64 {
+    match result {
+        Err(RpcError::Rejected { code, .. }) => code,
+        other => panic!("expected a rejection, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_methods_need_their_role() {
+    let access = RpcAccess {
+        tokens: vec!["wallet:letmein".parse().unwrap()],
+        jwt_secret: Some(b"secret".to_vec()),
+        ..RpcAccess::default()
+    };
+    let remote: IpAddr = "203.0.113.7".parse().unwrap();
+    assert_eq!(access.authenticate(None, remote), Ok(Role::Public));
+    assert_eq!(
+        access.authenticate(Some("letmein"), remote),
+        Ok(Role::Wallet)
+    );
+    assert!(access.authenticate(Some("wrong"), remote).is_err());
+
+    let admin = sign_jwt(b"secret", Role::Admin, None);
+    assert_eq!(access.authenticate(Some(&admin), remote), Ok(Role::Admin));
+    let expired = sign_jwt(b"secret", Role::Admin, Some(1));
+    assert!(access.authenticate(Some(&expired), remote).is_err());
+    let forged = sign_jwt(b"other", Role::Admin, None);
+    assert!(access.authenticate(Some(&forged), remote).is_err());
+
+    assert!(access.authorize(Role::Public, "chain_getStatus").is_ok());
+    assert!(access.authorize(Role::Public, "tx_submit").is_err());
+    assert!(access.authorize(Role::Wallet, "tx_submit").is_ok());
+    assert!(access.authorize(Role::Wallet, "admin_peerStats").is_err());
+    assert!(format!("{:?}", access.tokens).contains("redacted"));
+    assert!("nobody:x".parse::<Token>().is_err());
+
+    // Without credentials configured, only local callers get past public.
+    let open = RpcAccess::default();
+    assert_eq!(open.authenticate(None, remote), Ok(Role::Public));
+    let loopback = [
+        IpAddr::V4(Ipv4Addr::LOCALHOST),
+        IpAddr::V6(Ipv6Addr::LOCALHOST),
+    ];
+    for local in loopback {
+        assert_eq!(open.authenticate(None, local), Ok(Role::Admin));
+    }
+}
+
+#[tokio::test]
+async fn test_listeners_serve_their_roles_and_check_credentials() {
+    let node = Node::new(NodeType::FullNode, true).unwrap();
+    let (user, _) = User::generate(Amount::from_smv(10));
+    node.add_user(user.clone()).await.unwrap();
+
+    let access = RpcAccess {
+        tokens: vec!["admin:s3cret".parse().unwrap()],
+        cors_origins: vec!["https://wallet.example".to_string()],
+        ..RpcAccess::default()
+    };
+    let public = node
+        .start_rpc_with(
+            "127.0.0.1:0".parse().unwrap(),
+            RpcAccess {
+                roles: vec![Role::Public],
+                ..access.clone()
+            },
+        )
+        .await
+        .unwrap();
+    let private = node
+        .start_rpc_with("127.0.0.1:0".parse().unwrap(), access)
+        .await
+        .unwrap();
+
+    let anonymous = RpcClient::new(public);
+    anonymous.status().await.unwrap();
+    let peers = anonymous.call("peers_list", json!([])).await;
+    assert_eq!(rejected_code(peers), -32601);
+
+    let peers = RpcClient::new(private).call("peers_list", json!([])).await;
+    assert_eq!(rejected_code(peers), -32030);
+    let admin = RpcClient::new(private).with_token("s3cret".to_string());
+    admin.call("peers_list", json!([])).await.unwrap();
+    let wrong = RpcClient::new(private).with_token("guess".to_string());
+    assert_eq!(rejected_code(wrong.status().await), -32030);
+
+    let preflight = request(
+        public,
+        format!(
+            "OPTIONS / HTTP/1.1\r\nHost: {}\r\nOrigin: https://wallet.example\r\nAccess-Control-Request-Method: POST\r\nConnection: close\r\n\r\n",
+            public
+        ),
+    )
+    .await
+    .to_lowercase();
+    assert!(
+        preflight.contains("access-control-allow-origin: https://wallet.example"),
+        "{}",
+        preflight
+    );
+}
+
+#[test]
+fn test_method_lists_and_call_limits() {
+    let access = RpcAccess {
+        allowed_methods: vec!["chain_*".to_string(), "tx_submit".to_string()],
+        denied_methods: vec!["chain_getHeaders".to_string()],
+        ..RpcAccess::default()
+    };
+    assert!(access.authorize(Role::Admin, "chain_getStatus").is_ok());
+    assert!(access.authorize(Role::Admin, "tx_submit").is_ok());
+    assert_eq!(
+        access.authorize(Role::Admin, "chain_getHeaders"),
+        Err(AccessError::Filtered)
+    );
+    assert_eq!(
+        access.authorize(Role::Admin, "peers_list"),
+        Err(AccessError::Filtered)
+    );
+
+    let limiter = CallLimiter::new(RateLimit {
+        per_second: 0.001,
+        burst: 2.0,
+    });
+    let ip = CallerKey::Ip("10.0.0.1".parse().unwrap());
+    assert!(limiter.allow(&ip));
+    assert!(limiter.allow(&ip));
+    assert!(!limiter.allow(&ip));
+    // Other callers have budgets of their own.
+    assert!(limiter.allow(&CallerKey::Ip("10.0.0.2".parse().unwrap())));
+    assert!(limiter.allow(&CallerKey::credential("s3cret")));
+}
+
+#[tokio::test]
+async fn test_callers_are_limited_by_address_or_credential() {
+    let node = Node::new(NodeType::FullNode, true).unwrap();
+    let addr = node
+        .start_rpc_with(
+            "127.0.0.1:0".parse().unwrap(),
+            RpcAccess {
+                tokens: vec!["wallet:s3cret".parse().unwrap()],
+                denied_methods: vec!["tx_submitRaw".to_string()],
+                rate_limit: Some(RateLimit {
+                    per_second: 0.001,
+                    burst: 3.0,
+                }),
+                ..RpcAccess::default()
+            },
+        )
+        .await
+        .unwrap();
+
+    let anonymous = RpcClient::new(addr);
+    let raw = anonymous.call("tx_submitRaw", json!(["00"])).await;
+    assert_eq!(rejected_code(raw), -32601);
+    anonymous.status().await.unwrap();
+    anonymous.status().await.unwrap();
+    assert_eq!(rejected_code(anonymous.status().await), -32031);
+
+    // A credential brings its own budget, even from the same address.
+    let wallet = RpcClient::new(addr).with_token("s3cret".to_string());
+    for _ in 0..3 {
+        wallet.status().await.unwrap();
+    }
+    assert_eq!(rejected_code(wallet.status().await), -32031);
+}
@@ -0,0 +1,38 @@
+use smvblock::{amount::Amount, error::BlockchainError};
+
+#[test]
+fn test_amount_display_and_parse_round_trip() {
+    let amount: Amount = "1.25 SMV".parse().unwrap();
+    assert_eq!(amount.base_units(), 125_000_000);
+    assert_eq!(amount.to_string(), "1.25 SMV");
+
+    assert_eq!("3".parse::<Amount>().unwrap(), Amount::from_smv(3));
+    assert_eq!(Amount::from_smv(3).to_string(), "3 SMV");
+    assert_eq!(Amount::from_base_units(1).to_string(), "0.00000001 SMV");
+}
+
+#[test]
+fn test_amount_rejects_malformed_input() {
+    assert!("".parse::<Amount>().is_err());
+    assert!("1.2.3".parse::<Amount>().is_err());
+    assert!("-1".parse::<Amount>().is_err());
+    assert!("0.000000001".parse::<Amount>().is_err());
+    assert!("1 ETH".parse::<Amount>().is_err());
+}
+
+#[test]
+fn test_amount_arithmetic_is_checked() {
+    let max = Amount::from_base_units(u128::MAX);
+    assert_eq!(
+        max.checked_add(Amount::from_base_units(1)),
+        Err(BlockchainError::Overflow)
+    );
+    assert_eq!(
+        Amount::ZERO.checked_sub(Amount::from_base_units(1)),
+        Err(BlockchainError::Overflow)
+    );
+    assert_eq!(
+        Amount::checked_sum([Amount::from_smv(1), Amount::from_smv(2)]),
+        Ok(Amount::from_smv(3))
+    );
+}
@@ -0,0 +1,203 @@
+use ed25519_dalek::SigningKey;
+use smvblock::{
+    amount::Amount,
+    beacon::{self, Commit},
+    blockchain::{Transaction, Transfer, TxData, TxKind, User},
+    error::BlockchainError,
+    node::{Node, NodeType},
+    params::{Beacon, ChainParams, Liveness},
+    wallet,
+};
+
+const EPOCH_LENGTH: u64 = 4;
+
+fn beacon_tx(key: &SigningKey, kind: TxKind, epoch: u64, nonce: u64) -> Transaction {
+    let address = wallet::key_address(key);
+    let secret = beacon::secret(key, epoch);
+    let data = match kind {
+        TxKind::Commit => TxData::Commit(Commit {
+            commitment: beacon::commitment(&address, &secret),
+            epoch,
+        }),
+        _ => TxData::Secret(secret),
+    };
+    Transfer {
+        receiver: address,
+        amount: Amount::ZERO,
+        fee: Amount::ZERO,
+        nonce,
+        kind,
+        data,
+    }
+    .into_transaction(key)
+}
+
+/// A node with four-block epochs slashing a tenth of the stake for a
+/// missed reveal, whose only validator stakes 60 SMV, past genesis.
+async fn node() -> (Node, (User, SigningKey)) {
+    let mut node = Node::new(NodeType::FullNode, true).unwrap();
+    node.blockchain.set_chain_params(ChainParams {
+        liveness: Liveness {
+            epoch_length: EPOCH_LENGTH,
+            ..Liveness::default()
+        },
+        beacon: Beacon { penalty_bps: 1_000 },
+        ..ChainParams::default()
+    });
+    let (validator, validator_key) = User::generate(Amount::from_smv(100));
+    node.add_user(validator.clone()).await.unwrap();
+    node.stake(validator.address, Amount::from_smv(60))
+        .await
+        .unwrap();
+    node.produce_block().await.unwrap();
+    (node, (validator, validator_key))
+}
+
+/// Produces blocks until the next one is the first of `epoch`.
+async fn produce_until(node: &mut Node, epoch: u64) {
+    while node.blockchain.chain_head().get().unwrap().1 + 1 < epoch * EPOCH_LENGTH {
+        node.produce_block().await.unwrap();
+    }
+}
+
+async fn stake(node: &Node, user: &User) -> Amount {
+    let account = node.blockchain.get_user(&user.address).await.unwrap();
+    account.unwrap().stake
+}
+
+#[test]
+fn test_commitments_and_seeds() {
+    let commit = Commit {
+        commitment: [7; 32],
+        epoch: 3,
+    };
+    let data = TxData::Commit(commit);
+    assert_eq!(TxData::from_bytes(&data.to_bytes()), Ok(data));
+    assert_eq!(data.kind(), Some(TxKind::Commit));
+    assert_eq!("commit".parse(), Ok(TxKind::Commit));
+    assert_eq!("reveal".parse(), Ok(TxKind::Reveal));
+
+    assert_eq!(beacon::epoch(7, 4), 1);
+    assert_eq!(beacon::epoch_heights(1, 4), 4..8);
+
+    // Secrets are worked out again rather than kept, and differ by epoch.
+    let (_, key) = User::generate(Amount::ZERO);
+    assert_eq!(beacon::secret(&key, 2), beacon::secret(&key, 2));
+    assert_ne!(beacon::secret(&key, 2), beacon::secret(&key, 3));
+
+    // Every secret revealed changes the seed, whatever order it came in.
+    let secrets = [[1; 32], [2; 32]];
+    assert_ne!(beacon::seed(2, &secrets), beacon::seed(2, &secrets[..1]));
+    assert_eq!(
+        beacon::seed(2, &secrets),
+        beacon::seed(2, &[secrets[1], secrets[0]])
+    );
+    assert_ne!(beacon::seed(2, &secrets), beacon::seed(3, &secrets));
+}
+
+#[tokio::test]
+async fn test_revealed_secrets_seed_the_epoch_after() {
+    let (mut node, (validator, validator_key)) = node().await;
+    node.blockchain
+        .add_transaction(beacon_tx(&validator_key, TxKind::Commit, 0, 0))
+        .await
+        .unwrap();
+    node.produce_block().await.unwrap();
+    produce_until(&mut node, 1).await;
+
+    let secret = beacon::secret(&validator_key, 0);
+    node.blockchain
+        .add_transaction(beacon_tx(&validator_key, TxKind::Reveal, 0, 1))
+        .await
+        .unwrap();
+    node.produce_block().await.unwrap();
+    produce_until(&mut node, 2).await;
+
+    assert_eq!(
+        node.blockchain.beacon(2).await.unwrap(),
+        beacon::seed(2, &[secret])
+    );
+    assert_eq!(
+        node.blockchain.beacon(3).await.unwrap(),
+        beacon::seed(3, &[])
+    );
+    // Revealing kept the stake whole.
+    assert_eq!(stake(&node, &validator).await, Amount::from_smv(60));
+    assert!(
+        node.blockchain
+            .verify_chain(false)
+            .await
+            .unwrap()
+            .problems
+            .is_empty()
+    );
+}
+
+#[tokio::test]
+async fn test_withheld_reveal_is_slashed() {
+    let (mut node, (validator, validator_key)) = node().await;
+    node.blockchain
+        .add_transaction(beacon_tx(&validator_key, TxKind::Commit, 0, 0))
+        .await
+        .unwrap();
+    node.produce_block().await.unwrap();
+    produce_until(&mut node, 2).await;
+    assert_eq!(stake(&node, &validator).await, Amount::from_smv(54));
+}
+
+#[tokio::test]
+async fn test_beacon_rules_are_enforced() {
+    let (mut node, (_, validator_key)) = node().await;
+    let (holder, holder_key) = User::generate(Amount::from_smv(10));
+    node.add_user(holder.clone()).await.unwrap();
+    let refused = |result: Result<(), BlockchainError>| {
+        assert!(matches!(result, Err(BlockchainError::Beacon(_))));
+    };
+
+    // Not a validator, for the wrong epoch, and nothing to reveal.
+    refused(
+        node.blockchain
+            .add_transaction(beacon_tx(&holder_key, TxKind::Commit, 0, 0))
+            .await,
+    );
+    refused(
+        node.blockchain
+            .add_transaction(beacon_tx(&validator_key, TxKind::Commit, 1, 0))
+            .await,
+    );
+    refused(
+        node.blockchain
+            .add_transaction(beacon_tx(&validator_key, TxKind::Reveal, 0, 0))
+            .await,
+    );
+
+    node.blockchain
+        .add_transaction(beacon_tx(&validator_key, TxKind::Commit, 0, 0))
+        .await
+        .unwrap();
+    node.produce_block().await.unwrap();
+    // Committed once an epoch only.
+    refused(
+        node.blockchain
+            .add_transaction(beacon_tx(&validator_key, TxKind::Commit, 0, 1))
+            .await,
+    );
+
+    produce_until(&mut node, 1).await;
+    // The secret must be the one committed to.
+    refused(
+        node.blockchain
+            .add_transaction(beacon_tx(&validator_key, TxKind::Reveal, 1, 1))
+            .await,
+    );
+    node.blockchain
+        .add_transaction(beacon_tx(&validator_key, TxKind::Reveal, 0, 1))
+        .await
+        .unwrap();
+    node.produce_block().await.unwrap();
+    refused(
+        node.blockchain
+            .add_transaction(beacon_tx(&validator_key, TxKind::Reveal, 0, 2))
+            .await,
+    );
+}
@@ -1,31 +1,47 @@
-use hex;
 use smvblock::{
-    blockchain::User,
+    amount::Amount,
+    blockchain::{
+        Block, BlockHeader, MAX_BLOCK_BYTES, MAX_FUTURE_DRIFT_SECS, Transaction, Transfer, TxData,
+        TxKind, User, compute_merkle_root, merkle_proof, verify_merkle_proof,
+    },
+    db::Database,
+    error::BlockchainError,
+    liveness,
+    mempool::MempoolConfig,
+    monetary::MonetaryPolicy,
     node::{Node, NodeType},
+    params::ChainParams,
+    signer::BlockSigner,
+    verify::{VerifiedBlock, verify_blocks},
 };
+use std::time::Duration;
 
 #[tokio::test]
 async fn test_basic_flow_transaction_and_block() {
     let mut node = Node::new(NodeType::FullNode, true).unwrap();
 
-    let (user1, user1_pk) = User::generate(100);
-    let (user2, _) = User::generate(100);
+    let (user1, user1_pk) = User::generate(Amount::from_smv(100));
+    let (user2, _) = User::generate(Amount::from_smv(100));
 
     node.add_user(user1.clone()).await.unwrap();
     node.add_user(user2.clone()).await.unwrap();
 
-    node.stake(user1.address, 30).await.unwrap();
-    node.stake(user2.address, 20).await.unwrap();
+    node.stake(user1.address, Amount::from_smv(30))
+        .await
+        .unwrap();
+    node.stake(user2.address, Amount::from_smv(20))
+        .await
+        .unwrap();
 
     let users = node.get_users().await.unwrap();
     assert_eq!(users.len(), 2);
 
     let u1 = users.iter().find(|u| u.address == user1.address).unwrap();
     let u2 = users.iter().find(|u| u.address == user2.address).unwrap();
-    assert_eq!(u1.stake, 30);
-    assert_eq!(u2.stake, 20);
+    assert_eq!(u1.stake, Amount::from_smv(30));
+    assert_eq!(u2.stake, Amount::from_smv(20));
 
-    node.send_transaction(user1_pk.clone(), user2.address, 20)
+    node.send_transaction(user1_pk.clone(), user2.address, Amount::from_smv(20))
         .await
         .unwrap();
 
@@ -36,29 +52,40 @@ async fn test_basic_flow_transaction_and_block() {
     let u1 = users.iter().find(|u| u.address == user1.address).unwrap();
     let u2 = users.iter().find(|u| u.address == user2.address).unwrap();
 
-    assert_eq!(u1.balance + u1.stake + u2.balance + u2.stake, 200 + 1);
+    assert_eq!(
+        Amount::checked_sum([u1.balance, u1.stake, u2.balance, u2.stake]).unwrap(),
+        Amount::from_smv(200)
+            .checked_add(MonetaryPolicy::default().scheduled_reward(0, Amount::ZERO))
+            .unwrap()
+    );
 }
 
 #[tokio::test]
 async fn test_transaction_exceeding_balance_fails() {
     let node = Node::new(NodeType::FullNode, true).unwrap();
 
-    let (user1, pk1) = User::generate(100);
-    let (user2, _) = User::generate(100);
+    let (user1, pk1) = User::generate(Amount::from_smv(100));
+    let (user2, _) = User::generate(Amount::from_smv(100));
 
     node.add_user(user1.clone()).await.unwrap();
     node.add_user(user2.clone()).await.unwrap();
 
-    node.stake(user1.address, 80).await.unwrap();
+    node.stake(user1.address, Amount::from_smv(80))
+        .await
+        .unwrap();
 
-    let result = node.send_transaction(pk1.clone(), user2.address, 30).await;
+    let result = node
+        .send_transaction(pk1.clone(), user2.address, Amount::from_smv(30))
+        .await;
 
     assert!(
         result.is_err(),
         "Transaction should fail due to insufficient balance"
     );
 
-    let result_ok = node.send_transaction(pk1, user2.address, 15).await;
+    let result_ok = node
+        .send_transaction(pk1, user2.address, Amount::from_smv(15))
+        .await;
     assert!(
         result_ok.is_ok(),
         "Transaction with valid balance should succeed"
@@ -69,10 +96,669 @@ async fn test_transaction_exceeding_balance_fails() {
 async fn test_produce_block_with_no_transactions() {
     let mut node = Node::new(NodeType::FullNode, true).unwrap();
 
-    let (user, _) = User::generate(100);
+    let (user, _) = User::generate(Amount::from_smv(100));
     node.add_user(user.clone()).await.unwrap();
-    node.stake(user.address, 50).await.unwrap();
+    node.stake(user.address, Amount::from_smv(50))
+        .await
+        .unwrap();
 
     let block_hash = node.produce_block().await.unwrap();
     assert_ne!(block_hash, [0u8; 32]); // still produces a block
 }
+
+#[tokio::test]
+async fn test_block_hash_commits_to_header_only() {
+    let mut node = Node::new(NodeType::FullNode, true).unwrap();
+
+    let (user, pk) = User::generate(Amount::from_smv(100));
+    let (receiver, _) = User::generate(Amount::ZERO);
+    node.add_user(user.clone()).await.unwrap();
+    node.add_user(receiver.clone()).await.unwrap();
+    node.stake(user.address, Amount::from_smv(50))
+        .await
+        .unwrap();
+    node.send_transaction(pk, receiver.address, Amount::from_smv(10))
+        .await
+        .unwrap();
+
+    let block_hash = node.produce_block().await.unwrap();
+
+    // Stored blocks come back without their transactions but keep their hash.
+    let stored = node
+        .blockchain
+        .get_block(block_hash)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(stored.transactions.is_empty());
+    assert_eq!(stored.hash(), block_hash);
+    assert_eq!(stored.header.proposer, user.address);
+    assert_eq!(
+        stored.header.canonical_bytes().len(),
+        BlockHeader::ENCODED_LEN
+    );
+}
+
+#[tokio::test]
+async fn test_block_timestamp_rules() {
+    let mut node = Node::new(NodeType::FullNode, true).unwrap();
+
+    let (user, _) = User::generate(Amount::from_smv(100));
+    node.add_user(user.clone()).await.unwrap();
+    node.stake(user.address, Amount::from_smv(50))
+        .await
+        .unwrap();
+
+    for _ in 0..3 {
+        node.produce_block().await.unwrap();
+    }
+
+    let latest = node.blockchain.get_latest_block().await.unwrap().unwrap();
+    let median = node.blockchain.median_time_past().await.unwrap().unwrap();
+
+    let mut stale = Block::new(
+        latest.hash(),
+        latest.header.height + 1,
+        user.address,
+        vec![],
+    );
+    stale.header.timestamp = median;
+    assert!(node.blockchain.add_block(stale).await.is_err());
+
+    let mut future = Block::new(
+        latest.hash(),
+        latest.header.height + 1,
+        user.address,
+        vec![],
+    );
+    future.header.timestamp += MAX_FUTURE_DRIFT_SECS + 10;
+    assert!(future.verify().is_err());
+}
+
+#[tokio::test]
+async fn test_balance_overflow_is_rejected() {
+    let node = Node::new(NodeType::FullNode, true).unwrap();
+
+    let (user, _) = User::generate(Amount::from_base_units(u128::MAX));
+    node.add_user(user.clone()).await.unwrap();
+
+    let result = node
+        .reward_validator(user.address, Amount::from_smv(1))
+        .await;
+    assert_eq!(result, Err(BlockchainError::Overflow.to_string()));
+
+    let users = node.get_users().await.unwrap();
+    assert_eq!(users[0].balance, Amount::from_base_units(u128::MAX));
+}
+
+#[tokio::test]
+async fn test_failed_block_leaves_state_untouched() {
+    let mut node = Node::new(NodeType::FullNode, true).unwrap();
+
+    let (sender, key) = User::generate(Amount::from_smv(100));
+    let (receiver, _) = User::generate(Amount::ZERO);
+    node.add_user(sender.clone()).await.unwrap();
+    node.add_user(receiver.clone()).await.unwrap();
+
+    // The first transfer alone would succeed; the second overdraws.
+    let transactions = (0..2)
+        .map(|nonce| {
+            Transfer {
+                receiver: receiver.address,
+                amount: Amount::from_smv(60),
+                fee: Amount::ZERO,
+                nonce,
+                kind: TxKind::Transfer,
+                data: TxData::None,
+            }
+            .into_transaction(&key)
+        })
+        .collect();
+    let block = Block::new([0u8; 32], 0, sender.address, transactions);
+
+    assert!(node.blockchain.add_block(block).await.is_err());
+
+    let users = node.get_users().await.unwrap();
+    let s = users.iter().find(|u| u.address == sender.address).unwrap();
+    let r = users
+        .iter()
+        .find(|u| u.address == receiver.address)
+        .unwrap();
+    assert_eq!(s.balance, Amount::from_smv(100));
+    assert_eq!(r.balance, Amount::ZERO);
+    assert!(node.blockchain.get_blocks().await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_excessive_coinbase_is_rejected() {
+    let mut node = Node::new(NodeType::FullNode, true).unwrap();
+
+    let (user, key) = User::generate(Amount::from_smv(100));
+    node.add_user(user.clone()).await.unwrap();
+    node.stake(user.address, Amount::from_smv(50))
+        .await
+        .unwrap();
+
+    let mut block = Block::new([0u8; 32], 0, user.address, vec![]);
+    block.header.coinbase = node
+        .blockchain
+        .block_reward(0)
+        .await
+        .unwrap()
+        .checked_add(Amount::from_base_units(1))
+        .unwrap();
+    block.header.state_root = node.blockchain.state_root_after(&block).await.unwrap();
+    block.sign(&key);
+
+    let result = node.blockchain.add_block(block).await;
+    assert_eq!(
+        result,
+        Err("Coinbase exceeds the allowed block reward".to_string())
+    );
+}
+
+#[tokio::test]
+async fn test_rejected_reorganization_restores_the_chain() {
+    let mut node = Node::new(NodeType::FullNode, true).unwrap();
+
+    let (user, key) = User::generate(Amount::from_smv(100));
+    node.add_user(user.clone()).await.unwrap();
+    node.stake(user.address, Amount::from_smv(50))
+        .await
+        .unwrap();
+
+    let fork_point = node.produce_block().await.unwrap();
+    let head = node.produce_block().await.unwrap();
+    let state_root = node.blockchain.state_root().await.unwrap();
+
+    // A competing block whose state root does not match its execution.
+    let parent = node
+        .blockchain
+        .get_block(fork_point)
+        .await
+        .unwrap()
+        .unwrap();
+    let mut block = Block::new(fork_point, 1, user.address, vec![]);
+    block.header.timestamp = parent.header.timestamp + 1;
+    block.header.state_root = [1u8; 32];
+    block.sign(&key);
+    let result = node.blockchain.reorganize(fork_point, vec![block]).await;
+    assert_eq!(
+        result,
+        Err("State root does not match block execution".to_string())
+    );
+
+    let latest = node.blockchain.get_latest_block().await.unwrap().unwrap();
+    assert_eq!(latest.hash(), head);
+    assert_eq!(node.blockchain.chain_head().get(), Some((head, 1)));
+    assert_eq!(node.blockchain.state_root().await.unwrap(), state_root);
+}
+
+#[tokio::test]
+async fn test_chain_head_follows_the_stored_chain() {
+    let path = std::env::temp_dir().join(format!("smvblock-head-{}.db", rand::random::<u64>()));
+    let db = Database::new(path.to_str(), false).unwrap();
+    let mut node = Node::with_database(NodeType::FullNode, db);
+    let (user, _) = User::generate(Amount::from_smv(100));
+    node.add_user(user.clone()).await.unwrap();
+    node.stake(user.address, Amount::from_smv(50))
+        .await
+        .unwrap();
+    assert_eq!(node.blockchain.chain_head().get(), None);
+
+    let mut changes = node.blockchain.chain_head().subscribe();
+    node.produce_block().await.unwrap();
+    let head = node.produce_block().await.unwrap();
+    assert!(changes.has_changed().unwrap());
+    assert_eq!(*changes.borrow_and_update(), Some((head, 1)));
+    drop(node);
+
+    // Reopening reads the head back from the database.
+    let db = Database::new(path.to_str(), false).unwrap();
+    let node = Node::with_database(NodeType::FullNode, db);
+    assert_eq!(node.blockchain.chain_head().get(), Some((head, 1)));
+
+    drop(node);
+    for suffix in ["", "-wal", "-shm"] {
+        let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+    }
+}
+
+#[tokio::test]
+async fn test_validator_proposes_signed_blocks() {
+    let mut node = Node::new(NodeType::FullNode, true).unwrap();
+    node.blockchain.set_chain_params(ChainParams {
+        block_time: Duration::from_millis(50),
+        ..ChainParams::default()
+    });
+
+    let (validator, key) = User::generate(Amount::from_smv(100));
+    node.add_user(validator.clone()).await.unwrap();
+    node.stake(validator.address, Amount::from_smv(50))
+        .await
+        .unwrap();
+    node.start_validator(BlockSigner::Local(key));
+
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(5);
+    let block = loop {
+        if let Some(block) = node.blockchain.get_latest_block().await.unwrap() {
+            break block;
+        }
+        assert!(tokio::time::Instant::now() < deadline, "no block produced");
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    };
+    node.shutdown().await.unwrap();
+
+    // The only staker is the proposer of every slot.
+    assert_eq!(block.header.proposer, validator.address);
+    assert!(block.header.verify_signature(&validator.public_key));
+
+    let (_, impostor) = User::generate(Amount::ZERO);
+    let (_, height) = smvblock::sync::chain_tip(&node.blockchain);
+    let mut forged = Block::new(block.hash(), height, validator.address, vec![]);
+    forged.sign(&impostor);
+    assert_eq!(
+        node.blockchain.clone().add_block(forged).await,
+        Err("Invalid proposer signature".to_string())
+    );
+}
+
+#[tokio::test]
+async fn test_blocks_must_be_signed_by_the_slot_proposer() {
+    let mut node = Node::new(NodeType::FullNode, true).unwrap();
+    let mut validators = Vec::new();
+    for stake in [60, 40] {
+        let (user, key) = User::generate(Amount::from_smv(100));
+        node.add_user(user.clone()).await.unwrap();
+        node.stake(user.address, Amount::from_smv(stake))
+            .await
+            .unwrap();
+        validators.push((user, key));
+    }
+    let timestamp = chrono::Utc::now().timestamp() - 60;
+    let slot = liveness::slot(timestamp, node.blockchain.block_time(0).await.unwrap());
+    let picked = node
+        .blockchain
+        .slot_proposer([0; 32], 0, slot)
+        .await
+        .unwrap();
+    let (proposer, key) = validators
+        .iter()
+        .find(|(user, _)| user.address == picked)
+        .unwrap();
+    let (other, other_key) = validators
+        .iter()
+        .find(|(user, _)| user.address != picked)
+        .unwrap();
+    let block = |proposer: &User| {
+        let mut block = Block::new([0; 32], 0, proposer.address, vec![]);
+        block.header.timestamp = timestamp;
+        block
+    };
+
+    let mut wrong_slot = block(other);
+    wrong_slot.header.state_root = node.blockchain.state_root_after(&wrong_slot).await.unwrap();
+    wrong_slot.sign(other_key);
+    assert_eq!(
+        node.blockchain.add_block(wrong_slot).await,
+        Err("Proposer was not picked for the block's slot".to_string())
+    );
+
+    let mut block = block(proposer);
+    block.header.state_root = node.blockchain.state_root_after(&block).await.unwrap();
+    assert_eq!(
+        node.blockchain.add_block(block.clone()).await,
+        Err("Block is not signed by its proposer".to_string())
+    );
+    block.sign(key);
+    node.blockchain.add_block(block).await.unwrap();
+
+    // Only blocks produced by hand on this node go unsigned.
+    node.produce_block().await.unwrap();
+    assert_eq!(node.blockchain.get_blocks().await.unwrap().len(), 2);
+}
+
+#[test]
+fn test_merkle_proofs_show_a_transaction_is_in_a_block() {
+    let (_, key) = User::generate(Amount::ZERO);
+    let transactions: Vec<Transaction> = (0..5)
+        .map(|nonce| {
+            Transfer {
+                receiver: [nonce as u8; 32],
+                amount: Amount::from_smv(1),
+                fee: Amount::ZERO,
+                nonce,
+                kind: TxKind::Transfer,
+                data: TxData::None,
+            }
+            .into_transaction(&key)
+        })
+        .collect();
+
+    for count in 1..=transactions.len() {
+        let block = &transactions[..count];
+        let root = compute_merkle_root(block);
+        let count = count as u32;
+        for (index, tx) in block.iter().enumerate() {
+            let index = index as u32;
+            let proof = merkle_proof(block, index as usize);
+            assert!(verify_merkle_proof(tx, index, count, &proof, &root));
+            let other = &transactions[(index as usize + 1) % transactions.len()];
+            assert!(!verify_merkle_proof(other, index, count, &proof, &root));
+            if count > 1 {
+                assert!(!verify_merkle_proof(tx, index ^ 1, count, &proof, &root));
+            }
+            // Nor can a proof claim a block of another size.
+            assert!(!verify_merkle_proof(tx, index, count + 1, &proof, &root));
+        }
+    }
+}
+
+#[test]
+fn test_repeated_transactions_change_the_merkle_root_and_are_refused() {
+    let (user, key) = User::generate(Amount::ZERO);
+    let transactions: Vec<Transaction> = (0..3)
+        .map(|nonce| {
+            Transfer {
+                receiver: [nonce as u8; 32],
+                amount: Amount::from_smv(1),
+                fee: Amount::ZERO,
+                nonce,
+                kind: TxKind::Transfer,
+                data: TxData::None,
+            }
+            .into_transaction(&key)
+        })
+        .collect();
+    let mut repeated = transactions.clone();
+    repeated.push(transactions[2].clone());
+    assert_ne!(
+        compute_merkle_root(&transactions),
+        compute_merkle_root(&repeated)
+    );
+
+    let block = Block::new([0; 32], 1, user.address, repeated);
+    assert_eq!(
+        block.verify(),
+        Err("Block includes a transaction twice".to_string())
+    );
+}
+
+#[tokio::test]
+async fn test_replayed_transactions_are_refused_when_blocks_are_applied() {
+    let mut node = Node::new(NodeType::FullNode, true).unwrap();
+    let (sender, key) = User::generate(Amount::from_smv(100));
+    let (receiver, _) = User::generate(Amount::ZERO);
+    node.add_user(sender.clone()).await.unwrap();
+    node.add_user(receiver.clone()).await.unwrap();
+    node.stake(sender.address, Amount::from_smv(50))
+        .await
+        .unwrap();
+    let tx = Transfer {
+        receiver: receiver.address,
+        amount: Amount::from_smv(10),
+        fee: Amount::ZERO,
+        nonce: 0,
+        kind: TxKind::Transfer,
+        data: TxData::None,
+    }
+    .into_transaction(&key);
+
+    // Twice in one block, even past the checks on its contents.
+    let block = Block::new([0; 32], 0, sender.address, vec![tx.clone(), tx.clone()]);
+    assert_eq!(
+        node.blockchain.execute_block(&block).await.map(|_| ()),
+        Err("Block includes a transaction twice".to_string())
+    );
+
+    // Again in a later block, once confirmed.
+    node.blockchain.add_transaction(tx.clone()).await.unwrap();
+    let first = node.produce_block().await.unwrap();
+    let mut block = Block::new(first, 1, sender.address, vec![tx.clone()]);
+    block.header.timestamp += 1;
+    assert_eq!(
+        node.blockchain.execute_block(&block).await.map(|_| ()),
+        Err(format!(
+            "Transaction {} is already confirmed",
+            hex::encode(tx.hash())
+        ))
+    );
+    assert!(node.blockchain.add_block(block).await.is_err());
+
+    // A transaction signed again with a spent nonce hashes differently but
+    // is refused all the same.
+    let reused = Transfer {
+        receiver: receiver.address,
+        amount: Amount::from_smv(20),
+        fee: Amount::ZERO,
+        nonce: 0,
+        kind: TxKind::Transfer,
+        data: TxData::None,
+    }
+    .into_transaction(&key);
+    let block = Block::new(first, 1, sender.address, vec![reused]);
+    assert_eq!(
+        node.blockchain.execute_block(&block).await.map(|_| ()),
+        Err("Invalid nonce: expected 1, got 0".to_string())
+    );
+
+    let receiver = node.blockchain.get_user(&receiver.address).await.unwrap();
+    assert_eq!(receiver.unwrap().balance, Amount::from_smv(10));
+}
+
+#[tokio::test]
+async fn test_blocks_must_extend_the_head_they_are_written_on() {
+    let mut node = Node::new(NodeType::FullNode, true).unwrap();
+    let (validator, _) = User::generate(Amount::from_smv(100));
+    node.add_user(validator.clone()).await.unwrap();
+    node.stake(validator.address, Amount::from_smv(50))
+        .await
+        .unwrap();
+    let parent = node.produce_block().await.unwrap();
+    let (_, height) = node.blockchain.chain_head().tip();
+
+    // Built on the head, which moves before the block is written.
+    let mut stale = Block::new(parent, height, validator.address, vec![]);
+    stale.header.timestamp += 30;
+    node.produce_block().await.unwrap();
+    assert_eq!(
+        node.blockchain.add_checkpointed_block(stale).await,
+        Err(BlockchainError::NotOnHead.to_string())
+    );
+}
+
+#[tokio::test]
+async fn test_blocks_are_capped_and_leave_the_rest_pending() {
+    let mut node = Node::new(NodeType::FullNode, true).unwrap();
+    let (sender, key) = User::generate(Amount::from_smv(100));
+    let (receiver, _) = User::generate(Amount::ZERO);
+    node.add_user(sender.clone()).await.unwrap();
+    node.add_user(receiver.clone()).await.unwrap();
+    node.stake(sender.address, Amount::from_smv(50))
+        .await
+        .unwrap();
+    node.blockchain
+        .set_mempool_config(MempoolConfig {
+            max_transactions: 10_000,
+            max_bytes: 4 * MAX_BLOCK_BYTES,
+            max_pending_per_sender: 10_000,
+            ..MempoolConfig::default()
+        })
+        .await;
+
+    let mut total = 0;
+    let mut nonce = 0;
+    while total <= MAX_BLOCK_BYTES {
+        let tx = Transfer {
+            receiver: receiver.address,
+            amount: Amount::from_base_units(1),
+            fee: Amount::ZERO,
+            nonce,
+            kind: TxKind::Transfer,
+            data: TxData::None,
+        }
+        .into_transaction(&key);
+        total += tx.size();
+        nonce += 1;
+        node.blockchain.add_transaction(tx).await.unwrap();
+    }
+
+    let hash = node.produce_block().await.unwrap();
+    let block = node.blockchain.get_full_block(hash).await.unwrap().unwrap();
+    let size: usize = block.transactions.iter().map(|tx| tx.size()).sum();
+    assert!(size <= MAX_BLOCK_BYTES);
+    let left = node.blockchain.pending_count().await;
+    assert!(left > 0);
+    assert_eq!(block.transactions.len() + left, nonce as usize);
+
+    node.produce_block().await.unwrap();
+    assert_eq!(node.blockchain.pending_count().await, 0);
+}
+
+#[tokio::test]
+async fn test_senders_of_the_same_payload_send_different_transactions() {
+    let mut node = Node::new(NodeType::FullNode, true).unwrap();
+    let (alice, alice_key) = User::generate(Amount::from_smv(100));
+    let (bob, bob_key) = User::generate(Amount::from_smv(100));
+    let (carol, _) = User::generate(Amount::ZERO);
+    for user in [&alice, &bob, &carol] {
+        node.add_user(user.clone()).await.unwrap();
+    }
+    node.stake(alice.address, Amount::from_smv(50))
+        .await
+        .unwrap();
+    let payload = Transfer {
+        receiver: carol.address,
+        amount: Amount::from_smv(10),
+        fee: Amount::ZERO,
+        nonce: 0,
+        kind: TxKind::Transfer,
+        data: TxData::None,
+    };
+    let from_alice = payload.clone().into_transaction(&alice_key);
+    let from_bob = payload.into_transaction(&bob_key);
+    assert_ne!(from_alice.hash(), from_bob.hash());
+
+    node.blockchain
+        .add_transaction(from_alice.clone())
+        .await
+        .unwrap();
+    node.blockchain
+        .add_transaction(from_bob.clone())
+        .await
+        .unwrap();
+    node.produce_block().await.unwrap();
+
+    assert_eq!(node.blockchain.pending_count().await, 0);
+    let carol = node.blockchain.get_user(&carol.address).await.unwrap();
+    assert_eq!(carol.unwrap().balance, Amount::from_smv(20));
+    for tx in [from_alice, from_bob] {
+        let found = node.blockchain.get_transaction(&tx.hash()).await.unwrap();
+        assert_eq!(found, Some((tx, true)));
+    }
+}
+
+#[tokio::test]
+async fn test_downloaded_blocks_are_verified_in_order_up_to_a_bad_one() {
+    let (user, key) = User::generate(Amount::ZERO);
+    let mut blocks: Vec<Block> = (0..40)
+        .map(|height| {
+            let transactions = (0..3)
+                .map(|nonce| {
+                    Transfer {
+                        receiver: [height as u8; 32],
+                        amount: Amount::from_smv(1),
+                        fee: Amount::ZERO,
+                        nonce: height * 3 + nonce,
+                        kind: TxKind::Transfer,
+                        data: TxData::None,
+                    }
+                    .into_transaction(&key)
+                })
+                .collect();
+            Block::new([0; 32], height, user.address, transactions)
+        })
+        .collect();
+    blocks[25].transactions[1].signature[0] ^= 1;
+    assert!(VerifiedBlock::new(blocks[25].clone()).is_err());
+
+    let mut verified = verify_blocks(blocks);
+    for height in 0..25 {
+        let block = verified.recv().await.unwrap().unwrap();
+        assert_eq!(block.block().header.height, height);
+    }
+    assert!(verified.recv().await.unwrap().is_err());
+    assert!(verified.recv().await.is_none());
+}
+
+#[tokio::test]
+async fn test_staking_transactions_move_funds_between_balance_and_stake() {
+    let mut node = Node::new(NodeType::FullNode, true).unwrap();
+    let (user, key) = User::generate(Amount::from_smv(100));
+    let (other, _) = User::generate(Amount::ZERO);
+    node.add_user(user.clone()).await.unwrap();
+    node.add_user(other.clone()).await.unwrap();
+    node.stake(user.address, Amount::from_smv(10))
+        .await
+        .unwrap();
+    let tx = |kind, receiver, amount, nonce| {
+        Transfer {
+            receiver,
+            amount: Amount::from_smv(amount),
+            fee: Amount::ZERO,
+            nonce,
+            kind,
+            data: TxData::None,
+        }
+        .into_transaction(&key)
+    };
+
+    let misdirected = node
+        .blockchain
+        .add_transaction(tx(TxKind::Stake, other.address, 20, 0))
+        .await
+        .unwrap_err();
+    assert_eq!(misdirected, BlockchainError::WrongReceiver(TxKind::Stake));
+    node.blockchain
+        .add_transaction(tx(TxKind::Stake, user.address, 20, 0))
+        .await
+        .unwrap();
+    // The pending stake cannot be unstaked before it is confirmed.
+    let early = node
+        .blockchain
+        .add_transaction(tx(TxKind::Unstake, user.address, 25, 1))
+        .await
+        .unwrap_err();
+    assert_eq!(early, BlockchainError::InsufficientStake);
+
+    node.produce_block().await.unwrap();
+    let staked = node
+        .blockchain
+        .get_user(&user.address)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(staked.stake, Amount::from_smv(30));
+
+    node.blockchain
+        .add_transaction(tx(TxKind::Unstake, user.address, 25, 1))
+        .await
+        .unwrap();
+    node.produce_block().await.unwrap();
+    let unstaked = node
+        .blockchain
+        .get_user(&user.address)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(unstaked.stake, Amount::from_smv(5));
+    assert_eq!(
+        unstaked.balance,
+        staked
+            .balance
+            .checked_add(Amount::from_smv(25))
+            .unwrap()
+            .checked_add(MonetaryPolicy::default().scheduled_reward(1, Amount::ZERO))
+            .unwrap()
+    );
+}
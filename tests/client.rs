@@ -0,0 +1,457 @@
+use smvblock::{
+    amount::Amount,
+    blockchain::{Transaction, Transfer, TxData, TxKind, User},
+    client::{BlockId, Payment, RpcClient, UnsignedTransfer, parse_hash},
+    contacts::Contacts,
+    error::RpcError,
+    node::{Node, NodeType},
+    wallet::{Wallet, key_address},
+};
+use std::time::Duration;
+
+#[tokio::test]
+async fn test_client_reads_balances_and_nonces_from_a_node() {
+    let mut node = Node::new(NodeType::FullNode, true).unwrap();
+    let (user, key) = User::generate(Amount::from_smv(100));
+    let (receiver, _) = User::generate(Amount::ZERO);
+    node.add_user(user.clone()).await.unwrap();
+    node.add_user(receiver.clone()).await.unwrap();
+    node.stake(user.address, Amount::from_smv(10))
+        .await
+        .unwrap();
+    let addr = node
+        .start_rpc("127.0.0.1:0".parse().unwrap())
+        .await
+        .unwrap();
+    let client: RpcClient = format!("http://{}/", addr).parse().unwrap();
+
+    let account = client.balance(&user.address).await.unwrap();
+    assert_eq!(account.balance, Amount::from_smv(90));
+    assert_eq!(account.stake, Amount::from_smv(10));
+    assert_eq!(client.nonce(&user.address).await.unwrap(), 0);
+
+    let tx = Transfer {
+        receiver: receiver.address,
+        amount: Amount::from_smv(5),
+        fee: Amount::ZERO,
+        nonce: 0,
+        kind: TxKind::Transfer,
+        data: TxData::None,
+    }
+    .into_transaction(&key);
+    node.blockchain.add_transaction(tx.clone()).await.unwrap();
+    assert_eq!(client.nonce(&user.address).await.unwrap(), 1);
+    let pending = client.transaction(&tx.hash()).await.unwrap().unwrap();
+    assert_eq!(pending["status"], "pending");
+
+    let block = node.produce_block().await.unwrap();
+    let by_height = client.block(BlockId::Height(0)).await.unwrap().unwrap();
+    assert_eq!(by_height["hash"], hex::encode(block));
+    assert_eq!(by_height["confirmations"], 1);
+    assert_eq!(by_height["transactions"][0]["hash"], hex::encode(tx.hash()));
+    let by_hash = client.block(BlockId::Hash(block)).await.unwrap();
+    assert_eq!(by_hash, Some(by_height));
+    assert!(client.block(BlockId::Height(5)).await.unwrap().is_none());
+    let confirmed = client.transaction(&tx.hash()).await.unwrap().unwrap();
+    assert_eq!(confirmed["status"], "confirmed");
+    assert_eq!(confirmed["block"]["height"], 0);
+
+    let unknown = client.balance(&[9; 32]).await.unwrap_err();
+    assert!(
+        matches!(&unknown, RpcError::Rejected { message, .. } if message.contains("Account not found")),
+        "{}",
+        unknown
+    );
+}
+
+#[tokio::test]
+async fn test_wallet_keys_are_encrypted_and_sign_transfers_locally() {
+    let dir = std::env::temp_dir().join(format!("smvblock-wallet-{}", rand::random::<u64>()));
+    let wallet = Wallet::open(&dir);
+    let address = wallet.create("alice", "hunter2").unwrap();
+    assert!(
+        wallet
+            .create("alice", "other")
+            .unwrap_err()
+            .contains("already exists")
+    );
+    assert!(wallet.create("../alice", "hunter2").is_err());
+    let (_, key) = User::generate(Amount::ZERO);
+    let imported = wallet.import("bob", &key, "swordfish").unwrap();
+    assert_eq!(imported, key_address(&key));
+    assert_eq!(
+        wallet.list().unwrap(),
+        vec![
+            ("alice".to_string(), address),
+            ("bob".to_string(), imported)
+        ]
+    );
+    let keyfile = std::fs::read_to_string(dir.join("bob.json")).unwrap();
+    assert!(!keyfile.contains(&hex::encode(key.to_bytes())));
+    let wrong = wallet.unlock("alice", "hunter3").unwrap_err();
+    assert!(wrong.contains("Wrong password"), "{}", wrong);
+    let alice = wallet.unlock("alice", "hunter2").unwrap();
+    assert_eq!(key_address(&alice), address);
+
+    let mut node = Node::new(NodeType::FullNode, true).unwrap();
+    let (receiver, _) = User::generate(Amount::ZERO);
+    node.add_user(User {
+        address,
+        public_key: alice.verifying_key().to_bytes(),
+        balance: Amount::from_smv(20),
+        stake: Amount::ZERO,
+        delegate: None,
+        commission_bps: 0,
+        missed_slots: 0,
+        jailed: false,
+    })
+    .await
+    .unwrap();
+    node.add_user(receiver.clone()).await.unwrap();
+    node.stake(address, Amount::from_smv(10)).await.unwrap();
+    let addr = node
+        .start_rpc("127.0.0.1:0".parse().unwrap())
+        .await
+        .unwrap();
+    let client = RpcClient::new(addr);
+
+    let hash = client
+        .transfer(&alice, receiver.address, Amount::from_smv(3), Amount::ZERO)
+        .await
+        .unwrap();
+    assert_eq!(client.nonce(&address).await.unwrap(), 1);
+    node.produce_block().await.unwrap();
+    let tx = client.transaction(&hash).await.unwrap().unwrap();
+    assert_eq!(tx["sender"], hex::encode(address));
+    assert_eq!(
+        client.balance(&receiver.address).await.unwrap().balance,
+        Amount::from_smv(3)
+    );
+
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+#[tokio::test]
+async fn test_transfers_built_and_signed_offline_can_be_broadcast() {
+    let mut node = Node::new(NodeType::FullNode, true).unwrap();
+    let (user, key) = User::generate(Amount::from_smv(50));
+    let (receiver, other_key) = User::generate(Amount::ZERO);
+    node.add_user(user.clone()).await.unwrap();
+    node.add_user(receiver.clone()).await.unwrap();
+    node.stake(user.address, Amount::from_smv(10))
+        .await
+        .unwrap();
+    let addr = node
+        .start_rpc("127.0.0.1:0".parse().unwrap())
+        .await
+        .unwrap();
+    let client = RpcClient::new(addr);
+
+    let unsigned = UnsignedTransfer {
+        sender: user.address,
+        transfer: Transfer {
+            receiver: receiver.address,
+            amount: Amount::from_smv(4),
+            fee: Amount::ZERO,
+            nonce: client.nonce(&user.address).await.unwrap(),
+            kind: TxKind::Transfer,
+            data: TxData::None,
+        },
+    };
+    let carried = UnsignedTransfer::from_json(&unsigned.to_json()).unwrap();
+    assert_eq!(carried, unsigned);
+    assert!(carried.clone().sign(&other_key).is_err());
+    let bytes = carried.sign(&key).unwrap().to_bytes();
+
+    let mut padded = bytes.clone();
+    padded.push(0);
+    let err = client.submit_raw(&padded).await.unwrap_err();
+    assert!(err.to_string().contains("trailing bytes"), "{}", err);
+    let hash = client.submit_raw(&bytes).await.unwrap();
+    assert_eq!(Transaction::from_bytes(&bytes).unwrap().hash(), hash);
+    node.produce_block().await.unwrap();
+    assert_eq!(
+        client.balance(&receiver.address).await.unwrap().balance,
+        Amount::from_smv(4)
+    );
+    let history = client.history(&receiver.address, 10).await.unwrap();
+    assert_eq!(history.len(), 1);
+    assert_eq!(history[0]["hash"], hex::encode(hash));
+    assert_eq!(client.status().await.unwrap()["height"], 0);
+    assert!(client.peers().await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_client_output_can_be_json_or_a_table() {
+    let mut node = Node::new(NodeType::FullNode, true).unwrap();
+    let (user, _) = User::generate(Amount::from_smv(30));
+    node.add_user(user.clone()).await.unwrap();
+    node.stake(user.address, Amount::from_smv(10))
+        .await
+        .unwrap();
+    node.produce_block().await.unwrap();
+    let addr = node
+        .start_rpc("127.0.0.1:0".parse().unwrap())
+        .await
+        .unwrap();
+    let client = |args: Vec<String>| {
+        tokio::task::spawn_blocking(move || {
+            let output = std::process::Command::new(env!("CARGO_BIN_EXE_client"))
+                .arg("--node")
+                .arg(addr.to_string())
+                .args(args)
+                .output()
+                .unwrap();
+            assert!(output.status.success(), "{:?}", output);
+            String::from_utf8(output.stdout).unwrap()
+        })
+    };
+    let address = hex::encode(user.address);
+
+    let json = client(vec![
+        "--output".into(),
+        "json".into(),
+        "balance".into(),
+        "--address".into(),
+        address.clone(),
+    ])
+    .await
+    .unwrap();
+    let account: serde_json::Value = serde_json::from_str(&json).unwrap();
+    assert_eq!(account["address"], address);
+    assert_eq!(account["stake"], Amount::from_smv(10).to_string());
+
+    let plain = client(vec!["nonce".into(), "--address".into(), address])
+        .await
+        .unwrap();
+    assert_eq!(plain, "0\n");
+
+    let table = client(vec![
+        "get-block".into(),
+        "--height".into(),
+        "0".into(),
+        "--output".into(),
+        "table".into(),
+    ])
+    .await
+    .unwrap();
+    let rows: Vec<&str> = table.lines().collect();
+    assert!(
+        rows.iter().any(|row| row.starts_with("height ")),
+        "{}",
+        table
+    );
+    assert!(rows.contains(&"transactions:"), "{}", table);
+}
+
+#[tokio::test]
+async fn test_waiting_for_confirmations_returns_once_the_block_is_deep_enough() {
+    let mut node = Node::new(NodeType::FullNode, true).unwrap();
+    let (user, key) = User::generate(Amount::from_smv(30));
+    let (receiver, _) = User::generate(Amount::ZERO);
+    node.add_user(user.clone()).await.unwrap();
+    node.add_user(receiver.clone()).await.unwrap();
+    node.stake(user.address, Amount::from_smv(10))
+        .await
+        .unwrap();
+    let addr = node
+        .start_rpc("127.0.0.1:0".parse().unwrap())
+        .await
+        .unwrap();
+    let client = RpcClient::new(addr);
+    let hash = client
+        .transfer(&key, receiver.address, Amount::from_smv(1), Amount::ZERO)
+        .await
+        .unwrap();
+
+    let timeout = Duration::from_millis(1500);
+    let err = client
+        .wait_for_confirmations(&hash, 1, timeout)
+        .await
+        .unwrap_err();
+    assert!(err.contains("Timed out"), "{}", err);
+
+    let waiter = {
+        let client = client.clone();
+        tokio::spawn(async move {
+            client
+                .wait_for_confirmations(&hash, 2, Duration::from_secs(30))
+                .await
+        })
+    };
+    let block = node.produce_block().await.unwrap();
+    node.produce_block().await.unwrap();
+    let tx = waiter.await.unwrap().unwrap();
+    assert_eq!(tx["block"]["block_hash"], hex::encode(block));
+    assert_eq!(tx["confirmations"], 2);
+}
+
+#[tokio::test]
+async fn test_client_stakes_and_lists_validators() {
+    let mut node = Node::new(NodeType::FullNode, true).unwrap();
+    let (user, key) = User::generate(Amount::from_smv(30));
+    let (other, other_key) = User::generate(Amount::from_smv(30));
+    node.add_user(user.clone()).await.unwrap();
+    node.add_user(other.clone()).await.unwrap();
+    node.stake(user.address, Amount::from_smv(10))
+        .await
+        .unwrap();
+    let addr = node
+        .start_rpc("127.0.0.1:0".parse().unwrap())
+        .await
+        .unwrap();
+    let client = RpcClient::new(addr);
+
+    let hash = client
+        .stake(&other_key, Amount::from_smv(30), Amount::ZERO)
+        .await
+        .unwrap();
+    node.produce_block().await.unwrap();
+    let tx = client.transaction(&hash).await.unwrap().unwrap();
+    assert_eq!(tx["kind"], "stake");
+
+    let validators = client.validators().await.unwrap();
+    assert_eq!(validators["total_stake"], Amount::from_smv(40).to_string());
+    let list = validators["validators"].as_array().unwrap();
+    assert_eq!(list[0]["address"], hex::encode(other.address));
+    assert_eq!(list[0]["share"], 0.75);
+    assert_eq!(list[1]["address"], hex::encode(user.address));
+
+    client
+        .unstake(&key, Amount::from_smv(10), Amount::ZERO)
+        .await
+        .unwrap();
+    node.produce_block().await.unwrap();
+    let validators = client.validators().await.unwrap();
+    assert_eq!(validators["validators"].as_array().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn test_client_sends_a_batch_of_payments_with_sequential_nonces() {
+    let node = Node::new(NodeType::FullNode, true).unwrap();
+    let (user, key) = User::generate(Amount::from_smv(20));
+    let (alice, _) = User::generate(Amount::ZERO);
+    let (bob, _) = User::generate(Amount::ZERO);
+    node.add_user(user.clone()).await.unwrap();
+    node.add_user(alice.clone()).await.unwrap();
+    node.add_user(bob.clone()).await.unwrap();
+    let addr = node
+        .start_rpc("127.0.0.1:0".parse().unwrap())
+        .await
+        .unwrap();
+    let client = RpcClient::new(addr);
+
+    let csv = format!(
+        "# payroll\nto,amount,fee\n{},5,\n{},50,0.1\n{},3,0.5\n",
+        hex::encode(alice.address),
+        hex::encode(bob.address),
+        hex::encode(bob.address)
+    );
+    let payments = Payment::parse_batch(&csv, Amount::from_smv(1), parse_hash).unwrap();
+    assert_eq!(payments.len(), 3);
+    assert_eq!(payments[0].fee, Amount::from_smv(1));
+    assert_eq!(payments[2].fee, "0.5".parse().unwrap());
+    let json = format!(
+        r#"[{{"to": "{}", "amount": "5"}}, {{"to": "{}", "amount": 3, "fee": "0.5"}}]"#,
+        hex::encode(alice.address),
+        hex::encode(bob.address)
+    );
+    let from_json = Payment::parse_batch(&json, Amount::from_smv(1), parse_hash).unwrap();
+    assert_eq!(from_json, vec![payments[0].clone(), payments[2].clone()]);
+    let bad = Payment::parse_batch("to,amount\nnot-hex,1\n", Amount::ZERO, parse_hash).unwrap_err();
+    assert!(bad.starts_with("Line 2:"), "{}", bad);
+
+    // The overdrawn payment is rejected and the one after takes its nonce.
+    let results = client.send_batch(&key, &payments).await.unwrap();
+    assert!(results[0].is_ok());
+    assert!(results[1].is_err());
+    let last = results[2].clone().unwrap();
+    let tx = client.transaction(&last).await.unwrap().unwrap();
+    assert_eq!(tx["nonce"], 1);
+    assert_eq!(client.nonce(&user.address).await.unwrap(), 2);
+}
+
+#[test]
+fn test_contacts_name_addresses_for_commands() {
+    let path = std::env::temp_dir()
+        .join(format!("smvblock-contacts-{}", rand::random::<u64>()))
+        .join("contacts.json");
+    let contacts = Contacts::open(&path);
+    assert!(contacts.list().unwrap().is_empty());
+    contacts.add("bob", [2; 32]).unwrap();
+    contacts.add("alice", [1; 32]).unwrap();
+    assert!(
+        contacts
+            .add("alice", [3; 32])
+            .unwrap_err()
+            .contains("already exists")
+    );
+    assert!(contacts.add(&hex::encode([3; 32]), [3; 32]).is_err());
+    assert!(contacts.add("a,b", [3; 32]).is_err());
+
+    let reopened = Contacts::open(&path);
+    assert_eq!(
+        reopened.list().unwrap(),
+        vec![("alice".to_string(), [1; 32]), ("bob".to_string(), [2; 32])]
+    );
+    assert_eq!(reopened.resolve("alice").unwrap(), [1; 32]);
+    assert_eq!(reopened.resolve(&hex::encode([7; 32])).unwrap(), [7; 32]);
+    assert!(reopened.resolve("carol").is_err());
+    let payments = Payment::parse_batch("to,amount\nbob,1\n", Amount::ZERO, |to| {
+        reopened.resolve(to)
+    })
+    .unwrap();
+    assert_eq!(payments[0].receiver, [2; 32]);
+
+    assert_eq!(reopened.remove("bob").unwrap(), [2; 32]);
+    assert!(reopened.remove("bob").is_err());
+    assert!(reopened.resolve("bob").is_err());
+
+    let _ = std::fs::remove_dir_all(path.parent().unwrap());
+}
+
+#[tokio::test]
+async fn test_client_fails_over_between_endpoints() {
+    let node = Node::new(NodeType::FullNode, true).unwrap();
+    let (user, key) = User::generate(Amount::from_smv(10));
+    node.add_user(user.clone()).await.unwrap();
+    let live = node
+        .start_rpc("127.0.0.1:0".parse().unwrap())
+        .await
+        .unwrap();
+    // Nothing listens on a port just given back.
+    let dead = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    // Accepts connections but never answers.
+    let silent = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let silent_addr = silent.local_addr().unwrap();
+    tokio::spawn(async move {
+        let mut held = Vec::new();
+        while let Ok((stream, _)) = silent.accept().await {
+            held.push(stream);
+        }
+    });
+
+    let client: RpcClient = format!("http://{},http://{}/", dead, live).parse().unwrap();
+    assert_eq!(
+        client.to_string(),
+        format!("http://{},http://{}", dead, live)
+    );
+    assert_eq!(client.nonce(&user.address).await.unwrap(), 0);
+
+    // Queries move on from a node that goes quiet; submissions do not, as
+    // the quiet node may have taken them.
+    let client =
+        RpcClient::with_endpoints(vec![silent_addr, live]).with_timeout(Duration::from_millis(200));
+    assert_eq!(client.nonce(&user.address).await.unwrap(), 0);
+    let err = client
+        .transfer(&key, user.address, Amount::from_smv(1), Amount::ZERO)
+        .await
+        .unwrap_err();
+    assert!(matches!(err, RpcError::Unreachable(_)), "{}", err);
+
+    let down = RpcClient::new(dead).balance(&user.address).await;
+    assert!(matches!(down, Err(RpcError::Unreachable(_))), "{:?}", down);
+}
@@ -0,0 +1,330 @@
+use futures::lock::Mutex;
+use smvblock::{
+    amount::Amount,
+    blockchain::{Block, Blockchain, Transfer, TxData, TxKind, User},
+    db::{Database, ReadPool, database_path},
+    node::{BackupConfig, Node, NodeType},
+    p2p::PeerRecord,
+    params,
+};
+use std::sync::Arc;
+use std::time::Duration;
+
+#[test]
+fn test_connections_share_the_file_without_busy_errors() {
+    let path = std::env::temp_dir().join(format!("smvblock-wal-{}.db", rand::random::<u64>()));
+    let db = Database::new(path.to_str(), false).unwrap();
+    let other = db.try_clone().unwrap();
+    let (user, _) = User::generate(Amount::from_smv(5));
+    db.add_user(&user).unwrap();
+
+    // Another connection holds the write lock for a while.
+    let writer = rusqlite::Connection::open(&path).unwrap();
+    writer.execute_batch("BEGIN IMMEDIATE").unwrap();
+    let holder = std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(200));
+        writer.execute_batch("COMMIT").unwrap();
+    });
+
+    // Reads go ahead and writes wait their turn.
+    assert_eq!(other.get_users().unwrap().len(), 1);
+    let (second, _) = User::generate(Amount::from_smv(1));
+    other.add_user(&second).unwrap();
+    holder.join().unwrap();
+    assert_eq!(db.get_users().unwrap().len(), 2);
+
+    drop((db, other));
+    for suffix in ["", "-wal", "-shm"] {
+        let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+    }
+}
+
+#[tokio::test]
+async fn test_reads_do_not_wait_for_the_node_connection() {
+    let path = std::env::temp_dir().join(format!("smvblock-pool-{}.db", rand::random::<u64>()));
+    let db = Database::new(path.to_str(), false).unwrap();
+    let (user, _) = User::generate(Amount::from_smv(5));
+    db.add_user(&user).unwrap();
+    let database = Arc::new(Mutex::new(db));
+    let blockchain = Blockchain::new(database.clone());
+
+    // The node's connection is busy, as while it applies a block.
+    let node = database.lock().await;
+    let (other, _) = User::generate(Amount::from_smv(1));
+    node.add_user(&other).unwrap();
+    let read = tokio::time::timeout(Duration::from_secs(5), async {
+        let users = blockchain.top_accounts(false, 10).await.unwrap();
+        (
+            users.len(),
+            blockchain.get_user(&other.address).await.unwrap(),
+        )
+    });
+    assert_eq!(read.await.unwrap(), (2, Some(other)));
+
+    // Pooled connections only read.
+    let pool = ReadPool::open(&node).unwrap();
+    let write = pool.read(move |db| db.add_user(&user)).await;
+    assert!(write.is_err());
+
+    drop((node, blockchain, pool));
+    drop(database);
+    for suffix in ["", "-wal", "-shm"] {
+        let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+    }
+}
+
+#[test]
+fn test_older_databases_are_migrated_in_place() {
+    let path = std::env::temp_dir().join(format!("smvblock-migrate-{}.db", rand::random::<u64>()));
+    // The peers table as written before node IDs were stored.
+    let old = rusqlite::Connection::open(&path).unwrap();
+    old.execute_batch(
+        "CREATE TABLE peers (
+            address TEXT PRIMARY KEY,
+            node_type TEXT,
+            last_seen INTEGER NOT NULL,
+            successes INTEGER NOT NULL,
+            failures INTEGER NOT NULL
+        );
+        INSERT INTO peers VALUES ('127.0.0.1:4001', NULL, 7, 1, 0);",
+    )
+    .unwrap();
+    drop(old);
+
+    let db = Database::new(path.to_str(), false).unwrap();
+    let addr = "127.0.0.1:4001".parse().unwrap();
+    let peers = db.load_peers().unwrap();
+    assert_eq!(peers.len(), 1);
+    assert_eq!(peers[0].last_seen, 7);
+    assert_eq!(peers[0].node_id, None);
+    db.save_peer(&PeerRecord {
+        node_id: Some([4; 32]),
+        ..PeerRecord::new(addr)
+    })
+    .unwrap();
+    drop(db);
+
+    // Reopening applies nothing twice.
+    let db = Database::new(path.to_str(), false).unwrap();
+    assert_eq!(db.load_peers().unwrap()[0].node_id, Some([4; 32]));
+    drop(db);
+    let versions: Vec<u32> = rusqlite::Connection::open(&path)
+        .unwrap()
+        .prepare("SELECT version FROM schema_migrations ORDER BY version")
+        .unwrap()
+        .query_map([], |row| row.get(0))
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(
+        versions,
+        vec![
+            1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19
+        ]
+    );
+
+    for suffix in ["", "-wal", "-shm"] {
+        let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+    }
+}
+
+#[test]
+fn test_in_memory_databases_are_independent() {
+    let db = Database::in_memory().unwrap();
+    let other = Database::in_memory().unwrap();
+    let (user, _) = User::generate(Amount::from_smv(5));
+    db.add_user(&user).unwrap();
+
+    assert_eq!(db.get_user(&user.address).unwrap(), Some(user));
+    assert!(other.get_users().unwrap().is_empty());
+    assert!(db.try_clone().is_err());
+}
+
+#[test]
+fn test_restoring_a_backup_undoes_later_writes() {
+    let dir = std::env::temp_dir().join(format!("smvblock-backup-{}", rand::random::<u64>()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let mut db = Database::new(dir.join("node.db").to_str(), false).unwrap();
+    let (user, _) = User::generate(Amount::from_smv(5));
+    db.add_user(&user).unwrap();
+    db.backup(&dir.join("backup.db")).unwrap();
+
+    let (later, _) = User::generate(Amount::from_smv(1));
+    db.add_user(&later).unwrap();
+    db.restore(&dir.join("backup.db")).unwrap();
+    assert_eq!(db.get_users().unwrap(), vec![user]);
+    assert!(db.restore(&dir.join("missing.db")).is_err());
+
+    drop(db);
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+#[tokio::test]
+async fn test_periodic_backups_keep_the_newest() {
+    let dir = std::env::temp_dir().join(format!("smvblock-backups-{}", rand::random::<u64>()));
+    let db = Database::new(dir.with_extension("db").to_str(), false).unwrap();
+    let node = Node::with_database(NodeType::FullNode, db);
+    let (user, _) = User::generate(Amount::from_smv(5));
+    node.add_user(user.clone()).await.unwrap();
+
+    node.start_backups(BackupConfig {
+        dir: dir.clone(),
+        interval: Duration::from_millis(50),
+        keep: 2,
+    })
+    .await
+    .unwrap();
+    tokio::time::sleep(Duration::from_millis(400)).await;
+    node.shutdown().await.unwrap();
+
+    let backups: Vec<_> = std::fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    assert_eq!(backups.len(), 2);
+    for backup in &backups {
+        let copy = Database::new(backup.to_str(), false).unwrap();
+        assert_eq!(copy.get_users().unwrap(), vec![user.clone()]);
+    }
+
+    let _ = std::fs::remove_dir_all(&dir);
+    for suffix in ["", "-wal", "-shm"] {
+        let _ = std::fs::remove_file(format!("{}{}", dir.with_extension("db").display(), suffix));
+    }
+}
+
+#[tokio::test]
+async fn test_verify_finds_corruption_and_reindex_rebuilds_positions() {
+    let path = std::env::temp_dir().join(format!("smvblock-verify-{}.db", rand::random::<u64>()));
+    let db = Database::new(path.to_str(), false).unwrap();
+    let mut node = Node::with_database(NodeType::FullNode, db);
+    let (user, key) = User::generate(Amount::from_smv(100));
+    let (receiver, _) = User::generate(Amount::ZERO);
+    node.add_user(user.clone()).await.unwrap();
+    node.add_user(receiver.clone()).await.unwrap();
+    node.stake(user.address, Amount::from_smv(50))
+        .await
+        .unwrap();
+    let tx = Transfer {
+        receiver: receiver.address,
+        amount: Amount::from_smv(10),
+        fee: Amount::ZERO,
+        nonce: 0,
+        kind: TxKind::Transfer,
+        data: TxData::None,
+    }
+    .into_transaction(&key);
+    node.blockchain.add_transaction(tx.clone()).await.unwrap();
+    node.produce_block().await.unwrap();
+    node.produce_block().await.unwrap();
+
+    let report = node.blockchain.verify_chain(false).await.unwrap();
+    assert_eq!(report.blocks, 2);
+    assert!(report.problems.is_empty(), "{:?}", report.problems);
+
+    let conn = rusqlite::Connection::open(&path).unwrap();
+    conn.execute(
+        "UPDATE transactions SET block_height = NULL, tx_index = NULL",
+        [],
+    )
+    .unwrap();
+    conn.execute("DROP INDEX transactions_hash", []).unwrap();
+    conn.execute(
+        "CREATE INDEX transactions_hash ON transactions (tx_hash)",
+        [],
+    )
+    .unwrap();
+    assert_eq!(node.blockchain.reindex().await.unwrap(), 1);
+    let (_, location) = node
+        .blockchain
+        .get_transaction_with_block(&tx.hash())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!((location.height, location.index), (0, 0));
+
+    conn.execute("UPDATE transactions SET amount = '1'", [])
+        .unwrap();
+    conn.execute("UPDATE users SET balance = '0'", []).unwrap();
+    let report = node.blockchain.verify_chain(false).await.unwrap();
+    assert_eq!(report.problems.len(), 2, "{:?}", report.problems);
+    assert!(report.problems[0].contains("does not match its stored transactions"));
+    assert!(report.problems[1].contains("state root"));
+
+    drop((conn, node));
+    for suffix in ["", "-wal", "-shm"] {
+        let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+    }
+}
+
+#[test]
+fn test_each_chain_gets_its_own_directory() {
+    let data_dir = std::path::Path::new("/data");
+    assert_eq!(
+        database_path(data_dir, "devnet").unwrap(),
+        data_dir.join("devnet").join("chain.db")
+    );
+    assert_ne!(
+        database_path(data_dir, "mainnet").unwrap(),
+        database_path(data_dir, "devnet").unwrap()
+    );
+    for chain_id in ["", "..", "a/b", "/etc"] {
+        assert!(database_path(data_dir, chain_id).is_err());
+    }
+}
+
+#[test]
+fn test_cached_accounts_and_blocks_follow_applied_and_reverted_blocks() {
+    let mut db = Database::in_memory().unwrap();
+    let (user, _) = User::generate(Amount::from_smv(5));
+    db.add_user(&user).unwrap();
+    assert_eq!(db.get_user(&user.address).unwrap(), Some(user.clone()));
+
+    let genesis = Block::new([0; 32], 0, user.address, vec![]);
+    db.add_block(&genesis).unwrap();
+    let block = Block::new(genesis.hash(), 1, user.address, vec![]);
+    let paid = User {
+        balance: Amount::from_smv(7),
+        ..user.clone()
+    };
+    db.commit_block(&block, std::slice::from_ref(&paid), Amount::ZERO)
+        .unwrap();
+    assert_eq!(db.get_user(&user.address).unwrap(), Some(paid));
+    assert_eq!(
+        db.get_block_by_height(1).unwrap().unwrap().hash(),
+        block.hash()
+    );
+
+    db.revert_block(&block.hash()).unwrap();
+    assert_eq!(db.get_user(&user.address).unwrap(), Some(user));
+    assert!(db.get_block(&block.hash()).unwrap().is_none());
+    assert!(db.get_block_by_height(1).unwrap().is_none());
+    assert_eq!(
+        db.get_block_by_height(0).unwrap().unwrap().hash(),
+        genesis.hash()
+    );
+}
+
+#[test]
+fn test_reverting_a_block_deletes_the_accounts_it_created() {
+    let mut db = Database::in_memory().unwrap();
+    let (user, _) = User::generate(Amount::from_smv(5));
+    db.add_user(&user).unwrap();
+    let genesis = Block::new([0; 32], 0, user.address, vec![]);
+    db.add_block(&genesis).unwrap();
+
+    let block = Block::new(genesis.hash(), 1, user.address, vec![]);
+    let treasury = User {
+        balance: Amount::from_smv(2),
+        ..params::empty_treasury()
+    };
+    db.commit_block(&block, std::slice::from_ref(&treasury), Amount::from_smv(1))
+        .unwrap();
+    assert_eq!(db.get_user(&params::TREASURY).unwrap(), Some(treasury));
+    assert_eq!(db.get_supply().unwrap().burned, Amount::from_smv(1));
+
+    let restored = db.revert_block(&block.hash()).unwrap().unwrap();
+    assert!(restored.is_empty());
+    assert_eq!(db.get_user(&params::TREASURY).unwrap(), None);
+    assert_eq!(db.get_supply().unwrap().burned, Amount::ZERO);
+}
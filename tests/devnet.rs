@@ -0,0 +1,107 @@
+use smvblock::amount::Amount;
+use smvblock::devnet::{ChaosConfig, DEVNET_KEYS_FILE, Devnet, DevnetConfig};
+use std::time::Duration;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_devnet_converges_after_chaos() {
+    let config = DevnetConfig {
+        nodes: 4,
+        block_time: Duration::from_millis(200),
+        ..DevnetConfig::default()
+    };
+    let dir = config.dir.clone();
+    let mut devnet = Devnet::start(config).await.unwrap();
+
+    let events = devnet
+        .run_chaos(ChaosConfig {
+            duration: Duration::from_secs(3),
+            fault_time: Duration::from_millis(500),
+            drop_rate: 0.3,
+            max_delay: Duration::from_millis(100),
+            settle_time: Duration::from_secs(60),
+            seed: 7,
+        })
+        .await
+        .unwrap();
+    assert!(!events.is_empty());
+
+    // The chain kept growing through it all.
+    let node = devnet.node(0).unwrap();
+    let head = node.blockchain.get_latest_block().await.unwrap().unwrap();
+    assert!(head.header.height > 0);
+
+    devnet.shutdown().await.unwrap();
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_devnet_accounts_are_funded_and_the_same_every_run() {
+    let config = DevnetConfig {
+        nodes: 2,
+        block_time: Duration::from_millis(200),
+        ..DevnetConfig::default()
+    };
+    let dir = config.dir.clone();
+    let devnet = Devnet::start(config.clone()).await.unwrap();
+    let alice = devnet.account("alice").unwrap().clone();
+    let bob = devnet.account("bob").unwrap().address();
+
+    let keys: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(dir.join(DEVNET_KEYS_FILE)).unwrap())
+            .unwrap();
+    assert_eq!(keys["accounts"][0]["name"], "alice");
+    assert_eq!(
+        keys["accounts"][0]["secret_key"],
+        hex::encode(alice.key.to_bytes())
+    );
+
+    // Another devnet from the same seed has the same genesis block.
+    let other_config = DevnetConfig {
+        dir: dir.with_extension("other"),
+        ..config
+    };
+    let other = Devnet::start(other_config.clone()).await.unwrap();
+    let genesis = |devnet: &Devnet| {
+        let blockchain = devnet.node(0).unwrap().blockchain.clone();
+        async move {
+            blockchain
+                .get_block_by_height(0)
+                .await
+                .unwrap()
+                .unwrap()
+                .hash()
+        }
+    };
+    assert_eq!(genesis(&devnet).await, genesis(&other).await);
+    assert_eq!(other.account("alice").unwrap().address(), alice.address());
+    other.shutdown().await.unwrap();
+
+    let node = devnet.node(0).unwrap();
+    node.send_transaction(alice.key.clone(), bob, Amount::from_smv(5))
+        .await
+        .unwrap();
+    let expected = DevnetConfig::default()
+        .account_balance
+        .checked_add(Amount::from_smv(5))
+        .unwrap();
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(10);
+    while node
+        .blockchain
+        .get_user(&bob)
+        .await
+        .unwrap()
+        .unwrap()
+        .balance
+        != expected
+    {
+        assert!(
+            tokio::time::Instant::now() < deadline,
+            "transfer never landed"
+        );
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+
+    devnet.shutdown().await.unwrap();
+    let _ = std::fs::remove_dir_all(dir);
+    let _ = std::fs::remove_dir_all(other_config.dir);
+}
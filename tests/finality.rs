@@ -0,0 +1,92 @@
+use smvblock::{
+    amount::Amount,
+    blockchain::User,
+    finality::{FinalityTracker, Vote, VoteOutcome},
+    node::{Node, NodeType},
+};
+use std::collections::HashMap;
+
+#[test]
+fn test_two_thirds_of_stake_finalizes() {
+    let (alice, alice_key) = User::generate(Amount::ZERO);
+    let (bob, bob_key) = User::generate(Amount::ZERO);
+    let (carol, _) = User::generate(Amount::ZERO);
+    let stakes = HashMap::from([
+        (alice.address, Amount::from_smv(40)),
+        (bob.address, Amount::from_smv(30)),
+        (carol.address, Amount::from_smv(35)),
+    ]);
+    let block_hash = [7u8; 32];
+
+    let mut tracker = FinalityTracker::new();
+    let vote = Vote::sign(block_hash, 1, &alice_key);
+    assert!(vote.verify());
+    assert_eq!(
+        tracker.add_vote(vote.clone(), &stakes, None),
+        Ok(VoteOutcome::Recorded)
+    );
+    assert_eq!(
+        tracker.add_vote(vote, &stakes, None),
+        Ok(VoteOutcome::Duplicate)
+    );
+
+    // 70 of 105 is exactly two thirds.
+    let vote = Vote::sign(block_hash, 1, &bob_key);
+    assert_eq!(
+        tracker.add_vote(vote, &stakes, None),
+        Ok(VoteOutcome::Finalized)
+    );
+    assert_eq!(tracker.vote_count(&block_hash), 0);
+
+    let late = Vote::sign([8u8; 32], 1, &alice_key);
+    assert!(tracker.add_vote(late, &stakes, Some(1)).is_err());
+}
+
+#[test]
+fn test_unstaked_and_forged_votes_are_rejected() {
+    let (alice, alice_key) = User::generate(Amount::ZERO);
+    let (_, outsider_key) = User::generate(Amount::ZERO);
+    let stakes = HashMap::from([(alice.address, Amount::from_smv(10))]);
+
+    let mut tracker = FinalityTracker::new();
+    let vote = Vote::sign([1u8; 32], 0, &outsider_key);
+    assert!(tracker.add_vote(vote, &stakes, None).is_err());
+
+    let mut forged = Vote::sign([1u8; 32], 0, &alice_key);
+    forged.height = 5;
+    assert!(!forged.verify());
+}
+
+#[tokio::test]
+async fn test_finalized_block_is_included_in_next_header() {
+    let mut node = Node::new(NodeType::FullNode, true).unwrap();
+
+    let (alice, alice_key) = User::generate(Amount::from_smv(100));
+    let (bob, bob_key) = User::generate(Amount::from_smv(100));
+    node.add_user(alice.clone()).await.unwrap();
+    node.add_user(bob.clone()).await.unwrap();
+    node.stake(alice.address, Amount::from_smv(50))
+        .await
+        .unwrap();
+    node.stake(bob.address, Amount::from_smv(50)).await.unwrap();
+
+    let block_hash = node.produce_block().await.unwrap();
+    assert_eq!(
+        node.attest(&alice_key, block_hash).await,
+        Ok(VoteOutcome::Recorded)
+    );
+    assert_eq!(node.blockchain.latest_finalized().await.unwrap(), None);
+
+    assert_eq!(
+        node.attest(&bob_key, block_hash).await,
+        Ok(VoteOutcome::Finalized)
+    );
+    assert_eq!(
+        node.blockchain.latest_finalized().await.unwrap(),
+        Some((block_hash, 0))
+    );
+
+    let next = node.produce_block().await.unwrap();
+    let next = node.blockchain.get_block(next).await.unwrap().unwrap();
+    assert_eq!(next.header.finalized_hash, block_hash);
+}
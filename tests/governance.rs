@@ -0,0 +1,296 @@
+use ed25519_dalek::SigningKey;
+use smvblock::{
+    amount::Amount,
+    blockchain::{Transfer, TxData, TxKind, User},
+    error::BlockchainError,
+    governance::{
+        Ballot, MIN_VOTING_PERIOD, Parameter, ParameterChange, Parameters, ProposalStatus,
+    },
+    node::{Node, NodeType},
+    params::{ChainParams, Feature, ForkSchedule},
+    wallet,
+};
+
+fn governance_tx(
+    key: &SigningKey,
+    kind: TxKind,
+    data: TxData,
+    nonce: u64,
+) -> smvblock::blockchain::Transaction {
+    Transfer {
+        receiver: wallet::key_address(key),
+        amount: Amount::ZERO,
+        fee: Amount::ZERO,
+        nonce,
+        kind,
+        data,
+    }
+    .into_transaction(key)
+}
+
+/// A node with two validators staking 70 and 30 SMV.
+async fn node() -> (Node, [(User, SigningKey); 2]) {
+    let node = Node::new(NodeType::FullNode, true).unwrap();
+    let mut stakers = Vec::new();
+    for stake in [70, 30] {
+        let (user, key) = User::generate(Amount::from_smv(100));
+        node.add_user(user.clone()).await.unwrap();
+        node.stake(user.address, Amount::from_smv(stake))
+            .await
+            .unwrap();
+        stakers.push((user, key));
+    }
+    (node, stakers.try_into().unwrap())
+}
+
+fn next_height(node: &Node) -> u64 {
+    node.blockchain
+        .chain_head()
+        .get()
+        .map_or(0, |(_, height)| height + 1)
+}
+
+async fn produce_until(node: &mut Node, height: u64) {
+    while next_height(node) <= height {
+        node.produce_block().await.unwrap();
+    }
+}
+
+#[test]
+fn test_proposals_and_votes_round_trip_through_a_transaction() {
+    let change = ParameterChange {
+        parameter: Parameter::BlockReward,
+        value: Amount::from_smv(5).base_units(),
+        activation_height: 1_234,
+    };
+    let proposal = TxData::Proposal(change);
+    assert_eq!(TxData::from_bytes(&proposal.to_bytes()), Ok(proposal));
+    let ballot = TxData::Ballot(Ballot {
+        proposal: 7,
+        approve: true,
+    });
+    assert_eq!(TxData::from_bytes(&ballot.to_bytes()), Ok(ballot));
+
+    // Each kind carries its own data and no other.
+    let (sender, key) = User::generate(Amount::ZERO);
+    let vote = |data| Transfer {
+        receiver: sender.address,
+        amount: Amount::ZERO,
+        fee: Amount::ZERO,
+        nonce: 0,
+        kind: TxKind::Vote,
+        data,
+    };
+    assert_eq!(vote(ballot).check_data(), Ok(()));
+    for data in [proposal, TxData::None] {
+        assert_eq!(
+            vote(data).check_data(),
+            Err(BlockchainError::WrongData(TxKind::Vote))
+        );
+    }
+    let transfer = Transfer {
+        kind: TxKind::Transfer,
+        ..vote(ballot)
+    };
+    assert_eq!(
+        transfer.check_data(),
+        Err(BlockchainError::WrongData(TxKind::Transfer))
+    );
+    let mut mismatched = vote(ballot).into_transaction(&key);
+    mismatched.payload.data = proposal;
+    assert!(!mismatched.verify());
+
+    let instant = ParameterChange {
+        parameter: Parameter::BlockTime,
+        value: 0,
+        activation_height: 1,
+    };
+    assert!(instant.check().is_err());
+    assert_eq!(Parameter::BlockTime.parse_value("2500 ms"), Ok(2_500));
+}
+
+#[tokio::test]
+async fn test_accepted_change_applies_from_its_activation_height() {
+    let (mut node, [(large, large_key), (_, small_key)]) = node().await;
+    node.produce_block().await.unwrap();
+    let activation_height = next_height(&node) + MIN_VOTING_PERIOD;
+    let change = ParameterChange {
+        parameter: Parameter::BlockReward,
+        value: Amount::from_smv(5).base_units(),
+        activation_height,
+    };
+
+    node.blockchain
+        .add_transaction(governance_tx(
+            &large_key,
+            TxKind::Propose,
+            TxData::Proposal(change),
+            0,
+        ))
+        .await
+        .unwrap();
+    node.produce_block().await.unwrap();
+    let ballot = |approve| {
+        TxData::Ballot(Ballot {
+            proposal: 1,
+            approve,
+        })
+    };
+    node.blockchain
+        .add_transaction(governance_tx(&large_key, TxKind::Vote, ballot(true), 1))
+        .await
+        .unwrap();
+    node.blockchain
+        .add_transaction(governance_tx(&small_key, TxKind::Vote, ballot(false), 0))
+        .await
+        .unwrap();
+    node.produce_block().await.unwrap();
+
+    let again = node
+        .blockchain
+        .add_transaction(governance_tx(&large_key, TxKind::Vote, ballot(false), 2))
+        .await
+        .unwrap_err();
+    assert!(matches!(again, BlockchainError::Governance(_)));
+
+    let proposals = node.blockchain.proposals().await.unwrap();
+    let (proposal, tally) = &proposals[0];
+    assert_eq!(proposal.proposer, large.address);
+    assert_eq!(proposal.status, ProposalStatus::Voting);
+    assert_eq!(tally.approve, Amount::from_smv(70));
+    assert_eq!(tally.reject, Amount::from_smv(30));
+
+    produce_until(&mut node, activation_height - 1).await;
+    let proposals = node.blockchain.proposals().await.unwrap();
+    assert_eq!(proposals[0].0.status, ProposalStatus::Accepted);
+    assert_eq!(
+        node.blockchain
+            .parameters(activation_height - 1)
+            .await
+            .unwrap(),
+        Parameters::default()
+    );
+    let closed = node
+        .blockchain
+        .add_transaction(governance_tx(&small_key, TxKind::Vote, ballot(true), 1))
+        .await
+        .unwrap_err();
+    assert!(matches!(closed, BlockchainError::Governance(_)));
+
+    let hash = node.produce_block().await.unwrap();
+    let block = node.blockchain.get_block(hash).await.unwrap().unwrap();
+    assert_eq!(block.header.height, activation_height);
+    assert_eq!(block.header.coinbase, Amount::from_smv(5));
+}
+
+#[tokio::test]
+async fn test_governance_waits_for_its_fork() {
+    let (mut node, [(_, key), _]) = node().await;
+    let fork_height = 3;
+    let mut forks = ForkSchedule::default();
+    forks.set(Feature::Governance, fork_height);
+    node.blockchain.set_chain_params(ChainParams {
+        forks,
+        ..ChainParams::default()
+    });
+    assert_eq!("governance".parse(), Ok(Feature::Governance));
+
+    let proposal = |nonce| {
+        governance_tx(
+            &key,
+            TxKind::Propose,
+            TxData::Proposal(ParameterChange {
+                parameter: Parameter::BlockTime,
+                value: 2_000,
+                activation_height: fork_height + MIN_VOTING_PERIOD,
+            }),
+            nonce,
+        )
+    };
+    let early = node
+        .blockchain
+        .add_transaction(proposal(0))
+        .await
+        .unwrap_err();
+    assert!(matches!(early, BlockchainError::Governance(_)));
+
+    produce_until(&mut node, fork_height - 1).await;
+    node.blockchain.add_transaction(proposal(0)).await.unwrap();
+    node.produce_block().await.unwrap();
+    assert_eq!(node.blockchain.proposals().await.unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn test_proposal_without_a_supermajority_is_rejected() {
+    let (mut node, [(_, large_key), (small, small_key)]) = node().await;
+    let (poor, poor_key) = User::generate(Amount::from_smv(10));
+    node.add_user(poor.clone()).await.unwrap();
+    node.produce_block().await.unwrap();
+    let change = |activation_height| ParameterChange {
+        parameter: Parameter::MinFeePerByte,
+        value: 1,
+        activation_height,
+    };
+
+    let soon = next_height(&node) + MIN_VOTING_PERIOD - 1;
+    let early = node
+        .blockchain
+        .add_transaction(governance_tx(
+            &small_key,
+            TxKind::Propose,
+            TxData::Proposal(change(soon)),
+            0,
+        ))
+        .await
+        .unwrap_err();
+    assert!(matches!(early, BlockchainError::Governance(_)));
+
+    let activation_height = soon + 1;
+    node.blockchain
+        .add_transaction(governance_tx(
+            &small_key,
+            TxKind::Propose,
+            TxData::Proposal(change(activation_height)),
+            0,
+        ))
+        .await
+        .unwrap();
+    node.produce_block().await.unwrap();
+    let approve = TxData::Ballot(Ballot {
+        proposal: 1,
+        approve: true,
+    });
+    let unstaked = node
+        .blockchain
+        .add_transaction(governance_tx(&poor_key, TxKind::Vote, approve, 0))
+        .await
+        .unwrap_err();
+    assert!(matches!(unstaked, BlockchainError::Governance(_)));
+    node.blockchain
+        .add_transaction(governance_tx(&small_key, TxKind::Vote, approve, 1))
+        .await
+        .unwrap();
+
+    produce_until(&mut node, activation_height).await;
+    let proposals = node.blockchain.proposals().await.unwrap();
+    assert_eq!(proposals[0].0.status, ProposalStatus::Rejected);
+    assert_eq!(
+        node.blockchain.parameters(activation_height).await.unwrap(),
+        Parameters::default()
+    );
+    // Nothing governs the fee, so a free transfer still goes through.
+    node.blockchain
+        .add_transaction(
+            Transfer {
+                receiver: small.address,
+                amount: Amount::from_smv(1),
+                fee: Amount::ZERO,
+                nonce: 0,
+                kind: TxKind::Transfer,
+                data: TxData::None,
+            }
+            .into_transaction(&large_key),
+        )
+        .await
+        .unwrap();
+}
@@ -0,0 +1,115 @@
+#![cfg(feature = "grpc")]
+
+use smvblock::{
+    amount::Amount,
+    blockchain::{Transfer, TxData, TxKind, User},
+    error::BlockchainError,
+    grpc::proto::{self, node_client::NodeClient},
+    node::{Node, NodeType},
+};
+use std::time::Duration;
+use tonic::Code;
+
+fn transaction_message(tx: &smvblock::blockchain::Transaction) -> proto::Transaction {
+    proto::Transaction {
+        receiver: tx.payload.receiver.to_vec(),
+        amount: tx.payload.amount.base_units().to_string(),
+        fee: tx.payload.fee.base_units().to_string(),
+        nonce: tx.payload.nonce,
+        kind: proto::TxKind::Transfer.into(),
+        sender_public_key: tx.sender_public_key.to_vec(),
+        signature: tx.signature.to_vec(),
+        hash: Vec::new(),
+        data: tx.payload.data.to_bytes(),
+    }
+}
+
+#[tokio::test]
+async fn test_grpc_submit_query_and_stream() {
+    let mut node = Node::new(NodeType::FullNode, true).unwrap();
+    let (sender, key) = User::generate(Amount::from_smv(100));
+    let (receiver, _) = User::generate(Amount::ZERO);
+    node.add_user(sender.clone()).await.unwrap();
+    node.add_user(receiver.clone()).await.unwrap();
+    node.stake(sender.address, Amount::from_smv(10))
+        .await
+        .unwrap();
+    let addr = node
+        .start_grpc("127.0.0.1:0".parse().unwrap())
+        .await
+        .unwrap();
+    let mut client = NodeClient::connect(format!("http://{}", addr))
+        .await
+        .unwrap();
+
+    let status = client
+        .get_status(proto::GetStatusRequest {})
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(status.height, None);
+    let mut blocks = client
+        .subscribe_blocks(proto::SubscribeBlocksRequest {})
+        .await
+        .unwrap()
+        .into_inner();
+
+    let tx = Transfer {
+        receiver: receiver.address,
+        amount: Amount::from_smv(5),
+        fee: Amount::ZERO,
+        nonce: 0,
+        kind: TxKind::Transfer,
+        data: TxData::None,
+    }
+    .into_transaction(&key);
+    let submitted = client
+        .submit_transaction(transaction_message(&tx))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(submitted.hash, tx.hash().to_vec());
+    let refused = client
+        .submit_transaction(transaction_message(&tx))
+        .await
+        .unwrap_err();
+    assert_eq!(refused.code(), Code::FailedPrecondition);
+    assert_eq!(
+        refused.metadata().get("smv-error-code").unwrap(),
+        BlockchainError::Underpriced.code().to_string().as_str()
+    );
+
+    let hash = node.produce_block().await.unwrap();
+    let block = tokio::time::timeout(Duration::from_secs(5), blocks.message())
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+    assert_eq!(block.header.unwrap().hash, hash.to_vec());
+    assert_eq!(block.transactions[0].hash, tx.hash().to_vec());
+    assert_eq!(block.transactions[0].amount, "500000000");
+
+    let account = client
+        .get_account(proto::GetAccountRequest {
+            address: receiver.address.to_vec(),
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(account.balance, "500000000");
+    let by_height = client
+        .get_block(proto::GetBlockRequest {
+            id: Some(proto::get_block_request::Id::Height(0)),
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(by_height.header.unwrap().hash, hash.to_vec());
+    let missing = client
+        .get_account(proto::GetAccountRequest {
+            address: vec![9; 32],
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(missing.code(), Code::NotFound);
+}
@@ -0,0 +1,195 @@
+use chrono::Utc;
+use ed25519_dalek::SigningKey;
+use smvblock::{
+    amount::Amount,
+    blockchain::{Address, Block, Hash, Transfer, TxData, TxKind, User},
+    error::BlockchainError,
+    liveness,
+    node::{Node, NodeType},
+    params::{ChainParams, Feature, ForkSchedule, Liveness},
+};
+use std::time::Duration;
+
+fn unjail(key: &SigningKey, address: Address) -> smvblock::blockchain::Transaction {
+    Transfer {
+        receiver: address,
+        amount: Amount::ZERO,
+        fee: Amount::ZERO,
+        nonce: 0,
+        kind: TxKind::Unjail,
+        data: TxData::None,
+    }
+    .into_transaction(key)
+}
+
+/// A node with one-second slots and four-block epochs, jailing validators
+/// for a single missed slot at a tenth of their stake, where validators
+/// stake 60 and 40 SMV. Proposers are seeded by the parent's hash rather
+/// than the beacon, so a test can pick one by varying a block.
+async fn node() -> (Node, [(User, SigningKey); 2]) {
+    let mut node = Node::new(NodeType::FullNode, true).unwrap();
+    let mut forks = ForkSchedule::default();
+    forks.set(Feature::Beacon, u64::MAX);
+    node.blockchain.set_chain_params(ChainParams {
+        forks,
+        block_time: Duration::from_secs(1),
+        liveness: Liveness {
+            epoch_length: 4,
+            max_missed: 0,
+            penalty_bps: 1_000,
+        },
+        ..ChainParams::default()
+    });
+    let mut validators = Vec::new();
+    for stake in [60, 40] {
+        let (user, key) = User::generate(Amount::from_smv(100));
+        node.add_user(user.clone()).await.unwrap();
+        node.stake(user.address, Amount::from_smv(stake))
+            .await
+            .unwrap();
+        validators.push((user, key));
+    }
+    (node, validators.try_into().unwrap())
+}
+
+/// A block on top of `parent` stamped `timestamp`, minting `coinbase`.
+/// It is added unsigned, as if produced by hand, so any validator may
+/// propose it whatever the slot.
+async fn block(
+    node: &Node,
+    parent: Hash,
+    height: u64,
+    proposer: Address,
+    timestamp: i64,
+    coinbase: Amount,
+) -> Block {
+    let mut block = Block::new(parent, height, proposer, vec![]);
+    block.header.timestamp = timestamp;
+    block.header.coinbase = coinbase;
+    block.header.state_root = node.blockchain.state_root_after(&block).await.unwrap();
+    block
+}
+
+async fn account(node: &Node, address: Address) -> User {
+    node.blockchain.get_user(&address).await.unwrap().unwrap()
+}
+
+#[test]
+fn test_slots_between_blocks_are_missed() {
+    let second = Duration::from_secs(1);
+    assert_eq!(liveness::missed_slots(100, 101, second, 10), 101..101);
+    assert_eq!(liveness::missed_slots(100, 104, second, 10), 101..104);
+    assert_eq!(liveness::missed_slots(100, 104, second, 2), 101..103);
+    // A block stamped no later than its parent misses nothing.
+    assert!(liveness::missed_slots(100, 100, second, 10).is_empty());
+    assert_eq!(liveness::slot(104, Duration::from_secs(5)), 20);
+    assert!(liveness::ends_epoch(3, 4));
+    assert!(!liveness::ends_epoch(4, 4));
+}
+
+#[tokio::test]
+async fn test_validator_missing_slots_is_jailed_until_it_unjails() {
+    let (mut node, [(online, online_key), (offline, offline_key)]) = node().await;
+    let start = Utc::now().timestamp() - 600;
+    let genesis = block(&node, [0u8; 32], 0, online.address, start, Amount::ZERO).await;
+    let genesis_hash = genesis.hash();
+    node.blockchain.add_local_block(genesis).await.unwrap();
+
+    // A first block after which the next slot falls to the offline
+    // validator; its reward only varies the block's hash.
+    let mut coinbase = Amount::ZERO;
+    let first = loop {
+        let first = block(&node, genesis_hash, 1, online.address, start + 1, coinbase).await;
+        let next = node
+            .blockchain
+            .slot_proposer(
+                first.hash(),
+                2,
+                liveness::slot(start + 2, Duration::from_secs(1)),
+            )
+            .await
+            .unwrap();
+        if next == offline.address {
+            break first;
+        }
+        coinbase = coinbase.checked_add(Amount::from_base_units(1)).unwrap();
+    };
+    let first_hash = first.hash();
+    node.blockchain.add_local_block(first).await.unwrap();
+
+    // Its slot passes without a block.
+    let second = block(
+        &node,
+        first_hash,
+        2,
+        online.address,
+        start + 3,
+        Amount::ZERO,
+    )
+    .await;
+    let second_hash = second.hash();
+    node.blockchain.add_local_block(second).await.unwrap();
+    assert_eq!(account(&node, offline.address).await.missed_slots, 1);
+    assert_eq!(account(&node, online.address).await.missed_slots, 0);
+
+    let burned = node.blockchain.supply().await.unwrap().burned;
+    let last = block(
+        &node,
+        second_hash,
+        3,
+        online.address,
+        start + 4,
+        Amount::ZERO,
+    )
+    .await;
+    node.blockchain.add_local_block(last).await.unwrap();
+    let jailed = account(&node, offline.address).await;
+    assert!(jailed.jailed);
+    assert_eq!(jailed.missed_slots, 0);
+    assert_eq!(jailed.stake, Amount::from_smv(36));
+    assert_eq!(
+        node.blockchain.supply().await.unwrap().burned,
+        burned.checked_add(Amount::from_smv(4)).unwrap()
+    );
+    let validators = node.blockchain.validators().await.unwrap();
+    assert_eq!(validators.len(), 1);
+    assert_eq!(validators[0].user.address, online.address);
+    assert!(
+        node.blockchain
+            .verify_chain(false)
+            .await
+            .unwrap()
+            .problems
+            .is_empty()
+    );
+
+    let free = node
+        .blockchain
+        .add_transaction(unjail(&online_key, online.address))
+        .await
+        .unwrap_err();
+    assert!(matches!(free, BlockchainError::Staking(_)));
+    node.blockchain
+        .add_transaction(unjail(&offline_key, offline.address))
+        .await
+        .unwrap();
+    node.produce_block().await.unwrap();
+    assert!(!account(&node, offline.address).await.jailed);
+    assert_eq!(node.blockchain.validators().await.unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn test_last_validators_are_never_jailed() {
+    let (node, _) = node().await;
+    let validators = node.blockchain.validators().await.unwrap();
+    let absent: Vec<User> = validators
+        .iter()
+        .map(|validator| User {
+            missed_slots: 5,
+            ..validator.user.clone()
+        })
+        .collect();
+    assert!(liveness::to_jail(&absent, &validators, 0).is_empty());
+    assert_eq!(liveness::to_jail(&absent[..1], &validators, 0).len(), 1);
+    assert!(liveness::to_jail(&absent[..1], &validators, 5).is_empty());
+}
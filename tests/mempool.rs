@@ -0,0 +1,407 @@
+use futures::lock::Mutex;
+use smvblock::{
+    amount::Amount,
+    blockchain::{Block, Blockchain, Transfer, TxData, TxKind, User},
+    db::Database,
+    error::BlockchainError,
+    mempool::{Mempool, MempoolConfig},
+};
+use std::sync::Arc;
+use std::time::Duration;
+
+fn transfer(amount: u64, fee: u64, nonce: u64) -> Transfer {
+    let (receiver, _) = User::generate(Amount::ZERO);
+    Transfer {
+        receiver: receiver.address,
+        amount: Amount::from_smv(amount),
+        fee: Amount::from_base_units(fee.into()),
+        nonce,
+        kind: TxKind::Transfer,
+        data: TxData::None,
+    }
+}
+
+#[test]
+fn test_replace_by_fee_requires_higher_fee() {
+    let (_, key) = User::generate(Amount::from_smv(100));
+    let mut mempool = Mempool::new();
+
+    let original = transfer(10, 2, 0).into_transaction(&key);
+    mempool.insert(original.clone()).unwrap();
+
+    let same_fee = Transfer {
+        amount: Amount::from_smv(11),
+        ..original.payload.clone()
+    };
+    assert!(mempool.insert(same_fee.into_transaction(&key)).is_err());
+
+    let higher_fee = Transfer {
+        fee: Amount::from_base_units(3),
+        ..original.payload.clone()
+    };
+    let replaced = mempool.insert(higher_fee.into_transaction(&key)).unwrap();
+    assert_eq!(replaced, Some(original));
+    assert_eq!(mempool.len(), 1);
+}
+
+#[test]
+fn test_drain_orders_by_fee_and_keeps_nonce_order() {
+    let (_, alice) = User::generate(Amount::from_smv(100));
+    let (_, bob) = User::generate(Amount::from_smv(100));
+    let mut mempool = Mempool::new();
+
+    mempool
+        .insert(transfer(1, 1, 0).into_transaction(&alice))
+        .unwrap();
+    mempool
+        .insert(transfer(1, 50, 1).into_transaction(&alice))
+        .unwrap();
+    mempool
+        .insert(transfer(1, 10, 0).into_transaction(&bob))
+        .unwrap();
+
+    let fees: Vec<u128> = mempool
+        .drain()
+        .iter()
+        .map(|tx| tx.payload.fee.base_units())
+        .collect();
+    assert_eq!(fees, vec![10, 1, 50]);
+    assert!(mempool.is_empty());
+}
+
+#[test]
+fn test_full_mempool_evicts_lowest_fee() {
+    let (_, alice) = User::generate(Amount::from_smv(100));
+    let (_, bob) = User::generate(Amount::from_smv(100));
+    let mut mempool = Mempool::with_config(MempoolConfig {
+        max_transactions: 1,
+        ..MempoolConfig::default()
+    });
+
+    mempool
+        .insert(transfer(1, 1, 0).into_transaction(&alice))
+        .unwrap();
+    assert!(
+        mempool
+            .insert(transfer(1, 1, 0).into_transaction(&bob))
+            .is_err()
+    );
+    mempool
+        .insert(transfer(1, 5, 0).into_transaction(&bob))
+        .unwrap();
+
+    assert_eq!(mempool.len(), 1);
+    assert_eq!(mempool.stats().evicted_low_fee, 1);
+    assert_eq!(mempool.stats().rejected_full, 1);
+}
+
+#[test]
+fn test_eviction_follows_replacements_and_inclusions() {
+    let [alice, bob, carol, dave] = [(); 4].map(|_| User::generate(Amount::from_smv(100)).1);
+    let mut mempool = Mempool::with_config(MempoolConfig {
+        max_transactions: 2,
+        ..MempoolConfig::default()
+    });
+    let fees = |mempool: &Mempool| {
+        let mut fees: Vec<u128> = mempool
+            .pending()
+            .iter()
+            .map(|tx| tx.payload.fee.base_units())
+            .collect();
+        fees.sort();
+        fees
+    };
+
+    let included = transfer(1, 10, 0).into_transaction(&alice);
+    mempool.insert(included.clone()).unwrap();
+    mempool
+        .insert(transfer(1, 1, 1).into_transaction(&alice))
+        .unwrap();
+    mempool.remove_included(&[included]);
+    let bob_tx = transfer(1, 5, 0);
+    mempool
+        .insert(bob_tx.clone().into_transaction(&bob))
+        .unwrap();
+    mempool
+        .insert(transfer(1, 3, 0).into_transaction(&carol))
+        .unwrap();
+    assert_eq!(fees(&mempool), vec![3, 5]);
+
+    // Bumping bob's fee takes it out of reach of the next eviction.
+    let bumped = Transfer {
+        fee: Amount::from_base_units(20),
+        ..bob_tx
+    };
+    mempool.insert(bumped.into_transaction(&bob)).unwrap();
+    mempool
+        .insert(transfer(1, 4, 0).into_transaction(&dave))
+        .unwrap();
+    assert_eq!(fees(&mempool), vec![4, 20]);
+    assert_eq!(mempool.stats().evicted_low_fee, 2);
+}
+
+#[test]
+fn test_fee_floor_and_sender_cap() {
+    let (_, key) = User::generate(Amount::from_smv(100));
+    let mut mempool = Mempool::with_config(MempoolConfig {
+        min_fee_per_byte: Amount::from_base_units(1),
+        max_pending_per_sender: 1,
+        ..MempoolConfig::default()
+    });
+
+    let cheap = transfer(1, 1, 0).into_transaction(&key);
+    assert!(matches!(
+        mempool.insert(cheap),
+        Err(BlockchainError::FeeTooLow { .. })
+    ));
+
+    mempool
+        .insert(transfer(1, 1_000, 0).into_transaction(&key))
+        .unwrap();
+    assert_eq!(
+        mempool.insert(transfer(1, 1_000, 1).into_transaction(&key)),
+        Err(BlockchainError::TooManyPending { limit: 1 })
+    );
+    // Replacing the pending nonce does not count against the cap.
+    mempool
+        .insert(transfer(1, 2_000, 0).into_transaction(&key))
+        .unwrap();
+    assert_eq!(mempool.len(), 1);
+}
+
+#[test]
+fn test_expired_transactions_are_evicted() {
+    let (_, key) = User::generate(Amount::from_smv(100));
+    let mut mempool = Mempool::with_config(MempoolConfig {
+        ttl: Duration::ZERO,
+        ..MempoolConfig::default()
+    });
+
+    mempool
+        .insert(transfer(1, 1, 0).into_transaction(&key))
+        .unwrap();
+    assert!(mempool.drain().is_empty());
+    assert_eq!(mempool.stats().evicted_expired, 1);
+    assert_eq!(mempool.bytes(), 0);
+}
+
+#[test]
+fn test_expiry_takes_the_higher_nonces_with_it() {
+    let (_, key) = User::generate(Amount::from_smv(100));
+    let mut mempool = Mempool::with_config(MempoolConfig {
+        ttl: Duration::from_millis(200),
+        ..MempoolConfig::default()
+    });
+    for nonce in 0..3 {
+        mempool
+            .insert(transfer(1, 1, nonce).into_transaction(&key))
+            .unwrap();
+    }
+    std::thread::sleep(Duration::from_millis(100));
+    // The replacement starts its clock again, but cannot be included once
+    // the nonce before it is gone.
+    mempool
+        .insert(transfer(1, 2, 1).into_transaction(&key))
+        .unwrap();
+    std::thread::sleep(Duration::from_millis(150));
+    mempool.evict_expired();
+
+    assert!(mempool.pending().is_empty());
+    assert_eq!(mempool.stats().evicted_expired, 3);
+    assert_eq!(mempool.bytes(), 0);
+}
+
+#[tokio::test]
+async fn test_mempool_survives_restart() {
+    let path = std::env::temp_dir().join(format!("smvblock-mempool-{}.db", rand::random::<u64>()));
+    let open = || {
+        let db = Database::new(path.to_str(), false).unwrap();
+        Blockchain::new(Arc::new(Mutex::new(db)))
+    };
+    let (sender, key) = User::generate(Amount::from_smv(100));
+    let (receiver, _) = User::generate(Amount::ZERO);
+    let tx = Transfer {
+        receiver: receiver.address,
+        ..transfer(10, 2, 0)
+    }
+    .into_transaction(&key);
+
+    let db = Database::new(path.to_str(), false).unwrap();
+    db.add_user(&sender).unwrap();
+    db.add_user(&receiver).unwrap();
+    drop(db);
+
+    let blockchain = open();
+    blockchain.add_transaction(tx.clone()).await.unwrap();
+    blockchain.persist_mempool().await.unwrap();
+    drop(blockchain);
+
+    let blockchain = open();
+    assert_eq!(blockchain.restore_mempool().await, Ok(1));
+    assert_eq!(blockchain.get_pending_transactions().await, vec![tx]);
+    assert_eq!(blockchain.restore_mempool().await, Ok(0));
+
+    let _ = std::fs::remove_file(path);
+}
+
+#[tokio::test]
+async fn test_pool_checks_transactions_against_chain_state() {
+    let db = Database::new(None, true).unwrap();
+    let (sender, key) = User::generate(Amount::from_smv(100));
+    let (receiver, _) = User::generate(Amount::ZERO);
+    db.add_user(&sender).unwrap();
+    db.add_user(&receiver).unwrap();
+    let blockchain = Blockchain::new(Arc::new(Mutex::new(db)));
+    let to = |amount, nonce| {
+        Transfer {
+            receiver: receiver.address,
+            ..transfer(amount, 1, nonce)
+        }
+        .into_transaction(&key)
+    };
+
+    let unknown = transfer(1, 1, 0).into_transaction(&key);
+    assert_eq!(
+        blockchain.add_transaction(unknown).await,
+        Err(BlockchainError::UnknownReceiver)
+    );
+    assert_eq!(
+        blockchain.add_transaction(to(60, 20)).await,
+        Err(BlockchainError::NonceGap {
+            expected: 0,
+            got: 20
+        })
+    );
+    blockchain.add_transaction(to(60, 0)).await.unwrap();
+    // Only 40 SMV is left once the first transfer is counted.
+    assert_eq!(
+        blockchain.add_transaction(to(50, 1)).await,
+        Err(BlockchainError::InsufficientBalance)
+    );
+    blockchain.add_transaction(to(30, 1)).await.unwrap();
+    assert_eq!(blockchain.get_pending_transactions().await.len(), 2);
+}
+
+#[test]
+fn test_included_transactions_leave_the_pool() {
+    let (_, key) = User::generate(Amount::from_smv(100));
+    let mut mempool = Mempool::new();
+
+    let txs: Vec<_> = (0..3)
+        .map(|nonce| transfer(1, 2, nonce).into_transaction(&key))
+        .collect();
+    for tx in &txs {
+        mempool.insert(tx.clone()).unwrap();
+    }
+
+    mempool.remove_included(&txs[1..2]);
+    assert_eq!(mempool.pending(), vec![txs[2].clone()]);
+    assert_eq!(mempool.bytes(), txs[2].size());
+}
+
+#[tokio::test]
+async fn test_transactions_past_a_nonce_gap_wait_for_it_to_fill() {
+    let db = Database::in_memory().unwrap();
+    let (sender, key) = User::generate(Amount::from_smv(100));
+    let (receiver, _) = User::generate(Amount::ZERO);
+    db.add_user(&sender).unwrap();
+    db.add_user(&receiver).unwrap();
+    let blockchain = Blockchain::new(Arc::new(Mutex::new(db)));
+    let to = |amount, nonce| {
+        Transfer {
+            receiver: receiver.address,
+            ..transfer(amount, 1, nonce)
+        }
+        .into_transaction(&key)
+    };
+
+    blockchain.add_transaction(to(10, 2)).await.unwrap();
+    blockchain.add_transaction(to(10, 1)).await.unwrap();
+    assert_eq!(blockchain.pending_count().await, 0);
+    assert_eq!(blockchain.queued_count().await, 2);
+
+    blockchain.add_transaction(to(10, 0)).await.unwrap();
+    let nonces: Vec<u64> = blockchain
+        .get_pending_transactions()
+        .await
+        .iter()
+        .map(|tx| tx.payload.nonce)
+        .collect();
+    assert_eq!(nonces, vec![0, 1, 2]);
+    assert_eq!(blockchain.queued_count().await, 0);
+}
+
+#[tokio::test]
+async fn test_a_block_filling_the_gap_releases_queued_transactions() {
+    let db = Database::in_memory().unwrap();
+    let (sender, key) = User::generate(Amount::from_smv(100));
+    let (receiver, _) = User::generate(Amount::ZERO);
+    db.add_user(&sender).unwrap();
+    db.add_user(&receiver).unwrap();
+    let mut blockchain = Blockchain::new(Arc::new(Mutex::new(db)));
+    blockchain
+        .create_genesis_block_at(chrono::Utc::now().timestamp() - 60)
+        .await
+        .unwrap();
+    let to = |nonce| {
+        Transfer {
+            receiver: receiver.address,
+            ..transfer(10, 1, nonce)
+        }
+        .into_transaction(&key)
+    };
+
+    blockchain.add_transaction(to(1)).await.unwrap();
+    assert_eq!(blockchain.pending_count().await, 0);
+
+    // The first transaction reaches the chain without passing through
+    // this mempool, as it would in a peer's block.
+    let genesis = blockchain.get_latest_block().await.unwrap().unwrap();
+    let mut block = Block::new(genesis.hash(), 1, sender.address, vec![to(0)]);
+    block.header.state_root = blockchain.state_root_after(&block).await.unwrap();
+    blockchain.add_local_block(block).await.unwrap();
+
+    let pending = blockchain.get_pending_transactions().await;
+    assert_eq!(pending, vec![to(1)]);
+    assert_eq!(blockchain.queued_count().await, 0);
+}
+
+#[test]
+fn test_future_queue_is_bounded() {
+    let (_, key) = User::generate(Amount::from_smv(100));
+    let mut mempool = Mempool::with_config(MempoolConfig {
+        max_future_per_sender: 2,
+        max_future: 3,
+        ..MempoolConfig::default()
+    });
+
+    mempool
+        .park(transfer(1, 1, 2).into_transaction(&key), 0)
+        .unwrap();
+    assert_eq!(
+        mempool.park(transfer(1, 1, 3).into_transaction(&key), 0),
+        Err(BlockchainError::NonceGap {
+            expected: 0,
+            got: 3
+        })
+    );
+    assert_eq!(
+        mempool.park(transfer(1, 1, 2).into_transaction(&key), 0),
+        Err(BlockchainError::Underpriced)
+    );
+
+    for other in 0..2 {
+        let (_, key) = User::generate(Amount::from_smv(100));
+        mempool
+            .park(transfer(1, 1, other + 1).into_transaction(&key), 0)
+            .unwrap();
+    }
+    let (_, late) = User::generate(Amount::from_smv(100));
+    assert!(
+        mempool
+            .park(transfer(1, 1, 1).into_transaction(&late), 0)
+            .is_err()
+    );
+    assert_eq!(mempool.future_len(), 3);
+    assert!(mempool.is_empty());
+}
@@ -0,0 +1,41 @@
+use smvblock::{
+    amount::Amount,
+    monetary::{EmissionCurve, MonetaryPolicy},
+};
+
+#[test]
+fn test_halving_schedule() {
+    let policy = MonetaryPolicy {
+        max_supply: Amount::from_smv(1_000),
+        curve: EmissionCurve::Halving {
+            initial_reward: Amount::from_smv(4),
+            interval: 10,
+        },
+    };
+
+    assert_eq!(policy.block_reward(0, Amount::ZERO), Amount::from_smv(4));
+    assert_eq!(policy.block_reward(10, Amount::ZERO), Amount::from_smv(2));
+    assert_eq!(policy.block_reward(25, Amount::ZERO), Amount::from_smv(1));
+    assert_eq!(policy.block_reward(10_000, Amount::ZERO), Amount::ZERO);
+}
+
+#[test]
+fn test_reward_never_exceeds_max_supply() {
+    let policy = MonetaryPolicy {
+        max_supply: Amount::from_smv(100),
+        curve: EmissionCurve::FixedInflation {
+            annual_rate_bps: 1_000,
+            blocks_per_year: 1,
+        },
+    };
+
+    assert_eq!(
+        policy.block_reward(0, Amount::from_smv(50)),
+        Amount::from_smv(5)
+    );
+    assert_eq!(
+        policy.block_reward(0, Amount::from_smv(98)),
+        Amount::from_smv(2)
+    );
+    assert_eq!(policy.block_reward(0, Amount::from_smv(100)), Amount::ZERO);
+}
@@ -0,0 +1,1484 @@
+use ed25519_dalek::SigningKey;
+use futures::lock::Mutex;
+use rand::rngs::OsRng;
+use smvblock::{
+    amount::Amount,
+    blockchain::{Block, Transaction, TransactionLocation, Transfer, TxData, TxKind, User},
+    db::Database,
+    events::NodeEvent,
+    finality::Vote,
+    node::{Node, NodeType},
+    noise::{self, NoiseReader, NoiseWriter},
+    p2p::{
+        Backoff, Compression, ConnectionLimits, DEFAULT_CHAIN_ID, DisconnectReason, MAX_FRAME_SIZE,
+        Message, NODE_VERSION, P2P, PROTOCOL_VERSION, encode_frame, read_frame,
+    },
+    proxy::Socks5Proxy,
+    signer::BlockSigner,
+    sync::SyncState,
+};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::oneshot;
+
+/// The next message for the node, skipping the `Hello` of new peers.
+async fn next_gossip(p2p: &P2P) -> Message {
+    loop {
+        match p2p.next_message().await {
+            Some((_, Message::Hello { .. })) => continue,
+            Some((_, message)) => return message,
+            None => panic!("network closed"),
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_broadcast_reaches_connected_peer() {
+    let db = Arc::new(Mutex::new(Database::new(None, true).unwrap()));
+    let listener = P2P::new(db.clone(), NodeType::FullNode);
+    let dialer = P2P::new(db, NodeType::FullNode);
+
+    let addr = listener
+        .listen("127.0.0.1:0".parse().unwrap())
+        .await
+        .unwrap();
+    dialer.connect(addr).await.unwrap();
+
+    let (_, key) = User::generate(Amount::ZERO);
+    let vote = Vote::sign([3u8; 32], 4, &key);
+    dialer.broadcast(Message::Vote(vote.clone()), None).await;
+
+    let message = tokio::time::timeout(Duration::from_secs(5), next_gossip(&listener))
+        .await
+        .unwrap();
+    assert_eq!(message, Message::Vote(vote));
+}
+
+#[tokio::test]
+async fn test_traffic_is_counted_and_fast_peers_are_slowed_down() {
+    let db = Arc::new(Mutex::new(Database::new(None, true).unwrap()));
+    let mut listener = P2P::new(db.clone(), NodeType::FullNode);
+    let dialer = P2P::new(db, NodeType::FullNode);
+    let (_, key) = User::generate(Amount::ZERO);
+    let vote = Message::Vote(Vote::sign([3u8; 32], 4, &key));
+    let size = encode_frame(&vote, None).unwrap().len() as u64;
+    // Ten votes a second.
+    listener.set_peer_bandwidth(Some(size * 10));
+
+    let addr = listener
+        .listen("127.0.0.1:0".parse().unwrap())
+        .await
+        .unwrap();
+    dialer.connect(addr).await.unwrap();
+    let started = std::time::Instant::now();
+    for _ in 0..30 {
+        dialer.broadcast(vote.clone(), None).await;
+    }
+    for _ in 0..30 {
+        tokio::time::timeout(Duration::from_secs(10), next_gossip(&listener))
+            .await
+            .unwrap();
+    }
+    assert!(started.elapsed() >= Duration::from_secs(1));
+
+    let sent = dialer.peer_traffic().await[0].1.kinds["vote"];
+    let received = listener.peer_traffic().await[0].1.clone();
+    assert_eq!(sent.messages_sent, 30);
+    assert_eq!(sent.bytes_sent, 30 * size);
+    assert_eq!(received.kinds["vote"].messages_received, 30);
+    assert_eq!(received.kinds["vote"].bytes_received, 30 * size);
+    assert_eq!(
+        listener.total_traffic().await.bytes_received,
+        received.bytes_received
+    );
+}
+
+#[tokio::test]
+async fn test_shutdown_says_goodbye_to_peers() {
+    let db = Arc::new(Mutex::new(Database::new(None, true).unwrap()));
+    let listener = P2P::new(db.clone(), NodeType::FullNode);
+    let dialer = P2P::new(db, NodeType::FullNode);
+
+    let addr = listener
+        .listen("127.0.0.1:0".parse().unwrap())
+        .await
+        .unwrap();
+    dialer.connect(addr).await.unwrap();
+
+    dialer.shutdown().await;
+    assert!(dialer.peers().await.is_empty());
+    assert!(dialer.connect(addr).await.is_err());
+
+    // The listener drops the connection on goodbye.
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while !listener.peers().await.is_empty() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn test_discovery_dials_peers_learned_from_peers() {
+    let db = Arc::new(Mutex::new(Database::new(None, true).unwrap()));
+    let seed = P2P::new(db.clone(), NodeType::FullNode);
+    let first = P2P::new(db.clone(), NodeType::FullNode);
+    let second = P2P::new(db, NodeType::FullNode);
+
+    let localhost = "127.0.0.1:0".parse().unwrap();
+    let seed_addr = seed.listen(localhost).await.unwrap();
+    let first_addr = first.listen(localhost).await.unwrap();
+    second.listen(localhost).await.unwrap();
+
+    first.connect(seed_addr).await.unwrap();
+    second.connect(seed_addr).await.unwrap();
+
+    // The seed only learns where `first` listens once its hello arrives.
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while !second.known_peers().await.contains(&first_addr) {
+            second.discover(8).await;
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    })
+    .await
+    .unwrap();
+
+    second.discover(8).await;
+    assert!(second.peers().await.contains(&first_addr));
+}
+
+/// The addresses `seed` hands out to a newly connected node.
+async fn ask_for_peers(seed: SocketAddr) -> Vec<SocketAddr> {
+    let key = SigningKey::generate(&mut OsRng);
+    let (mut reader, mut writer) = handshake(seed, &key).await;
+    send(&mut writer, &hello(key.verifying_key().to_bytes())).await;
+    send(&mut writer, &Message::GetPeers).await;
+    tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            match receive(&mut reader).await {
+                Some(Message::Peers(peers)) => return peers,
+                Some(_) => continue,
+                None => panic!("connection closed"),
+            }
+        }
+    })
+    .await
+    .unwrap()
+}
+
+#[tokio::test]
+async fn test_seed_hands_out_peers_its_crawler_found_alive() {
+    let seed_db = Arc::new(Mutex::new(Database::new(None, true).unwrap()));
+    let seed = P2P::new(seed_db, NodeType::SeedNode);
+    let db = Arc::new(Mutex::new(Database::new(None, true).unwrap()));
+    let first = P2P::new(db.clone(), NodeType::FullNode);
+    let second = P2P::new(db, NodeType::FullNode);
+
+    let localhost = "127.0.0.1:0".parse().unwrap();
+    let seed_addr = seed.listen(localhost).await.unwrap();
+    let first_addr = first.listen(localhost).await.unwrap();
+    let second_addr = second.listen(localhost).await.unwrap();
+    first.connect(second_addr).await.unwrap();
+    first.connect(seed_addr).await.unwrap();
+
+    // The seed hears of `second` from `first` without dialing it.
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while !seed.known_peers().await.contains(&second_addr) {
+            seed.discover(0).await;
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    })
+    .await
+    .unwrap();
+    // Only peers found alive are handed out.
+    assert_eq!(ask_for_peers(seed_addr).await, vec![first_addr]);
+
+    seed.crawl(8).await;
+    assert!(!seed.peers().await.contains(&second_addr));
+    let mut peers = ask_for_peers(seed_addr).await;
+    peers.sort();
+    let mut expected = vec![first_addr, second_addr];
+    expected.sort();
+    assert_eq!(peers, expected);
+}
+
+#[tokio::test]
+async fn test_failed_dial_is_retried_with_backoff_until_the_peer_is_up() {
+    let backoff = Backoff {
+        initial: Duration::from_millis(50),
+        max: Duration::from_millis(200),
+    };
+    for failures in 1..10 {
+        let delay = backoff.delay(failures);
+        let full = (backoff.initial * 2u32.pow(failures - 1)).min(backoff.max);
+        assert!(delay > full / 2 && delay <= full, "{:?}", delay);
+    }
+
+    let db = Arc::new(Mutex::new(Database::new(None, true).unwrap()));
+    let mut dialer = P2P::new(db.clone(), NodeType::FullNode);
+    dialer.set_backoff(backoff);
+    let addr = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    assert!(dialer.connect(addr).await.is_err());
+    dialer.retry_connect(addr);
+
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    let listener = P2P::new(db, NodeType::FullNode);
+    listener.listen(addr).await.unwrap();
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while dialer.peers().await.is_empty() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn test_address_book_survives_restart() {
+    let db = Arc::new(Mutex::new(Database::new(None, true).unwrap()));
+    let listener = P2P::new(db.clone(), NodeType::FullNode);
+    let dialer = P2P::new(db.clone(), NodeType::FullNode);
+
+    let addr = listener
+        .listen("127.0.0.1:0".parse().unwrap())
+        .await
+        .unwrap();
+    dialer.connect(addr).await.unwrap();
+    dialer.shutdown().await;
+
+    let restarted = P2P::new(db, NodeType::LightNode);
+    assert!(restarted.load_peers().await.unwrap() >= 1);
+    assert!(restarted.known_peers().await.contains(&addr));
+}
+
+/// Completes a Noise handshake with `addr` as a bare client.
+async fn handshake(addr: SocketAddr, key: &SigningKey) -> (NoiseReader, NoiseWriter) {
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let (reader, writer, _) = noise::handshake(stream, true, &key.to_scalar_bytes())
+        .await
+        .unwrap();
+    (reader, writer)
+}
+
+async fn send(writer: &mut NoiseWriter, message: &Message) {
+    let _ = writer
+        .write_all(&encode_frame(message, None).unwrap())
+        .await;
+}
+
+fn hello(identity: [u8; 32]) -> Message {
+    Message::Hello {
+        identity,
+        chain_id: DEFAULT_CHAIN_ID.to_string(),
+        genesis: None,
+        listen_addr: None,
+        node_type: NodeType::FullNode,
+        compression: Vec::new(),
+        timestamp: chrono::Utc::now().timestamp_millis(),
+    }
+}
+
+/// Reads until the remote side closes the connection.
+async fn wait_for_close(reader: &mut NoiseReader) {
+    let mut byte = [0u8; 1];
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while reader.read_exact(&mut byte).await.unwrap_or(false) {}
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn test_peers_authenticate_with_identity_keys() {
+    let db = Arc::new(Mutex::new(Database::new(None, true).unwrap()));
+    let listener = P2P::new(db.clone(), NodeType::FullNode);
+    let dialer = P2P::new(db, NodeType::FullNode);
+
+    let addr = listener
+        .listen("127.0.0.1:0".parse().unwrap())
+        .await
+        .unwrap();
+    dialer.connect(addr).await.unwrap();
+
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while dialer.peer_identity(addr).await.is_none() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap();
+    assert_eq!(dialer.peer_identity(addr).await, Some(listener.identity()));
+}
+
+#[tokio::test]
+async fn test_hello_with_foreign_identity_disconnects_peer() {
+    let db = Arc::new(Mutex::new(Database::new(None, true).unwrap()));
+    let listener = P2P::new(db, NodeType::FullNode);
+    let addr = listener
+        .listen("127.0.0.1:0".parse().unwrap())
+        .await
+        .unwrap();
+
+    let key = SigningKey::generate(&mut OsRng);
+    let (mut reader, mut writer) = handshake(addr, &key).await;
+    send(&mut writer, &hello(listener.identity())).await;
+
+    wait_for_close(&mut reader).await;
+    assert!(listener.peers().await.is_empty());
+}
+
+#[tokio::test]
+async fn test_oversized_message_disconnects_peer() {
+    let db = Arc::new(Mutex::new(Database::new(None, true).unwrap()));
+    let listener = P2P::new(db, NodeType::FullNode);
+    let addr = listener
+        .listen("127.0.0.1:0".parse().unwrap())
+        .await
+        .unwrap();
+
+    let key = SigningKey::generate(&mut OsRng);
+    let (mut reader, mut writer) = handshake(addr, &key).await;
+    let oversized = (MAX_FRAME_SIZE as u32 + 1).to_be_bytes();
+    let _ = writer.write_all(&oversized).await;
+
+    wait_for_close(&mut reader).await;
+    assert!(listener.peers().await.is_empty());
+}
+
+#[tokio::test]
+async fn test_flooding_peer_is_disconnected() {
+    let db = Arc::new(Mutex::new(Database::new(None, true).unwrap()));
+    let listener = P2P::new(db, NodeType::FullNode);
+    let addr = listener
+        .listen("127.0.0.1:0".parse().unwrap())
+        .await
+        .unwrap();
+
+    let key = SigningKey::generate(&mut OsRng);
+    let (mut reader, mut writer) = handshake(addr, &key).await;
+    send(&mut writer, &hello(key.verifying_key().to_bytes())).await;
+    for _ in 0..50 {
+        send(&mut writer, &Message::GetPeers).await;
+    }
+
+    wait_for_close(&mut reader).await;
+    assert!(listener.peers().await.is_empty());
+}
+
+#[tokio::test]
+async fn test_peer_that_stops_reading_is_dropped() {
+    let db = Arc::new(Mutex::new(Database::new(None, true).unwrap()));
+    let listener = P2P::new(db, NodeType::FullNode);
+    let addr = listener
+        .listen("127.0.0.1:0".parse().unwrap())
+        .await
+        .unwrap();
+
+    let key = SigningKey::generate(&mut OsRng);
+    let (_reader, mut writer) = handshake(addr, &key).await;
+    send(&mut writer, &hello(key.verifying_key().to_bytes())).await;
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while listener.peers().await.is_empty() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap();
+
+    // Never read, so the socket buffers fill and then the peer's queue.
+    let peers = Message::Peers(vec!["127.0.0.1:9000".parse().unwrap(); 100]);
+    for _ in 0..100_000 {
+        if listener.peers().await.is_empty() {
+            break;
+        }
+        listener.broadcast(peers.clone(), None).await;
+    }
+    assert!(listener.peers().await.is_empty());
+}
+
+#[tokio::test]
+async fn test_unknown_protocol_version_disconnects_peer() {
+    let db = Arc::new(Mutex::new(Database::new(None, true).unwrap()));
+    let listener = P2P::new(db, NodeType::FullNode);
+    let addr = listener
+        .listen("127.0.0.1:0".parse().unwrap())
+        .await
+        .unwrap();
+
+    let key = SigningKey::generate(&mut OsRng);
+    let (mut reader, mut writer) = handshake(addr, &key).await;
+    let mut frame = encode_frame(&hello(key.verifying_key().to_bytes()), None).unwrap();
+    frame[4] = PROTOCOL_VERSION + 1;
+    let _ = writer.write_all(&frame).await;
+
+    wait_for_close(&mut reader).await;
+    assert!(listener.peers().await.is_empty());
+}
+
+#[tokio::test]
+async fn test_large_frames_are_compressed() {
+    let addrs = vec!["127.0.0.1:9000".parse().unwrap(); 100];
+    let message = Message::Peers(addrs);
+
+    let plain = encode_frame(&message, None).unwrap();
+    let compressed = encode_frame(&message, Some(Compression::Snappy)).unwrap();
+    assert!(compressed.len() < plain.len());
+
+    // Small messages are not worth compressing.
+    let small = encode_frame(&Message::GetPeers, Some(Compression::Snappy)).unwrap();
+    assert_eq!(small, encode_frame(&Message::GetPeers, None).unwrap());
+}
+
+#[tokio::test]
+async fn test_peers_without_compression_still_talk() {
+    let db = Arc::new(Mutex::new(Database::new(None, true).unwrap()));
+    let listener = P2P::new(db.clone(), NodeType::FullNode);
+    let mut dialer = P2P::new(db, NodeType::FullNode);
+    dialer.set_compression(false);
+
+    let addr = listener
+        .listen("127.0.0.1:0".parse().unwrap())
+        .await
+        .unwrap();
+    dialer.connect(addr).await.unwrap();
+
+    let (_, key) = User::generate(Amount::ZERO);
+    let vote = Vote::sign([5u8; 32], 1, &key);
+    dialer.broadcast(Message::Vote(vote.clone()), None).await;
+
+    let message = tokio::time::timeout(Duration::from_secs(5), next_gossip(&listener))
+        .await
+        .unwrap();
+    assert_eq!(message, Message::Vote(vote));
+}
+
+fn temp_node(name: &str) -> (Node, std::path::PathBuf) {
+    let path = std::env::temp_dir().join(format!("smvblock-{}-{}.db", name, rand::random::<u64>()));
+    let db = Database::new(path.to_str(), false).unwrap();
+    (Node::with_database(NodeType::FullNode, db), path)
+}
+
+#[tokio::test]
+async fn test_transactions_and_blocks_reach_peers() {
+    let (mut producer, producer_path) = temp_node("producer");
+    let (follower, follower_path) = temp_node("follower");
+
+    let (user, key) = User::generate(Amount::from_smv(100));
+    let signer = BlockSigner::Local(key.clone());
+    let (receiver, _) = User::generate(Amount::ZERO);
+    for node in [&producer, &follower] {
+        node.add_user(user.clone()).await.unwrap();
+        node.add_user(receiver.clone()).await.unwrap();
+        node.stake(user.address, Amount::from_smv(50))
+            .await
+            .unwrap();
+    }
+
+    let localhost = "127.0.0.1:0".parse().unwrap();
+    let addr = producer.start_network(localhost).await.unwrap();
+    follower.start_network(localhost).await.unwrap();
+    follower.connect(addr).await.unwrap();
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while producer.p2p.peers().await.is_empty() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap();
+
+    // Submitted to the follower, gossiped to the producer.
+    follower
+        .send_transaction(key, receiver.address, Amount::from_smv(10))
+        .await
+        .unwrap();
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while producer
+            .blockchain
+            .get_pending_transactions()
+            .await
+            .is_empty()
+        {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap();
+    let hash = producer.produce_signed_block(&signer).await.unwrap();
+
+    let block = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            if let Some(block) = follower.blockchain.get_full_block(hash).await.unwrap() {
+                return block;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap();
+    assert_eq!(block.transactions.len(), 1);
+
+    let received = follower
+        .blockchain
+        .get_user(&receiver.address)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(received.balance, Amount::from_smv(10));
+    assert!(
+        follower
+            .blockchain
+            .get_pending_transactions()
+            .await
+            .is_empty()
+    );
+
+    let _ = std::fs::remove_file(producer_path);
+    let _ = std::fs::remove_file(follower_path);
+}
+
+#[tokio::test]
+async fn test_late_joiner_syncs_missing_blocks() {
+    let (mut producer, producer_path) = temp_node("producer");
+    let (late, late_path) = temp_node("late");
+
+    let (user, key) = User::generate(Amount::from_smv(100));
+    let signer = BlockSigner::Local(key.clone());
+    let (receiver, _) = User::generate(Amount::ZERO);
+    for node in [&producer, &late] {
+        node.add_user(user.clone()).await.unwrap();
+        node.add_user(receiver.clone()).await.unwrap();
+        node.stake(user.address, Amount::from_smv(50))
+            .await
+            .unwrap();
+    }
+
+    producer
+        .send_transaction(key, receiver.address, Amount::from_smv(10))
+        .await
+        .unwrap();
+    let mut head = [0u8; 32];
+    for _ in 0..3 {
+        head = producer.produce_signed_block(&signer).await.unwrap();
+    }
+
+    let localhost = "127.0.0.1:0".parse().unwrap();
+    let addr = producer.start_network(localhost).await.unwrap();
+    late.start_network(localhost).await.unwrap();
+    late.connect(addr).await.unwrap();
+
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while late.blockchain.get_block(head).await.unwrap().is_none() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap();
+    assert_eq!(late.blockchain.get_blocks().await.unwrap().len(), 3);
+    assert_eq!(
+        late.blockchain.state_root().await.unwrap(),
+        producer.blockchain.state_root().await.unwrap()
+    );
+
+    let _ = std::fs::remove_file(producer_path);
+    let _ = std::fs::remove_file(late_path);
+}
+
+#[tokio::test]
+async fn test_sync_downloads_blocks_from_several_peers() {
+    let (mut producer, producer_path) = temp_node("parallel-producer");
+    let (mirror, mirror_path) = temp_node("parallel-mirror");
+    let (late, late_path) = temp_node("parallel-late");
+
+    let (user, key) = User::generate(Amount::from_smv(100));
+    let signer = BlockSigner::Local(key.clone());
+    for node in [&producer, &mirror, &late] {
+        node.add_user(user.clone()).await.unwrap();
+        node.stake(user.address, Amount::from_smv(50))
+            .await
+            .unwrap();
+    }
+
+    // More blocks than one peer is asked for at a time.
+    let mut head = [0u8; 32];
+    for _ in 0..40 {
+        head = producer.produce_signed_block(&signer).await.unwrap();
+    }
+
+    let localhost = "127.0.0.1:0".parse().unwrap();
+    let producer_addr = producer.start_network(localhost).await.unwrap();
+    let mirror_addr = mirror.start_network(localhost).await.unwrap();
+    late.start_network(localhost).await.unwrap();
+
+    let synced = |node: &Node| {
+        let blockchain = node.blockchain.clone();
+        async move {
+            tokio::time::timeout(Duration::from_secs(10), async {
+                while blockchain.get_block(head).await.unwrap().is_none() {
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+            })
+            .await
+            .unwrap();
+        }
+    };
+    mirror.connect(producer_addr).await.unwrap();
+    synced(&mirror).await;
+
+    late.connect(producer_addr).await.unwrap();
+    late.connect(mirror_addr).await.unwrap();
+    synced(&late).await;
+    assert_eq!(late.blockchain.get_blocks().await.unwrap().len(), 40);
+    assert_eq!(
+        late.blockchain.state_root().await.unwrap(),
+        producer.blockchain.state_root().await.unwrap()
+    );
+
+    for path in [producer_path, mirror_path, late_path] {
+        let _ = std::fs::remove_file(path);
+    }
+}
+
+#[tokio::test]
+async fn test_new_node_starts_from_state_snapshot() {
+    let (mut producer, producer_path) = temp_node("snapshot-producer");
+    let (mut late, late_path) = temp_node("snapshot-late");
+    late.set_snapshot_distance(Some(10));
+
+    let (user, key) = User::generate(Amount::from_smv(100));
+    let (receiver, _) = User::generate(Amount::ZERO);
+    producer.add_user(user.clone()).await.unwrap();
+    producer.add_user(receiver.clone()).await.unwrap();
+    producer
+        .stake(user.address, Amount::from_smv(50))
+        .await
+        .unwrap();
+
+    producer
+        .send_transaction(key, receiver.address, Amount::from_smv(10))
+        .await
+        .unwrap();
+    let first = producer.produce_block().await.unwrap();
+    let mut head = first;
+    for _ in 0..20 {
+        head = producer.produce_block().await.unwrap();
+    }
+
+    let localhost = "127.0.0.1:0".parse().unwrap();
+    let addr = producer.start_network(localhost).await.unwrap();
+    late.start_network(localhost).await.unwrap();
+    late.connect(addr).await.unwrap();
+
+    tokio::time::timeout(Duration::from_secs(10), async {
+        while late.blockchain.get_block(head).await.unwrap().is_none() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap();
+
+    // The accounts came from the snapshot, not from replaying the blocks.
+    assert_eq!(late.blockchain.get_blocks().await.unwrap().len(), 21);
+    let replayed = late
+        .blockchain
+        .get_full_block(first)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(replayed.transactions.is_empty());
+    assert_eq!(
+        late.blockchain.state_root().await.unwrap(),
+        producer.blockchain.state_root().await.unwrap()
+    );
+    assert_eq!(
+        late.blockchain.account_nonce(&user.address).await.unwrap(),
+        1
+    );
+
+    let _ = std::fs::remove_file(producer_path);
+    let _ = std::fs::remove_file(late_path);
+}
+
+#[tokio::test]
+async fn test_checkpoint_pins_the_synced_chain() {
+    let (mut producer, producer_path) = temp_node("checkpoint-producer");
+    let (mut trusting, trusting_path) = temp_node("checkpoint-trusting");
+    let (mut wary, wary_path) = temp_node("checkpoint-wary");
+
+    let (user, key) = User::generate(Amount::from_smv(100));
+    let signer = BlockSigner::Local(key.clone());
+    for node in [&producer, &trusting, &wary] {
+        node.add_user(user.clone()).await.unwrap();
+        node.stake(user.address, Amount::from_smv(50))
+            .await
+            .unwrap();
+    }
+
+    let mut hashes = Vec::new();
+    for _ in 0..10 {
+        hashes.push(producer.produce_signed_block(&signer).await.unwrap());
+    }
+    trusting.set_checkpoint(Some((5, hashes[5])));
+    wary.set_checkpoint(Some((5, [7u8; 32])));
+
+    let localhost = "127.0.0.1:0".parse().unwrap();
+    let addr = producer.start_network(localhost).await.unwrap();
+    for node in [&trusting, &wary] {
+        node.start_network(localhost).await.unwrap();
+        node.connect(addr).await.unwrap();
+    }
+
+    let head = hashes[9];
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while trusting.blockchain.get_block(head).await.unwrap().is_none() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap();
+    assert_eq!(
+        trusting.blockchain.state_root().await.unwrap(),
+        producer.blockchain.state_root().await.unwrap()
+    );
+
+    // A chain without the checkpointed block is not followed at all.
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert!(wary.blockchain.get_blocks().await.unwrap().is_empty());
+
+    for path in [producer_path, trusting_path, wary_path] {
+        let _ = std::fs::remove_file(path);
+    }
+}
+
+#[tokio::test]
+async fn test_node_reorganizes_onto_a_longer_fork() {
+    let (mut short, short_path) = temp_node("fork-short");
+    let (mut long, long_path) = temp_node("fork-long");
+
+    let (user, key) = User::generate(Amount::from_smv(100));
+    let signer = BlockSigner::Local(key.clone());
+    let (receiver, _) = User::generate(Amount::ZERO);
+    for node in [&short, &long] {
+        node.add_user(user.clone()).await.unwrap();
+        node.add_user(receiver.clone()).await.unwrap();
+        node.stake(user.address, Amount::from_smv(50))
+            .await
+            .unwrap();
+    }
+
+    // Both chains share their first block, then go their own ways.
+    let shared = short.produce_signed_block(&signer).await.unwrap();
+    let block = short
+        .blockchain
+        .get_full_block(shared)
+        .await
+        .unwrap()
+        .unwrap();
+    long.blockchain.add_block(block).await.unwrap();
+    let abandoned = short.produce_signed_block(&signer).await.unwrap();
+    long.send_transaction(key, receiver.address, Amount::from_smv(10))
+        .await
+        .unwrap();
+    long.produce_signed_block(&signer).await.unwrap();
+    let head = long.produce_signed_block(&signer).await.unwrap();
+
+    let mut events = short.subscribe();
+    let localhost = "127.0.0.1:0".parse().unwrap();
+    let addr = long.start_network(localhost).await.unwrap();
+    short.start_network(localhost).await.unwrap();
+    short.connect(addr).await.unwrap();
+
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while short.blockchain.get_block(head).await.unwrap().is_none() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap();
+    assert!(
+        short
+            .blockchain
+            .get_block(abandoned)
+            .await
+            .unwrap()
+            .is_none()
+    );
+    assert_eq!(
+        short.blockchain.state_root().await.unwrap(),
+        long.blockchain.state_root().await.unwrap()
+    );
+    let (peer, reorg) = tokio::time::timeout(Duration::from_secs(1), async {
+        let mut peer = None;
+        loop {
+            match events.recv().await {
+                Ok(NodeEvent::PeerConnected { node_id, .. }) => peer = Some(node_id),
+                Ok(NodeEvent::ReorgHappened {
+                    old_head,
+                    new_head,
+                    depth,
+                }) => return (peer, (old_head, new_head, depth)),
+                _ => {}
+            }
+        }
+    })
+    .await
+    .unwrap();
+    assert_eq!(peer, Some(long.p2p.identity()));
+    assert_eq!(reorg, (abandoned, head, 1));
+
+    // The shorter chain never replaces the longer one.
+    assert!(
+        long.blockchain
+            .get_block(abandoned)
+            .await
+            .unwrap()
+            .is_none()
+    );
+
+    let _ = std::fs::remove_file(short_path);
+    let _ = std::fs::remove_file(long_path);
+}
+
+#[tokio::test]
+async fn test_node_identity_survives_restart() {
+    let (node, path) = temp_node("identity");
+    let identity = node.p2p.identity();
+    drop(node);
+
+    let db = Database::new(path.to_str(), false).unwrap();
+    let restarted = Node::with_database(NodeType::FullNode, db);
+    assert_eq!(restarted.p2p.identity(), identity);
+
+    let _ = std::fs::remove_file(path);
+}
+
+#[tokio::test]
+async fn test_second_connection_from_a_node_is_refused() {
+    let db = Arc::new(Mutex::new(Database::new(None, true).unwrap()));
+    let listener = P2P::new(db, NodeType::FullNode);
+    let addr = listener
+        .listen("127.0.0.1:0".parse().unwrap())
+        .await
+        .unwrap();
+
+    let key = SigningKey::generate(&mut OsRng);
+    let identity = key.verifying_key().to_bytes();
+    let (_first_reader, mut first) = handshake(addr, &key).await;
+    send(&mut first, &hello(identity)).await;
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while listener.peer_addr(&identity).await.is_none() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap();
+
+    let (mut reader, mut second) = handshake(addr, &key).await;
+    send(&mut second, &hello(identity)).await;
+    wait_for_close(&mut reader).await;
+    assert_eq!(listener.peers().await.len(), 1);
+}
+
+#[tokio::test]
+async fn test_nodes_dialing_each_other_keep_one_connection() {
+    let db = Arc::new(Mutex::new(Database::new(None, true).unwrap()));
+    let a = P2P::new(db.clone(), NodeType::FullNode);
+    let b = P2P::new(db, NodeType::FullNode);
+    let a_addr = a.listen("127.0.0.1:0".parse().unwrap()).await.unwrap();
+    let b_addr = b.listen("127.0.0.1:0".parse().unwrap()).await.unwrap();
+
+    assert!(a.connect(a_addr).await.is_err());
+
+    let (to_b, to_a) = tokio::join!(a.connect(b_addr), b.connect(a_addr));
+    to_b.unwrap();
+    to_a.unwrap();
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while a.peer_addr(&b.identity()).await.is_none()
+            || b.peer_addr(&a.identity()).await.is_none()
+            || a.peers().await.len() != 1
+            || b.peers().await.len() != 1
+        {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert_eq!(a.peers().await.len(), 1);
+    assert_eq!(b.peers().await.len(), 1);
+
+    assert!(a.connect(b_addr).await.is_err());
+}
+
+#[tokio::test]
+async fn test_peers_on_another_chain_are_refused() {
+    let (mut listener, listener_path) = temp_node("genesis");
+    let addr = listener
+        .start_network("127.0.0.1:0".parse().unwrap())
+        .await
+        .unwrap();
+    let (user, _) = User::generate(Amount::from_smv(100));
+    listener.add_user(user.clone()).await.unwrap();
+    listener
+        .stake(user.address, Amount::from_smv(50))
+        .await
+        .unwrap();
+    let genesis = listener.produce_block().await.unwrap();
+
+    let key = SigningKey::generate(&mut OsRng);
+    let identity = key.verifying_key().to_bytes();
+    let refused = [
+        Message::Hello {
+            identity,
+            chain_id: "devnet".to_string(),
+            genesis: Some(genesis),
+            listen_addr: None,
+            node_type: NodeType::FullNode,
+            compression: Vec::new(),
+            timestamp: chrono::Utc::now().timestamp_millis(),
+        },
+        Message::Hello {
+            identity,
+            chain_id: DEFAULT_CHAIN_ID.to_string(),
+            genesis: Some([7u8; 32]),
+            listen_addr: None,
+            node_type: NodeType::FullNode,
+            compression: Vec::new(),
+            timestamp: chrono::Utc::now().timestamp_millis(),
+        },
+    ];
+    for hello in refused {
+        let (mut reader, mut writer) = handshake(addr, &key).await;
+        send(&mut writer, &hello).await;
+        wait_for_close(&mut reader).await;
+    }
+    assert!(listener.p2p.peers().await.is_empty());
+
+    listener.shutdown().await.unwrap();
+    let _ = std::fs::remove_file(listener_path);
+}
+
+/// The next message from a bare client connection, or `None` once closed.
+async fn receive(reader: &mut NoiseReader) -> Option<Message> {
+    let payload = read_frame(reader).await.ok()??;
+    bincode::serde::decode_from_slice(&payload, bincode::config::standard())
+        .ok()
+        .map(|(message, _)| message)
+}
+
+#[tokio::test]
+async fn test_inbound_limit_turns_away_all_but_reserved_peers() {
+    let db = Arc::new(Mutex::new(Database::new(None, true).unwrap()));
+    let reserved = SigningKey::generate(&mut OsRng);
+    let mut listener = P2P::new(db, NodeType::FullNode);
+    listener.set_connection_limits(ConnectionLimits {
+        max_inbound: 1,
+        reserved: [reserved.verifying_key().to_bytes()].into(),
+        ..ConnectionLimits::default()
+    });
+    let addr = listener
+        .listen("127.0.0.1:0".parse().unwrap())
+        .await
+        .unwrap();
+
+    let listener = &listener;
+    let wait_for_peers = |count| async move {
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while listener.peers().await.len() != count {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+    };
+
+    let first = SigningKey::generate(&mut OsRng);
+    let (_first_reader, mut first_writer) = handshake(addr, &first).await;
+    send(&mut first_writer, &hello(first.verifying_key().to_bytes())).await;
+    wait_for_peers(1).await;
+
+    let key = SigningKey::generate(&mut OsRng);
+    let (mut reader, mut writer) = handshake(addr, &key).await;
+    send(&mut writer, &hello(key.verifying_key().to_bytes())).await;
+    let mut refusal = None;
+    while let Some(message) = receive(&mut reader).await {
+        refusal = Some(message);
+    }
+    assert_eq!(
+        refusal,
+        Some(Message::Disconnect(DisconnectReason::TooManyPeers))
+    );
+    wait_for_peers(1).await;
+
+    let (_reserved_reader, mut reserved_writer) = handshake(addr, &reserved).await;
+    send(
+        &mut reserved_writer,
+        &hello(reserved.verifying_key().to_bytes()),
+    )
+    .await;
+    wait_for_peers(2).await;
+}
+
+/// A minimal SOCKS5 proxy that serves one IPv4 `CONNECT` and reports its
+/// target.
+async fn socks5_proxy() -> (SocketAddr, oneshot::Receiver<SocketAddr>) {
+    let (target_tx, target_rx) = oneshot::channel();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let (mut client, _) = listener.accept().await.unwrap();
+        let mut greeting = [0u8; 3];
+        client.read_exact(&mut greeting).await.unwrap();
+        client.write_all(&[5, 0]).await.unwrap();
+        let mut request = [0u8; 10];
+        client.read_exact(&mut request).await.unwrap();
+        let ip: [u8; 4] = request[4..8].try_into().unwrap();
+        let port = u16::from_be_bytes([request[8], request[9]]);
+        let target_addr = SocketAddr::from((ip, port));
+        let mut target = TcpStream::connect(target_addr).await.unwrap();
+        let _ = target_tx.send(target_addr);
+        client
+            .write_all(&[5, 0, 0, 1, 0, 0, 0, 0, 0, 0])
+            .await
+            .unwrap();
+        let _ = tokio::io::copy_bidirectional(&mut client, &mut target).await;
+    });
+    (addr, target_rx)
+}
+
+#[tokio::test]
+async fn test_dialing_through_socks5_proxy() {
+    let db = Arc::new(Mutex::new(Database::new(None, true).unwrap()));
+    let listener = P2P::new(db.clone(), NodeType::FullNode);
+    let mut dialer = P2P::new(db, NodeType::FullNode);
+    let addr = listener
+        .listen("127.0.0.1:0".parse().unwrap())
+        .await
+        .unwrap();
+
+    let (proxy_addr, target) = socks5_proxy().await;
+    let proxy: Socks5Proxy = format!("socks5://{}", proxy_addr).parse().unwrap();
+    dialer.set_proxy(Some(proxy));
+    dialer.connect(addr).await.unwrap();
+    assert_eq!(target.await.unwrap(), addr);
+
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while listener.peer_addr(&dialer.identity()).await.is_none() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn test_listening_on_ipv4_and_ipv6_advertises_external_addr() {
+    let db = Arc::new(Mutex::new(Database::new(None, true).unwrap()));
+    let external: SocketAddr = "203.0.113.7:4001".parse().unwrap();
+    let mut listener = P2P::new(db.clone(), NodeType::FullNode);
+    listener.set_external_addr(Some(external));
+    let v4 = listener
+        .listen("127.0.0.1:0".parse().unwrap())
+        .await
+        .unwrap();
+    let v6 = listener
+        .listen(SocketAddr::new("::1".parse().unwrap(), v4.port()))
+        .await
+        .unwrap();
+    assert_eq!(v6.port(), v4.port());
+
+    let dialer = P2P::new(db, NodeType::FullNode);
+    dialer.connect(v6).await.unwrap();
+
+    let key = SigningKey::generate(&mut OsRng);
+    let (mut reader, _writer) = handshake(v4, &key).await;
+    match receive(&mut reader).await {
+        Some(Message::Hello { listen_addr, .. }) => assert_eq!(listen_addr, Some(external)),
+        other => panic!("expected hello, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn test_pruned_node_drops_old_blocks_and_says_so() {
+    let path = std::env::temp_dir().join(format!("smvblock-pruned-{}.db", rand::random::<u64>()));
+    let db = Database::new(path.to_str(), false).unwrap();
+    let mut node = Node::with_database(NodeType::PrunedNode, db);
+    node.set_prune_depth(1);
+
+    let (user, key) = User::generate(Amount::from_smv(100));
+    let (receiver, _) = User::generate(Amount::ZERO);
+    node.add_user(user.clone()).await.unwrap();
+    node.add_user(receiver.clone()).await.unwrap();
+    node.stake(user.address, Amount::from_smv(50))
+        .await
+        .unwrap();
+    let tx = Transfer {
+        receiver: receiver.address,
+        amount: Amount::from_smv(10),
+        fee: Amount::ZERO,
+        nonce: 0,
+        kind: TxKind::Transfer,
+        data: TxData::None,
+    }
+    .into_transaction(&key);
+    node.blockchain.add_transaction(tx.clone()).await.unwrap();
+    let first = node.produce_block().await.unwrap();
+    for _ in 0..2 {
+        node.produce_block().await.unwrap();
+    }
+
+    let addr = node
+        .start_network("127.0.0.1:0".parse().unwrap())
+        .await
+        .unwrap();
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while node.blockchain.oldest_block().await.unwrap() != 2 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap();
+    let block = node
+        .blockchain
+        .get_full_block(first)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(block.transactions.is_empty());
+    // The pruned transaction's nonce stays used.
+    assert!(node.blockchain.add_transaction(tx).await.is_err());
+
+    let key = SigningKey::generate(&mut OsRng);
+    let (mut reader, mut writer) = handshake(addr, &key).await;
+    send(&mut writer, &hello(key.verifying_key().to_bytes())).await;
+    let status = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            match receive(&mut reader).await {
+                Some(Message::Status { oldest_block, .. }) => return oldest_block,
+                Some(_) => continue,
+                None => panic!("connection closed"),
+            }
+        }
+    })
+    .await
+    .unwrap();
+    assert_eq!(status, 2);
+
+    let _ = std::fs::remove_file(path);
+}
+
+#[tokio::test]
+async fn test_status_reports_the_node_and_its_sync_state() {
+    let (mut node, path) = temp_node("status");
+    let (validator, _) = User::generate(Amount::from_smv(100));
+    node.add_user(validator.clone()).await.unwrap();
+    node.stake(validator.address, Amount::from_smv(50))
+        .await
+        .unwrap();
+    node.produce_block().await.unwrap();
+    node.produce_block().await.unwrap();
+    let addr = node
+        .start_network("127.0.0.1:0".parse().unwrap())
+        .await
+        .unwrap();
+
+    let key = SigningKey::generate(&mut OsRng);
+    let (mut reader, mut writer) = handshake(addr, &key).await;
+    send(&mut writer, &hello(key.verifying_key().to_bytes())).await;
+    let status = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            match receive(&mut reader).await {
+                Some(status @ Message::Status { .. }) => return status,
+                Some(_) => continue,
+                None => panic!("connection closed"),
+            }
+        }
+    })
+    .await
+    .unwrap();
+    let Message::Status {
+        height,
+        peers,
+        pending_transactions,
+        sync,
+        version,
+        ..
+    } = status
+    else {
+        unreachable!()
+    };
+    assert_eq!((height, peers, pending_transactions), (2, 1, 0));
+    assert_eq!(sync, SyncState::Synced);
+    assert_eq!(version, NODE_VERSION);
+
+    // A peer claiming a longer chain puts the node to syncing.
+    send(
+        &mut writer,
+        &Message::Status {
+            height: 50,
+            head: [9; 32],
+            oldest_block: 0,
+            finalized_height: None,
+            peers: 1,
+            pending_transactions: 0,
+            sync: SyncState::Synced,
+            version: NODE_VERSION.to_string(),
+            uptime: 0,
+        },
+    )
+    .await;
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while node.sync_state()
+            != (SyncState::Syncing {
+                current: 2,
+                target: 50,
+            })
+        {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap();
+
+    node.shutdown().await.unwrap();
+    let _ = std::fs::remove_file(path);
+}
+
+/// Waits for the node to know of `count` transactions of `address`.
+async fn wait_for_transactions(
+    node: &Node,
+    address: [u8; 32],
+    count: usize,
+) -> Vec<(Transaction, TransactionLocation)> {
+    tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            let found = node
+                .blockchain
+                .get_transactions_by_address(&address, None, 10)
+                .await
+                .unwrap();
+            if found.len() >= count {
+                return found;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap()
+}
+
+#[tokio::test]
+async fn test_light_node_proves_watched_transactions_against_its_headers() {
+    let (mut producer, producer_path) = temp_node("producer");
+    let light_path =
+        std::env::temp_dir().join(format!("smvblock-light-{}.db", rand::random::<u64>()));
+    let db = Database::new(light_path.to_str(), false).unwrap();
+    let light = Node::with_database(NodeType::LightNode, db);
+
+    let (user, key) = User::generate(Amount::from_smv(100));
+    let (receiver, _) = User::generate(Amount::ZERO);
+    let (other, _) = User::generate(Amount::ZERO);
+    for account in [&user, &receiver, &other] {
+        producer.add_user(account.clone()).await.unwrap();
+    }
+    producer
+        .stake(user.address, Amount::from_smv(50))
+        .await
+        .unwrap();
+    // One transfer in the first block and two in the second.
+    for (nonce, to) in [receiver.address, other.address, receiver.address]
+        .into_iter()
+        .enumerate()
+    {
+        let tx = Transfer {
+            receiver: to,
+            amount: Amount::from_smv(1),
+            fee: Amount::ZERO,
+            nonce: nonce as u64,
+            kind: TxKind::Transfer,
+            data: TxData::None,
+        }
+        .into_transaction(&key);
+        producer.blockchain.add_transaction(tx).await.unwrap();
+        if nonce != 1 {
+            producer.produce_block().await.unwrap();
+        }
+    }
+    let head = producer.produce_block().await.unwrap();
+
+    assert!(
+        light
+            .blockchain
+            .watch_address(&receiver.address)
+            .await
+            .unwrap()
+    );
+    assert!(
+        !light
+            .blockchain
+            .watch_address(&receiver.address)
+            .await
+            .unwrap()
+    );
+    let localhost = "127.0.0.1:0".parse().unwrap();
+    let addr = producer.start_network(localhost).await.unwrap();
+    light.start_network(localhost).await.unwrap();
+    light.connect(addr).await.unwrap();
+
+    let received = wait_for_transactions(&light, receiver.address, 2).await;
+    assert_eq!(received.len(), 2);
+    assert!(
+        received
+            .iter()
+            .all(|(tx, _)| tx.payload.receiver == receiver.address)
+    );
+    assert_eq!(light.blockchain.chain_head().get(), Some((head, 2)));
+    let (_, location) = received[0];
+    assert_eq!((location.height, location.index), (1, 1));
+    // Only the headers and the watched transactions were fetched.
+    let block = light
+        .blockchain
+        .get_full_block(location.block_hash)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(block.transactions.len(), 1);
+    assert!(
+        light
+            .blockchain
+            .get_user(&user.address)
+            .await
+            .unwrap()
+            .is_none()
+    );
+
+    // An address watched later is scanned for from the first block, once
+    // sync next runs.
+    light.blockchain.watch_address(&user.address).await.unwrap();
+    producer.produce_block().await.unwrap();
+    let sent = wait_for_transactions(&light, user.address, 3).await;
+    assert_eq!(sent.len(), 3);
+    assert!(
+        sent.iter()
+            .all(|(tx, _)| tx.sender_address() == user.address)
+    );
+
+    let _ = std::fs::remove_file(producer_path);
+    let _ = std::fs::remove_file(light_path);
+}
+
+#[tokio::test]
+async fn test_node_with_a_skewed_clock_stops_producing_blocks() {
+    let mut node = Node::new(NodeType::FullNode, true).unwrap();
+    let (user, _) = User::generate(Amount::from_smv(100));
+    node.add_user(user.clone()).await.unwrap();
+    node.stake(user.address, Amount::from_smv(50))
+        .await
+        .unwrap();
+    let addr = node
+        .start_network("127.0.0.1:0".parse().unwrap())
+        .await
+        .unwrap();
+
+    // Peers whose clocks are five minutes ahead of ours.
+    let mut connections = Vec::new();
+    for _ in 0..3 {
+        let key = SigningKey::generate(&mut OsRng);
+        let (reader, mut writer) = handshake(addr, &key).await;
+        let mut hello = hello(key.verifying_key().to_bytes());
+        if let Message::Hello { timestamp, .. } = &mut hello {
+            *timestamp += 5 * 60 * 1000;
+        }
+        send(&mut writer, &hello).await;
+        connections.push((reader, writer));
+    }
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while node.p2p.clock_offset().await.is_none() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap();
+
+    let offset = node.p2p.clock_offset().await.unwrap();
+    assert!((290_000..=300_000).contains(&offset), "{}", offset);
+    let error = node.produce_block().await.unwrap_err();
+    assert!(error.contains("clock"), "{}", error);
+
+    // With one of them gone there are too few to judge by.
+    drop(connections.pop());
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while node.p2p.clock_offset().await.is_some() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap();
+    node.produce_block().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_rejected_block_is_not_fetched_again_when_re_announced() {
+    let (mut node, path) = temp_node("dedupe");
+    let (user, key) = User::generate(Amount::from_smv(100));
+    node.add_user(user.clone()).await.unwrap();
+    node.stake(user.address, Amount::from_smv(50))
+        .await
+        .unwrap();
+    let genesis = node.produce_block().await.unwrap();
+    let addr = node
+        .start_network("127.0.0.1:0".parse().unwrap())
+        .await
+        .unwrap();
+
+    let mut forged = Transfer {
+        receiver: user.address,
+        amount: Amount::from_smv(1),
+        fee: Amount::ZERO,
+        nonce: 0,
+        kind: TxKind::Transfer,
+        data: TxData::None,
+    }
+    .into_transaction(&key);
+    forged.signature[0] ^= 1;
+    let block = Block::new(genesis, 1, user.address, vec![forged]);
+    let announcement = Message::NewBlock {
+        header: block.header.clone(),
+    };
+
+    let peer = SigningKey::generate(&mut OsRng);
+    let (mut reader, mut writer) = handshake(addr, &peer).await;
+    send(&mut writer, &hello(peer.verifying_key().to_bytes())).await;
+    send(&mut writer, &announcement).await;
+    loop {
+        if let Message::GetBlockBody { hash } = receive(&mut reader).await.unwrap() {
+            assert_eq!(hash, block.hash());
+            break;
+        }
+    }
+    let body = Message::BlockBody {
+        hash: block.hash(),
+        transactions: block.transactions.clone(),
+    };
+    send(&mut writer, &body).await;
+
+    // Announced again, then a request the node answers in turn.
+    send(&mut writer, &announcement).await;
+    send(&mut writer, &Message::GetBlockBody { hash: genesis }).await;
+    loop {
+        match receive(&mut reader).await.unwrap() {
+            Message::GetBlockBody { .. } => panic!("fetched a rejected block again"),
+            Message::BlockBody { hash, .. } if hash == genesis => break,
+            _ => {}
+        }
+    }
+
+    node.shutdown().await.unwrap();
+    let _ = std::fs::remove_file(path);
+}
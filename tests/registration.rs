@@ -0,0 +1,218 @@
+use chrono::Utc;
+use ed25519_dalek::{SigningKey, VerifyingKey};
+use smvblock::{
+    amount::Amount,
+    blockchain::{Address, Block, Transaction, Transfer, TxData, TxKind, User},
+    error::BlockchainError,
+    finality::{Vote, VoteOutcome},
+    node::{Node, NodeType},
+    staking::{Commission, Registration},
+    wallet,
+};
+
+fn key_tx(key: &SigningKey, kind: TxKind, consensus_key: &SigningKey, nonce: u64) -> Transaction {
+    let consensus_key = consensus_key.verifying_key().to_bytes();
+    let data = match kind {
+        TxKind::RegisterValidator => TxData::Registration(Registration {
+            consensus_key,
+            commission: Commission { bps: 500 },
+        }),
+        _ => TxData::ConsensusKey(consensus_key),
+    };
+    Transfer {
+        receiver: wallet::key_address(key),
+        amount: Amount::ZERO,
+        fee: Amount::ZERO,
+        nonce,
+        kind,
+        data,
+    }
+    .into_transaction(key)
+}
+
+/// A key for no account, to sign blocks with.
+fn consensus_key() -> SigningKey {
+    User::generate(Amount::ZERO).1
+}
+
+/// A node whose only validator stakes 60 SMV, past genesis.
+async fn node() -> (Node, (User, SigningKey)) {
+    let mut node = Node::new(NodeType::FullNode, true).unwrap();
+    let (validator, validator_key) = User::generate(Amount::from_smv(100));
+    node.add_user(validator.clone()).await.unwrap();
+    node.stake(validator.address, Amount::from_smv(60))
+        .await
+        .unwrap();
+    node.produce_block().await.unwrap();
+    (node, (validator, validator_key))
+}
+
+async fn for_key(node: &Node, key: &SigningKey) -> Option<Address> {
+    let key = key.verifying_key().to_bytes();
+    node.blockchain.validator_for_key(&key).await.unwrap()
+}
+
+/// The next block from `proposer`, signed with `key`.
+async fn signed_block(node: &Node, proposer: Address, key: &SigningKey) -> Block {
+    let (previous_hash, height) = node.blockchain.chain_head().get().unwrap();
+    let mut block = Block::new(previous_hash, height + 1, proposer, vec![]);
+    if let Some(median) = node.blockchain.median_time_past().await.unwrap() {
+        block.header.timestamp = Utc::now().timestamp().max(median + 1);
+    }
+    block.header.state_root = node.blockchain.state_root_after(&block).await.unwrap();
+    block.sign(key);
+    block
+}
+
+#[test]
+fn test_registration_round_trips_through_a_transaction() {
+    let registration = Registration {
+        consensus_key: [7; 32],
+        commission: Commission { bps: 250 },
+    };
+    let data = TxData::Registration(registration);
+    assert_eq!(TxData::from_bytes(&data.to_bytes()), Ok(data));
+    assert_eq!(data.kind(), Some(TxKind::RegisterValidator));
+    assert_eq!(data.consensus_key(), Some([7; 32]));
+    assert!(Commission { bps: 10_001 }.check().is_err());
+    assert_eq!("register_validator".parse(), Ok(TxKind::RegisterValidator));
+    assert_eq!("rotate_key".parse(), Ok(TxKind::RotateKey));
+}
+
+#[tokio::test]
+async fn test_validator_signs_with_its_registered_key_until_rotated() {
+    let (mut node, (validator, validator_key)) = node().await;
+    let first = consensus_key();
+    let second = consensus_key();
+    node.blockchain
+        .add_transaction(key_tx(&validator_key, TxKind::RegisterValidator, &first, 0))
+        .await
+        .unwrap();
+    node.produce_block().await.unwrap();
+
+    let account = node
+        .blockchain
+        .get_user(&validator.address)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(account.commission_bps, 500);
+    assert_eq!(for_key(&node, &first).await, Some(validator.address));
+    assert_eq!(for_key(&node, &validator_key).await, None);
+
+    // Blocks are signed with the consensus key, not the account key.
+    let block = signed_block(&node, validator.address, &validator_key).await;
+    assert!(node.blockchain.add_block(block).await.is_err());
+    let block = signed_block(&node, validator.address, &first).await;
+    let signed_hash = block.hash();
+    let signed_height = block.header.height;
+    node.blockchain.add_block(block).await.unwrap();
+
+    // So are finality votes.
+    let vote = Vote::sign(signed_hash, signed_height, &first);
+    assert_eq!(
+        node.blockchain.add_vote(vote).await,
+        Ok(VoteOutcome::Finalized)
+    );
+
+    node.blockchain
+        .add_transaction(key_tx(&validator_key, TxKind::RotateKey, &second, 1))
+        .await
+        .unwrap();
+    node.produce_block().await.unwrap();
+    assert_eq!(for_key(&node, &first).await, None);
+    assert_eq!(for_key(&node, &second).await, Some(validator.address));
+    let block = signed_block(&node, validator.address, &first).await;
+    assert!(node.blockchain.add_block(block).await.is_err());
+    let block = signed_block(&node, validator.address, &second).await;
+    node.blockchain.add_block(block).await.unwrap();
+
+    // Blocks signed before the rotation still check out.
+    assert!(
+        node.blockchain
+            .verify_chain(false)
+            .await
+            .unwrap()
+            .problems
+            .is_empty()
+    );
+}
+
+#[tokio::test]
+async fn test_key_rules_are_enforced() {
+    let (mut node, (_, validator_key)) = node().await;
+    let (holder, holder_key) = User::generate(Amount::from_smv(10));
+    node.add_user(holder.clone()).await.unwrap();
+    let consensus = consensus_key();
+    let refused = |result: Result<(), BlockchainError>| {
+        assert!(matches!(result, Err(BlockchainError::Staking(_))));
+    };
+
+    // Nothing to rotate yet.
+    refused(
+        node.blockchain
+            .add_transaction(key_tx(&validator_key, TxKind::RotateKey, &consensus, 0))
+            .await,
+    );
+    // Another account's own key.
+    refused(
+        node.blockchain
+            .add_transaction(key_tx(
+                &validator_key,
+                TxKind::RegisterValidator,
+                &holder_key,
+                0,
+            ))
+            .await,
+    );
+    // Not a point on the curve.
+    let mut invalid = key_tx(&validator_key, TxKind::RegisterValidator, &consensus, 0);
+    let point = (0u8..)
+        .map(|byte| [byte; 32])
+        .find(|key| VerifyingKey::from_bytes(key).is_err())
+        .unwrap();
+    invalid.payload.data = TxData::Registration(Registration {
+        consensus_key: point,
+        commission: Commission { bps: 500 },
+    });
+    refused(
+        node.blockchain
+            .add_transaction(Transaction::sign(
+                invalid.payload,
+                &mut validator_key.clone(),
+            ))
+            .await,
+    );
+
+    node.blockchain
+        .add_transaction(key_tx(
+            &validator_key,
+            TxKind::RegisterValidator,
+            &consensus,
+            0,
+        ))
+        .await
+        .unwrap();
+    node.produce_block().await.unwrap();
+    // Registered once only, and the key is taken.
+    refused(
+        node.blockchain
+            .add_transaction(key_tx(
+                &validator_key,
+                TxKind::RegisterValidator,
+                &consensus_key(),
+                1,
+            ))
+            .await,
+    );
+    refused(
+        node.blockchain
+            .add_transaction(key_tx(
+                &holder_key,
+                TxKind::RegisterValidator,
+                &consensus,
+                0,
+            ))
+            .await,
+    );
+}
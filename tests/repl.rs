@@ -0,0 +1,174 @@
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Command, Output, Stdio};
+
+fn smvblock(data_dir: &std::path::Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_smvblock"))
+        .arg("--data-dir")
+        .arg(data_dir)
+        .args(["--log-level", "error"])
+        .env("SMVBLOCK_WALLET_PASSWORD", "pw")
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn test_scripts_run_repl_commands_and_fail_on_the_first_error() {
+    let dir = std::env::temp_dir().join(format!("smvblock-repl-{}", rand::random::<u64>()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let output = smvblock(&dir, &["--exec", "add-user 10; show-users; show-mempool"]);
+    assert!(output.status.success(), "{:?}", output);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("Added user with address"), "{}", stdout);
+    assert!(stdout.contains("Balance: 10 SMV"), "{}", stdout);
+    assert!(stdout.contains("Pending transactions: 0"), "{}", stdout);
+
+    let script = dir.join("demo.smv");
+    std::fs::write(
+        &script,
+        "# Fails before it gets to show-users\nadd-user 5\n\nstake nobody 1\nshow-users\n",
+    )
+    .unwrap();
+    let output = smvblock(&dir, &["run", script.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("Line 4 (stake nobody 1) failed"),
+        "{}",
+        stderr
+    );
+    assert!(
+        !String::from_utf8(output.stdout)
+            .unwrap()
+            .contains("Balance")
+    );
+
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+#[test]
+fn test_bad_arguments_are_reported_instead_of_panicking() {
+    let dir = std::env::temp_dir().join(format!("smvblock-repl-{}", rand::random::<u64>()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let output = smvblock(&dir, &["--exec", "add-user lots"]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("invalid value 'lots'"), "{}", stderr);
+
+    let output = smvblock(&dir, &["--exec", "produce-blok"]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("unrecognized subcommand"), "{}", stderr);
+
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+#[test]
+fn test_blocks_are_produced_in_the_background_until_stopped() {
+    let dir = std::env::temp_dir().join(format!("smvblock-repl-{}", rand::random::<u64>()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let output = smvblock(
+        &dir,
+        &[
+            "--exec",
+            "auto-produce 1; stop-auto-produce; stop-auto-produce",
+        ],
+    );
+    assert_eq!(output.status.code(), Some(1));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(
+        stdout.contains("Producing a block every 1s with transactions pending"),
+        "{}",
+        stdout
+    );
+    assert!(
+        stdout.contains("Stopped producing blocks automatically"),
+        "{}",
+        stdout
+    );
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("Command 3 (stop-auto-produce) failed"),
+        "{}",
+        stderr
+    );
+
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+#[test]
+fn test_connect_drives_a_running_node_over_rpc() {
+    let dir = std::env::temp_dir().join(format!("smvblock-repl-{}", rand::random::<u64>()));
+    let (node_dir, repl_dir) = (dir.join("node"), dir.join("repl"));
+    std::fs::create_dir_all(&repl_dir).unwrap();
+
+    // A REPL serving RPC stands in for the running node.
+    let mut node = Command::new(env!("CARGO_BIN_EXE_smvblock"))
+        .arg("--data-dir")
+        .arg(&node_dir)
+        .args(["--log-level", "error", "--rpc-addr", "127.0.0.1:0"])
+        .env("SMVBLOCK_WALLET_PASSWORD", "pw")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdin = node.stdin.take().unwrap();
+    let mut lines = BufReader::new(node.stdout.take().unwrap()).lines();
+    let mut read_until = |prefix: &str| loop {
+        let line = lines.next().unwrap().unwrap();
+        if let Some(rest) = line.strip_prefix(prefix) {
+            return rest.trim().to_string();
+        }
+    };
+    let rpc_addr = read_until("Serving JSON-RPC on");
+    writeln!(stdin, "add-user 100").unwrap();
+    let alice = read_until("Added user with address:");
+    writeln!(stdin, "add-user 0").unwrap();
+    let bob = read_until("Added user with address:");
+    writeln!(
+        stdin,
+        "stake {} 50\nproduce-block\nwallet save {} alice",
+        alice, alice
+    )
+    .unwrap();
+    read_until("Saved");
+    std::fs::create_dir_all(repl_dir.join("wallet")).unwrap();
+    std::fs::copy(
+        node_dir.join("wallet").join("alice.json"),
+        repl_dir.join("wallet").join("alice.json"),
+    )
+    .unwrap();
+
+    let commands = format!(
+        "connect http://{}; show-block 0; wallet load alice; transact alice {} 5; show-mempool; add-user 1",
+        rpc_addr, bob
+    );
+    let output = smvblock(&repl_dir, &["--exec", &commands]);
+    assert_eq!(output.status.code(), Some(1));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("at height 0"), "{}", stdout);
+    assert!(
+        stdout.contains(&format!("Proposer:     {}", alice)),
+        "{}",
+        stdout
+    );
+    assert!(
+        stdout.contains(&format!("Sent 5 SMV from alice to {}", bob)),
+        "{}",
+        stdout
+    );
+    assert!(stdout.contains("Pending transactions: 1"), "{}", stdout);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("Command 6 (add-user 1) failed"),
+        "{}",
+        stderr
+    );
+
+    drop(stdin);
+    node.wait().unwrap();
+    let _ = std::fs::remove_dir_all(dir);
+}
@@ -0,0 +1,440 @@
+use bincode::config::standard;
+use futures::{SinkExt, StreamExt};
+use serde_json::{Value, json};
+use smvblock::{
+    amount::Amount,
+    blockchain::{Transfer, TxData, TxKind, User},
+    db::Database,
+    error::BlockchainError,
+    mempool::MempoolConfig,
+    node::{Node, NodeType},
+};
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+
+type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+async fn call(addr: SocketAddr, method: &str, params: Value) -> Value {
+    let body = json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params }).to_string();
+    let request = format!(
+        "POST / HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        addr,
+        body.len(),
+        body
+    );
+
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+
+    let (_, body) = response.split_once("\r\n\r\n").unwrap();
+    serde_json::from_str(body).unwrap()
+}
+
+async fn send(socket: &mut Socket, method: &str, params: Value) {
+    let request = json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params });
+    socket
+        .send(Message::Text(request.to_string().into()))
+        .await
+        .unwrap();
+}
+
+async fn receive(socket: &mut Socket) -> Value {
+    loop {
+        let message = tokio::time::timeout(Duration::from_secs(5), socket.next())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        if let Message::Text(text) = message {
+            return serde_json::from_str(text.as_str()).unwrap();
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_rpc_submit_and_query() {
+    let mut node = Node::new(NodeType::FullNode, true).unwrap();
+    let (sender, key) = User::generate(Amount::from_smv(100));
+    let (receiver, _) = User::generate(Amount::ZERO);
+    node.add_user(sender.clone()).await.unwrap();
+    node.add_user(receiver.clone()).await.unwrap();
+    node.stake(sender.address, Amount::from_smv(10))
+        .await
+        .unwrap();
+
+    let addr = node
+        .start_rpc("127.0.0.1:0".parse().unwrap())
+        .await
+        .unwrap();
+
+    let tx = Transfer {
+        receiver: receiver.address,
+        amount: Amount::from_smv(5),
+        fee: Amount::ZERO,
+        nonce: 0,
+        kind: TxKind::Transfer,
+        data: TxData::None,
+    }
+    .into_transaction(&key);
+    let raw = hex::encode(bincode::encode_to_vec(&tx, standard()).unwrap());
+
+    let submitted = call(addr, "tx_submit", json!([raw])).await;
+    assert_eq!(submitted["result"], json!(hex::encode(tx.hash())));
+    // Refusals carry a code per reason.
+    let again = call(addr, "tx_submit", json!([raw])).await;
+    assert_eq!(
+        again["error"]["code"],
+        json!(BlockchainError::Underpriced.code())
+    );
+    node.blockchain
+        .set_mempool_config(MempoolConfig {
+            min_fee_per_byte: Amount::from_base_units(1),
+            ..MempoolConfig::default()
+        })
+        .await;
+    let free = Transfer {
+        receiver: receiver.address,
+        amount: Amount::from_smv(1),
+        fee: Amount::ZERO,
+        nonce: 1,
+        kind: TxKind::Transfer,
+        data: TxData::None,
+    }
+    .into_transaction(&key);
+    let raw_free = hex::encode(bincode::encode_to_vec(&free, standard()).unwrap());
+    let refused = call(addr, "tx_submit", json!([raw_free])).await;
+    assert_eq!(
+        refused["error"]["code"],
+        json!(
+            BlockchainError::FeeTooLow {
+                required: Amount::ZERO
+            }
+            .code()
+        )
+    );
+    node.blockchain
+        .set_mempool_config(MempoolConfig::default())
+        .await;
+
+    let nonce = call(
+        addr,
+        "account_getNonce",
+        json!([hex::encode(sender.address)]),
+    )
+    .await;
+    assert_eq!(nonce["result"], json!(1));
+
+    let block_hash = node.produce_block().await.unwrap();
+
+    let status = call(addr, "chain_getStatus", json!([])).await;
+    assert_eq!(status["result"]["height"], json!(0));
+    assert_eq!(
+        status["result"]["latest_hash"],
+        json!(hex::encode(block_hash))
+    );
+    assert_eq!(status["result"]["network_height"], json!(0));
+    assert_eq!(status["result"]["sync"]["state"], json!("synced"));
+    assert_eq!(
+        status["result"]["version"],
+        json!(env!("CARGO_PKG_VERSION"))
+    );
+
+    let block = call(addr, "chain_getBlockByHeight", json!([0])).await;
+    assert_eq!(block["result"]["hash"], json!(hex::encode(block_hash)));
+    assert_eq!(
+        block["result"]["transactions"][0]["hash"],
+        json!(hex::encode(tx.hash()))
+    );
+    assert_eq!(block["result"]["confirmations"], json!(1));
+
+    let found = call(addr, "tx_get", json!([hex::encode(tx.hash())])).await;
+    assert_eq!(found["result"]["confirmed"], json!(true));
+    assert_eq!(found["result"]["status"], json!("confirmed"));
+    node.produce_block().await.unwrap();
+    let found = call(addr, "tx_get", json!([hex::encode(tx.hash())])).await;
+    assert_eq!(found["result"]["confirmations"], json!(2));
+
+    let balance = call(
+        addr,
+        "account_getBalance",
+        json!([hex::encode(receiver.address)]),
+    )
+    .await;
+    assert_eq!(balance["result"]["balance"], json!("5 SMV"));
+
+    let stats = call(addr, "admin_peerStats", json!([])).await;
+    assert_eq!(stats["result"]["peers"], json!([]));
+    assert_eq!(stats["result"]["total"]["bytes_received"], json!(0));
+
+    let unknown = call(addr, "chain_getNothing", json!([])).await;
+    assert_eq!(unknown["error"]["code"], json!(-32601));
+}
+
+#[tokio::test]
+async fn test_stats_summarize_recent_blocks() {
+    let mut node = Node::new(NodeType::FullNode, true).unwrap();
+    let (validator, key) = User::generate(Amount::from_smv(100));
+    let (idle, _) = User::generate(Amount::from_smv(100));
+    node.add_user(validator.clone()).await.unwrap();
+    node.add_user(idle.clone()).await.unwrap();
+    node.stake(validator.address, Amount::from_smv(30))
+        .await
+        .unwrap();
+    let addr = node
+        .start_rpc("127.0.0.1:0".parse().unwrap())
+        .await
+        .unwrap();
+    let empty = call(addr, "stats_get", json!([])).await;
+    assert_eq!(empty["result"], Value::Null);
+
+    let tx = Transfer {
+        receiver: idle.address,
+        amount: Amount::from_smv(5),
+        fee: Amount::from_smv(1),
+        nonce: 0,
+        kind: TxKind::Transfer,
+        data: TxData::None,
+    }
+    .into_transaction(&key);
+    node.blockchain.add_transaction(tx).await.unwrap();
+    node.produce_block().await.unwrap();
+    // Staking after the blocks leaves a validator that proposed none.
+    node.produce_block().await.unwrap();
+    node.stake(idle.address, Amount::from_smv(10))
+        .await
+        .unwrap();
+
+    let stats = call(addr, "stats_get", json!([])).await["result"].clone();
+    assert_eq!(stats["blocks"], json!(2));
+    assert_eq!(stats["from_height"], json!(0));
+    assert_eq!(stats["to_height"], json!(1));
+    assert_eq!(stats["transactions"], json!(1));
+    assert_eq!(stats["fees"], json!("1 SMV"));
+    assert_eq!(stats["validators"], json!(2));
+    assert_eq!(stats["active_validators"], json!(1));
+    assert_eq!(stats["total_stake"], json!("40 SMV"));
+    assert_eq!(stats["active_stake"], json!("30 SMV"));
+    assert_eq!(stats["participation"], json!(0.75));
+    assert!(stats["average_block_interval"].as_f64().unwrap() >= 0.0);
+
+    let latest = call(addr, "stats_get", json!([1])).await["result"].clone();
+    assert_eq!(latest["blocks"], json!(1));
+    assert_eq!(latest["average_block_interval"], Value::Null);
+}
+
+#[tokio::test]
+async fn test_state_ranks_accounts_and_audits_supply() {
+    let mut node = Node::new(NodeType::FullNode, true).unwrap();
+    let (whale, _) = User::generate(Amount::from_smv(100));
+    let (nine, _) = User::generate(Amount::from_smv(9));
+    let (ten, _) = User::generate(Amount::from_smv(10));
+    for user in [&whale, &nine, &ten] {
+        node.add_user(user.clone()).await.unwrap();
+    }
+    node.stake(whale.address, Amount::from_smv(30))
+        .await
+        .unwrap();
+    node.produce_block().await.unwrap();
+    let addr = node
+        .start_rpc("127.0.0.1:0".parse().unwrap())
+        .await
+        .unwrap();
+
+    let top = call(addr, "state_topAccounts", json!([3])).await["result"].clone();
+    let ranked: Vec<_> = top
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|account| account["address"].clone())
+        .collect();
+    // Ten outranks nine though its amount sorts first as text.
+    assert_eq!(
+        ranked,
+        [whale.address, ten.address, nine.address].map(|address| json!(hex::encode(address)))
+    );
+    let by_stake = call(addr, "state_topAccounts", json!([1, "stake"])).await;
+    assert_eq!(by_stake["result"][0]["stake"], json!("30 SMV"));
+    let invalid = call(addr, "state_topAccounts", json!([1, "age"])).await;
+    assert_eq!(invalid["error"]["code"], json!(-32602));
+
+    let supply = call(addr, "state_totalSupply", json!([])).await["result"].clone();
+    assert_eq!(supply["allocated"], json!("119 SMV"));
+    assert_eq!(supply["burned"], json!("0 SMV"));
+    assert_eq!(supply["consistent"], json!(true));
+    assert_ne!(supply["emitted"], json!("0 SMV"));
+
+    node.slash_validator(whale.address, Amount::from_smv(4))
+        .await
+        .unwrap();
+    let supply = call(addr, "state_totalSupply", json!([])).await["result"].clone();
+    assert_eq!(supply["burned"], json!("4 SMV"));
+    assert_eq!(supply["consistent"], json!(true));
+    assert_eq!(supply["expected"], supply["circulating"]);
+}
+
+#[tokio::test]
+async fn test_ws_subscriptions_receive_new_heads_and_account_changes() {
+    let mut node = Node::new(NodeType::FullNode, true).unwrap();
+    let (validator, _) = User::generate(Amount::from_smv(100));
+    node.add_user(validator.clone()).await.unwrap();
+    node.stake(validator.address, Amount::from_smv(10))
+        .await
+        .unwrap();
+
+    let addr = node
+        .start_rpc("127.0.0.1:0".parse().unwrap())
+        .await
+        .unwrap();
+    let (mut socket, _) = tokio_tungstenite::connect_async(format!("ws://{}/ws", addr))
+        .await
+        .unwrap();
+
+    send(&mut socket, "subscribe_newHeads", json!([])).await;
+    let heads = receive(&mut socket).await["result"].clone();
+    send(
+        &mut socket,
+        "subscribe_accountChanges",
+        json!([hex::encode(validator.address)]),
+    )
+    .await;
+    let account = receive(&mut socket).await["result"].clone();
+
+    let block_hash = node.produce_block().await.unwrap();
+
+    let head = receive(&mut socket).await;
+    assert_eq!(head["params"]["subscription"], heads);
+    assert_eq!(
+        head["params"]["result"]["hash"],
+        json!(hex::encode(block_hash))
+    );
+
+    let change = receive(&mut socket).await;
+    assert_eq!(change["params"]["subscription"], account);
+    assert_eq!(
+        change["params"]["result"]["address"],
+        json!(hex::encode(validator.address))
+    );
+}
+
+#[tokio::test]
+async fn test_rpc_pages_through_headers() {
+    let path = std::env::temp_dir().join(format!("smvblock-headers-{}.db", rand::random::<u64>()));
+    let mut node = Node::with_database(
+        NodeType::FullNode,
+        Database::new(path.to_str(), false).unwrap(),
+    );
+    let (validator, _) = User::generate(Amount::from_smv(100));
+    node.add_user(validator.clone()).await.unwrap();
+    node.stake(validator.address, Amount::from_smv(10))
+        .await
+        .unwrap();
+    let mut hashes = Vec::new();
+    for _ in 0..5 {
+        hashes.push(hex::encode(node.produce_block().await.unwrap()));
+    }
+
+    let addr = node
+        .start_rpc("127.0.0.1:0".parse().unwrap())
+        .await
+        .unwrap();
+    let mut seen = Vec::new();
+    let mut from = json!(0);
+    while !from.is_null() {
+        let page = call(addr, "chain_getHeaders", json!([from, 2])).await;
+        let headers = page["result"]["headers"].as_array().unwrap();
+        assert!(headers.len() <= 2);
+        seen.extend(headers.iter().map(|header| header["hash"].clone()));
+        from = page["result"]["next"].clone();
+    }
+    assert_eq!(
+        seen,
+        hashes.into_iter().map(Value::from).collect::<Vec<_>>()
+    );
+
+    let _ = std::fs::remove_file(path);
+}
+
+#[tokio::test]
+async fn test_rpc_lists_account_history() {
+    let path = std::env::temp_dir().join(format!("smvblock-history-{}.db", rand::random::<u64>()));
+    let mut node = Node::with_database(
+        NodeType::FullNode,
+        Database::new(path.to_str(), false).unwrap(),
+    );
+    let (sender, key) = User::generate(Amount::from_smv(100));
+    let (receiver, _) = User::generate(Amount::ZERO);
+    node.add_user(sender.clone()).await.unwrap();
+    node.add_user(receiver.clone()).await.unwrap();
+    node.stake(sender.address, Amount::from_smv(10))
+        .await
+        .unwrap();
+
+    let mut hashes = Vec::new();
+    for nonce in 0..3 {
+        let tx = Transfer {
+            receiver: receiver.address,
+            amount: Amount::from_smv(1),
+            fee: Amount::ZERO,
+            nonce,
+            kind: TxKind::Transfer,
+            data: TxData::None,
+        }
+        .into_transaction(&key);
+        hashes.push(json!(hex::encode(tx.hash())));
+        node.blockchain.add_transaction(tx).await.unwrap();
+        if nonce != 1 {
+            node.produce_block().await.unwrap();
+        }
+    }
+
+    let addr = node
+        .start_rpc("127.0.0.1:0".parse().unwrap())
+        .await
+        .unwrap();
+    let found = call(addr, "tx_get", json!([hashes[2]])).await;
+    assert_eq!(found["result"]["block"]["height"], json!(1));
+    assert_eq!(found["result"]["block"]["index"], json!(1));
+
+    let receiver = json!(hex::encode(receiver.address));
+    let first = call(addr, "account_getTransactions", json!([receiver, 2])).await;
+    let next = first["result"]["next"].clone();
+    let second = call(addr, "account_getTransactions", json!([receiver, 2, next])).await;
+    assert!(second["result"]["next"].is_null());
+    let listed: Vec<Value> = [first, second]
+        .iter()
+        .flat_map(|page| page["result"]["transactions"].as_array().unwrap().clone())
+        .map(|tx| tx["hash"].clone())
+        .collect();
+    hashes.reverse();
+    assert_eq!(listed, hashes);
+
+    let sent = call(
+        addr,
+        "account_getTransactions",
+        json!([hex::encode(sender.address)]),
+    )
+    .await;
+    assert_eq!(sent["result"]["transactions"].as_array().unwrap().len(), 3);
+
+    let at_genesis = call(addr, "account_getBalanceAt", json!([receiver, 0])).await;
+    assert_eq!(at_genesis["result"]["balance"], json!("1 SMV"));
+    let changes = call(addr, "account_getBalanceChanges", json!([receiver])).await;
+    let changes = changes["result"].as_array().unwrap();
+    assert_eq!(changes.len(), 2);
+    assert_eq!(changes[1]["height"], json!(1));
+    assert_eq!(changes[1]["before"], json!("1 SMV"));
+    assert_eq!(changes[1]["after"], json!("3 SMV"));
+
+    let watched = call(addr, "account_watch", json!([receiver])).await;
+    assert_eq!(watched["result"], json!(true));
+    let watched = call(addr, "account_watch", json!([receiver])).await;
+    assert_eq!(watched["result"], json!(false));
+
+    let _ = std::fs::remove_file(path);
+}
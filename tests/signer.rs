@@ -0,0 +1,65 @@
+use smvblock::{
+    amount::Amount,
+    blockchain::{Block, User},
+    node::{Node, NodeType},
+    params::ChainParams,
+    signer::{self, BlockSigner, RemoteSigner, SignerEndpoint},
+};
+use std::time::Duration;
+use tokio::sync::watch;
+
+#[tokio::test]
+async fn test_remote_signer_signs_proposals_once_per_height() {
+    let (validator, key) = User::generate(Amount::from_smv(100));
+    let (_shutdown, stopped) = watch::channel(false);
+    let endpoint = signer::serve(key, &"http://127.0.0.1:0".parse().unwrap(), stopped)
+        .await
+        .unwrap();
+    let remote = BlockSigner::Remote(RemoteSigner::connect(endpoint).await.unwrap());
+    assert_eq!(remote.public_key(), validator.public_key);
+
+    let mut node = Node::new(NodeType::FullNode, true).unwrap();
+    node.blockchain.set_chain_params(ChainParams {
+        block_time: Duration::from_millis(50),
+        ..ChainParams::default()
+    });
+    node.add_user(validator.clone()).await.unwrap();
+    node.stake(validator.address, Amount::from_smv(50))
+        .await
+        .unwrap();
+    node.start_validator(remote.clone());
+
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(5);
+    let block = loop {
+        if let Some(block) = node.blockchain.get_latest_block().await.unwrap() {
+            break block;
+        }
+        assert!(tokio::time::Instant::now() < deadline, "no block produced");
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    };
+    node.shutdown().await.unwrap();
+    assert!(block.header.verify_signature(&validator.public_key));
+
+    // A competing block at a height already signed for is refused.
+    let mut competing = Block::new(
+        block.header.previous_hash,
+        block.header.height,
+        validator.address,
+        vec![],
+    );
+    competing.header.timestamp += 1;
+    assert!(remote.sign(&mut competing).await.is_err());
+}
+
+#[test]
+fn test_signer_endpoints_parse() {
+    assert_eq!(
+        "http://127.0.0.1:7000".parse(),
+        Ok(SignerEndpoint::Tcp("127.0.0.1:7000".parse().unwrap()))
+    );
+    assert_eq!(
+        "unix:/run/smvblock/signer.sock".parse(),
+        Ok(SignerEndpoint::Unix("/run/smvblock/signer.sock".into()))
+    );
+    assert!("127.0.0.1:7000".parse::<SignerEndpoint>().is_err());
+}
@@ -0,0 +1,66 @@
+use smvblock::simulation::{Partition, Simulation, SimulationConfig};
+use std::time::Duration;
+
+async fn simulate(config: SimulationConfig) -> smvblock::simulation::SimulationReport {
+    Simulation::new(config).await.unwrap().run().await.unwrap()
+}
+
+#[tokio::test]
+async fn test_same_seed_plays_out_the_same() {
+    let config = SimulationConfig {
+        duration: Duration::from_secs(30),
+        loss: 0.2,
+        seed: 11,
+        ..SimulationConfig::default()
+    };
+    let first = simulate(config.clone()).await;
+    let second = simulate(config).await;
+
+    assert_eq!(first.heads, second.heads);
+    assert_eq!(first.finalized, second.finalized);
+    assert_eq!(first.messages_sent, second.messages_sent);
+    assert_eq!(first.messages_lost, second.messages_lost);
+    assert!(first.messages_lost > 0);
+}
+
+#[tokio::test]
+async fn test_finality_is_safe_and_live_under_latency_and_loss() {
+    for seed in 0..3 {
+        let report = simulate(SimulationConfig {
+            duration: Duration::from_secs(60),
+            min_latency: Duration::from_millis(50),
+            max_latency: Duration::from_millis(800),
+            loss: 0.2,
+            seed,
+            ..SimulationConfig::default()
+        })
+        .await;
+        report.check_safety().unwrap();
+        report.check_liveness(30).unwrap();
+    }
+}
+
+#[tokio::test]
+async fn test_majority_finalizes_through_a_partition_and_the_minority_catches_up() {
+    let report = simulate(SimulationConfig {
+        duration: Duration::from_secs(90),
+        loss: 0.05,
+        partitions: vec![Partition {
+            start: Duration::from_secs(10),
+            end: Duration::from_secs(40),
+            groups: vec![vec![0, 1, 2], vec![3]],
+        }],
+        seed: 3,
+        ..SimulationConfig::default()
+    })
+    .await;
+    report.check_safety().unwrap();
+
+    // Three of four validators hold enough stake to go on finalizing
+    // alone; the one cut off does not.
+    let at_heal = &report.finalized_after_faults;
+    assert!(at_heal[3] < at_heal[0]);
+    assert!(at_heal[0] >= Some(20));
+    report.check_liveness(20).unwrap();
+    assert!(report.finalized[3] >= at_heal[0]);
+}
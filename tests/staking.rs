@@ -0,0 +1,269 @@
+use ed25519_dalek::SigningKey;
+use smvblock::{
+    amount::Amount,
+    blockchain::{Address, Transaction, Transfer, TxData, TxKind, User},
+    error::BlockchainError,
+    node::{Node, NodeType},
+    params::{ChainParams, Feature, ForkSchedule},
+    staking::{self, Commission},
+    wallet,
+};
+
+fn staking_tx(
+    key: &SigningKey,
+    kind: TxKind,
+    receiver: Address,
+    amount: Amount,
+    nonce: u64,
+) -> Transaction {
+    Transfer {
+        receiver,
+        amount,
+        fee: Amount::ZERO,
+        nonce,
+        kind,
+        data: TxData::None,
+    }
+    .into_transaction(key)
+}
+
+fn commission_tx(key: &SigningKey, commission: Commission, nonce: u64) -> Transaction {
+    Transfer {
+        receiver: wallet::key_address(key),
+        amount: Amount::ZERO,
+        fee: Amount::ZERO,
+        nonce,
+        kind: TxKind::SetCommission,
+        data: TxData::Commission(commission),
+    }
+    .into_transaction(key)
+}
+
+/// A node whose only validator stakes 60 SMV, with an account of 100 SMV
+/// yet to stake.
+async fn node() -> (Node, (User, SigningKey), (User, SigningKey)) {
+    let node = Node::new(NodeType::FullNode, true).unwrap();
+    let (validator, validator_key) = User::generate(Amount::from_smv(100));
+    node.add_user(validator.clone()).await.unwrap();
+    node.stake(validator.address, Amount::from_smv(60))
+        .await
+        .unwrap();
+    let (holder, holder_key) = User::generate(Amount::from_smv(100));
+    node.add_user(holder.clone()).await.unwrap();
+    (node, (validator, validator_key), (holder, holder_key))
+}
+
+async fn account(node: &Node, address: Address) -> User {
+    node.blockchain.get_user(&address).await.unwrap().unwrap()
+}
+
+#[test]
+fn test_rewards_and_penalties_are_shared_by_stake() {
+    let commission = Commission { bps: 1_000 };
+    assert_eq!(commission.check(), Ok(()));
+    assert!(Commission { bps: 10_001 }.check().is_err());
+
+    let (mut validator, _) = User::generate(Amount::ZERO);
+    validator.stake = Amount::from_smv(60);
+    validator.commission_bps = commission.bps;
+    let (mut delegator, _) = User::generate(Amount::ZERO);
+    delegator.stake = Amount::from_smv(40);
+    delegator.delegate = Some(validator.address);
+
+    // A tenth is commission, the rest is shared 60 to 40.
+    let shares = staking::share_reward(&validator, &[delegator.clone()], Amount::from_smv(10));
+    assert_eq!(
+        shares,
+        vec![
+            (validator.address, Amount::from_base_units(640_000_000)),
+            (delegator.address, Amount::from_base_units(360_000_000)),
+        ]
+    );
+
+    let shares = staking::share_penalty(&validator, &[delegator.clone()], Amount::from_smv(10));
+    assert_eq!(
+        shares,
+        vec![
+            (validator.address, Amount::from_smv(6)),
+            (delegator.address, Amount::from_smv(4)),
+        ]
+    );
+    // No one loses more than they staked.
+    let shares = staking::share_penalty(&validator, &[delegator], Amount::from_smv(500));
+    assert_eq!(shares[0].1, Amount::from_smv(60));
+    assert_eq!(shares[1].1, Amount::from_smv(40));
+}
+
+#[tokio::test]
+async fn test_delegated_stake_weighs_for_its_validator_and_shares_its_reward() {
+    let (mut node, (validator, validator_key), (holder, holder_key)) = node().await;
+    let commission = Commission { bps: 1_000 };
+    node.blockchain
+        .add_transaction(staking_tx(
+            &holder_key,
+            TxKind::Delegate,
+            validator.address,
+            Amount::from_smv(40),
+            0,
+        ))
+        .await
+        .unwrap();
+    node.blockchain
+        .add_transaction(commission_tx(&validator_key, commission, 0))
+        .await
+        .unwrap();
+    node.produce_block().await.unwrap();
+
+    let validators = node.blockchain.validators().await.unwrap();
+    assert_eq!(validators.len(), 1);
+    assert_eq!(validators[0].user.address, validator.address);
+    assert_eq!(validators[0].user.commission_bps, commission.bps);
+    assert_eq!(validators[0].delegated, Amount::from_smv(40));
+    assert_eq!(validators[0].weight(), Amount::from_smv(100));
+    let delegator = account(&node, holder.address).await;
+    assert_eq!(delegator.delegate, Some(validator.address));
+    assert_eq!(delegator.stake, Amount::from_smv(40));
+
+    let hash = node.produce_block().await.unwrap();
+    let block = node.blockchain.get_block(hash).await.unwrap().unwrap();
+    let reward = block.header.coinbase;
+    let rest = reward.saturating_sub(reward.mul_div(1_000, 10_000));
+    assert_eq!(
+        account(&node, holder.address).await.balance,
+        delegator
+            .balance
+            .checked_add(rest.mul_div(40, 100))
+            .unwrap()
+    );
+    assert!(
+        node.blockchain
+            .verify_chain(false)
+            .await
+            .unwrap()
+            .problems
+            .is_empty()
+    );
+
+    // Unstaking everything ends the delegation.
+    node.blockchain
+        .add_transaction(staking_tx(
+            &holder_key,
+            TxKind::Unstake,
+            holder.address,
+            Amount::from_smv(40),
+            1,
+        ))
+        .await
+        .unwrap();
+    node.produce_block().await.unwrap();
+    let delegator = account(&node, holder.address).await;
+    assert_eq!(delegator.stake, Amount::ZERO);
+    assert_eq!(delegator.delegate, None);
+}
+
+#[tokio::test]
+async fn test_slashing_a_validator_slashes_its_delegators() {
+    let (mut node, (validator, _), (holder, holder_key)) = node().await;
+    node.blockchain
+        .add_transaction(staking_tx(
+            &holder_key,
+            TxKind::Delegate,
+            validator.address,
+            Amount::from_smv(40),
+            0,
+        ))
+        .await
+        .unwrap();
+    node.produce_block().await.unwrap();
+    let burned = node.blockchain.supply().await.unwrap().burned;
+
+    node.slash_validator(validator.address, Amount::from_smv(10))
+        .await
+        .unwrap();
+    assert_eq!(
+        account(&node, validator.address).await.stake,
+        Amount::from_smv(54)
+    );
+    assert_eq!(
+        account(&node, holder.address).await.stake,
+        Amount::from_smv(36)
+    );
+    assert_eq!(
+        node.blockchain.supply().await.unwrap().burned,
+        burned.checked_add(Amount::from_smv(10)).unwrap()
+    );
+}
+
+#[tokio::test]
+async fn test_delegation_rules_are_enforced() {
+    let (node, (_, validator_key), (holder, holder_key)) = node().await;
+    let delegate = |key: &SigningKey, to: Address, nonce| {
+        staking_tx(key, TxKind::Delegate, to, Amount::from_smv(10), nonce)
+    };
+    let refused = |result: Result<(), BlockchainError>| {
+        assert!(matches!(result, Err(BlockchainError::Staking(_))));
+    };
+
+    refused(
+        node.blockchain
+            .add_transaction(delegate(&holder_key, holder.address, 0))
+            .await,
+    );
+    let (stranger, _) = User::generate(Amount::from_smv(1));
+    node.add_user(stranger.clone()).await.unwrap();
+    refused(
+        node.blockchain
+            .add_transaction(delegate(&holder_key, stranger.address, 0))
+            .await,
+    );
+    // A validator cannot also delegate its own stake.
+    node.stake(holder.address, Amount::from_smv(10))
+        .await
+        .unwrap();
+    refused(
+        node.blockchain
+            .add_transaction(delegate(&validator_key, holder.address, 0))
+            .await,
+    );
+    let malformed = commission_tx(&validator_key, Commission { bps: 20_000 }, 0);
+    refused(node.blockchain.add_transaction(malformed).await);
+}
+
+#[tokio::test]
+async fn test_delegation_waits_for_its_fork() {
+    let (mut node, (validator, _), (_, holder_key)) = node().await;
+    let fork_height = 2;
+    let mut forks = ForkSchedule::default();
+    forks.set(Feature::Delegation, fork_height);
+    node.blockchain.set_chain_params(ChainParams {
+        forks,
+        ..ChainParams::default()
+    });
+    assert_eq!("delegation".parse(), Ok(Feature::Delegation));
+
+    let delegate = || {
+        staking_tx(
+            &holder_key,
+            TxKind::Delegate,
+            validator.address,
+            Amount::from_smv(10),
+            0,
+        )
+    };
+    let early = node
+        .blockchain
+        .add_transaction(delegate())
+        .await
+        .unwrap_err();
+    assert!(matches!(early, BlockchainError::Governance(_)));
+
+    for _ in 0..fork_height {
+        node.produce_block().await.unwrap();
+    }
+    node.blockchain.add_transaction(delegate()).await.unwrap();
+    node.produce_block().await.unwrap();
+    assert_eq!(
+        node.blockchain.validators().await.unwrap()[0].delegated,
+        Amount::from_smv(10)
+    );
+}
@@ -0,0 +1,106 @@
+use smvblock::{
+    amount::Amount,
+    blockchain::{Block, Transfer, TxData, TxKind, User},
+    db::Database,
+    store::{ChainStore, MemoryStore},
+};
+
+/// Runs the same blocks through `store`, so every backend is held to the
+/// same behaviour.
+fn exercise(store: &mut impl ChainStore) {
+    let (sender, key) = User::generate(Amount::from_smv(100));
+    let (receiver, _) = User::generate(Amount::ZERO);
+    store.add_account(&sender).unwrap();
+    assert!(store.add_account(&sender).is_err());
+    assert_eq!(
+        store.account(&sender.address).unwrap(),
+        Some(sender.clone())
+    );
+
+    let genesis = Block::new([0; 32], 0, sender.address, vec![]);
+    store.put_block(&genesis, &[], Amount::ZERO).unwrap();
+    let tx = Transfer {
+        receiver: receiver.address,
+        amount: Amount::from_smv(10),
+        fee: Amount::ZERO,
+        nonce: 0,
+        kind: TxKind::Transfer,
+        data: TxData::None,
+    }
+    .into_transaction(&key);
+    let block = Block::new(genesis.hash(), 1, sender.address, vec![tx.clone()]);
+    let paid = User {
+        balance: Amount::from_smv(90),
+        ..sender.clone()
+    };
+    let received = User {
+        balance: Amount::from_smv(10),
+        ..receiver.clone()
+    };
+    let burned = Amount::from_smv(1);
+    store
+        .put_block(&block, &[paid.clone(), received.clone()], burned)
+        .unwrap();
+
+    assert_eq!(store.block(&block.hash()).unwrap(), Some(block.clone()));
+    assert_eq!(store.block_at(1).unwrap(), Some(block.clone()));
+    assert_eq!(store.burned(&block.hash()).unwrap(), Some(burned));
+    assert_eq!(store.head().unwrap(), Some(block.header.clone()));
+    assert_eq!(
+        store.headers(0, 2, 10).unwrap(),
+        vec![genesis.header.clone(), block.header.clone()]
+    );
+    assert_eq!(store.headers(1, 2, 10).unwrap(), vec![block.header.clone()]);
+    assert_eq!(
+        store.headers(0, 2, 1).unwrap(),
+        vec![genesis.header.clone()]
+    );
+    let (stored, location) = store.transaction(&tx.hash()).unwrap().unwrap();
+    assert_eq!(stored, tx);
+    assert_eq!(
+        (location.block_hash, location.height, location.index),
+        (block.hash(), 1, 0)
+    );
+    let mut accounts = vec![paid.clone(), received.clone()];
+    accounts.sort_by_key(|user| user.address);
+    assert_eq!(store.accounts().unwrap(), accounts);
+
+    // A transaction is stored once only.
+    let mut replay = Block::new(block.hash(), 2, sender.address, vec![tx.clone()]);
+    replay.header.timestamp += 1;
+    assert!(
+        store
+            .put_block(&replay, std::slice::from_ref(&paid), Amount::ZERO)
+            .is_err()
+    );
+    assert_eq!(store.head().unwrap(), Some(block.header.clone()));
+
+    // Removing the block puts the accounts back as they were.
+    assert!(store.remove_block(&block.hash()).unwrap());
+    assert_eq!(store.block(&block.hash()).unwrap(), None);
+    assert_eq!(store.transaction(&tx.hash()).unwrap(), None);
+    assert_eq!(store.burned(&block.hash()).unwrap(), None);
+    assert_eq!(store.account(&sender.address).unwrap(), Some(sender));
+    assert_eq!(store.account(&receiver.address).unwrap(), None);
+    assert_eq!(store.head().unwrap(), Some(genesis.header.clone()));
+    // Genesis updated no accounts, so there is nothing to undo it with.
+    assert!(!store.remove_block(&genesis.hash()).unwrap());
+
+    assert_eq!(store.metadata("network").unwrap(), None);
+    store.set_metadata("network", "devnet").unwrap();
+    store.set_metadata("network", "testnet").unwrap();
+    assert_eq!(
+        store.metadata("network").unwrap(),
+        Some("testnet".to_string())
+    );
+}
+
+#[test]
+fn test_sqlite_store() {
+    exercise(&mut Database::in_memory().unwrap());
+}
+
+#[test]
+fn test_memory_store() {
+    exercise(&mut MemoryStore::new());
+}
@@ -0,0 +1,251 @@
+use ed25519_dalek::SigningKey;
+use smvblock::{
+    amount::Amount,
+    blockchain::{Transfer, TxData, TxKind, User},
+    governance::{Ballot, MIN_VOTING_PERIOD, Parameter, ParameterChange},
+    node::{Node, NodeType},
+    params::{self, ChainParams, FeeRouting, FeeSplit},
+};
+
+/// A node whose only validator stakes 50 SMV, burning a fifth of fees and
+/// paying three tenths to the treasury.
+async fn node() -> (Node, User, SigningKey) {
+    let mut node = Node::new(NodeType::FullNode, true).unwrap();
+    node.blockchain.set_chain_params(ChainParams {
+        fees: FeeRouting::new(2_000, 3_000).unwrap(),
+        ..ChainParams::default()
+    });
+    let (validator, key) = User::generate(Amount::from_smv(100));
+    node.add_user(validator.clone()).await.unwrap();
+    node.stake(validator.address, Amount::from_smv(50))
+        .await
+        .unwrap();
+    (node, validator, key)
+}
+
+fn next_height(node: &Node) -> u64 {
+    node.blockchain
+        .chain_head()
+        .get()
+        .map_or(0, |(_, height)| height + 1)
+}
+
+async fn balance(node: &Node, user: &User) -> Amount {
+    node.blockchain
+        .get_user(&user.address)
+        .await
+        .unwrap()
+        .unwrap()
+        .balance
+}
+
+#[test]
+fn test_fee_split_adds_up_to_the_fees() {
+    let routing = FeeRouting::new(2_000, 3_000).unwrap();
+    assert_eq!(
+        routing.split(Amount::from_smv(10)),
+        FeeSplit {
+            burned: Amount::from_smv(2),
+            treasury: Amount::from_smv(3),
+            proposer: Amount::from_smv(5),
+        }
+    );
+    // Shares round down, leaving the remainder to the proposer.
+    assert_eq!(
+        routing.split(Amount::from_base_units(7)),
+        FeeSplit {
+            burned: Amount::from_base_units(1),
+            treasury: Amount::from_base_units(2),
+            proposer: Amount::from_base_units(4),
+        }
+    );
+
+    assert!(FeeRouting::new(6_000, 5_000).is_err());
+    // Shares too large together, as governance may set them, leave the
+    // treasury what the burn does not take.
+    let governed = FeeRouting {
+        burn_bps: 6_000,
+        treasury_bps: 5_000,
+    };
+    let split = governed.split(Amount::from_smv(10));
+    assert_eq!(split.treasury, Amount::from_smv(4));
+    assert_eq!(split.proposer, Amount::ZERO);
+}
+
+#[tokio::test]
+async fn test_fees_are_burned_and_paid_to_the_treasury() {
+    let (mut node, validator, key) = node().await;
+    let (receiver, _) = User::generate(Amount::ZERO);
+    node.add_user(receiver.clone()).await.unwrap();
+    node.produce_block().await.unwrap();
+    assert_eq!(
+        node.blockchain.get_user(&params::TREASURY).await.unwrap(),
+        None
+    );
+
+    let before = balance(&node, &validator).await;
+    let supply_before = node.blockchain.supply().await.unwrap();
+    node.blockchain
+        .add_transaction(
+            Transfer {
+                receiver: receiver.address,
+                amount: Amount::from_smv(1),
+                fee: Amount::from_smv(10),
+                nonce: 0,
+                kind: TxKind::Transfer,
+                data: TxData::None,
+            }
+            .into_transaction(&key),
+        )
+        .await
+        .unwrap();
+    let hash = node.produce_block().await.unwrap();
+    let coinbase = node
+        .blockchain
+        .get_block(hash)
+        .await
+        .unwrap()
+        .unwrap()
+        .header
+        .coinbase;
+
+    // Sent 1 and paid 10 in fees, then got the coinbase and half the fees back.
+    let expected = before
+        .checked_add(coinbase)
+        .unwrap()
+        .checked_sub(Amount::from_smv(6))
+        .unwrap();
+    assert_eq!(balance(&node, &validator).await, expected);
+    let treasury = node
+        .blockchain
+        .get_user(&params::TREASURY)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(treasury.balance, Amount::from_smv(3));
+
+    let supply = node.blockchain.supply().await.unwrap();
+    assert_eq!(
+        supply.burned,
+        supply_before
+            .burned
+            .checked_add(Amount::from_smv(2))
+            .unwrap()
+    );
+    assert_eq!(supply.expected().unwrap(), supply.circulating);
+    assert!(
+        node.blockchain
+            .verify_chain(false)
+            .await
+            .unwrap()
+            .problems
+            .is_empty()
+    );
+}
+
+#[tokio::test]
+async fn test_accepted_grant_is_paid_out_of_the_treasury() {
+    let (mut node, validator, key) = node().await;
+    let (receiver, _) = User::generate(Amount::ZERO);
+    node.add_user(receiver.clone()).await.unwrap();
+    node.produce_block().await.unwrap();
+    let tx = |kind, receiver, amount, fee, nonce| {
+        Transfer {
+            receiver,
+            amount,
+            fee,
+            nonce,
+            kind,
+            data: TxData::None,
+        }
+        .into_transaction(&key)
+    };
+    let governance_tx = |kind, data, nonce| {
+        Transfer {
+            receiver: validator.address,
+            amount: Amount::ZERO,
+            fee: Amount::ZERO,
+            nonce,
+            kind,
+            data,
+        }
+        .into_transaction(&key)
+    };
+
+    // Funds the treasury with 3 SMV.
+    node.blockchain
+        .add_transaction(tx(
+            TxKind::Transfer,
+            receiver.address,
+            Amount::ZERO,
+            Amount::from_smv(10),
+            0,
+        ))
+        .await
+        .unwrap();
+    let activation_height = next_height(&node) + MIN_VOTING_PERIOD;
+    let grant = |value: u64| ParameterChange {
+        parameter: Parameter::TreasuryGrant,
+        value: Amount::from_smv(value).base_units(),
+        activation_height,
+    };
+    for (nonce, value) in [(1, 2), (2, 2)] {
+        node.blockchain
+            .add_transaction(governance_tx(
+                TxKind::Propose,
+                TxData::Proposal(grant(value)),
+                nonce,
+            ))
+            .await
+            .unwrap();
+    }
+    node.produce_block().await.unwrap();
+    for (nonce, proposal) in [(3, 1), (4, 2)] {
+        let ballot = Ballot {
+            proposal,
+            approve: true,
+        };
+        node.blockchain
+            .add_transaction(governance_tx(TxKind::Vote, TxData::Ballot(ballot), nonce))
+            .await
+            .unwrap();
+    }
+    while next_height(&node) < activation_height {
+        node.produce_block().await.unwrap();
+    }
+
+    let before = balance(&node, &validator).await;
+    let hash = node.produce_block().await.unwrap();
+    let coinbase = node
+        .blockchain
+        .get_block(hash)
+        .await
+        .unwrap()
+        .unwrap()
+        .header
+        .coinbase;
+    // Only the first grant fits in the treasury.
+    assert_eq!(
+        balance(&node, &validator).await,
+        before
+            .checked_add(coinbase)
+            .unwrap()
+            .checked_add(Amount::from_smv(2))
+            .unwrap()
+    );
+    let treasury = node
+        .blockchain
+        .get_user(&params::TREASURY)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(treasury.balance, Amount::from_smv(1));
+    assert!(
+        node.blockchain
+            .verify_chain(false)
+            .await
+            .unwrap()
+            .problems
+            .is_empty()
+    );
+}
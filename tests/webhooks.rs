@@ -0,0 +1,152 @@
+use axum::Router;
+use axum::extract::State;
+use axum::http::{HeaderMap, StatusCode};
+use axum::routing::post;
+use serde_json::{Value, json};
+use smvblock::{
+    amount::Amount,
+    blockchain::{Transfer, TxData, TxKind, User},
+    client::RpcClient,
+    node::{Node, NodeType},
+    p2p::Backoff,
+    webhooks::{self, SIGNATURE_HEADER, WebhookConfig},
+};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+
+/// Receives notifications, failing the first try of each so deliveries
+/// must be retried.
+#[derive(Clone)]
+struct Receiver {
+    tries: Arc<AtomicU32>,
+    delivered: mpsc::UnboundedSender<(String, String)>,
+}
+
+async fn receive(State(receiver): State<Receiver>, headers: HeaderMap, body: String) -> StatusCode {
+    if receiver.tries.fetch_add(1, Ordering::SeqCst) % 2 == 0 {
+        return StatusCode::SERVICE_UNAVAILABLE;
+    }
+    let signature = headers[SIGNATURE_HEADER].to_str().unwrap().to_string();
+    receiver.delivered.send((signature, body)).unwrap();
+    StatusCode::OK
+}
+
+async fn next(delivered: &mut mpsc::UnboundedReceiver<(String, String)>) -> (String, Value) {
+    let (signature, body) = tokio::time::timeout(Duration::from_secs(5), delivered.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    (signature, serde_json::from_str(&body).unwrap())
+}
+
+#[tokio::test]
+async fn test_webhooks_are_signed_and_retried() {
+    let (sender, mut delivered) = mpsc::unbounded_channel();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let hook_addr: SocketAddr = listener.local_addr().unwrap();
+    let router = Router::new()
+        .route("/hook", post(receive))
+        .with_state(Receiver {
+            tries: Arc::new(AtomicU32::new(0)),
+            delivered: sender,
+        });
+    tokio::spawn(async move { axum::serve(listener, router).await });
+
+    let mut node = Node::new(NodeType::FullNode, true).unwrap();
+    let (payer, key) = User::generate(Amount::from_smv(100));
+    let (payee, _) = User::generate(Amount::ZERO);
+    node.add_user(payer.clone()).await.unwrap();
+    node.add_user(payee.clone()).await.unwrap();
+    node.stake(payer.address, Amount::from_smv(10))
+        .await
+        .unwrap();
+    node.start_webhooks(WebhookConfig {
+        attempts: 3,
+        timeout: Duration::from_secs(5),
+        backoff: Backoff {
+            initial: Duration::from_millis(10),
+            max: Duration::from_millis(50),
+        },
+    });
+    let rpc = RpcClient::new(
+        node.start_rpc("127.0.0.1:0".parse().unwrap())
+            .await
+            .unwrap(),
+    );
+
+    let url = format!("http://{}/hook", hook_addr);
+    let registered = rpc
+        .call("webhook_register", json!([hex::encode(payee.address), url]))
+        .await
+        .unwrap();
+    let secret: [u8; 32] = hex::decode(registered["secret"].as_str().unwrap())
+        .unwrap()
+        .try_into()
+        .unwrap();
+    let again = rpc
+        .call("webhook_register", json!([hex::encode(payee.address), url]))
+        .await
+        .unwrap();
+    assert_eq!(again, registered);
+    rpc.call("webhook_register", json!([hex::encode(payer.address), url]))
+        .await
+        .unwrap();
+    assert!(
+        rpc.call(
+            "webhook_register",
+            json!([hex::encode(payer.address), "ftp://x"])
+        )
+        .await
+        .is_err()
+    );
+    let listed = rpc.call("webhook_list", json!([])).await.unwrap();
+    assert_eq!(listed.as_array().unwrap().len(), 2);
+    assert!(listed[0].get("secret").is_none());
+
+    let tx = Transfer {
+        receiver: payee.address,
+        amount: Amount::from_smv(5),
+        fee: Amount::ZERO,
+        nonce: 0,
+        kind: TxKind::Transfer,
+        data: TxData::None,
+    }
+    .into_transaction(&key);
+    node.blockchain.add_transaction(tx.clone()).await.unwrap();
+    node.produce_block().await.unwrap();
+
+    let mut events = Vec::new();
+    for _ in 0..2 {
+        let (signature, notification) = next(&mut delivered).await;
+        assert_eq!(notification["transaction"]["hash"], hex::encode(tx.hash()));
+        assert_eq!(notification["height"], json!(0));
+        if notification["event"] == "received" {
+            assert_eq!(notification["webhook"], registered["id"]);
+            let body = notification.to_string();
+            assert_eq!(signature, webhooks::sign(&secret, body.as_bytes()));
+        }
+        events.push(notification["event"].as_str().unwrap().to_string());
+    }
+    events.sort();
+    assert_eq!(events, ["received", "sent"]);
+
+    node.slash_validator(payer.address, Amount::from_smv(4))
+        .await
+        .unwrap();
+    let (_, slashed) = next(&mut delivered).await;
+    assert_eq!(slashed["event"], "slashed");
+    assert_eq!(slashed["address"], hex::encode(payer.address));
+    assert_eq!(slashed["penalty"], "4 SMV");
+
+    let removed = rpc
+        .call("webhook_remove", json!([registered["id"]]))
+        .await
+        .unwrap();
+    assert_eq!(removed, json!(true));
+    let listed = rpc.call("webhook_list", json!([])).await.unwrap();
+    assert_eq!(listed.as_array().unwrap().len(), 1);
+}
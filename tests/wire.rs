@@ -0,0 +1,149 @@
+//! Golden vectors for the peer wire format. Each message is encoded as a
+//! frame and compared with the bytes this protocol version has always sent,
+//! so a change that would stop old and new nodes understanding each other
+//! fails here. When one does, bump `PROTOCOL_VERSION` and update the vectors.
+
+use ed25519_dalek::SigningKey;
+use smvblock::{
+    amount::Amount,
+    blockchain::{BlockHeader, Transfer, TxData, TxKind},
+    node::NodeType,
+    p2p::{Compression, DisconnectReason, Message, decode_message, encode_frame},
+    sync::SyncState,
+};
+
+fn header() -> BlockHeader {
+    BlockHeader {
+        previous_hash: [1; 32],
+        merkle_root: [2; 32],
+        state_root: [3; 32],
+        timestamp: 1_700_000_000,
+        height: 7,
+        proposer: [4; 32],
+        coinbase: Amount::from_smv(1),
+        finalized_hash: [5; 32],
+        signature: [6; 64],
+    }
+}
+
+fn messages() -> Vec<(Message, &'static str)> {
+    let key = SigningKey::from_bytes(&[7; 32]);
+    let tx = Transfer {
+        receiver: [8; 32],
+        amount: Amount::from_smv(5),
+        fee: Amount::from_smv(1),
+        nonce: 3,
+        kind: TxKind::Transfer,
+        data: TxData::None,
+    }
+    .into_transaction(&key);
+    vec![
+        (
+            Message::Hello {
+                identity: [9; 32],
+                chain_id: "smvblock".to_string(),
+                genesis: Some([10; 32]),
+                listen_addr: Some("127.0.0.1:4001".parse().unwrap()),
+                node_type: NodeType::FullNode,
+                compression: vec![Compression::Snappy],
+                timestamp: 1_700_000_000_000,
+            },
+            "000000620c0000090909090909090909090909090909090909090909090909090909090909090908736d76626c6f636b010a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a01007f000001fba10f000100fd00d0ca9f17030000",
+        ),
+        (Message::GetPeers, "000000030c0001"),
+        (
+            Message::Peers(vec!["10.0.0.1:4001".parse().unwrap()]),
+            "0000000c0c000201000a000001fba10f",
+        ),
+        (
+            Message::NewBlock { header: header() },
+            "000000ee0c0004010101010101010101010101010101010101010101010101010101010101010102020202020202020202020202020202020202020202020202020202020202020303030303030303030303030303030303030303030303030303030303030303fc00e2a7ca070404040404040404040404040404040404040404040404040404040404040404fc00e1f505050505050505050505050505050505050505050505050505050505050505050506060606060606060606060606060606060606060606060606060606060606060606060606060606060606060606060606060606060606060606060606060606",
+        ),
+        (
+            Message::GetBlockBody { hash: [11; 32] },
+            "000000230c00050b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b",
+        ),
+        (
+            Message::NewTransaction(tx.clone()),
+            "000000900c00070808080808080808080808080808080808080808080808080808080808080808fc0065cd1dfc00e1f505030000ea4a6c63e29c520abef5507b132ec5f9954776aebebe7b92421eea691446d22c6fe4485f2a97498dff884ad527315416c1a168e3b4e89207030db03a65ffbdf9a676a2308e94cd8875b22e7bf3d8ca6ce7b18fb5d73d20f0b9ba4d80472a1009",
+        ),
+        (
+            Message::BlockBody {
+                hash: [12; 32],
+                transactions: vec![tx],
+            },
+            "000000b10c00060c0c0c0c0c0c0c0c0c0c0c0c0c0c0c0c0c0c0c0c0c0c0c0c0c0c0c0c0c0c0c0c010808080808080808080808080808080808080808080808080808080808080808fc0065cd1dfc00e1f505030000ea4a6c63e29c520abef5507b132ec5f9954776aebebe7b92421eea691446d22c6fe4485f2a97498dff884ad527315416c1a168e3b4e89207030db03a65ffbdf9a676a2308e94cd8875b22e7bf3d8ca6ce7b18fb5d73d20f0b9ba4d80472a1009",
+        ),
+        (
+            Message::Status {
+                height: 8,
+                head: [13; 32],
+                oldest_block: 0,
+                finalized_height: Some(5),
+                peers: 3,
+                pending_transactions: 2,
+                sync: SyncState::Syncing {
+                    current: 8,
+                    target: 10,
+                },
+                version: "0.1.0".to_string(),
+                uptime: 60,
+            },
+            "000000330c0008080d0d0d0d0d0d0d0d0d0d0d0d0d0d0d0d0d0d0d0d0d0d0d0d0d0d0d0d0d0d0d0d000105030200080a05302e312e303c",
+        ),
+        (
+            Message::GetHeaders {
+                from_height: 2,
+                count: 100,
+            },
+            "000000050c00090264",
+        ),
+        (
+            Message::Headers(vec![header()]),
+            "000000ef0c000a01010101010101010101010101010101010101010101010101010101010101010102020202020202020202020202020202020202020202020202020202020202020303030303030303030303030303030303030303030303030303030303030303fc00e2a7ca070404040404040404040404040404040404040404040404040404040404040404fc00e1f505050505050505050505050505050505050505050505050505050505050505050506060606060606060606060606060606060606060606060606060606060606060606060606060606060606060606060606060606060606060606060606060606",
+        ),
+        (
+            Message::GetBlocks(vec![[14; 32]]),
+            "000000240c000b010e0e0e0e0e0e0e0e0e0e0e0e0e0e0e0e0e0e0e0e0e0e0e0e0e0e0e0e0e0e0e0e",
+        ),
+        (
+            Message::GetSnapshot {
+                hash: None,
+                index: 1,
+            },
+            "000000050c000d0001",
+        ),
+        (
+            Message::GetTransactionProofs {
+                addresses: vec![[15; 32]],
+                from_height: 0,
+                to_height: 9,
+            },
+            "000000260c000f010f0f0f0f0f0f0f0f0f0f0f0f0f0f0f0f0f0f0f0f0f0f0f0f0f0f0f0f0f0f0f0f0009",
+        ),
+        (Message::Goodbye, "000000030c0011"),
+        (
+            Message::Disconnect(DisconnectReason::TooManyPeers),
+            "000000040c001200",
+        ),
+    ]
+}
+
+#[test]
+fn test_messages_keep_their_wire_encoding() {
+    for (message, expected) in messages() {
+        let frame = encode_frame(&message, None).unwrap();
+        assert_eq!(hex::encode(&frame), expected, "{}", message.kind());
+        assert_eq!(decode_message(&frame[6..]).unwrap(), message);
+    }
+}
+
+#[test]
+fn test_oversized_lengths_are_refused_before_allocating() {
+    // A `Hello` whose chain ID claims to run for about an exabyte.
+    let mut payload = vec![0];
+    payload.extend_from_slice(&[9; 32]);
+    payload.push(253);
+    payload.extend_from_slice(&(u64::MAX / 16).to_le_bytes());
+    assert!(decode_message(&payload).is_err());
+}